@@ -0,0 +1,18 @@
+use std::sync::OnceLock;
+
+/* The expiry of a bundle is judged against the clock of the BPA, which runs on virtual
+ * time under simulation.  The BPA installs its clock here at startup, and until it does
+ * the system clock is used */
+
+static CLOCK: OnceLock<fn() -> time::OffsetDateTime> = OnceLock::new();
+
+// Returns false if a clock has already been installed
+pub fn install(now: fn() -> time::OffsetDateTime) -> bool {
+    CLOCK.set(now).is_ok()
+}
+
+pub fn now() -> time::OffsetDateTime {
+    CLOCK
+        .get()
+        .map_or_else(time::OffsetDateTime::now_utc, |now| now())
+}
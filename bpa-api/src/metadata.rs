@@ -1,3 +1,4 @@
+use super::clock;
 use hardy_bpv7::prelude as bpv7;
 use std::sync::Arc;
 
@@ -7,6 +8,21 @@ pub struct Metadata {
     pub storage_name: Option<Arc<str>>,
     pub hash: Option<Arc<[u8]>>,
     pub received_at: Option<time::OffsetDateTime>,
+    pub priority: Priority,
+
+    // Where to send the bundle instead of its destination, for status reports whose
+    // Report-To is unreachable
+    pub return_path: Option<bpv7::Eid>,
+}
+
+// The class of traffic a bundle belongs to, bulk traffic is evicted from storage first
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum Priority {
+    Bulk,
+
+    #[default]
+    Normal,
+    Expedited,
 }
 
 #[derive(Debug, Default, Clone, Eq, PartialEq)]
@@ -37,22 +53,37 @@ impl Bundle {
     }
 
     pub fn creation_time(&self) -> time::OffsetDateTime {
-        if let Some(creation_time) = self.bundle.id.timestamp.creation_time {
+        Self::creation_time_of(&self.bundle, self.metadata.received_at)
+    }
+
+    pub fn expiry(&self) -> time::OffsetDateTime {
+        Self::expiry_of(&self.bundle, self.metadata.received_at)
+    }
+
+    // As creation_time(), for a bundle held apart from its metadata
+    pub fn creation_time_of(
+        bundle: &bpv7::Bundle,
+        received_at: Option<time::OffsetDateTime>,
+    ) -> time::OffsetDateTime {
+        if let Some(creation_time) = bundle.id.timestamp.creation_time {
             creation_time.into()
         } else {
-            self.metadata
-                .received_at
-                .unwrap_or_else(time::OffsetDateTime::now_utc)
-                .saturating_sub(Self::millis_to_duration(self.bundle.age.unwrap_or(0)))
+            received_at
+                .unwrap_or_else(clock::now)
+                .saturating_sub(Self::millis_to_duration(bundle.age.unwrap_or(0)))
         }
     }
 
-    pub fn expiry(&self) -> time::OffsetDateTime {
-        self.creation_time()
-            .saturating_add(Self::millis_to_duration(self.bundle.lifetime))
+    // As expiry(), for a bundle held apart from its metadata
+    pub fn expiry_of(
+        bundle: &bpv7::Bundle,
+        received_at: Option<time::OffsetDateTime>,
+    ) -> time::OffsetDateTime {
+        Self::creation_time_of(bundle, received_at)
+            .saturating_add(Self::millis_to_duration(bundle.lifetime))
     }
 
     pub fn has_expired(&self) -> bool {
-        self.expiry() <= time::OffsetDateTime::now_utc()
+        self.expiry() <= clock::now()
     }
 }
@@ -5,6 +5,39 @@ pub type Error = Box<dyn std::error::Error + Send + Sync>;
 pub type Result<T> = core::result::Result<T, Error>;
 pub type Sender = tokio::sync::mpsc::Sender<metadata::Bundle>;
 
+// Bounds on a scan, so that it can stop early rather than walk the whole of a huge store
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ScanBudget {
+    // Return at most this many bundles
+    pub max_bundles: Option<usize>,
+    // Stop returning bundles once this has passed
+    pub deadline: Option<std::time::Instant>,
+}
+
+impl ScanBudget {
+    // Whether a scan that has already returned `count` bundles should stop
+    pub fn is_spent(&self, count: usize) -> bool {
+        self.max_bundles.is_some_and(|max| count >= max)
+            || self
+                .deadline
+                .is_some_and(|deadline| std::time::Instant::now() >= deadline)
+    }
+}
+
+// What a storage engine can do efficiently, so the BPA can pick the best way to use it,
+// rather than assuming the least capable engine
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities {
+    // Bundle data can be written and read in chunks, without holding all of it in memory
+    pub streaming: bool,
+    // Bundles can be found by destination through an index, without walking every bundle
+    pub destination_queries: bool,
+    // A batch of changes is applied in a single transaction, cheaper than one at a time
+    pub transactional_batches: bool,
+    // The number of stored bundles can be estimated without walking them
+    pub approximate_counts: bool,
+}
+
 #[async_trait]
 pub trait MetadataStorage: Send + Sync {
     async fn load(&self, bundle_id: &bpv7::BundleId) -> Result<Option<metadata::Bundle>>;
@@ -22,6 +55,15 @@ pub trait MetadataStorage: Send + Sync {
         status: &metadata::BundleStatus,
     ) -> Result<()>;
 
+    // Move each bundle whose stored status is still the one given to `status`, as a single
+    // compare-and-set against the store, and say which were moved.  Bundles that have moved
+    // on, or gone, are left alone
+    async fn claim_bundle_statuses(
+        &self,
+        claims: &[(bpv7::BundleId, metadata::BundleStatus)],
+        status: &metadata::BundleStatus,
+    ) -> Result<Vec<bool>>;
+
     async fn remove(&self, bundle_id: &bpv7::BundleId) -> Result<()>;
 
     async fn confirm_exists(
@@ -29,16 +71,127 @@ pub trait MetadataStorage: Send + Sync {
         bundle_id: &bpv7::BundleId,
     ) -> Result<Option<metadata::Metadata>>;
 
-    async fn get_waiting_bundles(&self, limit: time::OffsetDateTime, tx: Sender) -> Result<()>;
+    // Bundles waiting until no later than `limit`, soonest first
+    async fn get_waiting_bundles(
+        &self,
+        limit: time::OffsetDateTime,
+        budget: ScanBudget,
+        tx: Sender,
+    ) -> Result<()>;
 
     async fn get_unconfirmed_bundles(&self, tx: Sender) -> Result<()>;
 
-    async fn poll_for_collection(&self, destination: bpv7::Eid, tx: Sender) -> Result<()>;
+    // Bundles awaiting collection by `destination`, oldest first
+    async fn poll_for_collection(
+        &self,
+        destination: bpv7::Eid,
+        budget: ScanBudget,
+        tx: Sender,
+    ) -> Result<()>;
+
+    async fn get_waiting_destinations(&self) -> Result<Vec<bpv7::Eid>>;
+
+    async fn get_waiting_bundles_for(&self, destination: &bpv7::Eid, tx: Sender) -> Result<()>;
+
+    async fn purge_tombstones(&self, older_than: time::OffsetDateTime) -> Result<u64>;
+
+    // Fragments awaiting reassembly with the same source and creation timestamp as `bundle_id`
+    async fn get_fragments(&self, bundle_id: &bpv7::BundleId, tx: Sender) -> Result<()>;
+
+    // Every fragment awaiting reassembly
+    async fn get_reassembly_pending(&self, tx: Sender) -> Result<()>;
+
+    // Every bundle that is not a Tombstone
+    async fn get_stored_bundles(&self, tx: Sender) -> Result<()>;
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::default()
+    }
+
+    // Engines with transactional batches override the following
+
+    async fn set_bundle_statuses(
+        &self,
+        updates: &[(bpv7::BundleId, metadata::BundleStatus)],
+    ) -> Result<()> {
+        for (bundle_id, status) in updates {
+            self.set_bundle_status(bundle_id, status).await?;
+        }
+        Ok(())
+    }
+
+    // Engines with approximate counts override the following
+
+    // The number of bundles that are not Tombstones, give or take
+    async fn approximate_count(&self) -> Result<Option<u64>> {
+        Ok(None)
+    }
+
+    // Engines that can hold small bundle data alongside the metadata override the following
+
+    fn supports_inline_data(&self) -> bool {
+        false
+    }
+
+    async fn store_inline(
+        &self,
+        _metadata: &metadata::Metadata,
+        _bundle: &bpv7::Bundle,
+        _data: &[u8],
+    ) -> Result<bool> {
+        Err("Inline bundle data is not supported by this metadata storage engine".into())
+    }
+
+    async fn load_inline(&self, _storage_name: &str) -> Result<Option<DataRef>> {
+        Ok(None)
+    }
+
+    async fn get_inline_bundles(&self, _tx: Sender) -> Result<()> {
+        Ok(())
+    }
+
+    // Engines that can record the delivery of a bundle to each of a number of registrations override the following
+
+    fn supports_multicast_delivery(&self) -> bool {
+        false
+    }
+
+    // Returns false if the delivery to the registration was already recorded
+    async fn confirm_delivery(
+        &self,
+        _bundle_id: &bpv7::BundleId,
+        _registration: &str,
+    ) -> Result<bool> {
+        Err("Multicast delivery is not supported by this metadata storage engine".into())
+    }
+
+    async fn get_deliveries(&self, _bundle_id: &bpv7::BundleId) -> Result<Vec<String>> {
+        Ok(Vec::new())
+    }
+
+    // Engines that can replace the hashes of stored bundles override the following
+
+    // Returns the number of bundles updated
+    async fn update_hashes(
+        &self,
+        _hashes: &[(std::sync::Arc<str>, std::sync::Arc<[u8]>)],
+    ) -> Result<u64> {
+        Err("Updating bundle hashes is not supported by this metadata storage engine".into())
+    }
+
+    // Engines that version the format of what they store override the following
+
+    fn schema_version(&self) -> Option<String> {
+        None
+    }
 }
 
 pub type DataRef = std::sync::Arc<dyn AsRef<[u8]> + Send + Sync>;
 pub type ListResponse = (std::sync::Arc<str>, Option<time::OffsetDateTime>);
 
+// The size of the chunks bundle data is streamed in
+pub const STREAM_CHUNK_SIZE: usize = 64 * 1024;
+
 #[async_trait]
 pub trait BundleStorage: Send + Sync {
     async fn list(&self, tx: tokio::sync::mpsc::Sender<ListResponse>) -> Result<()>;
@@ -48,4 +201,112 @@ pub trait BundleStorage: Send + Sync {
     async fn store(&self, data: &[u8]) -> Result<std::sync::Arc<str>>;
 
     async fn remove(&self, storage_name: &str) -> Result<()>;
+
+    // Engines that can read part of the bundle data without loading all of it override the following
+
+    async fn load_range(
+        &self,
+        storage_name: &str,
+        offset: u64,
+        len: u64,
+    ) -> Result<Option<DataRef>> {
+        let Some(data) = self.load(storage_name).await? else {
+            return Ok(None);
+        };
+        let data = (*data).as_ref();
+        let range = usize::try_from(offset)?..usize::try_from(offset.saturating_add(len))?;
+        let Some(data) = data.get(range) else {
+            return Err("Requested range is beyond the end of the bundle data".into());
+        };
+        Ok(Some(std::sync::Arc::new(data.to_vec()) as DataRef))
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::default()
+    }
+
+    // Engines with streaming override the following.  Bundles streamed in by CLAs are written
+    // with store_stream, and load_stream serves whole-bundle passes such as rehashing.  Parsing,
+    // reassembly and forwarding still take the whole bundle from load, which an engine should
+    // map rather than read into memory if it handles bundles too large to buffer
+
+    async fn store_stream(
+        &self,
+        mut rx: tokio::sync::mpsc::Receiver<Vec<u8>>,
+    ) -> Result<std::sync::Arc<str>> {
+        let mut data = Vec::new();
+        while let Some(chunk) = rx.recv().await {
+            data.extend_from_slice(&chunk);
+        }
+        self.store(&data).await
+    }
+
+    // Returns false if there is no data for `storage_name`
+    async fn load_stream(
+        &self,
+        storage_name: &str,
+        tx: tokio::sync::mpsc::Sender<Vec<u8>>,
+    ) -> Result<bool> {
+        let Some(data) = self.load(storage_name).await? else {
+            return Ok(false);
+        };
+        for chunk in (*data).as_ref().chunks(STREAM_CHUNK_SIZE) {
+            tx.send(chunk.to_vec()).await?;
+        }
+        Ok(true)
+    }
+
+    // Engines that can hold partially reassembled payloads in sparse files override the following.
+    // `reassembly_id` is a lowercase hex string identifying the original bundle.  The ranges
+    // written must survive a restart, and once any of the file has been released no range
+    // may be reported, so that holes are never read back as payload.
+
+    fn supports_sparse_reassembly(&self) -> bool {
+        false
+    }
+
+    async fn write_partial(&self, _reassembly_id: &str, _offset: u64, _data: &[u8]) -> Result<()> {
+        Err("Sparse reassembly is not supported by this bundle storage engine".into())
+    }
+
+    async fn load_partial(
+        &self,
+        _reassembly_id: &str,
+        _offset: u64,
+        _len: u64,
+    ) -> Result<Option<DataRef>> {
+        Ok(None)
+    }
+
+    // The offset and length of each range written, in the order they were written
+    async fn partial_ranges(&self, _reassembly_id: &str) -> Result<Vec<(u64, u64)>> {
+        Ok(Vec::new())
+    }
+
+    async fn release_partial(&self, _reassembly_id: &str, _offset: u64, _len: u64) -> Result<()> {
+        Ok(())
+    }
+
+    async fn remove_partial(&self, _reassembly_id: &str) -> Result<()> {
+        Ok(())
+    }
+
+    // Engines that can remove data by themselves once it is no longer needed, such as object
+    // stores with lifecycle rules, override the following.  The BPA hints when the bundle the
+    // data belongs to expires, so the data can be collected even while the node is offline,
+    // and copes with expired data that has gone.  Removing data that has gone must not fail
+
+    fn supports_expiry_hints(&self) -> bool {
+        false
+    }
+
+    async fn hint_expiry(&self, _storage_name: &str, _expiry: time::OffsetDateTime) -> Result<()> {
+        Ok(())
+    }
+
+    // Engines that version the format of what they store override the following
+
+    fn schema_version(&self) -> Option<String> {
+        None
+    }
 }
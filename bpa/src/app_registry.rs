@@ -13,7 +13,7 @@ pub struct Endpoint {
     token: String,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub enum StatusKind {
     Received = 1,
     Forwarded = 2,
@@ -25,39 +25,103 @@ struct Application {
     eid: bpv7::Eid,
     token: String,
     ident: String,
+    grpc_address: Option<String>,
     endpoint: Option<Channel>,
+    metrics_label: Option<String>,
+    ordered_delivery: bool,
+    congestion_signalling: bool,
+    acknowledged_delivery: bool,
+}
+
+impl Application {
+    // The key deliveries are recorded against: tokens are reissued on every registration,
+    // but the endpoint and ident are the same each time the application comes back
+    fn registration(&self) -> String {
+        format!("{} {}", self.eid, self.ident)
+    }
 }
 
 #[derive(Default)]
 struct Indexes {
-    applications_by_eid: HashMap<bpv7::Eid, Arc<Application>>,
+    // More than one application may register for a multicast endpoint
+    applications_by_eid: HashMap<bpv7::Eid, Vec<Arc<Application>>>,
     applications_by_token: HashMap<String, Arc<Application>>,
 }
 
+impl Indexes {
+    fn insert(&mut self, app: Arc<Application>) {
+        // A re-registration replaces the previous one
+        let members = self.applications_by_eid.entry(app.eid.clone()).or_default();
+        members.retain(|member| member.ident != app.ident);
+        members.push(app.clone());
+
+        self.applications_by_token.insert(app.token.clone(), app);
+    }
+}
+
+// The application registry settings, as they appear in the configuration
+#[derive(Debug, Default, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+struct Settings {
+    multicast_endpoints: Vec<String>,
+}
+
 #[derive(Clone)]
 pub struct AppRegistry {
     admin_endpoints: utils::admin_endpoints::AdminEndpoints,
+    multicast_endpoints: Option<Arc<bpv7::EidPatternMap<(), ()>>>,
     applications: Arc<RwLock<Indexes>>,
 }
 
 impl AppRegistry {
     pub fn new(
-        _config: &config::Config,
+        config: &config::Config,
         admin_endpoints: utils::admin_endpoints::AdminEndpoints,
     ) -> Self {
         Self {
             admin_endpoints,
+            multicast_endpoints: Self::load_multicast_endpoints(config),
             applications: Default::default(),
         }
     }
 
+    fn load_multicast_endpoints(
+        config: &config::Config,
+    ) -> Option<Arc<bpv7::EidPatternMap<(), ()>>> {
+        let settings: Settings = utils::settings::load(config, "application registry");
+        let patterns = settings.multicast_endpoints;
+        if patterns.is_empty() {
+            return None;
+        }
+
+        let mut m = bpv7::EidPatternMap::new();
+        for s in patterns {
+            let p: bpv7::EidPattern = s
+                .parse()
+                .trace_expect(&format!("Invalid EID pattern '{s}'"));
+            info!("Multicast delivery enabled for {p}");
+            m.insert(&p, (), ());
+        }
+        Some(Arc::new(m))
+    }
+
+    pub fn multicast_enabled(&self) -> bool {
+        self.multicast_endpoints.is_some()
+    }
+
+    fn is_multicast(&self, eid: &bpv7::Eid) -> bool {
+        self.multicast_endpoints
+            .as_ref()
+            .is_some_and(|m| !m.find(eid).is_empty())
+    }
+
     #[instrument(skip(self))]
     pub async fn register(
         &self,
         request: RegisterApplicationRequest,
     ) -> Result<RegisterApplicationResponse, tonic::Status> {
         // Connect to client gRPC address
-        let endpoint = if let Some(grpc_address) = request.grpc_address {
+        let endpoint = if let Some(grpc_address) = request.grpc_address.clone() {
             application_client::ApplicationClient::connect(grpc_address.clone())
                 .await
                 .map(|endpoint| Some(Arc::new(Mutex::new(endpoint))))
@@ -70,12 +134,12 @@ impl AppRegistry {
         };
 
         // Compose a token
-        let mut token = Alphanumeric.sample_string(&mut rand::thread_rng(), 16);
+        let mut token = utils::random::with_rng(|rng| Alphanumeric.sample_string(rng, 16));
         let mut applications = self.applications.write().await;
 
         // Check token is unique
         while applications.applications_by_token.contains_key(&token) {
-            token = Alphanumeric.sample_string(&mut rand::thread_rng(), 16);
+            token = utils::random::with_rng(|rng| Alphanumeric.sample_string(rng, 16));
         }
 
         // Compose EID
@@ -113,12 +177,13 @@ impl AppRegistry {
                     (None, Some(node_id)) => node_id
                         .to_eid(&format!(
                             "auto/{}",
-                            Alphanumeric.sample_string(&mut rand::thread_rng(), 16)
+                            utils::random::with_rng(|rng| Alphanumeric.sample_string(rng, 16))
                         ))
                         .map_err(|e| tonic::Status::invalid_argument(e.to_string()))?,
                     (Some(node_id), _) => node_id.to_eid(
-                        (Into::<u16>::into(rand::thread_rng().gen::<std::num::NonZeroU16>())
-                            & 0x7F7Fu16) as u32,
+                        (Into::<u16>::into(utils::random::with_rng(|rng| {
+                            rng.gen::<std::num::NonZeroU16>()
+                        })) & 0x7F7Fu16) as u32,
                     ),
                     _ => unreachable!(),
                 };
@@ -129,8 +194,19 @@ impl AppRegistry {
             },
         };
 
-        if request.endpoint.is_some() {
-            if let Some(application) = applications.applications_by_eid.get(&eid) {
+        // Deliveries to a multicast endpoint are recorded against the ident, so it must outlive the token
+        if self.is_multicast(&eid) && request.ident.is_empty() {
+            return Err(tonic::Status::invalid_argument(format!(
+                "Registrations for multicast endpoint {eid} require an ident"
+            )));
+        }
+
+        if request.endpoint.is_some() && !self.is_multicast(&eid) {
+            if let Some(application) = applications
+                .applications_by_eid
+                .get(&eid)
+                .and_then(|members| members.first())
+            {
                 if application.ident != request.ident {
                     return Err(tonic::Status::already_exists(format!(
                         "Endpoint {eid} already registered"
@@ -145,16 +221,16 @@ impl AppRegistry {
         };
         let app = Arc::new(Application {
             eid,
+            metrics_label: utils::labels::application_label(&request.ident),
             ident: request.ident,
             token: response.token.clone(),
+            grpc_address: request.grpc_address,
             endpoint,
+            ordered_delivery: request.ordered_delivery.unwrap_or(false),
+            congestion_signalling: request.congestion_signalling.unwrap_or(false),
+            acknowledged_delivery: request.acknowledged_delivery.unwrap_or(false),
         });
-        applications
-            .applications_by_eid
-            .insert(app.eid.clone(), app.clone());
-        applications
-            .applications_by_token
-            .insert(app.token.clone(), app);
+        applications.insert(app);
         Ok(response)
     }
 
@@ -165,12 +241,77 @@ impl AppRegistry {
     ) -> Result<UnregisterApplicationResponse, tonic::Status> {
         let mut applications = self.applications.write().await;
 
-        applications
+        let app = applications
             .applications_by_token
             .remove(&request.token)
-            .and_then(|app| applications.applications_by_eid.remove(&app.eid))
-            .ok_or(tonic::Status::not_found("No such application registered"))
-            .map(|_| UnregisterApplicationResponse {})
+            .ok_or(tonic::Status::not_found("No such application registered"))?;
+
+        if let Some(members) = applications.applications_by_eid.get_mut(&app.eid) {
+            members.retain(|member| member.token != app.token);
+            if members.is_empty() {
+                applications.applications_by_eid.remove(&app.eid);
+            }
+        }
+        Ok(UnregisterApplicationResponse {})
+    }
+
+    pub async fn snapshot(&self) -> Vec<handoff::AppState> {
+        self.applications
+            .read()
+            .await
+            .applications_by_token
+            .values()
+            .map(|app| handoff::AppState {
+                eid: app.eid.to_string(),
+                token: app.token.clone(),
+                ident: app.ident.clone(),
+                grpc_address: app.grpc_address.clone(),
+                ordered_delivery: app.ordered_delivery,
+                congestion_signalling: app.congestion_signalling,
+                acknowledged_delivery: app.acknowledged_delivery,
+            })
+            .collect()
+    }
+
+    #[instrument(skip_all)]
+    pub async fn restore(&self, state: Vec<handoff::AppState>) {
+        let mut applications = self.applications.write().await;
+        for app in state {
+            let Ok(eid) = app.eid.parse::<bpv7::Eid>() else {
+                warn!("Failed to restore application {}: invalid EID", app.ident);
+                continue;
+            };
+
+            // Connect lazily, so an unresponsive application does not hold up the handover
+            let endpoint = match app
+                .grpc_address
+                .as_ref()
+                .map(|grpc_address| tonic::transport::Endpoint::from_shared(grpc_address.clone()))
+                .transpose()
+            {
+                Ok(endpoint) => endpoint.map(|endpoint| {
+                    Arc::new(Mutex::new(application_client::ApplicationClient::new(
+                        endpoint.connect_lazy(),
+                    )))
+                }),
+                Err(e) => {
+                    warn!("Failed to restore application {}: {e}", app.ident);
+                    continue;
+                }
+            };
+
+            applications.insert(Arc::new(Application {
+                eid,
+                token: app.token,
+                metrics_label: utils::labels::application_label(&app.ident),
+                ident: app.ident,
+                grpc_address: app.grpc_address,
+                endpoint,
+                ordered_delivery: app.ordered_delivery,
+                congestion_signalling: app.congestion_signalling,
+                acknowledged_delivery: app.acknowledged_delivery,
+            }));
+        }
     }
 
     #[instrument(skip(self))]
@@ -184,6 +325,66 @@ impl AppRegistry {
             .map(|app| app.eid.clone())
     }
 
+    // Whether the application wants to be told to try later when the BPA is congested
+    pub async fn congestion_signalling(&self, token: &str) -> bool {
+        self.applications
+            .read()
+            .await
+            .applications_by_token
+            .get(token)
+            .is_some_and(|app| app.congestion_signalling)
+    }
+
+    // Whether the application acknowledges the bundles delivered to it by delivery id
+    pub async fn acknowledged_delivery(&self, token: &str) -> bool {
+        self.applications
+            .read()
+            .await
+            .applications_by_token
+            .get(token)
+            .is_some_and(|app| app.acknowledged_delivery)
+    }
+
+    pub async fn metrics_label(&self, eid: &bpv7::Eid) -> Option<String> {
+        self.applications
+            .read()
+            .await
+            .applications_by_eid
+            .get(eid)
+            .and_then(|members| members.first())
+            .and_then(|app| app.metrics_label.clone())
+    }
+
+    // Whether any application registered for the endpoint asked for ordered delivery
+    pub async fn ordered_delivery(&self, eid: &bpv7::Eid) -> bool {
+        self.applications
+            .read()
+            .await
+            .applications_by_eid
+            .get(eid)
+            .is_some_and(|members| members.iter().any(|app| app.ordered_delivery))
+    }
+
+    // Returns the registration key as well, if the application is registered for a multicast endpoint
+    #[instrument(skip(self))]
+    pub async fn find_registration_by_token(
+        &self,
+        token: &str,
+    ) -> Result<(bpv7::Eid, Option<String>), tonic::Status> {
+        self.applications
+            .read()
+            .await
+            .applications_by_token
+            .get(token)
+            .ok_or(tonic::Status::not_found("No such application"))
+            .map(|app| {
+                (
+                    app.eid.clone(),
+                    self.is_multicast(&app.eid).then(|| app.registration()),
+                )
+            })
+    }
+
     #[instrument(skip(self))]
     pub async fn find_by_eid(&self, eid: &bpv7::Eid) -> Option<Endpoint> {
         self.applications
@@ -191,11 +392,31 @@ impl AppRegistry {
             .await
             .applications_by_eid
             .get(eid)
+            .and_then(|members| members.first())
             .map(|app| Endpoint {
                 token: app.token.clone(),
                 inner: app.endpoint.clone(),
             })
     }
+
+    #[instrument(skip(self))]
+    pub async fn find_all_by_eid(&self, eid: &bpv7::Eid) -> Vec<Endpoint> {
+        self.applications
+            .read()
+            .await
+            .applications_by_eid
+            .get(eid)
+            .map(|members| {
+                members
+                    .iter()
+                    .map(|app| Endpoint {
+                        token: app.token.clone(),
+                        inner: app.endpoint.clone(),
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
 }
 
 impl Endpoint {
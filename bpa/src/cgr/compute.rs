@@ -0,0 +1,125 @@
+use super::*;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+
+/* Earliest-arrival routes over the contact plan, from the local node at a given time.
+ * A bundle can leave a node over any contact from it that has not yet ended, waiting
+ * for the contact to start if need be, and arrives one-way light time later.  As every
+ * contact delivers in order, a search by earliest arrival at each node finds the
+ * earliest arrival at every reachable node.  Contact volume is not considered */
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hop {
+    pub via: u32,                      // The neighbour to send to
+    pub depart: time::OffsetDateTime,  // When the contact to the neighbour is usable
+    pub arrival: time::OffsetDateTime, // When the bundle is expected at the destination
+}
+
+pub fn routes(
+    plan: &plan::ContactPlan,
+    local: u32,
+    now: time::OffsetDateTime,
+) -> HashMap<u32, Hop> {
+    let mut best: HashMap<u32, Hop> = HashMap::new();
+    let mut queue = BinaryHeap::new();
+    queue.push(Reverse((now, local)));
+
+    while let Some(Reverse((at, node))) = queue.pop() {
+        // Skip stale queue entries
+        if node != local && best.get(&node).is_some_and(|hop| hop.arrival < at) {
+            continue;
+        }
+        let first_hop = best.get(&node).cloned();
+
+        for contact in &plan.contacts {
+            if contact.from != node || contact.to == local || contact.rate == 0 || contact.end <= at
+            {
+                continue;
+            }
+            let depart = at.max(contact.start);
+            let arrival = depart + plan.owlt(contact.from, contact.to, depart);
+            if best
+                .get(&contact.to)
+                .is_some_and(|hop| hop.arrival <= arrival)
+            {
+                continue;
+            }
+
+            let hop = match &first_hop {
+                // Leaving the local node, this contact is the first hop
+                None => Hop {
+                    via: contact.to,
+                    depart,
+                    arrival,
+                },
+                Some(first_hop) => Hop {
+                    arrival,
+                    ..first_hop.clone()
+                },
+            };
+            best.insert(contact.to, hop);
+            queue.push(Reverse((arrival, contact.to)));
+        }
+    }
+    best
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test() {
+        let now = time::OffsetDateTime::UNIX_EPOCH;
+        let plan = plan::ContactPlan::parse(
+            "a contact +0 +100 1 2 1000\n\
+             a contact +50 +100 2 3 1000\n\
+             a contact +200 +300 1 3 1000\n\
+             a contact +10 +20 1 4 1000\n\
+             a contact +30 +40 4 3 1000\n\
+             a contact +0 +100 3 1 1000\n\
+             a range +0 +1000 1 2 2\n",
+            now,
+        )
+        .unwrap();
+        let at = |secs| now + time::Duration::seconds(secs);
+
+        let routes = routes(&plan, 1, now);
+        assert_eq!(
+            routes[&2],
+            Hop {
+                via: 2,
+                depart: at(0),
+                arrival: at(2)
+            }
+        );
+
+        // Via 4 arrives at 30, sooner than via 2 at 50, or directly at 200
+        assert_eq!(
+            routes[&3],
+            Hop {
+                via: 4,
+                depart: at(10),
+                arrival: at(30)
+            }
+        );
+
+        // Later, the contacts to 4 are gone
+        let routes = super::routes(&plan, 1, at(25));
+        assert_eq!(routes[&3].via, 2);
+        assert_eq!(routes[&3].arrival, at(50));
+        assert!(!routes.contains_key(&4));
+
+        // And then only the direct contact is left
+        let routes = super::routes(&plan, 1, at(150));
+        assert_eq!(
+            routes[&3],
+            Hop {
+                via: 3,
+                depart: at(200),
+                arrival: at(200)
+            }
+        );
+        assert!(!routes.contains_key(&2));
+    }
+}
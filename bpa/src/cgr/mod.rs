@@ -0,0 +1,177 @@
+use super::*;
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+mod compute;
+mod plan;
+
+/* Contact Graph Routing: routes to every node in the contact plan are computed from
+ * the earliest arrival over the contacts, and installed in the FIB.  While the contact
+ * to the first hop is up, bundles go via that neighbour, which the CLAs resolve;
+ * until then they wait for it to come up.  Routes are recomputed whenever a contact or
+ * range starts or ends */
+
+#[derive(Clone, serde::Deserialize)]
+struct Config {
+    contact_plan: PathBuf,
+
+    #[serde(default = "Config::default_distance")]
+    distance: u32,
+
+    #[serde(default = "Config::default_protocol_id")]
+    protocol_id: String,
+}
+
+impl Config {
+    fn new(config: &::config::Config) -> Option<Self> {
+        utils::settings::get_with_default::<Option<Config>, _>(config, "cgr", None)
+            .trace_expect("Invalid 'cgr' section in configuration")
+    }
+
+    fn default_distance() -> u32 {
+        fib::DISTANCE_COMPUTED
+    }
+
+    fn default_protocol_id() -> String {
+        "cgr".to_string()
+    }
+}
+
+struct Cgr {
+    config: Config,
+    fib: fib::Fib,
+    local: utils::admin_endpoints::IpnNodeId,
+    plan: plan::ContactPlan,
+    routes: HashMap<u32, (bpv7::EidPattern, fib::Action)>,
+}
+
+impl Cgr {
+    fn action(&self, hop: &compute::Hop, now: time::OffsetDateTime) -> fib::Action {
+        if hop.depart <= now {
+            fib::Action::Via(self.local.with_node_number(hop.via).to_eid(0))
+        } else {
+            fib::Action::Wait(hop.depart)
+        }
+    }
+
+    async fn refresh_routes(&mut self, now: time::OffsetDateTime) {
+        let mut routes = HashMap::new();
+        for (node, hop) in compute::routes(&self.plan, self.local.node_number(), now) {
+            let pattern = self
+                .local
+                .with_node_number(node)
+                .to_pattern()
+                .trace_expect("Failed to build EID pattern for contact plan node");
+
+            // The cost is how long the bundle takes to arrive, to rank against other routes
+            let cost = (hop.arrival - now)
+                .whole_seconds()
+                .clamp(0, u32::MAX as i64) as u32;
+            routes.insert(node, (pattern, self.action(&hop, now), cost));
+        }
+
+        // Remove routes that have gone or changed
+        let previous = std::mem::take(&mut self.routes);
+        for (node, (pattern, action)) in previous {
+            match routes.get(&node) {
+                Some((_, new_action, _)) if *new_action == action => {
+                    self.routes.insert(node, (pattern, action));
+                }
+                _ => {
+                    self.fib.remove(&self.config.protocol_id, &pattern).await;
+                }
+            }
+        }
+
+        // Add the new ones
+        for (node, (pattern, action, cost)) in routes {
+            if self.routes.contains_key(&node) {
+                continue;
+            }
+            if let Err(e) = self
+                .fib
+                .add(
+                    self.config.protocol_id.clone(),
+                    &pattern,
+                    self.config.distance,
+                    cost,
+                    action.clone(),
+                    None,
+                )
+                .await
+            {
+                error!("Failed to insert contact graph route {pattern}: {e}");
+            } else {
+                self.routes.insert(node, (pattern, action));
+            }
+        }
+    }
+
+    async fn run(mut self, cancel_token: tokio_util::sync::CancellationToken) {
+        loop {
+            let now = utils::clock::now();
+            self.refresh_routes(now).await;
+
+            let Some(next) = self.plan.next_change(now) else {
+                info!("Contact plan has no further contacts");
+                break;
+            };
+            if !utils::clock::sleep(next - now, &cancel_token).await {
+                break;
+            }
+        }
+    }
+}
+
+#[instrument(skip_all)]
+pub async fn init(
+    config: &::config::Config,
+    admin_endpoints: &utils::admin_endpoints::AdminEndpoints,
+    fib: fib::Fib,
+    task_set: &mut tokio::task::JoinSet<()>,
+    cancel_token: tokio_util::sync::CancellationToken,
+) {
+    let Some(config) = Config::new(config) else {
+        info!("No contact plan configured");
+        return;
+    };
+
+    let Some(local) = admin_endpoints.ipn.clone() else {
+        error!("Contact Graph Routing requires an ipn administrative endpoint");
+        panic!("Contact Graph Routing requires an ipn administrative endpoint");
+    };
+
+    info!(
+        "Loading contact plan from '{}'",
+        config.contact_plan.to_string_lossy()
+    );
+    let s = tokio::fs::read_to_string(&config.contact_plan)
+        .await
+        .trace_expect(&format!(
+            "Failed to read contact plan '{}'",
+            config.contact_plan.to_string_lossy()
+        ));
+    let plan = match plan::ContactPlan::parse(&s, utils::clock::now()) {
+        Ok(plan) => plan,
+        Err((line, e)) => {
+            error!("Failed to parse contact plan at line {line}: {e}");
+            panic!("Failed to parse contact plan at line {line}: {e}");
+        }
+    };
+    info!(
+        "Contact plan has {} contacts and {} ranges",
+        plan.contacts.len(),
+        plan.ranges.len()
+    );
+
+    task_set.spawn(
+        Cgr {
+            config,
+            fib,
+            local,
+            plan,
+            routes: HashMap::new(),
+        }
+        .run(cancel_token),
+    );
+}
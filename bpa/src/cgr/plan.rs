@@ -0,0 +1,207 @@
+use super::*;
+use thiserror::Error;
+use time::macros::format_description;
+
+/* Contact plans in the format of ION's ionadmin:
+ *
+ *   a contact <start> <end> <from node> <to node> <rate in bytes/sec>
+ *   a range <start> <end> <from node> <to node> <one-way light time in secs>
+ *
+ * Times are either '+seconds', relative to when the plan is loaded, or absolute UTC
+ * times as 'yyyy/mm/dd-hh:mm:ss'.  A range applies in both directions unless the
+ * reverse direction has a range of its own.  Other commands are ignored */
+
+#[derive(Error, Debug)]
+pub enum ParseError {
+    #[error("Expecting a '{0}' parameter")]
+    MissingParameter(&'static str),
+
+    #[error("Unexpected parameter '{0}'")]
+    UnexpectedParameter(String),
+
+    #[error("Contact or range ends before it starts")]
+    Backwards,
+
+    #[error(transparent)]
+    Time(#[from] time::error::Parse),
+
+    #[error(transparent)]
+    Integer(#[from] std::num::ParseIntError),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Contact {
+    pub from: u32,
+    pub to: u32,
+    pub start: time::OffsetDateTime,
+    pub end: time::OffsetDateTime,
+    pub rate: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Range {
+    pub from: u32,
+    pub to: u32,
+    pub start: time::OffsetDateTime,
+    pub end: time::OffsetDateTime,
+    pub owlt: time::Duration,
+}
+
+#[derive(Debug, Default)]
+pub struct ContactPlan {
+    pub contacts: Vec<Contact>,
+    pub ranges: Vec<Range>,
+}
+
+impl ContactPlan {
+    // The one-way light time from one node to another at a time, zero if unknown
+    pub fn owlt(&self, from: u32, to: u32, at: time::OffsetDateTime) -> time::Duration {
+        let find = |from, to| {
+            self.ranges
+                .iter()
+                .find(|r| r.from == from && r.to == to && r.start <= at && r.end > at)
+        };
+        find(from, to)
+            .or_else(|| find(to, from))
+            .map_or(time::Duration::ZERO, |r| r.owlt)
+    }
+
+    // The first time after 'now' that a contact or range starts or ends
+    pub fn next_change(&self, now: time::OffsetDateTime) -> Option<time::OffsetDateTime> {
+        self.contacts
+            .iter()
+            .flat_map(|c| [c.start, c.end])
+            .chain(self.ranges.iter().flat_map(|r| [r.start, r.end]))
+            .filter(|t| *t > now)
+            .min()
+    }
+
+    pub fn parse(s: &str, loaded_at: time::OffsetDateTime) -> Result<Self, (usize, ParseError)> {
+        let mut plan = Self::default();
+        for (idx, line) in s.lines().enumerate() {
+            plan.parse_line(line, loaded_at).map_err(|e| (idx + 1, e))?;
+        }
+        Ok(plan)
+    }
+
+    fn parse_line(
+        &mut self,
+        line: &str,
+        loaded_at: time::OffsetDateTime,
+    ) -> Result<(), ParseError> {
+        let mut parts = line.split_whitespace();
+        match (parts.next(), parts.next()) {
+            (Some("a"), Some(kind @ ("contact" | "range"))) => {
+                let start = parse_time(parts.next(), "start", loaded_at)?;
+                let end = parse_time(parts.next(), "end", loaded_at)?;
+                let from = parts
+                    .next()
+                    .ok_or(ParseError::MissingParameter("from"))?
+                    .parse()?;
+                let to = parts
+                    .next()
+                    .ok_or(ParseError::MissingParameter("to"))?
+                    .parse()?;
+                let value: u64 = parts
+                    .next()
+                    .ok_or(ParseError::MissingParameter(if kind == "contact" {
+                        "rate"
+                    } else {
+                        "owlt"
+                    }))?
+                    .parse()?;
+                if let Some(s) = parts.next().filter(|s| !s.starts_with('#')) {
+                    return Err(ParseError::UnexpectedParameter(s.to_string()));
+                }
+                if end < start {
+                    return Err(ParseError::Backwards);
+                }
+
+                if kind == "contact" {
+                    self.contacts.push(Contact {
+                        from,
+                        to,
+                        start,
+                        end,
+                        rate: value,
+                    });
+                } else {
+                    self.ranges.push(Range {
+                        from,
+                        to,
+                        start,
+                        end,
+                        owlt: time::Duration::seconds(value.min(i64::MAX as u64) as i64),
+                    });
+                }
+                Ok(())
+            }
+            (None, _) => Ok(()),
+            (Some(s), _) if s.starts_with('#') => Ok(()),
+            _ => {
+                trace!("Ignoring contact plan command: {line}");
+                Ok(())
+            }
+        }
+    }
+}
+
+fn parse_time(
+    s: Option<&str>,
+    name: &'static str,
+    loaded_at: time::OffsetDateTime,
+) -> Result<time::OffsetDateTime, ParseError> {
+    let s = s.ok_or(ParseError::MissingParameter(name))?;
+    if let Some(secs) = s.strip_prefix('+') {
+        let secs: u64 = secs.parse()?;
+        Ok(loaded_at.saturating_add(time::Duration::seconds(secs.min(i64::MAX as u64) as i64)))
+    } else {
+        Ok(time::PrimitiveDateTime::parse(
+            s,
+            format_description!("[year]/[month]/[day]-[hour]:[minute]:[second]"),
+        )?
+        .assume_utc())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test() {
+        let now = time::OffsetDateTime::UNIX_EPOCH;
+        let plan = ContactPlan::parse(
+            "# A comment\n\
+             m horizon +0\n\
+             a contact +0 +3600 1 2 100000\n\
+             a contact 1970/01/01-02:00:00 1970/01/01-03:00:00 2 3 1000 # trailing\n\
+             a range +0 +7200 1 2 5\n",
+            now,
+        )
+        .unwrap();
+        assert_eq!(plan.contacts.len(), 2);
+        assert_eq!(plan.contacts[0].end, now + time::Duration::hours(1));
+        assert_eq!(plan.contacts[1].start, now + time::Duration::hours(2));
+        assert_eq!(plan.contacts[1].rate, 1000);
+
+        // Ranges apply in both directions
+        assert_eq!(plan.owlt(1, 2, now), time::Duration::seconds(5));
+        assert_eq!(plan.owlt(2, 1, now), time::Duration::seconds(5));
+        assert_eq!(plan.owlt(2, 3, now), time::Duration::ZERO);
+
+        assert_eq!(
+            plan.next_change(now + time::Duration::minutes(1)),
+            Some(now + time::Duration::hours(1))
+        );
+
+        assert!(matches!(
+            ContactPlan::parse("a contact +10 +0 1 2 1", now),
+            Err((1, ParseError::Backwards))
+        ));
+        assert!(matches!(
+            ContactPlan::parse("\na range +0 +10 1 2", now),
+            Err((2, ParseError::MissingParameter("owlt")))
+        ));
+    }
+}
@@ -6,41 +6,203 @@ use std::sync::Arc;
 use tokio::sync::{Mutex, RwLock};
 use tokio_util::bytes::Bytes;
 
+/* Most CLAs are separate processes, that register over gRPC and are sent bundles through
+ * their 'cla' service.  A CLA compiled into the BPA implements the Cla trait instead, the
+ * same contract as the 'cla' service, and is registered with 'register_local', which hands
+ * it a LocalSink to call in place of the 'cla_sink' service, saving the serialization and
+ * round trips.  In-process CLAs are not carried over a handover to a new instance, as the
+ * new instance registers its own.
+ *
+ * CLAs may say what they can do when they register.  The largest bundle a CLA can send caps
+ * the path MTU locally originated bundles are fragmented to fit, and larger bundles are not
+ * sent to it at all.  The cost and latency class of a CLA, then whether it is reliable, order
+ * equal cost routes through different CLAs in the FIB.
+ *
+ * With a 'cla_health' section, each gRPC CLA is called every 'interval' seconds to check it
+ * is alive.  A CLA that fails to answer is marked down in the FIB, so its routes are skipped
+ * at once rather than when a bundle next fails to forward, and the BPA reconnects to it with
+ * exponential backoff until it answers again.  A CLA that registers again is checked over
+ * its new channel instead */
+
 type Channel = Arc<Mutex<cla_client::ClaClient<tonic::transport::Channel>>>;
 
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LatencyClass {
+    Low,
+    #[default]
+    Medium,
+    High,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities {
+    // The largest bundle, in bytes, the CLA can send to any neighbour
+    pub max_bundle_size: Option<u64>,
+    // Whether the CLA confirms delivery to the next hop, rather than sending best effort
+    pub reliable: bool,
+    // Lower cost CLAs are preferred among equal cost routes
+    pub cost: u32,
+    pub latency: Option<LatencyClass>,
+}
+
+impl Capabilities {
+    fn preference(&self) -> fib::Preference {
+        fib::Preference {
+            cost: self.cost,
+            latency: self.latency.unwrap_or_default(),
+            best_effort: !self.reliable,
+        }
+    }
+}
+
+impl From<ClaCapabilities> for Capabilities {
+    fn from(capabilities: ClaCapabilities) -> Self {
+        Self {
+            max_bundle_size: capabilities.max_bundle_size,
+            reliable: capabilities.reliable,
+            cost: capabilities.cost,
+            latency: match capabilities.latency() {
+                cla_capabilities::LatencyClass::Unknown => None,
+                cla_capabilities::LatencyClass::Low => Some(LatencyClass::Low),
+                cla_capabilities::LatencyClass::Medium => Some(LatencyClass::Medium),
+                cla_capabilities::LatencyClass::High => Some(LatencyClass::High),
+            },
+        }
+    }
+}
+
+#[tonic::async_trait]
+pub trait Cla: Send + Sync {
+    // What the CLA can do, as the Capabilities of the gRPC RegisterCla call
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::default()
+    }
+
+    // Forward a bundle towards `destination`, via `address` if the BPA has resolved the next
+    // hop, as the gRPC ForwardBundle call.  The handle of a Pending result is ignored
+    async fn forward_bundle(
+        &self,
+        destination: &bpv7::Eid,
+        address: Option<String>,
+        bundle: Bytes,
+    ) -> Result<ForwardBundleResult, Error>;
+}
+
+#[derive(Clone)]
+enum Transport {
+    Grpc(Channel),
+    Local(Arc<dyn Cla>),
+}
+
 pub struct Endpoint {
-    inner: Channel,
+    inner: Transport,
     handle: u32,
+    capabilities: Capabilities,
+    cancel_token: tokio_util::sync::CancellationToken,
 }
 
-struct Cla {
+struct Registration {
     ident: String,
+    instance_id: String,
     name: String,
-    endpoint: Channel,
+    // The principal that registered the CLA, if registrations are authenticated
+    owner: Option<String>,
+    // Empty for in-process CLAs
+    grpc_address: String,
+    endpoint: Transport,
+    capabilities: Capabilities,
+    neighbours: Mutex<Vec<(bpv7::EidPattern, u32, Option<u64>)>>,
+    // Cancelled when the CLA registers again, abandoning forwarding over the old channel
+    cancel_token: tokio_util::sync::CancellationToken,
+}
+
+pub struct ClaStatus {
+    pub handle: u32,
+    pub name: String,
+    pub neighbours: usize,
+    pub healthy: bool,
 }
 
-/*#[derive(Clone)]
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+struct HealthConfig {
+    // In seconds
+    interval: u64,
+    // How long to wait for an answer or a connection, in seconds
+    timeout: u64,
+    // The longest wait between attempts to reconnect, in seconds
+    max_backoff: u64,
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        Self {
+            interval: 10,
+            timeout: 5,
+            max_backoff: 300,
+        }
+    }
+}
+
+// The CLA registry settings, as they appear in the configuration
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+struct Settings {
+    cla_health: Option<HealthConfig>,
+    cla_failover: bool,
+    cla_failover_retry: u32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            cla_health: None,
+            cla_failover: false,
+            cla_failover_retry: 30,
+        }
+    }
+}
+
+#[derive(Clone)]
 struct Config {
+    failover: bool,
+    // How long a CLA that failed to forward stays marked down, unless heard from sooner
+    failover_retry: time::Duration,
+    health: Option<HealthConfig>,
 }
 
 impl Config {
     fn new(config: &config::Config) -> Self {
+        let settings: Settings = utils::settings::load(config, "CLA registry");
+        let health = settings.cla_health;
+        if let Some(health) = &health {
+            if health.interval == 0 || health.timeout == 0 {
+                error!("'cla_health' requires a non-zero 'interval' and 'timeout'");
+                panic!("'cla_health' requires a non-zero 'interval' and 'timeout'");
+            }
+            info!("Checking the health of CLAs every {}s", health.interval);
+        }
+
         Self {
+            // Marking CLAs down is the point of checking their health
+            failover: health.is_some() || settings.cla_failover,
+            failover_retry: time::Duration::seconds(settings.cla_failover_retry.into()),
+            health,
         }
     }
-}*/
+}
 
 #[derive(Clone)]
 pub struct ClaRegistry {
-    //config: Config,
-    clas: Arc<RwLock<HashMap<u32, Arc<Cla>>>>,
+    config: Config,
+    clas: Arc<RwLock<HashMap<u32, Arc<Registration>>>>,
     fib: Option<fib::Fib>,
 }
 
 impl ClaRegistry {
-    pub fn new(_config: &config::Config, fib: Option<fib::Fib>) -> Self {
+    pub fn new(config: &config::Config, fib: Option<fib::Fib>) -> Self {
         Self {
-            //config: Config::new(config),
+            config: Config::new(config),
             fib,
             clas: Arc::new(RwLock::new(HashMap::new())),
         }
@@ -50,6 +212,7 @@ impl ClaRegistry {
     pub async fn register(
         &self,
         request: RegisterClaRequest,
+        owner: Option<String>,
     ) -> Result<RegisterClaResponse, tonic::Status> {
         // Connect to client gRPC address
         let endpoint = Arc::new(Mutex::new(
@@ -64,39 +227,157 @@ impl ClaRegistry {
                 })?,
         ));
 
+        let capabilities = request
+            .capabilities
+            .map(Capabilities::from)
+            .unwrap_or_default();
+
         let mut clas = self.clas.write().await;
 
-        // Compose a handle
-        let mut rng = rand::thread_rng();
-        let mut handle = rng.gen::<std::num::NonZeroU32>().into();
+        // Do a linear search for re-registration with the same identity
+        let previous = clas
+            .iter()
+            .find(|(_, cla)| {
+                cla.ident == request.ident
+                    && cla.instance_id == request.instance_id
+                    && matches!(cla.endpoint, Transport::Grpc(_))
+            })
+            .map(|(handle, cla)| (*handle, cla.clone()));
+
+        let Some((handle, previous)) = previous else {
+            let handle = new_handle(&clas);
+
+            info!("Registered new CLA: {}/{}", request.name, request.ident);
+            self.invalidate_fib();
+
+            let cla = Arc::new(Registration {
+                ident: request.ident,
+                instance_id: request.instance_id,
+                name: request.name,
+                owner,
+                grpc_address: request.grpc_address,
+                endpoint: Transport::Grpc(endpoint),
+                capabilities,
+                neighbours: Default::default(),
+                cancel_token: Default::default(),
+            });
+            self.set_preference(handle, Some(&capabilities)).await;
+            self.watch_health(handle, &cla);
+            clas.insert(handle, cla);
+            return Ok(RegisterClaResponse { handle });
+        };
 
-        // Check handle is unique
-        while clas.contains_key(&handle) {
-            handle = rng.gen::<std::num::NonZeroU32>().into();
+        // Only the principal that registered the CLA may replace it, or another could take
+        // over its neighbour routes.  Registrations restored from older instances have no
+        // recorded owner
+        if previous.owner.is_some() && previous.owner != owner {
+            warn!(
+                target: "audit",
+                principal = owner.as_deref().unwrap_or_default(),
+                cla = request.name.as_str(),
+                "CLA re-registration refused, the CLA was registered by another principal"
+            );
+            return Err(tonic::Status::permission_denied(format!(
+                "The CLA {}/{} was registered by another principal",
+                previous.name, previous.ident
+            )));
         }
 
-        // Do a linear search for re-registration with the same name
-        for cla in clas.values() {
-            if cla.ident != request.ident {
-                return Err(tonic::Status::already_exists(format!(
-                    "CLA {} already registered",
-                    request.ident
-                )));
+        // The CLA has restarted, so replace the old registration, keeping the handle so
+        // FIB entries and bundles awaiting forwarding confirmation remain valid
+        previous.cancel_token.cancel();
+
+        let neighbours = std::mem::take(&mut *previous.neighbours.lock().await);
+        if previous.name != request.name {
+            if let Some(fib) = &self.fib {
+                // Move the neighbour routes to the new name
+                for (neighbour, priority, mtu) in &neighbours {
+                    fib.remove(&format!("cla:{}", previous.name), neighbour)
+                        .await;
+                    fib.add(
+                        format!("cla:{}", request.name),
+                        neighbour,
+                        fib::DISTANCE_NEIGHBOUR,
+                        *priority,
+                        fib::Action::Forward(fib::Endpoint {
+                            handle,
+                            address: None,
+                            mtu: *mtu,
+                            latency: None,
+                        }),
+                        None,
+                    )
+                    .await
+                    .map_err(tonic::Status::from_error)?;
+                }
             }
         }
 
-        info!("Registered new CLA: {}/{}", request.name, request.ident);
+        info!(
+            "Re-registered CLA: {}/{}, replacing {}/{}",
+            request.name, request.ident, previous.name, previous.ident
+        );
 
-        let cla = Arc::new(Cla {
+        let cla = Arc::new(Registration {
             ident: request.ident,
+            instance_id: request.instance_id,
             name: request.name,
-            endpoint,
+            owner,
+            grpc_address: request.grpc_address,
+            endpoint: Transport::Grpc(endpoint),
+            capabilities,
+            neighbours: Mutex::new(neighbours),
+            cancel_token: Default::default(),
         });
+        self.set_preference(handle, Some(&capabilities)).await;
+        self.watch_health(handle, &cla);
+        clas.insert(handle, cla);
+
+        // The CLA is clearly working again
+        self.set_health(handle, true).await;
+        self.invalidate_fib();
 
-        clas.insert(handle, cla.clone());
         Ok(RegisterClaResponse { handle })
     }
 
+    // Register a CLA compiled into the BPA, which is sent bundles by calling it directly
+    #[instrument(skip(self, cla, dispatcher))]
+    pub async fn register_local(
+        &self,
+        ident: &str,
+        name: &str,
+        cla: Arc<dyn Cla>,
+        dispatcher: Arc<dispatcher::Dispatcher>,
+    ) -> LocalSink {
+        let mut clas = self.clas.write().await;
+        let handle = new_handle(&clas);
+
+        info!("Registered new in-process CLA: {name}/{ident}");
+        self.invalidate_fib();
+
+        let capabilities = cla.capabilities();
+        self.set_preference(handle, Some(&capabilities)).await;
+        clas.insert(
+            handle,
+            Arc::new(Registration {
+                ident: ident.to_string(),
+                instance_id: String::new(),
+                name: name.to_string(),
+                owner: None,
+                grpc_address: String::new(),
+                endpoint: Transport::Local(cla),
+                capabilities,
+                neighbours: Default::default(),
+                cancel_token: Default::default(),
+            }),
+        );
+        LocalSink {
+            handle,
+            cla_registry: self.clone(),
+            dispatcher,
+        }
+    }
+
     #[instrument(skip(self))]
     pub async fn unregister(
         &self,
@@ -104,12 +385,28 @@ impl ClaRegistry {
     ) -> Result<UnregisterClaResponse, tonic::Status> {
         let mut clas = self.clas.write().await;
 
-        clas.remove(&request.handle)
-            .ok_or(tonic::Status::not_found("No such CLA registered"))
-            .map(|cla| {
-                info!("Unregistered CLA: {}/{}", cla.name, cla.ident);
-                UnregisterClaResponse {}
-            })
+        let cla = clas
+            .remove(&request.handle)
+            .ok_or(tonic::Status::not_found("No such CLA registered"))?;
+        cla.cancel_token.cancel();
+        info!("Unregistered CLA: {}/{}", cla.name, cla.ident);
+        self.set_preference(request.handle, None).await;
+        self.invalidate_fib();
+        Ok(UnregisterClaResponse {})
+    }
+
+    async fn set_preference(&self, handle: u32, capabilities: Option<&Capabilities>) {
+        if let Some(fib) = &self.fib {
+            fib.set_preference(handle, capabilities.map(Capabilities::preference))
+                .await
+        }
+    }
+
+    // Lookups cached by the FIB may name CLAs that have come or gone
+    fn invalidate_fib(&self) {
+        if let Some(fib) = &self.fib {
+            fib.invalidate();
+        }
     }
 
     #[instrument(skip(self))]
@@ -121,14 +418,175 @@ impl ClaRegistry {
         }
     }
 
+    #[instrument(skip(self))]
+    pub async fn set_health(&self, handle: u32, healthy: bool) {
+        if !self.config.failover {
+            return;
+        }
+
+        let Some(fib) = &self.fib else {
+            return;
+        };
+        if !fib.set_health(handle, healthy).await || healthy {
+            return;
+        }
+
+        // Try the CLA again later, in case nothing else shows it has recovered
+        let Some(cancel_token) = self
+            .clas
+            .read()
+            .await
+            .get(&handle)
+            .map(|cla| cla.cancel_token.clone())
+        else {
+            return;
+        };
+        let retry = self.config.failover_retry;
+        let fib = fib.clone();
+        tokio::spawn(async move {
+            if utils::clock::sleep(retry, &cancel_token).await {
+                trace!("Retrying CLA {handle} after {retry}");
+                fib.set_health(handle, true).await;
+            }
+        });
+    }
+
+    // Check a gRPC CLA answers until it registers again or unregisters, marking it down and
+    // reconnecting to it with exponential backoff when it does not
+    fn watch_health(&self, handle: u32, cla: &Registration) {
+        let Some(config) = self.config.health.clone() else {
+            return;
+        };
+        let Transport::Grpc(channel) = cla.endpoint.clone() else {
+            return;
+        };
+        let name = cla.name.clone();
+        let grpc_address = cla.grpc_address.clone();
+        let cancel_token = cla.cancel_token.clone();
+        let cla_registry = self.clone();
+
+        tokio::spawn(async move {
+            let interval = time::Duration::seconds(config.interval as i64);
+            let max_backoff = time::Duration::seconds(config.max_backoff.max(1) as i64);
+            let timeout = std::time::Duration::from_secs(config.timeout);
+            let mut backoff: Option<time::Duration> = None;
+            loop {
+                if !utils::clock::sleep(backoff.unwrap_or(interval), &cancel_token).await {
+                    break;
+                }
+
+                if backoff.is_some() {
+                    // The CLA may have restarted at the same address without registering again
+                    match connect(&grpc_address, timeout).await {
+                        Ok(client) => *channel.lock().await = client,
+                        Err(e) => {
+                            trace!("Failed to reconnect to CLA {name} at {grpc_address}: {e}");
+                            backoff = backoff
+                                .map(|b: time::Duration| b.saturating_mul(2).min(max_backoff));
+                            continue;
+                        }
+                    }
+                }
+
+                match check_health(&channel, handle, timeout).await {
+                    Ok(()) => {
+                        if backoff.take().is_some() {
+                            info!("CLA {name} is answering again");
+                        }
+
+                        // It may have been marked down for failing to forward
+                        cla_registry.set_health(handle, true).await;
+                    }
+                    Err(e) => {
+                        if cancel_token.is_cancelled() {
+                            break;
+                        }
+                        metrics::counter!("cla_health_checks_failed_total", "cla" => name.clone())
+                            .increment(1);
+                        backoff = match backoff {
+                            None => {
+                                warn!("CLA {name} failed its health check: {e}");
+                                cla_registry.set_health(handle, false).await;
+                                Some(time::Duration::SECOND.min(max_backoff))
+                            }
+                            Some(b) => Some(b.saturating_mul(2).min(max_backoff)),
+                        };
+                    }
+                }
+            }
+        });
+    }
+
+    #[instrument(skip(self))]
+    pub async fn find_by_name(&self, name: &str) -> Option<u32> {
+        self.clas
+            .read()
+            .await
+            .iter()
+            .find(|(_, cla)| cla.name == name)
+            .map(|(handle, _)| *handle)
+    }
+
+    pub async fn name(&self, handle: u32) -> Option<String> {
+        self.clas
+            .read()
+            .await
+            .get(&handle)
+            .map(|cla| cla.name.clone())
+    }
+
     #[instrument(skip(self))]
     pub async fn find(&self, handle: u32) -> Option<Endpoint> {
         self.clas.read().await.get(&handle).map(|cla| Endpoint {
             handle,
             inner: cla.endpoint.clone(),
+            capabilities: cla.capabilities,
+            cancel_token: cla.cancel_token.clone(),
         })
     }
 
+    // The registered CLAs, ordered by name
+    pub async fn status(&self) -> Vec<ClaStatus> {
+        let clas = self
+            .clas
+            .read()
+            .await
+            .iter()
+            .map(|(handle, cla)| (*handle, cla.clone()))
+            .collect::<Vec<_>>();
+        let mut status = Vec::new();
+        for (handle, cla) in clas {
+            let healthy = match &self.fib {
+                Some(fib) => !fib.is_down(handle).await,
+                None => true,
+            };
+            status.push(ClaStatus {
+                handle,
+                name: cla.name.clone(),
+                neighbours: cla.neighbours.lock().await.len(),
+                healthy,
+            });
+        }
+        status.sort_by(|a, b| a.name.cmp(&b.name).then(a.handle.cmp(&b.handle)));
+        status
+    }
+
+    // The neighbours added by every CLA
+    pub async fn neighbours(&self) -> Vec<bpv7::EidPattern> {
+        let clas = self.clas.read().await.values().cloned().collect::<Vec<_>>();
+        let mut neighbours = Vec::new();
+        for cla in clas {
+            neighbours.extend(
+                cla.neighbours
+                    .lock()
+                    .await
+                    .iter()
+                    .map(|(pattern, _, _)| pattern.clone()),
+            );
+        }
+        neighbours
+    }
+
     #[instrument(skip(self))]
     pub async fn add_neighbour(&self, request: AddNeighbourRequest) -> Result<(), tonic::Status> {
         let cla = self
@@ -139,22 +597,42 @@ impl ClaRegistry {
             .ok_or(tonic::Status::not_found("No such CLA registered"))?
             .clone();
 
-        let Some(fib) = &self.fib else {
-            return Ok(());
-        };
-
         let neighbour = request
             .neighbour
             .parse::<bpv7::EidPattern>()
             .map_err(|e| tonic::Status::invalid_argument(e.to_string()))?;
 
+        // Remember the neighbour, in case we hand over to a new instance.  A neighbour added
+        // again, as a CLA does after a handover, replaces the one already known
+        let replaced = {
+            let mut neighbours = cla.neighbours.lock().await;
+            let replaced = neighbours.len();
+            neighbours.retain(|(pattern, _, _)| pattern != &neighbour);
+            let replaced = neighbours.len() != replaced;
+            neighbours.push((neighbour.clone(), request.priority, request.mtu));
+            replaced
+        };
+
+        let Some(fib) = &self.fib else {
+            return Ok(());
+        };
+
+        let id = format!("cla:{}", cla.name);
+        if replaced {
+            fib.remove(&id, &neighbour).await;
+        }
         fib.add(
-            format!("cla:{}", cla.name),
+            id,
             &neighbour,
+            fib::DISTANCE_NEIGHBOUR,
             request.priority,
             fib::Action::Forward(fib::Endpoint {
                 handle: request.handle,
+                address: None,
+                mtu: request.mtu,
+                latency: None,
             }),
+            None,
         )
         .await
         .map_err(tonic::Status::from_error)
@@ -182,6 +660,11 @@ impl ClaRegistry {
             .parse::<bpv7::EidPattern>()
             .map_err(|e| tonic::Status::invalid_argument(e.to_string()))?;
 
+        cla.neighbours
+            .lock()
+            .await
+            .retain(|(pattern, _, _)| pattern != &neighbour);
+
         if fib
             .remove(&format!("cla:{}", cla.name), &neighbour)
             .await
@@ -194,6 +677,215 @@ impl ClaRegistry {
     }
 }
 
+// A random, unused, non-zero handle
+fn new_handle(clas: &HashMap<u32, Arc<Registration>>) -> u32 {
+    utils::random::with_rng(|rng| loop {
+        let handle: u32 = rng.gen::<std::num::NonZeroU32>().into();
+        if !clas.contains_key(&handle) {
+            return handle;
+        }
+    })
+}
+
+async fn connect(
+    grpc_address: &str,
+    timeout: std::time::Duration,
+) -> Result<cla_client::ClaClient<tonic::transport::Channel>, Error> {
+    Ok(cla_client::ClaClient::new(
+        tonic::transport::Endpoint::from_shared(grpc_address.to_string())?
+            .connect_timeout(timeout)
+            .connect()
+            .await?,
+    ))
+}
+
+// Call the CLA's CheckHealth, on a clone of the client so as not to wait behind forwarding
+async fn check_health(
+    channel: &Channel,
+    handle: u32,
+    timeout: std::time::Duration,
+) -> Result<(), Error> {
+    let mut client = channel.lock().await.clone();
+    tokio::time::timeout(timeout, client.check_health(CheckHealthRequest { handle }))
+        .await
+        .map_err(|_| "No answer")??;
+    Ok(())
+}
+
+// What an in-process CLA calls, in place of the 'cla_sink' gRPC service
+#[derive(Clone)]
+pub struct LocalSink {
+    handle: u32,
+    cla_registry: ClaRegistry,
+    dispatcher: Arc<dispatcher::Dispatcher>,
+}
+
+impl LocalSink {
+    pub fn handle(&self) -> u32 {
+        self.handle
+    }
+
+    pub async fn receive_bundle(
+        &self,
+        data: Bytes,
+    ) -> Result<Option<dispatcher::Rejection>, Error> {
+        self.cla_registry.exists(self.handle).await?;
+
+        // The CLA is clearly working
+        self.cla_registry.set_health(self.handle, true).await;
+
+        // Account for the bundle while we hold it, shedding if we are holding too much
+        let Some(_reservation) =
+            utils::memory::try_reserve(utils::memory::Subsystem::Ingress, data.len())
+        else {
+            return Err("Ingress memory limit reached".into());
+        };
+
+        self.dispatcher
+            .record_ingress(Some(self.handle), &[], &data)
+            .await;
+        self.dispatcher.receive_bundle(data).await
+    }
+
+    pub async fn confirm_forwarding(&self, bundle_id: &str) -> Result<(), Error> {
+        self.cla_registry.exists(self.handle).await?;
+        self.cla_registry.set_health(self.handle, true).await;
+        Ok(self
+            .dispatcher
+            .confirm_forwarding(self.handle, bundle_id)
+            .await?)
+    }
+
+    pub async fn add_neighbour(
+        &self,
+        neighbour: &str,
+        priority: u32,
+        mtu: Option<u64>,
+    ) -> Result<(), Error> {
+        Ok(self
+            .cla_registry
+            .add_neighbour(AddNeighbourRequest {
+                handle: self.handle,
+                priority,
+                neighbour: neighbour.to_string(),
+                mtu,
+            })
+            .await?)
+    }
+
+    pub async fn remove_neighbour(&self, neighbour: &str) -> Result<(), Error> {
+        Ok(self
+            .cla_registry
+            .remove_neighbour(RemoveNeighbourRequest {
+                handle: self.handle,
+                neighbour: neighbour.to_string(),
+            })
+            .await?)
+    }
+
+    pub async fn unregister(self) -> Result<(), Error> {
+        self.cla_registry
+            .unregister(UnregisterClaRequest {
+                handle: self.handle,
+            })
+            .await?;
+        Ok(())
+    }
+}
+
+impl ClaRegistry {
+    pub async fn snapshot(&self) -> Vec<handoff::ClaState> {
+        let mut state = Vec::new();
+        for (handle, cla) in self.clas.read().await.iter() {
+            // The new instance registers its own in-process CLAs
+            if let Transport::Local(_) = cla.endpoint {
+                continue;
+            }
+            state.push(handoff::ClaState {
+                handle: *handle,
+                ident: cla.ident.clone(),
+                instance_id: cla.instance_id.clone(),
+                name: cla.name.clone(),
+                owner: cla.owner.clone(),
+                grpc_address: cla.grpc_address.clone(),
+                capabilities: cla.capabilities,
+                neighbours: cla
+                    .neighbours
+                    .lock()
+                    .await
+                    .iter()
+                    .map(|(pattern, priority, mtu)| (pattern.to_string(), *priority, *mtu))
+                    .collect(),
+            });
+        }
+        state
+    }
+
+    #[instrument(skip_all)]
+    pub async fn restore(&self, state: Vec<handoff::ClaState>) {
+        let mut clas = self.clas.write().await;
+        for cla in state {
+            // Connect lazily, so an unresponsive CLA does not hold up the handover
+            let endpoint = match tonic::transport::Endpoint::from_shared(cla.grpc_address.clone()) {
+                Ok(endpoint) => Arc::new(Mutex::new(cla_client::ClaClient::new(
+                    endpoint.connect_lazy(),
+                ))),
+                Err(e) => {
+                    warn!(
+                        "Failed to restore CLA {}/{} at {}: {e}",
+                        cla.name, cla.ident, cla.grpc_address
+                    );
+                    continue;
+                }
+            };
+
+            let mut neighbours = Vec::new();
+            for (neighbour, priority, mtu) in cla.neighbours {
+                let Ok(neighbour) = neighbour.parse::<bpv7::EidPattern>() else {
+                    warn!("Invalid neighbour {neighbour} for CLA {}", cla.name);
+                    continue;
+                };
+                if let Some(fib) = &self.fib {
+                    fib.add(
+                        format!("cla:{}", cla.name),
+                        &neighbour,
+                        fib::DISTANCE_NEIGHBOUR,
+                        priority,
+                        fib::Action::Forward(fib::Endpoint {
+                            handle: cla.handle,
+                            address: None,
+                            mtu,
+                            latency: None,
+                        }),
+                        None,
+                    )
+                    .await
+                    .trace_expect("Failed to restore neighbour");
+                }
+                neighbours.push((neighbour, priority, mtu));
+            }
+
+            info!("Restored CLA: {}/{}", cla.name, cla.ident);
+
+            let handle = cla.handle;
+            let cla = Arc::new(Registration {
+                ident: cla.ident,
+                instance_id: cla.instance_id,
+                name: cla.name,
+                owner: cla.owner,
+                grpc_address: cla.grpc_address,
+                endpoint: Transport::Grpc(endpoint),
+                capabilities: cla.capabilities,
+                neighbours: Mutex::new(neighbours),
+                cancel_token: Default::default(),
+            });
+            self.set_preference(handle, Some(&cla.capabilities)).await;
+            self.watch_health(handle, &cla);
+            clas.insert(handle, cla);
+        }
+    }
+}
+
 pub enum ForwardBundleResult {
     Sent,
     Pending(u32, Option<time::OffsetDateTime>),
@@ -201,23 +893,58 @@ pub enum ForwardBundleResult {
 }
 
 impl Endpoint {
+    pub fn capabilities(&self) -> &Capabilities {
+        &self.capabilities
+    }
+
     #[instrument(skip(self))]
     pub async fn forward_bundle(
         &self,
         destination: &bpv7::Eid,
+        address: Option<String>,
         bundle: Bytes,
     ) -> Result<ForwardBundleResult, Error> {
-        let r = self
-            .inner
-            .lock()
-            .await
-            .forward_bundle(tonic::Request::new(ForwardBundleRequest {
-                handle: self.handle,
-                destination: destination.to_string(),
-                bundle,
-            }))
-            .await?
-            .into_inner();
+        let channel = match &self.inner {
+            Transport::Grpc(channel) => channel,
+            Transport::Local(cla) => {
+                return cla
+                    .forward_bundle(destination, address, bundle)
+                    .await
+                    .map(|r| match r {
+                        ForwardBundleResult::Pending(_, until) => {
+                            ForwardBundleResult::Pending(self.handle, until)
+                        }
+                        r => r,
+                    });
+            }
+        };
+
+        #[allow(unused_mut)]
+        let mut request = tonic::Request::new(ForwardBundleRequest {
+            handle: self.handle,
+            destination: destination.to_string(),
+            address,
+            bundle,
+        });
+
+        // Let the CLA continue the trace
+        #[cfg(feature = "otlp")]
+        utils::otlp::inject(request.metadata_mut());
+
+        let r = tokio::select! {
+            r = async {
+                channel
+                    .lock()
+                    .await
+                    .forward_bundle(request)
+                    .await
+            } => r?.into_inner(),
+            _ = self.cancel_token.cancelled() => {
+                // The CLA has re-registered, so retry straight away over the new channel
+                trace!("CLA re-registered while forwarding");
+                return Ok(ForwardBundleResult::Congested(utils::clock::now()));
+            }
+        };
 
         let delay = if let Some(t) = r.delay {
             Some(grpc::from_timestamp(t)?)
@@ -237,9 +964,7 @@ impl Endpoint {
                 if let Some(delay) = delay {
                     Ok(ForwardBundleResult::Congested(delay))
                 } else {
-                    Ok(ForwardBundleResult::Congested(
-                        time::OffsetDateTime::now_utc(),
-                    ))
+                    Ok(ForwardBundleResult::Congested(utils::clock::now()))
                 }
             }
             v => {
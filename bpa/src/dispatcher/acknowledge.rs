@@ -0,0 +1,220 @@
+use super::*;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/* Each bundle collected by, or pushed to, an application is given a delivery id, unique to
+ * the node and increasing with each delivery, so an application can recognise the bundles
+ * it has already processed by the highest id it has processed, rather than by BundleId.
+ * Applications registered for acknowledged delivery acknowledge each bundle by its delivery
+ * id once processed, and until then the bundle remains pending collection, and is
+ * delivered again with the same id, e.g. after the application restarts.  Delivery ids are
+ * only held in memory, so a bundle still unacknowledged when the BPA restarts is delivered
+ * again with a new id.  The first id is the time in microseconds, so ids keep increasing
+ * across restarts of the BPA as well */
+
+#[derive(Default)]
+struct Pending {
+    by_id: HashMap<u64, (bpv7::BundleId, Option<String>)>,
+    // The delivery ids of each bundle, by multicast registration
+    by_bundle: HashMap<bpv7::BundleId, Vec<(Option<String>, u64)>>,
+}
+
+pub struct DeliveryIds {
+    next: AtomicU64,
+    pending: std::sync::Mutex<Pending>,
+}
+
+impl Default for DeliveryIds {
+    fn default() -> Self {
+        Self::starting_at(
+            (time::OffsetDateTime::now_utc().unix_timestamp_nanos() / 1000).max(1) as u64,
+        )
+    }
+}
+
+impl DeliveryIds {
+    fn starting_at(first: u64) -> Self {
+        Self {
+            next: AtomicU64::new(first),
+            pending: Default::default(),
+        }
+    }
+
+    // The delivery id of the bundle to the registration, the same until it is forgotten
+    fn assign(&self, bundle_id: &bpv7::BundleId, registration: &Option<String>) -> u64 {
+        let mut pending = self.pending.lock().trace_expect("Failed to lock mutex");
+        if let Some(delivery_id) = self.find(&pending, bundle_id, registration) {
+            return delivery_id;
+        }
+
+        let delivery_id = self.next.fetch_add(1, Ordering::Relaxed);
+        pending
+            .by_id
+            .insert(delivery_id, (bundle_id.clone(), registration.clone()));
+        pending
+            .by_bundle
+            .entry(bundle_id.clone())
+            .or_default()
+            .push((registration.clone(), delivery_id));
+        delivery_id
+    }
+
+    fn find(
+        &self,
+        pending: &Pending,
+        bundle_id: &bpv7::BundleId,
+        registration: &Option<String>,
+    ) -> Option<u64> {
+        pending.by_bundle.get(bundle_id).and_then(|deliveries| {
+            deliveries
+                .iter()
+                .find(|(r, _)| r == registration)
+                .map(|(_, delivery_id)| *delivery_id)
+        })
+    }
+
+    fn pending_id(&self, bundle_id: &bpv7::BundleId, registration: &Option<String>) -> Option<u64> {
+        let pending = self.pending.lock().trace_expect("Failed to lock mutex");
+        self.find(&pending, bundle_id, registration)
+    }
+
+    fn contains(&self, delivery_id: u64) -> bool {
+        self.pending
+            .lock()
+            .trace_expect("Failed to lock mutex")
+            .by_id
+            .contains_key(&delivery_id)
+    }
+
+    fn get(&self, delivery_id: u64) -> Option<(bpv7::BundleId, Option<String>)> {
+        self.pending
+            .lock()
+            .trace_expect("Failed to lock mutex")
+            .by_id
+            .get(&delivery_id)
+            .cloned()
+    }
+
+    fn remove(&self, delivery_id: u64) {
+        let mut pending = self.pending.lock().trace_expect("Failed to lock mutex");
+        let Some((bundle_id, _)) = pending.by_id.remove(&delivery_id) else {
+            return;
+        };
+        if let Some(deliveries) = pending.by_bundle.get_mut(&bundle_id) {
+            deliveries.retain(|(_, id)| *id != delivery_id);
+            if deliveries.is_empty() {
+                pending.by_bundle.remove(&bundle_id);
+            }
+        }
+    }
+
+    fn forget(&self, bundle_id: &bpv7::BundleId) {
+        let mut pending = self.pending.lock().trace_expect("Failed to lock mutex");
+        if let Some(deliveries) = pending.by_bundle.remove(bundle_id) {
+            for (_, delivery_id) in deliveries {
+                pending.by_id.remove(&delivery_id);
+            }
+        }
+    }
+}
+
+impl Dispatcher {
+    pub(super) fn assign_delivery_id(
+        &self,
+        bundle_id: &bpv7::BundleId,
+        registration: &Option<String>,
+    ) -> u64 {
+        self.delivery_ids.assign(bundle_id, registration)
+    }
+
+    // The delivery id of a bundle delivered but not yet acknowledged
+    pub(super) fn pending_delivery_id(
+        &self,
+        bundle_id: &bpv7::BundleId,
+        registration: &Option<String>,
+    ) -> Option<u64> {
+        self.delivery_ids.pending_id(bundle_id, registration)
+    }
+
+    pub(super) fn is_delivery_pending(&self, delivery_id: u64) -> bool {
+        self.delivery_ids.contains(delivery_id)
+    }
+
+    pub(super) fn forget_delivery_ids(&self, bundle_id: &bpv7::BundleId) {
+        self.delivery_ids.forget(bundle_id);
+    }
+
+    // Take delivery of the bundles the application has processed.  Ids that are unknown,
+    // already acknowledged or delivered to another application are ignored
+    #[instrument(skip(self))]
+    pub async fn acknowledge(
+        &self,
+        destination: bpv7::Eid,
+        registration: Option<String>,
+        delivery_ids: Vec<u64>,
+    ) -> Result<(), Error> {
+        for delivery_id in delivery_ids {
+            let Some((bundle_id, r)) = self.delivery_ids.get(delivery_id) else {
+                trace!("Delivery {delivery_id} is not pending acknowledgement");
+                continue;
+            };
+            if r != registration {
+                continue;
+            }
+
+            let Some(bundle) = self.store.load(&bundle_id).await? else {
+                self.delivery_ids.remove(delivery_id);
+                continue;
+            };
+            if bundle.bundle.destination != destination {
+                continue;
+            }
+            self.delivery_ids.remove(delivery_id);
+
+            if let metadata::BundleStatus::CollectionPending = &bundle.metadata.status {
+                metrics::counter!("bundles_acknowledged_total").increment(1);
+                self.delivered(bundle, registration.clone()).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bundle_id(n: u64) -> bpv7::BundleId {
+        bpv7::BundleId {
+            source: "ipn:1.1".parse().unwrap(),
+            timestamp: bpv7::CreationTimestamp {
+                creation_time: Some(bpv7::DtnTime::new(1000)),
+                sequence_number: n,
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn delivery_ids() {
+        let ids = DeliveryIds::starting_at(10);
+        let member = Some("member".to_string());
+
+        // Stable until acknowledged, and distinct per registration
+        assert_eq!(ids.assign(&bundle_id(1), &None), 10);
+        assert_eq!(ids.assign(&bundle_id(2), &None), 11);
+        assert_eq!(ids.assign(&bundle_id(1), &None), 10);
+        assert_eq!(ids.assign(&bundle_id(1), &member), 12);
+        assert_eq!(ids.get(12), Some((bundle_id(1), member.clone())));
+
+        ids.remove(10);
+        assert!(!ids.contains(10));
+        assert_eq!(ids.pending_id(&bundle_id(1), &member), Some(12));
+        assert_eq!(ids.assign(&bundle_id(1), &None), 13);
+
+        // Forgetting a bundle forgets every delivery of it
+        ids.forget(&bundle_id(1));
+        assert!(!ids.contains(12) && !ids.contains(13));
+        assert!(ids.contains(11));
+    }
+}
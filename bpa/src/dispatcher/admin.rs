@@ -19,14 +19,44 @@ impl Dispatcher {
             return Ok(DispatchResult::Done);
         };
 
-        match cbor::decode::parse(data.as_ref().as_ref()) {
+        // Signalling bundles may have an empty payload, but an administrative record cannot
+        let record = match bundle
+            .bundle
+            .blocks
+            .get(&1)
+            .map(|block| block.block_data(data.as_ref().as_ref()))
+        {
+            Some(Ok(record)) if !record.is_empty() => record,
+            _ => {
+                trace!("Administrative record has an empty or unreadable payload");
+                return Ok(DispatchResult::Drop(Some(
+                    bpv7::StatusReportReasonCode::BlockUnintelligible,
+                )));
+            }
+        };
+
+        match cbor::decode::parse(&record) {
             Err(e) => {
                 trace!("Failed to parse administrative record: {e}");
                 Ok(DispatchResult::Drop(Some(
                     bpv7::StatusReportReasonCode::BlockUnintelligible,
                 )))
             }
+            Ok(bpv7::AdministrativeRecord::BundleInBundle(record)) => {
+                // A bundle has come out of a tunnel that ends here
+                self.decapsulate_bundle(bundle, record).await
+            }
+            Ok(bpv7::AdministrativeRecord::Diagnostic(record)) => {
+                // A peer is telling us about a problem with a bundle we sent
+                warn!(
+                    "Diagnostic from {} regarding bundle {:?}: {}",
+                    bundle.bundle.id.source, record.bundle_id, record.message
+                );
+                Ok(DispatchResult::Drop(None))
+            }
             Ok(bpv7::AdministrativeRecord::BundleStatusReport(report)) => {
+                self.publish_status_report(&bundle.bundle.id.source, &report);
+
                 // Check if the report is for a bundle sourced from a local service
                 if !self
                     .config
@@ -38,6 +68,14 @@ impl Dispatcher {
                         bpv7::StatusReportReasonCode::DestinationEndpointIDUnavailable,
                     )))
                 } else {
+                    // Release the bundle from any retransmission buffer
+                    if report.delivered.is_some() {
+                        self.delivery_reported(&report.bundle_id).await?;
+                    }
+
+                    // Publish what it says of any bundle being tracked
+                    self.match_receipt(&bundle.bundle.id.source, &report);
+
                     // Find a live service to notify
                     if let Some(endpoint) = self
                         .app_registry
@@ -0,0 +1,117 @@
+use super::*;
+
+impl Dispatcher {
+    // Find the tunnel endpoint a bundle must be encapsulated for, if any
+    pub(super) fn bibe_tunnel(&self, bundle: &metadata::Bundle) -> Option<bpv7::Eid> {
+        let tunnel = self
+            .config
+            .bibe_tunnels
+            .find(&bundle.bundle.destination)
+            .first()
+            .map(|tunnel| (*tunnel).clone())?;
+
+        // Bundles already destined for the tunnel endpoint are forwarded as normal
+        (tunnel != bundle.bundle.destination).then_some(tunnel)
+    }
+
+    #[instrument(skip(self))]
+    pub(super) async fn encapsulate_bundle(
+        &self,
+        bundle: &mut metadata::Bundle,
+        tunnel: bpv7::Eid,
+    ) -> Result<DispatchResult, Error> {
+        // The encapsulating bundle lives no longer than the bundle it carries
+        let lifetime = (bundle.expiry() - clock::now()).whole_milliseconds();
+        if lifetime <= 0 {
+            trace!("Bundle lifetime has expired before encapsulation");
+            return Ok(DispatchResult::Drop(Some(
+                bpv7::StatusReportReasonCode::LifetimeExpired,
+            )));
+        }
+
+        let Some(source_data) = self.load_data(bundle).await? else {
+            // Bundle data was deleted sometime during processing - this is benign
+            return Ok(DispatchResult::Done);
+        };
+
+        // Entering the tunnel is a hop, so increment Hop Count, etc...
+        let boundary = self.crosses_boundary(&tunnel, None).await;
+        let data = self.update_extension_blocks(bundle, source_data, boundary);
+
+        trace!("Encapsulating bundle for tunnel endpoint {tunnel}");
+
+        // Custody transfer is not supported, so no retransmission is requested
+        let (encapsulated, encapsulated_data) = bpv7::Builder::new()
+            .flags(bpv7::BundleFlags {
+                is_admin_record: true,
+                ..Default::default()
+            })
+            .source(self.config.admin_endpoints.get_admin_endpoint(&tunnel))
+            .destination(tunnel)
+            .lifetime(lifetime.min(u64::MAX as i128) as u64)
+            .add_payload_block(cbor::encode::emit(
+                &bpv7::AdministrativeRecord::BundleInBundle(bpv7::BibeRecord {
+                    transmission_id: 0,
+                    retransmission_time: 0,
+                    bundle: data.into(),
+                }),
+            ))
+            .build();
+
+        let metadata = self
+            .store
+            .store(
+                &encapsulated,
+                &encapsulated_data,
+                metadata::BundleStatus::default(),
+                None,
+                None,
+            )
+            .await?
+            .trace_expect("Duplicate bundle generated by builder!");
+
+        self.dispatch_bundle(metadata::Bundle {
+            metadata,
+            bundle: encapsulated,
+        })
+        .await?;
+
+        // The original has left this node, inside the tunnel
+        self.report_bundle_forwarded(bundle)
+            .await
+            .map(|_| DispatchResult::Drop(None))
+    }
+
+    #[instrument(skip(self, record))]
+    pub(super) async fn decapsulate_bundle(
+        &self,
+        bundle: &metadata::Bundle,
+        record: bpv7::BibeRecord,
+    ) -> Result<DispatchResult, Error> {
+        trace!(
+            "Decapsulating bundle received from tunnel source {}",
+            bundle.bundle.id.source
+        );
+
+        // Process the encapsulated bundle as if it had just been received from a CLA
+        match self.receive_bundle(Bytes::from(record.bundle)).await? {
+            Some(rejection @ Rejection::Unintelligible(_)) => {
+                trace!("Encapsulated bundle rejected: {rejection}");
+                Ok(DispatchResult::Drop(Some(
+                    bpv7::StatusReportReasonCode::BlockUnintelligible,
+                )))
+            }
+            Some(rejection @ Rejection::StorageFull(_)) => {
+                trace!("Encapsulated bundle rejected: {rejection}");
+                Ok(DispatchResult::Drop(Some(
+                    bpv7::StatusReportReasonCode::DepletedStorage,
+                )))
+            }
+            Some(rejection) => {
+                trace!("Encapsulated bundle rejected: {rejection}");
+                Ok(DispatchResult::Drop(None))
+            }
+            None => Ok(DispatchResult::Drop(None)),
+        }
+    }
+}
@@ -1,10 +1,46 @@
 use super::*;
+use hardy_bpa_api::storage;
 
 pub struct CollectResponse {
     pub bundle_id: String,
     pub expiry: time::OffsetDateTime,
     pub app_ack_requested: bool,
     pub data: Bytes,
+    pub latency: Option<time::Duration>,
+    pub payload_len: Option<u64>,
+    pub delivery_id: u64,
+}
+
+// The longest head of a CBOR byte string
+const MAX_HEAD_LEN: u64 = 9;
+
+// The length of the head of the byte string holding an ADU, and the length of the ADU, from
+// the start of the byte string.  None if the byte string is of indefinite length
+fn adu_extent(head: &[u8]) -> Option<(u64, u64)> {
+    let (first, rest) = head.split_first()?;
+    if first >> 5 != 2 {
+        return None;
+    }
+    match first & 0x1F {
+        n @ 0..=23 => Some((1, n as u64)),
+        24 => Some((2, *rest.first()? as u64)),
+        25 => Some((
+            3,
+            u16::from_be_bytes(rest.get(..2)?.try_into().ok()?) as u64,
+        )),
+        26 => Some((
+            5,
+            u32::from_be_bytes(rest.get(..4)?.try_into().ok()?) as u64,
+        )),
+        27 => Some((9, u64::from_be_bytes(rest.get(..8)?.try_into().ok()?))),
+        _ => None,
+    }
+}
+
+#[derive(Debug)]
+pub struct PayloadRange {
+    pub offset: u64,
+    pub len: Option<u64>,
 }
 
 impl Dispatcher {
@@ -12,7 +48,10 @@ impl Dispatcher {
     pub async fn collect(
         &self,
         destination: bpv7::Eid,
+        registration: Option<String>,
+        acknowledged: bool,
         bundle_id: String,
+        range: Option<PayloadRange>,
     ) -> Result<Option<CollectResponse>, Error> {
         // Lookup bundle
         let Some(bundle) = self
@@ -32,35 +71,301 @@ impl Dispatcher {
             return Ok(None);
         }
 
+        // Each multicast registration collects a bundle only once
+        if let Some(registration) = &registration {
+            if self
+                .store
+                .get_deliveries(&bundle.bundle.id)
+                .await?
+                .contains(registration)
+            {
+                return Ok(None);
+            }
+        }
+
+        if let Some(range) = range {
+            if self.delivery_transforms.applies(&bundle.bundle) {
+                return Err(
+                    "Payload ranges cannot be collected from encrypted or compressed payloads"
+                        .into(),
+                );
+            }
+            return self
+                .collect_range(bundle, registration, acknowledged, range)
+                .await;
+        }
+
         // Get the data!
         let Some(data) = self.load_data(&bundle).await? else {
             // Bundle data was deleted sometime during processing
             return Ok(None);
         };
+        let latency = self.measure_latency(&bundle, data.as_ref().as_ref());
 
-        // By the time we get here, we're safe to report delivery
-        self.report_bundle_delivery(&bundle).await?;
+        // Undo any transforms applied to the payload in transit
+        let data: Bytes = match self
+            .delivery_transforms
+            .inverse(&bundle.bundle, data.as_ref().as_ref())
+        {
+            Ok(Some(data)) => data.into(),
+            Ok(None) => data.as_ref().as_ref().to_vec().into(),
+            Err(e) => {
+                warn!(
+                    "Failed to restore the payload of bundle {:?}: {e}",
+                    bundle.bundle.id
+                );
+                self.drop_bundle(
+                    bundle,
+                    Some(bpv7::StatusReportReasonCode::BlockUnintelligible),
+                )
+                .await?;
+                return Ok(None);
+            }
+        };
 
         // Prepare the response
         let response = CollectResponse {
             bundle_id: bundle.bundle.id.to_key(),
-            data: data.as_ref().as_ref().to_vec().into(),
+            latency,
+            data,
             expiry: bundle.expiry(),
             app_ack_requested: bundle.bundle.flags.app_ack_requested,
+            payload_len: None,
+            delivery_id: self.assign_delivery_id(&bundle.bundle.id, &registration),
         };
 
-        // And we are done with the bundle
-        self.drop_bundle(bundle, None).await?;
+        // By the time we get here, we're safe to report delivery, unless the application
+        // will acknowledge it
+        if !acknowledged {
+            self.delivered(bundle, registration).await?;
+        }
 
         Ok(Some(response))
     }
 
+    async fn collect_range(
+        &self,
+        bundle: metadata::Bundle,
+        registration: Option<String>,
+        acknowledged: bool,
+        range: PayloadRange,
+    ) -> Result<Option<CollectResponse>, Error> {
+        let Some(payload) = bundle.bundle.blocks.get(&1) else {
+            return Err("Bundle has no payload block".into());
+        };
+
+        // Serve the range from the cached copy of the payload, if there is one
+        let cached = if self.caches_payload(payload) {
+            let Some(cached) = self.cached_payload(&bundle, payload).await? else {
+                return Ok(None);
+            };
+            Some(cached)
+        } else {
+            None
+        };
+
+        // The payload block holds the ADU as a CBOR byte string, which starts with its head
+        let block_start = (payload.data_start + payload.payload_offset) as u64;
+        let head_len = (payload.payload_len as u64).min(MAX_HEAD_LEN);
+        let head = match &cached {
+            Some(cached) => Some(cached.slice(..head_len as usize)),
+            None => self
+                .load_range(&bundle, block_start, head_len)
+                .await?
+                .map(Bytes::from),
+        };
+        let Some(head) = head else {
+            return self.data_gone(&bundle).await;
+        };
+
+        let (data, payload_len, offset, len) = match adu_extent(&head) {
+            Some((head_len, payload_len)) => {
+                // Clamp the range to the ADU
+                let offset = range.offset.min(payload_len);
+                let len = range.len.unwrap_or(payload_len).min(payload_len - offset);
+                let start = head_len + offset;
+
+                // Only load the part of the ADU requested
+                let data = match &cached {
+                    Some(cached) => Some(cached.slice(start as usize..(start + len) as usize)),
+                    None => self
+                        .load_range(&bundle, block_start + start, len)
+                        .await?
+                        .map(Bytes::from),
+                };
+                let Some(data) = data else {
+                    return self.data_gone(&bundle).await;
+                };
+                (data, payload_len, offset, len)
+            }
+            None => {
+                // An indefinite-length byte string, so the ADU is in chunks to be joined.  This
+                // loads the whole bundle into memory, but ingress rewrites non-canonical bundles
+                // before storing them, so only data stored by other means should end up here
+                let Some(data) = self.load_data(&bundle).await? else {
+                    return self.data_gone(&bundle).await;
+                };
+                let adu = payload.block_data((*data).as_ref())?;
+                let payload_len = adu.len() as u64;
+                let offset = range.offset.min(payload_len);
+                let len = range.len.unwrap_or(payload_len).min(payload_len - offset);
+                (
+                    Bytes::copy_from_slice(&adu[offset as usize..(offset + len) as usize]),
+                    payload_len,
+                    offset,
+                    len,
+                )
+            }
+        };
+
+        // Prepare the response
+        let response = CollectResponse {
+            bundle_id: bundle.bundle.id.to_key(),
+            latency: None,
+            data,
+            expiry: bundle.expiry(),
+            app_ack_requested: bundle.bundle.flags.app_ack_requested,
+            payload_len: Some(payload_len),
+            delivery_id: self.assign_delivery_id(&bundle.bundle.id, &registration),
+        };
+
+        // The bundle stays available until the application has read the end of the payload
+        if !acknowledged && offset + len == payload_len {
+            self.delivered(bundle, registration).await?;
+        }
+
+        Ok(Some(response))
+    }
+
+    // Load part of the bundle data, None if it has gone
+    async fn load_range(
+        &self,
+        bundle: &metadata::Bundle,
+        offset: u64,
+        len: u64,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        let storage_name = bundle.metadata.storage_name.as_ref().unwrap();
+        Ok(self
+            .store
+            .load_data_range(storage_name, offset, len)
+            .await?
+            .map(|data| data.as_ref().as_ref().to_vec()))
+    }
+
+    async fn data_gone(&self, bundle: &metadata::Bundle) -> Result<Option<CollectResponse>, Error> {
+        // Bundle data was deleted sometime during processing
+        warn!(
+            "Bundle data {} has gone from storage",
+            bundle.metadata.storage_name.as_deref().unwrap_or_default()
+        );
+        self.report_bundle_deletion(bundle, bpv7::StatusReportReasonCode::DepletedStorage)
+            .await?;
+        Ok(None)
+    }
+
+    // Report delivery, and drop the bundle, unless it is for a multicast endpoint.  The
+    // applications registered at any one time need not be all those the bundle is for, as
+    // after a restart, so a multicast bundle stays available to each until it expires
+    pub(super) async fn delivered(
+        &self,
+        bundle: metadata::Bundle,
+        registration: Option<String>,
+    ) -> Result<(), Error> {
+        let Some(registration) = registration else {
+            self.report_bundle_delivery(&bundle).await?;
+            self.count_bundle(utils::labels::Counter::Delivered, &bundle.bundle)
+                .await;
+            return self.drop_bundle(bundle, None).await;
+        };
+
+        // Recording the delivery is idempotent, so a repeated collection is not counted twice
+        if !self
+            .store
+            .confirm_delivery(&bundle.bundle.id, &registration)
+            .await?
+        {
+            return Ok(());
+        }
+        self.count_bundle(utils::labels::Counter::Delivered, &bundle.bundle)
+            .await;
+
+        let deliveries = self.store.get_deliveries(&bundle.bundle.id).await?;
+        if deliveries.len() == 1 {
+            // Report the first delivery only
+            self.report_bundle_delivery(&bundle).await?;
+        }
+        Ok(())
+    }
+
     #[instrument(skip(self))]
     pub async fn poll_for_collection(
         &self,
         destination: bpv7::Eid,
+        registration: Option<String>,
+        budget: storage::ScanBudget,
         tx: tokio::sync::mpsc::Sender<metadata::Bundle>,
     ) -> Result<(), Error> {
-        self.store.poll_for_collection(destination, tx).await
+        let Some(registration) = registration else {
+            return self
+                .store
+                .poll_for_collection(destination, budget, tx)
+                .await;
+        };
+
+        // Skip the bundles this registration has already collected, which must not count
+        // towards the budget
+        let (inner_tx, mut inner_rx) = tokio::sync::mpsc::channel::<metadata::Bundle>(16);
+        let store = self.store.clone();
+        let inner_budget = storage::ScanBudget {
+            max_bundles: None,
+            ..budget
+        };
+        let h = tokio::spawn(async move {
+            store
+                .poll_for_collection(destination, inner_budget, inner_tx)
+                .await
+        });
+
+        let mut count = 0;
+        while let Some(bundle) = inner_rx.recv().await {
+            if budget.is_spent(count) {
+                break;
+            }
+            if !self
+                .store
+                .get_deliveries(&bundle.bundle.id)
+                .await?
+                .contains(&registration)
+            {
+                if tx.send(bundle).await.is_err() {
+                    break;
+                }
+                count += 1;
+            }
+        }
+        drop(inner_rx);
+
+        h.await.trace_expect("Task terminated unexpectedly")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extent() {
+        for len in [0usize, 23, 24, 255, 256, 65536] {
+            let data = vec![7u8; len];
+            let encoded = cbor::encode::emit(data.as_slice());
+            let (head_len, adu_len) = adu_extent(&encoded).unwrap();
+            assert_eq!(adu_len, len as u64);
+            assert_eq!(&encoded[head_len as usize..], data.as_slice());
+        }
+
+        // Indefinite-length byte strings, and anything but a byte string
+        assert_eq!(adu_extent(&[0x5F, 0x41, 0x00, 0xFF]), None);
+        assert_eq!(adu_extent(&[0x61, 0x61]), None);
+        assert_eq!(adu_extent(&[]), None);
     }
 }
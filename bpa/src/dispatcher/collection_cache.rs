@@ -0,0 +1,166 @@
+use super::*;
+use sha2::Digest;
+use std::collections::HashMap;
+
+/* An in-memory cache of large payloads awaiting collection by local applications.  Each
+ * payload is held once, however many bundles carry it, so the same payload sent to a number
+ * of local services in separate bundles is loaded from the bundle storage once and served to
+ * each from the one copy.  The bundle storage still holds every bundle in full, so this
+ * saves memory and loads, not storage.  Each copy is counted against the bundles referring
+ * to it, and released when the last of them is delivered or dropped */
+
+struct Entry {
+    payload: Bytes,
+    refs: usize,
+}
+
+#[derive(Default)]
+struct Inner {
+    // Keyed by the SHA-256 hash of the payload
+    payloads: HashMap<[u8; 32], Entry>,
+    bundles: HashMap<bpv7::BundleId, [u8; 32]>,
+}
+
+#[derive(Default)]
+pub struct CollectionCache {
+    inner: std::sync::Mutex<Inner>,
+}
+
+impl CollectionCache {
+    // The payload held for `bundle_id`, if any
+    pub fn get(&self, bundle_id: &bpv7::BundleId) -> Option<Bytes> {
+        let inner = self.inner.lock().trace_expect("Failed to lock mutex");
+        let hash = inner.bundles.get(bundle_id)?;
+        inner.payloads.get(hash).map(|entry| entry.payload.clone())
+    }
+
+    // Hold `payload` for `bundle_id`, returning the copy already held if another bundle carries the same payload
+    pub fn insert(&self, bundle_id: &bpv7::BundleId, payload: &[u8]) -> Bytes {
+        let hash: [u8; 32] = sha2::Sha256::digest(payload).into();
+        let mut inner = self.inner.lock().trace_expect("Failed to lock mutex");
+        if let Some(previous) = inner.bundles.insert(bundle_id.clone(), hash) {
+            // Already held, perhaps by a concurrent collection
+            if previous == hash {
+                return inner.payloads[&hash].payload.clone();
+            }
+            Self::release_hash(&mut inner, &previous);
+        }
+
+        if let Some(entry) = inner.payloads.get_mut(&hash) {
+            entry.refs += 1;
+            metrics::counter!("collection_cache_bytes_saved_total").increment(payload.len() as u64);
+            return entry.payload.clone();
+        }
+
+        let payload = Bytes::copy_from_slice(payload);
+        inner.payloads.insert(
+            hash,
+            Entry {
+                payload: payload.clone(),
+                refs: 1,
+            },
+        );
+        metrics::gauge!("collection_cache_payloads").increment(1);
+        metrics::gauge!("collection_cache_bytes").increment(payload.len() as f64);
+        payload
+    }
+
+    pub fn release(&self, bundle_id: &bpv7::BundleId) {
+        let mut inner = self.inner.lock().trace_expect("Failed to lock mutex");
+        if let Some(hash) = inner.bundles.remove(bundle_id) {
+            Self::release_hash(&mut inner, &hash);
+        }
+    }
+
+    fn release_hash(inner: &mut Inner, hash: &[u8; 32]) {
+        let Some(entry) = inner.payloads.get_mut(hash) else {
+            return;
+        };
+        entry.refs -= 1;
+        if entry.refs == 0 {
+            let entry = inner.payloads.remove(hash).unwrap();
+            metrics::gauge!("collection_cache_payloads").decrement(1);
+            metrics::gauge!("collection_cache_bytes").decrement(entry.payload.len() as f64);
+        }
+    }
+}
+
+impl Dispatcher {
+    // Whether a payload is large enough to be cached, if payloads are cached at all
+    pub(super) fn caches_payload(&self, payload_block: &bpv7::Block) -> bool {
+        self.collection_cache.is_some()
+            && self
+                .config
+                .collection_cache_threshold
+                .is_some_and(|threshold| payload_block.payload_len >= threshold)
+    }
+
+    // The cached copy of the payload of a bundle awaiting collection, loading it if it is not
+    // yet held.  None if the bundle data has gone
+    pub(super) async fn cached_payload(
+        &self,
+        bundle: &metadata::Bundle,
+        payload_block: &bpv7::Block,
+    ) -> Result<Option<Bytes>, Error> {
+        let cache = self.collection_cache.as_ref().unwrap();
+        if let Some(payload) = cache.get(&bundle.bundle.id) {
+            return Ok(Some(payload));
+        }
+
+        // Load the bundle once, and keep only its payload
+        let Some(data) = self.load_data(bundle).await? else {
+            return Ok(None);
+        };
+        Ok(Some(cache.insert(
+            &bundle.bundle.id,
+            payload_block.payload((*data).as_ref()),
+        )))
+    }
+
+    pub(super) fn release_cached_payload(&self, bundle_id: &bpv7::BundleId) {
+        if let Some(cache) = &self.collection_cache {
+            cache.release(bundle_id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bundle_id(seq: u64) -> bpv7::BundleId {
+        bpv7::BundleId {
+            source: "ipn:1.1".parse().unwrap(),
+            timestamp: bpv7::CreationTimestamp {
+                creation_time: Some(bpv7::DtnTime::new(1000)),
+                sequence_number: seq,
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn refcount() {
+        let cache = CollectionCache::default();
+
+        let a = cache.insert(&bundle_id(1), b"payload");
+        let b = cache.insert(&bundle_id(2), b"payload");
+        let c = cache.insert(&bundle_id(3), b"other");
+        assert_eq!(a.as_ptr(), b.as_ptr());
+        assert_ne!(a.as_ptr(), c.as_ptr());
+        assert_eq!(cache.inner.lock().unwrap().payloads.len(), 2);
+
+        // Held until the last bundle carrying it is released
+        cache.release(&bundle_id(1));
+        assert_eq!(cache.get(&bundle_id(2)).as_deref(), Some(&b"payload"[..]));
+        cache.release(&bundle_id(2));
+        cache.release(&bundle_id(2));
+        assert!(cache.get(&bundle_id(2)).is_none());
+        assert_eq!(cache.inner.lock().unwrap().payloads.len(), 1);
+
+        // Re-inserting the same bundle does not count it twice
+        cache.insert(&bundle_id(3), b"other");
+        cache.release(&bundle_id(3));
+        assert!(cache.inner.lock().unwrap().payloads.is_empty());
+    }
+}
@@ -3,13 +3,142 @@ use utils::settings;
 
 const MAX_FORWARDING_DELAY_SECS: u32 = 5;
 
+#[derive(Debug, Clone, Copy)]
+pub enum CrcPolicy {
+    Keep,                 // Leave CRCs as received
+    Add(bpv7::CrcType),   // Add a CRC to blocks that have none
+    Force(bpv7::CrcType), // Replace every CRC
+}
+
+#[derive(Debug, Default, Clone, Copy, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+enum CrcPolicySetting {
+    #[default]
+    Keep,
+    AddCrc16,
+    AddCrc32,
+    ForceNone,
+    ForceCrc16,
+    ForceCrc32,
+}
+
+#[derive(Debug, Default, Clone, Copy, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+enum BibFailureSetting {
+    #[default]
+    Drop,
+    Strip,
+}
+
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+struct BibeTunnelSetting {
+    destination: String,
+    tunnel: String,
+}
+
+// The dispatcher settings, as they appear in the configuration
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+struct Settings {
+    status_reports: bool,
+    status_report_source: Option<String>,
+    status_report_return_path: bool,
+    wait_sample_interval: u64,
+    max_forwarding_delay: u32,
+    latency_block: bool,
+    slow_bundle_threshold: u64,
+    rewrite_diagnostic_interval: u64,
+    ordered_delivery_timeout: u64,
+    max_in_flight_reports: usize,
+    report_hop_limit: u64,
+    max_bundle_size: usize,
+    dedup_capacity: usize,
+    dedup_window: u64,
+    collection_cache_threshold: usize,
+    deny_sources: Vec<String>,
+    bibe_tunnels: Vec<BibeTunnelSetting>,
+    ipn_2_element: Vec<String>,
+    echo_endpoints: Vec<String>,
+    retransmit_sources: Vec<String>,
+    retransmit_interval: u64,
+    max_retransmissions: u32,
+    crc_policy: CrcPolicySetting,
+    bib_failure: BibFailureSetting,
+    egress_transforms: Vec<String>,
+    egress_dry_run: Vec<String>,
+    domain_endpoints: Vec<String>,
+    boundary_clas: Vec<String>,
+    bpv6_clas: Vec<String>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            status_reports: false,
+            status_report_source: None,
+            status_report_return_path: false,
+            wait_sample_interval: settings::WAIT_SAMPLE_INTERVAL_SECS,
+            max_forwarding_delay: MAX_FORWARDING_DELAY_SECS,
+            latency_block: false,
+            slow_bundle_threshold: 0,
+            rewrite_diagnostic_interval: 0,
+            ordered_delivery_timeout: 1000,
+            max_in_flight_reports: 0,
+            report_hop_limit: 0,
+            max_bundle_size: 0,
+            dedup_capacity: 0,
+            dedup_window: 0,
+            collection_cache_threshold: 0,
+            deny_sources: Vec::new(),
+            bibe_tunnels: Vec::new(),
+            ipn_2_element: Vec::new(),
+            echo_endpoints: Vec::new(),
+            retransmit_sources: Vec::new(),
+            retransmit_interval: 60,
+            max_retransmissions: 3,
+            crc_policy: CrcPolicySetting::Keep,
+            bib_failure: BibFailureSetting::Drop,
+            egress_transforms: Vec::new(),
+            egress_dry_run: Vec::new(),
+            domain_endpoints: Vec::new(),
+            boundary_clas: Vec::new(),
+            bpv6_clas: Vec::new(),
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct Config {
     pub admin_endpoints: utils::admin_endpoints::AdminEndpoints,
     pub status_reports: bool,
+    pub status_report_source: Option<bpv7::Eid>,
+    pub status_report_return_path: bool,
     pub wait_sample_interval: u64,
     pub max_forwarding_delay: u32,
+    pub latency_block: bool,
+    pub slow_bundle_threshold: Option<std::time::Duration>,
+    pub rewrite_diagnostic_interval: Option<time::Duration>,
+    pub ordered_delivery_timeout: time::Duration,
+    pub max_in_flight_reports: usize,
+    pub report_hop_limit: Option<u64>,
+    pub max_bundle_size: Option<usize>,
+    pub dedup_capacity: usize,
+    pub dedup_window: Option<time::Duration>,
+    pub collection_cache_threshold: Option<usize>,
+    pub deny_sources: bpv7::EidPatternMap<(), ()>,
+    pub bibe_tunnels: bpv7::EidPatternMap<(), bpv7::Eid>,
     pub ipn_2_element: bpv7::EidPatternMap<(), ()>,
+    pub echo_endpoints: Vec<bpv7::Eid>,
+    pub retransmit_sources: bpv7::EidPatternMap<(), ()>,
+    pub retransmit_interval: time::Duration,
+    pub max_retransmissions: u32,
+    pub crc_policy: CrcPolicy,
+    pub bib_failure: bpv7::bpsec::BibFailurePolicy,
+    pub egress_transforms: Vec<super::egress::Transform>,
+    pub egress_dry_run: Vec<super::egress::Transform>,
+    pub domain_endpoints: Option<bpv7::EidPatternMap<(), ()>>,
+    pub boundary_clas: Vec<String>,
+    pub bpv6_clas: Vec<String>,
 }
 
 impl Config {
@@ -17,30 +146,138 @@ impl Config {
         config: &::config::Config,
         admin_endpoints: utils::admin_endpoints::AdminEndpoints,
     ) -> Self {
+        let settings: Settings = settings::load(config, "dispatcher");
+        let status_report_source =
+            Self::load_status_report_source(settings.status_report_source, &admin_endpoints);
+        let echo_endpoints = Self::load_echo_endpoints(&settings.echo_endpoints, &admin_endpoints);
         let config = Self {
             admin_endpoints,
-            status_reports: settings::get_with_default(config, "status_reports", false)
-                .trace_expect("Invalid 'status_reports' value in configuration"),
-            wait_sample_interval: settings::get_with_default(
-                config,
-                "wait_sample_interval",
-                settings::WAIT_SAMPLE_INTERVAL_SECS,
-            )
-            .trace_expect("Invalid 'wait_sample_interval' value in configuration"),
-            max_forwarding_delay: settings::get_with_default::<u32, _>(
-                config,
-                "max_forwarding_delay",
-                MAX_FORWARDING_DELAY_SECS,
-            )
-            .trace_expect("Invalid 'max_forwarding_delay' value in configuration")
-            .min(1u32),
-            ipn_2_element: Self::load_ipn_2_element(config),
+            status_report_source,
+            status_reports: settings.status_reports,
+            status_report_return_path: settings.status_report_return_path,
+            wait_sample_interval: settings.wait_sample_interval,
+            max_forwarding_delay: settings.max_forwarding_delay.min(1u32),
+            latency_block: settings.latency_block,
+            slow_bundle_threshold: match settings.slow_bundle_threshold {
+                0 => None,
+                ms => Some(std::time::Duration::from_millis(ms)),
+            },
+            rewrite_diagnostic_interval: match settings.rewrite_diagnostic_interval {
+                0 => None,
+                secs => Some(time::Duration::seconds(secs.min(i64::MAX as u64) as i64)),
+            },
+            ordered_delivery_timeout: time::Duration::milliseconds(
+                settings.ordered_delivery_timeout.min(i64::MAX as u64) as i64,
+            ),
+            max_in_flight_reports: settings.max_in_flight_reports,
+            report_hop_limit: match settings.report_hop_limit {
+                0 => None,
+                hops => Some(hops),
+            },
+            max_bundle_size: match settings.max_bundle_size {
+                0 => None,
+                max => Some(max),
+            },
+            dedup_capacity: settings.dedup_capacity,
+            dedup_window: match settings.dedup_window {
+                0 => None,
+                secs => Some(time::Duration::seconds(secs.min(i64::MAX as u64) as i64)),
+            },
+            collection_cache_threshold: match settings.collection_cache_threshold {
+                0 => None,
+                threshold => Some(threshold),
+            },
+            deny_sources: Self::load_patterns(&settings.deny_sources, "deny_sources"),
+            bibe_tunnels: Self::load_bibe_tunnels(&settings.bibe_tunnels),
+            ipn_2_element: Self::load_patterns(&settings.ipn_2_element, "ipn_2_element"),
+            echo_endpoints,
+            retransmit_sources: Self::load_patterns(
+                &settings.retransmit_sources,
+                "retransmit_sources",
+            ),
+            retransmit_interval: time::Duration::seconds(
+                settings.retransmit_interval.clamp(1, i64::MAX as u64) as i64,
+            ),
+            max_retransmissions: settings.max_retransmissions,
+            crc_policy: match settings.crc_policy {
+                CrcPolicySetting::Keep => CrcPolicy::Keep,
+                CrcPolicySetting::AddCrc16 => CrcPolicy::Add(bpv7::CrcType::CRC16_X25),
+                CrcPolicySetting::AddCrc32 => CrcPolicy::Add(bpv7::CrcType::CRC32_CASTAGNOLI),
+                CrcPolicySetting::ForceNone => CrcPolicy::Force(bpv7::CrcType::None),
+                CrcPolicySetting::ForceCrc16 => CrcPolicy::Force(bpv7::CrcType::CRC16_X25),
+                CrcPolicySetting::ForceCrc32 => CrcPolicy::Force(bpv7::CrcType::CRC32_CASTAGNOLI),
+            },
+            bib_failure: match settings.bib_failure {
+                BibFailureSetting::Drop => bpv7::bpsec::BibFailurePolicy::Drop,
+                BibFailureSetting::Strip => bpv7::bpsec::BibFailurePolicy::Strip,
+            },
+            egress_transforms: Self::load_egress_transforms(&settings.egress_transforms),
+            egress_dry_run: Self::load_transform_list(&settings.egress_dry_run, "egress_dry_run"),
+            domain_endpoints: (!settings.domain_endpoints.is_empty())
+                .then(|| Self::load_patterns(&settings.domain_endpoints, "domain_endpoints")),
+            boundary_clas: settings.boundary_clas,
+            bpv6_clas: settings.bpv6_clas,
         };
 
         if !config.status_reports {
             info!("Bundle status reports are disabled by configuration");
         }
 
+        if let Some(source) = &config.status_report_source {
+            info!("Bundle status reports will be sourced from {source}");
+        }
+
+        if let CrcPolicy::Keep = config.crc_policy {
+        } else {
+            info!("Forwarded bundle CRC policy: {:?}", config.crc_policy);
+        }
+
+        for transform in &config.egress_dry_run {
+            info!(
+                "Egress transform '{}' is in dry-run mode, changes will be logged but not applied",
+                transform.name()
+            );
+        }
+
+        for pattern in &settings.domain_endpoints {
+            info!("Bundles for {pattern} remain within the administrative domain");
+        }
+
+        for name in &config.boundary_clas {
+            info!("Bundles forwarded by CLA '{name}' leave the administrative domain");
+        }
+
+        for name in &config.bpv6_clas {
+            if cfg!(feature = "bpv6") {
+                info!("Bundles forwarded by CLA '{name}' will be translated to BPv6");
+            } else {
+                warn!("CLA '{name}' is listed in 'bpv6_clas', but BPv6 support is not built in");
+            }
+        }
+
+        if config.dedup_capacity != 0 {
+            info!(
+                "Remembering up to {} recently received bundle ids for duplicate suppression",
+                config.dedup_capacity
+            );
+        }
+
+        if let Some(threshold) = config.collection_cache_threshold {
+            info!("Caching payloads of {threshold} bytes or more awaiting collection in memory");
+        }
+
+        for eid in &config.echo_endpoints {
+            info!("Echo service listening on {eid}");
+        }
+
+        for source in &settings.retransmit_sources {
+            info!(
+                "Bundles sent from {source} will be retransmitted every {} seconds, up to {} times, until their delivery is reported",
+                config.retransmit_interval.whole_seconds(),
+                config.max_retransmissions
+            );
+        }
+
         if config.max_forwarding_delay == 0 {
             info!("Forwarding synchronization delay disabled by configuration");
         }
@@ -48,14 +285,126 @@ impl Config {
         config
     }
 
-    fn load_ipn_2_element(config: &::config::Config) -> bpv7::EidPatternMap<(), ()> {
-        let mut m = bpv7::EidPatternMap::new();
-        for s in config
-            .get::<Vec<String>>("ipn_2_element")
-            .unwrap_or_default()
+    fn load_status_report_source(
+        source: Option<String>,
+        admin_endpoints: &utils::admin_endpoints::AdminEndpoints,
+    ) -> Option<bpv7::Eid> {
+        let source = source?;
+        let source = source.parse::<bpv7::Eid>().trace_expect(&format!(
+            "Invalid EID '{source}' for 'status_report_source'"
+        ));
+
+        // The source must be one of our own administrative endpoints
+        if matches!(source, bpv7::Eid::LocalNode { .. })
+            || !admin_endpoints.is_admin_endpoint(&source)
         {
-            let p = s.parse().trace_expect(&format!("Invalid EID pattern '{s}"));
+            error!("'status_report_source' {source} is not a configured administrative endpoint");
+            panic!("'status_report_source' {source} is not a configured administrative endpoint");
+        }
+        Some(source)
+    }
+
+    fn load_echo_endpoints(
+        endpoints: &[String],
+        admin_endpoints: &utils::admin_endpoints::AdminEndpoints,
+    ) -> Vec<bpv7::Eid> {
+        endpoints
+            .iter()
+            .map(|eid| {
+                let eid = eid
+                    .parse::<bpv7::Eid>()
+                    .trace_expect(&format!("Invalid EID '{eid}' in 'echo_endpoints'"));
+
+                // Echo endpoints must be local services, other than the administrative endpoints
+                if matches!(eid, bpv7::Eid::LocalNode { .. })
+                    || !admin_endpoints.is_local_service(&eid)
+                    || admin_endpoints.is_admin_endpoint(&eid)
+                {
+                    error!("Echo endpoint {eid} is not a local service endpoint");
+                    panic!("Echo endpoint {eid} is not a local service endpoint");
+                }
+                eid
+            })
+            .collect()
+    }
+
+    fn load_transform_list(names: &[String], key: &str) -> Vec<super::egress::Transform> {
+        names
+            .iter()
+            .map(|name| {
+                super::egress::Transform::from_name(name).unwrap_or_else(|| {
+                    error!("Unknown egress transform '{name}' in '{key}'");
+                    panic!("Unknown egress transform '{name}' in '{key}'");
+                })
+            })
+            .collect()
+    }
+
+    fn load_egress_transforms(names: &[String]) -> Vec<super::egress::Transform> {
+        // Configured transforms come first, in order, then any not mentioned in the default order
+        let mut transforms = Vec::new();
+        for transform in Self::load_transform_list(names, "egress_transforms") {
+            // Each transform runs once, at its first place in the list
+            if !transforms.contains(&transform) {
+                transforms.push(transform);
+            }
+        }
+        for transform in super::egress::Transform::DEFAULT_ORDER {
+            if !transforms.contains(&transform) {
+                transforms.push(transform);
+            }
+        }
+        transforms
+    }
+
+    fn load_patterns(patterns: &[String], key: &str) -> bpv7::EidPatternMap<(), ()> {
+        let mut m = bpv7::EidPatternMap::new();
+        let mut loaded: Vec<bpv7::EidPattern> = Vec::new();
+        for s in patterns {
+            let p = s
+                .parse()
+                .trace_expect(&format!("Invalid EID pattern '{s}' in '{key}'"));
+
+            // A pattern another covers adds nothing, and is probably a mistake
+            if let Some(other) = loaded.iter().find(|other| other.covers(&p)) {
+                warn!("EID pattern '{s}' in '{key}' is already covered by '{other}'");
+            } else if let Some(other) = loaded.iter().find(|other| p.covers(other)) {
+                warn!("EID pattern '{other}' in '{key}' is covered by '{s}'");
+            }
             m.insert(&p, (), ());
+            loaded.push(p);
+        }
+        m
+    }
+
+    fn load_bibe_tunnels(tunnels: &[BibeTunnelSetting]) -> bpv7::EidPatternMap<(), bpv7::Eid> {
+        let mut m = bpv7::EidPatternMap::new();
+        let mut loaded: Vec<(bpv7::EidPattern, bpv7::Eid)> = Vec::new();
+        for t in tunnels {
+            let p: bpv7::EidPattern = t.destination.parse().trace_expect(&format!(
+                "Invalid EID pattern '{}' in 'bibe_tunnels'",
+                t.destination
+            ));
+            let tunnel = t
+                .tunnel
+                .parse::<bpv7::Eid>()
+                .trace_expect(&format!("Invalid EID '{}' in 'bibe_tunnels'", t.tunnel));
+            info!(
+                "Bundles for {} will be tunnelled to {tunnel}",
+                t.destination
+            );
+
+            // Bundles matching both could be tunnelled to either
+            for (other, other_tunnel) in &loaded {
+                if *other_tunnel != tunnel && other.intersects(&p) {
+                    warn!(
+                        "'bibe_tunnels' destinations '{other}' and '{}' overlap, but are tunnelled to different endpoints",
+                        t.destination
+                    );
+                }
+            }
+            m.insert(&p, (), tunnel.clone());
+            loaded.push((p, tunnel));
         }
         m
     }
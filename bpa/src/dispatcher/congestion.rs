@@ -0,0 +1,66 @@
+use super::*;
+
+/* Applications that register for congestion signalling are told to try later, rather
+ * than having their bundles accepted, when sending would only add to a backlog: when
+ * the dispatcher or the bundle storage is over its soft memory limit, or when every CLA
+ * the destination is routed to last reported congestion.  The suggested backoff is as
+ * long as the CLAs asked for, or a short fixed time under memory pressure */
+
+const MEMORY_BACKOFF: time::Duration = time::Duration::seconds(1);
+
+fn over_memory_limit() -> bool {
+    utils::memory::over_limit(utils::memory::Subsystem::Dispatcher)
+        || utils::memory::over_limit(utils::memory::Subsystem::MemStorage)
+}
+
+impl Dispatcher {
+    // Whether bundles from local applications would only add to a backlog right now, as
+    // the dispatcher is over its soft memory limits or its queue is full
+    pub fn is_busy(&self) -> bool {
+        over_memory_limit() || self.tx.capacity() == 0
+    }
+
+    // Remember until when a CLA is congested, or that it is no longer
+    pub(super) fn note_congestion(&self, handle: u32, until: Option<time::OffsetDateTime>) {
+        let mut congested_clas = self
+            .congested_clas
+            .lock()
+            .trace_expect("Failed to lock mutex");
+        if let Some(until) = until {
+            congested_clas.insert(handle, until);
+        } else {
+            congested_clas.remove(&handle);
+        }
+    }
+
+    // How long a local application should wait before sending to 'destination', if at all
+    #[instrument(skip(self))]
+    pub async fn send_backoff(&self, destination: &bpv7::Eid) -> Option<time::Duration> {
+        if over_memory_limit() {
+            trace!("Over the soft memory limit, asking the application to back off");
+            return Some(MEMORY_BACKOFF);
+        }
+
+        let fib::ForwardAction { clas, .. } = self.fib.as_ref()?.find(destination).await.ok()?;
+        if clas.is_empty() {
+            return None;
+        }
+
+        let now = clock::now();
+        let mut congested_clas = self
+            .congested_clas
+            .lock()
+            .trace_expect("Failed to lock mutex");
+        congested_clas.retain(|_, until| *until > now);
+
+        // Only if there is no uncongested way out
+        let mut backoff = None;
+        for endpoint in clas {
+            let until = congested_clas.get(&endpoint.handle)?;
+            backoff = Some(backoff.map_or(*until, |b: time::OffsetDateTime| b.min(*until)));
+        }
+        let backoff = backoff? - now;
+        trace!("Every CLA towards {destination} is congested, asking the application to back off for {backoff}");
+        Some(backoff)
+    }
+}
@@ -0,0 +1,164 @@
+use super::*;
+use std::collections::{HashMap, VecDeque};
+
+/* The ids of recently received bundles, fragments included, are remembered so that copies
+ * re-received at a high rate are recognised as duplicates without asking the metadata
+ * storage each time.  An id is forgotten once 'dedup_window' has passed since it was last
+ * seen, or when it is the least recently seen of more than 'dedup_capacity' ids.  The
+ * metadata storage remains the authority: ids not remembered here are checked there */
+
+pub struct Dedup {
+    window: Option<time::Duration>,
+    capacity: usize,
+    inner: std::sync::Mutex<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    // When each id was last seen, and the generation of its entry in `order`
+    seen: HashMap<bpv7::BundleId, (time::OffsetDateTime, u64)>,
+    // Least recently seen first.  Entries superseded by a later sighting are skipped
+    order: VecDeque<(bpv7::BundleId, u64)>,
+    generation: u64,
+}
+
+impl Inner {
+    fn touch(&mut self, bundle_id: &bpv7::BundleId, now: time::OffsetDateTime) {
+        self.generation += 1;
+        self.seen.insert(bundle_id.clone(), (now, self.generation));
+        self.order.push_back((bundle_id.clone(), self.generation));
+    }
+
+    fn is_current(&self, bundle_id: &bpv7::BundleId, generation: u64) -> bool {
+        self.seen
+            .get(bundle_id)
+            .is_some_and(|(_, g)| *g == generation)
+    }
+}
+
+impl Dedup {
+    pub fn new(window: Option<time::Duration>, capacity: usize) -> Self {
+        Self {
+            window,
+            capacity,
+            inner: Default::default(),
+        }
+    }
+
+    // Returns true if `bundle_id` has been seen recently, which counts as seeing it again
+    pub fn check(&self, bundle_id: &bpv7::BundleId, now: time::OffsetDateTime) -> bool {
+        let mut inner = self.inner.lock().trace_expect("Failed to lock mutex");
+        let Some((last_seen, _)) = inner.seen.get(bundle_id) else {
+            return false;
+        };
+        if self.window.is_some_and(|window| now - *last_seen >= window) {
+            return false;
+        }
+        inner.touch(bundle_id, now);
+        true
+    }
+
+    // Remember that `bundle_id` has been seen
+    pub fn insert(&self, bundle_id: &bpv7::BundleId, now: time::OffsetDateTime) {
+        let mut inner = self.inner.lock().trace_expect("Failed to lock mutex");
+        inner.touch(bundle_id, now);
+
+        // Forget the least recently seen, while there are too many or they are too old
+        while let Some((bundle_id, generation)) = inner.order.front().cloned() {
+            if inner.is_current(&bundle_id, generation) {
+                let (last_seen, _) = inner.seen[&bundle_id];
+                if inner.seen.len() <= self.capacity
+                    && self.window.is_none_or(|window| now - last_seen < window)
+                {
+                    break;
+                }
+                inner.seen.remove(&bundle_id);
+            }
+            inner.order.pop_front();
+        }
+
+        // Don't let superseded entries build up
+        if inner.order.len() > self.capacity.saturating_mul(2).max(16) {
+            let Inner { seen, order, .. } = &mut *inner;
+            order.retain(|(bundle_id, generation)| {
+                seen.get(bundle_id).is_some_and(|(_, g)| g == generation)
+            });
+        }
+    }
+}
+
+impl Dispatcher {
+    // Returns true if the bundle is a duplicate of one received recently
+    pub(super) fn is_recent_duplicate(&self, bundle_id: &bpv7::BundleId) -> bool {
+        let Some(dedup) = &self.dedup else {
+            return false;
+        };
+        if !dedup.check(bundle_id, clock::now()) {
+            return false;
+        }
+        trace!("Bundle was received recently, dropping duplicate");
+        metrics::counter!("bundles_duplicate_total", "detected_by" => "cache").increment(1);
+        true
+    }
+
+    pub(super) fn note_received(&self, bundle_id: &bpv7::BundleId) {
+        if let Some(dedup) = &self.dedup {
+            dedup.insert(bundle_id, clock::now());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bundle_id(seq: u64) -> bpv7::BundleId {
+        bpv7::BundleId {
+            source: "ipn:1.1".parse().unwrap(),
+            timestamp: bpv7::CreationTimestamp {
+                creation_time: Some(bpv7::DtnTime::new(1000)),
+                sequence_number: seq,
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn capacity() {
+        let dedup = Dedup::new(None, 2);
+        let now = time::OffsetDateTime::now_utc();
+
+        assert!(!dedup.check(&bundle_id(1), now));
+        dedup.insert(&bundle_id(1), now);
+        dedup.insert(&bundle_id(2), now);
+        assert!(dedup.check(&bundle_id(1), now));
+
+        // 2 is now the least recently seen
+        dedup.insert(&bundle_id(3), now);
+        assert!(!dedup.check(&bundle_id(2), now));
+        assert!(dedup.check(&bundle_id(1), now));
+        assert!(dedup.check(&bundle_id(3), now));
+
+        // Fragments are distinct
+        let mut fragment = bundle_id(1);
+        fragment.fragment_info = Some(bpv7::FragmentInfo {
+            offset: 0,
+            total_len: 100,
+        });
+        assert!(!dedup.check(&fragment, now));
+    }
+
+    #[test]
+    fn window() {
+        let dedup = Dedup::new(Some(time::Duration::seconds(10)), 100);
+        let now = time::OffsetDateTime::now_utc();
+
+        dedup.insert(&bundle_id(1), now);
+        dedup.insert(&bundle_id(2), now);
+        assert!(dedup.check(&bundle_id(1), now + time::Duration::seconds(5)));
+        assert!(!dedup.check(&bundle_id(2), now + time::Duration::seconds(10)));
+
+        // Seeing 1 again extended its window
+        assert!(dedup.check(&bundle_id(1), now + time::Duration::seconds(14)));
+    }
+}
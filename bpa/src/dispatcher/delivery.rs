@@ -0,0 +1,314 @@
+use super::*;
+use std::collections::HashMap;
+use std::io::Read;
+use utils::settings;
+
+/* Transforms applied to a payload in transit are undone before the bundle is delivered
+ * to a local application, so applications always receive the plaintext ADU.  Each
+ * transform is registered against the extension block type that marks the payload as
+ * transformed, and they are undone in registration order: decryption first, as
+ * confidentiality is applied last by the source, then decompression.
+ *
+ * The compression block data is a CBOR array of the algorithm code and the length of the
+ * uncompressed payload.  Decompression stops at the declared length, and refuses payloads
+ * that declare more than the configured limit, so a small bundle cannot expand without
+ * bound */
+
+// Block type code from the private/experimental range
+pub const COMPRESSION_BLOCK_TYPE: u64 = 193;
+
+// Compression algorithm codes carried in the compression block
+const COMPRESSION_DEFLATE: u64 = 1;
+
+// The largest uncompressed payload accepted by default
+const DEFAULT_DECOMPRESSION_LIMIT: u64 = 16 * 1024 * 1024;
+
+pub trait PayloadTransform: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    // The extension block that marks the payload as transformed
+    fn block_type(&self) -> bpv7::BlockType;
+
+    // Undo the transform marked by 'block_number', returning the new payload and the blocks to remove
+    fn inverse(
+        &self,
+        bundle: &bpv7::Bundle,
+        block_number: u64,
+        data: &[u8],
+        payload: Vec<u8>,
+    ) -> Result<(Vec<u8>, Vec<u64>), Error>;
+}
+
+struct Decryption {
+    keys: HashMap<bpv7::Eid, Box<[u8]>>,
+}
+
+impl PayloadTransform for Decryption {
+    fn name(&self) -> &'static str {
+        "decryption"
+    }
+
+    fn block_type(&self) -> bpv7::BlockType {
+        bpv7::BlockType::BlockSecurity
+    }
+
+    fn inverse(
+        &self,
+        bundle: &bpv7::Bundle,
+        block_number: u64,
+        data: &[u8],
+        payload: Vec<u8>,
+    ) -> Result<(Vec<u8>, Vec<u64>), Error> {
+        // Only BCBs that target the payload concern us
+        if bundle.blocks.get(&1).and_then(|block| block.bcb) != Some(block_number) {
+            return Ok((payload, Vec::new()));
+        }
+
+        let Some(plaintext) = bundle.decrypt_block(1, data, |source, _| {
+            Ok(self
+                .keys
+                .get(source)
+                .map(|key| bpv7::bpsec::KeyMaterial::SymmetricKey(key.clone())))
+        })?
+        else {
+            return Ok((payload, Vec::new()));
+        };
+
+        // The other targets of the BCB are of no use to the application without it
+        let mut remove = bundle
+            .blocks
+            .iter()
+            .filter(|(n, block)| **n != 1 && block.bcb == Some(block_number))
+            .map(|(n, _)| *n)
+            .collect::<Vec<_>>();
+        remove.push(block_number);
+
+        Ok((plaintext.into(), remove))
+    }
+}
+
+struct Decompression {
+    limit: u64,
+}
+
+impl PayloadTransform for Decompression {
+    fn name(&self) -> &'static str {
+        "decompression"
+    }
+
+    fn block_type(&self) -> bpv7::BlockType {
+        bpv7::BlockType::Unrecognised(COMPRESSION_BLOCK_TYPE)
+    }
+
+    fn inverse(
+        &self,
+        bundle: &bpv7::Bundle,
+        block_number: u64,
+        data: &[u8],
+        payload: Vec<u8>,
+    ) -> Result<(Vec<u8>, Vec<u64>), Error> {
+        let block = bundle.blocks.get(&block_number).unwrap();
+        let (algorithm, len) = cbor::decode::parse_array(&block.block_data(data)?, |a, _, _| {
+            Ok::<_, cbor::decode::Error>((a.parse::<u64>()?, a.parse::<u64>()?))
+        })
+        .map(|(v, _)| v)?;
+
+        if len > self.limit {
+            return Err(format!(
+                "Compressed payload declares {len} bytes, more than the limit of {}",
+                self.limit
+            )
+            .into());
+        }
+
+        match algorithm {
+            COMPRESSION_DEFLATE => {
+                // Read one byte past the declared length, to detect payloads that overrun it
+                let mut plaintext = Vec::new();
+                flate2::read::DeflateDecoder::new(payload.as_slice())
+                    .take(len + 1)
+                    .read_to_end(&mut plaintext)?;
+                if plaintext.len() as u64 != len {
+                    return Err(format!(
+                        "Compressed payload does not match its declared length of {len} bytes"
+                    )
+                    .into());
+                }
+                Ok((plaintext, vec![block_number]))
+            }
+            algorithm => {
+                Err(format!("Unsupported payload compression algorithm {algorithm}").into())
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Registry {
+    transforms: Vec<Box<dyn PayloadTransform>>,
+}
+
+impl Registry {
+    pub fn new(config: &::config::Config) -> Self {
+        let mut registry = Self::default();
+
+        let keys = load_keys(config, "bcb_keys");
+        if !keys.is_empty() {
+            registry.register(Box::new(Decryption { keys }));
+        }
+
+        if settings::get_with_default(config, "payload_decompression", true)
+            .trace_expect("Invalid 'payload_decompression' value in configuration")
+        {
+            registry.register(Box::new(Decompression {
+                limit: settings::get_with_default(
+                    config,
+                    "payload_decompression_limit",
+                    DEFAULT_DECOMPRESSION_LIMIT,
+                )
+                .trace_expect("Invalid 'payload_decompression_limit' value in configuration"),
+            }));
+        }
+        registry
+    }
+
+    pub fn register(&mut self, transform: Box<dyn PayloadTransform>) {
+        info!("Registered '{}' delivery transform", transform.name());
+        self.transforms.push(transform);
+    }
+
+    pub fn applies(&self, bundle: &bpv7::Bundle) -> bool {
+        self.transforms.iter().any(|transform| {
+            bundle
+                .blocks
+                .values()
+                .any(|block| block.block_type == transform.block_type())
+        })
+    }
+
+    // Returns the rewritten bundle data, or None if no transforms apply
+    pub fn inverse(&self, bundle: &bpv7::Bundle, data: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+        if !self.applies(bundle) {
+            return Ok(None);
+        }
+
+        let payload_block = bundle.blocks.get(&1).ok_or("Bundle has no payload block")?;
+        let mut payload = payload_block.block_data(data)?.into_vec();
+        let mut remove = Vec::new();
+        for transform in &self.transforms {
+            let mut block_numbers = bundle
+                .blocks
+                .iter()
+                .filter(|(_, block)| block.block_type == transform.block_type())
+                .map(|(block_number, _)| *block_number)
+                .collect::<Vec<_>>();
+            block_numbers.sort();
+
+            for block_number in block_numbers {
+                let (new_payload, blocks) =
+                    transform.inverse(bundle, block_number, data, payload)?;
+                payload = new_payload;
+                remove.extend(blocks);
+            }
+        }
+
+        if remove.is_empty() {
+            return Ok(None);
+        }
+
+        let mut editor = bpv7::Editor::new(bundle, data);
+        for block_number in remove {
+            editor = editor.remove_extension_block(block_number);
+        }
+        Ok(Some(
+            editor
+                .replace_extension_block(bpv7::BlockType::Payload)
+                .data(payload)
+                .build()
+                .build(),
+        ))
+    }
+}
+
+// Symmetric keys, in hex, by security source
+pub(super) fn load_keys(config: &::config::Config, table: &str) -> HashMap<bpv7::Eid, Box<[u8]>> {
+    let mut keys = HashMap::new();
+    for (source, key) in config.get_table(table).unwrap_or_default() {
+        let eid = source
+            .parse::<bpv7::Eid>()
+            .trace_expect(&format!("Invalid EID '{source}' in '{table}'"));
+        let key = key
+            .into_string()
+            .ok()
+            .and_then(|key| utils::settings::decode_hex(&key))
+            .trace_expect(&format!("Invalid key for '{source}' in '{table}'"));
+        keys.insert(eid, key);
+    }
+    keys
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn compressed_bundle(payload: &[u8], declared_len: u64) -> (bpv7::Bundle, Vec<u8>) {
+        let mut encoder =
+            flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(payload).unwrap();
+
+        bpv7::Builder::new()
+            .source("ipn:1.1".parse().unwrap())
+            .destination("ipn:2.1".parse().unwrap())
+            .add_extension_block(bpv7::BlockType::Unrecognised(COMPRESSION_BLOCK_TYPE))
+            .data(cbor::encode::emit_array(Some(2), |a| {
+                a.emit(COMPRESSION_DEFLATE);
+                a.emit(declared_len);
+            }))
+            .build()
+            .add_payload_block(encoder.finish().unwrap())
+            .build()
+    }
+
+    fn registry(limit: u64) -> Registry {
+        let mut registry = Registry::default();
+        registry.register(Box::new(Decompression { limit }));
+        registry
+    }
+
+    #[test]
+    fn decompress() {
+        let payload = b"Hello, hello, hello, hello!".repeat(8);
+        let (bundle, data) = compressed_bundle(&payload, payload.len() as u64);
+
+        let data = registry(1024).inverse(&bundle, &data).unwrap().unwrap();
+        let Ok(bpv7::ValidBundle::Valid(bundle, _)) =
+            bpv7::ValidBundle::parse(&data, |_, _| Ok(None))
+        else {
+            panic!("Decompressed bundle is invalid");
+        };
+        assert!(!registry(1024).applies(&bundle));
+        assert_eq!(
+            bundle
+                .blocks
+                .get(&1)
+                .unwrap()
+                .block_data(&data)
+                .unwrap()
+                .as_ref(),
+            payload.as_slice()
+        );
+    }
+
+    #[test]
+    fn bounded() {
+        let payload = vec![0u8; 4096];
+
+        // More than the limit, or more than declared
+        let (bundle, data) = compressed_bundle(&payload, payload.len() as u64);
+        assert!(registry(1024).inverse(&bundle, &data).is_err());
+
+        let (bundle, data) = compressed_bundle(&payload, 512);
+        assert!(registry(1024).inverse(&bundle, &data).is_err());
+    }
+}
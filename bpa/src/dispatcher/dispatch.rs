@@ -1,4 +1,8 @@
 use super::*;
+use tracing::Instrument;
+
+// The most waiting bundles re-dispatched in a single batch
+const REDISPATCH_BATCH_SIZE: usize = 256;
 
 pub(super) enum DispatchResult {
     Done,
@@ -10,7 +14,7 @@ impl Dispatcher {
     #[inline]
     pub async fn dispatch_bundle(&self, bundle: metadata::Bundle) -> Result<(), Error> {
         // Put bundle into channel, ignoring errors as the only ones are intentional
-        _ = self.tx.send(bundle).await;
+        _ = self.tx.send((bundle, tracing::Span::current())).await;
         Ok(())
     }
 
@@ -40,6 +44,20 @@ impl Dispatcher {
                         {
                             // The bundle is for the Administrative Endpoint
                             self.administrative_bundle(&mut bundle).await?
+                        } else if self.is_echo_endpoint(&bundle.bundle.destination) {
+                            // The bundle is for the echo service
+                            self.echo_bundle(&mut bundle).await?
+                        } else if self.is_prophet_endpoint(&bundle.bundle.destination) {
+                            // The bundle is from the PRoPHET service of a neighbour
+                            self.prophet_bundle(&mut bundle).await?
+                        } else if self
+                            .app_registry
+                            .ordered_delivery(&bundle.bundle.destination)
+                            .await
+                        {
+                            // Hold until the bundles created before it have been released
+                            self.sequence_bundle(bundle.clone()).await?;
+                            DispatchResult::Done
                         } else {
                             // The bundle is ready for collection
                             trace!("Bundle is ready for local delivery");
@@ -48,6 +66,9 @@ impl Dispatcher {
                                 .await
                                 .map(|_| DispatchResult::Continue)?
                         }
+                    } else if let Some(tunnel) = self.bibe_tunnel(&bundle) {
+                        // Send through a tunnel to another BPA
+                        self.encapsulate_bundle(&mut bundle, tunnel).await?
                     } else {
                         // Forward to another BPA
                         self.forward_bundle(&mut bundle).await?
@@ -58,10 +79,13 @@ impl Dispatcher {
                     DispatchResult::Done
                 }
                 metadata::BundleStatus::CollectionPending => {
-                    // Check if we have a local service registered
-                    if let Some(endpoint) = self
+                    // Push to any subscribed applications
+                    self.push_notify(&bundle.bundle.destination);
+
+                    // Check if we have local services registered
+                    for endpoint in self
                         .app_registry
-                        .find_by_eid(&bundle.bundle.destination)
+                        .find_all_by_eid(&bundle.bundle.destination)
                         .await
                     {
                         // Notify that the bundle is ready for collection
@@ -87,6 +111,107 @@ impl Dispatcher {
         }
     }
 
+    #[instrument(skip(self))]
+    pub async fn redispatch(&self, pattern: &bpv7::EidPattern) -> Result<u64, Error> {
+        let (tx, mut rx) = tokio::sync::mpsc::channel::<metadata::Bundle>(16);
+        let store = self.store.clone();
+        let scan_pattern = pattern.clone();
+        let h = tokio::spawn(
+            async move { store.get_waiting_bundles_matching(&scan_pattern, tx).await },
+        );
+
+        // Clear the wait states in batches, if the store can do that in one go
+        let batch_size = if self.store.metadata_capabilities().transactional_batches {
+            REDISPATCH_BATCH_SIZE
+        } else {
+            1
+        };
+
+        let mut count = 0u64;
+        let mut batch = Vec::new();
+        while let Some(bundle) = rx.recv().await {
+            batch.push(bundle);
+            if batch.len() >= batch_size {
+                count = count.saturating_add(self.redispatch_batch(&mut batch).await?);
+            }
+        }
+        count = count.saturating_add(self.redispatch_batch(&mut batch).await?);
+
+        h.await.trace_expect("Task terminated unexpectedly")?;
+
+        info!("Re-dispatched {count} waiting bundles for {pattern}");
+        Ok(count)
+    }
+
+    async fn redispatch_batch(&self, batch: &mut Vec<metadata::Bundle>) -> Result<u64, Error> {
+        // Clear the wait state, and dispatch now, unless the bundle has fallen due meanwhile
+        self.store
+            .claim_statuses(batch, metadata::BundleStatus::DispatchPending)
+            .await?;
+        let count = batch.len() as u64;
+        for bundle in batch.drain(..) {
+            self.dispatch_bundle(bundle).await?;
+        }
+        Ok(count)
+    }
+
+    // Returns false if there is no such bundle, or it is not in a state to be re-dispatched
+    #[instrument(skip(self))]
+    pub async fn redispatch_bundle(&self, bundle_id: &bpv7::BundleId) -> Result<bool, Error> {
+        let Some(mut bundle) = self.store.load(bundle_id).await? else {
+            return Ok(false);
+        };
+        match bundle.metadata.status {
+            // Still being received, or already gone
+            metadata::BundleStatus::IngressPending | metadata::BundleStatus::Tombstone(_) => {
+                return Ok(false)
+            }
+            metadata::BundleStatus::DispatchPending => {}
+            _ => {
+                if !self
+                    .store
+                    .claim_status(&mut bundle, metadata::BundleStatus::DispatchPending)
+                    .await?
+                {
+                    // Something else is dispatching it already
+                    return Ok(false);
+                }
+            }
+        }
+
+        info!("Re-dispatching bundle {bundle_id:?} by request");
+        self.dispatch_bundle(bundle).await?;
+        Ok(true)
+    }
+
+    // Returns false if there is no such bundle
+    #[instrument(skip(self))]
+    pub async fn delete_bundle(&self, bundle_id: &bpv7::BundleId) -> Result<bool, Error> {
+        let Some(bundle) = self.store.load(bundle_id).await? else {
+            return Ok(false);
+        };
+        if let metadata::BundleStatus::Tombstone(_) = bundle.metadata.status {
+            return Ok(false);
+        }
+
+        info!("Deleting bundle {bundle_id:?} by request");
+        self.drop_bundle(
+            bundle,
+            Some(bpv7::StatusReportReasonCode::NoAdditionalInformation),
+        )
+        .await?;
+        Ok(true)
+    }
+
+    #[instrument(skip(self))]
+    async fn shed_bundle(&self, mut bundle: metadata::Bundle) -> Result<(), Error> {
+        // Park the bundle in the store, it falls due again on the next tick of the store
+        trace!("Dispatcher is over its memory limit, deferring bundle");
+        self.store
+            .set_status(&mut bundle, metadata::BundleStatus::Waiting(clock::now()))
+            .await
+    }
+
     pub(super) async fn bundle_wait(
         &self,
         bundle: &mut metadata::Bundle,
@@ -100,7 +225,7 @@ impl Dispatcher {
             )));
         }
 
-        let wait = until - time::OffsetDateTime::now_utc();
+        let wait = until - clock::now();
         if wait > time::Duration::new(self.config.wait_sample_interval as i64, 0) {
             // Nothing to do now, it will be picked up later
             trace!("Bundle will wait offline until: {until}");
@@ -114,7 +239,7 @@ impl Dispatcher {
         trace!("Bundle will wait inline until: {until}");
 
         // Wait a bit
-        if !cancellable_sleep(wait, &self.cancel_token).await {
+        if !clock::sleep(wait, &self.cancel_token).await {
             // Cancelled
             Ok(DispatchResult::Done)
         } else {
@@ -134,7 +259,7 @@ impl Dispatcher {
                 bpv7::StatusReportReasonCode::NoTimelyContactWithNextNodeOnRoute,
             )));
         }
-        let wait = until - time::OffsetDateTime::now_utc();
+        let wait = until - clock::now();
         if wait > time::Duration::new(self.config.wait_sample_interval as i64, 0) {
             // Nothing to do now, it will be picked up later
             return Ok(DispatchResult::Done);
@@ -143,15 +268,19 @@ impl Dispatcher {
         trace!("Bundle will wait inline until: {until}");
 
         // Wait a bit
-        if !cancellable_sleep(wait, &self.cancel_token).await {
+        if !clock::sleep(wait, &self.cancel_token).await {
             // Cancelled
             Ok(DispatchResult::Done)
-        } else {
+        } else if self
+            .store
+            .claim_status(bundle, metadata::BundleStatus::DispatchPending)
+            .await?
+        {
             // Clear the wait state, and keep dispatching
-            self.store
-                .set_status(bundle, metadata::BundleStatus::DispatchPending)
-                .await
-                .map(|_| DispatchResult::Continue)
+            Ok(DispatchResult::Continue)
+        } else {
+            // It has been re-dispatched meanwhile
+            Ok(DispatchResult::Done)
         }
     }
 
@@ -161,7 +290,7 @@ impl Dispatcher {
         bundle: &mut metadata::Bundle,
     ) -> Result<DispatchResult, Error> {
         // Check if it's worth us waiting inline
-        let wait = until - time::OffsetDateTime::now_utc();
+        let wait = until - clock::now();
         if wait > time::Duration::new(self.config.wait_sample_interval as i64, 0) {
             // Nothing to do now, it will be picked up later
             trace!("Bundle will wait offline until: {until}");
@@ -171,7 +300,7 @@ impl Dispatcher {
         trace!("Bundle will wait inline until: {until}");
 
         // Wait a bit
-        if !cancellable_sleep(wait, &self.cancel_token).await {
+        if !clock::sleep(wait, &self.cancel_token).await {
             // Cancelled
             return Ok(DispatchResult::Done);
         }
@@ -185,9 +314,14 @@ impl Dispatcher {
             Some(status) => {
                 if status == bundle.metadata.status {
                     // Clear the wait state
-                    self.store
-                        .set_status(bundle, metadata::BundleStatus::DispatchPending)
-                        .await?;
+                    if !self
+                        .store
+                        .claim_status(bundle, metadata::BundleStatus::DispatchPending)
+                        .await?
+                    {
+                        // It has been re-dispatched meanwhile
+                        return Ok(DispatchResult::Done);
+                    }
                 } else {
                     bundle.metadata.status = status;
                 }
@@ -200,7 +334,7 @@ impl Dispatcher {
 #[instrument(skip_all)]
 pub(super) async fn dispatch_task(
     dispatcher: Arc<Dispatcher>,
-    mut rx: tokio::sync::mpsc::Receiver<metadata::Bundle>,
+    mut rx: tokio::sync::mpsc::Receiver<(metadata::Bundle, tracing::Span)>,
 ) {
     // We're going to spawn a bunch of tasks
     let mut task_set = tokio::task::JoinSet::new();
@@ -222,11 +356,40 @@ pub(super) async fn dispatch_task(
             },
             bundle = rx.recv() => {
                 let dispatcher = dispatcher.clone();
-                let bundle = bundle.trace_expect("Dispatcher channel unexpectedly closed");
+                let (bundle, origin) = bundle.trace_expect("Dispatcher channel unexpectedly closed");
+
+                // Processing is not part of whatever dispatched the bundle, but follows from it
+                let span = tracing::info_span!(parent: None, "dispatch");
+                span.follows_from(&origin);
+                drop(origin);
+
+                // Account for the bundle data the task may load
+                let bytes = bundle
+                    .bundle
+                    .blocks
+                    .values()
+                    .map(|block| block.data_start + block.data_len)
+                    .max()
+                    .unwrap_or(0);
+                // In strict mode every bundle must fit within the budget, or wait its turn
+                let reservation = match &bundle.metadata.status {
+                    _ if utils::memory::is_strict() => {
+                        utils::memory::try_reserve(utils::memory::Subsystem::Dispatcher, bytes)
+                    }
+                    metadata::BundleStatus::DispatchPending => {
+                        utils::memory::try_reserve(utils::memory::Subsystem::Dispatcher, bytes)
+                    }
+                    _ => Some(utils::memory::reserve(utils::memory::Subsystem::Dispatcher, bytes)),
+                };
 
                 task_set.spawn(async move {
-                    dispatcher.process_bundle(bundle).await.trace_expect("Failed to dispatch bundle");
-                });
+                    if let Some(reservation) = reservation {
+                        dispatcher.process_bundle(bundle).await.trace_expect("Failed to dispatch bundle");
+                        drop(reservation);
+                    } else {
+                        dispatcher.shed_bundle(bundle).await.trace_expect("Failed to shed bundle");
+                    }
+                }.instrument(span));
             },
             Some(r) = task_set.join_next(), if !task_set.is_empty() => {
                 r.trace_expect("Task terminated unexpectedly");
@@ -242,3 +405,66 @@ pub(super) async fn dispatch_task(
         r.trace_expect("Task terminated unexpectedly")
     }
 }
+
+pub(super) async fn fib_event_task(
+    dispatcher: Arc<Dispatcher>,
+    mut rx: tokio::sync::broadcast::Receiver<fib::Event>,
+) {
+    // Next hops suppressed for flapping, by when they may be reused
+    let mut suppressed = std::collections::BTreeSet::new();
+    loop {
+        let wait = suppressed
+            .first()
+            .map_or(time::Duration::ZERO, |(until, _)| *until - clock::now());
+        tokio::select! {
+            event = rx.recv() => match event {
+                Ok(fib::Event::Active(pattern)) => {
+                    // Don't wait for the poller to notice the route is back, but don't hold
+                    // up the events behind it either
+                    let dispatcher = dispatcher.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = dispatcher.redispatch(&pattern).await {
+                            warn!("Failed to re-dispatch bundles for {pattern}: {e}");
+                        }
+                    });
+                }
+                Ok(fib::Event::Inactive(pattern)) => {
+                    trace!("Routes to {pattern} are inactive");
+                }
+                Ok(fib::Event::Dampened { handle, until }) => {
+                    suppressed.insert((until, handle));
+                }
+                Err(tokio::sync::broadcast::error::RecvError::Lagged(n)) => {
+                    warn!("Missed {n} FIB events");
+
+                    // Any Dampened events missed would leave their next hops suppressed
+                    // forever, so start again from what the FIB has suppressed
+                    if let Some(fib) = &dispatcher.fib {
+                        suppressed = fib.suppressed().await.into_iter().collect();
+                    }
+                }
+                Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+            },
+            completed = clock::sleep(wait, &dispatcher.cancel_token), if !suppressed.is_empty() => {
+                if !completed {
+                    break;
+                }
+                let Some(fib) = &dispatcher.fib else {
+                    break;
+                };
+                while let Some((until, handle)) = suppressed.first().copied() {
+                    if until > clock::now() {
+                        break;
+                    }
+                    suppressed.pop_first();
+
+                    // Reuse sends Active events for the routes, which re-dispatches them
+                    if let Some(retry) = fib.reuse(handle).await {
+                        suppressed.insert((retry, handle));
+                    }
+                }
+            },
+            _ = dispatcher.cancel_token.cancelled() => break
+        }
+    }
+}
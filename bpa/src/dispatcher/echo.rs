@@ -0,0 +1,188 @@
+use super::*;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::sync::oneshot;
+
+/* The echo service returns every bundle sent to one of the configured echo endpoints to
+ * its source, with the same payload, like dtnping's responders in other implementations.
+ * Pings are sent from the local echo endpoint with a payload of the nonce of this BPA and
+ * a ping id, so a reply is recognised whichever implementation echoed it, and is never
+ * echoed back again, even if it arrives after the ping has timed out */
+
+pub struct Pings {
+    nonce: u64,
+    next_id: AtomicU64,
+    outstanding: std::sync::Mutex<HashMap<u64, oneshot::Sender<()>>>,
+}
+
+impl Default for Pings {
+    fn default() -> Self {
+        Self {
+            nonce: utils::random::with_rng(|rng| rng.next_u64()),
+            next_id: Default::default(),
+            outstanding: Default::default(),
+        }
+    }
+}
+
+fn encode_ping(nonce: u64, id: u64) -> Vec<u8> {
+    cbor::encode::emit_array(Some(2), |a| {
+        a.emit(nonce);
+        a.emit(id);
+    })
+}
+
+fn decode_ping(data: &[u8]) -> Option<(u64, u64)> {
+    cbor::decode::parse_array(data, |a, _, _| {
+        Ok::<_, cbor::decode::Error>((a.parse()?, a.parse()?))
+    })
+    .map(|(ping, _)| ping)
+    .ok()
+}
+
+impl Dispatcher {
+    pub(super) fn is_echo_endpoint(&self, eid: &bpv7::Eid) -> bool {
+        self.config.echo_endpoints.contains(eid)
+    }
+
+    // The echo endpoint to send pings to `destination` from, preferring the same scheme
+    fn echo_source(&self, destination: &bpv7::Eid) -> Option<bpv7::Eid> {
+        let is_dtn = |eid: &bpv7::Eid| matches!(eid, bpv7::Eid::Dtn { .. });
+        self.config
+            .echo_endpoints
+            .iter()
+            .find(|eid| is_dtn(eid) == is_dtn(destination))
+            .or(self.config.echo_endpoints.first())
+            .cloned()
+    }
+
+    #[instrument(skip(self))]
+    pub(super) async fn echo_bundle(
+        &self,
+        bundle: &mut metadata::Bundle,
+    ) -> Result<DispatchResult, Error> {
+        let Some(data) = self.load_data(bundle).await? else {
+            // Bundle data was deleted sometime during processing - this is benign
+            return Ok(DispatchResult::Done);
+        };
+
+        let Some(Ok(payload)) = bundle
+            .bundle
+            .blocks
+            .get(&1)
+            .map(|block| block.block_data(data.as_ref().as_ref()))
+        else {
+            trace!("Echo request has an unreadable payload");
+            return Ok(DispatchResult::Drop(Some(
+                bpv7::StatusReportReasonCode::BlockUnintelligible,
+            )));
+        };
+
+        // Check for a reply to one of our own pings
+        if let Some((nonce, id)) = decode_ping(&payload) {
+            if nonce == self.pings.nonce {
+                let waiter = self
+                    .pings
+                    .outstanding
+                    .lock()
+                    .trace_expect("Failed to lock mutex")
+                    .remove(&id);
+                match waiter {
+                    Some(waiter) => {
+                        _ = waiter.send(());
+                    }
+                    None => trace!("Ignoring late reply to ping {id}"),
+                }
+                return Ok(DispatchResult::Drop(None));
+            }
+        }
+
+        if let bpv7::Eid::Null = bundle.bundle.id.source {
+            trace!("Cannot echo an anonymous bundle");
+            return Ok(DispatchResult::Drop(None));
+        }
+
+        trace!("Echoing bundle to {}", bundle.bundle.id.source);
+        metrics::counter!("echo_bundles_total").increment(1);
+        self.local_dispatch(SendRequest {
+            source: bundle.bundle.destination.clone(),
+            destination: bundle.bundle.id.source.clone(),
+            data: Bytes::from(payload.into_vec()),
+            lifetime: Some(bundle.bundle.lifetime),
+            flags: None,
+            track: false,
+        })
+        .await
+        .map(|_| DispatchResult::Drop(None))
+    }
+
+    // Ping the echo service at `destination`, returning the round trip time, or None if no
+    // reply arrives within `timeout`
+    #[instrument(skip(self))]
+    pub async fn ping(
+        &self,
+        destination: bpv7::Eid,
+        timeout: time::Duration,
+    ) -> Result<Option<time::Duration>, Error> {
+        let Some(source) = self.echo_source(&destination) else {
+            return Err("No echo endpoints are configured".into());
+        };
+
+        let id = self.pings.next_id.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = oneshot::channel();
+        self.pings
+            .outstanding
+            .lock()
+            .trace_expect("Failed to lock mutex")
+            .insert(id, tx);
+
+        let sent = clock::now();
+        let r = match self
+            .local_dispatch(SendRequest {
+                source,
+                destination,
+                data: Bytes::from(encode_ping(self.pings.nonce, id)),
+                // The ping is of no use once it has timed out
+                lifetime: Some(timeout.whole_milliseconds().clamp(1, u64::MAX as i128) as u64),
+                flags: None,
+                track: false,
+            })
+            .await
+        {
+            Err(e) => Err(e),
+            Ok(_) => tokio::select! {
+                r = rx => Ok(r.ok().map(|_| clock::now() - sent)),
+                _ = clock::sleep(timeout, &self.cancel_token) => Ok(None),
+            },
+        };
+
+        self.pings
+            .outstanding
+            .lock()
+            .trace_expect("Failed to lock mutex")
+            .remove(&id);
+
+        metrics::counter!("echo_pings_total").increment(1);
+        if let Ok(None) = r {
+            metrics::counter!("echo_pings_lost_total").increment(1);
+        }
+        r
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ping_roundtrip() {
+        let data = encode_ping(0x0123_4567_89ab_cdef, 42);
+        assert_eq!(decode_ping(&data), Some((0x0123_4567_89ab_cdef, 42)));
+    }
+
+    #[test]
+    fn foreign_payload() {
+        assert_eq!(decode_ping(b"hello"), None);
+        assert_eq!(decode_ping(&cbor::encode::emit("hello")), None);
+    }
+}
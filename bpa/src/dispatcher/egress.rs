@@ -0,0 +1,336 @@
+use super::*;
+use std::collections::HashMap;
+
+/* Bundles leave our administrative domain when they are forwarded to a destination that
+ * matches none of the 'domain_endpoints', if any are configured, or via a CLA registered
+ * under one of the 'boundary_clas' names.  The 'strip_unknown' transform removes the
+ * unrecognised extension blocks, including those of private and experimental types, from
+ * bundles leaving the domain, so metadata used within the domain is not leaked beyond it.
+ * Blocks flagged to delete the bundle if they cannot be processed are left, as are blocks
+ * targeted by a BIB or BCB, as removing them would invalidate the security operation */
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transform {
+    RemoveUnrecognised, // Remove unrecognised blocks flagged for deletion
+    StripUnknown,       // Remove unrecognised blocks from bundles leaving the domain
+    PreviousNode,       // Replace the Previous Node block
+    HopCount,           // Increment the Hop Count
+    BundleAge,          // Update the Bundle Age
+    CrcPolicy,          // Apply the configured CRC policy
+}
+
+impl Transform {
+    // The order transforms are applied in, unless configured otherwise
+    pub const DEFAULT_ORDER: [Transform; 6] = [
+        Transform::RemoveUnrecognised,
+        Transform::StripUnknown,
+        Transform::PreviousNode,
+        Transform::HopCount,
+        Transform::BundleAge,
+        Transform::CrcPolicy,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Transform::RemoveUnrecognised => "remove_unrecognised",
+            Transform::StripUnknown => "strip_unknown",
+            Transform::PreviousNode => "previous_node",
+            Transform::HopCount => "hop_count",
+            Transform::BundleAge => "bundle_age",
+            Transform::CrcPolicy => "crc_policy",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::DEFAULT_ORDER
+            .into_iter()
+            .find(|transform| transform.name() == name)
+    }
+}
+
+// The parts of a block we compare when describing what a transform changed
+type BlockSummary = (bpv7::BlockType, bpv7::CrcType, Vec<u8>);
+
+fn summarise_blocks(data: &[u8]) -> Option<HashMap<u64, BlockSummary>> {
+    let (bundle, data) = match bpv7::ValidBundle::parse(data, |_, _| Ok(None)).ok()? {
+        bpv7::ValidBundle::Valid(bundle, _) => (bundle, data),
+        bpv7::ValidBundle::Rewritten(bundle, data, _) => {
+            return Some(summarise(&bundle, &data));
+        }
+        bpv7::ValidBundle::Invalid(..) => return None,
+    };
+    Some(summarise(&bundle, data))
+}
+
+fn summarise(bundle: &bpv7::Bundle, data: &[u8]) -> HashMap<u64, BlockSummary> {
+    bundle
+        .blocks
+        .iter()
+        .map(|(block_number, block)| {
+            (
+                *block_number,
+                (
+                    block.block_type,
+                    block.crc_type,
+                    block.payload(data).to_vec(),
+                ),
+            )
+        })
+        .collect()
+}
+
+fn describe_changes(before: &[u8], after: &[u8]) -> String {
+    let (Some(before), Some(after)) = (summarise_blocks(before), summarise_blocks(after)) else {
+        return "the result is not a valid bundle".to_string();
+    };
+
+    let mut block_numbers = before.keys().chain(after.keys()).collect::<Vec<_>>();
+    block_numbers.sort();
+    block_numbers.dedup();
+
+    let mut changes = Vec::new();
+    for block_number in block_numbers {
+        match (before.get(block_number), after.get(block_number)) {
+            (Some((block_type, _, _)), None) => {
+                changes.push(format!("remove block {block_number} ({block_type})"))
+            }
+            (None, Some((block_type, _, _))) => {
+                changes.push(format!("add block {block_number} ({block_type})"))
+            }
+            (Some((block_type, crc_before, data_before)), Some((_, crc_after, data_after))) => {
+                if crc_before != crc_after {
+                    changes.push(format!(
+                        "change block {block_number} ({block_type}) CRC from {crc_before:?} to {crc_after:?}"
+                    ));
+                }
+                if data_before != data_after {
+                    changes.push(format!("change block {block_number} ({block_type}) data"));
+                }
+            }
+            (None, None) => {}
+        }
+    }
+
+    if changes.is_empty() {
+        "no changes".to_string()
+    } else {
+        changes.join(", ")
+    }
+}
+
+// The blocks targeted by BIBs, or None if a BIB cannot be parsed
+fn bib_targets(bundle: &bpv7::Bundle, data: &[u8]) -> Option<Vec<u64>> {
+    let mut targets = Vec::new();
+    for block in bundle.blocks.values() {
+        if let bpv7::BlockType::BlockIntegrity = block.block_type {
+            // The security targets are the first item of the Abstract Security Block
+            let asb = block.block_data(data).ok()?;
+            cbor::decode::parse_array(&asb, |a, _, _| {
+                while let Some(target) = a.try_parse::<u64>()? {
+                    targets.push(target);
+                }
+                Ok::<_, cbor::decode::Error>(())
+            })
+            .ok()?;
+        }
+    }
+    Some(targets)
+}
+
+impl Dispatcher {
+    // Whether a bundle sent towards `destination`, via the CLA if known, leaves the domain
+    pub(super) async fn crosses_boundary(
+        &self,
+        destination: &bpv7::Eid,
+        handle: Option<u32>,
+    ) -> bool {
+        if self
+            .config
+            .domain_endpoints
+            .as_ref()
+            .is_some_and(|domain| domain.find(destination).is_empty())
+        {
+            return true;
+        }
+        let (Some(handle), false) = (handle, self.config.boundary_clas.is_empty()) else {
+            return false;
+        };
+        self.cla_registry
+            .name(handle)
+            .await
+            .is_some_and(|name| self.config.boundary_clas.contains(&name))
+    }
+
+    // Translate the bundle to RFC 5050 if the CLA is one of the 'bpv6_clas', or None if not
+    pub(super) async fn bpv6_egress(
+        &self,
+        bundle: &metadata::Bundle,
+        data: &[u8],
+        handle: u32,
+    ) -> Option<Result<Vec<u8>, String>> {
+        if self.config.bpv6_clas.is_empty() {
+            return None;
+        }
+        let name = self.cla_registry.name(handle).await?;
+        if !self.config.bpv6_clas.contains(&name) {
+            return None;
+        }
+
+        cfg_if::cfg_if! {
+            if #[cfg(feature = "bpv6")] {
+                let r = hardy_bpv6::from_bpv7(&bundle.bundle, data).map_err(|e| e.to_string());
+                if r.is_ok() {
+                    metrics::counter!("bpv6_bundles_translated_total", "direction" => "egress")
+                        .increment(1);
+                }
+                Some(r)
+            } else {
+                let _ = (bundle, data);
+                None
+            }
+        }
+    }
+
+    pub(super) fn update_extension_blocks(
+        &self,
+        bundle: &metadata::Bundle,
+        source_data: hardy_bpa_api::storage::DataRef,
+        boundary: bool,
+    ) -> Vec<u8> {
+        let data = source_data.as_ref().as_ref();
+        let mut editor = bpv7::Editor::new(&bundle.bundle, data);
+
+        for transform in &self.config.egress_transforms {
+            editor = if self.config.egress_dry_run.contains(transform) {
+                // Log what the transform would do, but leave the bundle alone
+                let before = editor.clone().build();
+                let after = self
+                    .egress_transform(*transform, bundle, data, boundary, editor.clone())
+                    .build();
+                info!(
+                    "Egress transform '{}' (dry-run) on bundle {:?}: {}",
+                    transform.name(),
+                    bundle.bundle.id,
+                    describe_changes(&before, &after)
+                );
+                editor
+            } else {
+                self.egress_transform(*transform, bundle, data, boundary, editor)
+            };
+        }
+
+        editor.build()
+    }
+
+    fn egress_transform<'a>(
+        &self,
+        transform: Transform,
+        bundle: &metadata::Bundle,
+        data: &[u8],
+        boundary: bool,
+        mut editor: bpv7::Editor<'a>,
+    ) -> bpv7::Editor<'a> {
+        match transform {
+            Transform::RemoveUnrecognised => {
+                // Remove unrecognized blocks we are supposed to
+                for (block_number, block) in &bundle.bundle.blocks {
+                    if let bpv7::BlockType::Unrecognised(_) = &block.block_type {
+                        if block.flags.delete_block_on_failure {
+                            editor = editor.remove_extension_block(*block_number);
+                        }
+                    }
+                }
+                editor
+            }
+            Transform::StripUnknown => {
+                if !boundary {
+                    return editor;
+                }
+                let Some(bib_targets) = bib_targets(&bundle.bundle, data) else {
+                    return editor;
+                };
+                for (block_number, block) in &bundle.bundle.blocks {
+                    if let bpv7::BlockType::Unrecognised(_) = &block.block_type {
+                        if !block.flags.delete_bundle_on_failure
+                            && block.bcb.is_none()
+                            && !bib_targets.contains(block_number)
+                        {
+                            trace!(
+                                "Stripping block {block_number} ({}) at the domain boundary",
+                                block.block_type
+                            );
+                            editor = editor.remove_extension_block(*block_number);
+                        }
+                    }
+                }
+                editor
+            }
+            Transform::PreviousNode => {
+                // Previous Node Block
+                editor
+                    .replace_extension_block(bpv7::BlockType::PreviousNode)
+                    .data(cbor::encode::emit(
+                        &self
+                            .config
+                            .admin_endpoints
+                            .get_admin_endpoint(&bundle.bundle.destination),
+                    ))
+                    .build()
+            }
+            Transform::HopCount => {
+                // Increment Hop Count
+                if let Some(hop_count) = &bundle.bundle.hop_count {
+                    editor = editor
+                        .replace_extension_block(bpv7::BlockType::HopCount)
+                        .data(cbor::encode::emit(&bpv7::HopInfo {
+                            limit: hop_count.limit,
+                            count: hop_count.count + 1,
+                        }))
+                        .build();
+                }
+                editor
+            }
+            Transform::BundleAge => {
+                // Update Bundle Age, if required
+                if bundle.bundle.age.is_some() || bundle.bundle.id.timestamp.creation_time.is_none()
+                {
+                    // We have a bundle age block already, or no valid clock at bundle source
+                    // So we must add an updated bundle age block
+                    let bundle_age = (clock::now() - bundle.creation_time())
+                        .whole_milliseconds()
+                        .clamp(0, u64::MAX as i128) as u64;
+
+                    editor = editor
+                        .replace_extension_block(bpv7::BlockType::BundleAge)
+                        .data(cbor::encode::emit(bundle_age))
+                        .build();
+                }
+                editor
+            }
+            Transform::CrcPolicy => {
+                // Apply the CRC policy, unless BPSec is in use, as CRCs must not change under integrity or confidentiality
+                if bundle.bundle.blocks.values().any(|block| {
+                    matches!(
+                        block.block_type,
+                        bpv7::BlockType::BlockIntegrity | bpv7::BlockType::BlockSecurity
+                    )
+                }) {
+                    return editor;
+                }
+
+                match self.config.crc_policy {
+                    super::config::CrcPolicy::Keep => editor,
+                    super::config::CrcPolicy::Add(crc_type) => {
+                        editor.update_crc_types(|current| match current {
+                            bpv7::CrcType::None => crc_type,
+                            current => current,
+                        })
+                    }
+                    super::config::CrcPolicy::Force(crc_type) => {
+                        editor.update_crc_types(|_| crc_type)
+                    }
+                }
+            }
+        }
+    }
+}
@@ -1,9 +1,23 @@
 use super::*;
 
 impl Dispatcher {
+    #[instrument(skip_all, fields(
+        fib = tracing::field::Empty,
+        load = tracing::field::Empty,
+        cla = tracing::field::Empty
+    ))]
     pub(super) async fn forward_bundle(
         &self,
         bundle: &mut metadata::Bundle,
+    ) -> Result<DispatchResult, Error> {
+        let mut timer = StageTimer::new("forwarding", self.config.slow_bundle_threshold);
+        self.forward_bundle_inner(bundle, &mut timer).await
+    }
+
+    async fn forward_bundle_inner(
+        &self,
+        bundle: &mut metadata::Bundle,
+        timer: &mut StageTimer,
     ) -> Result<DispatchResult, Error> {
         let Some(fib) = &self.fib else {
             /* If forwarding is disabled in the configuration, then we can only deliver bundles.
@@ -21,7 +35,8 @@ impl Dispatcher {
          * But it might be rebooting or jammed, so we keep retrying for a "reasonable" amount of time */
         let mut previous = false;
         let mut retries = 0;
-        let mut destination = &bundle.bundle.destination;
+        let return_path = self.report_return_path(bundle);
+        let mut destination = return_path.as_ref().unwrap_or(&bundle.bundle.destination);
 
         loop {
             // Check bundle expiry
@@ -32,8 +47,28 @@ impl Dispatcher {
                 )));
             }
 
+            // Only transmit to the destination while one of its windows is open
+            let windows = match self.destination_window(destination) {
+                shaping::Gate::Open(windows) => windows,
+                shaping::Gate::Closed(next) => {
+                    self.note_window_deferral(destination);
+                    let Some(next) = next else {
+                        return Ok(DispatchResult::Drop(Some(
+                            bpv7::StatusReportReasonCode::NoTimelyContactWithNextNodeOnRoute,
+                        )));
+                    };
+                    return self.bundle_wait(bundle, next).await;
+                }
+            };
+
             // Lookup/Perform actions
             let action = match fib.find(destination).await {
+                Ok(action) => Ok(self.resolve_next_hops(action).await),
+                Err(reason) => Err(reason),
+            };
+            timer.stage("fib");
+
+            let mut action = match action {
                 Err(reason) => {
                     trace!("Bundle is black-holed");
                     return Ok(DispatchResult::Drop(reason));
@@ -41,16 +76,58 @@ impl Dispatcher {
                 Ok(fib::ForwardAction {
                     clas,
                     until: Some(until),
+                    ..
                 }) if clas.is_empty() => {
                     return self.bundle_wait(bundle, until).await;
                 }
                 Ok(action) => action,
             };
 
+            /* Pass over next hops with a known latency that the bundle would not outlive,
+             * and if that is every one of them, drop the bundle now, rather than spend link
+             * capacity on a bundle that cannot possibly arrive in time */
+            let now = clock::now();
+            let expiry = bundle.expiry();
+            let candidates = action.clas.len();
+            action.clas.retain(|endpoint| {
+                endpoint.latency.is_none_or(|latency| {
+                    now.checked_add(latency)
+                        .is_some_and(|arrival| arrival <= expiry)
+                })
+            });
+            if candidates != 0 && action.clas.is_empty() {
+                trace!("Bundle would expire before reaching any next hop");
+                metrics::counter!("forwarding_latency_budget_drops_total").increment(1);
+                return Ok(DispatchResult::Drop(Some(
+                    bpv7::StatusReportReasonCode::NoTimelyContactWithNextNodeOnRoute,
+                )));
+            }
+
             let mut congestion_wait = None;
 
             // For each CLA
             for endpoint in &action.clas {
+                // Pass over CLAs outside their transmission windows, as if congested
+                let windows = match self.cla_window(endpoint.handle).await {
+                    shaping::Gate::Open(cla_windows) => [windows.as_slice(), &cla_windows].concat(),
+                    shaping::Gate::Closed(next) => {
+                        trace!("No transmission window for CLA {} is open", endpoint.handle);
+                        if let Some(next) = next {
+                            congestion_wait = congestion_wait
+                                .map_or(Some(next), |w: time::OffsetDateTime| Some(w.min(next)));
+                        }
+                        continue;
+                    }
+                };
+
+                // Defer forwarding over duty-cycled CLAs until their link is ready
+                if let Some(until) = self.link_wait(endpoint.handle).await {
+                    trace!("Link of CLA {} is not ready", endpoint.handle);
+                    congestion_wait = congestion_wait
+                        .map_or(Some(until), |w: time::OffsetDateTime| Some(w.min(until)));
+                    continue;
+                }
+
                 // Find the named CLA
                 if let Some(e) = self.cla_registry.find(endpoint.handle).await {
                     // Get bundle data from store, now we know we need it!
@@ -58,24 +135,69 @@ impl Dispatcher {
                         // Bundle data was deleted sometime during processing
                         return Ok(DispatchResult::Done);
                     };
+                    timer.stage("load");
 
-                    // Increment Hop Count, etc...
-                    let data = self.update_extension_blocks(bundle, source_data);
+                    // Translate the bundle for BPv6 next hops, or increment Hop Count, etc...
+                    let data = match self
+                        .bpv6_egress(bundle, source_data.as_ref().as_ref(), endpoint.handle)
+                        .await
+                    {
+                        Some(Ok(data)) => data,
+                        Some(Err(e)) => {
+                            trace!(
+                                "Cannot translate bundle to BPv6 for CLA {}: {e}",
+                                endpoint.handle
+                            );
+                            continue;
+                        }
+                        None => {
+                            let boundary = self
+                                .crosses_boundary(destination, Some(endpoint.handle))
+                                .await;
+                            self.update_extension_blocks(bundle, source_data, boundary)
+                        }
+                    };
+                    let len = data.len();
+
+                    // Pass over CLAs that cannot send a bundle this big
+                    if e.capabilities()
+                        .max_bundle_size
+                        .is_some_and(|max| len as u64 > max)
+                    {
+                        trace!("Bundle is too big for CLA {}", endpoint.handle);
+                        continue;
+                    }
 
-                    match e.forward_bundle(destination, data.into()).await {
+                    let r = e
+                        .forward_bundle(destination, endpoint.address.clone(), data.into())
+                        .await;
+                    timer.stage("cla");
+
+                    // Remember congestion, for applications that want to hear about it
+                    self.note_congestion(
+                        endpoint.handle,
+                        match &r {
+                            Ok(cla_registry::ForwardBundleResult::Congested(until)) => Some(*until),
+                            _ => None,
+                        },
+                    );
+
+                    match r {
                         Ok(cla_registry::ForwardBundleResult::Sent) => {
+                            self.note_window_sent(&windows, len);
+
                             // We have successfully forwarded!
-                            return self
-                                .report_bundle_forwarded(bundle)
-                                .await
-                                .map(|_| DispatchResult::Drop(None));
+                            self.report_bundle_forwarded(bundle).await?;
+                            return self.retain_forwarded(bundle).await;
                         }
                         Ok(cla_registry::ForwardBundleResult::Pending(handle, until)) => {
+                            self.note_window_sent(&windows, len);
+
                             // CLA will report successful forwarding
                             // Don't wait longer than expiry
                             let until = until.unwrap_or_else(|| {
                                 warn!("CLA endpoint has not provided a suitable AckPending delay, defaulting to 1 minute");
-                                time::OffsetDateTime::now_utc() + time::Duration::minutes(1)
+                                clock::now() + time::Duration::minutes(1)
                             }).min(bundle.expiry());
 
                             // Set the bundle status to 'Forward Acknowledgement Pending' and re-dispatch
@@ -95,7 +217,12 @@ impl Dispatcher {
                             congestion_wait = congestion_wait
                                 .map_or(Some(until), |w: time::OffsetDateTime| Some(w.min(until)))
                         }
-                        Err(e) => trace!("CLA failed to forward {e}"),
+                        Err(e) => {
+                            trace!("CLA failed to forward {e}");
+
+                            // Fail over to any other routes
+                            self.cla_registry.set_health(endpoint.handle, false).await
+                        }
                     }
                 } else {
                     trace!("FIB has entry for unknown CLA: {endpoint:?}");
@@ -145,7 +272,7 @@ impl Dispatcher {
                 trace!("Retrying ({retries}) FIB lookup to allow FIB and CLAs to resync");
 
                 // Async sleep for 1 second
-                if !cancellable_sleep(time::Duration::seconds(1), &self.cancel_token).await {
+                if !clock::sleep(time::Duration::seconds(1), &self.cancel_token).await {
                     // Cancelled
                     return Ok(DispatchResult::Done);
                 }
@@ -153,59 +280,29 @@ impl Dispatcher {
         }
     }
 
-    fn update_extension_blocks(
-        &self,
-        bundle: &metadata::Bundle,
-        source_data: hardy_bpa_api::storage::DataRef,
-    ) -> Vec<u8> {
-        let mut editor = bpv7::Editor::new(&bundle.bundle, source_data.as_ref().as_ref());
-
-        // Remove unrecognized blocks we are supposed to
-        for (block_number, block) in &bundle.bundle.blocks {
-            if let bpv7::BlockType::Unrecognised(_) = &block.block_type {
-                if block.flags.delete_block_on_failure {
-                    editor = editor.remove_extension_block(*block_number);
+    // Give the next hops the FIB has no route to over to the resolvers
+    async fn resolve_next_hops(&self, mut action: fib::ForwardAction) -> fib::ForwardAction {
+        for eid in std::mem::take(&mut action.resolve) {
+            for address in self.resolvers.resolve(&eid).await {
+                let Some(handle) = self.cla_registry.find_by_name(&address.cla).await else {
+                    trace!(
+                        "Next hop {eid} resolves to unregistered CLA '{}'",
+                        address.cla
+                    );
+                    continue;
+                };
+                let endpoint = fib::Endpoint {
+                    handle,
+                    address: Some(address.address),
+                    mtu: None,
+                    latency: None,
+                };
+                if !action.clas.contains(&endpoint) {
+                    action.clas.push(endpoint);
                 }
             }
         }
-
-        // Previous Node Block
-        editor = editor
-            .replace_extension_block(bpv7::BlockType::PreviousNode)
-            .data(cbor::encode::emit(
-                &self
-                    .config
-                    .admin_endpoints
-                    .get_admin_endpoint(&bundle.bundle.destination),
-            ))
-            .build();
-
-        // Increment Hop Count
-        if let Some(hop_count) = &bundle.bundle.hop_count {
-            editor = editor
-                .replace_extension_block(bpv7::BlockType::HopCount)
-                .data(cbor::encode::emit(&bpv7::HopInfo {
-                    limit: hop_count.limit,
-                    count: hop_count.count + 1,
-                }))
-                .build();
-        }
-
-        // Update Bundle Age, if required
-        if bundle.bundle.age.is_some() || bundle.bundle.id.timestamp.creation_time.is_none() {
-            // We have a bundle age block already, or no valid clock at bundle source
-            // So we must add an updated bundle age block
-            let bundle_age = (time::OffsetDateTime::now_utc() - bundle.creation_time())
-                .whole_milliseconds()
-                .clamp(0, u64::MAX as i128) as u64;
-
-            editor = editor
-                .replace_extension_block(bpv7::BlockType::BundleAge)
-                .data(cbor::encode::emit(bundle_age))
-                .build();
-        }
-
-        editor.build()
+        action
     }
 
     #[instrument(skip(self))]
@@ -214,7 +311,7 @@ impl Dispatcher {
         handle: u32,
         bundle_id: &str,
     ) -> Result<(), tonic::Status> {
-        let Some(bundle) = self
+        let Some(mut bundle) = self
             .store
             .load(
                 &bpv7::BundleId::from_key(bundle_id)
@@ -233,10 +330,18 @@ impl Dispatcher {
                     .await
                     .map_err(tonic::Status::from_error)?;
 
-                // And drop the bundle
-                self.drop_bundle(bundle, None)
+                // And drop the bundle, unless it is kept for retransmission
+                match self
+                    .retain_forwarded(&mut bundle)
                     .await
-                    .map_err(tonic::Status::from_error)
+                    .map_err(tonic::Status::from_error)?
+                {
+                    DispatchResult::Drop(reason) => self
+                        .drop_bundle(bundle, reason)
+                        .await
+                        .map_err(tonic::Status::from_error),
+                    _ => Ok(()),
+                }
             }
             _ => Err(tonic::Status::not_found("No such bundle")),
         }
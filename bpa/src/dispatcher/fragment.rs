@@ -1,22 +1,805 @@
 use super::*;
+use sha2::Digest;
+
+/* Fragments of bundles for local services are held as ReassemblyPending until the
+ * fragments received cover the whole payload of the original bundle.  The original is
+ * then rebuilt from the first fragment, with the payloads of the others spliced in, and
+ * ingested as if it had been received whole.  Fragments wholly covered by others,
+ * duplicates included, add nothing and are dropped as they are found.  Fragments still
+ * waiting when their lifetime passes are dropped by the reassembly task.
+ *
+ * If the bundle storage supports it, the payload of each fragment is instead written into
+ * a sparse file as it arrives, and the fragment dropped, keeping only the one that starts
+ * the payload, which the original is rebuilt from.  The storage records the ranges written,
+ * and once they cover the payload it is streamed back into the store.  Holes are punched
+ * in the file as it is read, so the disk never holds the partial payload and the
+ * reassembled bundle in full at once.  A crash part way through leaves a file that no
+ * longer covers the payload, and the fragment kept expires in time */
+
+// How much of a partial payload is read back, and released, at a time
+const PARTIAL_CHUNK_SIZE: u64 = 1024 * 1024;
+
+// Offset into the original payload, and length, of a fragment payload
+type Range = (u64, u64);
+
+#[derive(Debug, Default, PartialEq)]
+struct Plan {
+    // Fragment index, offset into the fragment payload, and length, in payload order
+    pieces: Vec<(usize, u64, u64)>,
+    // Fragments that add nothing, or claim to lie beyond the end of the payload
+    redundant: Vec<usize>,
+    complete: bool,
+}
+
+fn plan(total_len: u64, ranges: &[Range]) -> Plan {
+    // Longest first at the same offset, so shorter duplicates are found redundant
+    let mut order = (0..ranges.len()).collect::<Vec<_>>();
+    order.sort_by_key(|idx| (ranges[*idx].0, std::cmp::Reverse(ranges[*idx].1)));
+
+    let mut plan = Plan::default();
+    let mut gap = false;
+    let mut reach = 0u64;
+    for idx in order {
+        let (offset, len) = ranges[idx];
+        let end = offset.saturating_add(len);
+        if len == 0 || end <= reach || end > total_len {
+            plan.redundant.push(idx);
+            continue;
+        }
+
+        if offset > reach {
+            gap = true;
+        }
+
+        // Only the part not already covered
+        let start = offset.max(reach);
+        plan.pieces.push((idx, start - offset, end - start));
+        reach = end;
+    }
+    // Nothing to reassemble from fragments that carry no payload
+    plan.complete = !gap && reach == total_len && !plan.pieces.is_empty();
+    plan
+}
+
+// Lowercase hex, as the bundle storage expects, identifying the original bundle
+fn reassembly_id(original_id: &bpv7::BundleId) -> String {
+    let mut hasher = sha2::Sha256::new();
+    hasher.update(cbor::encode::emit(&original_id.source));
+    hasher.update(
+        original_id
+            .timestamp
+            .creation_time
+            .map_or(0, |t| t.millisecs())
+            .to_be_bytes(),
+    );
+    hasher.update(original_id.timestamp.sequence_number.to_be_bytes());
+    hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+// The length of the payload of a bundle, from the length of its payload block, without
+// loading the data.  Ingress rewrites non-canonical bundles, so the payload is a byte string
+// with the shortest head for its length
+fn payload_len(bundle: &bpv7::Bundle) -> Result<u64, Error> {
+    let block_len = bundle
+        .blocks
+        .get(&1)
+        .ok_or("Bundle has no payload block")?
+        .payload_len as u64;
+    [1, 2, 3, 5, 9]
+        .into_iter()
+        .find_map(|head_len| {
+            let len = block_len.checked_sub(head_len)?;
+            let canonical = match len {
+                0..=23 => 1,
+                24..=0xFF => 2,
+                0x100..=0xFFFF => 3,
+                0x1_0000..=0xFFFF_FFFF => 5,
+                _ => 9,
+            };
+            (canonical == head_len).then_some(len)
+        })
+        .ok_or_else(|| "Payload block is too short for its byte string".into())
+}
+
+// The payload of a bundle, unwrapped from the byte string of its payload block
+fn payload_data(bundle: &bpv7::Bundle, data: &[u8]) -> Result<Box<[u8]>, Error> {
+    Ok(bundle
+        .blocks
+        .get(&1)
+        .ok_or("Bundle has no payload block")?
+        .block_data(data)?)
+}
+
+// The range of the original payload each fragment covers, from the parsed fragments alone,
+// so no data is loaded until the payload is spliced together
+fn fragment_ranges(fragments: &[metadata::Bundle]) -> Result<Vec<Range>, Error> {
+    fragments
+        .iter()
+        .map(|fragment| {
+            let offset = fragment
+                .bundle
+                .id
+                .fragment_info
+                .as_ref()
+                .map_or(0, |i| i.offset);
+            Ok((offset, payload_len(&fragment.bundle)?))
+        })
+        .collect()
+}
+
+// Rebuild the original bundle from its first fragment and the reassembled payload
+fn unfragment(first: &bpv7::Bundle, first_data: &[u8], payload: Vec<u8>) -> Vec<u8> {
+    bpv7::Editor::new(first, first_data)
+        .unfragment()
+        .replace_extension_block(bpv7::BlockType::Payload)
+        .data(payload)
+        .build()
+        .build()
+}
+
+/* Locally originated bundles larger than the smallest MTU advertised by the CLAs on the
+ * route to their destination are fragmented at source, so nodes further along the path
+ * need not fragment them, which is harder once BPSec blocks are involved.  Every
+ * fragment carries all the extension blocks of the original */
+
+// The payload ranges of fragments of at most 'mtu' bytes, each carrying 'overhead' bytes
+// besides its part of the payload, or None if not even one byte of payload fits
+fn split(total_len: u64, overhead: u64, mtu: u64) -> Option<Vec<Range>> {
+    let max = mtu.checked_sub(overhead).filter(|max| *max > 0)?;
+    Some(
+        (0..total_len)
+            .step_by(max.min(usize::MAX as u64) as usize)
+            .map(|offset| (offset, max.min(total_len - offset)))
+            .collect(),
+    )
+}
 
 impl Dispatcher {
     #[instrument(skip(self))]
     pub(super) async fn reassemble(
         &self,
-        _bundle: &mut metadata::Bundle,
+        bundle: &mut metadata::Bundle,
+    ) -> Result<DispatchResult, Error> {
+        let Some(fragment_info) = bundle.bundle.id.fragment_info.clone() else {
+            return Ok(DispatchResult::Continue);
+        };
+
+        // Serialise reassembly, so two final fragments cannot both complete the bundle
+        let _guard = self.reassembly_lock.lock().await;
+
+        let original_id = bpv7::BundleId {
+            source: bundle.bundle.id.source.clone(),
+            timestamp: bundle.bundle.id.timestamp.clone(),
+            fragment_info: None,
+        };
+        if self.store.check_status(&original_id).await?.is_some() {
+            trace!("Fragment of a bundle that has already been reassembled");
+            return Ok(DispatchResult::Drop(None));
+        }
+
+        // A bundle with an empty payload cannot be fragmented
+        if fragment_info.total_len == 0 {
+            trace!("Fragment of a bundle with an empty payload");
+            return Ok(DispatchResult::Drop(Some(
+                bpv7::StatusReportReasonCode::BlockUnintelligible,
+            )));
+        }
+
+        if self
+            .config
+            .max_bundle_size
+            .is_some_and(|max| fragment_info.total_len > max as u64)
+        {
+            trace!(
+                "Reassembled bundle of {} bytes would exceed 'max_bundle_size'",
+                fragment_info.total_len
+            );
+            return Ok(DispatchResult::Drop(Some(
+                bpv7::StatusReportReasonCode::DepletedStorage,
+            )));
+        }
+
+        // Fragments that claim to lie beyond the end of the payload cannot be reassembled, and
+        // must not be written into the sparse file
+        if fragment_info
+            .offset
+            .checked_add(payload_len(&bundle.bundle)?)
+            .is_none_or(|end| end > fragment_info.total_len)
+        {
+            trace!("Fragment lies beyond the end of the payload");
+            return Ok(DispatchResult::Drop(Some(
+                bpv7::StatusReportReasonCode::BlockUnintelligible,
+            )));
+        }
+
+        // Keep the payload in the sparse file before waiting, so no waiting fragment is missing from it
+        let reassembly_id = reassembly_id(&original_id);
+        let sparse = self.store.supports_sparse_reassembly();
+        if sparse {
+            let Some(data) = self.load_data(bundle).await? else {
+                return Ok(DispatchResult::Drop(Some(
+                    bpv7::StatusReportReasonCode::DepletedStorage,
+                )));
+            };
+            let payload = bundle
+                .bundle
+                .blocks
+                .get(&1)
+                .ok_or("Fragment has no payload block")?
+                .block_data(data.as_ref().as_ref())?;
+            self.store
+                .write_partial(&reassembly_id, fragment_info.offset, &payload)
+                .await?;
+        }
+
+        // Wait for the other fragments
+        self.store
+            .set_status(bundle, metadata::BundleStatus::ReassemblyPending)
+            .await?;
+
+        let fragments = self
+            .load_fragments(&original_id, fragment_info.total_len)
+            .await?;
+        if sparse {
+            return self
+                .reassemble_sparse(&reassembly_id, fragments, fragment_info.total_len)
+                .await;
+        }
+
+        let ranges = fragment_ranges(&fragments)?;
+        let plan = plan(fragment_info.total_len, &ranges);
+
+        let mut fragments = fragments.into_iter().map(Some).collect::<Vec<_>>();
+        for idx in &plan.redundant {
+            if let Some(fragment) = fragments[*idx].take() {
+                trace!("Dropping redundant fragment {:?}", fragment.bundle.id);
+                self.drop_bundle(fragment, None).await?;
+            }
+        }
+
+        if !plan.complete {
+            trace!("Waiting for more fragments");
+            return Ok(DispatchResult::Done);
+        }
+
+        // The original is rebuilt from the first fragment
+        let first = fragments[plan.pieces[0].0].as_ref().unwrap();
+        let Some(first_data) = self.load_data(first).await? else {
+            // Wait for the fragment to be received again
+            return Ok(DispatchResult::Done);
+        };
+
+        let Some(payload) = self
+            .splice_payload(&fragments, &plan, fragment_info.total_len)
+            .await?
+        else {
+            // Wait for the fragment to be received again
+            return Ok(DispatchResult::Done);
+        };
+
+        let received_at = first.metadata.received_at;
+        let data = unfragment(&first.bundle, first_data.as_ref().as_ref(), payload);
+        self.ingress_reassembled(
+            fragments.into_iter().flatten().collect(),
+            received_at,
+            &data,
+            None,
+        )
+        .await
+    }
+
+    // Reassemble from the sparse file, which holds the payload of every fragment received
+    async fn reassemble_sparse(
+        &self,
+        reassembly_id: &str,
+        fragments: Vec<metadata::Bundle>,
+        total_len: u64,
     ) -> Result<DispatchResult, Error> {
-        /* Either wait for more fragments to arrive
-        self.store.set_status(&mut bundle, metadata::BundleStatus::ReassemblyPending).await?;
+        // Keep the fragment at the start of the payload, which the original is rebuilt from,
+        // or failing that any one fragment, so the sparse file expires along with it
+        let keep = fragments
+            .iter()
+            .position(|f| {
+                f.bundle
+                    .id
+                    .fragment_info
+                    .as_ref()
+                    .is_some_and(|i| i.offset == 0)
+            })
+            .unwrap_or(0);
+        let mut first = None;
+        for (idx, fragment) in fragments.into_iter().enumerate() {
+            if idx == keep {
+                first = Some(fragment);
+            } else {
+                trace!(
+                    "Dropping fragment {:?} held in the sparse file",
+                    fragment.bundle.id
+                );
+                self.drop_bundle(fragment, None).await?;
+            }
+        }
+
+        let Some(first) = first.filter(|f| {
+            f.bundle
+                .id
+                .fragment_info
+                .as_ref()
+                .is_some_and(|i| i.offset == 0)
+        }) else {
+            trace!("Waiting for more fragments");
+            return Ok(DispatchResult::Done);
+        };
+
+        // Only the ranges recorded as written are read, never a hole
+        let ranges = self.store.partial_ranges(reassembly_id).await?;
+        if !plan(total_len, &ranges).complete {
+            trace!("Waiting for more fragments");
+            return Ok(DispatchResult::Done);
+        }
+
+        let Some(first_data) = self.load_data(&first).await? else {
+            // Wait for the fragment to be received again
+            return Ok(DispatchResult::Done);
+        };
+        let (head, mut payload) = bpv7::Editor::new(&first.bundle, first_data.as_ref().as_ref())
+            .unfragment()
+            .build_streamed(total_len);
+        drop(first_data);
+
+        // Stream the bundle into the store, releasing the sparse file as it is read
+        let (tx, rx) = tokio::sync::mpsc::channel(16);
+        let read = async move {
+            if tx.send(head).await.is_err() {
+                return Ok(false);
+            }
+            let mut offset = 0;
+            while offset < total_len {
+                let len = PARTIAL_CHUNK_SIZE.min(total_len - offset);
+                let Some(data) = self.store.load_partial(reassembly_id, offset, len).await? else {
+                    return Ok(false);
+                };
+                let chunk = (*data).as_ref().to_vec();
+                payload.update(&chunk);
+                if tx.send(chunk).await.is_err() {
+                    return Ok(false);
+                }
+                self.store
+                    .release_partial(reassembly_id, offset, len)
+                    .await?;
+                offset += len;
+            }
+            Ok::<_, Error>(tx.send(payload.finish()).await.is_ok())
+        };
+        let stored = match tokio::join!(self.store.store_data_stream(rx), read) {
+            (Ok(stored), Ok(true)) => stored,
+            (stored, read) => {
+                if let Ok((storage_name, _)) = &stored {
+                    self.store.delete_data(storage_name).await?;
+                }
+                stored?;
+                read?;
+                warn!("Partial payload {reassembly_id} has gone from storage");
+                return Ok(DispatchResult::Done);
+            }
+        };
+
+        // Holes read back as zeros, so a released file must not be read again
+        self.store.remove_partial(reassembly_id).await?;
+
+        let Some(data) = self.store.load_data(&stored.0).await? else {
+            return Err(
+                format!("Reassembled bundle data {} has gone from storage", stored.0).into(),
+            );
+        };
+        let received_at = first.metadata.received_at;
+        self.ingress_reassembled(vec![first], received_at, (*data).as_ref(), Some(stored))
+            .await
+    }
+
+    // Ingest the reassembled bundle, and drop the fragments it was reassembled from.
+    // `stored` is where the data has been written already, if it has
+    async fn ingress_reassembled(
+        &self,
+        fragments: Vec<metadata::Bundle>,
+        received_at: Option<time::OffsetDateTime>,
+        data: &[u8],
+        stored: Option<(Arc<str>, Arc<[u8]>)>,
+    ) -> Result<DispatchResult, Error> {
+        let parsed = bpv7::ValidBundle::parse(data, |_, _| Ok(None));
+        let stored = match (&parsed, stored) {
+            (Ok(bpv7::ValidBundle::Valid(..)), stored) => stored,
+            (_, Some((storage_name, _))) => {
+                // The stored data is not what will be ingested
+                self.store.delete_data(&storage_name).await?;
+                None
+            }
+            (_, None) => None,
+        };
+
+        let (original, stored, report_unsupported) = match parsed? {
+            bpv7::ValidBundle::Valid(original, report_unsupported) => {
+                let stored = match stored {
+                    Some(stored) => stored,
+                    None => self.store.store_data(data).await?,
+                };
+                (original, stored, report_unsupported)
+            }
+            bpv7::ValidBundle::Rewritten(original, data, report_unsupported) => (
+                original,
+                self.store.store_data(&data).await?,
+                report_unsupported,
+            ),
+            bpv7::ValidBundle::Invalid(_, reason, e) => {
+                warn!("Reassembled bundle is invalid: {e}");
+                for fragment in fragments {
+                    self.drop_bundle(fragment, Some(reason)).await?;
+                }
+                return Ok(DispatchResult::Done);
+            }
+        };
+        trace!("Reassembled bundle {:?}", original.id);
+
+        let (storage_name, hash) = stored;
+        self.ingress_bundle(
+            metadata::Bundle {
+                metadata: metadata::Metadata {
+                    storage_name: Some(storage_name),
+                    hash: Some(hash),
+                    received_at,
+                    ..Default::default()
+                },
+                bundle: original,
+            },
+            None,
+            report_unsupported,
+        )
+        .await?;
+
+        // The fragments are no longer needed
+        for fragment in fragments {
+            self.drop_bundle(fragment, None).await?;
+        }
+        Ok(DispatchResult::Done)
+    }
+
+    // Splice the payloads of the fragments together, in the order of the plan.
+    // Returns None if the data of a fragment has gone
+    async fn splice_payload(
+        &self,
+        fragments: &[Option<metadata::Bundle>],
+        plan: &Plan,
+        total_len: u64,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        let mut payload = Vec::with_capacity(total_len as usize);
+        for (idx, offset, len) in &plan.pieces {
+            let fragment = fragments[*idx].as_ref().unwrap();
+            let Some(data) = self.load_data(fragment).await? else {
+                return Ok(None);
+            };
+            payload.extend_from_slice(
+                payload_data(&fragment.bundle, data.as_ref().as_ref())?
+                    .get(*offset as usize..(*offset + *len) as usize)
+                    .ok_or("Fragment payload is shorter than its payload block")?,
+            );
+        }
+        Ok(Some(payload))
+    }
+
+    async fn load_fragments(
+        &self,
+        original_id: &bpv7::BundleId,
+        total_len: u64,
+    ) -> Result<Vec<metadata::Bundle>, Error> {
+        let (tx, mut rx) = tokio::sync::mpsc::channel::<metadata::Bundle>(16);
+        let h = tokio::spawn(async move {
+            let mut fragments = Vec::new();
+            while let Some(fragment) = rx.recv().await {
+                // Fragments that disagree on the total length cannot be from the same bundle
+                if fragment
+                    .bundle
+                    .id
+                    .fragment_info
+                    .as_ref()
+                    .is_some_and(|i| i.total_len == total_len)
+                {
+                    fragments.push(fragment);
+                }
+            }
+            fragments
+        });
+
+        self.store.get_fragments(original_id, tx).await?;
+        Ok(h.await.trace_expect("Task terminated unexpectedly"))
+    }
+
+    async fn expire_fragments(&self) -> Result<(), Error> {
+        let (tx, mut rx) = tokio::sync::mpsc::channel::<metadata::Bundle>(16);
+        let h = tokio::spawn(async move {
+            let mut expired = Vec::new();
+            while let Some(fragment) = rx.recv().await {
+                if fragment.has_expired() {
+                    expired.push(fragment);
+                }
+            }
+            expired
+        });
+
+        self.store.get_reassembly_pending(tx).await?;
+
+        let _guard = self.reassembly_lock.lock().await;
+        for fragment in h.await.trace_expect("Task terminated unexpectedly") {
+            trace!("Fragment {:?} lifetime has expired", fragment.bundle.id);
+
+            // The fragments of a bundle expire together, so its sparse file can go too
+            if self.store.supports_sparse_reassembly() {
+                self.store
+                    .remove_partial(&reassembly_id(&bpv7::BundleId {
+                        source: fragment.bundle.id.source.clone(),
+                        timestamp: fragment.bundle.id.timestamp.clone(),
+                        fragment_info: None,
+                    }))
+                    .await?;
+            }
+            self.drop_bundle(
+                fragment,
+                Some(bpv7::StatusReportReasonCode::LifetimeExpired),
+            )
+            .await?;
+        }
+        Ok(())
+    }
+}
+
+impl Dispatcher {
+    // The smallest of the MTU of the route, the largest bundle any of its CLAs can send, and
+    // the largest bundle the destination accepts
+    async fn path_mtu(&self, destination: &bpv7::Eid) -> Option<u64> {
+        let action = match &self.fib {
+            Some(fib) => fib.find(destination).await.ok(),
+            None => None,
+        };
+        let mut mtus = Vec::new();
+        if let Some(action) = action {
+            mtus.extend(action.mtu());
+            for endpoint in &action.clas {
+                if let Some(cla) = self.cla_registry.find(endpoint.handle).await {
+                    mtus.extend(cla.capabilities().max_bundle_size);
+                }
+            }
+        }
+        mtus.extend(
+            self.peer_capabilities_of(destination)
+                .and_then(|peer| peer.max_bundle_size),
+        );
+        mtus.into_iter().min()
+    }
+
+    // Fragment a locally originated bundle to fit the path MTU to its destination
+    #[instrument(skip(self, data))]
+    pub(super) async fn fragment_at_source(
+        &self,
+        bundle: bpv7::Bundle,
+        data: Vec<u8>,
+    ) -> Result<Vec<(bpv7::Bundle, Vec<u8>)>, Error> {
+        let Some(mtu) = self
+            .path_mtu(&bundle.destination)
+            .await
+            .filter(|mtu| data.len() as u64 > *mtu)
+        else {
+            return Ok(vec![(bundle, data)]);
+        };
+
+        if bundle.flags.do_not_fragment {
+            trace!("Bundle is larger than the path MTU of {mtu} bytes, but must not be fragmented");
+            return Ok(vec![(bundle, data)]);
+        }
+
+        let Some(fragments) = fragment(&bundle, &data, mtu)? else {
+            trace!("Bundle cannot be fragmented to fit the path MTU of {mtu} bytes");
+            return Ok(vec![(bundle, data)]);
+        };
+
+        trace!(
+            "Fragmenting bundle into {} fragments to fit the path MTU of {mtu} bytes",
+            fragments.len()
+        );
+        Ok(fragments)
+    }
+}
+
+// Fragment a bundle into fragments of at most 'mtu' bytes, or None if it cannot be split
+fn fragment(
+    bundle: &bpv7::Bundle,
+    data: &[u8],
+    mtu: u64,
+) -> Result<Option<Vec<(bpv7::Bundle, Vec<u8>)>>, Error> {
+    let payload = payload_data(bundle, data)?;
+    let total_len = payload.len() as u64;
+
+    // Measure a fragment with no payload, allowing for the largest payload header
+    let overhead = bpv7::Editor::new(bundle, data)
+        .fragment(total_len, total_len)
+        .replace_extension_block(bpv7::BlockType::Payload)
+        .data(Vec::new())
+        .build()
+        .build()
+        .len() as u64
+        + 9;
+
+    let Some(ranges) = split(total_len, overhead, mtu).filter(|ranges| ranges.len() > 1) else {
+        return Ok(None);
+    };
+
+    let mut fragments = Vec::with_capacity(ranges.len());
+    for (offset, len) in ranges {
+        let fragment = bpv7::Editor::new(bundle, data)
+            .fragment(offset, total_len)
+            .replace_extension_block(bpv7::BlockType::Payload)
+            .data(payload[offset as usize..(offset + len) as usize].to_vec())
+            .build()
+            .build();
+
+        let bpv7::ValidBundle::Valid(fragment_bundle, _) =
+            bpv7::ValidBundle::parse(&fragment, |_, _| Ok(None))?
+        else {
+            return Err("Fragment generated by editor is invalid".into());
+        };
+        fragments.push((fragment_bundle, fragment));
+    }
+    Ok(Some(fragments))
+}
+
+pub(super) async fn reassembly_task(dispatcher: Arc<Dispatcher>) {
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(
+        dispatcher.config.wait_sample_interval.max(1),
+    ));
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {
+                if let Err(e) = dispatcher.expire_fragments().await {
+                    warn!("Failed to expire fragments: {e}");
+                }
+            },
+            _ = dispatcher.cancel_token.cancelled() => break
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test() {
+        // In order, no overlaps
+        let p = plan(30, &[(0, 10), (10, 10), (20, 10)]);
+        assert!(p.complete);
+        assert_eq!(p.pieces, vec![(0, 0, 10), (1, 0, 10), (2, 0, 10)]);
+        assert!(p.redundant.is_empty());
+
+        // A gap
+        let p = plan(30, &[(20, 10), (0, 10)]);
+        assert!(!p.complete);
+        assert!(p.redundant.is_empty());
+
+        // Duplicates and contained fragments are redundant
+        let p = plan(30, &[(0, 10), (0, 10), (0, 20), (5, 5), (20, 10)]);
+        assert!(p.complete);
+        assert_eq!(p.pieces, vec![(2, 0, 20), (4, 0, 10)]);
+        assert_eq!(p.redundant, vec![0, 1, 3]);
+
+        // Overlaps contribute only the uncovered part
+        let p = plan(30, &[(0, 15), (10, 20)]);
+        assert!(p.complete);
+        assert_eq!(p.pieces, vec![(0, 0, 15), (1, 5, 15)]);
+
+        // Fragments beyond the end of the payload are dropped
+        let p = plan(20, &[(0, 20), (10, 20)]);
+        assert!(p.complete);
+        assert_eq!(p.redundant, vec![1]);
+
+        // Empty fragments add nothing, and never complete an empty payload
+        let p = plan(10, &[(0, 0), (0, 10), (10, 0)]);
+        assert!(p.complete);
+        assert_eq!(p.redundant, vec![0, 2]);
+        let p = plan(0, &[(0, 0)]);
+        assert!(!p.complete);
+        assert!(p.pieces.is_empty());
+    }
+
+    #[test]
+    fn test_payload_len() {
+        for len in [0usize, 23, 24, 255, 256, 65535, 65536] {
+            let (bundle, data) = bpv7::Builder::new()
+                .source("ipn:1.1".parse().unwrap())
+                .destination("ipn:2.1".parse().unwrap())
+                .add_payload_block(vec![7u8; len])
+                .build();
+            assert_eq!(payload_len(&bundle).unwrap(), len as u64);
+            assert_eq!(payload_data(&bundle, &data).unwrap().len(), len);
+        }
+    }
+
+    #[test]
+    fn test_split() {
+        // Fits in one
+        assert_eq!(split(10, 20, 30), Some(vec![(0, 10)]));
+
+        // The last fragment takes what is left
+        assert_eq!(split(25, 20, 30), Some(vec![(0, 10), (10, 10), (20, 5)]));
+
+        // Nothing to split
+        assert_eq!(split(0, 20, 30), Some(vec![]));
+
+        // No room for any payload
+        assert_eq!(split(10, 30, 30), None);
+        assert_eq!(split(10, 40, 30), None);
+    }
+
+    #[test]
+    fn test_reassemble() {
+        let original_payload = (0..1000u32).map(|n| n as u8).collect::<Vec<_>>();
+        let (bundle, data) = bpv7::Builder::new()
+            .source("ipn:1.1".parse().unwrap())
+            .destination("ipn:2.1".parse().unwrap())
+            .add_payload_block(original_payload.clone())
+            .build();
+
+        let fragments = fragment(&bundle, &data, 300).unwrap().unwrap();
+        assert!(fragments.len() > 1);
 
-        Or
+        // The fragments exactly cover the payload, in any order
+        let mut ranges = fragments
+            .iter()
+            .map(|(f, d)| {
+                (
+                    f.id.fragment_info.as_ref().unwrap().offset,
+                    payload_data(f, d).unwrap().len() as u64,
+                )
+            })
+            .collect::<Vec<_>>();
+        ranges.reverse();
+        let p = plan(original_payload.len() as u64, &ranges);
 
-        // TODO: We need to handle the case when the reassembled fragment is larger than our total RAM!
-        Reassemble and self.enqueue_bundle()
+        // The lengths are known without loading the data
+        let fragment_bundles = fragments
+            .iter()
+            .rev()
+            .map(|(f, _)| metadata::Bundle {
+                metadata: Default::default(),
+                bundle: f.clone(),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(fragment_ranges(&fragment_bundles).unwrap(), ranges);
+        assert!(p.complete);
+        assert!(p.redundant.is_empty());
 
-        */
+        let mut payload = Vec::new();
+        for (idx, offset, len) in &p.pieces {
+            let (f, d) = &fragments[fragments.len() - 1 - idx];
+            payload.extend_from_slice(
+                &payload_data(f, d).unwrap()[*offset as usize..(*offset + *len) as usize],
+            );
+        }
 
-        warn!("Bundle requires fragment reassembly");
-        todo!()
+        let (first, first_data) = &fragments[0];
+        let data = unfragment(first, first_data, payload);
+        let bpv7::ValidBundle::Valid(reassembled, _) =
+            bpv7::ValidBundle::parse(&data, |_, _| Ok(None)).unwrap()
+        else {
+            panic!("Reassembled bundle is invalid");
+        };
+        assert!(reassembled.id.fragment_info.is_none());
+        assert_eq!(
+            payload_data(&reassembled, &data).unwrap().as_ref(),
+            original_payload.as_slice()
+        );
     }
 }
@@ -1,69 +1,286 @@
 use super::*;
 
+// Why a bundle received from a CLA was not accepted
+#[derive(Debug, Clone)]
+pub enum Rejection {
+    TooBig(usize),
+    Unintelligible(String),
+    PolicyDenied(String),
+    Duplicate,
+    StorageFull(String),
+}
+
+impl std::fmt::Display for Rejection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Rejection::TooBig(len) => write!(f, "Bundle of {len} bytes is too big"),
+            Rejection::Unintelligible(e) => write!(f, "Unintelligible bundle: {e}"),
+            Rejection::PolicyDenied(e) => write!(f, "Bundle denied by policy: {e}"),
+            Rejection::Duplicate => write!(f, "Duplicate bundle"),
+            Rejection::StorageFull(e) => write!(f, "Storage full: {e}"),
+        }
+    }
+}
+
 impl Dispatcher {
-    #[instrument(skip(self, data))]
-    pub async fn receive_bundle(&self, data: Bytes) -> Result<(), Error> {
+    #[instrument(skip(self, data), fields(
+        parse = tracing::field::Empty,
+        store = tracing::field::Empty,
+        ingress = tracing::field::Empty
+    ))]
+    pub async fn receive_bundle(&self, data: Bytes) -> Result<Option<Rejection>, Error> {
         // Capture received_at as soon as possible
-        let received_at = Some(time::OffsetDateTime::now_utc());
+        let received_at = Some(clock::now());
+        let timer = StageTimer::new("ingress", self.config.slow_bundle_threshold);
 
         // Do a fast pre-check
         if data.is_empty() {
-            return Err(cbor::decode::Error::NotEnoughData.into());
-        } else if data[0] == 0x06 {
+            return Ok(Some(Rejection::Unintelligible(
+                cbor::decode::Error::NotEnoughData.to_string(),
+            )));
+        }
+
+        #[cfg(feature = "bpv6")]
+        let data = if data[0] == hardy_bpv6::VERSION {
+            match Self::translate_bpv6(&data) {
+                Ok(data) => data,
+                Err(rejection) => return Ok(Some(rejection)),
+            }
+        } else {
+            data
+        };
+
+        if data[0] == 0x06 {
             trace!("Data looks like a BPv6 bundle");
-            return Err(cbor::decode::Error::IncorrectType(
-                "BPv7 bundle".to_string(),
+            return Ok(Some(Rejection::Unintelligible(
                 "Possible BPv6 bundle".to_string(),
-            )
-            .into());
+            )));
+        } else if let Some(rejection) = self.check_size(data.len()) {
+            return Ok(Some(rejection));
         }
 
+        self.receive_data(&data, None, received_at, timer).await
+    }
+
+    // Receive a bundle whose data has already been streamed into the bundle storage.  The
+    // stored data is removed again unless the bundle is accepted exactly as received.  Only the
+    // write is streamed: the bundle is parsed from the whole stored data, as its blocks are
+    // checked and it may be rewritten, so the engine should map large bundles rather than read
+    // them into memory
+    #[instrument(skip(self, hash), fields(
+        parse = tracing::field::Empty,
+        store = tracing::field::Empty,
+        ingress = tracing::field::Empty
+    ))]
+    pub async fn receive_stored_bundle(
+        &self,
+        storage_name: Arc<str>,
+        hash: Arc<[u8]>,
+    ) -> Result<Option<Rejection>, Error> {
+        let received_at = Some(clock::now());
+        let timer = StageTimer::new("ingress", self.config.slow_bundle_threshold);
+
+        let Some(data) = self.store.load_data(&storage_name).await? else {
+            return Err(
+                format!("Streamed bundle data {storage_name} has gone from storage").into(),
+            );
+        };
+
+        #[cfg(feature = "bpv6")]
+        if (*data).as_ref().first() == Some(&hardy_bpv6::VERSION) {
+            // The translated bundle is stored in place of the streamed data
+            self.discard_stream(&storage_name).await?;
+            return match Self::translate_bpv6((*data).as_ref()) {
+                Ok(data) => self.receive_data(&data, None, received_at, timer).await,
+                Err(rejection) => Ok(Some(rejection)),
+            };
+        }
+
+        self.receive_data(
+            (*data).as_ref(),
+            Some((storage_name, hash)),
+            received_at,
+            timer,
+        )
+        .await
+    }
+
+    // Translate an RFC 5050 bundle to BPv7 as it enters the node
+    #[cfg(feature = "bpv6")]
+    fn translate_bpv6(data: &[u8]) -> Result<Bytes, Rejection> {
+        trace!("Translating BPv6 bundle");
+        match hardy_bpv6::to_bpv7(data) {
+            Ok(data) => {
+                metrics::counter!("bpv6_bundles_translated_total", "direction" => "ingress")
+                    .increment(1);
+                Ok(data.into())
+            }
+            Err(e) => {
+                trace!("Untranslatable BPv6 bundle received: {e}");
+                Err(Rejection::Unintelligible(format!("BPv6 bundle: {e}")))
+            }
+        }
+    }
+
+    async fn receive_data(
+        &self,
+        data: &[u8],
+        stored: Option<(Arc<str>, Arc<[u8]>)>,
+        received_at: Option<time::OffsetDateTime>,
+        mut timer: StageTimer,
+    ) -> Result<Option<Rejection>, Error> {
+        // Account for the scratch space used while parsing
+        let Some(reservation) =
+            utils::memory::try_reserve(utils::memory::Subsystem::Parse, data.len())
+        else {
+            self.discard_stored(stored).await?;
+            return Ok(Some(Rejection::StorageFull(
+                "Parse memory limit reached".to_string(),
+            )));
+        };
+
         // Parse the bundle
-        match bpv7::ValidBundle::parse(&data, |_, _| Ok(None))? {
+        let (bundle, crc_report) = match bpv7::ValidBundle::parse_with_diagnostics(
+            data,
+            |source, context| Ok(self.integrity_keys.key(source, context)),
+            self.config.bib_failure,
+        ) {
+            Ok(r) => r,
+            Err(e) => {
+                trace!("Unintelligible bundle received: {e}");
+                self.discard_stored(stored).await?;
+                return Ok(Some(Rejection::Unintelligible(e.to_string())));
+            }
+        };
+        timer.stage("parse");
+        drop(reservation);
+
+        // Refuse bundles from denied sources before storing anything
+        let bundle_id = match &bundle {
+            bpv7::ValidBundle::Valid(bundle, _)
+            | bpv7::ValidBundle::Rewritten(bundle, _, _)
+            | bpv7::ValidBundle::Invalid(bundle, _, _) => &bundle.id,
+        };
+        if let Some(rejection) = self.check_source(&bundle_id.source) {
+            self.discard_stored(stored).await?;
+            return Ok(Some(rejection));
+        }
+
+        // Keep score of the peer the bundle came from, and police it if need be
+        let (previous_node, invalid) = match &bundle {
+            bpv7::ValidBundle::Valid(bundle, _) | bpv7::ValidBundle::Rewritten(bundle, _, _) => {
+                (bundle.previous_node.clone(), false)
+            }
+            bpv7::ValidBundle::Invalid(bundle, _, _) => (bundle.previous_node.clone(), true),
+        };
+        Self::note_crc_report(previous_node.as_ref(), &crc_report);
+        if let Some(rejection) = self.police_peer(previous_node.as_ref(), invalid) {
+            self.discard_stored(stored).await?;
+            return Ok(Some(rejection));
+        }
+
+        // Learn what we can of the peers the bundle has come from
+        match &bundle {
+            bpv7::ValidBundle::Valid(bundle, _) => self.observe_peers(bundle, data),
+            bpv7::ValidBundle::Rewritten(bundle, rewritten, _) => {
+                self.observe_peers(bundle, rewritten)
+            }
+            bpv7::ValidBundle::Invalid(..) => {}
+        }
+
+        // Spare the metadata storage the copies of bundles received moments ago
+        if self.is_recent_duplicate(bundle_id) {
+            self.note_peer_event(previous_node.as_ref(), reputation::Event::Duplicate);
+            self.discard_stored(stored).await?;
+            return Ok(Some(Rejection::Duplicate));
+        }
+
+        // Make room in the store, or refuse the bundle if we cannot.  Nothing is evicted for
+        // a bundle that is stored already
+        let _reservation = match &bundle {
+            bpv7::ValidBundle::Valid(bundle, _) | bpv7::ValidBundle::Rewritten(bundle, _, _) => {
+                if self.store.occupancy().is_some()
+                    && self.store.check_status(bundle_id).await?.is_some()
+                {
+                    self.note_peer_event(previous_node.as_ref(), reputation::Event::Duplicate);
+                    self.discard_stored(stored).await?;
+                    return Ok(Some(Rejection::Duplicate));
+                }
+                match self.make_room(bundle, received_at).await? {
+                    Ok(reservation) => Some(reservation),
+                    Err(rejection) => {
+                        self.discard_stored(stored).await?;
+                        return Ok(Some(rejection));
+                    }
+                }
+            }
+            bpv7::ValidBundle::Invalid(..) => None,
+        };
+
+        let r = match bundle {
             bpv7::ValidBundle::Valid(bundle, report_unsupported) => {
-                // Write the bundle data to the store
-                let (storage_name, hash) = self.store.store_data(&data).await?;
-                self.ingress_bundle(
-                    metadata::Bundle {
-                        metadata: metadata::Metadata {
-                            storage_name: Some(storage_name),
-                            hash: Some(hash),
-                            received_at,
-                            ..Default::default()
-                        },
-                        bundle,
+                // Write the bundle data to the store, unless it is there already
+                let (storage_name, hash) = match stored {
+                    Some(stored) => stored,
+                    None => self.store.store_data(data).await?,
+                };
+                timer.stage("store");
+                let bundle = metadata::Bundle {
+                    metadata: metadata::Metadata {
+                        storage_name: Some(storage_name),
+                        hash: Some(hash),
+                        received_at,
+                        ..Default::default()
                     },
-                    None,
-                    report_unsupported,
-                )
+                    bundle,
+                };
+                self.note_expired_arrival(&bundle);
+                self.ingress_bundle(bundle, None, report_unsupported).await
             }
-            bpv7::ValidBundle::Rewritten(bundle, data, report_unsupported) => {
+            bpv7::ValidBundle::Rewritten(bundle, rewritten, report_unsupported) => {
+                self.discard_stored(stored).await?;
+                let diagnosed = self
+                    .config
+                    .rewrite_diagnostic_interval
+                    .is_some()
+                    .then(|| bundle.clone());
+
                 // Write the bundle data to the store
-                let (storage_name, hash) = self.store.store_data(&data).await?;
-                self.ingress_bundle(
-                    metadata::Bundle {
-                        metadata: metadata::Metadata {
-                            storage_name: Some(storage_name),
-                            hash: Some(hash),
-                            received_at,
-                            ..Default::default()
-                        },
-                        bundle,
+                let (storage_name, hash) = self.store.store_data(&rewritten).await?;
+                timer.stage("store");
+                let bundle = metadata::Bundle {
+                    metadata: metadata::Metadata {
+                        storage_name: Some(storage_name),
+                        hash: Some(hash),
+                        received_at,
+                        ..Default::default()
                     },
-                    None,
-                    report_unsupported,
-                )
+                    bundle,
+                };
+                self.note_expired_arrival(&bundle);
+                let r = self.ingress_bundle(bundle, None, report_unsupported).await;
+
+                // Let the previous node know it sent us a non-canonical bundle, which is no
+                // reason to refuse the bundle itself.  Duplicates are not worth telling about
+                if let (Ok(true), Some(bundle)) = (&r, diagnosed) {
+                    if let Err(e) = self
+                        .report_rewritten(&bundle, data, &rewritten, &crc_report)
+                        .await
+                    {
+                        warn!("Failed to report rewritten bundle {:?}: {e}", bundle.id);
+                    }
+                }
+                r
             }
             bpv7::ValidBundle::Invalid(bundle, reason, e) => {
                 trace!("Invalid bundle received: {e}");
+                self.discard_stored(stored).await?;
 
                 // Don't bother saving the bundle data, it's garbage
                 self.ingress_bundle(
                     metadata::Bundle {
                         metadata: metadata::Metadata {
-                            status: metadata::BundleStatus::Tombstone(
-                                time::OffsetDateTime::now_utc(),
-                            ),
+                            status: metadata::BundleStatus::Tombstone(clock::now()),
                             received_at,
                             ..Default::default()
                         },
@@ -72,18 +289,95 @@ impl Dispatcher {
                     Some(reason),
                     false,
                 )
+                .await
+            }
+        };
+
+        timer.stage("ingress");
+        r.map(|accepted| {
+            if !accepted {
+                self.note_peer_event(previous_node.as_ref(), reputation::Event::Duplicate);
+            }
+            (!accepted).then_some(Rejection::Duplicate)
+        })
+    }
+
+    // Count the CRCs of received blocks per previous node, to spot flaky links and broken peers
+    fn note_crc_report(previous_node: Option<&bpv7::Eid>, report: &bpv7::CrcReport) {
+        trace!(
+            "Block CRCs: present {:?}, absent {:?}, corrected {:?}, incorrect {}",
+            report.present,
+            report.absent,
+            report.corrected,
+            report.incorrect
+        );
+
+        let peer = previous_node.map_or_else(|| "unknown".to_string(), |eid| eid.to_string());
+        let count = |result: &'static str, n: usize| {
+            if n != 0 {
+                metrics::counter!(
+                    "bundle_block_crcs_total",
+                    "previous_node" => peer.clone(),
+                    "result" => result
+                )
+                .increment(n as u64);
             }
+        };
+        let crc16 = report
+            .present
+            .iter()
+            .filter(|(_, crc_type)| *crc_type == bpv7::CrcType::CRC16_X25)
+            .count();
+        count("crc16", crc16);
+        count("crc32", report.present.len() - crc16);
+        count("absent", report.absent.len());
+        count("corrected", report.corrected.len());
+        count("incorrect", report.incorrect as usize);
+    }
+
+    fn note_expired_arrival(&self, bundle: &metadata::Bundle) {
+        if bundle.has_expired() {
+            self.note_peer_event(
+                bundle.bundle.previous_node.as_ref(),
+                reputation::Event::Expired,
+            );
         }
-        .await
     }
 
-    #[instrument(skip(self))]
+    async fn discard_stored(&self, stored: Option<(Arc<str>, Arc<[u8]>)>) -> Result<(), Error> {
+        match stored {
+            Some((storage_name, _)) => self.discard_stream(&storage_name).await,
+            None => Ok(()),
+        }
+    }
+
+    pub fn should_stream(&self, len: usize) -> bool {
+        self.store.should_stream(len)
+    }
+
+    // Write bundle data to the store as it arrives, for receive_stored_bundle() to process
+    pub async fn store_stream(
+        &self,
+        rx: tokio::sync::mpsc::Receiver<Vec<u8>>,
+    ) -> Result<(Arc<str>, Arc<[u8]>), Error> {
+        self.store.store_data_stream(rx).await
+    }
+
+    // Remove streamed data that will not be passed to receive_stored_bundle()
+    pub async fn discard_stream(&self, storage_name: &str) -> Result<(), Error> {
+        self.store.delete_data(storage_name).await
+    }
+
+    // Returns false if the bundle is a duplicate of one already stored
+    #[instrument(skip(self), fields(metadata = tracing::field::Empty))]
     pub async fn ingress_bundle(
         &self,
-        bundle: metadata::Bundle,
+        mut bundle: metadata::Bundle,
         reason: Option<bpv7::StatusReportReasonCode>,
         report_unsupported: bool,
-    ) -> Result<(), Error> {
+    ) -> Result<bool, Error> {
+        bundle.metadata.priority = self.store.classify(&bundle.bundle);
+
         // Report we have received the bundle
         let mut r = self
             .report_bundle_reception(
@@ -106,21 +400,30 @@ impl Dispatcher {
          */
 
         if r.is_ok() {
-            r = match self
+            let mut timer = StageTimer::new("metadata store", None);
+            let stored = self
                 .store
                 .store_metadata(&bundle.metadata, &bundle.bundle)
-                .await
-            {
-                Ok(true) => Ok(()),
+                .await;
+            timer.stage("metadata");
+
+            r = match stored {
+                Ok(true) => {
+                    self.note_received(&bundle.bundle.id);
+                    Ok(())
+                }
                 Ok(false) => {
                     // Bundle with matching id already exists in the metadata store
                     trace!("Bundle with matching id already exists in the metadata store");
+                    metrics::counter!("bundles_duplicate_total", "detected_by" => "metadata")
+                        .increment(1);
+                    self.note_received(&bundle.bundle.id);
 
                     // Drop the stored data if it was valid, and do not process further
                     if let Some(storage_name) = &bundle.metadata.storage_name {
                         self.store.delete_data(storage_name).await?;
                     }
-                    return Ok(());
+                    return Ok(false);
                 }
                 Err(e) => Err(e),
             };
@@ -138,7 +441,95 @@ impl Dispatcher {
                 self.store.delete_data(storage_name).await?;
             }
         }
-        r
+        r.map(|_| true)
+    }
+
+    pub fn check_size(&self, len: usize) -> Option<Rejection> {
+        if self.config.max_bundle_size.is_some_and(|max| len > max) {
+            trace!("Bundle of {len} bytes exceeds 'max_bundle_size'");
+            Some(Rejection::TooBig(len))
+        } else {
+            None
+        }
+    }
+
+    // Evict stored bundles to make room for `bundle`, as the storage quota requires
+    pub(super) async fn make_room(
+        &self,
+        bundle: &bpv7::Bundle,
+        received_at: Option<time::OffsetDateTime>,
+    ) -> Result<Result<store::Reservation, Rejection>, Error> {
+        let (evict, reservation) = match self.store.make_room(bundle, received_at) {
+            Ok(room) => room,
+            Err(refusal) => {
+                trace!("Bundle refused: {refusal}");
+                metrics::counter!("store_quota_refusals_total").increment(1);
+                return Ok(Err(Rejection::StorageFull(refusal.to_string())));
+            }
+        };
+
+        for bundle_id in evict {
+            // The bundle may have been dropped since it was chosen
+            let Some(bundle) = self.store.load(&bundle_id).await? else {
+                continue;
+            };
+            if let metadata::BundleStatus::Tombstone(_) = bundle.metadata.status {
+                continue;
+            }
+
+            trace!("Evicting bundle to make room in the store");
+            metrics::counter!(
+                "store_quota_evictions_total",
+                "priority" => utils::labels::priority_label(bundle.metadata.priority)
+            )
+            .increment(1);
+            self.drop_bundle(bundle, Some(bpv7::StatusReportReasonCode::DepletedStorage))
+                .await?;
+        }
+        Ok(Ok(reservation))
+    }
+
+    fn check_source(&self, source: &bpv7::Eid) -> Option<Rejection> {
+        if !self.config.deny_sources.find(source).is_empty() {
+            trace!("Bundle source {source} is denied by 'deny_sources'");
+            Some(Rejection::PolicyDenied(format!(
+                "Source {source} is denied"
+            )))
+        } else {
+            None
+        }
+    }
+
+    // Check what can be checked from the primary block alone, so that a CLA streaming a
+    // bundle can abandon the transfer before the rest of it has been received
+    #[instrument(skip(self))]
+    pub fn check_primary_block(&self, bundle: &bpv7::Bundle) -> Option<Rejection> {
+        if let Some(rejection) = self.check_source(&bundle.id.source) {
+            return Some(rejection);
+        }
+
+        if let bpv7::Eid::Null = bundle.destination {
+            trace!("Bundle is destined for the null endpoint");
+            return Some(Rejection::PolicyDenied(
+                "Bundle is destined for the null endpoint".to_string(),
+            ));
+        }
+
+        // Without a creation time, the expiry depends on the Bundle Age block still to come
+        if bundle.id.timestamp.creation_time.is_some()
+            && (metadata::Bundle {
+                bundle: bundle.clone(),
+                metadata: Default::default(),
+            })
+            .has_expired()
+        {
+            trace!("Bundle lifetime has expired");
+            return Some(Rejection::PolicyDenied(
+                "Bundle lifetime has expired".to_string(),
+            ));
+        }
+
+        None
     }
 
     #[instrument(skip(self))]
@@ -0,0 +1,49 @@
+use super::*;
+use std::collections::HashMap;
+
+/* Block Integrity Blocks on received bundles are verified against BIB-HMAC-SHA2 (RFC 9173)
+ * keys supplied by a key provider.  BIBs from security sources the provider has no key for
+ * are accepted unverified.  A block that fails verification either gets the bundle dropped,
+ * or is stripped from the bundle, as set by 'bib_failure' */
+
+pub trait KeyProvider: Send + Sync {
+    fn key(
+        &self,
+        source: &bpv7::Eid,
+        context: bpv7::bpsec::Context,
+    ) -> Option<bpv7::bpsec::KeyMaterial>;
+}
+
+// Symmetric keys from the 'bib_keys' configuration table
+pub struct ConfigKeys {
+    keys: HashMap<bpv7::Eid, Box<[u8]>>,
+}
+
+impl ConfigKeys {
+    pub fn new(config: &::config::Config) -> Self {
+        let keys = delivery::load_keys(config, "bib_keys");
+        if !keys.is_empty() {
+            info!(
+                "Verifying bundle integrity blocks from {} security sources",
+                keys.len()
+            );
+        }
+        Self { keys }
+    }
+}
+
+impl KeyProvider for ConfigKeys {
+    fn key(
+        &self,
+        source: &bpv7::Eid,
+        context: bpv7::bpsec::Context,
+    ) -> Option<bpv7::bpsec::KeyMaterial> {
+        match context {
+            bpv7::bpsec::Context::BIB_HMAC_SHA2 => self
+                .keys
+                .get(source)
+                .map(|key| bpv7::bpsec::KeyMaterial::SymmetricKey(key.clone())),
+            _ => None,
+        }
+    }
+}
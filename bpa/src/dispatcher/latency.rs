@@ -0,0 +1,53 @@
+use super::*;
+
+// Block type code from the private/experimental range
+const LATENCY_BLOCK_TYPE: u64 = 192;
+
+impl Dispatcher {
+    pub(super) fn add_latency_block(&self, builder: bpv7::Builder) -> bpv7::Builder {
+        if !self.config.latency_block {
+            return builder;
+        }
+
+        // Leave the block flags clear, so nodes that do not understand the block pass it on
+        builder
+            .add_extension_block(bpv7::BlockType::Unrecognised(LATENCY_BLOCK_TYPE))
+            .data(cbor::encode::emit(bpv7::DtnTime::now()))
+            .build()
+    }
+
+    pub(super) fn measure_latency(
+        &self,
+        bundle: &metadata::Bundle,
+        data: &[u8],
+    ) -> Option<time::Duration> {
+        let received_at = bundle.metadata.received_at?;
+        let block =
+            bundle.bundle.blocks.values().find(|block| {
+                block.block_type == bpv7::BlockType::Unrecognised(LATENCY_BLOCK_TYPE)
+            })?;
+
+        let sent_at: time::OffsetDateTime =
+            cbor::decode::parse_value(block.payload(data), |value, _, _| match value {
+                cbor::decode::Value::Bytes(data) => cbor::decode::parse::<bpv7::DtnTime>(data),
+                value => Err(cbor::decode::Error::IncorrectType(
+                    "Byte String".to_string(),
+                    value.type_name(false),
+                )),
+            })
+            .inspect_err(|e| trace!("Invalid latency block: {e}"))
+            .ok()?
+            .0
+            .into();
+
+        // A negative latency means the clocks are not synchronized
+        let latency = received_at - sent_at;
+        if latency.is_negative() {
+            trace!("Ignoring negative latency {latency}, clocks are not synchronized");
+            return None;
+        }
+
+        metrics::histogram!("bundle_latency_seconds").record(latency.as_seconds_f64());
+        Some(latency)
+    }
+}
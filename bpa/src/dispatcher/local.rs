@@ -7,11 +7,28 @@ pub struct SendRequest {
     pub data: Bytes,
     pub lifetime: Option<u64>,
     pub flags: Option<bpv7::BundleFlags>,
+    // Publish the outcomes reported of the bundle to watch_outcomes()
+    pub track: bool,
 }
 
 impl Dispatcher {
     #[instrument(skip(self))]
-    pub async fn local_dispatch(&self, mut request: SendRequest) -> Result<(), Error> {
+    pub async fn local_dispatch(&self, mut request: SendRequest) -> Result<bpv7::BundleId, Error> {
+        // Bundles kept for retransmission need to hear of their delivery
+        if self.is_retransmit_source(&request.source) {
+            request
+                .flags
+                .get_or_insert_with(Default::default)
+                .delivery_report_requested = true;
+        }
+
+        // Tracked bundles need to hear of their delivery or deletion
+        if request.track {
+            let flags = request.flags.get_or_insert_with(Default::default);
+            flags.delivery_report_requested = true;
+            flags.delete_report_requested = true;
+        }
+
         // Check to see if we should use ipn 2-element encoding
         if let bpv7::Eid::Ipn {
             allocator_id: da,
@@ -19,12 +36,15 @@ impl Dispatcher {
             service_number: ds,
         } = &request.destination
         {
-            // Check configured entries
+            // Check configured entries, and what the destination is known to understand
             if !self
                 .config
                 .ipn_2_element
                 .find(&request.destination)
                 .is_empty()
+                || self
+                    .peer_capabilities_of(&request.destination)
+                    .is_some_and(|peer| peer.ipn_3_element == Some(false))
             {
                 if let bpv7::Eid::Ipn {
                     allocator_id: sa,
@@ -63,6 +83,9 @@ impl Dispatcher {
             b = b.lifetime(lifetime);
         }
 
+        // Add a latency measurement block if configured
+        b = self.add_latency_block(b);
+
         // Build the bundle
         let (bundle, data) = b
             .source(request.source)
@@ -70,15 +93,42 @@ impl Dispatcher {
             .add_payload_block(request.data.into())
             .build();
 
-        // Store to store
-        let metadata = self
-            .store
-            .store(&bundle, &data, metadata::BundleStatus::default(), None)
-            .await?
-            .trace_expect("Duplicate bundle generated by builder!");
+        utils::labels::count(
+            utils::labels::Counter::Sent,
+            self.app_registry.metrics_label(&bundle.id.source).await,
+        );
 
-        // And get it dispatched
-        self.dispatch_bundle(metadata::Bundle { metadata, bundle })
-            .await
+        // Fragments are reported against the id of the whole bundle
+        let bundle_id = bundle.id.clone();
+        if request.track {
+            self.track_outcome(&bundle);
+        }
+
+        // Fragment now, rather than leave it to a node further along the path
+        for (bundle, data) in self.fragment_at_source(bundle, data).await? {
+            // Make room in the store, or give up if we cannot
+            let _reservation = self
+                .make_room(&bundle, None)
+                .await?
+                .map_err(|rejection| rejection.to_string())?;
+
+            // Store to store
+            let metadata = self
+                .store
+                .store(
+                    &bundle,
+                    &data,
+                    metadata::BundleStatus::default(),
+                    None,
+                    None,
+                )
+                .await?
+                .trace_expect("Duplicate bundle generated by builder!");
+
+            // And get it dispatched
+            self.dispatch_bundle(metadata::Bundle { metadata, bundle })
+                .await?;
+        }
+        Ok(bundle_id)
     }
 }
@@ -1,29 +1,84 @@
+mod acknowledge;
 mod admin;
+mod bibe;
 mod collect;
+mod collection_cache;
 mod config;
+mod congestion;
+mod dedup;
+mod delivery;
 mod dispatch;
+mod echo;
+mod egress;
 mod forward;
 mod fragment;
 mod ingress;
+mod integrity;
+mod latency;
 mod local;
+mod peer_cache;
+mod prophet;
+mod push;
+mod receipts;
+mod recorder;
 mod report;
+mod reputation;
+mod retransmit;
+mod sequence;
+mod shaping;
+mod status_watch;
+mod telemetry;
+mod timing;
+mod wake;
 
 use super::*;
+pub use collect::{CollectResponse, PayloadRange};
 use dispatch::DispatchResult;
 use hardy_cbor as cbor;
+pub use ingress::Rejection;
 pub use local::SendRequest;
+pub use peer_cache::PeerCapabilities;
+pub use reputation::Trust;
+pub use status_watch::StatusReportEvent;
 use std::sync::Arc;
+use timing::StageTimer;
 use tokio_util::bytes::Bytes;
-use utils::cancel::cancellable_sleep;
+use utils::clock;
 
 pub struct Dispatcher {
     config: self::config::Config,
     cancel_token: tokio_util::sync::CancellationToken,
     store: Arc<store::Store>,
-    tx: tokio::sync::mpsc::Sender<metadata::Bundle>,
+    // Each bundle is queued with the span it was dispatched from, to link its processing to
+    tx: tokio::sync::mpsc::Sender<(metadata::Bundle, tracing::Span)>,
     cla_registry: cla_registry::ClaRegistry,
     app_registry: app_registry::AppRegistry,
     fib: Option<fib::Fib>,
+    resolvers: resolver::Resolvers,
+    dedup: Option<dedup::Dedup>,
+    reputation: Option<reputation::Reputation>,
+    peer_cache: Option<peer_cache::PeerCache>,
+    calendar: Option<shaping::Calendar>,
+    collection_cache: Option<collection_cache::CollectionCache>,
+    subscriptions: push::Subscriptions,
+    pings: echo::Pings,
+    status_watch: status_watch::StatusWatch,
+    receipts: receipts::Receipts,
+    prophet: Option<prophet::Prophet>,
+    telemetry: Option<telemetry::Telemetry>,
+    wake: Option<wake::Wake>,
+    recorder: Option<recorder::Recorder>,
+    retransmissions: retransmit::Retransmissions,
+    delivery_ids: acknowledge::DeliveryIds,
+    delivery_transforms: delivery::Registry,
+    integrity_keys: Box<dyn integrity::KeyProvider>,
+    sequencer: sequence::Sequencer,
+    reassembly_lock: tokio::sync::Mutex<()>,
+    // The status reports we have generated and not yet forwarded or dropped
+    reports_in_flight: std::sync::Mutex<std::collections::HashSet<bpv7::BundleId>>,
+    rewrite_diagnostics_sent:
+        tokio::sync::Mutex<std::collections::HashMap<bpv7::Eid, time::OffsetDateTime>>,
+    congested_clas: std::sync::Mutex<std::collections::HashMap<u32, time::OffsetDateTime>>,
 }
 
 impl Dispatcher {
@@ -38,22 +93,101 @@ impl Dispatcher {
         task_set: &mut tokio::task::JoinSet<()>,
         cancel_token: tokio_util::sync::CancellationToken,
     ) -> Arc<Self> {
+        if app_registry.multicast_enabled() && !store.supports_multicast_delivery() {
+            error!("Metadata storage engine does not support multicast delivery, required by 'multicast_endpoints'");
+            panic!("Metadata storage engine does not support multicast delivery, required by 'multicast_endpoints'");
+        }
+
+        let prophet = prophet::Prophet::new(config, &admin_endpoints);
+        if prophet.is_some() && fib.is_none() {
+            warn!("PRoPHET routing has no effect with forwarding disabled");
+        }
+
+        let telemetry = telemetry::Telemetry::new(config, &admin_endpoints);
+
+        let dispatcher_config = self::config::Config::new(config, admin_endpoints);
+        let sequencer = sequence::Sequencer::new(dispatcher_config.ordered_delivery_timeout);
+        let dedup = (dispatcher_config.dedup_capacity != 0).then(|| {
+            dedup::Dedup::new(
+                dispatcher_config.dedup_window,
+                dispatcher_config.dedup_capacity,
+            )
+        });
+
+        let collection_cache = dispatcher_config
+            .collection_cache_threshold
+            .map(|_| collection_cache::CollectionCache::default());
+
+        let recorder = recorder::Recorder::new(config, task_set, cancel_token.clone());
+
         // Create a channel for bundles
-        let (tx, rx) = tokio::sync::mpsc::channel(16);
+        let (tx, rx) = tokio::sync::mpsc::channel(utils::memory::dispatch_queue());
         let dispatcher = Arc::new(Self {
-            config: self::config::Config::new(config, admin_endpoints),
+            config: dispatcher_config,
             cancel_token,
             store,
             tx,
             cla_registry,
             app_registry,
             fib,
+            resolvers: resolver::Resolvers::new(config),
+            dedup,
+            reputation: reputation::Reputation::new(config),
+            peer_cache: peer_cache::PeerCache::new(config),
+            calendar: shaping::Calendar::new(config),
+            collection_cache,
+            subscriptions: Default::default(),
+            pings: Default::default(),
+            status_watch: Default::default(),
+            receipts: Default::default(),
+            prophet,
+            telemetry,
+            wake: wake::Wake::new(config),
+            recorder,
+            retransmissions: Default::default(),
+            delivery_ids: Default::default(),
+            delivery_transforms: delivery::Registry::new(config),
+            integrity_keys: Box::new(integrity::ConfigKeys::new(config)),
+            sequencer,
+            reassembly_lock: Default::default(),
+            reports_in_flight: Default::default(),
+            rewrite_diagnostics_sent: Default::default(),
+            congested_clas: Default::default(),
         });
 
         // Spawn the dispatch task
         let dispatcher_cloned = dispatcher.clone();
         task_set.spawn(dispatch::dispatch_task(dispatcher_cloned, rx));
 
+        // Spawn the ordered delivery timeout task
+        task_set.spawn(sequence::sequence_task(dispatcher.clone()));
+
+        // Spawn the fragment expiry task
+        task_set.spawn(fragment::reassembly_task(dispatcher.clone()));
+
+        // Spawn the telemetry task
+        if dispatcher.telemetry.is_some() {
+            task_set.spawn(telemetry::telemetry_task(dispatcher.clone()));
+        }
+
+        // Spawn the peer cache save task
+        if dispatcher.peer_cache.is_some() {
+            task_set.spawn(peer_cache::peer_cache_task(dispatcher.clone()));
+        }
+
+        // Spawn the FIB event task
+        if let Some(fib) = &dispatcher.fib {
+            task_set.spawn(dispatch::fib_event_task(
+                dispatcher.clone(),
+                fib.subscribe(),
+            ));
+
+            // Spawn the PRoPHET exchange task
+            if dispatcher.prophet.is_some() {
+                task_set.spawn(prophet::prophet_task(dispatcher.clone()));
+            }
+        }
+
         dispatcher
     }
 
@@ -64,17 +198,48 @@ impl Dispatcher {
         // Try to load the data, but treat errors as 'Storage Depleted'
         let storage_name = bundle.metadata.storage_name.as_ref().unwrap();
         if let Some(data) = self.store.load_data(storage_name).await? {
-            return Ok(Some(data));
+            if self.store.verify_data(&bundle.metadata, (*data).as_ref()) {
+                return Ok(Some(data));
+            }
+
+            // Never serve corrupt data
+            warn!("Bundle data {storage_name} does not match its hash");
+            self.store
+                .quarantine_data(storage_name, (*data).as_ref())
+                .await;
+            return self
+                .drop_bundle(
+                    bundle.clone(),
+                    Some(bpv7::StatusReportReasonCode::DepletedStorage),
+                )
+                .await
+                .map(|_| None);
         }
 
-        warn!("Bundle data {storage_name} has gone from storage");
+        // A bundle storage given expiry hints may have collected the data of an expired bundle
+        let reason = if bundle.expiry() <= clock::now() {
+            trace!("Bundle data {storage_name} has expired from storage");
+            bpv7::StatusReportReasonCode::LifetimeExpired
+        } else {
+            warn!("Bundle data {storage_name} has gone from storage");
+            bpv7::StatusReportReasonCode::DepletedStorage
+        };
 
         // Report the bundle has gone
-        self.report_bundle_deletion(bundle, bpv7::StatusReportReasonCode::DepletedStorage)
+        self.report_bundle_deletion(bundle, reason)
             .await
             .map(|_| None)
     }
 
+    // Count a bundle against the application it is destined for, or else the one that sent it
+    async fn count_bundle(&self, counter: utils::labels::Counter, bundle: &bpv7::Bundle) {
+        let label = match self.app_registry.metrics_label(&bundle.destination).await {
+            Some(label) => Some(label),
+            None => self.app_registry.metrics_label(&bundle.id.source).await,
+        };
+        utils::labels::count(counter, label);
+    }
+
     #[instrument(skip(self))]
     async fn drop_bundle(
         &self,
@@ -83,6 +248,8 @@ impl Dispatcher {
     ) -> Result<(), Error> {
         if let Some(reason) = reason {
             self.report_bundle_deletion(&bundle, reason).await?;
+            self.count_bundle(utils::labels::Counter::Dropped, &bundle.bundle)
+                .await;
         }
 
         // Leave a tombstone in the metadata, so we can ignore duplicates
@@ -90,13 +257,15 @@ impl Dispatcher {
             // Don't update Tombstone timestamp
         } else {
             self.store
-                .set_status(
-                    &mut bundle,
-                    metadata::BundleStatus::Tombstone(time::OffsetDateTime::now_utc()),
-                )
+                .set_status(&mut bundle, metadata::BundleStatus::Tombstone(clock::now()))
                 .await?;
         }
 
+        self.forget_retransmissions(&bundle.bundle.id);
+        self.forget_delivery_ids(&bundle.bundle.id);
+        self.report_done(&bundle.bundle);
+        self.release_cached_payload(&bundle.bundle.id);
+
         // Delete the bundle from the bundle store
         if let Some(storage_name) = bundle.metadata.storage_name {
             self.store.delete_data(&storage_name).await?;
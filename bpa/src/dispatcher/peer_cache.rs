@@ -0,0 +1,490 @@
+use super::*;
+use std::collections::{BTreeSet, HashMap};
+
+/* What is learned of peer nodes is cached by node ID, so egress can be tailored to what the
+ * node at the other end understands.  CLAs advertise what they learn of a peer, such as the
+ * largest bundle it accepts, when they set up a session with it; the bundles received add
+ * what they show: a 3-element ipn EID proves the node that wrote it understands them, and a
+ * BIB or BCB proves its security source supports the security context.  An advertisement
+ * overrides what it states, observations only ever add.  Peers are forgotten once nothing
+ * has been heard of them for the 'ttl', and the cache is saved to 'file', if set, so it
+ * survives a restart.  The file is a CBOR array of peers, each an array of: the node ID,
+ * the time last updated in seconds since the Unix epoch, whether advertised, the max bundle
+ * size or 0 if unknown, 3-element ipn support as 0 if unknown, 1 if not or 2 if supported,
+ * and the array of security context ids */
+
+// Forget the least recently updated peers beyond this many
+const MAX_PEERS: usize = 4096;
+
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(default)]
+struct Config {
+    file: Option<std::path::PathBuf>,
+    // In seconds
+    ttl: u64,
+    // Seconds between saves of a changed cache
+    save_interval: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            file: None,
+            ttl: 7 * 24 * 60 * 60,
+            save_interval: 60,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PeerCapabilities {
+    // BPSec security context ids
+    pub bpsec_contexts: BTreeSet<u64>,
+    pub max_bundle_size: Option<u64>,
+    pub ipn_3_element: Option<bool>,
+}
+
+pub struct PeerSummary {
+    pub node_id: bpv7::Eid,
+    pub capabilities: PeerCapabilities,
+    pub advertised: bool,
+    pub updated: time::OffsetDateTime,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Peer {
+    capabilities: PeerCapabilities,
+    advertised: bool,
+    updated: time::OffsetDateTime,
+}
+
+// The node ID of the node an EID belongs to
+pub fn node_id(eid: &bpv7::Eid) -> Option<bpv7::Eid> {
+    match eid {
+        bpv7::Eid::Ipn {
+            allocator_id,
+            node_number,
+            ..
+        }
+        | bpv7::Eid::LegacyIpn {
+            allocator_id,
+            node_number,
+            ..
+        } => Some(bpv7::Eid::Ipn {
+            allocator_id: *allocator_id,
+            node_number: *node_number,
+            service_number: 0,
+        }),
+        bpv7::Eid::Dtn { node_name, .. } => Some(bpv7::Eid::Dtn {
+            node_name: node_name.clone(),
+            demux: [].into(),
+        }),
+        _ => None,
+    }
+}
+
+pub struct PeerCache {
+    config: Config,
+    ttl: time::Duration,
+    // The peers, and whether they have changed since the cache was saved
+    peers: std::sync::Mutex<(HashMap<bpv7::Eid, Peer>, bool)>,
+}
+
+impl PeerCache {
+    pub fn new(config: &::config::Config) -> Option<Self> {
+        let config =
+            utils::settings::get_with_default::<Option<Config>, _>(config, "peer_cache", None)
+                .trace_expect("Invalid 'peer_cache' section in configuration")?;
+
+        let peers = match &config.file {
+            Some(path) => match std::fs::read(path) {
+                Ok(data) => decode(&data).unwrap_or_else(|e| {
+                    warn!("Ignoring invalid peer cache {}: {e}", path.display());
+                    HashMap::new()
+                }),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+                Err(e) => {
+                    warn!("Failed to read peer cache {}: {e}", path.display());
+                    HashMap::new()
+                }
+            },
+            None => HashMap::new(),
+        };
+        info!(
+            "Caching peer capabilities, {} peers known, forgotten after {}s",
+            peers.len(),
+            config.ttl
+        );
+        Some(Self::with_peers(config, peers))
+    }
+
+    fn with_peers(config: Config, peers: HashMap<bpv7::Eid, Peer>) -> Self {
+        Self {
+            ttl: time::Duration::seconds(config.ttl.min(i64::MAX as u64) as i64),
+            config,
+            peers: std::sync::Mutex::new((peers, false)),
+        }
+    }
+
+    // Update the peer `node_id`, adding it if need be
+    fn update(&self, node_id: &bpv7::Eid, now: time::OffsetDateTime, f: impl FnOnce(&mut Peer)) {
+        let mut guard = self.peers.lock().trace_expect("Failed to lock mutex");
+        let (peers, dirty) = &mut *guard;
+        if peers.len() >= MAX_PEERS && !peers.contains_key(node_id) {
+            peers.retain(|_, peer| peer.updated + self.ttl > now);
+            if peers.len() >= MAX_PEERS {
+                if let Some(oldest) = peers
+                    .iter()
+                    .min_by_key(|(_, peer)| peer.updated)
+                    .map(|(node_id, _)| node_id.clone())
+                {
+                    peers.remove(&oldest);
+                }
+            }
+        }
+
+        let peer = peers.entry(node_id.clone()).or_insert_with(|| Peer {
+            capabilities: PeerCapabilities::default(),
+            advertised: false,
+            updated: now,
+        });
+        f(peer);
+        peer.updated = now;
+        *dirty = true;
+    }
+
+    // What a CLA has learned of a peer, which overrides whatever it states
+    pub fn advertise(
+        &self,
+        node_id: &bpv7::Eid,
+        capabilities: PeerCapabilities,
+        now: time::OffsetDateTime,
+    ) {
+        self.update(node_id, now, |peer| {
+            if !capabilities.bpsec_contexts.is_empty() {
+                peer.capabilities.bpsec_contexts = capabilities.bpsec_contexts;
+            }
+            if capabilities.max_bundle_size.is_some() {
+                peer.capabilities.max_bundle_size = capabilities.max_bundle_size;
+            }
+            if capabilities.ipn_3_element.is_some() {
+                peer.capabilities.ipn_3_element = capabilities.ipn_3_element;
+            }
+            peer.advertised = true;
+        })
+    }
+
+    // The peer has written a 3-element ipn EID
+    pub fn observe_ipn_3_element(&self, node_id: &bpv7::Eid, now: time::OffsetDateTime) {
+        self.update(node_id, now, |peer| {
+            peer.capabilities.ipn_3_element = Some(true)
+        })
+    }
+
+    // The peer has been the security source of a security block
+    pub fn observe_bpsec_context(
+        &self,
+        node_id: &bpv7::Eid,
+        context: u64,
+        now: time::OffsetDateTime,
+    ) {
+        self.update(node_id, now, |peer| {
+            peer.capabilities.bpsec_contexts.insert(context);
+        })
+    }
+
+    pub fn get(&self, node_id: &bpv7::Eid, now: time::OffsetDateTime) -> Option<PeerCapabilities> {
+        self.peers
+            .lock()
+            .trace_expect("Failed to lock mutex")
+            .0
+            .get(node_id)
+            .filter(|peer| peer.updated + self.ttl > now)
+            .map(|peer| peer.capabilities.clone())
+    }
+
+    // The peers not yet forgotten, ordered by node ID
+    pub fn summary(&self, now: time::OffsetDateTime) -> Vec<PeerSummary> {
+        let mut summary = self
+            .peers
+            .lock()
+            .trace_expect("Failed to lock mutex")
+            .0
+            .iter()
+            .filter(|(_, peer)| peer.updated + self.ttl > now)
+            .map(|(node_id, peer)| PeerSummary {
+                node_id: node_id.clone(),
+                capabilities: peer.capabilities.clone(),
+                advertised: peer.advertised,
+                updated: peer.updated,
+            })
+            .collect::<Vec<_>>();
+        summary.sort_by_key(|peer| peer.node_id.to_string());
+        summary
+    }
+
+    // Write the cache to its file, if it has changed since it was last written
+    pub async fn save(&self, now: time::OffsetDateTime) {
+        let Some(path) = &self.config.file else {
+            return;
+        };
+        let data = {
+            let mut guard = self.peers.lock().trace_expect("Failed to lock mutex");
+            let (peers, dirty) = &mut *guard;
+            if !*dirty {
+                return;
+            }
+            peers.retain(|_, peer| peer.updated + self.ttl > now);
+            *dirty = false;
+            encode(peers)
+        };
+
+        // Write a new file and move it into place, so a crash cannot leave half a cache
+        let mut new_path = path.clone().into_os_string();
+        new_path.push(".new");
+        if let Err(e) = async {
+            tokio::fs::write(&new_path, data).await?;
+            tokio::fs::rename(&new_path, path).await
+        }
+        .await
+        {
+            warn!("Failed to save peer cache {}: {e}", path.display());
+            self.peers.lock().trace_expect("Failed to lock mutex").1 = true;
+        }
+    }
+}
+
+fn encode(peers: &HashMap<bpv7::Eid, Peer>) -> Vec<u8> {
+    cbor::encode::emit_array(Some(peers.len()), |a| {
+        for (node_id, peer) in peers {
+            a.emit_array(Some(6), |a| {
+                a.emit(node_id.to_string());
+                a.emit(peer.updated.unix_timestamp().max(0) as u64);
+                a.emit(peer.advertised);
+                a.emit(peer.capabilities.max_bundle_size.unwrap_or(0));
+                a.emit(match peer.capabilities.ipn_3_element {
+                    None => 0u64,
+                    Some(false) => 1,
+                    Some(true) => 2,
+                });
+                a.emit_array(Some(peer.capabilities.bpsec_contexts.len()), |a| {
+                    for context in &peer.capabilities.bpsec_contexts {
+                        a.emit(*context);
+                    }
+                });
+            });
+        }
+    })
+}
+
+fn decode(data: &[u8]) -> Result<HashMap<bpv7::Eid, Peer>, Error> {
+    cbor::decode::parse_array(data, |a, _, _| {
+        let mut peers = HashMap::new();
+        while let Some((node_id, peer)) = a.try_parse_array(|a, _, _| {
+            let node_id = a.parse_value(|value, _, tags| match value {
+                cbor::decode::Value::Text(s) => Ok(s.to_string()),
+                value => Err(cbor::decode::Error::IncorrectType(
+                    "Text String".to_string(),
+                    value.type_name(!tags.is_empty()),
+                )),
+            })?;
+            let node_id = node_id
+                .parse::<bpv7::Eid>()
+                .ok()
+                .and_then(|eid| self::node_id(&eid))
+                .ok_or_else(|| format!("Invalid node ID '{node_id}'"))?;
+            let updated = time::OffsetDateTime::from_unix_timestamp(
+                a.parse::<u64>()?.min(i64::MAX as u64) as i64,
+            )?;
+            let advertised = a.parse::<bool>()?;
+            let max_bundle_size = Some(a.parse::<u64>()?).filter(|size| *size != 0);
+            let ipn_3_element = match a.parse::<u64>()? {
+                1 => Some(false),
+                2 => Some(true),
+                _ => None,
+            };
+            let bpsec_contexts = a.parse_array(|a, _, _| {
+                let mut contexts = BTreeSet::new();
+                while let Some(context) = a.try_parse::<u64>()? {
+                    contexts.insert(context);
+                }
+                Ok::<_, cbor::decode::Error>(contexts)
+            })?;
+            Ok::<_, Error>((
+                node_id,
+                Peer {
+                    capabilities: PeerCapabilities {
+                        bpsec_contexts,
+                        max_bundle_size,
+                        ipn_3_element,
+                    },
+                    advertised,
+                    updated,
+                },
+            ))
+        })? {
+            peers.insert(node_id, peer);
+        }
+        Ok::<_, Error>(peers)
+    })
+    .map(|(peers, _)| peers)
+}
+
+// The security context id and security source of a BIB or BCB
+fn security_context(block: &bpv7::Block, data: &[u8]) -> Option<(u64, bpv7::Eid)> {
+    let asb = block.block_data(data).ok()?;
+    cbor::decode::parse_array(&asb, |a, _, _| {
+        a.parse_array(|a, _, _| {
+            while a.try_parse::<u64>()?.is_some() {}
+            Ok::<_, cbor::decode::Error>(())
+        })?;
+        let context = a.parse::<u64>()?;
+        let _flags = a.parse::<u64>()?;
+        let source = a.parse::<bpv7::Eid>()?;
+        Ok::<_, Error>((context, source))
+    })
+    .ok()
+    .map(|(r, _)| r)
+}
+
+impl Dispatcher {
+    // Learn what we can of the nodes a received bundle has passed through
+    pub(super) fn observe_peers(&self, bundle: &bpv7::Bundle, data: &[u8]) {
+        let Some(peer_cache) = &self.peer_cache else {
+            return;
+        };
+        let now = clock::now();
+        for eid in [Some(&bundle.id.source), bundle.previous_node.as_ref()]
+            .into_iter()
+            .flatten()
+        {
+            if let bpv7::Eid::Ipn { .. } = eid {
+                if let Some(node_id) = node_id(eid) {
+                    peer_cache.observe_ipn_3_element(&node_id, now);
+                }
+            }
+        }
+        for block in bundle.blocks.values() {
+            if let bpv7::BlockType::BlockIntegrity | bpv7::BlockType::BlockSecurity =
+                block.block_type
+            {
+                if let Some((context, node_id)) = security_context(block, data)
+                    .and_then(|(context, source)| Some((context, node_id(&source)?)))
+                {
+                    peer_cache.observe_bpsec_context(&node_id, context, now);
+                }
+            }
+        }
+    }
+
+    // What a CLA has learned of a peer, ignored unless the peer cache is enabled
+    pub fn advertise_peer(&self, eid: &bpv7::Eid, capabilities: PeerCapabilities) {
+        let (Some(peer_cache), Some(node_id)) = (&self.peer_cache, node_id(eid)) else {
+            return;
+        };
+        trace!("Peer {node_id} advertised: {capabilities:?}");
+        peer_cache.advertise(&node_id, capabilities, clock::now());
+    }
+
+    // What is known of the node `eid` belongs to
+    pub(super) fn peer_capabilities_of(&self, eid: &bpv7::Eid) -> Option<PeerCapabilities> {
+        self.peer_cache.as_ref()?.get(&node_id(eid)?, clock::now())
+    }
+
+    // None if the peer cache is not enabled
+    pub fn peer_capabilities(&self) -> Option<Vec<PeerSummary>> {
+        self.peer_cache
+            .as_ref()
+            .map(|peer_cache| peer_cache.summary(clock::now()))
+    }
+}
+
+pub async fn peer_cache_task(dispatcher: Arc<Dispatcher>) {
+    let Some(peer_cache) = &dispatcher.peer_cache else {
+        return;
+    };
+    let interval = time::Duration::seconds(peer_cache.config.save_interval.max(1) as i64);
+    while clock::sleep(interval, &dispatcher.cancel_token).await {
+        peer_cache.save(clock::now()).await;
+    }
+
+    // And once more on the way out
+    peer_cache.save(clock::now()).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(seconds: i64) -> time::OffsetDateTime {
+        time::OffsetDateTime::from_unix_timestamp(seconds).unwrap()
+    }
+
+    fn cache() -> PeerCache {
+        PeerCache::with_peers(
+            Config {
+                ttl: 100,
+                ..Default::default()
+            },
+            HashMap::new(),
+        )
+    }
+
+    #[test]
+    fn advertise_and_observe() {
+        let cache = cache();
+        let node = node_id(&"ipn:2.7".parse().unwrap()).unwrap();
+        assert_eq!(
+            node,
+            bpv7::Eid::Ipn {
+                allocator_id: 0,
+                node_number: 2,
+                service_number: 0
+            }
+        );
+
+        cache.observe_bpsec_context(&node, 1, at(1000));
+        cache.observe_ipn_3_element(&node, at(1000));
+        cache.advertise(
+            &node,
+            PeerCapabilities {
+                max_bundle_size: Some(65536),
+                ipn_3_element: Some(false),
+                ..Default::default()
+            },
+            at(1010),
+        );
+        assert_eq!(
+            cache.get(&node, at(1020)),
+            Some(PeerCapabilities {
+                bpsec_contexts: [1].into(),
+                max_bundle_size: Some(65536),
+                ipn_3_element: Some(false),
+            })
+        );
+
+        // Forgotten once the ttl has passed since the last update
+        assert!(cache.get(&node, at(1110)).is_none());
+        assert!(cache.summary(at(1110)).is_empty());
+    }
+
+    #[test]
+    fn roundtrip() {
+        let cache = cache();
+        let ipn = node_id(&"ipn:3.0".parse().unwrap()).unwrap();
+        let dtn = node_id(&"dtn://node/app".parse().unwrap()).unwrap();
+        cache.advertise(
+            &ipn,
+            PeerCapabilities {
+                bpsec_contexts: [1, 2].into(),
+                max_bundle_size: Some(1024),
+                ipn_3_element: Some(true),
+            },
+            at(2000),
+        );
+        cache.observe_bpsec_context(&dtn, 2, at(2001));
+
+        let peers = cache.peers.lock().unwrap().0.clone();
+        assert_eq!(decode(&encode(&peers)).unwrap(), peers);
+    }
+}
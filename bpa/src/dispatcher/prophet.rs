@@ -0,0 +1,480 @@
+use super::*;
+use std::collections::{HashMap, HashSet};
+
+/* PRoPHET (RFC 6693) routing: each node keeps the predictability of delivering a bundle to
+ * every other node.  Meeting a node makes delivering to it more likely, and each
+ * predictability ages over time, so nodes that have not been met for a while fall away.
+ * Neighbours are the ipn nodes the CLAs have added as neighbours, and each time one is
+ * added it counts as an encounter.  While connected, neighbours exchange their tables as
+ * bundles sent to the PRoPHET service of each other, and a neighbour that is likely to meet
+ * a node makes it transitively more likely that we will deliver to that node too.  Bundles
+ * for a node are routed via the connected neighbour with the highest predictability for it,
+ * as long as that is greater than our own (the GRTR strategy).  Predictabilities are only
+ * held in memory, so are relearned from scratch when the BPA restarts */
+
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(default)]
+pub struct Config {
+    // The ipn service number the tables are exchanged on, the same for every node
+    service_number: u32,
+    // Seconds between exchanges with each neighbour
+    interval: u64,
+    // P_encounter, P_first_threshold, beta and gamma in RFC 6693
+    encounter: f64,
+    forget: f64,
+    beta: f64,
+    gamma: f64,
+    // Seconds in each unit of time predictabilities age by gamma
+    time_unit: u64,
+    distance: u32,
+    protocol_id: String,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            service_number: 6693,
+            interval: 30,
+            encounter: 0.5,
+            forget: 0.1,
+            beta: 0.9,
+            gamma: 0.999,
+            time_unit: 30,
+            distance: fib::DISTANCE_PROBABILISTIC,
+            protocol_id: "prophet".to_string(),
+        }
+    }
+}
+
+impl Config {
+    fn validate(&self) -> Result<(), String> {
+        for (key, value) in [
+            ("encounter", self.encounter),
+            ("forget", self.forget),
+            ("beta", self.beta),
+            ("gamma", self.gamma),
+        ] {
+            if !(0.0..=1.0).contains(&value) {
+                return Err(format!("'{key}' must be between 0 and 1"));
+            }
+        }
+        if self.interval == 0 || self.time_unit == 0 {
+            return Err("'interval' and 'time_unit' must be greater than 0".to_string());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct Table {
+    // Our predictability for each node number
+    own: HashMap<u32, f64>,
+    // When `own` was last aged
+    aged: Option<time::OffsetDateTime>,
+    // The predictabilities last received from each connected neighbour
+    neighbours: HashMap<u32, HashMap<u32, f64>>,
+}
+
+impl Table {
+    fn age(&mut self, config: &Config, now: time::OffsetDateTime) {
+        let Some(aged) = self.aged else {
+            self.aged = Some(now);
+            return;
+        };
+        let units = (now - aged).whole_seconds() / config.time_unit as i64;
+        if units <= 0 {
+            return;
+        }
+        self.aged = Some(aged + time::Duration::seconds(units * config.time_unit as i64));
+
+        let factor = config.gamma.powi(units.clamp(0, i32::MAX as i64) as i32);
+        self.own.retain(|_, p| {
+            *p *= factor;
+            *p >= config.forget
+        });
+    }
+
+    fn encounter(&mut self, config: &Config, node: u32) {
+        let p = self.own.entry(node).or_default();
+        *p += (1.0 - *p) * config.encounter;
+    }
+
+    fn disconnect(&mut self, node: u32) {
+        self.neighbours.remove(&node);
+    }
+
+    fn receive(&mut self, config: &Config, local: u32, from: u32, mut table: HashMap<u32, f64>) {
+        table.remove(&local);
+        let p_from = self.own.get(&from).copied().unwrap_or_default();
+        for (node, p_node) in &table {
+            if *node == from {
+                continue;
+            }
+            // Transitivity
+            let p = p_from * p_node.clamp(0.0, 1.0) * config.beta;
+            if p >= config.forget {
+                let own = self.own.entry(*node).or_default();
+                *own = own.max(p);
+            }
+        }
+        self.neighbours.insert(from, table);
+    }
+
+    // The neighbour to forward via for each node, and its predictability for the node
+    fn routes(&self, connected: &HashSet<u32>) -> HashMap<u32, (u32, f64)> {
+        let mut routes: HashMap<u32, (u32, f64)> = HashMap::new();
+        for (neighbour, table) in &self.neighbours {
+            if !connected.contains(neighbour) {
+                continue;
+            }
+            for (node, p) in table {
+                if connected.contains(node) || *p <= self.own.get(node).copied().unwrap_or_default()
+                {
+                    continue;
+                }
+                let better = match routes.get(node) {
+                    Some((via, best)) => *p > *best || (*p == *best && *neighbour < *via),
+                    None => true,
+                };
+                if better {
+                    routes.insert(*node, (*neighbour, *p));
+                }
+            }
+        }
+        routes
+    }
+}
+
+fn encode_table(table: &HashMap<u32, f64>) -> Vec<u8> {
+    cbor::encode::emit_array(Some(table.len()), |a| {
+        for (node, p) in table {
+            a.emit_array(Some(2), |a| {
+                a.emit(*node);
+                a.emit(*p);
+            });
+        }
+    })
+}
+
+fn decode_table(data: &[u8]) -> Option<HashMap<u32, f64>> {
+    cbor::decode::parse_array(data, |a, _, _| {
+        let mut table = HashMap::new();
+        while let Some((node, p)) = a.try_parse_array(|a, _, _| {
+            Ok::<_, cbor::decode::Error>((a.parse::<u32>()?, a.parse::<f64>()?))
+        })? {
+            table.insert(node, p);
+        }
+        Ok::<_, cbor::decode::Error>(table)
+    })
+    .map(|(table, _)| table)
+    .ok()
+}
+
+// The node number of a neighbour added by a CLA, if it is every service of one ipn node
+fn neighbour_node(
+    local: &utils::admin_endpoints::IpnNodeId,
+    pattern: &bpv7::EidPattern,
+) -> Option<u32> {
+    let node = pattern
+        .to_string()
+        .strip_prefix("ipn:")?
+        .split('.')
+        .nth(1)?
+        .parse::<u32>()
+        .ok()?;
+    let node_id = local.with_node_number(node);
+    (node != local.node_number() && node_id.to_pattern().ok().as_ref() == Some(pattern))
+        .then_some(node)
+}
+
+// The connected neighbours, and the pattern and neighbour of the FIB route to each node
+type Installed = (HashSet<u32>, HashMap<u32, (bpv7::EidPattern, u32)>);
+
+pub struct Prophet {
+    config: Config,
+    local: utils::admin_endpoints::IpnNodeId,
+    table: std::sync::Mutex<Table>,
+    routes: tokio::sync::Mutex<Installed>,
+}
+
+impl Prophet {
+    pub fn new(
+        config: &::config::Config,
+        admin_endpoints: &utils::admin_endpoints::AdminEndpoints,
+    ) -> Option<Self> {
+        let config =
+            utils::settings::get_with_default::<Option<Config>, _>(config, "prophet", None)
+                .trace_expect("Invalid 'prophet' section in configuration")?;
+        if let Err(e) = config.validate() {
+            error!("Invalid 'prophet' section in configuration: {e}");
+            panic!("Invalid 'prophet' section in configuration: {e}");
+        }
+
+        let Some(local) = admin_endpoints.ipn.clone() else {
+            error!("PRoPHET routing requires an ipn administrative endpoint");
+            panic!("PRoPHET routing requires an ipn administrative endpoint");
+        };
+
+        info!(
+            "PRoPHET routing exchanging predictabilities on service {} every {} seconds",
+            config.service_number, config.interval
+        );
+        Some(Self {
+            config,
+            local,
+            table: Default::default(),
+            routes: Default::default(),
+        })
+    }
+
+    fn endpoint(&self, node: u32) -> bpv7::Eid {
+        self.local
+            .with_node_number(node)
+            .to_eid(self.config.service_number)
+    }
+}
+
+impl Dispatcher {
+    pub(super) fn is_prophet_endpoint(&self, eid: &bpv7::Eid) -> bool {
+        self.prophet
+            .as_ref()
+            .is_some_and(|prophet| *eid == prophet.endpoint(prophet.local.node_number()))
+    }
+
+    #[instrument(skip(self))]
+    pub(super) async fn prophet_bundle(
+        &self,
+        bundle: &mut metadata::Bundle,
+    ) -> Result<DispatchResult, Error> {
+        let Some(prophet) = &self.prophet else {
+            return Ok(DispatchResult::Drop(None));
+        };
+
+        let bpv7::Eid::Ipn { node_number, .. } = &bundle.bundle.id.source else {
+            trace!("Ignoring PRoPHET bundle from {}", bundle.bundle.id.source);
+            return Ok(DispatchResult::Drop(None));
+        };
+        let from = *node_number;
+        if bundle.bundle.id.source != prophet.endpoint(from) {
+            trace!("Ignoring PRoPHET bundle from {}", bundle.bundle.id.source);
+            return Ok(DispatchResult::Drop(None));
+        }
+
+        let Some(data) = self.load_data(bundle).await? else {
+            // Bundle data was deleted sometime during processing - this is benign
+            return Ok(DispatchResult::Done);
+        };
+
+        let Some(table) = bundle
+            .bundle
+            .blocks
+            .get(&1)
+            .and_then(|block| block.block_data(data.as_ref().as_ref()).ok())
+            .and_then(|payload| decode_table(&payload))
+        else {
+            trace!("PRoPHET bundle has an unreadable payload");
+            return Ok(DispatchResult::Drop(Some(
+                bpv7::StatusReportReasonCode::BlockUnintelligible,
+            )));
+        };
+
+        if prophet.routes.lock().await.0.contains(&from) {
+            trace!("Received {} predictabilities from node {from}", table.len());
+            metrics::counter!("prophet_tables_received_total").increment(1);
+            prophet
+                .table
+                .lock()
+                .trace_expect("Failed to lock mutex")
+                .receive(&prophet.config, prophet.local.node_number(), from, table);
+            self.refresh_prophet_routes(prophet).await;
+        } else {
+            trace!("Ignoring predictabilities from node {from}, which is not a neighbour");
+        }
+        Ok(DispatchResult::Drop(None))
+    }
+
+    async fn refresh_prophet_routes(&self, prophet: &Prophet) {
+        let Some(fib) = &self.fib else {
+            return;
+        };
+
+        let mut guard = prophet.routes.lock().await;
+        let (connected, installed) = &mut *guard;
+        let routes = prophet
+            .table
+            .lock()
+            .trace_expect("Failed to lock mutex")
+            .routes(connected);
+
+        // Remove routes that have gone or changed
+        let previous = std::mem::take(installed);
+        for (node, (pattern, via)) in previous {
+            match routes.get(&node) {
+                Some((neighbour, _)) if *neighbour == via => {
+                    installed.insert(node, (pattern, via));
+                }
+                _ => {
+                    fib.remove(&prophet.config.protocol_id, &pattern).await;
+                }
+            }
+        }
+
+        // Add the new ones
+        for (node, (neighbour, p)) in routes {
+            if installed.contains_key(&node) {
+                continue;
+            }
+            let pattern = prophet
+                .local
+                .with_node_number(node)
+                .to_pattern()
+                .trace_expect("Failed to build EID pattern for PRoPHET node");
+            // The less likely the neighbour is to deliver, the higher the cost
+            let cost = ((1.0 - p) * 1000.0).round() as u32;
+            if let Err(e) = fib
+                .add(
+                    prophet.config.protocol_id.clone(),
+                    &pattern,
+                    prophet.config.distance,
+                    cost,
+                    fib::Action::Via(prophet.local.with_node_number(neighbour).to_eid(0)),
+                    None,
+                )
+                .await
+            {
+                error!("Failed to insert PRoPHET route {pattern}: {e}");
+            } else {
+                installed.insert(node, (pattern, neighbour));
+            }
+        }
+        metrics::gauge!("prophet_routes").set(installed.len() as f64);
+    }
+
+    // Note the neighbours that have come and gone, and send our table to each of them
+    async fn prophet_exchange(&self, prophet: &Prophet) {
+        let now = clock::now();
+        let neighbours = self
+            .cla_registry
+            .neighbours()
+            .await
+            .iter()
+            .filter_map(|pattern| neighbour_node(&prophet.local, pattern))
+            .collect::<HashSet<_>>();
+
+        let own = {
+            let mut connected = prophet.routes.lock().await;
+            let mut table = prophet.table.lock().trace_expect("Failed to lock mutex");
+            table.age(&prophet.config, now);
+            for node in neighbours.difference(&connected.0) {
+                trace!("Encountered node {node}");
+                metrics::counter!("prophet_encounters_total").increment(1);
+                table.encounter(&prophet.config, *node);
+            }
+            for node in connected.0.difference(&neighbours) {
+                table.disconnect(*node);
+            }
+            connected.0 = neighbours.clone();
+            metrics::gauge!("prophet_predictabilities").set(table.own.len() as f64);
+            table.own.clone()
+        };
+        self.refresh_prophet_routes(prophet).await;
+
+        let data = Bytes::from(encode_table(&own));
+        for node in neighbours {
+            if let Err(e) = self
+                .local_dispatch(SendRequest {
+                    source: prophet.endpoint(prophet.local.node_number()),
+                    destination: prophet.endpoint(node),
+                    data: data.clone(),
+                    // The table is stale by the next exchange
+                    lifetime: Some(prophet.config.interval * 1000),
+                    flags: None,
+                    track: false,
+                })
+                .await
+            {
+                warn!("Failed to send PRoPHET predictabilities to node {node}: {e}");
+            }
+        }
+    }
+}
+
+pub async fn prophet_task(dispatcher: Arc<Dispatcher>) {
+    let Some(prophet) = &dispatcher.prophet else {
+        return;
+    };
+    let interval = time::Duration::seconds(prophet.config.interval as i64);
+    loop {
+        dispatcher.prophet_exchange(prophet).await;
+        if !clock::sleep(interval, &dispatcher.cancel_token).await {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn predictabilities() {
+        let config = Config {
+            encounter: 0.5,
+            forget: 0.01,
+            beta: 0.5,
+            gamma: 0.5,
+            time_unit: 10,
+            ..Default::default()
+        };
+        let mut table = Table::default();
+        let now = time::OffsetDateTime::now_utc();
+        table.age(&config, now);
+
+        // Encounters, then transitivity via node 2
+        table.encounter(&config, 2);
+        table.encounter(&config, 2);
+        assert_eq!(table.own[&2], 0.75);
+        table.receive(&config, 1, 2, HashMap::from([(1, 1.0), (3, 0.5)]));
+        assert_eq!(table.own[&3], 0.1875);
+        assert!(!table.own.contains_key(&1));
+
+        // Routed via the neighbour that is more likely to deliver
+        let connected = HashSet::from([2]);
+        assert_eq!(table.routes(&connected), HashMap::from([(3, (2, 0.5))]));
+        table.disconnect(2);
+        assert!(table.routes(&connected).is_empty());
+
+        // Aging by whole time units, forgetting the unlikely
+        table.age(&config, now + time::Duration::seconds(25));
+        assert_eq!(table.own[&2], 0.1875);
+        table.age(&config, now + time::Duration::seconds(60));
+        assert!(!table.own.contains_key(&3));
+        assert_eq!(table.own[&2], 0.01171875);
+    }
+
+    #[test]
+    fn neighbours() {
+        let config = ::config::Config::builder()
+            .set_override("administrative_endpoint", "ipn:1.0")
+            .unwrap()
+            .build()
+            .unwrap();
+        let local = utils::admin_endpoints::AdminEndpoints::init(&config)
+            .ipn
+            .unwrap();
+        let node = |s: &str| neighbour_node(&local, &s.parse().unwrap());
+        assert_eq!(node("ipn:0.2.*"), Some(2));
+        assert_eq!(node("ipn:2.*"), Some(2));
+        assert_eq!(node("ipn:1.*"), None);
+        assert_eq!(node("ipn:2.1"), None);
+        assert_eq!(node("ipn:[2-3].*"), None);
+        assert_eq!(node("dtn://node/**"), None);
+    }
+
+    #[test]
+    fn table_roundtrip() {
+        let table = HashMap::from([(2, 0.75), (3, 0.125)]);
+        assert_eq!(decode_table(&encode_table(&table)), Some(table));
+        assert_eq!(decode_table(b"hello"), None);
+    }
+}
@@ -0,0 +1,178 @@
+use super::*;
+use hardy_bpa_api::storage;
+use std::collections::{HashMap, HashSet};
+use std::sync::Weak;
+use tokio::sync::{mpsc, Notify};
+
+/* Applications may subscribe to have bundles pushed to them as they become ready for
+ * collection, rather than polling for them.  Each subscription is served by a task that
+ * collects every bundle pending for the application, oldest first, and then sleeps until
+ * another bundle for the endpoint becomes ready.  A slot in the subscriber's stream is
+ * reserved before each bundle is collected, so a slow application holds back its own
+ * deliveries, and none are recorded once it has gone.  Bundles not yet pushed when an
+ * application disconnects stay pending, and are pushed when it subscribes again, as are
+ * bundles pushed to an application acknowledging delivery that it did not acknowledge */
+
+#[derive(Default)]
+pub struct Subscriptions {
+    // The subscriptions hold the only strong references, so ended ones are dropped lazily
+    inner: std::sync::Mutex<HashMap<bpv7::Eid, Vec<Weak<Notify>>>>,
+}
+
+impl Subscriptions {
+    fn add(&self, destination: &bpv7::Eid, notify: &Arc<Notify>) {
+        let mut inner = self.inner.lock().trace_expect("Failed to lock mutex");
+        let subscribers = inner.entry(destination.clone()).or_default();
+        subscribers.retain(|s| s.strong_count() > 0);
+        subscribers.push(Arc::downgrade(notify));
+    }
+
+    fn notify(&self, destination: &bpv7::Eid) {
+        let mut inner = self.inner.lock().trace_expect("Failed to lock mutex");
+        let Some(subscribers) = inner.get_mut(destination) else {
+            return;
+        };
+        subscribers.retain(|s| match s.upgrade() {
+            Some(notify) => {
+                notify.notify_one();
+                true
+            }
+            None => false,
+        });
+        if subscribers.is_empty() {
+            inner.remove(destination);
+        }
+    }
+}
+
+impl Dispatcher {
+    // Push the bundles for `destination` to `tx` as they become ready, until it is closed
+    #[instrument(skip(self, tx))]
+    pub fn subscribe(
+        self: &Arc<Self>,
+        destination: bpv7::Eid,
+        registration: Option<String>,
+        acknowledged: bool,
+        tx: mpsc::Sender<CollectResponse>,
+    ) {
+        // Subscribe before the first scan, so nothing that becomes ready during it is missed
+        let notify = Arc::new(Notify::new());
+        self.subscriptions.add(&destination, &notify);
+
+        metrics::gauge!("push_subscriptions").increment(1);
+        let dispatcher = self.clone();
+        tokio::spawn(async move {
+            dispatcher
+                .push_task(destination, registration, acknowledged, notify, tx)
+                .await;
+            metrics::gauge!("push_subscriptions").decrement(1);
+        });
+    }
+
+    // Wake the subscriptions for the destination of a bundle that is ready for collection
+    pub(super) fn push_notify(&self, destination: &bpv7::Eid) {
+        self.subscriptions.notify(destination);
+    }
+
+    async fn push_task(
+        self: Arc<Self>,
+        destination: bpv7::Eid,
+        registration: Option<String>,
+        acknowledged: bool,
+        notify: Arc<Notify>,
+        tx: mpsc::Sender<CollectResponse>,
+    ) {
+        // The deliveries pushed awaiting acknowledgement, so they are not pushed again
+        let mut pushed = HashSet::new();
+        loop {
+            match self
+                .push_pending(&destination, &registration, acknowledged, &mut pushed, &tx)
+                .await
+            {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(e) => warn!("Failed to push bundles for {destination}: {e}"),
+            }
+
+            tokio::select! {
+                _ = notify.notified() => {}
+                _ = tx.closed() => break,
+                _ = self.cancel_token.cancelled() => break,
+            }
+        }
+        trace!("Subscription for {destination} ended");
+    }
+
+    // Returns false if the subscriber has gone
+    async fn push_pending(
+        self: &Arc<Self>,
+        destination: &bpv7::Eid,
+        registration: &Option<String>,
+        acknowledged: bool,
+        pushed: &mut HashSet<u64>,
+        tx: &mpsc::Sender<CollectResponse>,
+    ) -> Result<bool, Error> {
+        pushed.retain(|delivery_id| self.is_delivery_pending(*delivery_id));
+
+        let (scan_tx, mut scan_rx) = mpsc::channel::<metadata::Bundle>(16);
+        let dispatcher = self.clone();
+        let scan_destination = destination.clone();
+        let scan_registration = registration.clone();
+        let h = tokio::spawn(async move {
+            dispatcher
+                .poll_for_collection(
+                    scan_destination,
+                    scan_registration,
+                    storage::ScanBudget::default(),
+                    scan_tx,
+                )
+                .await
+        });
+
+        let mut result = Ok(true);
+        while let Some(bundle) = scan_rx.recv().await {
+            if self
+                .pending_delivery_id(&bundle.bundle.id, registration)
+                .is_some_and(|delivery_id| pushed.contains(&delivery_id))
+            {
+                continue;
+            }
+
+            // Wait for room in the stream before the bundle is collected, and so delivered
+            let Ok(permit) = tx.reserve().await else {
+                result = Ok(false);
+                break;
+            };
+
+            match self
+                .collect(
+                    destination.clone(),
+                    registration.clone(),
+                    acknowledged,
+                    bundle.bundle.id.to_key(),
+                    None,
+                )
+                .await
+            {
+                Ok(Some(response)) => {
+                    if acknowledged {
+                        pushed.insert(response.delivery_id);
+                    }
+                    permit.send(response);
+                    metrics::counter!("push_bundles_total").increment(1);
+                }
+                Ok(None) => {
+                    // Collected, dropped or expired since the scan
+                }
+                Err(e) => {
+                    result = Err(e);
+                    break;
+                }
+            }
+        }
+        drop(scan_rx);
+
+        h.await.trace_expect("Task terminated unexpectedly")?;
+        result
+    }
+}
@@ -0,0 +1,167 @@
+use super::*;
+use std::collections::HashMap;
+use tokio::sync::broadcast;
+
+/* A local application may ask to track a bundle it sends.  The dispatcher then remembers
+ * the bundle, asks for reports of its delivery and deletion, and publishes what the status
+ * reports that come back say of it, to the streams the application watches.  Reports are
+ * matched by source and creation timestamp, so a report on any fragment counts for the
+ * bundle it was cut from.  A bundle is forgotten once it is reported delivered or deleted,
+ * or once tracking space runs short and it has been expired for a while.  Watchers that
+ * fall behind miss outcomes, rather than hold up the processing of administrative records */
+
+const OUTCOME_CAPACITY: usize = 256;
+
+// The most bundles tracked at once
+const MAX_TRACKED: usize = 65536;
+
+// How long after a bundle expires reports of it are still expected
+const EXPIRY_GRACE: time::Duration = time::Duration::minutes(10);
+
+#[derive(Debug, Clone)]
+pub struct SendOutcome {
+    // As the send returned it, never of a fragment
+    pub bundle_id: bpv7::BundleId,
+    pub reported_by: bpv7::Eid,
+    pub kind: app_registry::StatusKind,
+    pub reason: bpv7::StatusReportReasonCode,
+    pub timestamp: Option<time::OffsetDateTime>,
+}
+
+pub struct Receipts {
+    // The expiry of each tracked bundle
+    tracked: std::sync::Mutex<HashMap<bpv7::BundleId, time::OffsetDateTime>>,
+    tx: broadcast::Sender<Arc<SendOutcome>>,
+}
+
+impl Default for Receipts {
+    fn default() -> Self {
+        Self {
+            tracked: Default::default(),
+            tx: broadcast::channel(OUTCOME_CAPACITY).0,
+        }
+    }
+}
+
+fn whole_bundle(bundle_id: &bpv7::BundleId) -> bpv7::BundleId {
+    bpv7::BundleId {
+        fragment_info: None,
+        ..bundle_id.clone()
+    }
+}
+
+impl Receipts {
+    // Returns false if there is no room to track the bundle
+    fn track(&self, bundle_id: &bpv7::BundleId, expiry: time::OffsetDateTime) -> bool {
+        let mut tracked = self.tracked.lock().trace_expect("Failed to lock mutex");
+        if tracked.len() >= MAX_TRACKED {
+            let now = clock::now();
+            tracked.retain(|_, expiry| *expiry + EXPIRY_GRACE > now);
+            if tracked.len() >= MAX_TRACKED {
+                return false;
+            }
+        }
+        tracked.insert(whole_bundle(bundle_id), expiry);
+        true
+    }
+
+    // The outcomes a status report brings of a tracked bundle
+    fn outcomes(
+        &self,
+        reported_by: &bpv7::Eid,
+        report: &bpv7::BundleStatusReport,
+    ) -> Vec<SendOutcome> {
+        let bundle_id = whole_bundle(&report.bundle_id);
+        let mut tracked = self.tracked.lock().trace_expect("Failed to lock mutex");
+        if !tracked.contains_key(&bundle_id) {
+            return Vec::new();
+        }
+
+        // Nothing more is expected once the bundle is delivered or deleted
+        if report.delivered.is_some() || report.deleted.is_some() {
+            tracked.remove(&bundle_id);
+        }
+
+        [
+            (app_registry::StatusKind::Received, &report.received),
+            (app_registry::StatusKind::Forwarded, &report.forwarded),
+            (app_registry::StatusKind::Delivered, &report.delivered),
+            (app_registry::StatusKind::Deleted, &report.deleted),
+        ]
+        .into_iter()
+        .filter_map(|(kind, assertion)| {
+            assertion.as_ref().map(|assertion| SendOutcome {
+                bundle_id: bundle_id.clone(),
+                reported_by: reported_by.clone(),
+                kind,
+                reason: report.reason,
+                timestamp: assertion.0.map(|t| t.into()),
+            })
+        })
+        .collect()
+    }
+}
+
+impl Dispatcher {
+    pub fn watch_outcomes(&self) -> broadcast::Receiver<Arc<SendOutcome>> {
+        self.receipts.tx.subscribe()
+    }
+
+    // Track a locally originated bundle, which must have asked for delivery and deletion reports
+    pub(super) fn track_outcome(&self, bundle: &bpv7::Bundle) {
+        let expiry = metadata::Bundle::expiry_of(bundle, None);
+        if !self.receipts.track(&bundle.id, expiry) {
+            warn!("Too many bundles tracked, not tracking {:?}", bundle.id);
+        }
+    }
+
+    pub(super) fn match_receipt(&self, reported_by: &bpv7::Eid, report: &bpv7::BundleStatusReport) {
+        for outcome in self.receipts.outcomes(reported_by, report) {
+            // Sending only fails if no-one is watching
+            _ = self.receipts.tx.send(Arc::new(outcome));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn outcomes() {
+        let receipts = Receipts::default();
+        let bundle_id = bpv7::BundleId {
+            source: "ipn:1.7".parse().unwrap(),
+            timestamp: bpv7::CreationTimestamp::now(),
+            fragment_info: None,
+        };
+        let reported_by: bpv7::Eid = "ipn:2.0".parse().unwrap();
+        assert!(receipts.track(&bundle_id, clock::now() + time::Duration::hours(1)));
+
+        // A report on a fragment counts for the whole bundle
+        let mut report = bpv7::BundleStatusReport {
+            bundle_id: bpv7::BundleId {
+                fragment_info: Some(bpv7::FragmentInfo {
+                    offset: 0,
+                    total_len: 100,
+                }),
+                ..bundle_id.clone()
+            },
+            forwarded: Some(bpv7::StatusAssertion(None)),
+            ..Default::default()
+        };
+        let outcomes = receipts.outcomes(&reported_by, &report);
+        assert_eq!(outcomes.len(), 1);
+        assert_eq!(outcomes[0].bundle_id, bundle_id);
+        assert!(matches!(
+            outcomes[0].kind,
+            app_registry::StatusKind::Forwarded
+        ));
+
+        // Delivery is the end of it
+        report.forwarded = None;
+        report.delivered = Some(bpv7::StatusAssertion(None));
+        assert_eq!(receipts.outcomes(&reported_by, &report).len(), 1);
+        assert!(receipts.outcomes(&reported_by, &report).is_empty());
+    }
+}
@@ -0,0 +1,159 @@
+use super::*;
+use tokio::io::AsyncWriteExt;
+use utils::journal;
+
+/* When 'ingress_journal' names a file, every bundle received from a CLA or the embedding
+ * application is appended to it, exactly as it was received, along with when and from where
+ * it arrived.  The journal can be replayed through a test instance with '--replay', to
+ * reproduce problems seen in the field.  Recording must never hold up ingress, so records
+ * are queued to a writer task, and dropped if the queue is full.  Bundles the BPA creates
+ * itself, or unwraps from BIBE encapsulation, are not recorded */
+
+const QUEUE_CAPACITY: usize = 256;
+
+pub struct Recorder {
+    tx: tokio::sync::mpsc::Sender<journal::Record>,
+}
+
+impl Recorder {
+    pub fn new(
+        config: &::config::Config,
+        task_set: &mut tokio::task::JoinSet<()>,
+        cancel_token: tokio_util::sync::CancellationToken,
+    ) -> Option<Self> {
+        let path = utils::settings::get_with_default::<Option<std::path::PathBuf>, _>(
+            config,
+            "ingress_journal",
+            None,
+        )
+        .trace_expect("Invalid 'ingress_journal' value in configuration")?;
+
+        info!("Recording received bundles to '{}'", path.display());
+        let (tx, rx) = tokio::sync::mpsc::channel(QUEUE_CAPACITY);
+        task_set.spawn(writer_task(path, rx, cancel_token));
+        Some(Self { tx })
+    }
+}
+
+async fn open(path: &std::path::Path) -> Result<tokio::fs::File, Error> {
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await?;
+    if file.metadata().await?.len() == 0 {
+        file.write_all(journal::MAGIC).await?;
+    }
+    Ok(file)
+}
+
+async fn writer_task(
+    path: std::path::PathBuf,
+    mut rx: tokio::sync::mpsc::Receiver<journal::Record>,
+    cancel_token: tokio_util::sync::CancellationToken,
+) {
+    let mut file = open(&path).await.trace_expect(&format!(
+        "Failed to open ingress journal '{}'",
+        path.display()
+    ));
+
+    loop {
+        let record = tokio::select! {
+            record = rx.recv() => record,
+            _ = cancel_token.cancelled() => {
+                // Write what is already queued before stopping
+                rx.close();
+                rx.recv().await
+            }
+        };
+        let Some(record) = record else {
+            break;
+        };
+        if let Err(e) = file.write_all(&record.encode()).await {
+            error!("Failed to write to ingress journal: {e}");
+            metrics::counter!("ingress_journal_dropped_total").increment(1);
+        }
+    }
+
+    if let Err(e) = file.flush().await {
+        error!("Failed to flush ingress journal: {e}");
+    }
+}
+
+impl Dispatcher {
+    // Append a received bundle to the ingress journal, if recording
+    pub async fn record_ingress(&self, cla: Option<u32>, source: &[u8], data: &Bytes) {
+        let Some(recorder) = &self.recorder else {
+            return;
+        };
+        let received_at = clock::now();
+        let cla = match cla {
+            Some(handle) => self.cla_registry.name(handle).await,
+            None => None,
+        };
+        let record = journal::Record {
+            received_at,
+            cla,
+            source: Bytes::copy_from_slice(source),
+            data: data.clone(),
+        };
+        if recorder.tx.try_send(record).is_err() {
+            metrics::counter!("ingress_journal_dropped_total").increment(1);
+        }
+    }
+
+    // Record a bundle that was streamed into the bundle storage as it was received
+    pub async fn record_stored_ingress(&self, cla: u32, source: &[u8], storage_name: &str) {
+        if self.recorder.is_none() {
+            return;
+        }
+        match self.store.load_data(storage_name).await {
+            Ok(Some(data)) => {
+                let data = Bytes::copy_from_slice((*data).as_ref());
+                self.record_ingress(Some(cla), source, &data).await
+            }
+            Ok(None) => {}
+            Err(e) => warn!("Failed to load streamed bundle data {storage_name} to record: {e}"),
+        }
+    }
+
+    // Feed the bundles in an ingress journal back in, at their original pace in virtual time
+    // unless `paced` is false, in which case as fast as they are accepted
+    pub async fn replay(&self, path: std::path::PathBuf, paced: bool) -> Result<(), Error> {
+        let file = std::fs::File::open(&path)?;
+        let mut reader = journal::Reader::new(std::io::BufReader::new(file))?;
+
+        // Read the journal off the runtime, as it may be large
+        let (tx, mut rx) = tokio::sync::mpsc::channel(16);
+        let read = tokio::task::spawn_blocking(move || {
+            while let Some(record) = reader.next_record()? {
+                if tx.blocking_send(record).is_err() {
+                    break;
+                }
+            }
+            Ok::<_, Error>(())
+        });
+
+        let (mut accepted, mut rejected) = (0usize, 0usize);
+        while let Some(record) = rx.recv().await {
+            if paced && !clock::sleep(record.received_at - clock::now(), &self.cancel_token).await {
+                break;
+            }
+            match self.receive_bundle(record.data).await? {
+                None => accepted += 1,
+                Some(rejection) => {
+                    info!(
+                        "Replayed bundle received at {} from {} was rejected: {rejection}",
+                        record.received_at,
+                        record.cla.as_deref().unwrap_or("the application")
+                    );
+                    rejected += 1;
+                }
+            }
+        }
+        drop(rx);
+
+        info!("Replay finished, {accepted} bundles accepted, {rejected} rejected");
+        read.await?
+    }
+}
@@ -12,6 +12,22 @@ impl Dispatcher {
             return Ok(());
         }
 
+        // Protect against report feedback loops
+        if self.too_many_reports() {
+            trace!("Too many status reports in flight, suppressing reception report");
+            metrics::counter!("status_reports_suppressed_total").increment(1);
+            return Ok(());
+        }
+
+        // Reports from far along the path add little, unless we are the end of it
+        if reason == bpv7::StatusReportReasonCode::NoAdditionalInformation
+            && self.too_many_hops(&bundle.bundle)
+        {
+            trace!("Bundle has travelled too far, suppressing reception report");
+            metrics::counter!("status_reports_suppressed_total").increment(1);
+            return Ok(());
+        }
+
         trace!("Reporting bundle reception to {}", &bundle.bundle.report_to);
 
         self.dispatch_status_report(
@@ -34,6 +50,7 @@ impl Dispatcher {
                 },
             )),
             &bundle.bundle.report_to,
+            bundle.bundle.previous_node.as_ref(),
         )
         .await
     }
@@ -68,6 +85,7 @@ impl Dispatcher {
                 },
             )),
             &bundle.bundle.report_to,
+            bundle.bundle.previous_node.as_ref(),
         )
         .await
     }
@@ -100,6 +118,7 @@ impl Dispatcher {
                 },
             )),
             &bundle.bundle.report_to,
+            bundle.bundle.previous_node.as_ref(),
         )
         .await
     }
@@ -134,6 +153,7 @@ impl Dispatcher {
                 },
             )),
             &bundle.bundle.report_to,
+            bundle.bundle.previous_node.as_ref(),
         )
         .await
     }
@@ -143,6 +163,7 @@ impl Dispatcher {
         &self,
         payload: Vec<u8>,
         report_to: &bpv7::Eid,
+        previous_node: Option<&bpv7::Eid>,
     ) -> Result<(), Error> {
         // Check reports are enabled
         if !self.config.status_reports {
@@ -154,26 +175,216 @@ impl Dispatcher {
             return Ok(());
         }
 
+        // If we can't reach report_to, send the report back the way the bundle came
+        let mut return_path = None;
+        if let Some(previous_node) = previous_node {
+            if self.config.status_report_return_path
+                && !self.config.admin_endpoints.is_admin_endpoint(previous_node)
+                && self.is_unroutable(report_to).await
+            {
+                trace!("No route to {report_to}, returning report via {previous_node}");
+                return_path = Some(previous_node.clone());
+            }
+        }
+
+        let bundle = self
+            .store_admin_record(payload, report_to, return_path)
+            .await?;
+        self.reports_in_flight
+            .lock()
+            .trace_expect("Failed to lock mutex")
+            .insert(bundle.bundle.id.clone());
+
+        // Put bundle into channel
+        self.dispatch_bundle(bundle).await
+    }
+
+    fn too_many_hops(&self, bundle: &bpv7::Bundle) -> bool {
+        self.config.report_hop_limit.is_some_and(|limit| {
+            bundle
+                .hop_count
+                .as_ref()
+                .is_some_and(|hop_info| hop_info.count > limit)
+        }) && !self
+            .config
+            .admin_endpoints
+            .is_local_service(&bundle.destination)
+    }
+
+    fn too_many_reports(&self) -> bool {
+        self.config.max_in_flight_reports != 0
+            && self
+                .reports_in_flight
+                .lock()
+                .trace_expect("Failed to lock mutex")
+                .len()
+                >= self.config.max_in_flight_reports
+    }
+
+    // A status report we generated has been forwarded or dropped
+    pub(super) fn report_done(&self, bundle: &bpv7::Bundle) {
+        // Only reports from dispatch_status_report() are counted, not diagnostics or reports
+        // stored before a restart
+        if bundle.flags.is_admin_record {
+            self.reports_in_flight
+                .lock()
+                .trace_expect("Failed to lock mutex")
+                .remove(&bundle.id);
+        }
+    }
+
+    // Use the pinned source if configured and fit for the destination, otherwise derive it
+    // from the destination
+    fn report_source(&self, destination: &bpv7::Eid) -> bpv7::Eid {
+        let derived = self.config.admin_endpoints.get_admin_endpoint(destination);
+        match &self.config.status_report_source {
+            Some(source)
+                if self.config.admin_endpoints.is_admin_endpoint(source)
+                    && std::mem::discriminant(source) == std::mem::discriminant(&derived) =>
+            {
+                source.clone()
+            }
+            Some(source) => {
+                trace!("Pinned status report source {source} does not suit destination {destination}, using {derived}");
+                derived
+            }
+            None => derived,
+        }
+    }
+
+    async fn store_admin_record(
+        &self,
+        payload: Vec<u8>,
+        destination: &bpv7::Eid,
+        return_path: Option<bpv7::Eid>,
+    ) -> Result<metadata::Bundle, Error> {
+        let source = self.report_source(destination);
+
         // Build the bundle
         let (bundle, data) = bpv7::Builder::new()
             .flags(bpv7::BundleFlags {
                 is_admin_record: true,
                 ..Default::default()
             })
-            .source(self.config.admin_endpoints.get_admin_endpoint(report_to))
-            .destination(report_to.clone())
+            .source(source)
+            .destination(destination.clone())
             .add_payload_block(payload)
             .build();
 
         // Store to store
         let metadata = self
             .store
-            .store(&bundle, &data, metadata::BundleStatus::default(), None)
+            .store(
+                &bundle,
+                &data,
+                metadata::BundleStatus::default(),
+                None,
+                return_path,
+            )
             .await?
             .trace_expect("Duplicate bundle generated by builder!");
 
-        // Put bundle into channel
-        self.dispatch_bundle(metadata::Bundle { metadata, bundle })
-            .await
+        Ok(metadata::Bundle { metadata, bundle })
+    }
+
+    #[instrument(skip(self, original, rewritten, crc_report))]
+    pub(super) async fn report_rewritten(
+        &self,
+        bundle: &bpv7::Bundle,
+        original: &[u8],
+        rewritten: &[u8],
+        crc_report: &bpv7::CrcReport,
+    ) -> Result<(), Error> {
+        // Check diagnostics are enabled
+        let Some(interval) = self.config.rewrite_diagnostic_interval else {
+            return Ok(());
+        };
+
+        // We can only tell the previous node if we know who it is
+        let Some(previous_node) = &bundle.previous_node else {
+            return Ok(());
+        };
+        if self.config.admin_endpoints.is_admin_endpoint(previous_node) {
+            return Ok(());
+        }
+
+        // Rate limit per previous node
+        {
+            let now = clock::now();
+            let mut sent = self.rewrite_diagnostics_sent.lock().await;
+
+            // Forget previous nodes that are no longer rate limited, as they are unauthenticated
+            sent.retain(|_, last| now < *last + interval);
+            if sent
+                .get(previous_node)
+                .is_some_and(|last| now < *last + interval)
+            {
+                trace!("Suppressing diagnostic to {previous_node}, rate limit");
+                return Ok(());
+            }
+            sent.insert(previous_node.clone(), now);
+        }
+
+        // Describe what we can of the deviation
+        let mut message = format!(
+            "Bundle was not in canonical form and has been re-encoded, {} bytes received, {} bytes after re-encoding",
+            original.len(),
+            rewritten.len()
+        );
+        if original
+            .strip_prefix(&[0xD9, 0xD9, 0xF7])
+            .unwrap_or(original)
+            .first()
+            != Some(&0x9F)
+        {
+            message.push_str(", the bundle is not an indefinite-length array");
+        }
+        if !crc_report.corrected.is_empty() {
+            message.push_str(&format!(
+                ", the CRCs of blocks {:?} were recalculated",
+                crc_report.corrected
+            ));
+        }
+        if !crc_report.absent.is_empty() {
+            message.push_str(&format!(", blocks {:?} have no CRC", crc_report.absent));
+        }
+
+        trace!("Sending diagnostic to {previous_node}: {message}");
+
+        let bundle = self
+            .store_admin_record(
+                cbor::encode::emit(&bpv7::AdministrativeRecord::Diagnostic(
+                    bpv7::DiagnosticRecord {
+                        bundle_id: bundle.id.clone(),
+                        message,
+                    },
+                )),
+                previous_node,
+                None,
+            )
+            .await?;
+
+        self.dispatch_bundle(bundle).await
+    }
+
+    async fn is_unroutable(&self, to: &bpv7::Eid) -> bool {
+        match &self.fib {
+            Some(fib) => matches!(
+                fib.find(to).await,
+                Ok(fib::ForwardAction {
+                    clas,
+                    until: None,
+                    resolve,
+                }) if clas.is_empty() && resolve.is_empty()
+            ),
+            None => false,
+        }
+    }
+
+    pub(super) fn report_return_path(&self, bundle: &metadata::Bundle) -> Option<bpv7::Eid> {
+        if !self.config.status_report_return_path {
+            return None;
+        }
+        bundle.metadata.return_path.clone()
     }
 }
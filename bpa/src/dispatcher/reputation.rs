@@ -0,0 +1,343 @@
+use super::*;
+use std::collections::HashMap;
+
+/* Peers are scored by what arrives from them, keyed by the Previous Node of the bundles
+ * received.  The counts of bundles received, and of those that were malformed, had expired
+ * on arrival or were duplicates, decay with the configured half-life, so a peer recovers
+ * once it behaves.  Peers scoring below the threshold, or marked distrusted by an
+ * administrator, are policed: their bundles are rate limited, and their invalid bundles
+ * are refused outright rather than stored as tombstones and reported on */
+
+// Forget peers that have been quiet for long enough, once there are this many
+const MAX_PEERS: usize = 4096;
+const FORGET_BELOW: f64 = 0.01;
+
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(default)]
+struct Config {
+    // In seconds
+    half_life: u64,
+    // Bundles received before a peer is scored
+    min_bundles: u64,
+    // Peers scoring below this, from 0 to 1, are policed
+    threshold: f64,
+    // Bundles per second accepted from a policed peer, 0 for no limit
+    rate_limit: f64,
+    drop_invalid: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            half_life: 600,
+            min_bundles: 20,
+            threshold: 0.5,
+            rate_limit: 10.0,
+            drop_invalid: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trust {
+    Trusted,
+    Distrusted,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Event {
+    Expired,
+    Duplicate,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Verdict {
+    Accept,
+    RateLimited,
+    DropInvalid,
+}
+
+pub struct PeerSummary {
+    pub peer: bpv7::Eid,
+    pub received: f64,
+    pub malformed: f64,
+    pub expired: f64,
+    pub duplicate: f64,
+    pub score: Option<f64>,
+    pub trust: Option<Trust>,
+    pub policed: bool,
+}
+
+struct Peer {
+    received: f64,
+    malformed: f64,
+    expired: f64,
+    duplicate: f64,
+    tokens: f64,
+    updated: time::OffsetDateTime,
+    trust: Option<Trust>,
+}
+
+impl Peer {
+    fn new(config: &Config, now: time::OffsetDateTime) -> Self {
+        Self {
+            received: 0.0,
+            malformed: 0.0,
+            expired: 0.0,
+            duplicate: 0.0,
+            tokens: config.rate_limit.max(1.0),
+            updated: now,
+            trust: None,
+        }
+    }
+
+    // Decay the counts, and refill the rate limit, up to `now`
+    fn advance(&mut self, config: &Config, now: time::OffsetDateTime) {
+        let elapsed = (now - self.updated).as_seconds_f64();
+        if elapsed <= 0.0 {
+            return;
+        }
+        let decay = 0.5f64.powf(elapsed / config.half_life.max(1) as f64);
+        self.received *= decay;
+        self.malformed *= decay;
+        self.expired *= decay;
+        self.duplicate *= decay;
+        self.tokens = (self.tokens + elapsed * config.rate_limit).min(config.rate_limit.max(1.0));
+        self.updated = now;
+    }
+
+    fn score(&self, config: &Config) -> Option<f64> {
+        if self.received < config.min_bundles as f64 {
+            return None;
+        }
+        let bad = self.malformed + self.expired + self.duplicate;
+        Some((1.0 - bad / self.received).clamp(0.0, 1.0))
+    }
+
+    fn is_policed(&self, config: &Config) -> bool {
+        match self.trust {
+            Some(Trust::Trusted) => false,
+            Some(Trust::Distrusted) => true,
+            None => self
+                .score(config)
+                .is_some_and(|score| score < config.threshold),
+        }
+    }
+}
+
+pub struct Reputation {
+    config: Config,
+    peers: std::sync::Mutex<HashMap<bpv7::Eid, Peer>>,
+}
+
+impl Reputation {
+    pub fn new(config: &::config::Config) -> Option<Self> {
+        let config =
+            utils::settings::get_with_default::<Option<Config>, _>(config, "reputation", None)
+                .trace_expect("Invalid 'reputation' section in configuration")?;
+        info!(
+            "Policing peers scoring below {} with a half-life of {}s",
+            config.threshold, config.half_life
+        );
+        Some(Self::with_config(config))
+    }
+
+    fn with_config(config: Config) -> Self {
+        Self {
+            config,
+            peers: Default::default(),
+        }
+    }
+
+    // Score a bundle arriving from `peer`, and decide whether to accept it
+    pub fn arrival(&self, peer: &bpv7::Eid, invalid: bool, now: time::OffsetDateTime) -> Verdict {
+        let mut peers = self.peers.lock().trace_expect("Failed to lock mutex");
+        if peers.len() >= MAX_PEERS && !peers.contains_key(peer) {
+            peers.retain(|_, p| {
+                p.advance(&self.config, now);
+                p.trust.is_some() || p.received >= FORGET_BELOW
+            });
+        }
+
+        let p = peers
+            .entry(peer.clone())
+            .or_insert_with(|| Peer::new(&self.config, now));
+        p.advance(&self.config, now);
+        p.received += 1.0;
+        if invalid {
+            p.malformed += 1.0;
+        }
+
+        if !p.is_policed(&self.config) {
+            return Verdict::Accept;
+        }
+        if self.config.rate_limit > 0.0 {
+            if p.tokens < 1.0 {
+                return Verdict::RateLimited;
+            }
+            p.tokens -= 1.0;
+        }
+        if invalid && self.config.drop_invalid {
+            Verdict::DropInvalid
+        } else {
+            Verdict::Accept
+        }
+    }
+
+    pub fn record(&self, peer: &bpv7::Eid, event: Event, now: time::OffsetDateTime) {
+        let mut peers = self.peers.lock().trace_expect("Failed to lock mutex");
+        let Some(p) = peers.get_mut(peer) else {
+            return;
+        };
+        p.advance(&self.config, now);
+        match event {
+            Event::Expired => p.expired += 1.0,
+            Event::Duplicate => p.duplicate += 1.0,
+        }
+    }
+
+    pub fn set_trust(&self, peer: bpv7::Eid, trust: Option<Trust>, now: time::OffsetDateTime) {
+        let mut peers = self.peers.lock().trace_expect("Failed to lock mutex");
+        peers
+            .entry(peer)
+            .or_insert_with(|| Peer::new(&self.config, now))
+            .trust = trust;
+    }
+
+    pub fn summary(&self, now: time::OffsetDateTime) -> Vec<PeerSummary> {
+        let mut peers = self.peers.lock().trace_expect("Failed to lock mutex");
+        peers
+            .iter_mut()
+            .map(|(peer, p)| {
+                p.advance(&self.config, now);
+                PeerSummary {
+                    peer: peer.clone(),
+                    received: p.received,
+                    malformed: p.malformed,
+                    expired: p.expired,
+                    duplicate: p.duplicate,
+                    score: p.score(&self.config),
+                    trust: p.trust,
+                    policed: p.is_policed(&self.config),
+                }
+            })
+            .collect()
+    }
+}
+
+impl Dispatcher {
+    // Returns a rejection if the peer the bundle arrived from is being policed
+    pub(super) fn police_peer(
+        &self,
+        previous_node: Option<&bpv7::Eid>,
+        invalid: bool,
+    ) -> Option<Rejection> {
+        let (Some(reputation), Some(peer)) = (&self.reputation, previous_node) else {
+            return None;
+        };
+        match reputation.arrival(peer, invalid, clock::now()) {
+            Verdict::Accept => None,
+            Verdict::RateLimited => {
+                trace!("Peer {peer} is being policed, and is over its rate limit");
+                metrics::counter!("bundles_policed_total", "action" => "rate_limited").increment(1);
+                Some(Rejection::PolicyDenied(format!(
+                    "Peer {peer} is rate limited"
+                )))
+            }
+            Verdict::DropInvalid => {
+                trace!("Peer {peer} is being policed, dropping invalid bundle");
+                metrics::counter!("bundles_policed_total", "action" => "dropped_invalid")
+                    .increment(1);
+                Some(Rejection::Unintelligible(format!(
+                    "Invalid bundle from policed peer {peer}"
+                )))
+            }
+        }
+    }
+
+    pub(super) fn note_peer_event(&self, previous_node: Option<&bpv7::Eid>, event: Event) {
+        if let (Some(reputation), Some(peer)) = (&self.reputation, previous_node) {
+            reputation.record(peer, event, clock::now());
+        }
+    }
+
+    // None if peer reputation is not enabled
+    pub fn peer_reputation(&self) -> Option<Vec<PeerSummary>> {
+        self.reputation
+            .as_ref()
+            .map(|reputation| reputation.summary(clock::now()))
+    }
+
+    // Returns false if peer reputation is not enabled
+    pub fn set_peer_trust(&self, peer: bpv7::Eid, trust: Option<Trust>) -> bool {
+        let Some(reputation) = &self.reputation else {
+            return false;
+        };
+        info!("Trust of peer {peer} overridden: {trust:?}");
+        reputation.set_trust(peer, trust, clock::now());
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reputation() -> Reputation {
+        Reputation::with_config(Config {
+            half_life: 10,
+            min_bundles: 4,
+            threshold: 0.5,
+            rate_limit: 1.0,
+            drop_invalid: true,
+        })
+    }
+
+    #[test]
+    fn policing() {
+        let reputation = reputation();
+        let peer: bpv7::Eid = "ipn:1.0".parse().unwrap();
+        let now = time::OffsetDateTime::now_utc();
+
+        // Not scored until enough bundles have arrived
+        for _ in 0..3 {
+            assert_eq!(reputation.arrival(&peer, true, now), Verdict::Accept);
+        }
+
+        // Now mostly malformed
+        assert_eq!(reputation.arrival(&peer, true, now), Verdict::DropInvalid);
+        assert_eq!(reputation.arrival(&peer, false, now), Verdict::RateLimited);
+        let now = now + time::Duration::seconds(1);
+        assert_eq!(reputation.arrival(&peer, false, now), Verdict::Accept);
+
+        // Trusted peers are never policed
+        reputation.set_trust(peer.clone(), Some(Trust::Trusted), now);
+        assert_eq!(reputation.arrival(&peer, true, now), Verdict::Accept);
+        reputation.set_trust(peer.clone(), None, now);
+
+        // Recovers as the history decays
+        let mut now = now + time::Duration::seconds(100);
+        for _ in 0..20 {
+            now += time::Duration::seconds(1);
+            assert_eq!(reputation.arrival(&peer, false, now), Verdict::Accept);
+        }
+        let summary = reputation.summary(now);
+        assert!(summary[0].score.is_some_and(|score| score > 0.9));
+        assert!(!summary[0].policed);
+    }
+
+    #[test]
+    fn distrusted() {
+        let reputation = reputation();
+        let peer: bpv7::Eid = "ipn:2.0".parse().unwrap();
+        let now = time::OffsetDateTime::now_utc();
+
+        reputation.set_trust(peer.clone(), Some(Trust::Distrusted), now);
+        assert_eq!(reputation.arrival(&peer, false, now), Verdict::Accept);
+        assert_eq!(reputation.arrival(&peer, false, now), Verdict::RateLimited);
+
+        // Events from unknown peers are ignored
+        reputation.record(&"ipn:3.0".parse().unwrap(), Event::Duplicate, now);
+        assert_eq!(reputation.summary(now).len(), 1);
+    }
+}
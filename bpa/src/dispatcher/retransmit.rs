@@ -0,0 +1,130 @@
+use super::*;
+use std::collections::HashMap;
+
+/* Bundles sent by the local services matching 'retransmit_sources' are kept in a
+ * retransmission buffer once they have been forwarded, rather than dropped, until a status
+ * report of their delivery arrives.  If none arrives within 'retransmit_interval', the
+ * bundle is forwarded again, up to 'max_retransmissions' times, and it is never kept beyond
+ * its lifetime.  Such bundles always request delivery reports, sent to our administrative
+ * endpoint, whatever the application asked for.  Buffered bundles wait in the store like
+ * any other, but the count of retransmissions is only held in memory, so starts afresh
+ * after a restart */
+
+#[derive(Default)]
+pub struct Retransmissions {
+    counts: std::sync::Mutex<HashMap<bpv7::BundleId, u32>>,
+}
+
+impl Retransmissions {
+    // Returns the number of the next retransmission, or None if there are to be no more
+    fn next(&self, bundle_id: &bpv7::BundleId, max: u32) -> Option<u32> {
+        let mut counts = self.counts.lock().trace_expect("Failed to lock mutex");
+        let count = counts.entry(bundle_id.clone()).or_default();
+        if *count >= max {
+            counts.remove(bundle_id);
+            return None;
+        }
+        *count += 1;
+        Some(*count)
+    }
+
+    fn contains(&self, bundle_id: &bpv7::BundleId) -> bool {
+        self.counts
+            .lock()
+            .trace_expect("Failed to lock mutex")
+            .contains_key(bundle_id)
+    }
+
+    fn forget(&self, bundle_id: &bpv7::BundleId) {
+        self.counts
+            .lock()
+            .trace_expect("Failed to lock mutex")
+            .remove(bundle_id);
+    }
+}
+
+impl Dispatcher {
+    pub(super) fn is_retransmit_source(&self, source: &bpv7::Eid) -> bool {
+        !self.config.retransmit_sources.find(source).is_empty()
+    }
+
+    // Decide what happens to a bundle once it has been forwarded
+    pub(super) async fn retain_forwarded(
+        &self,
+        bundle: &mut metadata::Bundle,
+    ) -> Result<DispatchResult, Error> {
+        if !self.is_retransmit_source(&bundle.bundle.id.source) {
+            return Ok(DispatchResult::Drop(None));
+        }
+
+        let until = clock::now() + self.config.retransmit_interval;
+        if until >= bundle.expiry() {
+            trace!("Bundle would expire before it could be retransmitted");
+            return Ok(DispatchResult::Drop(None));
+        }
+
+        if self.retransmissions.contains(&bundle.bundle.id) {
+            metrics::counter!("bundles_retransmitted_total").increment(1);
+        }
+
+        let Some(count) = self
+            .retransmissions
+            .next(&bundle.bundle.id, self.config.max_retransmissions)
+        else {
+            trace!("Bundle has been retransmitted as often as allowed");
+            return Ok(DispatchResult::Drop(None));
+        };
+        trace!("Retransmission {count} of bundle at {until}, unless its delivery is reported");
+        self.store
+            .set_status(bundle, metadata::BundleStatus::Waiting(until))
+            .await
+            .map(|_| DispatchResult::Done)
+    }
+
+    // A bundle we sent has been delivered, so there is no need to keep it any longer
+    pub(super) async fn delivery_reported(&self, bundle_id: &bpv7::BundleId) -> Result<(), Error> {
+        if !self.is_retransmit_source(&bundle_id.source) {
+            return Ok(());
+        }
+        let Some(bundle) = self.store.load(bundle_id).await? else {
+            return Ok(());
+        };
+        if let metadata::BundleStatus::Tombstone(_) = bundle.metadata.status {
+            return Ok(());
+        }
+
+        trace!("Delivery reported, releasing bundle from the retransmission buffer");
+        self.drop_bundle(bundle, None).await
+    }
+
+    pub(super) fn forget_retransmissions(&self, bundle_id: &bpv7::BundleId) {
+        self.retransmissions.forget(bundle_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retransmission_count() {
+        let retransmissions = Retransmissions::default();
+        let bundle_id = bpv7::BundleId {
+            source: "ipn:1.1".parse().unwrap(),
+            timestamp: bpv7::CreationTimestamp {
+                creation_time: Some(bpv7::DtnTime::new(1000)),
+                sequence_number: 0,
+            },
+            ..Default::default()
+        };
+
+        assert_eq!(retransmissions.next(&bundle_id, 2), Some(1));
+        assert_eq!(retransmissions.next(&bundle_id, 2), Some(2));
+        assert_eq!(retransmissions.next(&bundle_id, 2), None);
+
+        // Counting starts afresh once the bundle is forgotten
+        assert_eq!(retransmissions.next(&bundle_id, 2), Some(1));
+        retransmissions.forget(&bundle_id);
+        assert_eq!(retransmissions.next(&bundle_id, 2), Some(1));
+    }
+}
@@ -0,0 +1,227 @@
+use super::*;
+use std::collections::{BTreeMap, HashMap};
+
+/* Bundles from the same source to an application that asked for ordered delivery are
+ * released for collection in creation timestamp order.  Each arrival is held until its
+ * immediate predecessor has been released, which is only recognisable when the source
+ * numbers its bundles consecutively, or until it has been held for the reorder timeout.
+ * The first bundle from a source has nothing to wait for, so is released straight away.
+ * Bundles arriving after a later bundle from the same source has been released are
+ * released straight away too, as it is too late to reorder them */
+
+// How long to remember the last bundle released for an idle source and destination
+const IDLE_TIMEOUT: time::Duration = time::Duration::hours(1);
+
+// Creation time in milliseconds, then sequence number
+type Key = (u64, u64);
+
+fn key(bundle: &metadata::Bundle) -> Key {
+    let timestamp = &bundle.bundle.id.timestamp;
+    (
+        timestamp.creation_time.map_or(0, |t| t.millisecs()),
+        timestamp.sequence_number,
+    )
+}
+
+struct Buffer {
+    last: Option<Key>,
+    last_released: time::OffsetDateTime,
+    held: BTreeMap<Key, (metadata::Bundle, time::OffsetDateTime)>,
+}
+
+impl Buffer {
+    fn new(now: time::OffsetDateTime) -> Self {
+        Self {
+            last: None,
+            last_released: now,
+            held: BTreeMap::new(),
+        }
+    }
+
+    fn is_next(&self, key: &Key) -> bool {
+        self.last
+            .is_some_and(|last| key.0 == last.0 && Some(key.1) == last.1.checked_add(1))
+    }
+
+    fn release(&mut self, now: time::OffsetDateTime, released: &mut Vec<metadata::Bundle>) {
+        // Everything held ahead of a bundle that has timed out goes with it
+        let due = self
+            .held
+            .iter()
+            .filter(|(_, (_, deadline))| *deadline <= now)
+            .map(|(key, _)| *key)
+            .next_back();
+
+        while let Some(key) = self.held.first_key_value().map(|(key, _)| *key) {
+            if !(due.is_some_and(|due| key <= due) || self.is_next(&key)) {
+                break;
+            }
+            let Some((_, (bundle, _))) = self.held.pop_first() else {
+                break;
+            };
+            self.last = Some(key);
+            self.last_released = now;
+            released.push(bundle);
+        }
+    }
+}
+
+pub struct Sequencer {
+    timeout: time::Duration,
+    buffers: std::sync::Mutex<HashMap<(bpv7::Eid, bpv7::Eid), Buffer>>,
+}
+
+impl Sequencer {
+    pub fn new(timeout: time::Duration) -> Self {
+        Self {
+            timeout,
+            buffers: Default::default(),
+        }
+    }
+
+    // Returns the bundles that can now be released, in order
+    pub fn hold(
+        &self,
+        bundle: metadata::Bundle,
+        now: time::OffsetDateTime,
+    ) -> Vec<metadata::Bundle> {
+        let key = key(&bundle);
+        let mut buffers = self.buffers.lock().trace_expect("Failed to lock mutex");
+        let buffer = buffers
+            .entry((
+                bundle.bundle.id.source.clone(),
+                bundle.bundle.destination.clone(),
+            ))
+            .or_insert_with(|| Buffer::new(now));
+
+        if buffer.last.is_some_and(|last| key <= last) || buffer.held.contains_key(&key) {
+            // Too late to reorder
+            return vec![bundle];
+        }
+
+        if buffer.last.is_none() && buffer.held.is_empty() {
+            // Nothing to order it against
+            buffer.last = Some(key);
+            buffer.last_released = now;
+            return vec![bundle];
+        }
+
+        buffer.held.insert(key, (bundle, now + self.timeout));
+
+        let mut released = Vec::new();
+        buffer.release(now, &mut released);
+        released
+    }
+
+    // Returns the bundles held for longer than the timeout, in order
+    pub fn expire(&self, now: time::OffsetDateTime) -> Vec<metadata::Bundle> {
+        let mut released = Vec::new();
+        let mut buffers = self.buffers.lock().trace_expect("Failed to lock mutex");
+        buffers.retain(|_, buffer| {
+            buffer.release(now, &mut released);
+            !buffer.held.is_empty() || now - buffer.last_released < IDLE_TIMEOUT
+        });
+        released
+    }
+}
+
+impl Dispatcher {
+    // Hold a bundle for an application that wants ordered delivery
+    pub(super) async fn sequence_bundle(&self, bundle: metadata::Bundle) -> Result<(), Error> {
+        for bundle in self.sequencer.hold(bundle, clock::now()) {
+            self.release_sequenced(bundle).await?;
+        }
+        Ok(())
+    }
+
+    async fn release_sequenced(&self, mut bundle: metadata::Bundle) -> Result<(), Error> {
+        trace!("Bundle is ready for ordered local delivery");
+        self.store
+            .set_status(&mut bundle, metadata::BundleStatus::CollectionPending)
+            .await?;
+
+        // Notify here, rather than via the dispatch queue, to keep the notifications in order
+        self.push_notify(&bundle.bundle.destination);
+        for endpoint in self
+            .app_registry
+            .find_all_by_eid(&bundle.bundle.destination)
+            .await
+        {
+            endpoint.collection_notify(&bundle.bundle.id).await;
+        }
+        Ok(())
+    }
+}
+
+pub(super) async fn sequence_task(dispatcher: Arc<Dispatcher>) {
+    let period: std::time::Duration = (dispatcher.config.ordered_delivery_timeout / 2i32)
+        .try_into()
+        .unwrap_or(std::time::Duration::from_millis(100));
+    let mut interval = tokio::time::interval(period.max(std::time::Duration::from_millis(10)));
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {
+                for bundle in dispatcher.sequencer.expire(clock::now()) {
+                    if let Err(e) = dispatcher.release_sequenced(bundle).await {
+                        warn!("Failed to release held bundle: {e}");
+                    }
+                }
+            },
+            _ = dispatcher.cancel_token.cancelled() => break
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bundle(seq: u64) -> metadata::Bundle {
+        metadata::Bundle {
+            metadata: Default::default(),
+            bundle: bpv7::Bundle {
+                id: bpv7::BundleId {
+                    source: "ipn:1.1".parse().unwrap(),
+                    timestamp: bpv7::CreationTimestamp {
+                        creation_time: Some(bpv7::DtnTime::new(1000)),
+                        sequence_number: seq,
+                    },
+                    ..Default::default()
+                },
+                destination: "ipn:2.1".parse().unwrap(),
+                ..Default::default()
+            },
+        }
+    }
+
+    fn seqs(bundles: Vec<metadata::Bundle>) -> Vec<u64> {
+        bundles
+            .iter()
+            .map(|b| b.bundle.id.timestamp.sequence_number)
+            .collect()
+    }
+
+    #[test]
+    fn test() {
+        let sequencer = Sequencer::new(time::Duration::seconds(1));
+        let now = time::OffsetDateTime::now_utc();
+
+        // The first bundle from the source is released straight away
+        assert_eq!(seqs(sequencer.hold(bundle(1), now)), vec![1]);
+
+        // A gap, so hold until the timeout
+        assert!(sequencer.hold(bundle(3), now).is_empty());
+        assert!(sequencer.expire(now).is_empty());
+        let now = now + time::Duration::seconds(1);
+        assert_eq!(seqs(sequencer.expire(now)), vec![3]);
+
+        // Successors are released as soon as their predecessor is
+        assert!(sequencer.hold(bundle(5), now).is_empty());
+        assert_eq!(seqs(sequencer.hold(bundle(4), now)), vec![4, 5]);
+
+        // Too late to reorder
+        assert_eq!(seqs(sequencer.hold(bundle(2), now)), vec![2]);
+    }
+}
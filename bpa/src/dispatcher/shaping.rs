@@ -0,0 +1,304 @@
+use super::*;
+
+/* Outbound traffic can be restricted to a calendar of transmission windows, e.g. to only
+ * transmit to a ground station while it is in view.  Each window applies either to the
+ * destinations matching a set of EID patterns, or to the CLA registered under a name, and
+ * may recur with a fixed period.  A destination or CLA with windows may only be used while
+ * at least one of its windows is open: bundles for a destination whose windows are all
+ * closed are parked as Waiting until the next opens, and CLAs whose windows are all closed
+ * are passed over as if congested */
+
+#[derive(Debug, serde::Deserialize)]
+struct WindowSetting {
+    name: String,
+    #[serde(default)]
+    destinations: Vec<String>,
+    cla: Option<String>,
+    // RFC 3339
+    start: String,
+    // In seconds
+    duration: u64,
+    // Seconds between the starts of the window, 0 for a window that does not recur
+    #[serde(default)]
+    period: u64,
+    // The nominal rate of the link in bytes per second, for utilisation metrics
+    #[serde(default)]
+    rate: u64,
+}
+
+#[derive(Debug, Default, serde::Deserialize)]
+struct Config {
+    #[serde(default)]
+    windows: Vec<WindowSetting>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Gate {
+    // The windows that are open, empty if there are no windows at all
+    Open(Vec<usize>),
+    // When the next window opens, if one ever does
+    Closed(Option<time::OffsetDateTime>),
+}
+
+struct Window {
+    name: String,
+    start: time::OffsetDateTime,
+    duration: time::Duration,
+    period: Option<time::Duration>,
+    rate: Option<u64>,
+    // The start of the latest occurrence sent in, and the bytes sent in it
+    sent: std::sync::Mutex<(time::OffsetDateTime, u64)>,
+}
+
+impl Window {
+    // The start of the occurrence of the window open at `now`, or else the next
+    fn occurrence(&self, now: time::OffsetDateTime) -> Option<time::OffsetDateTime> {
+        if now < self.start {
+            return Some(self.start);
+        }
+        let Some(period) = self.period else {
+            return (now < self.start + self.duration).then_some(self.start);
+        };
+        let n = (now - self.start).whole_seconds() / period.whole_seconds();
+        let start = self.start + time::Duration::seconds(period.whole_seconds().saturating_mul(n));
+        if now < start + self.duration {
+            Some(start)
+        } else {
+            Some(start + period)
+        }
+    }
+
+    fn is_open(&self, now: time::OffsetDateTime) -> bool {
+        self.occurrence(now).is_some_and(|start| start <= now)
+    }
+
+    fn note_sent(&self, bytes: u64, now: time::OffsetDateTime) {
+        metrics::counter!("shaping_bundles_sent_total", "window" => self.name.clone()).increment(1);
+        metrics::counter!("shaping_bytes_sent_total", "window" => self.name.clone())
+            .increment(bytes);
+
+        let Some(start) = self.occurrence(now) else {
+            return;
+        };
+        let mut sent = self.sent.lock().trace_expect("Failed to lock mutex");
+        if sent.0 != start {
+            *sent = (start, 0);
+        }
+        sent.1 = sent.1.saturating_add(bytes);
+
+        // The proportion of the capacity of the current occurrence used so far
+        if let Some(rate) = self.rate {
+            let capacity = rate as f64 * self.duration.as_seconds_f64();
+            metrics::gauge!("shaping_window_utilisation", "window" => self.name.clone())
+                .set(sent.1 as f64 / capacity);
+        }
+    }
+}
+
+pub struct Calendar {
+    windows: Vec<Window>,
+    destinations: bpv7::EidPatternMap<usize, usize>,
+    clas: Vec<(String, usize)>,
+}
+
+impl Calendar {
+    pub fn new(config: &::config::Config) -> Option<Self> {
+        let config =
+            utils::settings::get_with_default::<Option<Config>, _>(config, "shaping", None)
+                .trace_expect("Invalid 'shaping' section in configuration")?;
+        if config.windows.is_empty() {
+            return None;
+        }
+
+        let mut calendar = Self {
+            windows: Vec::new(),
+            destinations: bpv7::EidPatternMap::new(),
+            clas: Vec::new(),
+        };
+        for (idx, window) in config.windows.into_iter().enumerate() {
+            if window.destinations.is_empty() == window.cla.is_none() {
+                error!(
+                    "Transmission window '{}' must have either 'destinations' or 'cla'",
+                    window.name
+                );
+                panic!(
+                    "Transmission window '{}' must have either 'destinations' or 'cla'",
+                    window.name
+                );
+            }
+            if window.duration == 0 || (window.period != 0 && window.period < window.duration) {
+                error!(
+                    "Transmission window '{}' has an invalid duration or period",
+                    window.name
+                );
+                panic!(
+                    "Transmission window '{}' has an invalid duration or period",
+                    window.name
+                );
+            }
+
+            let start = time::OffsetDateTime::parse(
+                &window.start,
+                &time::format_description::well_known::Rfc3339,
+            )
+            .trace_expect(&format!(
+                "Invalid start of transmission window '{}'",
+                window.name
+            ));
+
+            for s in &window.destinations {
+                let pattern = s.parse::<bpv7::EidPattern>().trace_expect(&format!(
+                    "Invalid EID pattern '{s}' in transmission window '{}'",
+                    window.name
+                ));
+                calendar.destinations.insert(&pattern, idx, idx);
+            }
+            if let Some(cla) = window.cla {
+                calendar.clas.push((cla, idx));
+            }
+
+            info!("Transmission window '{}' opens at {start}", window.name);
+            calendar.windows.push(Window {
+                name: window.name,
+                start,
+                duration: time::Duration::seconds(window.duration.min(i64::MAX as u64) as i64),
+                period: match window.period {
+                    0 => None,
+                    secs => Some(time::Duration::seconds(secs.min(i64::MAX as u64) as i64)),
+                },
+                rate: match window.rate {
+                    0 => None,
+                    rate => Some(rate),
+                },
+                sent: std::sync::Mutex::new((start, 0)),
+            });
+        }
+        Some(calendar)
+    }
+
+    fn gate(&self, windows: &[usize], now: time::OffsetDateTime) -> Gate {
+        if windows.is_empty() {
+            return Gate::Open(Vec::new());
+        }
+        let open = windows
+            .iter()
+            .copied()
+            .filter(|idx| self.windows[*idx].is_open(now))
+            .collect::<Vec<_>>();
+        if !open.is_empty() {
+            return Gate::Open(open);
+        }
+        Gate::Closed(
+            windows
+                .iter()
+                .filter_map(|idx| self.windows[*idx].occurrence(now))
+                .min(),
+        )
+    }
+
+    pub fn destination(&self, destination: &bpv7::Eid, now: time::OffsetDateTime) -> Gate {
+        let windows = self
+            .destinations
+            .find(destination)
+            .into_iter()
+            .copied()
+            .collect::<Vec<_>>();
+        self.gate(&windows, now)
+    }
+
+    pub fn note_sent(&self, windows: &[usize], bytes: u64, now: time::OffsetDateTime) {
+        for idx in windows {
+            self.windows[*idx].note_sent(bytes, now);
+        }
+    }
+}
+
+impl Dispatcher {
+    // Whether `destination` may be transmitted to now, or else when it next may be
+    pub(super) fn destination_window(&self, destination: &bpv7::Eid) -> Gate {
+        match &self.calendar {
+            Some(calendar) => calendar.destination(destination, clock::now()),
+            None => Gate::Open(Vec::new()),
+        }
+    }
+
+    // Whether the CLA may be used now, or else when it next may be
+    pub(super) async fn cla_window(&self, handle: u32) -> Gate {
+        let Some(calendar) = &self.calendar else {
+            return Gate::Open(Vec::new());
+        };
+        let mut windows = Vec::new();
+        for (name, idx) in &calendar.clas {
+            if self.cla_registry.find_by_name(name).await == Some(handle) {
+                windows.push(*idx);
+            }
+        }
+        calendar.gate(&windows, clock::now())
+    }
+
+    pub(super) fn note_window_deferral(&self, destination: &bpv7::Eid) {
+        trace!("No transmission window towards {destination} is open");
+        metrics::counter!("shaping_bundles_deferred_total").increment(1);
+    }
+
+    pub(super) fn note_window_sent(&self, windows: &[usize], bytes: usize) {
+        if let Some(calendar) = &self.calendar {
+            calendar.note_sent(windows, bytes as u64, clock::now());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn window(start: time::OffsetDateTime, period: Option<i64>) -> Window {
+        Window {
+            name: "test".to_string(),
+            start,
+            duration: time::Duration::seconds(10),
+            period: period.map(time::Duration::seconds),
+            rate: None,
+            sent: std::sync::Mutex::new((start, 0)),
+        }
+    }
+
+    #[test]
+    fn occurrence() {
+        let start = time::OffsetDateTime::now_utc();
+        let s = time::Duration::seconds;
+
+        let once = window(start, None);
+        assert_eq!(once.occurrence(start - s(5)), Some(start));
+        assert!(!once.is_open(start - s(5)));
+        assert!(once.is_open(start + s(5)));
+        assert_eq!(once.occurrence(start + s(10)), None);
+
+        let recurring = window(start, Some(60));
+        assert!(recurring.is_open(start + s(125)));
+        assert!(!recurring.is_open(start + s(130)));
+        assert_eq!(recurring.occurrence(start + s(130)), Some(start + s(180)));
+    }
+
+    #[test]
+    fn gate() {
+        let start = time::OffsetDateTime::now_utc();
+        let s = time::Duration::seconds;
+        let calendar = Calendar {
+            windows: vec![window(start, Some(60)), window(start + s(30), Some(60))],
+            destinations: bpv7::EidPatternMap::new(),
+            clas: Vec::new(),
+        };
+
+        assert_eq!(calendar.gate(&[], start), Gate::Open(Vec::new()));
+        assert_eq!(calendar.gate(&[0, 1], start + s(35)), Gate::Open(vec![1]));
+        assert_eq!(
+            calendar.gate(&[0, 1], start + s(15)),
+            Gate::Closed(Some(start + s(30)))
+        );
+        assert_eq!(
+            calendar.gate(&[0], start + s(15)),
+            Gate::Closed(Some(start + s(60)))
+        );
+    }
+}
@@ -0,0 +1,57 @@
+use super::*;
+use tokio::sync::broadcast;
+
+/* Status reports arriving at our administrative endpoints are logged and published here,
+ * whether or not they concern a bundle sent by a local service, so operators can watch the
+ * fate of bundles as they are reported.  Watchers that fall behind miss reports, rather
+ * than hold up the processing of administrative records */
+
+const WATCH_CAPACITY: usize = 64;
+
+#[derive(Debug, Clone)]
+pub struct StatusReportEvent {
+    pub reported_by: bpv7::Eid,
+    pub report: bpv7::BundleStatusReport,
+}
+
+pub struct StatusWatch {
+    tx: broadcast::Sender<Arc<StatusReportEvent>>,
+}
+
+impl Default for StatusWatch {
+    fn default() -> Self {
+        Self {
+            tx: broadcast::channel(WATCH_CAPACITY).0,
+        }
+    }
+}
+
+impl Dispatcher {
+    pub fn watch_status_reports(&self) -> broadcast::Receiver<Arc<StatusReportEvent>> {
+        self.status_watch.tx.subscribe()
+    }
+
+    pub(super) fn publish_status_report(
+        &self,
+        reported_by: &bpv7::Eid,
+        report: &bpv7::BundleStatusReport,
+    ) {
+        info!(
+            reported_by = %reported_by,
+            bundle_id = ?report.bundle_id,
+            received = report.received.is_some(),
+            forwarded = report.forwarded.is_some(),
+            delivered = report.delivered.is_some(),
+            deleted = report.deleted.is_some(),
+            reason = ?report.reason,
+            "Bundle status report received"
+        );
+        metrics::counter!("status_reports_received_total").increment(1);
+
+        // Sending only fails if no-one is watching
+        _ = self.status_watch.tx.send(Arc::new(StatusReportEvent {
+            reported_by: reported_by.clone(),
+            report: report.clone(),
+        }));
+    }
+}
@@ -0,0 +1,232 @@
+use super::*;
+
+/* Telemetry about the node is sent periodically as bundles to a monitoring endpoint, so
+ * nodes can be monitored over the same links as the data, without IP connectivity to them.
+ * The payload is a CBOR map of: the time of the report in seconds since the Unix epoch,
+ * the bundles queued for dispatch, the bytes held by each subsystem, the bytes and bundles
+ * stored if a storage quota is configured, the bundles in each status if 'state_metrics'
+ * is enabled, and the status of each registered CLA.  Reports are not kept beyond the next
+ * report, as a newer report is worth more than an old one */
+
+#[derive(Debug, serde::Deserialize)]
+struct Config {
+    destination: String,
+    // Defaults to the administrative endpoint
+    source: Option<String>,
+    // Seconds between reports
+    #[serde(default = "Config::default_interval")]
+    interval: u64,
+}
+
+impl Config {
+    fn default_interval() -> u64 {
+        300
+    }
+}
+
+pub struct Telemetry {
+    source: bpv7::Eid,
+    destination: bpv7::Eid,
+    interval: time::Duration,
+}
+
+impl Telemetry {
+    pub fn new(
+        config: &::config::Config,
+        admin_endpoints: &utils::admin_endpoints::AdminEndpoints,
+    ) -> Option<Self> {
+        let config =
+            utils::settings::get_with_default::<Option<Config>, _>(config, "telemetry", None)
+                .trace_expect("Invalid 'telemetry' section in configuration")?;
+
+        let destination = config
+            .destination
+            .parse::<bpv7::Eid>()
+            .trace_expect("Invalid 'telemetry.destination' EID in configuration");
+        let source = match config.source {
+            Some(source) => {
+                let source = source
+                    .parse::<bpv7::Eid>()
+                    .trace_expect("Invalid 'telemetry.source' EID in configuration");
+                if !admin_endpoints.is_local_service(&source) {
+                    error!("'telemetry.source' {source} is not a local endpoint");
+                    panic!("'telemetry.source' {source} is not a local endpoint");
+                }
+                source
+            }
+            None => admin_endpoints.get_admin_endpoint(&destination),
+        };
+        if config.interval == 0 {
+            error!("'telemetry.interval' must be greater than 0");
+            panic!("'telemetry.interval' must be greater than 0");
+        }
+
+        info!(
+            "Sending telemetry from {source} to {destination} every {} seconds",
+            config.interval
+        );
+        Some(Self {
+            source,
+            destination,
+            interval: time::Duration::seconds(config.interval as i64),
+        })
+    }
+}
+
+struct Report {
+    time: i64,
+    dispatch_queue: usize,
+    memory: Vec<(&'static str, usize)>,
+    store: Option<(u64, u64)>,
+    states: Option<Vec<(&'static str, usize)>>,
+    clas: Vec<cla_registry::ClaStatus>,
+}
+
+impl Report {
+    fn encode(&self) -> Vec<u8> {
+        let count = 4 + self.store.is_some() as usize + self.states.is_some() as usize;
+        cbor::encode::emit_map(Some(count), |m| {
+            m.emit("time");
+            m.emit(self.time);
+            m.emit("dispatch_queue");
+            m.emit(self.dispatch_queue);
+            m.emit("memory");
+            m.emit_map(Some(self.memory.len()), |m| {
+                for (subsystem, bytes) in &self.memory {
+                    m.emit(*subsystem);
+                    m.emit(*bytes);
+                }
+            });
+            if let Some((bytes, bundles)) = self.store {
+                m.emit("store");
+                m.emit_map(Some(2), |m| {
+                    m.emit("bytes");
+                    m.emit(bytes);
+                    m.emit("bundles");
+                    m.emit(bundles);
+                });
+            }
+            if let Some(states) = &self.states {
+                m.emit("states");
+                m.emit_map(Some(states.len()), |m| {
+                    for (state, bundles) in states {
+                        m.emit(*state);
+                        m.emit(*bundles);
+                    }
+                });
+            }
+            m.emit("clas");
+            m.emit_array(Some(self.clas.len()), |a| {
+                for cla in &self.clas {
+                    a.emit_map(Some(4), |m| {
+                        m.emit("name");
+                        m.emit(cla.name.as_str());
+                        m.emit("handle");
+                        m.emit(cla.handle);
+                        m.emit("neighbours");
+                        m.emit(cla.neighbours);
+                        m.emit("healthy");
+                        m.emit(cla.healthy);
+                    });
+                }
+            });
+        })
+    }
+}
+
+impl Dispatcher {
+    async fn telemetry_report(&self) -> Report {
+        let mut states = self
+            .store
+            .bundles_in_state()
+            .map(|states| states.into_iter().collect::<Vec<_>>());
+        if let Some(states) = &mut states {
+            states.sort();
+        }
+        Report {
+            time: clock::now().unix_timestamp(),
+            dispatch_queue: self.tx.max_capacity() - self.tx.capacity(),
+            memory: utils::memory::usages(),
+            store: self.store.occupancy(),
+            states,
+            clas: self.cla_registry.status().await,
+        }
+    }
+
+    async fn send_telemetry(&self, telemetry: &Telemetry) {
+        let report = self.telemetry_report().await;
+        if let Err(e) = self
+            .local_dispatch(SendRequest {
+                source: telemetry.source.clone(),
+                destination: telemetry.destination.clone(),
+                data: Bytes::from(report.encode()),
+                lifetime: Some(
+                    telemetry
+                        .interval
+                        .whole_milliseconds()
+                        .clamp(1, u64::MAX as i128) as u64,
+                ),
+                flags: None,
+                track: false,
+            })
+            .await
+        {
+            warn!("Failed to send telemetry to {}: {e}", telemetry.destination);
+        } else {
+            metrics::counter!("telemetry_reports_total").increment(1);
+        }
+    }
+}
+
+pub async fn telemetry_task(dispatcher: Arc<Dispatcher>) {
+    let Some(telemetry) = &dispatcher.telemetry else {
+        return;
+    };
+    while clock::sleep(telemetry.interval, &dispatcher.cancel_token).await {
+        dispatcher.send_telemetry(telemetry).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_report() {
+        let report = Report {
+            time: 1000,
+            dispatch_queue: 2,
+            memory: vec![("ingress", 64)],
+            store: Some((4096, 3)),
+            states: None,
+            clas: vec![cla_registry::ClaStatus {
+                handle: 1,
+                name: "tcpcl".to_string(),
+                neighbours: 1,
+                healthy: true,
+            }],
+        };
+        let data = report.encode();
+
+        let keys = cbor::decode::parse_map(&data, |m, _, _| {
+            let mut keys = Vec::new();
+            while let Some(key) = m.try_parse_value(|value, _, _| match value {
+                cbor::decode::Value::Text(key) => Ok(key.to_string()),
+                _ => Err(cbor::decode::Error::IncorrectType(
+                    "Text".to_string(),
+                    value.type_name(false),
+                )),
+            })? {
+                keys.push(key);
+                m.skip_value(16)?;
+            }
+            Ok::<_, cbor::decode::Error>(keys)
+        })
+        .unwrap()
+        .0;
+        assert_eq!(
+            keys,
+            ["time", "dispatch_queue", "memory", "store", "clas"].map(String::from)
+        );
+    }
+}
@@ -0,0 +1,56 @@
+use super::*;
+
+// Times each processing stage of a bundle, recording the elapsed microseconds against the span.
+// The total is checked against the slow bundle threshold when the timer is dropped, so early
+// returns are caught too
+pub(super) struct StageTimer {
+    what: &'static str,
+    threshold: Option<std::time::Duration>,
+    span: tracing::Span,
+    started: std::time::Instant,
+    last: std::time::Instant,
+    stages: Vec<(&'static str, std::time::Duration)>,
+}
+
+impl StageTimer {
+    pub fn new(what: &'static str, threshold: Option<std::time::Duration>) -> Self {
+        let now = std::time::Instant::now();
+        Self {
+            what,
+            threshold,
+            span: tracing::Span::current(),
+            started: now,
+            last: now,
+            stages: Vec::new(),
+        }
+    }
+
+    pub fn stage(&mut self, stage: &'static str) {
+        let now = std::time::Instant::now();
+        let elapsed = now - self.last;
+        self.last = now;
+
+        let elapsed_us = elapsed.as_micros() as u64;
+        self.span.record(stage, elapsed_us);
+        trace!(stage, elapsed_us, "Processing stage complete");
+
+        self.stages.push((stage, elapsed));
+    }
+}
+
+impl Drop for StageTimer {
+    fn drop(&mut self) {
+        let total = self.started.elapsed();
+        if self.threshold.is_some_and(|threshold| total >= threshold) {
+            warn!(
+                "Slow bundle {}: {total:?} total, {}",
+                self.what,
+                self.stages
+                    .iter()
+                    .map(|(stage, elapsed)| format!("{stage} {elapsed:?}"))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        }
+    }
+}
@@ -0,0 +1,152 @@
+use super::*;
+use std::collections::HashMap;
+use tokio::sync::broadcast;
+
+/* On power-constrained platforms a radio may be kept powered down until there is something
+ * to send over it.  The CLAs named in the 'wake_on_bundle' section are duty-cycled: the
+ * dispatcher defers forwarding over them until the platform integration signals that their
+ * link is ready, and publishes a wake request when bundles are waiting for a link that is
+ * not.  Wake requests for a link are repeated at most once per 'wake_timeout', which is also
+ * how long deferred bundles wait before trying again, in case the signal never comes.  Once
+ * the link is ready, the bundles waiting on routes through the CLA are re-dispatched */
+
+const WATCH_CAPACITY: usize = 16;
+
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(default)]
+struct Config {
+    // The names the duty-cycled CLAs register with
+    clas: Vec<String>,
+    // In seconds
+    wake_timeout: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            clas: Vec::new(),
+            wake_timeout: 30,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct WakeRequest {
+    pub cla: String,
+}
+
+#[derive(Default)]
+struct Link {
+    ready: bool,
+    // When a wake request was last published, if the link has not been ready since
+    woken: Option<time::OffsetDateTime>,
+}
+
+pub struct Wake {
+    config: Config,
+    links: std::sync::Mutex<HashMap<String, Link>>,
+    tx: broadcast::Sender<WakeRequest>,
+}
+
+impl Wake {
+    pub fn new(config: &::config::Config) -> Option<Self> {
+        let config =
+            utils::settings::get_with_default::<Option<Config>, _>(config, "wake_on_bundle", None)
+                .trace_expect("Invalid 'wake_on_bundle' section in configuration")?;
+        if config.clas.is_empty() {
+            warn!("'wake_on_bundle' names no CLAs, ignoring");
+            return None;
+        }
+        info!(
+            "Deferring forwarding over CLAs {:?} until their links are ready",
+            config.clas
+        );
+        Some(Self {
+            config,
+            links: Default::default(),
+            tx: broadcast::channel(WATCH_CAPACITY).0,
+        })
+    }
+
+    fn is_duty_cycled(&self, cla: &str) -> bool {
+        self.config.clas.iter().any(|name| name == cla)
+    }
+}
+
+impl Dispatcher {
+    // None if no CLAs are duty-cycled
+    pub fn watch_wake_requests(&self) -> Option<broadcast::Receiver<WakeRequest>> {
+        self.wake.as_ref().map(|wake| wake.tx.subscribe())
+    }
+
+    // Returns false if the CLA is not duty-cycled
+    #[instrument(skip(self))]
+    pub async fn set_link_ready(&self, cla: &str, ready: bool) -> bool {
+        let Some(wake) = &self.wake else {
+            return false;
+        };
+        if !wake.is_duty_cycled(cla) {
+            return false;
+        }
+
+        {
+            // Scope the lock
+            let mut links = wake.links.lock().trace_expect("Failed to lock mutex");
+            let link = links.entry(cla.to_string()).or_default();
+            if link.ready == ready {
+                return true;
+            }
+            link.ready = ready;
+            link.woken = None;
+        }
+
+        info!(
+            "Link of CLA {cla} is {}",
+            if ready { "ready" } else { "not ready" }
+        );
+        if !ready {
+            return true;
+        }
+
+        // Don't wait for the deferred bundles to try again
+        let handle = self.cla_registry.find_by_name(cla).await;
+        if let (Some(fib), Some(handle)) = (&self.fib, handle) {
+            for pattern in fib.forwarded_patterns(handle).await {
+                if let Err(e) = self.redispatch(&pattern).await {
+                    warn!("Failed to re-dispatch bundles for {pattern}: {e}");
+                }
+            }
+        }
+        true
+    }
+
+    // When to try forwarding over the CLA again if its link is not ready, waking it if needed
+    pub(super) async fn link_wait(&self, handle: u32) -> Option<time::OffsetDateTime> {
+        let wake = self.wake.as_ref()?;
+        let cla = self.cla_registry.name(handle).await?;
+        if !wake.is_duty_cycled(&cla) {
+            return None;
+        }
+
+        let now = clock::now();
+        let timeout = time::Duration::seconds(wake.config.wake_timeout.min(i64::MAX as u64) as i64);
+        let mut links = wake.links.lock().trace_expect("Failed to lock mutex");
+        let link = links.entry(cla.clone()).or_default();
+        if link.ready {
+            return None;
+        }
+        if let Some(woken) = link.woken {
+            if woken + timeout > now {
+                return Some(woken + timeout);
+            }
+        }
+
+        info!("Bundles are waiting for the link of CLA {cla}, waking it");
+        metrics::counter!("link_wake_requests_total", "cla" => cla.clone()).increment(1);
+        link.woken = Some(now);
+
+        // Sending only fails if no-one is watching
+        _ = wake.tx.send(WakeRequest { cla });
+        Some(now + timeout)
+    }
+}
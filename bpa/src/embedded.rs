@@ -0,0 +1,422 @@
+use super::*;
+use hardy_bpa_api::storage;
+use hardy_proto::{application::*, cla::*};
+use std::{collections::HashMap, sync::Arc};
+use tokio_util::bytes::Bytes;
+
+/* The BPA can run in-process as part of another application, rather than as a daemon that
+ * applications talk to over gRPC.  The embedding application configures it with a Builder,
+ * optionally supplying its own storage engines in place of the configured ones, and then
+ * sends and receives bundles through the Bpa directly.  The convergence layers it runs itself
+ * can be registered as in-process CLAs.  The gRPC services can still be served alongside, for external CLAs and applications.  The clock, memory limits and
+ * metrics labels are process-wide, so only one Bpa should be started per process */
+
+#[derive(Default)]
+pub struct Builder {
+    config: Option<config::Config>,
+    metadata_storage: Option<Arc<dyn storage::MetadataStorage>>,
+    bundle_storage: Option<Arc<dyn storage::BundleStorage>>,
+    clas: Vec<RegisterClaRequest>,
+    local_clas: Vec<(String, String, Arc<dyn cla_registry::Cla>)>,
+    grpc: bool,
+}
+
+impl Builder {
+    // The configuration, as would be read from the configuration file of the daemon
+    pub fn with_config(mut self, config: config::Config) -> Self {
+        self.config = Some(config);
+        self
+    }
+
+    // Use these storage engines, rather than the configured ones
+    pub fn with_storage(
+        mut self,
+        metadata_storage: Arc<dyn storage::MetadataStorage>,
+        bundle_storage: Arc<dyn storage::BundleStorage>,
+    ) -> Self {
+        self.metadata_storage = Some(metadata_storage);
+        self.bundle_storage = Some(bundle_storage);
+        self
+    }
+
+    // Register a CLA, served over gRPC at `grpc_address`, once the BPA has started
+    pub fn with_cla(mut self, ident: &str, name: &str, grpc_address: &str) -> Self {
+        self.clas.push(RegisterClaRequest {
+            ident: ident.to_string(),
+            name: name.to_string(),
+            grpc_address: grpc_address.to_string(),
+            instance_id: String::new(),
+            capabilities: None,
+        });
+        self
+    }
+
+    // Register a CLA run in-process by the embedding application once the BPA has started,
+    // which is sent bundles by calling `cla`, and receives them through Bpa::local_sink
+    pub fn with_local_cla(
+        mut self,
+        ident: &str,
+        name: &str,
+        cla: Arc<dyn cla_registry::Cla>,
+    ) -> Self {
+        self.local_clas
+            .push((ident.to_string(), name.to_string(), cla));
+        self
+    }
+
+    // Serve the gRPC services as well, as configured
+    pub fn with_grpc(mut self, grpc: bool) -> Self {
+        self.grpc = grpc;
+        self
+    }
+
+    pub async fn start(self) -> Result<Bpa, Error> {
+        let config = self.config.unwrap_or_default();
+
+        // Init the process-wide state
+        let administrative_endpoints = startup::init_process(&config);
+
+        // New store
+        let store =
+            store::Store::with_engines(&config, false, self.metadata_storage, self.bundle_storage);
+        if store.read_only() {
+            return Err("An embedded BPA cannot be a read-only replica".into());
+        }
+
+        // Leave signal handling to the embedding application
+        let mut task_set = tokio::task::JoinSet::new();
+        let cancel_token = tokio_util::sync::CancellationToken::new();
+
+        // Build the node
+        let startup::Node {
+            store,
+            fib,
+            cla_registry,
+            app_registry,
+            dispatcher,
+        } = startup::start_node(
+            &config,
+            administrative_endpoints,
+            store,
+            None,
+            &mut task_set,
+            cancel_token.clone(),
+        )
+        .await;
+
+        // Start the store - this can take a while as the store is walked
+        store
+            .start(dispatcher.clone(), &mut task_set, cancel_token.clone())
+            .await;
+
+        if self.grpc {
+            grpc::init(
+                &config,
+                None,
+                store,
+                cla_registry.clone(),
+                app_registry.clone(),
+                dispatcher.clone(),
+                fib,
+                &mut task_set,
+                cancel_token.clone(),
+            );
+        }
+
+        for cla in self.clas {
+            let name = cla.name.clone();
+            cla_registry
+                .register(cla, None)
+                .await
+                .map_err(|e| format!("Failed to register CLA '{name}': {}", e.message()))?;
+        }
+
+        let mut local_sinks = HashMap::new();
+        for (ident, name, cla) in self.local_clas {
+            let sink = cla_registry
+                .register_local(&ident, &name, cla, dispatcher.clone())
+                .await;
+            local_sinks.insert(ident, sink);
+        }
+
+        info!("Started successfully");
+        Ok(Bpa {
+            cla_registry,
+            local_sinks,
+            app_registry,
+            dispatcher,
+            task_set,
+            cancel_token,
+        })
+    }
+}
+
+pub struct Bpa {
+    cla_registry: cla_registry::ClaRegistry,
+    local_sinks: HashMap<String, cla_registry::LocalSink>,
+    app_registry: app_registry::AppRegistry,
+    dispatcher: Arc<dispatcher::Dispatcher>,
+    task_set: tokio::task::JoinSet<()>,
+    cancel_token: tokio_util::sync::CancellationToken,
+}
+
+impl Bpa {
+    pub fn builder() -> Builder {
+        Builder::default()
+    }
+
+    // For everything not wrapped here
+    pub fn dispatcher(&self) -> &Arc<dispatcher::Dispatcher> {
+        &self.dispatcher
+    }
+
+    pub async fn register_application(
+        &self,
+        request: RegisterApplicationRequest,
+    ) -> Result<RegisterApplicationResponse, Error> {
+        Ok(self.app_registry.register(request).await?)
+    }
+
+    pub async fn unregister_application(&self, token: &str) -> Result<(), Error> {
+        self.app_registry
+            .unregister(UnregisterApplicationRequest {
+                token: token.to_string(),
+            })
+            .await?;
+        Ok(())
+    }
+
+    // Send a bundle from the application registered with `token`, returning its id
+    pub async fn send(
+        &self,
+        token: &str,
+        destination: bpv7::Eid,
+        data: Bytes,
+        lifetime: Option<u64>,
+        flags: Option<bpv7::BundleFlags>,
+    ) -> Result<bpv7::BundleId, Error> {
+        if let bpv7::Eid::Null = destination {
+            return Err("Cannot send to Null endpoint".into());
+        }
+        self.dispatcher
+            .local_dispatch(dispatcher::SendRequest {
+                source: self.app_registry.find_by_token(token).await?,
+                destination,
+                data,
+                lifetime,
+                flags,
+                track: false,
+            })
+            .await
+    }
+
+    // Collect a bundle awaiting the application registered with `token`
+    pub async fn collect(
+        &self,
+        token: &str,
+        bundle_id: String,
+    ) -> Result<Option<dispatcher::CollectResponse>, Error> {
+        let (destination, registration) =
+            self.app_registry.find_registration_by_token(token).await?;
+        let acknowledged = self.app_registry.acknowledged_delivery(token).await;
+        self.dispatcher
+            .collect(destination, registration, acknowledged, bundle_id, None)
+            .await
+    }
+
+    // Take delivery of the bundles collected by an application registered for
+    // acknowledged delivery
+    pub async fn acknowledge(&self, token: &str, delivery_ids: Vec<u64>) -> Result<(), Error> {
+        let (destination, registration) =
+            self.app_registry.find_registration_by_token(token).await?;
+        self.dispatcher
+            .acknowledge(destination, registration, delivery_ids)
+            .await
+    }
+
+    // Receive the bundles for the application registered with `token` as they arrive
+    pub async fn subscribe(
+        &self,
+        token: &str,
+    ) -> Result<tokio::sync::mpsc::Receiver<dispatcher::CollectResponse>, Error> {
+        let (destination, registration) =
+            self.app_registry.find_registration_by_token(token).await?;
+        let acknowledged = self.app_registry.acknowledged_delivery(token).await;
+        let (tx, rx) = tokio::sync::mpsc::channel(1);
+        self.dispatcher
+            .subscribe(destination, registration, acknowledged, tx);
+        Ok(rx)
+    }
+
+    // The sink of an in-process CLA registered with Builder::with_local_cla
+    pub fn local_sink(&self, ident: &str) -> Option<&cla_registry::LocalSink> {
+        self.local_sinks.get(ident)
+    }
+
+    // Register a CLA run in-process by the embedding application, which receives bundles
+    // through the returned sink, and is sent them by calling `cla`
+    pub async fn register_cla(
+        &self,
+        ident: &str,
+        name: &str,
+        cla: Arc<dyn cla_registry::Cla>,
+    ) -> cla_registry::LocalSink {
+        self.cla_registry
+            .register_local(ident, name, cla, self.dispatcher.clone())
+            .await
+    }
+
+    // Receive a bundle from a convergence layer run by the embedding application
+    pub async fn receive_bundle(
+        &self,
+        data: Bytes,
+    ) -> Result<Option<dispatcher::Rejection>, Error> {
+        self.dispatcher.record_ingress(None, &[], &data).await;
+        self.dispatcher.receive_bundle(data).await
+    }
+
+    // Stop the BPA, and wait for it to finish
+    pub async fn shutdown(mut self) {
+        info!("Stopping...");
+        self.cancel_token.cancel();
+        while let Some(r) = self.task_set.join_next().await {
+            r.trace_expect("Task terminated unexpectedly")
+        }
+        info!("Stopped");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Start a BPA as ipn:1.0, storing into a directory of its own
+    async fn start(name: &str, builder: Builder) -> (Bpa, std::path::PathBuf) {
+        let dir = std::env::temp_dir().join(format!("hardy-bpa-{name}-{}", std::process::id()));
+        let config = config::Config::builder()
+            .add_source(config::File::from_str(
+                &format!(
+                    r#"
+                    administrative_endpoint = "ipn:1.0"
+
+                    [sqlite]
+                    db_dir = "{0}/db"
+
+                    [localdisk]
+                    store_dir = "{0}/bundles"
+                    "#,
+                    dir.display()
+                ),
+                config::FileFormat::Toml,
+            ))
+            .build()
+            .unwrap();
+        (builder.with_config(config).start().await.unwrap(), dir)
+    }
+
+    fn parse(data: &[u8]) -> (bpv7::Bundle, Vec<u8>) {
+        let bpv7::ValidBundle::Valid(bundle, _) =
+            bpv7::ValidBundle::parse(data, |_, _| Ok(None)).unwrap()
+        else {
+            panic!("Bundle is invalid");
+        };
+        let payload = bundle.blocks[&1].block_data(data).unwrap().to_vec();
+        (bundle, payload)
+    }
+
+    // An in-process CLA that hands the bundles it is sent to the test
+    struct Loopback(tokio::sync::mpsc::Sender<(bpv7::Eid, Bytes)>);
+
+    #[tonic::async_trait]
+    impl cla_registry::Cla for Loopback {
+        async fn forward_bundle(
+            &self,
+            destination: &bpv7::Eid,
+            _address: Option<String>,
+            bundle: Bytes,
+        ) -> Result<cla_registry::ForwardBundleResult, Error> {
+            self.0.send((destination.clone(), bundle)).await?;
+            Ok(cla_registry::ForwardBundleResult::Sent)
+        }
+    }
+
+    async fn register(bpa: &Bpa) -> String {
+        bpa.register_application(RegisterApplicationRequest {
+            endpoint: Some(register_application_request::Endpoint::IpnServiceNumber(42)),
+            ident: "test".to_string(),
+            ..Default::default()
+        })
+        .await
+        .unwrap()
+        .token
+    }
+
+    #[tokio::test]
+    async fn send_and_collect() {
+        let (bpa, dir) = start("send", Bpa::builder()).await;
+
+        let token = register(&bpa).await;
+        let mut rx = bpa.subscribe(&token).await.unwrap();
+
+        let bundle_id = bpa
+            .send(
+                &token,
+                "ipn:1.42".parse().unwrap(),
+                Bytes::from_static(b"Hello"),
+                None,
+                None,
+            )
+            .await
+            .unwrap();
+
+        let response = tokio::time::timeout(std::time::Duration::from_secs(10), rx.recv())
+            .await
+            .expect("Bundle was not delivered")
+            .unwrap();
+        assert_eq!(response.bundle_id, bundle_id.to_key());
+        assert_eq!(parse(&response.data).1, b"Hello");
+
+        bpa.shutdown().await;
+        _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn forward_local() {
+        let (tx, mut rx) = tokio::sync::mpsc::channel(1);
+        let (bpa, dir) = start(
+            "forward",
+            Bpa::builder().with_local_cla("loopback", "test", Arc::new(Loopback(tx))),
+        )
+        .await;
+
+        bpa.local_sink("loopback")
+            .unwrap()
+            .add_neighbour("ipn:2.*", 0, None)
+            .await
+            .unwrap();
+
+        let token = register(&bpa).await;
+        bpa.send(
+            &token,
+            "ipn:2.1".parse().unwrap(),
+            Bytes::from_static(b"Hello"),
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+
+        let (destination, data) =
+            tokio::time::timeout(std::time::Duration::from_secs(10), rx.recv())
+                .await
+                .expect("Bundle was not forwarded")
+                .unwrap();
+        let (bundle, payload) = parse(&data);
+        assert_eq!(destination, "ipn:2.1".parse::<bpv7::Eid>().unwrap());
+        assert_eq!(bundle.destination, destination);
+        assert_eq!(payload, b"Hello");
+
+        bpa.shutdown().await;
+        _ = std::fs::remove_dir_all(dir);
+    }
+}
@@ -1,6 +1,6 @@
 use super::*;
 use rand::prelude::*;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 use tokio::sync::RwLock;
 use utils::settings;
@@ -8,7 +8,13 @@ use utils::settings;
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Endpoint {
     pub handle: u32, // The CLA handle
-                     // TODO: Metrics, e.g.: Bandwidth, Contact deadline
+    // The CLA-specific address of the next hop, if resolved
+    pub address: Option<String>,
+    // The largest bundle the CLA advertises it can send to the next hop
+    pub mtu: Option<u64>,
+    // The expected latency to the next hop, accumulated over the routes that led to it
+    pub latency: Option<time::Duration>,
+    // TODO: Metrics, e.g.: Bandwidth, Contact deadline
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
@@ -36,33 +42,499 @@ impl std::fmt::Display for Action {
     }
 }
 
+#[derive(Clone)]
 pub struct ForwardAction {
     pub clas: Vec<Endpoint>,                 // Available endpoints for forwarding
     pub until: Option<time::OffsetDateTime>, // Timestamp of next forwarding opportunity
+    pub resolve: Vec<bpv7::Eid>,             // Next hops with no route, to be resolved
+}
+
+impl ForwardAction {
+    // The smallest MTU of the endpoints, as any of them may be used
+    pub fn mtu(&self) -> Option<u64> {
+        self.clas.iter().filter_map(|endpoint| endpoint.mtu).min()
+    }
 }
 
 type ForwardResult = Result<ForwardAction, Option<bpv7::StatusReportReasonCode>>;
 
 type TableKey = String;
 
+/* Routes are ranked by administrative distance, which reflects how much the source of
+ * the route is trusted, then by cost within that source.  The lowest ranked routes are
+ * used, falling back to higher ranked forwarding routes in order, so neighbours, static
+ * routes and computed routes can coexist predictably */
+
+// Administrative distance of routes to directly connected neighbours, added by CLAs
+pub const DISTANCE_NEIGHBOUR: u32 = 0;
+
+// Default administrative distance of static routes
+pub const DISTANCE_STATIC: u32 = 1;
+
+// Default administrative distance of routes computed from contact plans
+pub const DISTANCE_COMPUTED: u32 = 20;
+
+// Default administrative distance of routes learned from delivery predictabilities
+pub const DISTANCE_PROBABILISTIC: u32 = 30;
+
+// Ordered by distance, then cost, then action, which breaks ties between equal routes
 #[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub struct TableEntry {
-    pub priority: u32,
+    pub distance: u32,
+    pub cost: u32,
     pub action: Action,
+    pub latency: Option<time::Duration>, // The expected latency of the hop, if known
 }
 
 type Table = bpv7::EidPatternMap<TableKey, Vec<TableEntry>>;
 
-#[derive(Default, Clone)]
+// The routes of the table by source and pattern
+type RouteMap = HashMap<(TableKey, bpv7::EidPattern), Vec<TableEntry>>;
+
+#[derive(Debug, Clone)]
+pub enum Event {
+    Inactive(bpv7::EidPattern), // Routes to the pattern are unusable
+    Active(bpv7::EidPattern),   // Routes to the pattern are usable again
+    // The CLA handle is suppressed for flapping, until no earlier than `until`
+    Dampened {
+        handle: u32,
+        until: time::OffsetDateTime,
+    },
+}
+
+/* Next hops that flap, losing their last forwarding route or being marked down over and
+ * over, are dampened much as RFC 2439 dampens BGP routes.  Each flap adds to a penalty
+ * that decays with the configured half-life.  Once the penalty passes the suppress
+ * threshold the next hop is not forwarded to, and coming back up does not re-dispatch
+ * the bundles waiting for it, until the penalty has decayed below the reuse threshold.
+ * This stops a flapping neighbour from making the dispatcher park and re-dispatch its
+ * queues on every flap */
+
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+struct DampeningConfig {
+    // In seconds
+    half_life: u64,
+    // Added to the penalty of a next hop each time it flaps
+    penalty: f64,
+    // Next hops are suppressed once their penalty exceeds this...
+    suppress: f64,
+    // ... and reused once it has decayed below this
+    reuse: f64,
+    // The longest a next hop stays suppressed after its last flap, in seconds
+    max_suppress: u64,
+}
+
+// The FIB settings, as they appear in the configuration
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+struct Settings {
+    forwarding: bool,
+    route_dampening: Option<DampeningConfig>,
+    fib_cache_ttl: u64,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            forwarding: true,
+            route_dampening: None,
+            fib_cache_ttl: 1000,
+        }
+    }
+}
+
+impl Default for DampeningConfig {
+    fn default() -> Self {
+        Self {
+            half_life: 900,
+            penalty: 1000.0,
+            suppress: 2000.0,
+            reuse: 750.0,
+            max_suppress: 3600,
+        }
+    }
+}
+
+struct Penalty {
+    value: f64,
+    updated: time::OffsetDateTime,
+    suppressed: bool,
+}
+
+struct Dampening {
+    config: DampeningConfig,
+    penalties: HashMap<u32, Penalty>,
+}
+
+impl Dampening {
+    fn new(config: DampeningConfig) -> Self {
+        Self {
+            config,
+            penalties: HashMap::new(),
+        }
+    }
+
+    fn half_life(&self) -> f64 {
+        self.config.half_life.max(1) as f64
+    }
+
+    // Decay the penalty up to `now`
+    fn advance(&self, penalty: &mut Penalty, now: time::OffsetDateTime) {
+        let elapsed = (now - penalty.updated).as_seconds_f64();
+        if elapsed > 0.0 {
+            penalty.value *= 0.5f64.powf(elapsed / self.half_life());
+            penalty.updated = now;
+        }
+    }
+
+    // When the penalty will have decayed to the reuse threshold
+    fn reuse_at(&self, penalty: &Penalty) -> time::OffsetDateTime {
+        let wait = (penalty.value / self.config.reuse).log2().max(0.0) * self.half_life();
+        penalty.updated + time::Duration::seconds_f64(wait)
+    }
+
+    fn is_suppressed(&self, handle: u32) -> bool {
+        self.penalties
+            .get(&handle)
+            .is_some_and(|penalty| penalty.suppressed)
+    }
+
+    // Record a flap of a next hop, returning when it can be reused if it is newly suppressed
+    fn flap(&mut self, handle: u32, now: time::OffsetDateTime) -> Option<time::OffsetDateTime> {
+        // Forget next hops that have been stable for long enough
+        let forget = self.config.reuse / 2.0;
+        let mut penalties = std::mem::take(&mut self.penalties);
+        penalties.retain(|_, penalty| {
+            self.advance(penalty, now);
+            penalty.suppressed || penalty.value >= forget
+        });
+        self.penalties = penalties;
+
+        // Cap the penalty, so no next hop is suppressed for longer than the maximum
+        let ceiling =
+            self.config.reuse * 2f64.powf(self.config.max_suppress as f64 / self.half_life());
+        let penalty = self.penalties.entry(handle).or_insert(Penalty {
+            value: 0.0,
+            updated: now,
+            suppressed: false,
+        });
+        penalty.value = (penalty.value + self.config.penalty).min(ceiling);
+        if penalty.suppressed || penalty.value <= self.config.suppress {
+            return None;
+        }
+        penalty.suppressed = true;
+        let penalty = &self.penalties[&handle];
+        Some(self.reuse_at(penalty))
+    }
+
+    // Reuse a suppressed next hop if its penalty has decayed enough, otherwise return when
+    // to try again
+    fn reuse(&mut self, handle: u32, now: time::OffsetDateTime) -> Option<time::OffsetDateTime> {
+        let mut penalty = self.penalties.remove(&handle)?;
+        self.advance(&mut penalty, now);
+        let retry = if penalty.value < self.config.reuse {
+            penalty.suppressed = false;
+            None
+        } else {
+            // Rounding may leave the penalty a hair above the threshold at the reuse time
+            Some(self.reuse_at(&penalty).max(now + time::Duration::SECOND))
+        };
+        self.penalties.insert(handle, penalty);
+        retry
+    }
+}
+
+// Orders equal-cost next hops through different CLAs, lowest first, from the capabilities
+// the CLAs registered with
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Preference {
+    pub cost: u32,
+    pub latency: cla_registry::LatencyClass,
+    // CLAs that confirm delivery to the next hop are preferred over best effort ones
+    pub best_effort: bool,
+}
+
+#[derive(Default)]
+struct Health {
+    down: HashSet<u32>,                                      // CLA handles marked down
+    routes: HashMap<u32, HashMap<String, bpv7::EidPattern>>, // Patterns to each CLA, by text
+    dampening: Option<Dampening>,                            // None if dampening is disabled
+    preferences: HashMap<u32, Preference>,                   // Absent is the default preference
+}
+
+impl Health {
+    // Whether the CLA can be forwarded to
+    fn is_usable(&self, handle: u32) -> bool {
+        !self.down.contains(&handle) && !self.is_suppressed(handle)
+    }
+
+    fn preference(&self, handle: u32) -> Preference {
+        self.preferences.get(&handle).copied().unwrap_or_default()
+    }
+
+    fn is_suppressed(&self, handle: u32) -> bool {
+        self.dampening
+            .as_ref()
+            .is_some_and(|dampening| dampening.is_suppressed(handle))
+    }
+
+    // Returns the event to send if the flap suppresses the CLA
+    fn flap(&mut self, handle: u32) -> Option<Event> {
+        let until = self.dampening.as_mut()?.flap(handle, utils::clock::now())?;
+        warn!("Next hop {handle} is flapping, suppressing its routes until {until}");
+        metrics::counter!("fib_dampened_next_hops_total").increment(1);
+        Some(Event::Dampened { handle, until })
+    }
+}
+
+/* Bursts of bundles to the same destination, and the retries of the forwarding loop, would
+ * otherwise walk the table for the same EID over and over, so lookup results are cached
+ * for a short while.  The cache is cleared whenever routes, the health of CLAs or the
+ * registered CLAs change, so it only ever saves work.  Results are cached in preference
+ * order, with the ranges of equal next hops, which are shuffled afresh for every lookup so
+ * that bursts are still spread across them */
+
+// Clear the cache when it grows past this many destinations
+const MAX_CACHED: usize = 4096;
+
+#[derive(Default)]
+struct Cache {
+    ttl: time::Duration,
+    results: HashMap<bpv7::Eid, (time::OffsetDateTime, ForwardResult, Ties)>,
+}
+
+impl Cache {
+    fn get(&mut self, to: &bpv7::Eid, now: time::OffsetDateTime) -> Option<ForwardResult> {
+        match self.results.get(to) {
+            Some((expiry, result, ties)) if *expiry > now => {
+                Some(shuffle_ties(result.clone(), ties))
+            }
+            Some(_) => {
+                self.results.remove(to);
+                None
+            }
+            None => None,
+        }
+    }
+
+    fn insert(
+        &mut self,
+        to: &bpv7::Eid,
+        result: &ForwardResult,
+        ties: &Ties,
+        now: time::OffsetDateTime,
+    ) {
+        if !self.ttl.is_positive() {
+            return;
+        }
+        if self.results.len() >= MAX_CACHED {
+            self.results.retain(|_, (expiry, _, _)| *expiry > now);
+            if self.results.len() >= MAX_CACHED {
+                self.results.clear();
+            }
+        }
+        self.results
+            .insert(to.clone(), (now + self.ttl, result.clone(), ties.clone()));
+    }
+}
+
+#[derive(Clone)]
 pub struct Fib {
     entries: Arc<RwLock<Table>>,
+    // The same routes by source and pattern, as the table cannot be walked
+    routes: Arc<RwLock<RouteMap>>,
+    health: Arc<RwLock<Health>>,
+    events: tokio::sync::broadcast::Sender<Event>,
+    cache: Arc<std::sync::Mutex<Cache>>,
+}
+
+impl Default for Fib {
+    fn default() -> Self {
+        Self {
+            entries: Default::default(),
+            routes: Default::default(),
+            health: Default::default(),
+            events: tokio::sync::broadcast::channel(16).0,
+            cache: Default::default(),
+        }
+    }
 }
 
 impl Fib {
     pub fn new(config: &config::Config) -> Option<Self> {
-        settings::get_with_default::<bool, _>(config, "forwarding", true)
-            .trace_expect("Invalid 'forwarding' value in configuration")
-            .then(Self::default)
+        let settings: Settings = settings::load(config, "FIB");
+        if !settings.forwarding {
+            return None;
+        }
+
+        let dampening = settings.route_dampening.map(|config| {
+            info!(
+                "Dampening flapping next hops above a penalty of {}, with a half-life of {}s",
+                config.suppress, config.half_life
+            );
+            Dampening::new(config)
+        });
+
+        let ttl = settings.fib_cache_ttl;
+        if ttl != 0 {
+            info!("Caching FIB lookups for {ttl}ms");
+        }
+
+        Some(Self {
+            health: Arc::new(RwLock::new(Health {
+                dampening,
+                ..Default::default()
+            })),
+            cache: Arc::new(std::sync::Mutex::new(Cache {
+                ttl: time::Duration::milliseconds(ttl.min(i64::MAX as u64) as i64),
+                ..Default::default()
+            })),
+            ..Default::default()
+        })
+    }
+
+    // Forget the cached lookups, as the routes or CLAs they were made with have changed
+    pub fn invalidate(&self) {
+        self.cache
+            .lock()
+            .trace_expect("Failed to lock mutex")
+            .results
+            .clear();
+    }
+
+    pub fn subscribe(&self) -> tokio::sync::broadcast::Receiver<Event> {
+        self.events.subscribe()
+    }
+
+    // Returns false if the CLA was already marked as `healthy`
+    #[instrument(skip(self))]
+    pub async fn set_health(&self, handle: u32, healthy: bool) -> bool {
+        // Every bundle sent or received calls this, so only queue for the write lock on a change
+        if self.health.read().await.down.contains(&handle) != healthy {
+            return false;
+        }
+
+        let patterns = {
+            // Scope the lock
+            let mut health = self.health.write().await;
+            let changed = if healthy {
+                health.down.remove(&handle)
+            } else {
+                health.down.insert(handle)
+            };
+            if !changed {
+                return false;
+            }
+            self.invalidate();
+            if healthy && health.is_suppressed(handle) {
+                // The routes become active when the CLA is reused
+                info!("Next hop {handle} is up, but suppressed for flapping");
+                return true;
+            }
+            if !healthy {
+                if let Some(event) = health.flap(handle) {
+                    _ = self.events.send(event);
+                }
+            }
+            health
+                .routes
+                .get(&handle)
+                .map(|patterns| patterns.values().cloned().collect::<Vec<_>>())
+                .unwrap_or_default()
+        };
+
+        for pattern in patterns {
+            let event = if healthy {
+                info!("Route {pattern} => forward {handle} is active");
+                Event::Active(pattern)
+            } else {
+                info!("Route {pattern} => forward {handle} is inactive");
+                Event::Inactive(pattern)
+            };
+
+            // Nobody listening is fine
+            _ = self.events.send(event);
+        }
+        true
+    }
+
+    // Set how the CLA compares with others at equal cost, None when it has gone
+    pub async fn set_preference(&self, handle: u32, preference: Option<Preference>) {
+        let mut health = self.health.write().await;
+        let changed = match preference {
+            Some(preference) => health.preferences.insert(handle, preference) != Some(preference),
+            None => health.preferences.remove(&handle).is_some(),
+        };
+        if changed {
+            self.invalidate();
+        }
+    }
+
+    // Whether the CLA has been marked down
+    pub async fn is_down(&self, handle: u32) -> bool {
+        self.health.read().await.down.contains(&handle)
+    }
+
+    // The patterns with routes forwarding to the CLA
+    pub async fn forwarded_patterns(&self, handle: u32) -> Vec<bpv7::EidPattern> {
+        self.health
+            .read()
+            .await
+            .routes
+            .get(&handle)
+            .map(|patterns| patterns.values().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    // Reuse a CLA suppressed for flapping once its penalty has decayed, returning when to
+    // try again if it has not
+    #[instrument(skip(self))]
+    pub async fn reuse(&self, handle: u32) -> Option<time::OffsetDateTime> {
+        let patterns = {
+            // Scope the lock
+            let mut health = self.health.write().await;
+            if let Some(retry) = health
+                .dampening
+                .as_mut()?
+                .reuse(handle, utils::clock::now())
+            {
+                return Some(retry);
+            }
+            info!("Next hop {handle} is no longer suppressed");
+            self.invalidate();
+            if health.down.contains(&handle) {
+                return None;
+            }
+            health
+                .routes
+                .get(&handle)
+                .map(|patterns| patterns.values().cloned().collect::<Vec<_>>())
+                .unwrap_or_default()
+        };
+
+        for pattern in patterns {
+            info!("Route {pattern} => forward {handle} is active");
+
+            // Nobody listening is fine
+            _ = self.events.send(Event::Active(pattern));
+        }
+        None
+    }
+
+    // The CLAs currently suppressed for flapping, with when each may be reused, for those
+    // that missed the Dampened events
+    pub async fn suppressed(&self) -> Vec<(time::OffsetDateTime, u32)> {
+        let health = self.health.read().await;
+        let Some(dampening) = &health.dampening else {
+            return Vec::new();
+        };
+        dampening
+            .penalties
+            .iter()
+            .filter(|(_, penalty)| penalty.suppressed)
+            .map(|(handle, penalty)| (dampening.reuse_at(penalty), *handle))
+            .collect()
     }
 
     #[instrument(skip_all)]
@@ -70,80 +542,206 @@ impl Fib {
         &self,
         id: String,
         pattern: &bpv7::EidPattern,
-        priority: u32,
+        distance: u32,
+        cost: u32,
         action: Action,
+        latency: Option<time::Duration>,
     ) -> Result<(), Error> {
-        info!("Add route {pattern} => {action}, priority {priority}, source '{id}'");
+        if let Some(latency) = latency {
+            info!("Add route {pattern} => {action}, distance {distance}, cost {cost}, latency {latency}, source '{id}'");
+        } else {
+            info!(
+                "Add route {pattern} => {action}, distance {distance}, cost {cost}, source '{id}'"
+            );
+        }
+
+        if let Action::Forward(endpoint) = &action {
+            self.health
+                .write()
+                .await
+                .routes
+                .entry(endpoint.handle)
+                .or_default()
+                .insert(pattern.to_string(), pattern.clone());
+        }
 
         let mut entries = self.entries.write().await;
-        let entry = TableEntry { priority, action };
+        let entry = TableEntry {
+            distance,
+            cost,
+            action,
+            latency,
+        };
+        let mut routes = self.routes.write().await;
         if let Some(mut prev) = entries.insert(pattern, id.clone(), vec![entry.clone()]) {
-            // We have previous - de-dedup
-            if prev.binary_search(&entry).is_err() {
-                prev.push(entry);
+            // We have previous - de-dedup, keeping the entries ordered
+            if let Err(idx) = prev.binary_search(&entry) {
+                prev.insert(idx, entry);
             }
-            entries.insert(pattern, id, prev);
+            entries.insert(pattern, id.clone(), prev.clone());
+            routes.insert((id, pattern.clone()), prev);
+        } else {
+            routes.insert((id, pattern.clone()), vec![entry]);
         }
+        self.invalidate();
         Ok(())
     }
 
     #[instrument(skip_all)]
     pub async fn remove(&self, id: &str, pattern: &bpv7::EidPattern) -> Option<Vec<TableEntry>> {
-        self.entries.write().await.remove(pattern, id).inspect(|v| {
+        let removed = self.entries.write().await.remove(pattern, id);
+        self.routes
+            .write()
+            .await
+            .remove(&(id.to_string(), pattern.clone()));
+        if let Some(v) = &removed {
+            let mut health = self.health.write().await;
             for e in v {
                 info!(
-                    "Removed route {pattern} => {}, priority {}, source '{id}'",
-                    e.action, e.priority
+                    "Removed route {pattern} => {}, distance {}, cost {}, source '{id}'",
+                    e.action, e.distance, e.cost
                 );
+
+                if let Action::Forward(endpoint) = &e.action {
+                    if let Some(patterns) = health.routes.get_mut(&endpoint.handle) {
+                        patterns.remove(&pattern.to_string());
+                        if patterns.is_empty() {
+                            // The next hop has been withdrawn entirely
+                            health.routes.remove(&endpoint.handle);
+                            if let Some(event) = health.flap(endpoint.handle) {
+                                _ = self.events.send(event);
+                            }
+                        }
+                    }
+                }
             }
-        })
+            self.invalidate();
+        }
+        removed
+    }
+
+    // Every route, by source and pattern
+    pub async fn dump(&self) -> Vec<(String, bpv7::EidPattern, TableEntry)> {
+        let mut routes = self
+            .routes
+            .read()
+            .await
+            .iter()
+            .flat_map(|((id, pattern), entries)| {
+                entries
+                    .iter()
+                    .map(|entry| (id.clone(), pattern.clone(), entry.clone()))
+            })
+            .collect::<Vec<_>>();
+        routes.sort_by(|a, b| (&a.0, a.1.to_string(), &a.2).cmp(&(&b.0, b.1.to_string(), &b.2)));
+        routes
     }
 
+    // The endpoints are returned in the order they should be tried
     #[instrument(skip(self))]
     pub async fn find(&self, to: &bpv7::Eid) -> ForwardResult {
-        let mut action = {
-            // Scope the lock
-            let entries = self.entries.read().await;
-            find_recurse(&entries, to, &mut HashSet::new())?
-        };
+        let now = utils::clock::now();
+        if let Some(result) = self
+            .cache
+            .lock()
+            .trace_expect("Failed to lock mutex")
+            .get(to, now)
+        {
+            metrics::counter!("fib_lookups_total", "cache" => "hit").increment(1);
+            return result;
+        }
+        metrics::counter!("fib_lookups_total", "cache" => "miss").increment(1);
+
+        // Cache the result before the locks are released, so a change that clears the cache
+        // cannot be overtaken by a result from before it
+        let entries = self.entries.read().await;
+        let health = self.health.read().await;
+        let mut ties = Ties::new();
+        let result = find_recurse(&entries, &health, to, &mut HashSet::new(), &mut ties);
+        self.cache
+            .lock()
+            .trace_expect("Failed to lock mutex")
+            .insert(to, &result, &ties, now);
+        shuffle_ties(result, &ties)
+    }
+}
+
+// The ranges of the candidate next hops that are equal in rank and preference
+type Ties = Vec<std::ops::Range<usize>>;
 
-        if action.clas.len() > 1 {
-            // For ECMP, we need a random order
-            action.clas.shuffle(&mut rand::thread_rng());
+// Shuffle equal next hops, for ECMP
+fn shuffle_ties(mut result: ForwardResult, ties: &Ties) -> ForwardResult {
+    if let Ok(action) = &mut result {
+        if !ties.is_empty() {
+            utils::random::with_rng(|rng| {
+                for tie in ties {
+                    action.clas[tie.clone()].shuffle(rng);
+                }
+            });
         }
-        Ok(action)
     }
+    result
 }
 
-#[instrument(skip(table, trail))]
-fn find_recurse(table: &Table, to: &bpv7::Eid, trail: &mut HashSet<bpv7::Eid>) -> ForwardResult {
+// Only the ties of the outermost call matter, as the next hops found by recursion are
+// ranked again by the bin they are found from
+#[instrument(skip(table, health, trail, ties))]
+fn find_recurse(
+    table: &Table,
+    health: &Health,
+    to: &bpv7::Eid,
+    trail: &mut HashSet<bpv7::Eid>,
+    ties: &mut Ties,
+) -> ForwardResult {
     // TODO: We currently pick the first Drop action we find, and do not tie-break on reason...
 
     let mut new_action = ForwardAction {
         clas: Vec::new(),
         until: None,
+        resolve: Vec::new(),
     };
 
     // Recursion check
-    if trail.insert(to.clone()) {
-        // Flatten and Filter on lowest priority
-        // TODO: This is a fairly brutal binning by priority, keeping the lowest bin
-        let mut priority = None;
-        let mut entries = Vec::new();
-        for entry in table.find(to).into_iter().flatten() {
-            match priority {
-                Some(lowest_priority) if lowest_priority < entry.priority => continue,
-                Some(lowest_priority) if lowest_priority > entry.priority => entries.clear(),
-                _ => {}
+    if !trail.insert(to.clone()) {
+        return Ok(new_action);
+    }
+
+    // Flatten and rank, skipping inactive entries, so other routes can take over
+    let mut entries = table
+        .find(to)
+        .into_iter()
+        .flatten()
+        .filter(|entry| match &entry.action {
+            Action::Forward(endpoint) => health.is_usable(endpoint.handle),
+            _ => true,
+        })
+        .collect::<Vec<_>>();
+    entries.sort();
+
+    // With no route at all, the next hop may yet be resolved to a CLA address
+    if entries.is_empty() {
+        new_action.resolve.push(to.clone());
+    }
+
+    // Bin by distance and cost
+    let mut bins: Vec<Vec<&TableEntry>> = Vec::new();
+    for entry in entries {
+        match bins.last_mut() {
+            Some(bin) if bin[0].distance == entry.distance && bin[0].cost == entry.cost => {
+                bin.push(entry)
             }
-            priority = Some(entry.priority);
-            entries.push(entry.action.clone());
+            _ => bins.push(vec![entry]),
         }
+    }
 
-        for action in entries {
-            match action {
+    let mut bins = bins.into_iter();
+    if let Some(bin) = bins.next() {
+        // The lowest ranked bin decides the action
+        let mut clas = Vec::new();
+        for entry in bin {
+            match &entry.action {
                 Action::Via(via) => {
-                    let action = find_recurse(table, &via, trail)?;
+                    let action = find_recurse(table, health, via, trail, &mut Ties::new())?;
                     new_action.until = match (new_action.until, action.until) {
                         (None, Some(_)) => action.until,
                         (_, None) => new_action.until,
@@ -151,28 +749,216 @@ fn find_recurse(table: &Table, to: &bpv7::Eid, trail: &mut HashSet<bpv7::Eid>) -
                             Some(new_until.min(current_until))
                         }
                     };
-                    new_action.clas.extend(action.clas)
+                    clas.extend(add_latency(action.clas, entry.latency));
+                    add_resolve(&mut new_action.resolve, action.resolve);
                 }
                 Action::Forward(c) => {
-                    new_action.clas.push(c);
+                    clas.extend(add_latency(vec![c.clone()], entry.latency));
                 }
                 Action::Drop(reason) => {
                     // Drop trumps everything else
-                    return Err(reason);
+                    trail.remove(to);
+                    return Err(*reason);
                 }
                 Action::Wait(until) => {
                     // Check we don't have a deadline in the past
-                    if until >= time::OffsetDateTime::now_utc() {
+                    if *until >= utils::clock::now() {
                         new_action.until = match new_action.until {
-                            None => Some(until),
-                            Some(new_until) if new_until > until => Some(until),
+                            None => Some(*until),
+                            Some(new_until) if new_until > *until => Some(*until),
                             w => w,
                         };
                     }
                 }
             }
         }
-        trail.remove(to);
+        add_candidates(&mut new_action.clas, clas, health, ties);
+
+        // If we are forwarding, higher ranked forwarding routes are fallbacks, in order
+        if !new_action.clas.is_empty() {
+            for bin in bins {
+                let mut clas = Vec::new();
+                for entry in bin {
+                    match &entry.action {
+                        Action::Forward(c) => {
+                            clas.extend(add_latency(vec![c.clone()], entry.latency))
+                        }
+                        Action::Via(via) => {
+                            // A fallback that leads nowhere is no fallback at all
+                            if let Ok(action) =
+                                find_recurse(table, health, via, trail, &mut Ties::new())
+                            {
+                                clas.extend(add_latency(action.clas, entry.latency));
+                                add_resolve(&mut new_action.resolve, action.resolve);
+                            }
+                        }
+                        Action::Drop(_) | Action::Wait(_) => {}
+                    }
+                }
+                add_candidates(&mut new_action.clas, clas, health, ties);
+            }
+        }
     }
+    trail.remove(to);
     Ok(new_action)
 }
+
+// Add the latency of a hop to the latency of the endpoints it leads to.  An unknown latency
+// counts as none, so bundles are only ever dropped on the strength of known latencies
+fn add_latency(clas: Vec<Endpoint>, latency: Option<time::Duration>) -> Vec<Endpoint> {
+    let Some(latency) = latency else {
+        return clas;
+    };
+    clas.into_iter()
+        .map(|mut c| {
+            c.latency = Some(c.latency.map_or(latency, |l| l + latency));
+            c
+        })
+        .collect()
+}
+
+// Append the endpoints of a bin of equal rank, ordered by the preference of their CLAs, skipping
+// any already reachable by a lower ranked route.  Runs of equal preference are added to `ties`
+fn add_candidates(
+    candidates: &mut Vec<Endpoint>,
+    clas: Vec<Endpoint>,
+    health: &Health,
+    ties: &mut Ties,
+) {
+    let same = |a: &Endpoint, b: &Endpoint| a.handle == b.handle && a.address == b.address;
+    let mut bin: Vec<Endpoint> = Vec::new();
+    for c in clas {
+        if !candidates.iter().chain(bin.iter()).any(|e| same(e, &c)) {
+            bin.push(c);
+        }
+    }
+    bin.sort_by_key(|c| health.preference(c.handle));
+
+    let mut start = candidates.len();
+    candidates.extend(bin);
+    while start < candidates.len() {
+        let preference = health.preference(candidates[start].handle);
+        let end = candidates[start..]
+            .iter()
+            .position(|c| health.preference(c.handle) != preference)
+            .map_or(candidates.len(), |n| start + n);
+        if end - start > 1 {
+            ties.push(start..end);
+        }
+        start = end;
+    }
+}
+
+fn add_resolve(resolve: &mut Vec<bpv7::Eid>, eids: Vec<bpv7::Eid>) {
+    for eid in eids {
+        if !resolve.contains(&eid) {
+            resolve.push(eid);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dampening() {
+        let mut dampening = Dampening::new(DampeningConfig {
+            half_life: 60,
+            penalty: 1000.0,
+            suppress: 2000.0,
+            reuse: 750.0,
+            max_suppress: 240,
+        });
+        let now = time::OffsetDateTime::now_utc();
+
+        // Suppressed on the third flap in quick succession
+        assert_eq!(dampening.flap(1, now), None);
+        assert_eq!(dampening.flap(1, now), None);
+        let until = dampening.flap(1, now).unwrap();
+        assert!(dampening.is_suppressed(1));
+        assert!(!dampening.is_suppressed(2));
+
+        // 3000 decays to 750 in two half-lives
+        assert!(((until - now) - time::Duration::seconds(120)).abs() < time::Duration::SECOND);
+        assert!(dampening
+            .reuse(1, now + time::Duration::seconds(60))
+            .is_some());
+        assert_eq!(dampening.reuse(1, until + time::Duration::SECOND), None);
+        assert!(!dampening.is_suppressed(1));
+
+        // However much it flaps, the penalty is capped by the maximum suppression time
+        for _ in 0..100 {
+            dampening.flap(2, now);
+        }
+        assert!(dampening
+            .reuse(2, now + time::Duration::seconds(239))
+            .is_some());
+        assert_eq!(dampening.reuse(2, now + time::Duration::seconds(241)), None);
+    }
+
+    fn endpoint(handle: u32) -> Endpoint {
+        Endpoint {
+            handle,
+            address: None,
+            mtu: None,
+            latency: None,
+        }
+    }
+
+    #[test]
+    fn preference() {
+        let mut health = Health::default();
+        health.preferences.insert(
+            1,
+            Preference {
+                cost: 10,
+                latency: cla_registry::LatencyClass::Low,
+                best_effort: false,
+            },
+        );
+        health.preferences.insert(
+            2,
+            Preference {
+                cost: 0,
+                latency: cla_registry::LatencyClass::High,
+                best_effort: false,
+            },
+        );
+        health.preferences.insert(
+            5,
+            Preference {
+                cost: 0,
+                latency: cla_registry::LatencyClass::Medium,
+                best_effort: true,
+            },
+        );
+
+        // The default preference is no cost and medium latency, and 4 is already a candidate
+        let mut candidates = vec![endpoint(4)];
+        let mut ties = Ties::new();
+        add_candidates(
+            &mut candidates,
+            (1..=5).map(endpoint).collect(),
+            &health,
+            &mut ties,
+        );
+        assert_eq!(
+            candidates
+                .iter()
+                .map(|endpoint| endpoint.handle)
+                .collect::<Vec<_>>(),
+            [4, 3, 5, 2, 1]
+        );
+        assert!(ties.is_empty());
+
+        // Equals are left for shuffling on every lookup
+        add_candidates(
+            &mut candidates,
+            (6..=8).map(endpoint).collect(),
+            &health,
+            &mut ties,
+        );
+        assert_eq!(ties, [5..8]);
+    }
+}
@@ -0,0 +1,740 @@
+use super::*;
+use admin_server::{Admin, AdminServer};
+use hardy_proto::admin::*;
+use tonic::{Request, Response, Status};
+
+pub struct Service {
+    store: Arc<store::Store>,
+
+    // Read-only replicas have no dispatcher, nor CLAs, applications or routes
+    dispatcher: Option<Arc<dispatcher::Dispatcher>>,
+    cla_registry: Option<cla_registry::ClaRegistry>,
+    app_registry: Option<app_registry::AppRegistry>,
+    fib: Option<fib::Fib>,
+    authz: Option<Arc<authz::Authz>>,
+}
+
+impl Service {
+    fn new(
+        _config: &config::Config,
+        store: Arc<store::Store>,
+        dispatcher: Option<Arc<dispatcher::Dispatcher>>,
+        cla_registry: Option<cla_registry::ClaRegistry>,
+        app_registry: Option<app_registry::AppRegistry>,
+        fib: Option<fib::Fib>,
+        authz: Option<Arc<authz::Authz>>,
+    ) -> Self {
+        Service {
+            store,
+            dispatcher,
+            cla_registry,
+            app_registry,
+            fib,
+            authz,
+        }
+    }
+
+    // The helpers box the Status to keep their Results small
+    fn authorize<T>(&self, request: &Request<T>, method: &str) -> Result<(), Box<Status>> {
+        match &self.authz {
+            Some(authz) => authz.check(request, method),
+            None => Ok(()),
+        }
+    }
+
+    fn dispatcher(&self, action: &str) -> Result<&Arc<dispatcher::Dispatcher>, Box<Status>> {
+        self.dispatcher.as_ref().ok_or_else(|| {
+            Box::new(Status::failed_precondition(format!(
+                "Bundles cannot be {action} by a read-only replica"
+            )))
+        })
+    }
+}
+
+fn to_storage_engine(
+    (name, schema_version): (&str, Option<String>),
+    capabilities: hardy_bpa_api::storage::Capabilities,
+) -> StorageEngine {
+    StorageEngine {
+        name: name.to_string(),
+        schema_version,
+        capabilities: utils::capabilities::storage_capabilities(capabilities),
+    }
+}
+
+fn parse_pattern(destination: &str) -> Result<bpv7::EidPattern, Box<Status>> {
+    destination.parse::<bpv7::EidPattern>().map_err(|e| {
+        Box::new(Status::invalid_argument(format!(
+            "Invalid destination pattern: {e}"
+        )))
+    })
+}
+
+fn parse_bundle_id(bundle_id: &str) -> Result<bpv7::BundleId, Box<Status>> {
+    bpv7::BundleId::from_key(bundle_id)
+        .map_err(|e| Box::new(Status::invalid_argument(format!("Invalid bundle id: {e}"))))
+}
+
+fn status_name(status: &metadata::BundleStatus) -> String {
+    match status {
+        metadata::BundleStatus::IngressPending => "ingress pending".to_string(),
+        metadata::BundleStatus::DispatchPending => "dispatch pending".to_string(),
+        metadata::BundleStatus::ReassemblyPending => "reassembly pending".to_string(),
+        metadata::BundleStatus::CollectionPending => "collection pending".to_string(),
+        metadata::BundleStatus::ForwardPending => "forward pending".to_string(),
+        metadata::BundleStatus::ForwardAckPending(handle, until) => {
+            format!("awaiting forwarding by CLA {handle} until {until}")
+        }
+        metadata::BundleStatus::Waiting(until) => format!("waiting until {until}"),
+        metadata::BundleStatus::Tombstone(since) => format!("tombstone since {since}"),
+    }
+}
+
+fn to_stored_bundle(bundle: &metadata::Bundle) -> StoredBundle {
+    StoredBundle {
+        bundle_id: bundle.bundle.id.to_key(),
+        source: bundle.bundle.id.source.to_string(),
+        destination: bundle.bundle.destination.to_string(),
+        status: status_name(&bundle.metadata.status),
+        expiry: Some(to_timestamp(bundle.expiry())),
+    }
+}
+
+fn to_trust(trust: Option<i32>) -> Result<Option<dispatcher::Trust>, Box<Status>> {
+    match trust.map(Trust::try_from) {
+        None => Ok(None),
+        Some(Ok(Trust::Trusted)) => Ok(Some(dispatcher::Trust::Trusted)),
+        Some(Ok(Trust::Distrusted)) => Ok(Some(dispatcher::Trust::Distrusted)),
+        _ => Err(Box::new(Status::invalid_argument("Invalid trust"))),
+    }
+}
+
+fn from_trust(trust: dispatcher::Trust) -> Trust {
+    match trust {
+        dispatcher::Trust::Trusted => Trust::Trusted,
+        dispatcher::Trust::Distrusted => Trust::Distrusted,
+    }
+}
+
+#[tonic::async_trait]
+impl Admin for Service {
+    #[instrument(skip(self))]
+    async fn redispatch(
+        &self,
+        request: Request<RedispatchRequest>,
+    ) -> Result<Response<RedispatchResponse>, Status> {
+        self.authorize(&request, "Redispatch").map_err(|e| *e)?;
+        let dispatcher = self.dispatcher("re-dispatched").map_err(|e| *e)?;
+
+        let pattern = parse_pattern(&request.into_inner().destination).map_err(|e| *e)?;
+
+        dispatcher
+            .redispatch(&pattern)
+            .await
+            .map(|count| Response::new(RedispatchResponse { count }))
+            .map_err(Status::from_error)
+    }
+
+    #[instrument(skip(self))]
+    async fn list_waiting(
+        &self,
+        request: Request<ListWaitingRequest>,
+    ) -> Result<Response<ListWaitingResponse>, Status> {
+        self.authorize(&request, "ListWaiting").map_err(|e| *e)?;
+        let pattern = parse_pattern(&request.into_inner().destination).map_err(|e| *e)?;
+
+        let (tx, mut rx) = tokio::sync::mpsc::channel::<metadata::Bundle>(16);
+        let store = self.store.clone();
+        let h = tokio::spawn(async move { store.get_waiting_bundles_matching(&pattern, tx).await });
+
+        let mut bundles = Vec::new();
+        while let Some(bundle) = rx.recv().await {
+            bundles.push(WaitingBundle {
+                bundle_id: bundle.bundle.id.to_key(),
+                destination: bundle.bundle.destination.to_string(),
+                expiry: Some(to_timestamp(bundle.expiry())),
+                until: match bundle.metadata.status {
+                    metadata::BundleStatus::Waiting(until) => Some(to_timestamp(until)),
+                    _ => None,
+                },
+            });
+        }
+
+        h.await
+            .trace_expect("Task terminated unexpectedly")
+            .map_err(Status::from_error)?;
+
+        Ok(Response::new(ListWaitingResponse { bundles }))
+    }
+
+    #[instrument(skip(self))]
+    async fn check_consistency(
+        &self,
+        request: Request<CheckConsistencyRequest>,
+    ) -> Result<Response<CheckConsistencyResponse>, Status> {
+        self.authorize(&request, "CheckConsistency")
+            .map_err(|e| *e)?;
+        let Some(dispatcher) = &self.dispatcher else {
+            return Err(Status::failed_precondition(
+                "The store cannot be checked by a read-only replica",
+            ));
+        };
+
+        // Dropping the request stops the check
+        self.store
+            .consistency_check(dispatcher, &tokio_util::sync::CancellationToken::new())
+            .await
+            .map(|r| {
+                Response::new(CheckConsistencyResponse {
+                    checked: r.checked,
+                    missing: r.missing,
+                    corrupt: r.corrupt,
+                })
+            })
+            .map_err(|e| Status::unavailable(e.to_string()))
+    }
+
+    #[instrument(skip(self))]
+    async fn get_config(
+        &self,
+        request: Request<GetConfigRequest>,
+    ) -> Result<Response<GetConfigResponse>, Status> {
+        self.authorize(&request, "GetConfig").map_err(|e| *e)?;
+        Ok(Response::new(GetConfigResponse {
+            settings: utils::settings::effective().into_iter().collect(),
+        }))
+    }
+
+    #[instrument(skip(self))]
+    async fn get_capabilities(
+        &self,
+        request: Request<GetCapabilitiesRequest>,
+    ) -> Result<Response<GetCapabilitiesResponse>, Status> {
+        self.authorize(&request, "GetCapabilities")
+            .map_err(|e| *e)?;
+        let clas = match &self.cla_registry {
+            Some(cla_registry) => cla_registry
+                .snapshot()
+                .await
+                .into_iter()
+                .map(|cla| RegisteredCla {
+                    ident: cla.ident,
+                    name: cla.name,
+                })
+                .collect(),
+            None => Vec::new(),
+        };
+
+        Ok(Response::new(GetCapabilitiesResponse {
+            version: utils::built_info::PKG_VERSION.to_string(),
+            features: utils::capabilities::features(),
+            bpsec_contexts: utils::capabilities::bpsec_contexts(),
+            metadata_storage: Some(to_storage_engine(
+                self.store.metadata_engine(),
+                self.store.metadata_capabilities(),
+            )),
+            bundle_storage: Some(to_storage_engine(
+                self.store.bundle_engine(),
+                self.store.bundle_capabilities(),
+            )),
+            clas,
+        }))
+    }
+
+    #[instrument(skip(self))]
+    async fn list_peers(
+        &self,
+        request: Request<ListPeersRequest>,
+    ) -> Result<Response<ListPeersResponse>, Status> {
+        self.authorize(&request, "ListPeers").map_err(|e| *e)?;
+        let Some(peers) = self
+            .dispatcher
+            .as_ref()
+            .and_then(|dispatcher| dispatcher.peer_reputation())
+        else {
+            return Err(Status::failed_precondition(
+                "Peer reputation is not enabled",
+            ));
+        };
+
+        Ok(Response::new(ListPeersResponse {
+            peers: peers
+                .into_iter()
+                .map(|peer| PeerReputation {
+                    peer: peer.peer.to_string(),
+                    received: peer.received,
+                    malformed: peer.malformed,
+                    expired: peer.expired,
+                    duplicate: peer.duplicate,
+                    score: peer.score,
+                    trust: peer.trust.map(|trust| from_trust(trust) as i32),
+                    policed: peer.policed,
+                })
+                .collect(),
+        }))
+    }
+
+    #[instrument(skip(self))]
+    async fn set_peer_trust(
+        &self,
+        request: Request<SetPeerTrustRequest>,
+    ) -> Result<Response<SetPeerTrustResponse>, Status> {
+        self.authorize(&request, "SetPeerTrust").map_err(|e| *e)?;
+        let request = request.into_inner();
+        let peer = request
+            .peer
+            .parse::<bpv7::Eid>()
+            .map_err(|e| Status::invalid_argument(format!("Invalid peer EID: {e}")))?;
+        let trust = to_trust(request.trust).map_err(|e| *e)?;
+
+        if !self
+            .dispatcher
+            .as_ref()
+            .is_some_and(|dispatcher| dispatcher.set_peer_trust(peer, trust))
+        {
+            return Err(Status::failed_precondition(
+                "Peer reputation is not enabled",
+            ));
+        }
+        Ok(Response::new(SetPeerTrustResponse {}))
+    }
+
+    #[instrument(skip(self))]
+    async fn list_peer_capabilities(
+        &self,
+        request: Request<ListPeerCapabilitiesRequest>,
+    ) -> Result<Response<ListPeerCapabilitiesResponse>, Status> {
+        self.authorize(&request, "ListPeerCapabilities")
+            .map_err(|e| *e)?;
+        let Some(peers) = self
+            .dispatcher
+            .as_ref()
+            .and_then(|dispatcher| dispatcher.peer_capabilities())
+        else {
+            return Err(Status::failed_precondition("Peer cache is not enabled"));
+        };
+
+        Ok(Response::new(ListPeerCapabilitiesResponse {
+            peers: peers
+                .into_iter()
+                .map(|peer| PeerCapabilities {
+                    node_id: peer.node_id.to_string(),
+                    bpsec_contexts: peer.capabilities.bpsec_contexts.into_iter().collect(),
+                    max_bundle_size: peer.capabilities.max_bundle_size,
+                    ipn3_element: peer.capabilities.ipn_3_element,
+                    advertised: peer.advertised,
+                    updated: Some(to_timestamp(peer.updated)),
+                })
+                .collect(),
+        }))
+    }
+
+    #[instrument(skip(self))]
+    async fn list_bundles(
+        &self,
+        request: Request<ListBundlesRequest>,
+    ) -> Result<Response<ListBundlesResponse>, Status> {
+        self.authorize(&request, "ListBundles").map_err(|e| *e)?;
+        let request = request.into_inner();
+        let pattern = parse_pattern(&request.destination).map_err(|e| *e)?;
+        let max_bundles = request.max_bundles.map(|max| max as usize);
+
+        let (tx, mut rx) = tokio::sync::mpsc::channel::<metadata::Bundle>(16);
+        let store = self.store.clone();
+        let h = tokio::spawn(async move { store.get_stored_bundles(tx).await });
+
+        let mut bundles = Vec::new();
+        while let Some(bundle) = rx.recv().await {
+            if max_bundles.is_some_and(|max| bundles.len() >= max) {
+                break;
+            }
+            if pattern.is_match(&bundle.bundle.destination) {
+                bundles.push(to_stored_bundle(&bundle));
+            }
+        }
+        drop(rx);
+
+        h.await
+            .trace_expect("Task terminated unexpectedly")
+            .map_err(Status::from_error)?;
+
+        Ok(Response::new(ListBundlesResponse { bundles }))
+    }
+
+    #[instrument(skip(self))]
+    async fn get_bundle(
+        &self,
+        request: Request<GetBundleRequest>,
+    ) -> Result<Response<GetBundleResponse>, Status> {
+        self.authorize(&request, "GetBundle").map_err(|e| *e)?;
+        let bundle_id = parse_bundle_id(&request.into_inner().bundle_id).map_err(|e| *e)?;
+        let Some(bundle) = self
+            .store
+            .load(&bundle_id)
+            .await
+            .map_err(Status::from_error)?
+        else {
+            return Err(Status::not_found("No such bundle"));
+        };
+
+        let mut blocks = bundle.bundle.blocks.iter().collect::<Vec<_>>();
+        blocks.sort_by_key(|(block_number, _)| **block_number);
+
+        Ok(Response::new(GetBundleResponse {
+            bundle: Some(to_stored_bundle(&bundle)),
+            report_to: bundle.bundle.report_to.to_string(),
+            created: Some(to_timestamp(bundle.creation_time())),
+            lifetime: bundle.bundle.lifetime,
+            previous_node: bundle
+                .bundle
+                .previous_node
+                .as_ref()
+                .map(|previous_node| previous_node.to_string()),
+            hop_count: bundle
+                .bundle
+                .hop_count
+                .as_ref()
+                .map(|hop_info| hop_info.count),
+            priority: format!("{:?}", bundle.metadata.priority),
+            storage_name: bundle
+                .metadata
+                .storage_name
+                .as_ref()
+                .map(|storage_name| storage_name.to_string()),
+            hash: bundle
+                .metadata
+                .hash
+                .as_ref()
+                .map(|hash| hash.to_vec().into()),
+            received_at: bundle.metadata.received_at.map(to_timestamp),
+            payload_length: bundle
+                .bundle
+                .blocks
+                .get(&1)
+                .map_or(0, |block| block.payload_len as u64),
+            block_types: blocks
+                .into_iter()
+                .map(|(_, block)| block.block_type.into())
+                .collect(),
+        }))
+    }
+
+    #[instrument(skip(self))]
+    async fn redispatch_bundle(
+        &self,
+        request: Request<RedispatchBundleRequest>,
+    ) -> Result<Response<RedispatchBundleResponse>, Status> {
+        self.authorize(&request, "RedispatchBundle")
+            .map_err(|e| *e)?;
+        let dispatcher = self.dispatcher("re-dispatched").map_err(|e| *e)?;
+        let bundle_id = parse_bundle_id(&request.into_inner().bundle_id).map_err(|e| *e)?;
+
+        if !dispatcher
+            .redispatch_bundle(&bundle_id)
+            .await
+            .map_err(Status::from_error)?
+        {
+            return Err(Status::not_found("No such bundle awaiting dispatch"));
+        }
+        Ok(Response::new(RedispatchBundleResponse {}))
+    }
+
+    #[instrument(skip(self))]
+    async fn delete_bundle(
+        &self,
+        request: Request<DeleteBundleRequest>,
+    ) -> Result<Response<DeleteBundleResponse>, Status> {
+        self.authorize(&request, "DeleteBundle").map_err(|e| *e)?;
+        let dispatcher = self.dispatcher("deleted").map_err(|e| *e)?;
+        let bundle_id = parse_bundle_id(&request.into_inner().bundle_id).map_err(|e| *e)?;
+
+        if !dispatcher
+            .delete_bundle(&bundle_id)
+            .await
+            .map_err(Status::from_error)?
+        {
+            return Err(Status::not_found("No such bundle"));
+        }
+        Ok(Response::new(DeleteBundleResponse {}))
+    }
+
+    #[instrument(skip(self))]
+    async fn list_applications(
+        &self,
+        request: Request<ListApplicationsRequest>,
+    ) -> Result<Response<ListApplicationsResponse>, Status> {
+        self.authorize(&request, "ListApplications")
+            .map_err(|e| *e)?;
+        let applications = match &self.app_registry {
+            // Never reveal the tokens
+            Some(app_registry) => app_registry
+                .snapshot()
+                .await
+                .into_iter()
+                .map(|app| RegisteredApplication {
+                    eid: app.eid,
+                    ident: app.ident,
+                    grpc_address: app.grpc_address,
+                    ordered_delivery: app.ordered_delivery,
+                })
+                .collect(),
+            None => Vec::new(),
+        };
+        Ok(Response::new(ListApplicationsResponse { applications }))
+    }
+
+    #[instrument(skip(self))]
+    async fn list_routes(
+        &self,
+        request: Request<ListRoutesRequest>,
+    ) -> Result<Response<ListRoutesResponse>, Status> {
+        self.authorize(&request, "ListRoutes").map_err(|e| *e)?;
+        let Some(fib) = &self.fib else {
+            return Err(Status::failed_precondition("Forwarding is disabled"));
+        };
+
+        Ok(Response::new(ListRoutesResponse {
+            routes: fib
+                .dump()
+                .await
+                .into_iter()
+                .map(|(source, pattern, entry)| FibRoute {
+                    source,
+                    destination: pattern.to_string(),
+                    distance: entry.distance,
+                    cost: entry.cost,
+                    action: entry.action.to_string(),
+                    latency: entry.latency.map(|latency| latency.whole_seconds() as u64),
+                })
+                .collect(),
+        }))
+    }
+
+    type PingStream = tokio_stream::wrappers::ReceiverStream<Result<PingResponse, Status>>;
+
+    #[instrument(skip(self))]
+    async fn ping(
+        &self,
+        request: Request<PingRequest>,
+    ) -> Result<Response<Self::PingStream>, Status> {
+        self.authorize(&request, "Ping").map_err(|e| *e)?;
+        let Some(dispatcher) = self.dispatcher.clone() else {
+            return Err(Status::failed_precondition(
+                "A read-only replica cannot send pings",
+            ));
+        };
+        let request = request.into_inner();
+        let destination = request
+            .destination
+            .parse::<bpv7::Eid>()
+            .map_err(|e| Status::invalid_argument(format!("Invalid destination: {e}")))?;
+        let count = request.count.unwrap_or(4);
+        let interval = std::time::Duration::from_millis(request.interval.unwrap_or(1000));
+        let timeout = time::Duration::milliseconds(
+            request.timeout.unwrap_or(10000).min(i64::MAX as u64) as i64,
+        );
+
+        let (tx, rx) = tokio::sync::mpsc::channel(16);
+        tokio::spawn(async move {
+            // Pings are sent every interval, whether or not the previous ones have returned
+            let mut pings = tokio::task::JoinSet::new();
+            for sequence in 0..count {
+                if sequence != 0 {
+                    tokio::select! {
+                        _ = tokio::time::sleep(interval) => {}
+                        _ = tx.closed() => return,
+                    }
+                }
+                let dispatcher = dispatcher.clone();
+                let destination = destination.clone();
+                let tx = tx.clone();
+                pings.spawn(async move {
+                    let response = dispatcher
+                        .ping(destination, timeout)
+                        .await
+                        .map(|round_trip| PingResponse {
+                            sequence,
+                            round_trip: round_trip.map(to_duration),
+                        })
+                        .map_err(|e| Status::unavailable(e.to_string()));
+                    _ = tx.send(response).await;
+                });
+            }
+            while pings.join_next().await.is_some() {}
+        });
+        Ok(Response::new(tokio_stream::wrappers::ReceiverStream::new(
+            rx,
+        )))
+    }
+
+    type WatchStatusReportsStream =
+        tokio_stream::wrappers::ReceiverStream<Result<StatusReportEvent, Status>>;
+
+    #[instrument(skip(self))]
+    async fn watch_status_reports(
+        &self,
+        request: Request<WatchStatusReportsRequest>,
+    ) -> Result<Response<Self::WatchStatusReportsStream>, Status> {
+        self.authorize(&request, "WatchStatusReports")
+            .map_err(|e| *e)?;
+        let Some(dispatcher) = &self.dispatcher else {
+            return Err(Status::failed_precondition(
+                "A read-only replica does not receive status reports",
+            ));
+        };
+        let source = request
+            .into_inner()
+            .source
+            .map(|source| parse_pattern(&source))
+            .transpose()
+            .map_err(|e| *e)?;
+
+        let mut reports = dispatcher.watch_status_reports();
+        let (tx, rx) = tokio::sync::mpsc::channel(16);
+        tokio::spawn(async move {
+            let mut lagged = 0;
+            loop {
+                let event = tokio::select! {
+                    r = reports.recv() => match r {
+                        Ok(event) => event,
+                        Err(tokio::sync::broadcast::error::RecvError::Lagged(n)) => {
+                            lagged += n;
+                            continue;
+                        }
+                        Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+                    },
+                    _ = tx.closed() => break,
+                };
+                if source
+                    .as_ref()
+                    .is_some_and(|source| !source.is_match(&event.report.bundle_id.source))
+                {
+                    continue;
+                }
+                if tx
+                    .send(Ok(to_status_report_event(&event, lagged)))
+                    .await
+                    .is_err()
+                {
+                    break;
+                }
+                lagged = 0;
+            }
+        });
+        Ok(Response::new(tokio_stream::wrappers::ReceiverStream::new(
+            rx,
+        )))
+    }
+
+    type WatchWakeRequestsStream =
+        tokio_stream::wrappers::ReceiverStream<Result<WakeRequestEvent, Status>>;
+
+    #[instrument(skip(self))]
+    async fn watch_wake_requests(
+        &self,
+        request: Request<WatchWakeRequestsRequest>,
+    ) -> Result<Response<Self::WatchWakeRequestsStream>, Status> {
+        self.authorize(&request, "WatchWakeRequests")
+            .map_err(|e| *e)?;
+        let Some(mut requests) = self
+            .dispatcher
+            .as_ref()
+            .and_then(|dispatcher| dispatcher.watch_wake_requests())
+        else {
+            return Err(Status::failed_precondition("No CLAs are duty-cycled"));
+        };
+
+        let (tx, rx) = tokio::sync::mpsc::channel(16);
+        tokio::spawn(async move {
+            let mut lagged = 0;
+            loop {
+                let request = tokio::select! {
+                    r = requests.recv() => match r {
+                        Ok(request) => request,
+                        Err(tokio::sync::broadcast::error::RecvError::Lagged(n)) => {
+                            lagged += n;
+                            continue;
+                        }
+                        Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+                    },
+                    _ = tx.closed() => break,
+                };
+                if tx
+                    .send(Ok(WakeRequestEvent {
+                        cla: request.cla,
+                        lagged,
+                    }))
+                    .await
+                    .is_err()
+                {
+                    break;
+                }
+                lagged = 0;
+            }
+        });
+        Ok(Response::new(tokio_stream::wrappers::ReceiverStream::new(
+            rx,
+        )))
+    }
+
+    #[instrument(skip(self))]
+    async fn set_link_ready(
+        &self,
+        request: Request<SetLinkReadyRequest>,
+    ) -> Result<Response<SetLinkReadyResponse>, Status> {
+        self.authorize(&request, "SetLinkReady").map_err(|e| *e)?;
+        let request = request.into_inner();
+        let Some(dispatcher) = &self.dispatcher else {
+            return Err(Status::failed_precondition(
+                "A read-only replica does not forward bundles",
+            ));
+        };
+        if !dispatcher.set_link_ready(&request.cla, request.ready).await {
+            return Err(Status::not_found(format!(
+                "CLA '{}' is not duty-cycled",
+                request.cla
+            )));
+        }
+        Ok(Response::new(SetLinkReadyResponse {}))
+    }
+}
+
+fn to_status_assertion(assertion: &Option<bpv7::StatusAssertion>) -> Option<StatusAssertion> {
+    assertion.as_ref().map(|assertion| StatusAssertion {
+        at: assertion.0.map(|t| to_timestamp(t.into())),
+    })
+}
+
+fn to_status_report_event(event: &dispatcher::StatusReportEvent, lagged: u64) -> StatusReportEvent {
+    StatusReportEvent {
+        reported_by: event.reported_by.to_string(),
+        bundle_id: event.report.bundle_id.to_key(),
+        source: event.report.bundle_id.source.to_string(),
+        received: to_status_assertion(&event.report.received),
+        forwarded: to_status_assertion(&event.report.forwarded),
+        delivered: to_status_assertion(&event.report.delivered),
+        deleted: to_status_assertion(&event.report.deleted),
+        reason: event.report.reason.into(),
+        lagged,
+    }
+}
+
+pub fn new_service(
+    config: &config::Config,
+    store: Arc<store::Store>,
+    dispatcher: Option<Arc<dispatcher::Dispatcher>>,
+    cla_registry: Option<cla_registry::ClaRegistry>,
+    app_registry: Option<app_registry::AppRegistry>,
+    fib: Option<fib::Fib>,
+    authz: Option<Arc<authz::Authz>>,
+) -> AdminServer<Service> {
+    AdminServer::new(Service::new(
+        config,
+        store,
+        dispatcher,
+        cla_registry,
+        app_registry,
+        fib,
+        authz,
+    ))
+}
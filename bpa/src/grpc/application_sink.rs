@@ -4,9 +4,13 @@ use hardy_proto::application::*;
 use tokio::sync::mpsc::*;
 use tonic::{Request, Response, Status};
 
+// How long a send stream waits before checking again whether the dispatcher is still busy
+const BUSY_PAUSE: std::time::Duration = std::time::Duration::from_millis(100);
+
 pub struct Service {
     app_registry: app_registry::AppRegistry,
     dispatcher: Arc<dispatcher::Dispatcher>,
+    registration_auth: Option<Arc<authn::RegistrationAuth>>,
 }
 
 impl Service {
@@ -14,10 +18,12 @@ impl Service {
         _config: &config::Config,
         app_registry: app_registry::AppRegistry,
         dispatcher: Arc<dispatcher::Dispatcher>,
+        registration_auth: Option<Arc<authn::RegistrationAuth>>,
     ) -> Self {
         Service {
             app_registry,
             dispatcher,
+            registration_auth,
         }
     }
 }
@@ -29,6 +35,11 @@ impl ApplicationSink for Service {
         &self,
         request: Request<RegisterApplicationRequest>,
     ) -> Result<Response<RegisterApplicationResponse>, Status> {
+        if let Some(registration_auth) = &self.registration_auth {
+            registration_auth
+                .check_application(&authn::Credentials::of(&request))
+                .map_err(|e| *e)?;
+        }
         self.app_registry
             .register(request.into_inner())
             .await
@@ -48,55 +59,73 @@ impl ApplicationSink for Service {
 
     #[instrument(skip(self))]
     async fn send(&self, request: Request<SendRequest>) -> Result<Response<SendResponse>, Status> {
-        let request = request.into_inner();
-        let mut send_request = dispatcher::SendRequest {
-            source: self.app_registry.find_by_token(&request.token).await?,
-            destination: match request
-                .destination
-                .parse::<bpv7::Eid>()
-                .map_err(|e| Status::from_error(e.into()))?
-            {
-                bpv7::Eid::Null => {
-                    return Err(Status::invalid_argument("Cannot send to Null endpoint"))
+        send_one(&self.app_registry, &self.dispatcher, request.into_inner())
+            .await
+            .map(Response::new)
+    }
+
+    type SendStreamStream =
+        tokio_stream::wrappers::ReceiverStream<Result<SendStreamResponse, Status>>;
+
+    #[instrument(skip(self))]
+    async fn send_stream(
+        &self,
+        request: Request<tonic::Streaming<SendStreamRequest>>,
+    ) -> Result<Response<Self::SendStreamStream>, Status> {
+        let mut stream = request.into_inner();
+        let app_registry = self.app_registry.clone();
+        let dispatcher = self.dispatcher.clone();
+        let (tx, rx) = channel(16);
+
+        /* Bundles are sent one at a time, in order.  While the dispatcher is busy the next
+         * request is not read, so gRPC flow control holds back the application, rather than
+         * the bundles piling up in memory here */
+        tokio::spawn(async move {
+            loop {
+                while dispatcher.is_busy() {
+                    tokio::select! {
+                        _ = tokio::time::sleep(BUSY_PAUSE) => {},
+                        _ = tx.closed() => return,
+                    }
                 }
-                eid => eid,
-            },
-            data: request.data,
-            lifetime: request.lifetime,
-            ..Default::default()
-        };
 
-        if let Some(flags) = request.flags {
-            let mut bundle_flags = bpv7::BundleFlags::default();
-            if flags & (send_request::SendFlags::DoNotFragment as u32) != 0 {
-                bundle_flags.do_not_fragment = true;
-            }
-            if flags & (send_request::SendFlags::RequestAck as u32) != 0 {
-                bundle_flags.app_ack_requested = true;
-            }
-            if flags & (send_request::SendFlags::ReportStatusTime as u32) != 0 {
-                bundle_flags.report_status_time = true;
-            }
-            if flags & (send_request::SendFlags::NotifyReception as u32) != 0 {
-                bundle_flags.receipt_report_requested = true;
-            }
-            if flags & (send_request::SendFlags::NotifyForwarding as u32) != 0 {
-                bundle_flags.forward_report_requested = true;
-            }
-            if flags & (send_request::SendFlags::NotifyDelivery as u32) != 0 {
-                bundle_flags.delivery_report_requested = true;
-            }
-            if flags & (send_request::SendFlags::NotifyDeletion as u32) != 0 {
-                bundle_flags.delete_report_requested = true;
-            }
-            send_request.flags = Some(bundle_flags);
-        }
+                let request = match stream.message().await {
+                    Ok(Some(request)) => request,
+                    Ok(None) => break,
+                    Err(status) => {
+                        _ = tx.send(Err(status)).await;
+                        break;
+                    }
+                };
+                let Some(send) = request.request else {
+                    _ = tx
+                        .send(Err(Status::invalid_argument("No send request")))
+                        .await;
+                    break;
+                };
 
-        self.dispatcher
-            .local_dispatch(send_request)
-            .await
-            .map(|_| Response::new(SendResponse {}))
-            .map_err(Status::from_error)
+                // A bundle that cannot be sent does not end the stream
+                let result = match send_one(&app_registry, &dispatcher, send).await {
+                    Ok(response) => send_stream_response::Result::Response(response),
+                    Err(status) => {
+                        send_stream_response::Result::Error(status.message().to_string())
+                    }
+                };
+                if tx
+                    .send(Ok(SendStreamResponse {
+                        sequence: request.sequence,
+                        result: Some(result),
+                    }))
+                    .await
+                    .is_err()
+                {
+                    break;
+                }
+            }
+        });
+        Ok(Response::new(tokio_stream::wrappers::ReceiverStream::new(
+            rx,
+        )))
     }
 
     #[instrument(skip(self))]
@@ -105,11 +134,27 @@ impl ApplicationSink for Service {
         request: Request<CollectRequest>,
     ) -> Result<Response<CollectResponse>, Status> {
         let request = request.into_inner();
+        let (destination, registration) = self
+            .app_registry
+            .find_registration_by_token(&request.token)
+            .await?;
+        let acknowledged = self
+            .app_registry
+            .acknowledged_delivery(&request.token)
+            .await;
         let Some(response) = self
             .dispatcher
             .collect(
-                self.app_registry.find_by_token(&request.token).await?,
+                destination,
+                registration,
+                acknowledged,
                 request.bundle_id,
+                (request.offset.is_some() || request.length.is_some()).then(|| {
+                    dispatcher::PayloadRange {
+                        offset: request.offset.unwrap_or(0),
+                        len: request.length,
+                    }
+                }),
             )
             .await
             .map_err(Status::from_error)?
@@ -122,6 +167,9 @@ impl ApplicationSink for Service {
             data: response.data,
             expiry: Some(to_timestamp(response.expiry)),
             ack_requested: response.app_ack_requested,
+            latency: response.latency.map(to_duration),
+            payload_length: response.payload_len,
+            delivery_id: response.delivery_id,
         }))
     }
 
@@ -142,7 +190,7 @@ impl ApplicationSink for Service {
                 // Double check that we are returning something valid
                 if let metadata::BundleStatus::CollectionPending = &bundle.metadata.status {
                     let expiry = bundle.expiry();
-                    if expiry > time::OffsetDateTime::now_utc()
+                    if expiry > utils::clock::now()
                         && tx_outer
                             .send(Ok(PollResponse {
                                 bundle_id: bundle.bundle.id.to_key(),
@@ -157,21 +205,217 @@ impl ApplicationSink for Service {
             }
         });
 
+        let (destination, registration) = self
+            .app_registry
+            .find_registration_by_token(&request.token)
+            .await?;
+        let budget = hardy_bpa_api::storage::ScanBudget {
+            max_bundles: request.max_bundles.map(|max| max as usize),
+            ..Default::default()
+        };
         self.dispatcher
-            .poll_for_collection(
-                self.app_registry.find_by_token(&request.token).await?,
-                tx_inner,
-            )
+            .poll_for_collection(destination, registration, budget, tx_inner)
             .await
             .map_err(Status::from_error)
             .map(|_| Response::new(tokio_stream::wrappers::ReceiverStream::new(rx_outer)))
     }
+
+    type SubscribeStream = tokio_stream::wrappers::ReceiverStream<Result<CollectResponse, Status>>;
+
+    #[instrument(skip(self))]
+    async fn subscribe(
+        &self,
+        request: Request<SubscribeRequest>,
+    ) -> Result<Response<Self::SubscribeStream>, Status> {
+        let request = request.into_inner();
+        let (destination, registration) = self
+            .app_registry
+            .find_registration_by_token(&request.token)
+            .await?;
+        let acknowledged = self
+            .app_registry
+            .acknowledged_delivery(&request.token)
+            .await;
+
+        // Keep the channels short, so the application's pace holds back delivery
+        let (tx_inner, mut rx_inner) = channel::<dispatcher::CollectResponse>(1);
+        let (tx_outer, rx_outer) = channel(1);
+
+        // Stream the response
+        tokio::spawn(async move {
+            while let Some(response) = rx_inner.recv().await {
+                if tx_outer
+                    .send(Ok(CollectResponse {
+                        bundle_id: response.bundle_id,
+                        data: response.data,
+                        expiry: Some(to_timestamp(response.expiry)),
+                        ack_requested: response.app_ack_requested,
+                        latency: response.latency.map(to_duration),
+                        payload_length: response.payload_len,
+                        delivery_id: response.delivery_id,
+                    }))
+                    .await
+                    .is_err()
+                {
+                    break;
+                }
+            }
+        });
+
+        self.dispatcher
+            .subscribe(destination, registration, acknowledged, tx_inner);
+        Ok(Response::new(tokio_stream::wrappers::ReceiverStream::new(
+            rx_outer,
+        )))
+    }
+
+    #[instrument(skip(self))]
+    async fn acknowledge(
+        &self,
+        request: Request<AcknowledgeRequest>,
+    ) -> Result<Response<AcknowledgeResponse>, Status> {
+        let request = request.into_inner();
+        let (destination, registration) = self
+            .app_registry
+            .find_registration_by_token(&request.token)
+            .await?;
+        self.dispatcher
+            .acknowledge(destination, registration, request.delivery_ids)
+            .await
+            .map(|_| Response::new(AcknowledgeResponse {}))
+            .map_err(Status::from_error)
+    }
+
+    type WatchOutcomesStream = tokio_stream::wrappers::ReceiverStream<Result<SendOutcome, Status>>;
+
+    #[instrument(skip(self))]
+    async fn watch_outcomes(
+        &self,
+        request: Request<WatchOutcomesRequest>,
+    ) -> Result<Response<Self::WatchOutcomesStream>, Status> {
+        let source = self
+            .app_registry
+            .find_by_token(&request.into_inner().token)
+            .await?;
+        let mut outcomes = self.dispatcher.watch_outcomes();
+        let (tx, rx) = channel(16);
+
+        // Stream the outcomes of the bundles this application sent, until it goes away
+        tokio::spawn(async move {
+            loop {
+                let outcome = match outcomes.recv().await {
+                    Ok(outcome) => outcome,
+                    Err(tokio::sync::broadcast::error::RecvError::Lagged(n)) => {
+                        warn!("Outcome watcher for {source} missed {n} outcomes");
+                        continue;
+                    }
+                    Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+                };
+                if outcome.bundle_id.source != source {
+                    continue;
+                }
+                if tx
+                    .send(Ok(SendOutcome {
+                        bundle_id: outcome.bundle_id.to_key(),
+                        kind: outcome.kind as i32,
+                        reason: outcome.reason.into(),
+                        timestamp: outcome.timestamp.map(to_timestamp),
+                        reported_by: outcome.reported_by.to_string(),
+                    }))
+                    .await
+                    .is_err()
+                {
+                    break;
+                }
+            }
+        });
+        Ok(Response::new(tokio_stream::wrappers::ReceiverStream::new(
+            rx,
+        )))
+    }
+}
+
+async fn send_one(
+    app_registry: &app_registry::AppRegistry,
+    dispatcher: &dispatcher::Dispatcher,
+    request: SendRequest,
+) -> Result<SendResponse, Status> {
+    let mut send_request = dispatcher::SendRequest {
+        source: app_registry.find_by_token(&request.token).await?,
+        destination: match request
+            .destination
+            .parse::<bpv7::Eid>()
+            .map_err(|e| Status::from_error(e.into()))?
+        {
+            bpv7::Eid::Null => {
+                return Err(Status::invalid_argument("Cannot send to Null endpoint"))
+            }
+            eid => eid,
+        },
+        data: request.data,
+        lifetime: request.lifetime,
+        track: request.track.unwrap_or(false),
+        ..Default::default()
+    };
+
+    if let Some(flags) = request.flags {
+        let mut bundle_flags = bpv7::BundleFlags::default();
+        if flags & (send_request::SendFlags::DoNotFragment as u32) != 0 {
+            bundle_flags.do_not_fragment = true;
+        }
+        if flags & (send_request::SendFlags::RequestAck as u32) != 0 {
+            bundle_flags.app_ack_requested = true;
+        }
+        if flags & (send_request::SendFlags::ReportStatusTime as u32) != 0 {
+            bundle_flags.report_status_time = true;
+        }
+        if flags & (send_request::SendFlags::NotifyReception as u32) != 0 {
+            bundle_flags.receipt_report_requested = true;
+        }
+        if flags & (send_request::SendFlags::NotifyForwarding as u32) != 0 {
+            bundle_flags.forward_report_requested = true;
+        }
+        if flags & (send_request::SendFlags::NotifyDelivery as u32) != 0 {
+            bundle_flags.delivery_report_requested = true;
+        }
+        if flags & (send_request::SendFlags::NotifyDeletion as u32) != 0 {
+            bundle_flags.delete_report_requested = true;
+        }
+        send_request.flags = Some(bundle_flags);
+    }
+
+    // Tell the application to back off, rather than accepting a growing backlog
+    if app_registry.congestion_signalling(&request.token).await {
+        if let Some(backoff) = dispatcher.send_backoff(&send_request.destination).await {
+            return Ok(SendResponse {
+                result: send_response::SendResult::TryLater as i32,
+                backoff: Some(to_duration(backoff)),
+                bundle_id: String::new(),
+            });
+        }
+    }
+
+    dispatcher
+        .local_dispatch(send_request)
+        .await
+        .map(|bundle_id| SendResponse {
+            result: send_response::SendResult::Accepted as i32,
+            backoff: None,
+            bundle_id: bundle_id.to_key(),
+        })
+        .map_err(Status::from_error)
 }
 
 pub fn new_service(
     config: &config::Config,
     app_registry: app_registry::AppRegistry,
     dispatcher: Arc<dispatcher::Dispatcher>,
+    registration_auth: Option<Arc<authn::RegistrationAuth>>,
 ) -> ApplicationSinkServer<Service> {
-    ApplicationSinkServer::new(Service::new(config, app_registry, dispatcher))
+    ApplicationSinkServer::new(Service::new(
+        config,
+        app_registry,
+        dispatcher,
+        registration_auth,
+    ))
 }
@@ -0,0 +1,318 @@
+use super::*;
+use sha2::Digest;
+use std::collections::HashMap;
+use tonic::{metadata::MetadataMap, transport::CertificateDer, Request, Status};
+
+/* Registration of CLAs and applications can be restricted to known principals, so that
+ * untrusted processes that can reach the gRPC address cannot register as CLAs.  Each request
+ * is passed to the authenticators in turn until one recognises the principal that made it:
+ * by a bearer token in the 'authorization' metadata, or by the SHA-256 fingerprint of the
+ * client certificate presented over mutual TLS, see 'grpc_tls'.  A principal may register
+ * the CLAs listed in its 'clas', '*' for any, and applications if 'applications' is set.
+ * Refusals are written to the audit log.  Without a 'registration_auth' section, anyone may
+ * register anything */
+
+// What a request carries that may identify who made it
+pub struct Credentials<'a> {
+    pub metadata: &'a MetadataMap,
+    pub peer_certs: Option<Arc<Vec<CertificateDer<'static>>>>,
+}
+
+impl<'a> Credentials<'a> {
+    pub fn of<T>(request: &'a Request<T>) -> Self {
+        Self {
+            metadata: request.metadata(),
+            peer_certs: request.peer_certs(),
+        }
+    }
+}
+
+// One means of establishing the principal that made a request
+pub trait Authenticator: Send + Sync {
+    // None if the request carries no credential of this kind, or one that is not known
+    fn authenticate(&self, credentials: &Credentials) -> Option<String>;
+}
+
+// Principals by bearer token
+pub struct TokenAuthenticator {
+    tokens: HashMap<String, String>,
+}
+
+impl Authenticator for TokenAuthenticator {
+    fn authenticate(&self, credentials: &Credentials) -> Option<String> {
+        credentials
+            .metadata
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .and_then(|token| self.tokens.get(token.trim()))
+            .cloned()
+    }
+}
+
+// Principals by the SHA-256 fingerprint of their TLS client certificate, which the TLS
+// handshake has already checked against the client CA
+pub struct CertificateAuthenticator {
+    fingerprints: HashMap<[u8; 32], String>,
+}
+
+impl Authenticator for CertificateAuthenticator {
+    fn authenticate(&self, credentials: &Credentials) -> Option<String> {
+        let leaf = credentials.peer_certs.as_ref()?.first()?;
+        let fingerprint: [u8; 32] = sha2::Sha256::digest(leaf).into();
+        self.fingerprints.get(&fingerprint).cloned()
+    }
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+struct Principal {
+    name: String,
+    token: Option<String>,
+    // The SHA-256 fingerprint of the client certificate, in hex
+    certificate: Option<String>,
+    #[serde(default)]
+    clas: Vec<String>,
+    #[serde(default)]
+    applications: bool,
+}
+
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+#[serde(default)]
+struct Config {
+    principals: Vec<Principal>,
+}
+
+struct Permissions {
+    clas: Vec<String>,
+    applications: bool,
+}
+
+impl Permissions {
+    fn may_register_cla(&self, name: &str) -> bool {
+        self.clas.iter().any(|cla| cla == "*" || cla == name)
+    }
+}
+
+pub struct RegistrationAuth {
+    authenticators: Vec<Box<dyn Authenticator>>,
+    permissions: HashMap<String, Permissions>,
+}
+
+impl RegistrationAuth {
+    pub fn new(config: &config::Config) -> Option<Self> {
+        let config = utils::settings::get_with_default::<Option<Config>, _>(
+            config,
+            "registration_auth",
+            None,
+        )
+        .trace_expect("Invalid 'registration_auth' section in configuration")?;
+
+        let mut tokens = HashMap::new();
+        let mut fingerprints = HashMap::new();
+        let mut permissions = HashMap::new();
+        for principal in config.principals {
+            if principal.token.is_none() && principal.certificate.is_none() {
+                error!(
+                    "Principal '{}' in 'registration_auth' has neither a token nor a certificate",
+                    principal.name
+                );
+                panic!(
+                    "Principal '{}' in 'registration_auth' has neither a token nor a certificate",
+                    principal.name
+                );
+            }
+            if let Some(token) = principal.token {
+                if tokens.insert(token, principal.name.clone()).is_some() {
+                    error!(
+                        "Duplicate token for '{}' in 'registration_auth'",
+                        principal.name
+                    );
+                    panic!(
+                        "Duplicate token for '{}' in 'registration_auth'",
+                        principal.name
+                    );
+                }
+            }
+            if let Some(certificate) = principal.certificate {
+                let fingerprint = utils::settings::decode_hex(&certificate.replace(':', ""))
+                    .and_then(|fingerprint| <[u8; 32]>::try_from(&*fingerprint).ok())
+                    .trace_expect(&format!(
+                        "Invalid certificate fingerprint for '{}' in 'registration_auth'",
+                        principal.name
+                    ));
+                if fingerprints
+                    .insert(fingerprint, principal.name.clone())
+                    .is_some()
+                {
+                    error!(
+                        "Duplicate certificate for '{}' in 'registration_auth'",
+                        principal.name
+                    );
+                    panic!(
+                        "Duplicate certificate for '{}' in 'registration_auth'",
+                        principal.name
+                    );
+                }
+            }
+            permissions.insert(
+                principal.name,
+                Permissions {
+                    clas: principal.clas,
+                    applications: principal.applications,
+                },
+            );
+        }
+
+        if permissions.is_empty() {
+            warn!("'registration_auth' has no principals, every registration will be refused");
+        }
+        info!(
+            "Registration requires authentication, {} principals configured",
+            permissions.len()
+        );
+
+        let mut authenticators: Vec<Box<dyn Authenticator>> = Vec::new();
+        if !tokens.is_empty() {
+            authenticators.push(Box::new(TokenAuthenticator { tokens }));
+        }
+        if !fingerprints.is_empty() {
+            authenticators.push(Box::new(CertificateAuthenticator { fingerprints }));
+        }
+        Some(Self {
+            authenticators,
+            permissions,
+        })
+    }
+
+    // The Status is boxed to keep the Result small
+    fn authenticate(
+        &self,
+        credentials: &Credentials,
+    ) -> Result<(String, &Permissions), Box<Status>> {
+        self.authenticators
+            .iter()
+            .find_map(|authenticator| authenticator.authenticate(credentials))
+            .and_then(|name| {
+                let permissions = self.permissions.get(&name)?;
+                Some((name, permissions))
+            })
+            .ok_or_else(|| {
+                warn!(target: "audit", "Unauthenticated registration refused");
+                metrics::counter!("registrations_refused_total", "reason" => "unauthenticated")
+                    .increment(1);
+                Box::new(Status::unauthenticated(
+                    "A valid bearer token or client certificate is required",
+                ))
+            })
+    }
+
+    // The principal registering the CLA, if it may
+    pub fn check_cla(&self, credentials: &Credentials, cla: &str) -> Result<String, Box<Status>> {
+        let (principal, permissions) = self.authenticate(credentials)?;
+        if !permissions.may_register_cla(cla) {
+            warn!(target: "audit", principal, cla, "CLA registration refused");
+            metrics::counter!("registrations_refused_total", "reason" => "permission_denied")
+                .increment(1);
+            return Err(Box::new(Status::permission_denied(format!(
+                "{principal} may not register the CLA '{cla}'"
+            ))));
+        }
+        info!(target: "audit", principal, cla, "CLA registration allowed");
+        Ok(principal)
+    }
+
+    pub fn check_application(&self, credentials: &Credentials) -> Result<(), Box<Status>> {
+        let (principal, permissions) = self.authenticate(credentials)?;
+        if !permissions.applications {
+            warn!(target: "audit", principal, "Application registration refused");
+            metrics::counter!("registrations_refused_total", "reason" => "permission_denied")
+                .increment(1);
+            return Err(Box::new(Status::permission_denied(format!(
+                "{principal} may not register applications"
+            ))));
+        }
+        info!(target: "audit", principal, "Application registration allowed");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CERTIFICATE: &[u8] = b"not really DER";
+
+    fn auth() -> RegistrationAuth {
+        RegistrationAuth {
+            authenticators: vec![
+                Box::new(TokenAuthenticator {
+                    tokens: [("tcp".to_string(), "tcpcl".to_string())].into(),
+                }),
+                Box::new(CertificateAuthenticator {
+                    fingerprints: [(sha2::Sha256::digest(CERTIFICATE).into(), "app".to_string())]
+                        .into(),
+                }),
+            ],
+            permissions: [
+                (
+                    "tcpcl".to_string(),
+                    Permissions {
+                        clas: vec!["TCPCLv4".to_string()],
+                        applications: false,
+                    },
+                ),
+                (
+                    "app".to_string(),
+                    Permissions {
+                        clas: Vec::new(),
+                        applications: true,
+                    },
+                ),
+            ]
+            .into(),
+        }
+    }
+
+    fn headers(token: Option<&str>) -> MetadataMap {
+        let mut metadata = MetadataMap::new();
+        if let Some(token) = token {
+            metadata.insert("authorization", format!("Bearer {token}").parse().unwrap());
+        }
+        metadata
+    }
+
+    #[test]
+    fn registration() {
+        let auth = auth();
+
+        let metadata = headers(Some("tcp"));
+        let credentials = Credentials {
+            metadata: &metadata,
+            peer_certs: None,
+        };
+        assert_eq!(
+            auth.check_cla(&credentials, "TCPCLv4").unwrap(),
+            "tcpcl".to_string()
+        );
+        assert!(auth.check_cla(&credentials, "LTPCL").is_err());
+        assert!(auth.check_application(&credentials).is_err());
+
+        let metadata = headers(None);
+        let credentials = Credentials {
+            metadata: &metadata,
+            peer_certs: Some(Arc::new(vec![CertificateDer::from(CERTIFICATE.to_vec())])),
+        };
+        assert!(auth.check_application(&credentials).is_ok());
+        assert!(auth.check_cla(&credentials, "TCPCLv4").is_err());
+
+        let metadata = headers(Some("wrong"));
+        let credentials = Credentials {
+            metadata: &metadata,
+            peer_certs: Some(Arc::new(vec![CertificateDer::from(b"other".to_vec())])),
+        };
+        assert_eq!(
+            auth.check_cla(&credentials, "TCPCLv4").unwrap_err().code(),
+            tonic::Code::Unauthenticated
+        );
+    }
+}
@@ -0,0 +1,188 @@
+use super::*;
+use std::collections::HashMap;
+use tonic::{Request, Status};
+
+/* Access to the admin service and the route API can be restricted by role, so that
+ * monitoring systems can be given credentials that cannot change anything.  Callers present
+ * a bearer token in the 'authorization' metadata, and the role of the token must be at least
+ * the role the policy requires of the method.  Reading state requires 'read_only', acting on
+ * bundles and links 'operator', and anything that drops bundles, changes routes or changes
+ * trust 'admin'.  The policy of any
+ * method can be overridden in configuration.  Calls to methods that require more than
+ * 'read_only' are written to the audit log, whether they are allowed or not.  Without an
+ * 'admin_auth' section, every caller may call every method */
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    ReadOnly,
+    Operator,
+    Admin,
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+struct Credential {
+    // Who the token was issued to, for the audit log
+    name: String,
+    token: String,
+    role: Role,
+}
+
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+#[serde(default)]
+struct Config {
+    tokens: Vec<Credential>,
+    // The role required by each method, by its name in the service definition
+    policy: HashMap<String, Role>,
+}
+
+// The role required by each method, unless configured otherwise.  Methods not listed,
+// including any added later, require 'admin'
+fn default_policy(method: &str) -> Role {
+    match method {
+        "ListWaiting"
+        | "GetCapabilities"
+        | "ListPeers"
+        | "ListPeerCapabilities"
+        | "ListBundles"
+        | "GetBundle"
+        | "ListApplications"
+        | "ListRoutes"
+        | "WatchStatusReports" => Role::ReadOnly,
+        "Redispatch" | "RedispatchBundle" | "Ping" | "WatchWakeRequests" | "SetLinkReady" => {
+            Role::Operator
+        }
+        _ => Role::Admin,
+    }
+}
+
+pub struct Authz {
+    // Name and role, by token
+    tokens: HashMap<String, (String, Role)>,
+    policy: HashMap<String, Role>,
+}
+
+impl Authz {
+    pub fn new(config: &config::Config) -> Option<Self> {
+        let config =
+            utils::settings::get_with_default::<Option<Config>, _>(config, "admin_auth", None)
+                .trace_expect("Invalid 'admin_auth' section in configuration")?;
+
+        let mut tokens = HashMap::new();
+        for credential in config.tokens {
+            if tokens
+                .insert(credential.token, (credential.name.clone(), credential.role))
+                .is_some()
+            {
+                error!("Duplicate token for '{}' in 'admin_auth'", credential.name);
+                panic!("Duplicate token for '{}' in 'admin_auth'", credential.name);
+            }
+        }
+        if tokens.is_empty() {
+            warn!("'admin_auth' has no tokens, the admin service will refuse every call");
+        }
+        info!(
+            "Admin service requires authorization, {} tokens configured",
+            tokens.len()
+        );
+        Some(Self {
+            tokens,
+            policy: config.policy,
+        })
+    }
+
+    pub fn check<T>(&self, request: &Request<T>, method: &str) -> Result<(), Box<Status>> {
+        let required = self
+            .policy
+            .get(method)
+            .copied()
+            .unwrap_or_else(|| default_policy(method));
+
+        let Some((name, role)) = request
+            .metadata()
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .and_then(|token| self.tokens.get(token.trim()))
+        else {
+            if required > Role::ReadOnly {
+                warn!(target: "audit", method, "Unauthenticated admin call refused");
+            }
+            metrics::counter!("admin_calls_refused_total", "reason" => "unauthenticated")
+                .increment(1);
+            return Err(Box::new(Status::unauthenticated(
+                "A valid bearer token is required",
+            )));
+        };
+
+        if *role < required {
+            warn!(target: "audit", principal = name, method, role = ?role, "Admin call refused");
+            metrics::counter!("admin_calls_refused_total", "reason" => "permission_denied")
+                .increment(1);
+            return Err(Box::new(Status::permission_denied(format!(
+                "{method} requires the {required:?} role"
+            ))));
+        }
+
+        if required > Role::ReadOnly {
+            info!(target: "audit", principal = name, method, role = ?role, "Admin call allowed");
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn authz() -> Authz {
+        Authz {
+            tokens: [
+                ("ro".to_string(), ("monitor".to_string(), Role::ReadOnly)),
+                ("op".to_string(), ("noc".to_string(), Role::Operator)),
+            ]
+            .into(),
+            policy: [("Ping".to_string(), Role::Admin)].into(),
+        }
+    }
+
+    fn request(token: Option<&str>) -> Request<()> {
+        let mut request = Request::new(());
+        if let Some(token) = token {
+            request
+                .metadata_mut()
+                .insert("authorization", format!("Bearer {token}").parse().unwrap());
+        }
+        request
+    }
+
+    #[test]
+    fn roles() {
+        let authz = authz();
+        assert!(authz.check(&request(Some("ro")), "ListBundles").is_ok());
+        assert!(authz.check(&request(Some("ro")), "Redispatch").is_err());
+        assert!(authz.check(&request(Some("op")), "Redispatch").is_ok());
+        assert!(authz.check(&request(Some("op")), "DeleteBundle").is_err());
+
+        // Configured policy overrides the default
+        assert!(authz.check(&request(Some("op")), "Ping").is_err());
+
+        // Unknown methods require admin
+        assert!(authz.check(&request(Some("op")), "SomethingNew").is_err());
+
+        assert_eq!(
+            authz
+                .check(&request(None), "ListBundles")
+                .unwrap_err()
+                .code(),
+            tonic::Code::Unauthenticated
+        );
+        assert_eq!(
+            authz
+                .check(&request(Some("wrong")), "ListBundles")
+                .unwrap_err()
+                .code(),
+            tonic::Code::Unauthenticated
+        );
+    }
+}
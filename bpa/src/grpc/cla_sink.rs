@@ -1,23 +1,124 @@
 use super::*;
 use cla_sink_server::{ClaSink, ClaSinkServer};
 use hardy_proto::cla::*;
+use tokio_util::bytes::{Bytes, BytesMut};
 use tonic::{Request, Response, Status};
 
+// The primary block of any sensible bundle fits in far less, so stop buffering if we have
+// this much of a streamed bundle and still cannot parse its primary block
+const MAX_PRIMARY_BLOCK_SIZE: usize = 16 * 1024;
+
 pub struct Service {
     cla_registry: cla_registry::ClaRegistry,
     dispatcher: Arc<dispatcher::Dispatcher>,
+    throttle: Option<throttle::Throttle>,
+    registration_auth: Option<Arc<authn::RegistrationAuth>>,
+}
+
+fn to_response(rejection: Option<dispatcher::Rejection>) -> ReceiveBundleResponse {
+    let Some(rejection) = rejection else {
+        return ReceiveBundleResponse::default();
+    };
+
+    let rejected = match &rejection {
+        dispatcher::Rejection::TooBig(_) => receive_bundle_response::Rejection::TooBig,
+        dispatcher::Rejection::Unintelligible(_) => {
+            receive_bundle_response::Rejection::Unintelligible
+        }
+        dispatcher::Rejection::PolicyDenied(_) => receive_bundle_response::Rejection::PolicyDenied,
+        dispatcher::Rejection::Duplicate => receive_bundle_response::Rejection::Duplicate,
+        dispatcher::Rejection::StorageFull(_) => receive_bundle_response::Rejection::StorageFull,
+    };
+    ReceiveBundleResponse {
+        rejected: Some(rejected as i32),
+        detail: rejection.to_string(),
+    }
 }
 
 impl Service {
     fn new(
-        _config: &config::Config,
+        config: &config::Config,
         cla_registry: cla_registry::ClaRegistry,
         dispatcher: Arc<dispatcher::Dispatcher>,
+        registration_auth: Option<Arc<authn::RegistrationAuth>>,
     ) -> Self {
         Service {
             cla_registry,
             dispatcher,
+            throttle: throttle::Throttle::new(config),
+            registration_auth,
+        }
+    }
+
+    // Refuse the bundle if the peer it arrived from is over its rate limit
+    async fn throttle(&self, handle: u32, source: &Bytes) -> Result<(), Status> {
+        let Some(throttle) = &self.throttle else {
+            return Ok(());
+        };
+        let name = self.cla_registry.name(handle).await;
+        if throttle.admit(handle, name.as_deref(), source, utils::clock::now()) {
+            return Ok(());
+        }
+
+        let name = name.unwrap_or_default();
+        trace!("Peer of CLA '{name}' is over its rate limit");
+        metrics::counter!("ingress_throttled_total", "cla" => name).increment(1);
+        Err(Status::resource_exhausted("Ingress rate limit reached"))
+    }
+
+    // Write the received start of a bundle, and the rest as it arrives, to storage
+    async fn receive_rest_streamed(
+        &self,
+        handle: u32,
+        source: Bytes,
+        data: BytesMut,
+        mut stream: tonic::Streaming<ReceiveBundleChunk>,
+    ) -> Result<Response<ReceiveBundleResponse>, Status> {
+        let (tx, rx) = tokio::sync::mpsc::channel(16);
+        let receive = async move {
+            let mut len = data.len();
+            if tx.send(data.to_vec()).await.is_err() {
+                // The store has failed, and will say why
+                return Ok(None);
+            }
+            while let Some(chunk) = stream.message().await? {
+                len += chunk.data.len();
+                if let Some(rejection) = self.dispatcher.check_size(len) {
+                    return Ok(Some(rejection));
+                }
+                if tx.send(chunk.data.to_vec()).await.is_err() {
+                    break;
+                }
+            }
+            Ok::<_, Status>(None)
+        };
+
+        let (stored, received) = tokio::join!(self.dispatcher.store_stream(rx), receive);
+        let stored = stored.map_err(Status::from_error);
+        let rejection = match received {
+            Ok(None) => {
+                let (storage_name, hash) = stored?;
+                self.dispatcher
+                    .record_stored_ingress(handle, &source, &storage_name)
+                    .await;
+                return self
+                    .dispatcher
+                    .receive_stored_bundle(storage_name, hash)
+                    .await
+                    .map(|rejection| Response::new(to_response(rejection)))
+                    .map_err(Status::from_error);
+            }
+            r => r,
+        };
+
+        // Don't keep the part of the bundle that was received
+        if let Ok((storage_name, _)) = &stored {
+            self.dispatcher
+                .discard_stream(storage_name)
+                .await
+                .map_err(Status::from_error)?;
         }
+        rejection.map(|rejection| Response::new(to_response(rejection)))
     }
 }
 
@@ -28,8 +129,16 @@ impl ClaSink for Service {
         &self,
         request: Request<RegisterClaRequest>,
     ) -> Result<Response<RegisterClaResponse>, Status> {
+        let owner = match &self.registration_auth {
+            Some(registration_auth) => Some(
+                registration_auth
+                    .check_cla(&authn::Credentials::of(&request), &request.get_ref().name)
+                    .map_err(|e| *e)?,
+            ),
+            None => None,
+        };
         self.cla_registry
-            .register(request.into_inner())
+            .register(request.into_inner(), owner)
             .await
             .map(Response::new)
     }
@@ -50,12 +159,120 @@ impl ClaSink for Service {
         &self,
         request: Request<ReceiveBundleRequest>,
     ) -> Result<Response<ReceiveBundleResponse>, Status> {
+        // Continue the trace of the CLA
+        #[cfg(feature = "otlp")]
+        utils::otlp::extract(request.metadata());
+
         let request = request.into_inner();
         self.cla_registry.exists(request.handle).await?;
+
+        // The CLA is clearly working
+        self.cla_registry.set_health(request.handle, true).await;
+
+        self.throttle(request.handle, &request.source).await?;
+
+        // Account for the bundle while we hold it, shedding if we are holding too much
+        let Some(_reservation) =
+            utils::memory::try_reserve(utils::memory::Subsystem::Ingress, request.bundle.len())
+        else {
+            return Err(Status::resource_exhausted("Ingress memory limit reached"));
+        };
+
+        self.dispatcher
+            .record_ingress(Some(request.handle), &request.source, &request.bundle)
+            .await;
         self.dispatcher
             .receive_bundle(request.bundle)
             .await
-            .map(|_| Response::new(ReceiveBundleResponse {}))
+            .map(|rejection| Response::new(to_response(rejection)))
+            .map_err(Status::from_error)
+    }
+
+    #[instrument(skip(self, request))]
+    async fn receive_bundle_stream(
+        &self,
+        request: Request<tonic::Streaming<ReceiveBundleChunk>>,
+    ) -> Result<Response<ReceiveBundleResponse>, Status> {
+        #[cfg(feature = "otlp")]
+        utils::otlp::extract(request.metadata());
+
+        let mut stream = request.into_inner();
+
+        // The first chunk identifies the CLA
+        let Some(chunk) = stream.message().await? else {
+            return Err(Status::invalid_argument("No bundle data received"));
+        };
+        self.cla_registry.exists(chunk.handle).await?;
+        self.cla_registry.set_health(chunk.handle, true).await;
+        self.throttle(chunk.handle, &chunk.source).await?;
+        let (handle, source) = (chunk.handle, chunk.source);
+
+        let Some(mut reservation) =
+            utils::memory::try_reserve(utils::memory::Subsystem::Ingress, chunk.data.len())
+        else {
+            return Err(Status::resource_exhausted("Ingress memory limit reached"));
+        };
+        let mut data = BytesMut::from(chunk.data.as_ref());
+
+        let mut peeked = false;
+        loop {
+            if let Some(rejection) = self.dispatcher.check_size(data.len()) {
+                return Ok(Response::new(to_response(Some(rejection))));
+            }
+
+            // Reject as early as possible, to save the CLA receiving the rest
+            if !peeked {
+                match bpv7::Bundle::peek_primary_block(&data) {
+                    Ok(Some(bundle)) => {
+                        peeked = true;
+                        if let Some(rejection) = self.dispatcher.check_primary_block(&bundle) {
+                            return Ok(Response::new(to_response(Some(rejection))));
+                        }
+                    }
+                    Ok(None) if data.len() > MAX_PRIMARY_BLOCK_SIZE => {
+                        return Ok(Response::new(to_response(Some(
+                            dispatcher::Rejection::Unintelligible(
+                                "No primary block found".to_string(),
+                            ),
+                        ))));
+                    }
+                    Ok(None) => {}
+                    Err(e) => {
+                        trace!("Unintelligible bundle received: {e}");
+                        return Ok(Response::new(to_response(Some(
+                            dispatcher::Rejection::Unintelligible(e.to_string()),
+                        ))));
+                    }
+                }
+            }
+
+            // Large bundles go straight to storage once the primary block has been checked
+            if peeked && self.dispatcher.should_stream(data.len()) {
+                drop(reservation);
+                return self
+                    .receive_rest_streamed(handle, source, data, stream)
+                    .await;
+            }
+
+            let Some(chunk) = stream.message().await? else {
+                break;
+            };
+
+            // Shed partial bundles too, rather than buffering without bound
+            if !reservation.try_grow(chunk.data.len()) {
+                return Err(Status::resource_exhausted("Ingress memory limit reached"));
+            }
+            data.extend_from_slice(&chunk.data);
+        }
+
+        let data = data.freeze();
+        self.dispatcher
+            .record_ingress(Some(handle), &source, &data)
+            .await;
+        self.dispatcher
+            .receive_bundle(data)
+            .await
+            .map(|rejection| Response::new(to_response(rejection)))
             .map_err(Status::from_error)
     }
 
@@ -66,6 +283,7 @@ impl ClaSink for Service {
     ) -> Result<Response<ConfirmForwardingResponse>, Status> {
         let request = request.into_inner();
         self.cla_registry.exists(request.handle).await?;
+        self.cla_registry.set_health(request.handle, true).await;
         self.dispatcher
             .confirm_forwarding(request.handle, &request.bundle_id)
             .await
@@ -93,12 +311,41 @@ impl ClaSink for Service {
             .await
             .map(|_| Response::new(RemoveNeighbourResponse {}))
     }
+
+    #[instrument(skip(self))]
+    async fn advertise_peer(
+        &self,
+        request: Request<AdvertisePeerRequest>,
+    ) -> Result<Response<AdvertisePeerResponse>, Status> {
+        let request = request.into_inner();
+        self.cla_registry.exists(request.handle).await?;
+        let node_id = request
+            .node_id
+            .parse::<bpv7::Eid>()
+            .map_err(|e| Status::invalid_argument(format!("Invalid node ID: {e}")))?;
+
+        self.dispatcher.advertise_peer(
+            &node_id,
+            dispatcher::PeerCapabilities {
+                bpsec_contexts: request.bpsec_contexts.into_iter().collect(),
+                max_bundle_size: request.max_bundle_size,
+                ipn_3_element: request.ipn3_element,
+            },
+        );
+        Ok(Response::new(AdvertisePeerResponse {}))
+    }
 }
 
 pub fn new_service(
     config: &config::Config,
     cla_registry: cla_registry::ClaRegistry,
     dispatcher: Arc<dispatcher::Dispatcher>,
+    registration_auth: Option<Arc<authn::RegistrationAuth>>,
 ) -> ClaSinkServer<Service> {
-    ClaSinkServer::new(Service::new(config, cla_registry, dispatcher))
+    ClaSinkServer::new(Service::new(
+        config,
+        cla_registry,
+        dispatcher,
+        registration_auth,
+    ))
 }
@@ -2,44 +2,187 @@ use super::*;
 use std::sync::Arc;
 use utils::settings;
 
+mod admin;
 mod application_sink;
+mod authn;
+mod authz;
 mod cla_sink;
+mod route_api;
+mod throttle;
 
 #[instrument(skip_all)]
+#[allow(clippy::too_many_arguments)]
 pub fn init(
     config: &config::Config,
+    listener: Option<std::net::TcpListener>,
+    store: Arc<store::Store>,
     cla_registry: cla_registry::ClaRegistry,
     app_registry: app_registry::AppRegistry,
     dispatcher: Arc<dispatcher::Dispatcher>,
+    fib: Option<fib::Fib>,
     task_set: &mut tokio::task::JoinSet<()>,
     cancel_token: tokio_util::sync::CancellationToken,
 ) {
-    // Get listen address from config
-    let grpc_address =
-        settings::get_with_default::<String, _>(config, "grpc_address", "[::1]:50051")
-            .trace_expect("Invalid 'grpc_address' value in configuration")
-            .parse()
-            .trace_expect("Invalid gRPC address and/or port in configuration");
+    let listener = bind(config, listener);
+
+    // Hand over to a new instance on request
+    #[cfg(unix)]
+    handoff::listen_for_upgrade(
+        std::os::fd::AsRawFd::as_raw_fd(&listener),
+        cla_registry.clone(),
+        app_registry.clone(),
+        task_set,
+        cancel_token.clone(),
+    );
+
+    // Both CLAs and applications register with the same principals
+    let registration_auth = authn::RegistrationAuth::new(config).map(Arc::new);
+
+    // Routes are managed under the same roles as the admin service
+    let authz = authz::Authz::new(config).map(Arc::new);
 
     // Add gRPC services to HTTP router
-    let router = tonic::transport::Server::builder()
+    let router = server(config)
         .add_service(cla_sink::new_service(
             config,
-            cla_registry,
+            cla_registry.clone(),
             dispatcher.clone(),
+            registration_auth.clone(),
         ))
         .add_service(application_sink::new_service(
             config,
-            app_registry,
-            dispatcher,
-        ));
+            app_registry.clone(),
+            dispatcher.clone(),
+            registration_auth,
+        ))
+        .add_service(admin::new_service(
+            config,
+            store,
+            Some(dispatcher),
+            Some(cla_registry),
+            Some(app_registry),
+            fib.clone(),
+            authz.clone(),
+        ))
+        // Routes can only be managed if forwarding is enabled
+        .add_optional_service(fib.map(|fib| route_api::new_service(config, fib, authz)));
+
+    serve(router, listener, task_set, cancel_token)
+}
+
+// A read-only replica only serves queries of the store
+#[instrument(skip_all)]
+pub fn init_replica(
+    config: &config::Config,
+    store: Arc<store::Store>,
+    task_set: &mut tokio::task::JoinSet<()>,
+    cancel_token: tokio_util::sync::CancellationToken,
+) {
+    let listener = bind(config, None);
+
+    // Add gRPC services to HTTP router
+    let router = server(config).add_service(admin::new_service(
+        config,
+        store,
+        None,
+        None,
+        None,
+        None,
+        authz::Authz::new(config).map(Arc::new),
+    ));
+
+    serve(router, listener, task_set, cancel_token)
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct TlsConfig {
+    // The PEM certificate chain and private key of the server
+    cert_file: std::path::PathBuf,
+    key_file: std::path::PathBuf,
+    // Verify the certificates of clients against the PEM CA certificates in this file
+    client_ca_file: Option<std::path::PathBuf>,
+    // Refuse clients without a certificate, rather than leaving them to other authentication
+    #[serde(default)]
+    require_client_certificates: bool,
+}
+
+fn read_pem(path: &std::path::Path) -> Vec<u8> {
+    std::fs::read(path).trace_expect(&format!("Failed to read '{}'", path.display()))
+}
+
+// A server, serving TLS if 'grpc_tls' is configured
+fn server(config: &config::Config) -> tonic::transport::Server {
+    let server = tonic::transport::Server::builder();
+    let Some(tls) = settings::get_with_default::<Option<TlsConfig>, _>(config, "grpc_tls", None)
+        .trace_expect("Invalid 'grpc_tls' section in configuration")
+    else {
+        return server;
+    };
+
+    let mut tls_config = tonic::transport::ServerTlsConfig::new().identity(
+        tonic::transport::Identity::from_pem(read_pem(&tls.cert_file), read_pem(&tls.key_file)),
+    );
+    if let Some(client_ca_file) = &tls.client_ca_file {
+        tls_config = tls_config
+            .client_ca_root(tonic::transport::Certificate::from_pem(read_pem(
+                client_ca_file,
+            )))
+            .client_auth_optional(!tls.require_client_certificates);
+        info!(
+            "gRPC server verifies client certificates, {}",
+            if tls.require_client_certificates {
+                "which are required"
+            } else {
+                "if presented"
+            }
+        );
+    } else if tls.require_client_certificates {
+        error!("'grpc_tls.require_client_certificates' requires 'grpc_tls.client_ca_file'");
+        panic!("'grpc_tls.require_client_certificates' requires 'grpc_tls.client_ca_file'");
+    }
+    server
+        .tls_config(tls_config)
+        .trace_expect("Invalid gRPC TLS configuration")
+}
+
+// Use the inherited listener, or bind to the listen address from config
+fn bind(config: &config::Config, listener: Option<std::net::TcpListener>) -> std::net::TcpListener {
+    let listener = listener.unwrap_or_else(|| {
+        let grpc_address: std::net::SocketAddr =
+            settings::get_with_default::<String, _>(config, "grpc_address", "[::1]:50051")
+                .trace_expect("Invalid 'grpc_address' value in configuration")
+                .parse()
+                .trace_expect("Invalid gRPC address and/or port in configuration");
+        std::net::TcpListener::bind(grpc_address)
+            .trace_expect(&format!("Failed to bind gRPC listener to {grpc_address}"))
+    });
+    listener
+        .set_nonblocking(true)
+        .trace_expect("Failed to configure gRPC listener");
+    listener
+}
+
+fn serve(
+    router: tonic::transport::server::Router,
+    listener: std::net::TcpListener,
+    task_set: &mut tokio::task::JoinSet<()>,
+    cancel_token: tokio_util::sync::CancellationToken,
+) {
+    let grpc_address = listener
+        .local_addr()
+        .trace_expect("Failed to get gRPC listener address");
+    let listener =
+        tokio::net::TcpListener::from_std(listener).trace_expect("Failed to start gRPC listener");
 
     // Start serving
     task_set.spawn(async move {
         router
-            .serve_with_shutdown(grpc_address, async {
-                cancel_token.cancelled().await;
-            })
+            .serve_with_incoming_shutdown(
+                tokio_stream::wrappers::TcpListenerStream::new(listener),
+                async {
+                    cancel_token.cancelled().await;
+                },
+            )
             .await
             .trace_expect("Failed to start gRPC server")
     });
@@ -60,3 +203,10 @@ pub fn to_timestamp(t: time::OffsetDateTime) -> prost_types::Timestamp {
         nanos: t.subsec_nanoseconds(),
     }
 }
+
+pub fn to_duration(d: time::Duration) -> prost_types::Duration {
+    prost_types::Duration {
+        seconds: d.whole_seconds(),
+        nanos: d.subsec_nanoseconds(),
+    }
+}
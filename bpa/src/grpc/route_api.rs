@@ -0,0 +1,279 @@
+use super::*;
+use hardy_proto::route_api::*;
+use route_api_server::{RouteApi, RouteApiServer};
+use std::collections::HashMap;
+use tonic::{Request, Response, Status};
+
+pub struct Service {
+    fib: fib::Fib,
+    authz: Option<Arc<authz::Authz>>,
+
+    // The routes added through this service, by source and destination
+    routes: tokio::sync::Mutex<HashMap<(String, bpv7::EidPattern), Vec<fib::TableEntry>>>,
+}
+
+impl Service {
+    fn new(_config: &config::Config, fib: fib::Fib, authz: Option<Arc<authz::Authz>>) -> Self {
+        Service {
+            fib,
+            authz,
+            routes: Default::default(),
+        }
+    }
+
+    fn authorize<T>(&self, request: &Request<T>, method: &str) -> Result<(), Box<Status>> {
+        match &self.authz {
+            Some(authz) => authz.check(request, method),
+            None => Ok(()),
+        }
+    }
+}
+
+// Routes from each source are kept apart from the routes of other sources in the FIB
+fn table_key(source: &str) -> String {
+    format!("route_api:{source}")
+}
+
+// The Status is boxed to keep the Result small, callers unbox it with `map_err(|e| *e)`
+fn parse_pattern(destination: &str) -> Result<bpv7::EidPattern, Box<Status>> {
+    destination.parse::<bpv7::EidPattern>().map_err(|e| {
+        Box::new(Status::invalid_argument(format!(
+            "Invalid destination pattern: {e}"
+        )))
+    })
+}
+
+fn to_action(action: Option<route::Action>) -> Result<fib::Action, Box<Status>> {
+    match action {
+        None => Err(Box::new(Status::invalid_argument("Missing route action"))),
+        Some(route::Action::Drop(action)) => Ok(fib::Action::Drop(
+            action
+                .reason
+                .map(bpv7::StatusReportReasonCode::try_from)
+                .transpose()
+                .map_err(|e| Status::invalid_argument(format!("Invalid drop reason: {e}")))?,
+        )),
+        Some(route::Action::Forward(handle)) => Ok(fib::Action::Forward(fib::Endpoint {
+            handle,
+            address: None,
+            mtu: None,
+            latency: None,
+        })),
+        Some(route::Action::Via(via)) => via
+            .parse()
+            .map(fib::Action::Via)
+            .map_err(|e| Box::new(Status::invalid_argument(format!("Invalid via EID: {e}")))),
+        Some(route::Action::Wait(until)) => from_timestamp(until)
+            .map(fib::Action::Wait)
+            .map_err(|e| Box::new(Status::invalid_argument(format!("Invalid wait time: {e}")))),
+    }
+}
+
+fn from_action(action: &fib::Action) -> route::Action {
+    match action {
+        fib::Action::Drop(reason) => route::Action::Drop(DropAction {
+            reason: reason.map(u64::from),
+        }),
+        fib::Action::Forward(endpoint) => route::Action::Forward(endpoint.handle),
+        fib::Action::Via(via) => route::Action::Via(via.to_string()),
+        fib::Action::Wait(until) => route::Action::Wait(to_timestamp(*until)),
+    }
+}
+
+#[tonic::async_trait]
+impl RouteApi for Service {
+    #[instrument(skip(self))]
+    async fn add_route(
+        &self,
+        request: Request<AddRouteRequest>,
+    ) -> Result<Response<AddRouteResponse>, Status> {
+        self.authorize(&request, "AddRoute").map_err(|e| *e)?;
+        let Some(route) = request.into_inner().route else {
+            return Err(Status::invalid_argument("Missing route"));
+        };
+        if route.source.is_empty() {
+            return Err(Status::invalid_argument("Missing route source"));
+        }
+        let pattern = parse_pattern(&route.destination).map_err(|e| *e)?;
+        let entry = fib::TableEntry {
+            distance: route.distance.unwrap_or(fib::DISTANCE_COMPUTED),
+            cost: route.cost,
+            action: to_action(route.action).map_err(|e| *e)?,
+            latency: route
+                .latency
+                .map(|latency| time::Duration::seconds(latency.min(i64::MAX as u64) as i64)),
+        };
+
+        // Hold the lock, so the FIB and our record of it agree
+        let mut routes = self.routes.lock().await;
+        self.fib
+            .add(
+                table_key(&route.source),
+                &pattern,
+                entry.distance,
+                entry.cost,
+                entry.action.clone(),
+                entry.latency,
+            )
+            .await
+            .map_err(Status::from_error)?;
+
+        let entries = routes.entry((route.source, pattern)).or_default();
+        if let Err(idx) = entries.binary_search(&entry) {
+            entries.insert(idx, entry);
+        }
+        Ok(Response::new(AddRouteResponse {}))
+    }
+
+    #[instrument(skip(self))]
+    async fn remove_route(
+        &self,
+        request: Request<RemoveRouteRequest>,
+    ) -> Result<Response<RemoveRouteResponse>, Status> {
+        self.authorize(&request, "RemoveRoute").map_err(|e| *e)?;
+        let request = request.into_inner();
+        let pattern = parse_pattern(&request.destination).map_err(|e| *e)?;
+
+        let mut routes = self.routes.lock().await;
+        let removed = self
+            .fib
+            .remove(&table_key(&request.source), &pattern)
+            .await
+            .map_or(0, |entries| entries.len() as u32);
+        routes.remove(&(request.source, pattern));
+
+        Ok(Response::new(RemoveRouteResponse { removed }))
+    }
+
+    #[instrument(skip(self))]
+    async fn list_routes(
+        &self,
+        request: Request<ListRoutesRequest>,
+    ) -> Result<Response<ListRoutesResponse>, Status> {
+        self.authorize(&request, "ListRoutes").map_err(|e| *e)?;
+        let source = request.into_inner().source;
+
+        let routes = self
+            .routes
+            .lock()
+            .await
+            .iter()
+            .filter(|((s, _), _)| source.as_ref().is_none_or(|source| source == s))
+            .flat_map(|((source, pattern), entries)| {
+                entries.iter().map(|entry| Route {
+                    source: source.clone(),
+                    destination: pattern.to_string(),
+                    distance: Some(entry.distance),
+                    cost: entry.cost,
+                    action: Some(from_action(&entry.action)),
+                    latency: entry.latency.map(|latency| latency.whole_seconds() as u64),
+                })
+            })
+            .collect();
+
+        Ok(Response::new(ListRoutesResponse { routes }))
+    }
+}
+
+pub fn new_service(
+    config: &config::Config,
+    fib: fib::Fib,
+    authz: Option<Arc<authz::Authz>>,
+) -> RouteApiServer<Service> {
+    RouteApiServer::new(Service::new(config, fib, authz))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request<T>(message: T, token: &str) -> Request<T> {
+        let mut request = Request::new(message);
+        request
+            .metadata_mut()
+            .insert("authorization", format!("Bearer {token}").parse().unwrap());
+        request
+    }
+
+    async fn listed(service: &Service, token: &str) -> Result<usize, Status> {
+        service
+            .list_routes(request(ListRoutesRequest::default(), token))
+            .await
+            .map(|response| response.into_inner().routes.len())
+    }
+
+    #[tokio::test]
+    async fn authorization() {
+        let config = config::Config::builder()
+            .add_source(config::File::from_str(
+                r#"
+                [[admin_auth.tokens]]
+                name = "monitor"
+                token = "ro"
+                role = "read_only"
+
+                [[admin_auth.tokens]]
+                name = "root"
+                token = "rw"
+                role = "admin"
+                "#,
+                config::FileFormat::Toml,
+            ))
+            .build()
+            .unwrap();
+        let service = Service::new(
+            &config,
+            fib::Fib::new(&config).unwrap(),
+            authz::Authz::new(&config).map(Arc::new),
+        );
+        let add = || AddRouteRequest {
+            route: Some(Route {
+                source: "test".to_string(),
+                destination: "ipn:2.*".to_string(),
+                action: Some(route::Action::Drop(DropAction { reason: None })),
+                ..Default::default()
+            }),
+        };
+        let remove = || RemoveRouteRequest {
+            source: "test".to_string(),
+            destination: "ipn:2.*".to_string(),
+        };
+
+        // Read-only callers may list the routes, but not change them
+        assert_eq!(
+            service
+                .add_route(request(add(), "ro"))
+                .await
+                .unwrap_err()
+                .code(),
+            tonic::Code::PermissionDenied
+        );
+        assert_eq!(listed(&service, "ro").await.unwrap(), 0);
+
+        assert!(service.add_route(request(add(), "rw")).await.is_ok());
+        assert_eq!(listed(&service, "ro").await.unwrap(), 1);
+        assert_eq!(
+            service
+                .remove_route(request(remove(), "ro"))
+                .await
+                .unwrap_err()
+                .code(),
+            tonic::Code::PermissionDenied
+        );
+        assert_eq!(
+            service
+                .remove_route(request(remove(), "rw"))
+                .await
+                .unwrap()
+                .into_inner()
+                .removed,
+            1
+        );
+
+        // Nothing with an unknown token
+        assert_eq!(
+            listed(&service, "wrong").await.unwrap_err().code(),
+            tonic::Code::Unauthenticated
+        );
+    }
+}
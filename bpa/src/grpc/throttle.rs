@@ -0,0 +1,198 @@
+use super::*;
+use std::collections::HashMap;
+use tokio_util::bytes::Bytes;
+
+/* Bundles received from CLAs may be rate limited by the 'ingress_rate_limit' settings, so
+ * a single misbehaving peer cannot starve the dispatcher.  Each peer has a token bucket,
+ * keyed by the handle of the CLA and the address of the peer the CLA reports in 'Source',
+ * or by the handle alone if the CLA reports no address.  The rate and burst may be set for
+ * all CLAs, and overridden for the CLAs registered under a name.  Bundles over the limit
+ * are refused with a RESOURCE_EXHAUSTED status, as when ingress memory runs short, which
+ * CLAs should treat as a signal to apply backpressure to their peer */
+
+// Forget peers whose buckets have refilled, once there are this many
+const MAX_BUCKETS: usize = 4096;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, serde::Deserialize)]
+#[serde(default)]
+struct Limit {
+    // Bundles per second, 0 for no limit
+    rate: f64,
+    // Bundles that may arrive at once, 0 for the rate
+    burst: f64,
+}
+
+impl Limit {
+    fn capacity(&self) -> f64 {
+        if self.burst > 0.0 {
+            self.burst
+        } else {
+            self.rate.max(1.0)
+        }
+    }
+}
+
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(default)]
+struct Config {
+    rate: f64,
+    burst: f64,
+    // Limits for the CLAs registered under a name
+    clas: HashMap<String, Limit>,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: time::OffsetDateTime,
+}
+
+impl Bucket {
+    // Refill up to `now`, returning true if the bucket is full
+    fn refill(&mut self, limit: &Limit, now: time::OffsetDateTime) -> bool {
+        let elapsed = (now - self.updated).as_seconds_f64();
+        if elapsed > 0.0 {
+            self.tokens = (self.tokens + elapsed * limit.rate).min(limit.capacity());
+            self.updated = now;
+        }
+        self.tokens >= limit.capacity()
+    }
+}
+
+pub struct Throttle {
+    limit: Limit,
+    clas: HashMap<String, Limit>,
+    buckets: std::sync::Mutex<HashMap<(u32, Bytes), (Limit, Bucket)>>,
+}
+
+impl Throttle {
+    pub fn new(config: &config::Config) -> Option<Self> {
+        let config = utils::settings::get_with_default::<Option<Config>, _>(
+            config,
+            "ingress_rate_limit",
+            None,
+        )
+        .trace_expect("Invalid 'ingress_rate_limit' section in configuration")?;
+
+        let throttle = Self::with_limits(
+            Limit {
+                rate: config.rate,
+                burst: config.burst,
+            },
+            config.clas,
+        );
+        if throttle.limit.rate > 0.0 {
+            info!(
+                "Rate limiting each CLA peer to {} bundles per second, bursts of {}",
+                throttle.limit.rate,
+                throttle.limit.capacity()
+            );
+        }
+        for (name, limit) in &throttle.clas {
+            if limit.rate > 0.0 {
+                info!(
+                    "Rate limiting each peer of CLA '{name}' to {} bundles per second, bursts of {}",
+                    limit.rate,
+                    limit.capacity()
+                );
+            } else {
+                info!("Not rate limiting the peers of CLA '{name}'");
+            }
+        }
+        Some(throttle)
+    }
+
+    fn with_limits(limit: Limit, clas: HashMap<String, Limit>) -> Self {
+        Self {
+            limit,
+            clas,
+            buckets: Default::default(),
+        }
+    }
+
+    fn limit_for(&self, name: Option<&str>) -> Limit {
+        name.and_then(|name| self.clas.get(name))
+            .copied()
+            .unwrap_or(self.limit)
+    }
+
+    // Take a token for a bundle arriving from `source` via the CLA, returning false if
+    // there are none left
+    pub fn admit(
+        &self,
+        handle: u32,
+        name: Option<&str>,
+        source: &Bytes,
+        now: time::OffsetDateTime,
+    ) -> bool {
+        let limit = self.limit_for(name);
+        if limit.rate <= 0.0 {
+            return true;
+        }
+
+        let mut buckets = self.buckets.lock().trace_expect("Failed to lock mutex");
+        let key = (handle, source.clone());
+        if buckets.len() >= MAX_BUCKETS && !buckets.contains_key(&key) {
+            // A full bucket is no different from a new one
+            buckets.retain(|_, (limit, bucket)| !bucket.refill(limit, now));
+        }
+
+        let (l, bucket) = buckets.entry(key).or_insert_with(|| {
+            (
+                limit,
+                Bucket {
+                    tokens: limit.capacity(),
+                    updated: now,
+                },
+            )
+        });
+        if *l != limit {
+            // The CLA has re-registered under another name
+            *l = limit;
+            bucket.tokens = bucket.tokens.min(limit.capacity());
+        }
+        bucket.refill(&limit, now);
+        if bucket.tokens < 1.0 {
+            return false;
+        }
+        bucket.tokens -= 1.0;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn token_bucket() {
+        let throttle = Throttle::with_limits(
+            Limit {
+                rate: 1.0,
+                burst: 2.0,
+            },
+            HashMap::from([("unlimited".to_string(), Limit::default())]),
+        );
+        let peer = Bytes::from_static(b"peer");
+        let now = time::OffsetDateTime::now_utc();
+
+        assert!(throttle.admit(1, None, &peer, now));
+        assert!(throttle.admit(1, None, &peer, now));
+        assert!(!throttle.admit(1, None, &peer, now));
+
+        // Other peers, and other CLAs, have their own buckets
+        assert!(throttle.admit(1, None, &Bytes::new(), now));
+        assert!(throttle.admit(2, None, &peer, now));
+
+        // The bucket refills at the rate
+        let now = now + time::Duration::milliseconds(500);
+        assert!(!throttle.admit(1, None, &peer, now));
+        let now = now + time::Duration::milliseconds(500);
+        assert!(throttle.admit(1, None, &peer, now));
+        assert!(!throttle.admit(1, None, &peer, now));
+
+        // Overridden for CLAs by name
+        for _ in 0..10 {
+            assert!(throttle.admit(3, Some("unlimited"), &peer, now));
+        }
+    }
+}
@@ -0,0 +1,338 @@
+use super::*;
+use hardy_cbor as cbor;
+use std::io::{Read, Seek, Write};
+
+/* A running instance hands over to a new binary when it receives SIGUSR2:
+ * it writes a snapshot of the CLA and application registries, then spawns the
+ * current executable with the gRPC listening socket inherited and these variables set.
+ * The new instance starts serving on the inherited socket immediately, and only
+ * checks the store once the previous instance has exited.
+ *
+ * The snapshot holds the tokens of the registered applications, so it is written to a
+ * file that is created exclusively, readable only by its owner, and unlinked before
+ * anything is written to it.  The new instance inherits the open file, not a path. */
+const LISTEN_FD_VAR: &str = "HARDY_BPA_LISTEN_FD";
+const STATE_FD_VAR: &str = "HARDY_BPA_HANDOFF_STATE_FD";
+const PID_VAR: &str = "HARDY_BPA_HANDOFF_PID";
+
+pub struct ClaState {
+    pub handle: u32,
+    pub ident: String,
+    pub instance_id: String,
+    pub name: String,
+    pub owner: Option<String>,
+    pub grpc_address: String,
+    pub capabilities: cla_registry::Capabilities,
+    pub neighbours: Vec<(String, u32, Option<u64>)>,
+}
+
+pub struct AppState {
+    pub eid: String,
+    pub token: String,
+    pub ident: String,
+    pub grpc_address: Option<String>,
+    pub ordered_delivery: bool,
+    pub congestion_signalling: bool,
+    pub acknowledged_delivery: bool,
+}
+
+pub struct Handoff {
+    pub listener: std::net::TcpListener,
+    state: std::fs::File,
+    previous_pid: u32,
+}
+
+#[cfg(unix)]
+pub fn take() -> Option<Handoff> {
+    use std::os::fd::FromRawFd;
+
+    let fd = std::env::var(LISTEN_FD_VAR).ok()?;
+    let fd = fd
+        .parse::<std::os::fd::RawFd>()
+        .trace_expect(&format!("Invalid {LISTEN_FD_VAR} value '{fd}'"));
+    let state_fd = std::env::var(STATE_FD_VAR)
+        .ok()
+        .and_then(|fd| fd.parse::<std::os::fd::RawFd>().ok())
+        .trace_expect(&format!("Invalid {STATE_FD_VAR} value"));
+    let previous_pid = std::env::var(PID_VAR)
+        .ok()
+        .and_then(|pid| pid.parse().ok())
+        .trace_expect(&format!("Invalid {PID_VAR} value"));
+
+    // Don't pass these on to any later instance
+    std::env::remove_var(LISTEN_FD_VAR);
+    std::env::remove_var(STATE_FD_VAR);
+    std::env::remove_var(PID_VAR);
+
+    info!("Taking over from previous instance (pid {previous_pid})");
+
+    Some(Handoff {
+        listener: unsafe { std::net::TcpListener::from_raw_fd(fd) },
+        state: unsafe { std::fs::File::from_raw_fd(state_fd) },
+        previous_pid,
+    })
+}
+
+#[cfg(not(unix))]
+pub fn take() -> Option<Handoff> {
+    None
+}
+
+impl Handoff {
+    #[instrument(skip_all)]
+    pub async fn restore(
+        &self,
+        cla_registry: &cla_registry::ClaRegistry,
+        app_registry: &app_registry::AppRegistry,
+    ) {
+        // The previous instance left the file positioned at the end of what it wrote
+        let mut data = Vec::new();
+        (&self.state)
+            .rewind()
+            .and_then(|_| (&self.state).read_to_end(&mut data))
+            .trace_expect("Failed to read handoff state");
+
+        let (clas, apps) = decode(&data).trace_expect("Invalid handoff state");
+        info!(
+            "Restoring {} CLA and {} application registrations",
+            clas.len(),
+            apps.len()
+        );
+        cla_registry.restore(clas).await;
+        app_registry.restore(apps).await;
+    }
+
+    #[cfg(unix)]
+    pub async fn wait_for_previous(&self, cancel_token: &tokio_util::sync::CancellationToken) {
+        let Ok(pid) = libc::pid_t::try_from(self.previous_pid) else {
+            return;
+        };
+
+        // The previous instance must have finished with the store before we check it
+        while unsafe { libc::kill(pid, 0) } == 0 {
+            if !utils::cancel::cancellable_sleep(time::Duration::milliseconds(100), cancel_token)
+                .await
+            {
+                return;
+            }
+        }
+        info!("Previous instance (pid {}) has exited", self.previous_pid);
+    }
+
+    #[cfg(not(unix))]
+    pub async fn wait_for_previous(&self, _cancel_token: &tokio_util::sync::CancellationToken) {}
+}
+
+#[cfg(unix)]
+pub fn listen_for_upgrade(
+    listener_fd: std::os::fd::RawFd,
+    cla_registry: cla_registry::ClaRegistry,
+    app_registry: app_registry::AppRegistry,
+    task_set: &mut tokio::task::JoinSet<()>,
+    cancel_token: tokio_util::sync::CancellationToken,
+) {
+    let mut upgrade_handler =
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::user_defined2())
+            .trace_expect("Failed to register signal handlers");
+
+    task_set.spawn(async move {
+        tokio::select! {
+            _ = upgrade_handler.recv() => {
+                info!("Received upgrade signal, handing over to new instance...");
+                match upgrade(listener_fd, &cla_registry, &app_registry).await {
+                    Ok(pid) => {
+                        info!("New instance started (pid {pid}), stopping...");
+                        cancel_token.cancel();
+                    }
+                    Err(e) => error!("Failed to hand over to new instance: {e}"),
+                }
+            }
+            _ = cancel_token.cancelled() => {}
+        }
+    });
+}
+
+#[cfg(unix)]
+async fn upgrade(
+    listener_fd: std::os::fd::RawFd,
+    cla_registry: &cla_registry::ClaRegistry,
+    app_registry: &app_registry::AppRegistry,
+) -> Result<u32, Error> {
+    use std::os::fd::AsRawFd;
+
+    // Snapshot the registries
+    let state = state_file()?;
+    (&state).write_all(&encode(
+        &cla_registry.snapshot().await,
+        &app_registry.snapshot().await,
+    ))?;
+
+    // Let the new instance inherit the listening socket and the snapshot
+    for fd in [listener_fd, state.as_raw_fd()] {
+        if unsafe { libc::fcntl(fd, libc::F_SETFD, 0) } != 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+    }
+
+    let child = std::process::Command::new(std::env::current_exe()?)
+        .args(std::env::args_os().skip(1))
+        .env(LISTEN_FD_VAR, listener_fd.to_string())
+        .env(STATE_FD_VAR, state.as_raw_fd().to_string())
+        .env(PID_VAR, std::process::id().to_string())
+        .spawn()?;
+    Ok(child.id())
+}
+
+// An anonymous file, that only this process and its children can reach
+#[cfg(unix)]
+fn state_file() -> Result<std::fs::File, Error> {
+    use rand::Rng;
+    use std::os::unix::fs::OpenOptionsExt;
+
+    let path = std::env::temp_dir().join(format!(
+        "hardy-bpa-handoff-{:016x}",
+        utils::random::with_rng(|rng| rng.gen::<u64>())
+    ));
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&path)?;
+    std::fs::remove_file(&path)?;
+    Ok(file)
+}
+
+fn encode(clas: &[ClaState], apps: &[AppState]) -> Vec<u8> {
+    cbor::encode::emit_array(Some(2), |a| {
+        a.emit_array(Some(clas.len()), |a| {
+            for cla in clas {
+                a.emit_array(Some(11), |a| {
+                    a.emit(cla.handle);
+                    a.emit(cla.ident.as_str());
+                    a.emit(cla.name.as_str());
+                    a.emit(cla.grpc_address.as_str());
+                    a.emit_array(Some(cla.neighbours.len()), |a| {
+                        for (neighbour, priority, mtu) in &cla.neighbours {
+                            a.emit_array(Some(3), |a| {
+                                a.emit(neighbour.as_str());
+                                a.emit(*priority);
+                                a.emit(mtu.unwrap_or(0));
+                            });
+                        }
+                    });
+                    a.emit(cla.instance_id.as_str());
+                    a.emit(cla.capabilities.max_bundle_size.unwrap_or(0));
+                    a.emit(cla.capabilities.reliable);
+                    a.emit(cla.capabilities.cost);
+                    a.emit(match cla.capabilities.latency {
+                        None => 0u8,
+                        Some(cla_registry::LatencyClass::Low) => 1,
+                        Some(cla_registry::LatencyClass::Medium) => 2,
+                        Some(cla_registry::LatencyClass::High) => 3,
+                    });
+                    a.emit(cla.owner.as_deref().unwrap_or_default());
+                });
+            }
+        });
+        a.emit_array(Some(apps.len()), |a| {
+            for app in apps {
+                a.emit_array(Some(7), |a| {
+                    a.emit(app.eid.as_str());
+                    a.emit(app.token.as_str());
+                    a.emit(app.ident.as_str());
+                    a.emit(app.grpc_address.as_deref().unwrap_or_default());
+                    a.emit(app.ordered_delivery);
+                    a.emit(app.congestion_signalling);
+                    a.emit(app.acknowledged_delivery);
+                });
+            }
+        });
+    })
+}
+
+fn try_parse_text(a: &mut cbor::decode::Array) -> Result<Option<String>, cbor::decode::Error> {
+    a.try_parse_value(|value, _, tags| match value {
+        cbor::decode::Value::Text(s) => Ok(s.to_string()),
+        value => Err(cbor::decode::Error::IncorrectType(
+            "Text String".to_string(),
+            value.type_name(!tags.is_empty()),
+        )),
+    })
+}
+
+fn parse_text(a: &mut cbor::decode::Array) -> Result<String, cbor::decode::Error> {
+    try_parse_text(a)?.ok_or(cbor::decode::Error::NotEnoughData)
+}
+
+type State = (Vec<ClaState>, Vec<AppState>);
+
+fn decode(data: &[u8]) -> Result<State, cbor::decode::Error> {
+    cbor::decode::parse_array(data, |a, _, _| {
+        let clas = a.parse_array(|a, _, _| {
+            let mut clas = Vec::new();
+            while let Some(cla) = a.try_parse_array(|a, _, _| {
+                Ok::<_, cbor::decode::Error>(ClaState {
+                    handle: a.parse()?,
+                    ident: parse_text(a)?,
+                    name: parse_text(a)?,
+                    grpc_address: parse_text(a)?,
+                    neighbours: a.parse_array(|a, _, _| {
+                        let mut neighbours = Vec::new();
+                        while let Some(neighbour) = a.try_parse_array(|a, _, _| {
+                            Ok::<_, cbor::decode::Error>((
+                                parse_text(a)?,
+                                a.parse()?,
+                                // Absent in snapshots from older instances, 0 is no MTU
+                                a.try_parse::<u64>()?.filter(|mtu| *mtu != 0),
+                            ))
+                        })? {
+                            neighbours.push(neighbour);
+                        }
+                        Ok::<_, cbor::decode::Error>(neighbours)
+                    })?,
+                    // Absent in snapshots from older instances
+                    instance_id: try_parse_text(a)?.unwrap_or_default(),
+                    capabilities: cla_registry::Capabilities {
+                        max_bundle_size: a.try_parse::<u64>()?.filter(|size| *size != 0),
+                        reliable: a.try_parse()?.unwrap_or(false),
+                        cost: a.try_parse()?.unwrap_or(0),
+                        latency: match a.try_parse::<u8>()? {
+                            Some(1) => Some(cla_registry::LatencyClass::Low),
+                            Some(2) => Some(cla_registry::LatencyClass::Medium),
+                            Some(3) => Some(cla_registry::LatencyClass::High),
+                            _ => None,
+                        },
+                    },
+                    // Absent in snapshots from older instances
+                    owner: try_parse_text(a)?.filter(|owner| !owner.is_empty()),
+                })
+            })? {
+                clas.push(cla);
+            }
+            Ok::<_, cbor::decode::Error>(clas)
+        })?;
+
+        let apps = a.parse_array(|a, _, _| {
+            let mut apps = Vec::new();
+            while let Some(app) = a.try_parse_array(|a, _, _| {
+                Ok::<_, cbor::decode::Error>(AppState {
+                    eid: parse_text(a)?,
+                    token: parse_text(a)?,
+                    ident: parse_text(a)?,
+                    grpc_address: Some(parse_text(a)?).filter(|s| !s.is_empty()),
+                    // Absent in snapshots from older instances
+                    ordered_delivery: a.try_parse()?.unwrap_or(false),
+                    congestion_signalling: a.try_parse()?.unwrap_or(false),
+                    acknowledged_delivery: a.try_parse()?.unwrap_or(false),
+                })
+            })? {
+                apps.push(app);
+            }
+            Ok::<_, cbor::decode::Error>(apps)
+        })?;
+
+        Ok((clas, apps))
+    })
+    .map(|(state, _)| state)
+}
@@ -0,0 +1,435 @@
+use super::*;
+use hardy_cbor as cbor;
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+
+/* IP Neighbour Discovery, in the style of IPND: beacons are multicast over UDP on the LAN
+ * advertising the administrative endpoints of the node, and the port each of its CLAs
+ * listens on, by the name the CLA registers with.  A beacon heard from another node adds
+ * routes to every service of that node, forwarded by the local CLA registered under the
+ * same name to the address the beacon came from and the advertised port.  The routes are
+ * removed if no beacon is heard from the node for three of its beacon periods.  Beacons
+ * are a CBOR array of: the version, the sequence number, the beacon period in seconds, the
+ * array of node EIDs, and the array of [CLA name, port] services */
+
+const BEACON_VERSION: u64 = 1;
+
+// Beacon periods without a beacon before a peer is forgotten
+const EXPIRY_PERIODS: i64 = 3;
+
+#[derive(Debug, Clone, serde::Deserialize)]
+struct Service {
+    cla: String,
+    port: u16,
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+struct Config {
+    #[serde(default = "Config::default_group")]
+    group: SocketAddrV4,
+    #[serde(default = "Config::default_interface")]
+    interface: Ipv4Addr,
+    // Seconds between beacons
+    #[serde(default = "Config::default_period")]
+    period: u32,
+    // The CLAs to advertise
+    #[serde(default)]
+    services: Vec<Service>,
+    #[serde(default = "Config::default_priority")]
+    priority: u32,
+    #[serde(default = "Config::default_protocol_id")]
+    protocol_id: String,
+}
+
+impl Config {
+    fn new(config: &::config::Config) -> Option<Self> {
+        utils::settings::get_with_default::<Option<Config>, _>(config, "ipnd", None)
+            .trace_expect("Invalid 'ipnd' section in configuration")
+    }
+
+    fn default_group() -> SocketAddrV4 {
+        SocketAddrV4::new(Ipv4Addr::new(224, 0, 0, 108), 4551)
+    }
+
+    fn default_interface() -> Ipv4Addr {
+        Ipv4Addr::UNSPECIFIED
+    }
+
+    fn default_period() -> u32 {
+        10
+    }
+
+    fn default_priority() -> u32 {
+        100
+    }
+
+    fn default_protocol_id() -> String {
+        "ipnd".to_string()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Beacon {
+    sequence: u64,
+    period: u32,
+    eids: Vec<bpv7::Eid>,
+    services: Vec<(String, u16)>,
+}
+
+impl Beacon {
+    fn encode(&self) -> Vec<u8> {
+        cbor::encode::emit_array(Some(5), |a| {
+            a.emit(BEACON_VERSION);
+            a.emit(self.sequence);
+            a.emit(self.period);
+            a.emit_array(Some(self.eids.len()), |a| {
+                for eid in &self.eids {
+                    a.emit(eid.to_string());
+                }
+            });
+            a.emit_array(Some(self.services.len()), |a| {
+                for (cla, port) in &self.services {
+                    a.emit_array(Some(2), |a| {
+                        a.emit(cla.as_str());
+                        a.emit(*port);
+                    });
+                }
+            });
+        })
+    }
+
+    fn decode(data: &[u8]) -> Option<Self> {
+        fn text(value: cbor::decode::Value) -> Result<String, cbor::decode::Error> {
+            match value {
+                cbor::decode::Value::Text(s) => Ok(s.to_string()),
+                _ => Err(cbor::decode::Error::IncorrectType(
+                    "Text".to_string(),
+                    value.type_name(false),
+                )),
+            }
+        }
+
+        cbor::decode::parse_array(data, |a, _, _| {
+            if a.parse::<u64>()? != BEACON_VERSION {
+                return Ok(None);
+            }
+            let sequence = a.parse::<u64>()?;
+            let period = a.parse::<u32>()?;
+            let eids = a.parse_array(|a, _, _| {
+                let mut eids = Vec::new();
+                while let Some(eid) = a.try_parse_value(|value, _, _| text(value))? {
+                    eids.push(eid);
+                }
+                Ok::<_, cbor::decode::Error>(eids)
+            })?;
+            let services = a.parse_array(|a, _, _| {
+                let mut services = Vec::new();
+                while let Some(service) = a.try_parse_array(|a, _, _| {
+                    Ok::<_, cbor::decode::Error>((
+                        a.parse_value(|value, _, _| text(value))?,
+                        a.parse::<u16>()?,
+                    ))
+                })? {
+                    services.push(service);
+                }
+                Ok::<_, cbor::decode::Error>(services)
+            })?;
+            Ok::<_, cbor::decode::Error>(Some((sequence, period, eids, services)))
+        })
+        .ok()
+        .and_then(|(beacon, _)| beacon)
+        .and_then(|(sequence, period, eids, services)| {
+            Some(Self {
+                sequence,
+                period,
+                eids: eids
+                    .iter()
+                    .map(|eid| eid.parse().ok())
+                    .collect::<Option<_>>()?,
+                services,
+            })
+        })
+    }
+}
+
+// Every service of the node the administrative endpoint `eid` belongs to
+fn node_pattern(eid: &bpv7::Eid) -> Option<bpv7::EidPattern> {
+    match eid {
+        bpv7::Eid::Ipn {
+            allocator_id,
+            node_number,
+            ..
+        }
+        | bpv7::Eid::LegacyIpn {
+            allocator_id,
+            node_number,
+            ..
+        } => format!("ipn:{allocator_id}.{node_number}.*").parse().ok(),
+        bpv7::Eid::Dtn { node_name, .. } => format!("dtn://{node_name}/**").parse().ok(),
+        _ => None,
+    }
+}
+
+// The routes to a peer, by the CLA handle and address they forward to
+type PeerRoutes = Vec<(u32, String)>;
+
+struct Peer {
+    patterns: Vec<bpv7::EidPattern>,
+    routes: PeerRoutes,
+    expires: time::OffsetDateTime,
+}
+
+struct Ipnd {
+    config: Config,
+    fib: fib::Fib,
+    cla_registry: cla_registry::ClaRegistry,
+    eids: Vec<bpv7::Eid>,
+    // Peers by the first EID they advertise
+    peers: HashMap<bpv7::Eid, Peer>,
+}
+
+impl Ipnd {
+    async fn add_routes(&self, patterns: &[bpv7::EidPattern], routes: &PeerRoutes) {
+        for pattern in patterns {
+            for (handle, address) in routes {
+                if let Err(e) = self
+                    .fib
+                    .add(
+                        self.config.protocol_id.clone(),
+                        pattern,
+                        fib::DISTANCE_NEIGHBOUR,
+                        self.config.priority,
+                        fib::Action::Forward(fib::Endpoint {
+                            handle: *handle,
+                            address: Some(address.clone()),
+                            mtu: None,
+                            latency: None,
+                        }),
+                        None,
+                    )
+                    .await
+                {
+                    error!("Failed to insert discovered route {pattern}: {e}");
+                }
+            }
+        }
+    }
+
+    async fn remove_routes(&self, patterns: &[bpv7::EidPattern]) {
+        for pattern in patterns {
+            self.fib.remove(&self.config.protocol_id, pattern).await;
+        }
+    }
+
+    async fn beacon_received(&mut self, beacon: Beacon, from: SocketAddr) {
+        let Some(key) = beacon.eids.first().cloned() else {
+            return;
+        };
+        if self.eids.contains(&key) {
+            // Our own beacon
+            return;
+        }
+
+        let mut routes = PeerRoutes::new();
+        for (cla, port) in &beacon.services {
+            match self.cla_registry.find_by_name(cla).await {
+                Some(handle) => {
+                    routes.push((handle, SocketAddr::new(from.ip(), *port).to_string()));
+                }
+                None => trace!("Peer {key} advertises CLA '{cla}', which is not registered"),
+            }
+        }
+        routes.sort();
+        let patterns = beacon
+            .eids
+            .iter()
+            .filter_map(node_pattern)
+            .collect::<Vec<_>>();
+        let expires = utils::clock::now()
+            + time::Duration::seconds(beacon.period.max(1) as i64 * EXPIRY_PERIODS);
+
+        if let Some(peer) = self.peers.get_mut(&key) {
+            peer.expires = expires;
+            if peer.routes == routes && peer.patterns == patterns {
+                return;
+            }
+            info!("Discovered neighbour {key} has changed");
+        } else {
+            info!("Discovered neighbour {key} at {}", from.ip());
+            metrics::counter!("ipnd_neighbours_discovered_total").increment(1);
+        }
+
+        if let Some(previous) = self.peers.remove(&key) {
+            self.remove_routes(&previous.patterns).await;
+        }
+        self.add_routes(&patterns, &routes).await;
+        self.peers.insert(
+            key,
+            Peer {
+                patterns,
+                routes,
+                expires,
+            },
+        );
+        metrics::gauge!("ipnd_neighbours").set(self.peers.len() as f64);
+    }
+
+    async fn expire_peers(&mut self, now: time::OffsetDateTime) {
+        let expired = self
+            .peers
+            .iter()
+            .filter(|(_, peer)| peer.expires <= now)
+            .map(|(eid, _)| eid.clone())
+            .collect::<Vec<_>>();
+        for eid in expired {
+            info!("Discovered neighbour {eid} has gone");
+            if let Some(peer) = self.peers.remove(&eid) {
+                self.remove_routes(&peer.patterns).await;
+            }
+        }
+        metrics::gauge!("ipnd_neighbours").set(self.peers.len() as f64);
+    }
+
+    async fn run(
+        mut self,
+        socket: tokio::net::UdpSocket,
+        cancel_token: tokio_util::sync::CancellationToken,
+    ) {
+        let period = time::Duration::seconds(self.config.period as i64);
+        let mut next_beacon = utils::clock::now();
+        let mut sequence = 0;
+        let mut buf = vec![0u8; 65536];
+        loop {
+            let now = utils::clock::now();
+            if next_beacon <= now {
+                let beacon = Beacon {
+                    sequence,
+                    period: self.config.period,
+                    eids: self.eids.clone(),
+                    services: self
+                        .config
+                        .services
+                        .iter()
+                        .map(|service| (service.cla.clone(), service.port))
+                        .collect(),
+                };
+                sequence = sequence.wrapping_add(1);
+                if let Err(e) = socket
+                    .send_to(&beacon.encode(), SocketAddr::V4(self.config.group))
+                    .await
+                {
+                    warn!("Failed to send IPND beacon: {e}");
+                }
+                self.expire_peers(now).await;
+                next_beacon = now + period;
+            }
+
+            tokio::select! {
+                r = socket.recv_from(&mut buf) => match r {
+                    Ok((len, from)) => match Beacon::decode(&buf[..len]) {
+                        Some(beacon) => self.beacon_received(beacon, from).await,
+                        None => trace!("Ignoring invalid IPND beacon from {from}"),
+                    },
+                    Err(e) => warn!("Failed to receive IPND beacon: {e}"),
+                },
+                r = utils::clock::sleep(next_beacon - now, &cancel_token) => if !r {
+                    break;
+                }
+            }
+        }
+
+        // Forget what we discovered
+        let peers = std::mem::take(&mut self.peers);
+        for peer in peers.values() {
+            self.remove_routes(&peer.patterns).await;
+        }
+    }
+}
+
+#[instrument(skip_all)]
+pub async fn init(
+    config: &::config::Config,
+    admin_endpoints: &utils::admin_endpoints::AdminEndpoints,
+    fib: fib::Fib,
+    cla_registry: cla_registry::ClaRegistry,
+    task_set: &mut tokio::task::JoinSet<()>,
+    cancel_token: tokio_util::sync::CancellationToken,
+) {
+    let Some(config) = Config::new(config) else {
+        info!("No neighbour discovery configured");
+        return;
+    };
+    if config.period == 0 {
+        error!("'ipnd.period' must be greater than 0");
+        panic!("'ipnd.period' must be greater than 0");
+    }
+
+    let mut eids = Vec::new();
+    if let Some(node_id) = &admin_endpoints.ipn {
+        eids.push(node_id.to_eid(0));
+    }
+    if let Some(node_id) = &admin_endpoints.dtn {
+        eids.push(
+            node_id
+                .to_eid("")
+                .trace_expect("Failed to build administrative endpoint"),
+        );
+    }
+
+    let socket = tokio::net::UdpSocket::bind(SocketAddrV4::new(
+        Ipv4Addr::UNSPECIFIED,
+        config.group.port(),
+    ))
+    .await
+    .trace_expect("Failed to bind IPND socket");
+    socket
+        .join_multicast_v4(*config.group.ip(), config.interface)
+        .trace_expect("Failed to join IPND multicast group");
+
+    info!(
+        "Sending neighbour discovery beacons to {} every {} seconds",
+        config.group, config.period
+    );
+    for service in &config.services {
+        info!("Advertising CLA '{}' on port {}", service.cla, service.port);
+    }
+
+    task_set.spawn(
+        Ipnd {
+            config,
+            fib,
+            cla_registry,
+            eids,
+            peers: HashMap::new(),
+        }
+        .run(socket, cancel_token),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn beacon_roundtrip() {
+        let beacon = Beacon {
+            sequence: 7,
+            period: 10,
+            eids: vec!["ipn:2.0".parse().unwrap(), "dtn://node2/".parse().unwrap()],
+            services: vec![("tcpcl".to_string(), 4556)],
+        };
+        assert_eq!(Beacon::decode(&beacon.encode()), Some(beacon));
+        assert_eq!(Beacon::decode(b"hello"), None);
+    }
+
+    #[test]
+    fn node_patterns() {
+        let pattern = node_pattern(&"ipn:2.0".parse().unwrap()).unwrap();
+        assert!(pattern.is_match(&"ipn:2.7".parse().unwrap()));
+        assert!(!pattern.is_match(&"ipn:3.0".parse().unwrap()));
+
+        let pattern = node_pattern(&"dtn://node2/".parse().unwrap()).unwrap();
+        assert!(pattern.is_match(&"dtn://node2/app".parse().unwrap()));
+        assert!(!pattern.is_match(&"dtn://node3/app".parse().unwrap()));
+    }
+}
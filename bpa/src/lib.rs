@@ -1,18 +1,30 @@
 pub mod app_registry;
+pub mod cgr;
 pub mod cla_registry;
 pub mod dispatcher;
 pub mod fib;
 pub mod grpc;
+pub mod handoff;
+pub mod ipnd;
+pub mod resolver;
+pub mod startup;
 pub mod static_routes;
 pub mod store;
 pub mod utils;
 
+mod embedded;
+
+pub use embedded::{Bpa, Builder};
+
 // This is the generic Error type used almost everywhere
 type Error = Box<dyn std::error::Error + Send + Sync>;
 
 // This is the effective prelude
+#[cfg(feature = "fuzzing")]
 use fuzz_macros::instrument;
 use hardy_bpa_api::metadata;
 use hardy_bpv7::prelude as bpv7;
 use trace_err::*;
+#[cfg(not(feature = "fuzzing"))]
+use tracing::instrument;
 use tracing::{error, info, trace, warn};
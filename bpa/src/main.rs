@@ -1,30 +1,16 @@
-mod app_registry;
-mod cla_registry;
-mod dispatcher;
-mod fib;
-mod grpc;
-mod static_routes;
-mod store;
-mod utils;
-
-// This is the generic Error type used almost everywhere
-type Error = Box<dyn std::error::Error + Send + Sync>;
-
-// This is the effective prelude
-use hardy_bpa_api::metadata;
-use hardy_bpv7::prelude as bpv7;
+use hardy_bpa::{grpc, handoff, startup, store, utils};
 use trace_err::*;
-use tracing::{error, info, instrument, trace, warn};
+use tracing::{error, info};
 
 #[tokio::main]
 async fn main() {
     // Parse command line
-    let Some((config, upgrade, config_source)) = utils::settings::init() else {
+    let Some((config, flags, config_source)) = utils::settings::init() else {
         return;
     };
 
-    // Init logger
-    utils::logger::init(&config);
+    // Init logger, which must live until the end
+    let _logger = utils::logger::init(&config);
     info!(
         "{} version {} starting...",
         utils::built_info::PKG_NAME,
@@ -32,59 +18,127 @@ async fn main() {
     );
     info!("{config_source}");
 
-    // Get administrative endpoints
-    let administrative_endpoints = utils::admin_endpoints::AdminEndpoints::init(&config);
+    // Check whether we are taking over from a previous instance
+    let handoff = handoff::take();
+
+    // Init the process-wide state
+    let administrative_endpoints = startup::init_process(&config);
 
     // New store
-    let store = store::Store::new(&config, upgrade);
+    let store = store::Store::new(&config, flags.upgrade);
+
+    // Report what we can do
+    utils::capabilities::log(&store);
+
+    if flags.rehash {
+        // Recompute the bundle hashes, and stop
+        let (mut task_set, cancel_token) = utils::cancel::new_cancellable_set();
+        store.rehash(cancel_token.clone()).await;
+        cancel_token.cancel();
+        while let Some(r) = task_set.join_next().await {
+            r.trace_expect("Task terminated unexpectedly")
+        }
+        info!("Stopped");
+        return;
+    }
 
-    // New FIB
-    let fib = fib::Fib::new(&config);
+    if store.read_only() {
+        // Serve queries of the store of another instance, without forwarding anything
+        let (mut task_set, cancel_token) = utils::cancel::new_cancellable_set();
+        grpc::init_replica(&config, store, &mut task_set, cancel_token.clone());
 
-    // New registries
-    let cla_registry = cla_registry::ClaRegistry::new(&config, fib.clone());
-    let app_registry = app_registry::AppRegistry::new(&config, administrative_endpoints.clone());
+        info!("Started successfully as a read-only replica");
+        while let Some(r) = task_set.join_next().await {
+            r.trace_expect("Task terminated unexpectedly")
+        }
+        info!("Stopped");
+        return;
+    }
 
     // Prepare for graceful shutdown
     let (mut task_set, cancel_token) = utils::cancel::new_cancellable_set();
 
-    // Load static routes
-    if let Some(fib) = &fib {
-        static_routes::init(&config, fib.clone(), &mut task_set, cancel_token.clone()).await;
-    }
-
-    // Create a new dispatcher
-    let dispatcher = dispatcher::Dispatcher::new(
+    // Build the node, taking over the registrations of the previous instance
+    let startup::Node {
+        store,
+        fib,
+        cla_registry,
+        app_registry,
+        dispatcher,
+    } = startup::start_node(
         &config,
         administrative_endpoints,
-        store.clone(),
-        cla_registry.clone(),
-        app_registry.clone(),
-        fib,
+        store,
+        handoff.as_ref(),
         &mut task_set,
         cancel_token.clone(),
-    );
+    )
+    .await;
 
-    // Start the store - this can take a while as the store is walked
-    store
-        .start(dispatcher.clone(), &mut task_set, cancel_token.clone())
-        .await;
+    // Keep hold of the dispatcher to replay a journal into, once started
+    let replay = flags
+        .replay
+        .map(|(path, paced)| (dispatcher.clone(), path, paced));
 
-    if !cancel_token.is_cancelled() {
-        // Init gRPC services
+    if let Some(handoff) = handoff {
+        // Keep serving on the inherited socket while the previous instance finishes
         grpc::init(
             &config,
+            Some(
+                handoff
+                    .listener
+                    .try_clone()
+                    .trace_expect("Failed to clone listener"),
+            ),
+            store.clone(),
             cla_registry,
             app_registry,
-            dispatcher,
+            dispatcher.clone(),
+            fib,
             &mut task_set,
             cancel_token.clone(),
         );
+
+        handoff.wait_for_previous(&cancel_token).await;
+
+        // Start the store - this can take a while as the store is walked
+        store
+            .start(dispatcher, &mut task_set, cancel_token.clone())
+            .await;
+    } else {
+        // Start the store - this can take a while as the store is walked
+        store
+            .start(dispatcher.clone(), &mut task_set, cancel_token.clone())
+            .await;
+
+        if !cancel_token.is_cancelled() {
+            // Init gRPC services
+            grpc::init(
+                &config,
+                None,
+                store,
+                cla_registry,
+                app_registry,
+                dispatcher,
+                fib,
+                &mut task_set,
+                cancel_token.clone(),
+            );
+        }
     }
 
     // Wait for all tasks to finish
     if !cancel_token.is_cancelled() {
         info!("Started successfully");
+
+        if let Some((dispatcher, path, paced)) = replay {
+            info!("Replaying ingress journal '{}'", path.display());
+            task_set.spawn(async move {
+                if let Err(e) = dispatcher.replay(path, paced).await {
+                    error!("Failed to replay ingress journal: {e}");
+                }
+            });
+        }
     }
 
     while let Some(r) = task_set.join_next().await {
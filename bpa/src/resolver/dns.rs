@@ -0,0 +1,49 @@
+use super::*;
+
+#[derive(serde::Deserialize)]
+pub struct Config {
+    // The CLA the resolved addresses are for
+    cla: String,
+    port: u16,
+
+    // Appended to the node name before lookup, e.g. ".dtn.example.com"
+    #[serde(default)]
+    suffix: String,
+}
+
+pub struct Dns {
+    config: Config,
+}
+
+impl Dns {
+    pub fn new(config: Config) -> Self {
+        info!(
+            "Resolving 'dtn' node names through DNS for CLA '{}'",
+            config.cla
+        );
+        Self { config }
+    }
+}
+
+#[async_trait]
+impl Resolver for Dns {
+    async fn resolve(&self, eid: &bpv7::Eid) -> Vec<Address> {
+        let bpv7::Eid::Dtn { node_name, .. } = eid else {
+            return Vec::new();
+        };
+
+        let host = format!("{node_name}{}", self.config.suffix);
+        match tokio::net::lookup_host(format!("{host}:{}", self.config.port)).await {
+            Ok(addrs) => addrs
+                .map(|addr| Address {
+                    cla: self.config.cla.clone(),
+                    address: addr.to_string(),
+                })
+                .collect(),
+            Err(e) => {
+                trace!("Failed to resolve {host}: {e}");
+                Vec::new()
+            }
+        }
+    }
+}
@@ -0,0 +1,69 @@
+use super::*;
+use hardy_bpa_api::async_trait;
+
+mod dns;
+mod static_table;
+
+/* Next hops are resolved to CLA-specific addresses when the FIB has no route to them,
+ * so routes can be given 'via' a node EID rather than through the neighbours a CLA
+ * has added.  Resolvers are asked in order: the static table from the configuration,
+ * then DNS for the node names of 'dtn' EIDs.  The first to resolve a next hop wins */
+
+// A CLA-specific address of a next hop
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Address {
+    pub cla: String, // The name the CLA registered with
+    pub address: String,
+}
+
+#[async_trait]
+pub trait Resolver: Send + Sync {
+    // The addresses of the node, in order of preference
+    async fn resolve(&self, eid: &bpv7::Eid) -> Vec<Address>;
+}
+
+#[derive(Default, serde::Deserialize)]
+struct Config {
+    #[serde(default, rename = "static")]
+    static_table: Vec<static_table::Entry>,
+    dns: Option<dns::Config>,
+}
+
+impl Config {
+    fn new(config: &::config::Config) -> Self {
+        utils::settings::get_with_default::<Option<Config>, _>(config, "resolver", None)
+            .trace_expect("Invalid 'resolver' section in configuration")
+            .unwrap_or_default()
+    }
+}
+
+pub struct Resolvers {
+    resolvers: Vec<Box<dyn Resolver>>,
+}
+
+impl Resolvers {
+    pub fn new(config: &::config::Config) -> Self {
+        let config = Config::new(config);
+        let mut resolvers: Vec<Box<dyn Resolver>> = Vec::new();
+        if !config.static_table.is_empty() {
+            resolvers.push(Box::new(static_table::StaticTable::new(
+                config.static_table,
+            )));
+        }
+        if let Some(config) = config.dns {
+            resolvers.push(Box::new(dns::Dns::new(config)));
+        }
+        Self { resolvers }
+    }
+
+    #[instrument(skip(self))]
+    pub async fn resolve(&self, eid: &bpv7::Eid) -> Vec<Address> {
+        for resolver in &self.resolvers {
+            let addresses = resolver.resolve(eid).await;
+            if !addresses.is_empty() {
+                return addresses;
+            }
+        }
+        Vec::new()
+    }
+}
@@ -0,0 +1,48 @@
+use super::*;
+
+// A static table entry, as it appears in the configuration
+#[derive(serde::Deserialize)]
+pub struct Entry {
+    eid: String,
+    cla: String,
+    address: String,
+}
+
+pub struct StaticTable {
+    entries: Vec<(bpv7::EidPattern, Address)>,
+}
+
+impl StaticTable {
+    pub fn new(entries: Vec<Entry>) -> Self {
+        let entries = entries
+            .into_iter()
+            .map(|entry| {
+                let pattern = entry.eid.parse::<bpv7::EidPattern>().trace_expect(&format!(
+                    "Invalid EID pattern '{}' in 'resolver.static'",
+                    entry.eid
+                ));
+                (
+                    pattern,
+                    Address {
+                        cla: entry.cla,
+                        address: entry.address,
+                    },
+                )
+            })
+            .collect::<Vec<_>>();
+
+        info!("Loaded {} static next hop addresses", entries.len());
+        Self { entries }
+    }
+}
+
+#[async_trait]
+impl Resolver for StaticTable {
+    async fn resolve(&self, eid: &bpv7::Eid) -> Vec<Address> {
+        self.entries
+            .iter()
+            .filter(|(pattern, _)| pattern.is_match(eid))
+            .map(|(_, address)| address.clone())
+            .collect()
+    }
+}
@@ -0,0 +1,101 @@
+use super::*;
+use std::sync::Arc;
+
+/* The startup shared by the daemon and the embedded API.  Each creates the store itself, as
+ * the daemon may only rehash it or serve it read-only, and starts the store and the gRPC
+ * services once the node is built, as a daemon taking over from a previous instance must
+ * wait for it to finish first */
+
+// The parts of a running node
+pub struct Node {
+    pub store: Arc<store::Store>,
+    pub fib: Option<fib::Fib>,
+    pub cla_registry: cla_registry::ClaRegistry,
+    pub app_registry: app_registry::AppRegistry,
+    pub dispatcher: Arc<dispatcher::Dispatcher>,
+}
+
+// Init the process-wide state, returning the administrative endpoints of the node
+pub fn init_process(config: &config::Config) -> utils::admin_endpoints::AdminEndpoints {
+    // Init the clock, which may be virtual under simulation
+    utils::clock::init(config);
+
+    // Init the random number generator, which may be seeded under simulation
+    utils::random::init(config);
+
+    // Init memory accounting and soft limits
+    utils::memory::init(config);
+
+    // Init metrics labels, and the exporter if configured
+    utils::labels::init(config);
+    utils::exporter::init(config);
+
+    // Get administrative endpoints
+    utils::admin_endpoints::AdminEndpoints::init(config)
+}
+
+// Build the node around the store, restoring the registrations of a previous instance if
+// taking over from one
+#[instrument(skip_all)]
+pub async fn start_node(
+    config: &config::Config,
+    administrative_endpoints: utils::admin_endpoints::AdminEndpoints,
+    store: Arc<store::Store>,
+    handoff: Option<&handoff::Handoff>,
+    task_set: &mut tokio::task::JoinSet<()>,
+    cancel_token: tokio_util::sync::CancellationToken,
+) -> Node {
+    // New FIB
+    let fib = fib::Fib::new(config);
+
+    // New registries
+    let cla_registry = cla_registry::ClaRegistry::new(config, fib.clone());
+    let app_registry = app_registry::AppRegistry::new(config, administrative_endpoints.clone());
+
+    // Restore the registrations of the previous instance
+    if let Some(handoff) = handoff {
+        handoff.restore(&cla_registry, &app_registry).await;
+    }
+
+    // Load static routes and the contact plan, and discover neighbours
+    if let Some(fib) = &fib {
+        static_routes::init(config, fib.clone(), task_set, cancel_token.clone()).await;
+        cgr::init(
+            config,
+            &administrative_endpoints,
+            fib.clone(),
+            task_set,
+            cancel_token.clone(),
+        )
+        .await;
+        ipnd::init(
+            config,
+            &administrative_endpoints,
+            fib.clone(),
+            cla_registry.clone(),
+            task_set,
+            cancel_token.clone(),
+        )
+        .await;
+    }
+
+    // Create a new dispatcher
+    let dispatcher = dispatcher::Dispatcher::new(
+        config,
+        administrative_endpoints,
+        store.clone(),
+        cla_registry.clone(),
+        app_registry.clone(),
+        fib.clone(),
+        task_set,
+        cancel_token,
+    );
+
+    Node {
+        store,
+        fib,
+        cla_registry,
+        app_registry,
+        dispatcher,
+    }
+}
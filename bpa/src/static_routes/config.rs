@@ -1,8 +1,8 @@
 use super::*;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use utils::settings;
 
-#[derive(Clone, Deserialize)]
+#[derive(Clone, Deserialize, Serialize)]
 pub struct Config {
     #[serde(default = "Config::default_path")]
     pub routes_file: PathBuf,
@@ -10,6 +10,9 @@ pub struct Config {
     #[serde(default = "Config::default_priority")]
     pub priority: u32,
 
+    #[serde(default = "Config::default_distance")]
+    pub distance: u32,
+
     #[serde(default = "Config::default_watch")]
     pub watch: bool,
 
@@ -17,11 +20,16 @@ pub struct Config {
     pub protocol_id: String,
 }
 
+// The static routes settings, as they appear in the configuration
+#[derive(Default, Deserialize, Serialize)]
+#[serde(default)]
+struct Settings {
+    static_routes: Option<Config>,
+}
+
 impl Config {
     pub fn new(config: &::config::Config) -> Option<Self> {
-        let mut config =
-            settings::get_with_default::<Option<config::Config>, _>(config, "static_routes", None)
-                .trace_expect("Invalid 'static_routes' section in configuration")?;
+        let mut config = settings::load::<Settings>(config, "static routes").static_routes?;
 
         // Try to create canonical file path
         if let Ok(r) = config.routes_file.canonicalize() {
@@ -45,6 +53,10 @@ impl Config {
         100
     }
 
+    fn default_distance() -> u32 {
+        fib::DISTANCE_STATIC
+    }
+
     fn default_watch() -> bool {
         true
     }
@@ -17,7 +17,9 @@ mod parse;
 
 #[derive(Debug, Clone, Eq, PartialEq)]
 struct StaticRoute {
+    distance: Option<u32>,
     priority: Option<u32>,
+    latency: Option<time::Duration>,
     action: fib::Action,
 }
 
@@ -53,11 +55,13 @@ impl StaticRoutes {
 
     async fn refresh_routes(&mut self, ignore_errors: bool) -> Result<(), Error> {
         // Reload the routes
+        let routes =
+            parse::load_routes(&self.config.routes_file, ignore_errors, self.config.watch).await?;
+        self.check_overlaps(&routes);
+
         let mut drop_routes = Vec::new();
         let mut add_routes = Vec::new();
-        for r in
-            parse::load_routes(&self.config.routes_file, ignore_errors, self.config.watch).await?
-        {
+        for r in routes {
             if let Some(v2) = self.routes.get(&r.0) {
                 if &r.1 != v2 {
                     drop_routes.push(r.0.clone());
@@ -81,8 +85,10 @@ impl StaticRoutes {
                 .add(
                     self.config.protocol_id.clone(),
                     &k,
+                    v.distance.unwrap_or(self.config.distance),
                     v.priority.unwrap_or(self.config.priority),
                     v.action.clone(),
+                    v.latency,
                 )
                 .await
             {
@@ -94,6 +100,31 @@ impl StaticRoutes {
         Ok(())
     }
 
+    // Warn of routes that will never be used, or that share bundles with another route of the
+    // same rank, as the FIB routes by the lowest ranked routes that match
+    fn check_overlaps(&self, routes: &[(bpv7::EidPattern, StaticRoute)]) {
+        let rank = |route: &StaticRoute| {
+            (
+                route.distance.unwrap_or(self.config.distance),
+                route.priority.unwrap_or(self.config.priority),
+            )
+        };
+        for (idx, (p1, r1)) in routes.iter().enumerate() {
+            for (p2, r2) in &routes[idx + 1..] {
+                if rank(r1) < rank(r2) && p1.covers(p2) {
+                    warn!("Static route for {p2} is shadowed by the route for {p1}");
+                } else if rank(r2) < rank(r1) && p2.covers(p1) {
+                    warn!("Static route for {p1} is shadowed by the route for {p2}");
+                } else if rank(r1) == rank(r2) && r1.action != r2.action && p1.intersects(p2) {
+                    warn!(
+                        "Static routes for {p1} ({}) and {p2} ({}) overlap with the same distance and priority",
+                        r1.action, r2.action
+                    );
+                }
+            }
+        }
+    }
+
     fn watch(
         &self,
         task_set: &mut tokio::task::JoinSet<()>,
@@ -145,17 +145,37 @@ impl std::str::FromStr for RouteLine {
                     arg: ArgOption::Some(1),
                     group: None,
                 },
+                Arg {
+                    name: "distance",
+                    arg: ArgOption::Some(1),
+                    group: None,
+                },
+                Arg {
+                    name: "latency",
+                    arg: ArgOption::Some(1),
+                    group: None,
+                },
             ],
         )?;
 
         Ok(Self(Some((
             pattern,
             StaticRoute {
+                distance: if let Some(distance) = parts.get("distance").unwrap_or(&None) {
+                    Some(distance.parse()?)
+                } else {
+                    None
+                },
                 priority: if let Some(priority) = parts.get("priority").unwrap_or(&None) {
                     Some(priority.parse()?)
                 } else {
                     None
                 },
+                latency: if let Some(latency) = parts.get("latency").unwrap_or(&None) {
+                    Some(time::Duration::seconds(latency.parse()?))
+                } else {
+                    None
+                },
                 action: if let Some(drop) = parts.get("drop") {
                     fib::Action::Drop(if let Some(reason) = drop {
                         Some(reason.parse::<u64>()?.try_into()?)
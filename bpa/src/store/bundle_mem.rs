@@ -18,8 +18,11 @@ impl AsRef<[u8]> for DataRefWrapper {
     }
 }
 
+// Bundle data, and the memory reserved for it
+type Entry = (Arc<[u8]>, utils::memory::Reservation);
+
 pub struct Storage {
-    bundles: RwLock<HashMap<String, Arc<[u8]>>>,
+    bundles: RwLock<HashMap<String, Entry>>,
 }
 
 impl Storage {
@@ -42,7 +45,7 @@ impl storage::BundleStorage for Storage {
     }
 
     async fn load(&self, storage_name: &str) -> storage::Result<Option<storage::DataRef>> {
-        if let Some(v) = self.bundles.read().await.get(storage_name) {
+        if let Some((v, _)) = self.bundles.read().await.get(storage_name) {
             Ok(Some(Arc::new(DataRefWrapper(v.clone()))))
         } else {
             Ok(None)
@@ -50,13 +53,18 @@ impl storage::BundleStorage for Storage {
     }
 
     async fn store(&self, data: &[u8]) -> storage::Result<Arc<str>> {
+        let Some(reservation) =
+            utils::memory::try_reserve(utils::memory::Subsystem::MemStorage, data.len())
+        else {
+            return Err("mem-storage memory limit reached".into());
+        };
+
         let mut bundles = self.bundles.write().await;
-        let mut rng = rand::thread_rng();
         loop {
-            let storage_name = Alphanumeric.sample_string(&mut rng, 64);
+            let storage_name = utils::random::with_rng(|rng| Alphanumeric.sample_string(rng, 64));
 
             if let hash_map::Entry::Vacant(e) = bundles.entry(storage_name.clone()) {
-                e.insert(Arc::from(data));
+                e.insert((Arc::from(data), reservation));
                 return Ok(storage_name.into());
             }
         }
@@ -0,0 +1,155 @@
+use super::*;
+
+/* The consistency check run at startup only finds data that went missing while the BPA
+ * was stopped.  Long-running nodes can also check the store periodically, or on demand
+ * via the admin API, to catch silent data loss: the data of every stored bundle is loaded
+ * and its hash verified, at a limited rate so the check does not compete with forwarding
+ * for storage bandwidth.  Bundles whose data has gone, or no longer matches its hash, are
+ * reported as deleted and left as Tombstones */
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct CheckResult {
+    pub checked: u64,
+    pub missing: u64,
+    pub corrupt: u64,
+}
+
+impl Store {
+    #[instrument(skip_all)]
+    pub async fn consistency_check(
+        &self,
+        dispatcher: &dispatcher::Dispatcher,
+        cancel_token: &tokio_util::sync::CancellationToken,
+    ) -> Result<CheckResult, Error> {
+        let Ok(_guard) = self.consistency_lock.try_lock() else {
+            return Err("A store consistency check is already running".into());
+        };
+        info!("Starting store consistency check...");
+
+        let (tx, rx) = tokio::sync::mpsc::channel::<metadata::Bundle>(16);
+        let (r, (mut result, failed)) = tokio::join!(
+            self.metadata_storage.get_stored_bundles(tx),
+            self.verify_bundles(rx, cancel_token)
+        );
+        r?;
+
+        // Act on the failures once the walk has finished, as the metadata storage may be locked until then
+        for (bundle, corrupt) in failed {
+            // The bundle may have been dropped while we were checking
+            match self
+                .metadata_storage
+                .get_bundle_status(&bundle.bundle.id)
+                .await?
+            {
+                None | Some(metadata::BundleStatus::Tombstone(_)) => continue,
+                _ => {}
+            }
+
+            let storage_name = bundle.metadata.storage_name.as_ref().unwrap();
+            let mut reason = bpv7::StatusReportReasonCode::DepletedStorage;
+            if corrupt {
+                warn!("Bundle data {storage_name} does not match its hash");
+                result.corrupt = result.corrupt.saturating_add(1);
+            } else if bundle.expiry() <= utils::clock::now() {
+                // Collected by a bundle storage given expiry hints, before we got to it
+                trace!("Bundle data {storage_name} has expired from storage");
+                reason = bpv7::StatusReportReasonCode::LifetimeExpired;
+            } else {
+                warn!("Bundle data {storage_name} has gone from storage");
+                result.missing = result.missing.saturating_add(1);
+            }
+
+            dispatcher.report_bundle_deletion(&bundle, reason).await?;
+
+            // Leave a Tombstone, so we still recognise it if it is received again
+            self.metadata_storage
+                .set_bundle_status(
+                    &bundle.bundle.id,
+                    &metadata::BundleStatus::Tombstone(utils::clock::now()),
+                )
+                .await?;
+            if let Some(quota) = &self.quota {
+                quota.remove(&bundle.bundle.id);
+            }
+
+            if corrupt {
+                if let Some(data) = self.load_data(storage_name).await? {
+                    self.quarantine_data(storage_name, (*data).as_ref()).await;
+                }
+                self.delete_data(storage_name).await?;
+            }
+        }
+
+        info!(
+            "Store consistency check complete, {} bundles checked, {} missing and {} corrupt bundles found",
+            result.checked, result.missing, result.corrupt
+        );
+        Ok(result)
+    }
+
+    // Returns the bundles that failed, and whether they failed because the data is corrupt
+    async fn verify_bundles(
+        &self,
+        mut rx: tokio::sync::mpsc::Receiver<metadata::Bundle>,
+        cancel_token: &tokio_util::sync::CancellationToken,
+    ) -> (CheckResult, Vec<(metadata::Bundle, bool)>) {
+        let mut result = CheckResult::default();
+        let mut failed = Vec::new();
+
+        let mut interval = self.config.consistency_check_rate.map(|rate| {
+            let mut interval =
+                tokio::time::interval(std::time::Duration::from_secs_f64(1.0 / rate as f64));
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            interval
+        });
+
+        loop {
+            let bundle = tokio::select! {
+                bundle = rx.recv() => match bundle {
+                    None => break,
+                    Some(bundle) => bundle,
+                },
+                _ = cancel_token.cancelled() => break,
+            };
+
+            let Some(storage_name) = &bundle.metadata.storage_name else {
+                continue;
+            };
+
+            // Rate limit the IO
+            if let Some(interval) = &mut interval {
+                interval.tick().await;
+            }
+
+            result.checked = result.checked.saturating_add(1);
+            match self.load_data(storage_name).await {
+                Ok(None) => failed.push((bundle, false)),
+                Ok(Some(data)) => {
+                    if bundle
+                        .metadata
+                        .hash
+                        .as_deref()
+                        .is_some_and(|hash| !hash_matches(hash, (*data).as_ref()))
+                    {
+                        failed.push((bundle, true));
+                    }
+                }
+                Err(e) => warn!("Failed to load bundle data {storage_name}: {e}"),
+            }
+        }
+        (result, failed)
+    }
+
+    pub(super) async fn consistency_task(
+        self: Arc<Self>,
+        interval: time::Duration,
+        dispatcher: Arc<dispatcher::Dispatcher>,
+        cancel_token: tokio_util::sync::CancellationToken,
+    ) {
+        while utils::clock::sleep(interval, &cancel_token).await {
+            if let Err(e) = self.consistency_check(&dispatcher, &cancel_token).await {
+                warn!("Store consistency check failed: {e}");
+            }
+        }
+    }
+}
@@ -0,0 +1,159 @@
+use std::time::{Duration, Instant};
+
+/* Adaptive concurrency for the bundle restart scan.  The number of bundles restarted in
+ * parallel grows while throughput keeps improving, and backs off when storage errors occur
+ * or the time taken per bundle grows without any gain in throughput.  Local disks saturate
+ * at a handful of requests, while object stores benefit from many more in flight. */
+
+// How long to measure throughput for before adjusting the limit
+const WINDOW: Duration = Duration::from_secs(1);
+
+// The minimum improvement in throughput considered worth growing for
+const GROWTH_THRESHOLD: f64 = 1.1;
+
+// The growth in latency, without improved throughput, that causes a back off
+const BACKOFF_THRESHOLD: f64 = 1.5;
+
+pub struct Limit {
+    limit: usize,
+    min: usize,
+    max: usize,
+    adaptive: bool,
+    window_start: Instant,
+    completed: u64,
+    total_latency: Duration,
+    best_throughput: f64,
+    best_latency: Option<Duration>,
+}
+
+impl Limit {
+    pub fn fixed(limit: usize) -> Self {
+        let limit = limit.max(1);
+        Self::new(limit, limit, limit, false)
+    }
+
+    pub fn adaptive(initial: usize, max: usize) -> Self {
+        let initial = initial.max(1);
+        Self::new(initial, initial, max.max(initial), true)
+    }
+
+    fn new(limit: usize, min: usize, max: usize, adaptive: bool) -> Self {
+        Self {
+            limit,
+            min,
+            max,
+            adaptive,
+            window_start: Instant::now(),
+            completed: 0,
+            total_latency: Duration::ZERO,
+            best_throughput: 0.0,
+            best_latency: None,
+        }
+    }
+
+    pub fn get(&self) -> usize {
+        self.limit
+    }
+
+    // Record a bundle restarted successfully, and how long it took
+    pub fn completed(&mut self, latency: Duration, now: Instant) {
+        if !self.adaptive {
+            return;
+        }
+
+        self.completed = self.completed.saturating_add(1);
+        self.total_latency = self.total_latency.saturating_add(latency);
+
+        let elapsed = now.saturating_duration_since(self.window_start);
+        if elapsed < WINDOW {
+            return;
+        }
+
+        let throughput = self.completed as f64 / elapsed.as_secs_f64();
+        let latency = self.total_latency / self.completed.clamp(1, u32::MAX as u64) as u32;
+
+        if throughput > self.best_throughput * GROWTH_THRESHOLD {
+            // Still improving, so try more
+            self.best_throughput = throughput;
+            self.best_latency = Some(latency);
+            self.limit = self.limit.saturating_mul(2).min(self.max);
+        } else if self
+            .best_latency
+            .is_some_and(|best| latency.as_secs_f64() > best.as_secs_f64() * BACKOFF_THRESHOLD)
+        {
+            // More in flight is just queueing in storage
+            self.back_off();
+        }
+
+        self.window_start = now;
+        self.completed = 0;
+        self.total_latency = Duration::ZERO;
+    }
+
+    // Record a storage error
+    pub fn failed(&mut self, now: Instant) {
+        if !self.adaptive {
+            return;
+        }
+
+        self.back_off();
+
+        // Start measuring again at the new limit
+        self.best_throughput = 0.0;
+        self.best_latency = None;
+        self.window_start = now;
+        self.completed = 0;
+        self.total_latency = Duration::ZERO;
+    }
+
+    fn back_off(&mut self) {
+        self.limit = (self.limit - (self.limit / 4).max(1)).max(self.min);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run_window(limit: &mut Limit, start: Instant, bundles: u32, latency: Duration) -> Instant {
+        let end = start + WINDOW;
+        for _ in 0..bundles {
+            limit.completed(latency, start);
+        }
+        limit.completed(latency, end);
+        end
+    }
+
+    #[test]
+    fn test() {
+        // Fixed limits never change
+        let mut limit = Limit::fixed(4);
+        let now = run_window(&mut limit, Instant::now(), 100, Duration::from_millis(1));
+        limit.failed(now);
+        assert_eq!(limit.get(), 4);
+
+        // Grow while throughput improves
+        let mut limit = Limit::adaptive(2, 16);
+        let start = limit.window_start;
+        let now = run_window(&mut limit, start, 10, Duration::from_millis(10));
+        assert_eq!(limit.get(), 4);
+        let now = run_window(&mut limit, now, 20, Duration::from_millis(10));
+        assert_eq!(limit.get(), 8);
+
+        // Hold when throughput stops improving
+        let now = run_window(&mut limit, now, 20, Duration::from_millis(10));
+        assert_eq!(limit.get(), 8);
+
+        // Back off when latency grows without improved throughput
+        let now = run_window(&mut limit, now, 20, Duration::from_millis(20));
+        assert_eq!(limit.get(), 6);
+
+        // Back off on error, but never below the initial limit
+        limit.failed(now);
+        assert_eq!(limit.get(), 5);
+        limit.failed(now);
+        limit.failed(now);
+        limit.failed(now);
+        assert_eq!(limit.get(), 2);
+    }
+}
@@ -0,0 +1,619 @@
+use super::*;
+use aes_gcm::{
+    aead::{Aead, KeyInit, Payload},
+    Aes256Gcm, Nonce,
+};
+use hardy_bpa_api::async_trait;
+use rand::RngCore;
+use std::borrow::Cow;
+
+/* With a key configured in 'storage_encryption', the metadata and bundle storage engines are
+ * wrapped so that bundle data, whether in the bundle storage or inline with the metadata,
+ * and the hashes of the data are encrypted with AES-256-GCM before they reach the engines,
+ * and decrypted as they are loaded.  Everything above the store sees plaintext.  The EIDs,
+ * timestamps and status of each bundle stay in the clear, as the engines index on them.
+ *
+ * Each encrypted value is MAGIC, the fingerprint of the key it was encrypted with, a random
+ * nonce and the ciphertext.  The first key of the keyring encrypts, and the rest are retired
+ * keys kept so that values encrypted before a key rotation can still be read.
+ *
+ * Hashes and inline data are bound to their storage name by the associated data, so one
+ * cannot be swapped for another.  Data in the bundle storage is only named once it has been
+ * stored, so it is bound through its hash instead, and the store always verifies data against
+ * its hash while encryption is enabled.
+ *
+ * Values without MAGIC were stored before encryption was enabled.  They are rejected, as
+ * anyone able to write to the storage could plant them, unless 'allow_plaintext' is set to
+ * migrate an existing store, in which case they are passed through as they are: no bundle
+ * begins with MAGIC, and encrypted hashes are never the length of a plain hash */
+
+const MAGIC: &[u8; 3] = b"HE1";
+const FINGERPRINT_LEN: usize = 4;
+const NONCE_LEN: usize = 12;
+const HEADER_LEN: usize = MAGIC.len() + FINGERPRINT_LEN + NONCE_LEN;
+
+// Associated data, so encrypted values cannot be swapped between purposes
+const DATA_AAD: &[u8] = b"bundle data";
+const HASH_AAD: &[u8] = b"bundle hash";
+
+// Associated data that also binds a value to its storage name
+fn named_aad(purpose: &[u8], storage_name: &str) -> Vec<u8> {
+    [purpose, b":", storage_name.as_bytes()].concat()
+}
+
+struct Key {
+    fingerprint: [u8; FINGERPRINT_LEN],
+    cipher: Aes256Gcm,
+}
+
+pub struct Keyring {
+    keys: Vec<Key>,
+    allow_plaintext: bool,
+}
+
+impl Keyring {
+    // The configured keyring, if storage encryption is enabled
+    pub fn new(config: &::config::Config) -> Option<Arc<Self>> {
+        let key =
+            settings::get_with_default::<Option<String>, _>(config, "storage_encryption.key", None)
+                .trace_expect("Invalid 'storage_encryption.key' value in configuration");
+        let key_file = settings::get_with_default::<Option<std::path::PathBuf>, _>(
+            config,
+            "storage_encryption.key_file",
+            None,
+        )
+        .trace_expect("Invalid 'storage_encryption.key_file' value in configuration");
+
+        let keys = match (key, key_file) {
+            (None, None) => return None,
+            (Some(_), Some(_)) => {
+                error!("Only one of 'storage_encryption.key' and 'storage_encryption.key_file' may be set");
+                panic!("Only one of 'storage_encryption.key' and 'storage_encryption.key_file' may be set");
+            }
+            (Some(key), None) => vec![key],
+            (None, Some(path)) => std::fs::read_to_string(&path)
+                .trace_expect(&format!(
+                    "Failed to read storage encryption key file '{}'",
+                    path.display()
+                ))
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(str::to_string)
+                .collect(),
+        };
+
+        let keys = keys
+            .iter()
+            .map(|key| {
+                settings::decode_hex(key)
+                    .filter(|key| key.len() == 32)
+                    .trace_expect("Invalid storage encryption key, keys must be 64 hex digits")
+            })
+            .collect::<Vec<_>>();
+        let mut keyring =
+            Self::from_keys(&keys).trace_expect("No storage encryption keys configured");
+        keyring.allow_plaintext =
+            settings::get_with_default(config, "storage_encryption.allow_plaintext", false)
+                .trace_expect(
+                    "Invalid 'storage_encryption.allow_plaintext' value in configuration",
+                );
+        if keyring.allow_plaintext {
+            warn!("Unencrypted bundle data and hashes will be read from storage");
+        }
+
+        info!(
+            "Encrypting stored bundle data, with {} retired keys",
+            keyring.keys.len() - 1
+        );
+        Some(Arc::new(keyring))
+    }
+
+    fn from_keys(keys: &[impl AsRef<[u8]>]) -> Option<Self> {
+        if keys.is_empty() {
+            return None;
+        }
+        Some(Self {
+            keys: keys
+                .iter()
+                .map(|key| {
+                    let key = key.as_ref();
+                    let mut fingerprint = [0u8; FINGERPRINT_LEN];
+                    fingerprint.copy_from_slice(&sha2::Sha256::digest(key)[..FINGERPRINT_LEN]);
+                    Key {
+                        fingerprint,
+                        cipher: Aes256Gcm::new_from_slice(key).trace_expect("Invalid key length"),
+                    }
+                })
+                .collect(),
+            allow_plaintext: false,
+        })
+    }
+
+    // Whether a value stored before encryption was enabled may be used as it is
+    fn check_plaintext(&self, what: &str) -> storage::Result<()> {
+        if !self.allow_plaintext {
+            return Err(format!("Stored {what} is not encrypted").into());
+        }
+        warn!("Using unencrypted {what} from storage");
+        Ok(())
+    }
+
+    fn seal(&self, plaintext: &[u8], aad: &[u8]) -> storage::Result<Vec<u8>> {
+        let key = &self.keys[0];
+        let mut nonce = [0u8; NONCE_LEN];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let ciphertext = key
+            .cipher
+            .encrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: plaintext,
+                    aad,
+                },
+            )
+            .map_err(|_| "Failed to encrypt bundle data")?;
+
+        let mut value = Vec::with_capacity(HEADER_LEN + ciphertext.len());
+        value.extend_from_slice(MAGIC);
+        value.extend_from_slice(&key.fingerprint);
+        value.extend_from_slice(&nonce);
+        value.extend_from_slice(&ciphertext);
+        Ok(value)
+    }
+
+    fn open<'a>(&self, value: &'a [u8], aad: &[u8]) -> storage::Result<Cow<'a, [u8]>> {
+        if !value.starts_with(MAGIC) {
+            // Stored before encryption was enabled
+            self.check_plaintext("bundle data")?;
+            return Ok(Cow::Borrowed(value));
+        }
+        if value.len() < HEADER_LEN {
+            return Err("Encrypted bundle data is truncated".into());
+        }
+        let (fingerprint, rest) = value[MAGIC.len()..].split_at(FINGERPRINT_LEN);
+        let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+        let Some(key) = self.keys.iter().find(|key| key.fingerprint == fingerprint) else {
+            return Err("Bundle data is encrypted with a key that is not in the keyring".into());
+        };
+        key.cipher
+            .decrypt(
+                Nonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad,
+                },
+            )
+            .map(Cow::Owned)
+            .map_err(|_| {
+                "Failed to decrypt bundle data, it is corrupt or has been tampered with".into()
+            })
+    }
+
+    fn seal_hash(&self, hash: &[u8], storage_name: &str) -> storage::Result<Arc<[u8]>> {
+        self.seal(hash, &named_aad(HASH_AAD, storage_name))
+            .map(Into::into)
+    }
+
+    fn open_hash(&self, hash: &Arc<[u8]>, storage_name: &str) -> storage::Result<Arc<[u8]>> {
+        if HashAlgorithm::from_hash(hash).is_some() {
+            // A plain hash, stored before encryption was enabled
+            self.check_plaintext("bundle hash")?;
+            return Ok(hash.clone());
+        }
+        self.open(hash, &named_aad(HASH_AAD, storage_name))
+            .map(Into::into)
+    }
+
+    fn seal_metadata<'a>(
+        &self,
+        metadata: &'a metadata::Metadata,
+    ) -> storage::Result<Cow<'a, metadata::Metadata>> {
+        let Some(hash) = &metadata.hash else {
+            return Ok(Cow::Borrowed(metadata));
+        };
+        let storage_name = metadata.storage_name.as_deref().unwrap_or_default();
+        Ok(Cow::Owned(metadata::Metadata {
+            hash: Some(self.seal_hash(hash, storage_name)?),
+            ..metadata.clone()
+        }))
+    }
+
+    fn open_metadata(&self, metadata: &mut metadata::Metadata) -> storage::Result<()> {
+        if let Some(hash) = &metadata.hash {
+            let storage_name = metadata.storage_name.as_deref().unwrap_or_default();
+            metadata.hash = Some(self.open_hash(hash, storage_name)?);
+        }
+        Ok(())
+    }
+
+    fn open_data(&self, data: storage::DataRef, aad: &[u8]) -> storage::Result<storage::DataRef> {
+        let opened = match self.open((*data).as_ref(), aad)? {
+            Cow::Borrowed(_) => None,
+            Cow::Owned(opened) => Some(opened),
+        };
+        Ok(opened.map_or(data, |opened| Arc::new(opened) as storage::DataRef))
+    }
+}
+
+pub struct MetadataStorage {
+    inner: Arc<dyn storage::MetadataStorage>,
+    keyring: Arc<Keyring>,
+}
+
+impl MetadataStorage {
+    pub fn wrap(
+        inner: Arc<dyn storage::MetadataStorage>,
+        keyring: Arc<Keyring>,
+    ) -> Arc<dyn storage::MetadataStorage> {
+        Arc::new(Self { inner, keyring })
+    }
+
+    // Run a scan of the inner engine, decrypting the bundles it finds on their way to `tx`
+    async fn relay<F, Fut>(&self, tx: storage::Sender, scan: F) -> storage::Result<()>
+    where
+        F: FnOnce(storage::Sender) -> Fut,
+        Fut: std::future::Future<Output = storage::Result<()>>,
+    {
+        let (inner_tx, mut rx) = tokio::sync::mpsc::channel::<metadata::Bundle>(tx.max_capacity());
+        let forward = async move {
+            while let Some(mut bundle) = rx.recv().await {
+                self.keyring.open_metadata(&mut bundle.metadata)?;
+                if tx.send(bundle).await.is_err() {
+                    break;
+                }
+            }
+            Ok::<_, storage::Error>(())
+        };
+        let (scanned, forwarded) = tokio::join!(scan(inner_tx), forward);
+        forwarded?;
+        scanned
+    }
+}
+
+#[async_trait]
+impl storage::MetadataStorage for MetadataStorage {
+    async fn load(&self, bundle_id: &bpv7::BundleId) -> storage::Result<Option<metadata::Bundle>> {
+        let Some(mut bundle) = self.inner.load(bundle_id).await? else {
+            return Ok(None);
+        };
+        self.keyring.open_metadata(&mut bundle.metadata)?;
+        Ok(Some(bundle))
+    }
+
+    async fn store(
+        &self,
+        metadata: &metadata::Metadata,
+        bundle: &bpv7::Bundle,
+    ) -> storage::Result<bool> {
+        let metadata = self.keyring.seal_metadata(metadata)?;
+        self.inner.store(&metadata, bundle).await
+    }
+
+    async fn get_bundle_status(
+        &self,
+        bundle_id: &bpv7::BundleId,
+    ) -> storage::Result<Option<metadata::BundleStatus>> {
+        self.inner.get_bundle_status(bundle_id).await
+    }
+
+    async fn set_bundle_status(
+        &self,
+        bundle_id: &bpv7::BundleId,
+        status: &metadata::BundleStatus,
+    ) -> storage::Result<()> {
+        self.inner.set_bundle_status(bundle_id, status).await
+    }
+
+    async fn claim_bundle_statuses(
+        &self,
+        claims: &[(bpv7::BundleId, metadata::BundleStatus)],
+        status: &metadata::BundleStatus,
+    ) -> storage::Result<Vec<bool>> {
+        self.inner.claim_bundle_statuses(claims, status).await
+    }
+
+    async fn remove(&self, bundle_id: &bpv7::BundleId) -> storage::Result<()> {
+        self.inner.remove(bundle_id).await
+    }
+
+    async fn confirm_exists(
+        &self,
+        bundle_id: &bpv7::BundleId,
+    ) -> storage::Result<Option<metadata::Metadata>> {
+        let Some(mut metadata) = self.inner.confirm_exists(bundle_id).await? else {
+            return Ok(None);
+        };
+        self.keyring.open_metadata(&mut metadata)?;
+        Ok(Some(metadata))
+    }
+
+    async fn get_waiting_bundles(
+        &self,
+        limit: time::OffsetDateTime,
+        budget: storage::ScanBudget,
+        tx: storage::Sender,
+    ) -> storage::Result<()> {
+        self.relay(tx, |tx| self.inner.get_waiting_bundles(limit, budget, tx))
+            .await
+    }
+
+    async fn get_unconfirmed_bundles(&self, tx: storage::Sender) -> storage::Result<()> {
+        self.relay(tx, |tx| self.inner.get_unconfirmed_bundles(tx))
+            .await
+    }
+
+    async fn poll_for_collection(
+        &self,
+        destination: bpv7::Eid,
+        budget: storage::ScanBudget,
+        tx: storage::Sender,
+    ) -> storage::Result<()> {
+        self.relay(tx, |tx| {
+            self.inner.poll_for_collection(destination, budget, tx)
+        })
+        .await
+    }
+
+    async fn get_waiting_destinations(&self) -> storage::Result<Vec<bpv7::Eid>> {
+        self.inner.get_waiting_destinations().await
+    }
+
+    async fn get_waiting_bundles_for(
+        &self,
+        destination: &bpv7::Eid,
+        tx: storage::Sender,
+    ) -> storage::Result<()> {
+        self.relay(tx, |tx| self.inner.get_waiting_bundles_for(destination, tx))
+            .await
+    }
+
+    async fn purge_tombstones(&self, older_than: time::OffsetDateTime) -> storage::Result<u64> {
+        self.inner.purge_tombstones(older_than).await
+    }
+
+    async fn get_fragments(
+        &self,
+        bundle_id: &bpv7::BundleId,
+        tx: storage::Sender,
+    ) -> storage::Result<()> {
+        self.relay(tx, |tx| self.inner.get_fragments(bundle_id, tx))
+            .await
+    }
+
+    async fn get_reassembly_pending(&self, tx: storage::Sender) -> storage::Result<()> {
+        self.relay(tx, |tx| self.inner.get_reassembly_pending(tx))
+            .await
+    }
+
+    async fn get_stored_bundles(&self, tx: storage::Sender) -> storage::Result<()> {
+        self.relay(tx, |tx| self.inner.get_stored_bundles(tx)).await
+    }
+
+    fn capabilities(&self) -> storage::Capabilities {
+        self.inner.capabilities()
+    }
+
+    async fn set_bundle_statuses(
+        &self,
+        updates: &[(bpv7::BundleId, metadata::BundleStatus)],
+    ) -> storage::Result<()> {
+        self.inner.set_bundle_statuses(updates).await
+    }
+
+    async fn approximate_count(&self) -> storage::Result<Option<u64>> {
+        self.inner.approximate_count().await
+    }
+
+    fn supports_inline_data(&self) -> bool {
+        self.inner.supports_inline_data()
+    }
+
+    async fn store_inline(
+        &self,
+        metadata: &metadata::Metadata,
+        bundle: &bpv7::Bundle,
+        data: &[u8],
+    ) -> storage::Result<bool> {
+        let metadata = self.keyring.seal_metadata(metadata)?;
+        let storage_name = metadata.storage_name.as_deref().unwrap_or_default();
+        let data = self
+            .keyring
+            .seal(data, &named_aad(DATA_AAD, storage_name))?;
+        self.inner.store_inline(&metadata, bundle, &data).await
+    }
+
+    async fn load_inline(&self, storage_name: &str) -> storage::Result<Option<storage::DataRef>> {
+        self.inner
+            .load_inline(storage_name)
+            .await?
+            .map(|data| {
+                self.keyring
+                    .open_data(data, &named_aad(DATA_AAD, storage_name))
+            })
+            .transpose()
+    }
+
+    async fn get_inline_bundles(&self, tx: storage::Sender) -> storage::Result<()> {
+        self.relay(tx, |tx| self.inner.get_inline_bundles(tx)).await
+    }
+
+    fn supports_multicast_delivery(&self) -> bool {
+        self.inner.supports_multicast_delivery()
+    }
+
+    async fn confirm_delivery(
+        &self,
+        bundle_id: &bpv7::BundleId,
+        registration: &str,
+    ) -> storage::Result<bool> {
+        self.inner.confirm_delivery(bundle_id, registration).await
+    }
+
+    async fn get_deliveries(&self, bundle_id: &bpv7::BundleId) -> storage::Result<Vec<String>> {
+        self.inner.get_deliveries(bundle_id).await
+    }
+
+    async fn update_hashes(&self, hashes: &[(Arc<str>, Arc<[u8]>)]) -> storage::Result<u64> {
+        let hashes = hashes
+            .iter()
+            .map(|(storage_name, hash)| {
+                Ok((
+                    storage_name.clone(),
+                    self.keyring.seal_hash(hash, storage_name)?,
+                ))
+            })
+            .collect::<storage::Result<Vec<_>>>()?;
+        self.inner.update_hashes(&hashes).await
+    }
+
+    fn schema_version(&self) -> Option<String> {
+        self.inner.schema_version()
+    }
+}
+
+pub struct BundleStorage {
+    inner: Arc<dyn storage::BundleStorage>,
+    keyring: Arc<Keyring>,
+}
+
+impl BundleStorage {
+    pub fn wrap(
+        inner: Arc<dyn storage::BundleStorage>,
+        keyring: Arc<Keyring>,
+    ) -> Arc<dyn storage::BundleStorage> {
+        if inner.capabilities().streaming || inner.supports_sparse_reassembly() {
+            warn!("Bundle data is encrypted as a whole, so large bundles are buffered in memory rather than streamed or reassembled in place by the bundle storage engine");
+        }
+        Arc::new(Self { inner, keyring })
+    }
+}
+
+/* Encrypted data is sealed as a whole, so the streaming, range and sparse reassembly methods
+ * of the inner engine must not be forwarded to, as they would read or write plaintext, or
+ * ciphertext at plaintext offsets.  Instead the trait defaults apply: streams are buffered and
+ * stored through store(), ranges and streams are read by decrypting all of the data through
+ * load(), and sparse reassembly is reported as unsupported, so fragments are reassembled in
+ * memory.  wrap() warns when this gives up something the inner engine could do */
+#[async_trait]
+impl storage::BundleStorage for BundleStorage {
+    async fn list(
+        &self,
+        tx: tokio::sync::mpsc::Sender<storage::ListResponse>,
+    ) -> storage::Result<()> {
+        self.inner.list(tx).await
+    }
+
+    async fn load(&self, storage_name: &str) -> storage::Result<Option<storage::DataRef>> {
+        self.inner
+            .load(storage_name)
+            .await?
+            .map(|data| self.keyring.open_data(data, DATA_AAD))
+            .transpose()
+    }
+
+    async fn store(&self, data: &[u8]) -> storage::Result<Arc<str>> {
+        let data = self.keyring.seal(data, DATA_AAD)?;
+        self.inner.store(&data).await
+    }
+
+    async fn remove(&self, storage_name: &str) -> storage::Result<()> {
+        self.inner.remove(storage_name).await
+    }
+
+    fn capabilities(&self) -> storage::Capabilities {
+        storage::Capabilities {
+            streaming: false,
+            ..self.inner.capabilities()
+        }
+    }
+
+    fn supports_expiry_hints(&self) -> bool {
+        self.inner.supports_expiry_hints()
+    }
+
+    async fn hint_expiry(
+        &self,
+        storage_name: &str,
+        expiry: time::OffsetDateTime,
+    ) -> storage::Result<()> {
+        self.inner.hint_expiry(storage_name, expiry).await
+    }
+
+    fn schema_version(&self) -> Option<String> {
+        self.inner.schema_version()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keyring(keys: &[[u8; 32]]) -> Keyring {
+        Keyring::from_keys(keys).unwrap()
+    }
+
+    #[test]
+    fn roundtrip() {
+        let keyring = keyring(&[[1; 32]]);
+        let sealed = keyring.seal(b"bundle", DATA_AAD).unwrap();
+        assert!(sealed.starts_with(MAGIC));
+        assert_eq!(&*keyring.open(&sealed, DATA_AAD).unwrap(), b"bundle");
+
+        // Not as a hash
+        assert!(keyring.open(&sealed, HASH_AAD).is_err());
+
+        // Tampered with
+        let mut tampered = sealed.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(keyring.open(&tampered, DATA_AAD).is_err());
+    }
+
+    #[test]
+    fn rotation() {
+        let old = keyring(&[[1; 32]]);
+        let sealed = old.seal(b"bundle", DATA_AAD).unwrap();
+
+        let rotated = keyring(&[[2; 32], [1; 32]]);
+        assert_eq!(&*rotated.open(&sealed, DATA_AAD).unwrap(), b"bundle");
+        assert!(rotated
+            .seal(b"bundle", DATA_AAD)
+            .is_ok_and(|sealed| old.open(&sealed, DATA_AAD).is_err()));
+
+        let unrelated = keyring(&[[3; 32]]);
+        assert!(unrelated.open(&sealed, DATA_AAD).is_err());
+    }
+
+    #[test]
+    fn named() {
+        let keyring = keyring(&[[1; 32]]);
+        let hash: Arc<[u8]> = [0; 32].into();
+        let sealed = keyring.seal_hash(&hash, "a").unwrap();
+        assert_eq!(keyring.open_hash(&sealed, "a").unwrap(), hash);
+
+        // Moved to another bundle
+        assert!(keyring.open_hash(&sealed, "b").is_err());
+    }
+
+    #[test]
+    fn plaintext() {
+        let mut keyring = keyring(&[[1; 32]]);
+
+        // Data and hashes stored before encryption was enabled are refused...
+        let hash: Arc<[u8]> = [&MAGIC[..], &[0; 29]].concat().into();
+        assert!(keyring.open(&[0x9f, 0x89, 0x07], DATA_AAD).is_err());
+        assert!(keyring.open_hash(&hash, "a").is_err());
+
+        // ... unless allowed
+        keyring.allow_plaintext = true;
+        assert!(matches!(
+            keyring.open(&[0x9f, 0x89, 0x07], DATA_AAD),
+            Ok(Cow::Borrowed(_))
+        ));
+        assert_eq!(keyring.open_hash(&hash, "a").unwrap(), hash);
+
+        let sealed = keyring.seal_hash(&hash, "a").unwrap();
+        assert!(HashAlgorithm::from_hash(&sealed).is_none());
+        assert_eq!(keyring.open_hash(&sealed, "a").unwrap(), hash);
+    }
+}
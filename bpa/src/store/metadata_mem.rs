@@ -75,6 +75,24 @@ impl storage::MetadataStorage for Storage {
             .ok_or(Error::NotFound.into())
     }
 
+    async fn claim_bundle_statuses(
+        &self,
+        claims: &[(bpv7::BundleId, metadata::BundleStatus)],
+        status: &metadata::BundleStatus,
+    ) -> storage::Result<Vec<bool>> {
+        let mut entries = self.entries.write().await;
+        Ok(claims
+            .iter()
+            .map(|(bundle_id, expected)| match entries.get_mut(bundle_id) {
+                Some(bundle) if &bundle.metadata.status == expected => {
+                    bundle.metadata.status = status.clone();
+                    true
+                }
+                _ => false,
+            })
+            .collect())
+    }
+
     async fn remove(&self, bundle_id: &bpv7::BundleId) -> storage::Result<()> {
         self.entries
             .write()
@@ -94,10 +112,12 @@ impl storage::MetadataStorage for Storage {
     async fn get_waiting_bundles(
         &self,
         limit: time::OffsetDateTime,
+        budget: storage::ScanBudget,
         tx: storage::Sender,
     ) -> storage::Result<()> {
         // Drop all tombstones and collect waiting
         let mut tombstones = Vec::new();
+        let mut waiting = Vec::new();
 
         let mut entries = self.entries.write().await;
 
@@ -112,14 +132,20 @@ impl storage::MetadataStorage for Storage {
                 | metadata::BundleStatus::Waiting(until)
                     if until <= limit =>
                 {
-                    if tx.send(bundle.clone()).await.is_err() {
-                        break;
-                    }
+                    waiting.push((until, bundle));
                 }
                 _ => {}
             }
         }
 
+        // Soonest first
+        waiting.sort_by_key(|(until, _)| *until);
+        for (count, (_, bundle)) in waiting.into_iter().enumerate() {
+            if budget.is_spent(count) || tx.send(bundle.clone()).await.is_err() {
+                break;
+            }
+        }
+
         // Remove tombstones from index
         for bundle_id in tombstones {
             entries.remove(&bundle_id);
@@ -127,6 +153,128 @@ impl storage::MetadataStorage for Storage {
         Ok(())
     }
 
+    async fn get_waiting_destinations(&self) -> storage::Result<Vec<bpv7::Eid>> {
+        let mut destinations = std::collections::HashSet::new();
+        for bundle in self.entries.read().await.values() {
+            if let metadata::BundleStatus::Waiting(_) | metadata::BundleStatus::ForwardPending =
+                bundle.metadata.status
+            {
+                destinations.insert(bundle.bundle.destination.clone());
+            }
+        }
+        Ok(destinations.into_iter().collect())
+    }
+
+    async fn get_waiting_bundles_for(
+        &self,
+        destination: &bpv7::Eid,
+        tx: storage::Sender,
+    ) -> storage::Result<()> {
+        for bundle in self.entries.read().await.values() {
+            if let metadata::BundleStatus::Waiting(_) | metadata::BundleStatus::ForwardPending =
+                bundle.metadata.status
+            {
+                if &bundle.bundle.destination == destination
+                    && tx.send(bundle.clone()).await.is_err()
+                {
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn purge_tombstones(&self, older_than: time::OffsetDateTime) -> storage::Result<u64> {
+        let mut purged = 0u64;
+        self.entries
+            .write()
+            .await
+            .retain(|_, bundle| match bundle.metadata.status {
+                metadata::BundleStatus::Tombstone(from) if from < older_than => {
+                    purged = purged.saturating_add(1);
+                    false
+                }
+                _ => true,
+            });
+        Ok(purged)
+    }
+
+    async fn get_fragments(
+        &self,
+        bundle_id: &bpv7::BundleId,
+        tx: storage::Sender,
+    ) -> storage::Result<()> {
+        for bundle in self.entries.read().await.values() {
+            if let metadata::BundleStatus::ReassemblyPending = bundle.metadata.status {
+                if bundle.bundle.id.fragment_info.is_some()
+                    && bundle.bundle.id.source == bundle_id.source
+                    && bundle.bundle.id.timestamp == bundle_id.timestamp
+                    && tx.send(bundle.clone()).await.is_err()
+                {
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn get_reassembly_pending(&self, tx: storage::Sender) -> storage::Result<()> {
+        for bundle in self.entries.read().await.values() {
+            if let metadata::BundleStatus::ReassemblyPending = bundle.metadata.status {
+                if tx.send(bundle.clone()).await.is_err() {
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn get_stored_bundles(&self, tx: storage::Sender) -> storage::Result<()> {
+        for bundle in self.entries.read().await.values() {
+            if let metadata::BundleStatus::Tombstone(_) = bundle.metadata.status {
+                continue;
+            }
+            if tx.send(bundle.clone()).await.is_err() {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn capabilities(&self) -> storage::Capabilities {
+        // There is no index by destination, but everything else is to hand
+        storage::Capabilities {
+            transactional_batches: true,
+            approximate_counts: true,
+            ..Default::default()
+        }
+    }
+
+    async fn set_bundle_statuses(
+        &self,
+        updates: &[(bpv7::BundleId, metadata::BundleStatus)],
+    ) -> storage::Result<()> {
+        // All or nothing
+        let mut entries = self.entries.write().await;
+        if updates
+            .iter()
+            .any(|(bundle_id, _)| !entries.contains_key(bundle_id))
+        {
+            return Err(Error::NotFound.into());
+        }
+        for (bundle_id, status) in updates {
+            if let Some(bundle) = entries.get_mut(bundle_id) {
+                bundle.metadata.status = status.clone();
+            }
+        }
+        Ok(())
+    }
+
+    async fn approximate_count(&self) -> storage::Result<Option<u64>> {
+        // Tombstones are counted too, which is near enough
+        Ok(Some(self.entries.read().await.len() as u64))
+    }
+
     async fn get_unconfirmed_bundles(&self, _tx: storage::Sender) -> storage::Result<()> {
         // We have no persistence, so therefore no orphans
         Ok(())
@@ -135,6 +283,7 @@ impl storage::MetadataStorage for Storage {
     async fn poll_for_collection(
         &self,
         _destination: bpv7::Eid,
+        _budget: storage::ScanBudget,
         _tx: storage::Sender,
     ) -> storage::Result<()> {
         todo!()
@@ -1,32 +1,267 @@
 use super::*;
 use hardy_bpa_api::storage;
 use sha2::Digest;
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc};
 use utils::settings;
 
+mod check;
+mod concurrency;
+mod encryption;
+mod quota;
+mod rehash;
+mod residency;
+mod urgency;
+mod wheel;
+
 #[cfg(feature = "mem-storage")]
 mod metadata_mem;
 
+pub use quota::Reservation;
+
 #[cfg(feature = "mem-storage")]
 mod bundle_mem;
 
-fn hash(data: &[u8]) -> Arc<[u8]> {
-    sha2::Sha256::digest(data).to_vec().into()
+// How many times to retry loading bundle data during restart before giving up
+const RESTART_RETRIES: u32 = 3;
+
+// How long to wait before the first retry, doubling for each retry after that
+const RESTART_RETRY_DELAY: time::Duration = time::Duration::milliseconds(500);
+
+// Storage names for bundle data held inline in the metadata storage
+const INLINE_PREFIX: &str = "inline:";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum HashAlgorithm {
+    Sha256,
+    Sha384,
+    Sha512,
+}
+
+impl HashAlgorithm {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "sha256" => Some(Self::Sha256),
+            "sha384" => Some(Self::Sha384),
+            "sha512" => Some(Self::Sha512),
+            _ => None,
+        }
+    }
+
+    // Hashes are not tagged, but the algorithms all have different digest lengths
+    fn from_hash(hash: &[u8]) -> Option<Self> {
+        match hash.len() {
+            32 => Some(Self::Sha256),
+            48 => Some(Self::Sha384),
+            64 => Some(Self::Sha512),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Sha256 => "sha256",
+            Self::Sha384 => "sha384",
+            Self::Sha512 => "sha512",
+        }
+    }
+
+    fn hash(self, data: &[u8]) -> Arc<[u8]> {
+        match self {
+            Self::Sha256 => sha2::Sha256::digest(data).to_vec().into(),
+            Self::Sha384 => sha2::Sha384::digest(data).to_vec().into(),
+            Self::Sha512 => sha2::Sha512::digest(data).to_vec().into(),
+        }
+    }
+
+    fn hasher(self) -> Hasher {
+        match self {
+            Self::Sha256 => Hasher::Sha256(sha2::Sha256::new()),
+            Self::Sha384 => Hasher::Sha384(sha2::Sha384::new()),
+            Self::Sha512 => Hasher::Sha512(sha2::Sha512::new()),
+        }
+    }
+}
+
+// For hashing bundle data as it is streamed, rather than all at once
+enum Hasher {
+    Sha256(sha2::Sha256),
+    Sha384(sha2::Sha384),
+    Sha512(sha2::Sha512),
+}
+
+impl Hasher {
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            Self::Sha256(h) => h.update(data),
+            Self::Sha384(h) => h.update(data),
+            Self::Sha512(h) => h.update(data),
+        }
+    }
+
+    fn finish(self) -> Arc<[u8]> {
+        match self {
+            Self::Sha256(h) => h.finalize().to_vec().into(),
+            Self::Sha384(h) => h.finalize().to_vec().into(),
+            Self::Sha512(h) => h.finalize().to_vec().into(),
+        }
+    }
+}
+
+// Hashes made with an algorithm we don't recognise cannot be checked, so are assumed to match
+fn hash_matches(hash: &[u8], data: &[u8]) -> bool {
+    HashAlgorithm::from_hash(hash).is_none_or(|algorithm| *algorithm.hash(data) == *hash)
+}
+
+fn is_inline(storage_name: &str) -> bool {
+    storage_name.starts_with(INLINE_PREFIX)
 }
 
 struct Config {
     wait_sample_interval: u64,
+    wait_scan_limit: Option<usize>,
+    inline_data_threshold: usize,
+    duplicate_window: u64,
+    restart_concurrency: usize,
+    restart_max_concurrency: usize,
+    consistency_check_interval: u64,
+    consistency_check_rate: Option<u32>,
+    hash_algorithm: HashAlgorithm,
+    verify_on_load: bool,
+    quarantine_dir: Option<std::path::PathBuf>,
+    read_only: bool,
+    state_metrics: bool,
+    bulk_destinations: bpv7::EidPatternMap<(), ()>,
+    expedited_destinations: bpv7::EidPatternMap<(), ()>,
+    max_bytes: Option<u64>,
+    max_bundles: Option<u64>,
+    eviction: quota::Eviction,
+}
+
+// The store settings, as they appear in the configuration
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+struct Settings {
+    wait_sample_interval: u64,
+    wait_scan_limit: usize,
+    inline_data_threshold: usize,
+    duplicate_window: u64,
+    restart_concurrency: usize,
+    restart_max_concurrency: usize,
+    consistency_check_interval: u64,
+    consistency_check_rate: u32,
+    hash_algorithm: String,
+    verify_on_load: bool,
+    quarantine_dir: Option<std::path::PathBuf>,
+    read_only: bool,
+    state_metrics: bool,
+    priority: PrioritySettings,
+    quota: QuotaSettings,
+}
+
+#[derive(Debug, Default, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+struct PrioritySettings {
+    bulk: Vec<String>,
+    expedited: Vec<String>,
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+struct QuotaSettings {
+    max_bytes: u64,
+    max_bundles: u64,
+    eviction: String,
+}
+
+impl Default for QuotaSettings {
+    fn default() -> Self {
+        Self {
+            max_bytes: 0,
+            max_bundles: 0,
+            eviction: "shortest_lifetime".to_string(),
+        }
+    }
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            wait_sample_interval: settings::WAIT_SAMPLE_INTERVAL_SECS,
+            wait_scan_limit: 0,
+            inline_data_threshold: 0,
+            duplicate_window: 0,
+            restart_concurrency: 0,
+            restart_max_concurrency: 256,
+            consistency_check_interval: 0,
+            consistency_check_rate: 100,
+            hash_algorithm: HashAlgorithm::Sha256.name().to_string(),
+            verify_on_load: false,
+            quarantine_dir: None,
+            read_only: false,
+            state_metrics: true,
+            priority: PrioritySettings::default(),
+            quota: QuotaSettings::default(),
+        }
+    }
 }
 
 impl Config {
     fn new(config: &config::Config) -> Self {
+        let settings: Settings = settings::load(config, "store");
         let config = Self {
-            wait_sample_interval: settings::get_with_default(
-                config,
-                "wait_sample_interval",
-                settings::WAIT_SAMPLE_INTERVAL_SECS,
-            )
-            .trace_expect("Invalid 'wait_sample_interval' value in configuration"),
+            wait_sample_interval: settings.wait_sample_interval,
+            wait_scan_limit: match settings.wait_scan_limit {
+                0 => None,
+                limit => Some(limit),
+            },
+            inline_data_threshold: settings.inline_data_threshold,
+            duplicate_window: settings.duplicate_window,
+            restart_concurrency: settings.restart_concurrency,
+            restart_max_concurrency: settings.restart_max_concurrency,
+            consistency_check_interval: settings.consistency_check_interval,
+            consistency_check_rate: match settings.consistency_check_rate {
+                0 => None,
+                rate => Some(rate),
+            },
+            hash_algorithm: HashAlgorithm::from_name(&settings.hash_algorithm).unwrap_or_else(
+                || {
+                    error!(
+                        "Unsupported 'hash_algorithm' value '{}' in configuration",
+                        settings.hash_algorithm
+                    );
+                    panic!(
+                        "Unsupported 'hash_algorithm' value '{}' in configuration",
+                        settings.hash_algorithm
+                    );
+                },
+            ),
+            verify_on_load: settings.verify_on_load,
+            quarantine_dir: settings.quarantine_dir,
+            read_only: settings.read_only,
+            state_metrics: settings.state_metrics,
+            bulk_destinations: Self::load_destinations(&settings.priority.bulk, "priority.bulk"),
+            expedited_destinations: Self::load_destinations(
+                &settings.priority.expedited,
+                "priority.expedited",
+            ),
+            max_bytes: match settings.quota.max_bytes {
+                0 => None,
+                max => Some(max),
+            },
+            max_bundles: match settings.quota.max_bundles {
+                0 => None,
+                max => Some(max),
+            },
+            eviction: quota::Eviction::from_name(&settings.quota.eviction).unwrap_or_else(|| {
+                error!(
+                    "Unsupported 'quota.eviction' value '{}' in configuration",
+                    settings.quota.eviction
+                );
+                panic!(
+                    "Unsupported 'quota.eviction' value '{}' in configuration",
+                    settings.quota.eviction
+                );
+            }),
         };
 
         if config.wait_sample_interval > i64::MAX as u64 {
@@ -34,20 +269,64 @@ impl Config {
             panic!("wait_sample_interval is too large");
         }
 
+        if config.consistency_check_interval > i64::MAX as u64 {
+            error!("consistency_check_interval is too large");
+            panic!("consistency_check_interval is too large");
+        }
+
+        if config.duplicate_window > i64::MAX as u64 {
+            error!("duplicate_window is too large");
+            panic!("duplicate_window is too large");
+        }
+
         config
     }
+
+    fn load_destinations(patterns: &[String], key: &str) -> bpv7::EidPatternMap<(), ()> {
+        let mut m = bpv7::EidPatternMap::new();
+        for s in patterns {
+            let p: bpv7::EidPattern = s
+                .parse()
+                .trace_expect(&format!("Invalid EID pattern '{s}' in '{key}'"));
+            m.insert(&p, (), ());
+        }
+        m
+    }
 }
 
 pub struct Store {
     config: Config,
     metadata_storage: Arc<dyn storage::MetadataStorage>,
     bundle_storage: Arc<dyn storage::BundleStorage>,
+
+    // Small bundle data waiting to be written alongside its metadata
+    pending_inline: std::sync::Mutex<HashMap<Arc<str>, Arc<[u8]>>>,
+
+    // Held while a runtime consistency check is running
+    consistency_lock: tokio::sync::Mutex<()>,
+
+    // The names of the storage engines in use
+    metadata_engine: String,
+    bundle_engine: String,
+
+    // When bundles entered their current status, if 'state_metrics' is enabled
+    residency: Option<residency::Residency>,
+
+    // When waiting bundles next need dispatching
+    deadlines: Arc<wheel::Deadlines>,
+
+    // The stored bundles counted against the quota, if either limit is set
+    quota: Option<Arc<quota::Quota>>,
+
+    // Whether the storage engines are wrapped to encrypt what they store
+    encrypted: bool,
 }
 
 fn init_metadata_storage(
     config: &config::Config,
     upgrade: bool,
-) -> Arc<dyn storage::MetadataStorage> {
+    read_only: bool,
+) -> (String, Arc<dyn storage::MetadataStorage>) {
     cfg_if::cfg_if! {
         if #[cfg(feature = "sqlite-storage")] {
             const DEFAULT: &str = hardy_sqlite_storage::CONFIG_KEY;
@@ -64,18 +343,40 @@ fn init_metadata_storage(
     info!("Using '{engine}' metadata storage engine");
 
     let config = config.get_table(&engine).unwrap_or_default();
-    match engine.as_str() {
+    let storage = match engine.as_str() {
         #[cfg(feature = "sqlite-storage")]
-        hardy_sqlite_storage::CONFIG_KEY => hardy_sqlite_storage::Storage::init(&config, upgrade),
+        hardy_sqlite_storage::CONFIG_KEY => {
+            hardy_sqlite_storage::Storage::init(&config, upgrade, read_only)
+        }
+
+        #[cfg(feature = "rocksdb-storage")]
+        hardy_rocksdb_storage::CONFIG_KEY if read_only => {
+            error!("The '{engine}' metadata storage engine cannot be opened read-only");
+            panic!("The '{engine}' metadata storage engine cannot be opened read-only");
+        }
+
+        #[cfg(feature = "rocksdb-storage")]
+        hardy_rocksdb_storage::CONFIG_KEY => hardy_rocksdb_storage::Storage::init(&config),
+
+        #[cfg(feature = "mem-storage")]
+        metadata_mem::CONFIG_KEY if read_only => {
+            error!("The '{engine}' metadata storage engine cannot be opened read-only");
+            panic!("The '{engine}' metadata storage engine cannot be opened read-only");
+        }
 
         #[cfg(feature = "mem-storage")]
         metadata_mem::CONFIG_KEY => metadata_mem::Storage::init(&config),
 
         _ => panic!("Unknown metadata storage engine: {engine}"),
-    }
+    };
+    (engine, storage)
 }
 
-fn init_bundle_storage(config: &config::Config, _upgrade: bool) -> Arc<dyn storage::BundleStorage> {
+fn init_bundle_storage(
+    config: &config::Config,
+    _upgrade: bool,
+    read_only: bool,
+) -> (String, Arc<dyn storage::BundleStorage>) {
     cfg_if::cfg_if! {
         if #[cfg(feature = "localdisk-storage")] {
             const DEFAULT: &str = hardy_localdisk_storage::CONFIG_KEY;
@@ -92,35 +393,191 @@ fn init_bundle_storage(config: &config::Config, _upgrade: bool) -> Arc<dyn stora
     info!("Using '{engine}' bundle storage engine");
 
     let config = config.get_table(&engine).unwrap_or_default();
-    match engine.as_str() {
+    let storage = match engine.as_str() {
         #[cfg(feature = "localdisk-storage")]
-        hardy_localdisk_storage::CONFIG_KEY => hardy_localdisk_storage::Storage::init(&config),
+        hardy_localdisk_storage::CONFIG_KEY => {
+            hardy_localdisk_storage::Storage::init(&config, read_only)
+        }
+
+        #[cfg(feature = "rocksdb-storage")]
+        hardy_rocksdb_storage::CONFIG_KEY if read_only => {
+            error!("The '{engine}' bundle storage engine cannot be opened read-only");
+            panic!("The '{engine}' bundle storage engine cannot be opened read-only");
+        }
+
+        #[cfg(feature = "rocksdb-storage")]
+        hardy_rocksdb_storage::CONFIG_KEY => hardy_rocksdb_storage::Storage::init(&config),
+
+        #[cfg(feature = "mem-storage")]
+        bundle_mem::CONFIG_KEY if read_only => {
+            error!("The '{engine}' bundle storage engine cannot be opened read-only");
+            panic!("The '{engine}' bundle storage engine cannot be opened read-only");
+        }
 
         #[cfg(feature = "mem-storage")]
         bundle_mem::CONFIG_KEY => bundle_mem::Storage::init(&config),
 
         _ => panic!("Unknown bundle storage engine: {engine}"),
-    }
+    };
+    (engine, storage)
 }
 
 impl Store {
     pub fn new(config: &config::Config, upgrade: bool) -> Arc<Self> {
+        Self::with_engines(config, upgrade, None, None)
+    }
+
+    // As new, but using the storage engines given, rather than those configured
+    pub fn with_engines(
+        config: &config::Config,
+        upgrade: bool,
+        metadata_storage: Option<Arc<dyn storage::MetadataStorage>>,
+        bundle_storage: Option<Arc<dyn storage::BundleStorage>>,
+    ) -> Arc<Self> {
+        let store_config = Config::new(config);
+        if store_config.read_only {
+            if upgrade {
+                error!("Cannot upgrade a store opened read-only");
+                panic!("Cannot upgrade a store opened read-only");
+            }
+            info!("Opening store read-only, as a replica of another instance");
+        }
+
         // Init pluggable storage engines
-        Arc::new(Self {
-            config: Config::new(config),
-            metadata_storage: init_metadata_storage(config, upgrade),
-            bundle_storage: init_bundle_storage(config, upgrade),
-        })
+        let (metadata_engine, mut metadata_storage) = match metadata_storage {
+            Some(storage) => ("embedded".to_string(), storage),
+            None => init_metadata_storage(config, upgrade, store_config.read_only),
+        };
+        let (bundle_engine, mut bundle_storage) = match bundle_storage {
+            Some(storage) => ("embedded".to_string(), storage),
+            None => init_bundle_storage(config, upgrade, store_config.read_only),
+        };
+
+        // Encrypt what the engines store, if configured
+        let keyring = encryption::Keyring::new(config);
+        if let Some(keyring) = keyring.clone() {
+            metadata_storage = encryption::MetadataStorage::wrap(metadata_storage, keyring.clone());
+            bundle_storage = encryption::BundleStorage::wrap(bundle_storage, keyring);
+        }
+
+        let mut store = Self {
+            metadata_storage,
+            bundle_storage,
+            config: store_config,
+            pending_inline: Default::default(),
+            consistency_lock: Default::default(),
+            metadata_engine,
+            bundle_engine,
+            residency: None,
+            deadlines: Default::default(),
+            quota: None,
+            encrypted: keyring.is_some(),
+        };
+        if store.config.state_metrics {
+            store.residency = Some(Default::default());
+        }
+
+        if store.config.max_bytes.is_some() || store.config.max_bundles.is_some() {
+            info!(
+                "Bundle storage limited to {} bytes and {} bundles, evicting by {:?}",
+                store
+                    .config
+                    .max_bytes
+                    .map_or("unlimited".to_string(), |max| max.to_string()),
+                store
+                    .config
+                    .max_bundles
+                    .map_or("unlimited".to_string(), |max| max.to_string()),
+                store.config.eviction
+            );
+            store.quota = Some(Arc::new(quota::Quota::new(
+                store.config.max_bytes,
+                store.config.max_bundles,
+                store.config.eviction,
+            )));
+        }
+
+        if store.config.inline_data_threshold != 0 {
+            if store.metadata_storage.supports_inline_data() {
+                info!(
+                    "Bundles of up to {} bytes will be stored inline with their metadata",
+                    store.config.inline_data_threshold
+                );
+            } else {
+                warn!("Metadata storage engine does not support inline bundle data, ignoring 'inline_data_threshold'");
+                store.config.inline_data_threshold = 0;
+            }
+        }
+        Arc::new(store)
+    }
+
+    pub fn read_only(&self) -> bool {
+        self.config.read_only
+    }
+
+    // The metadata storage engine in use, and the version of its schema, if it has one
+    pub fn metadata_engine(&self) -> (&str, Option<String>) {
+        (
+            &self.metadata_engine,
+            self.metadata_storage.schema_version(),
+        )
+    }
+
+    // The bundle storage engine in use, and the version of its layout, if it has one
+    pub fn bundle_engine(&self) -> (&str, Option<String>) {
+        (&self.bundle_engine, self.bundle_storage.schema_version())
+    }
+
+    pub fn metadata_capabilities(&self) -> storage::Capabilities {
+        self.metadata_storage.capabilities()
+    }
+
+    pub fn bundle_capabilities(&self) -> storage::Capabilities {
+        self.bundle_storage.capabilities()
+    }
+
+    // The bytes and bundles stored, if counted against a quota
+    pub fn occupancy(&self) -> Option<(u64, u64)> {
+        self.quota.as_ref().map(|quota| quota.usage())
+    }
+
+    // The number of bundles in each status, if 'state_metrics' is enabled
+    pub fn bundles_in_state(&self) -> Option<HashMap<&'static str, usize>> {
+        self.residency.as_ref().map(|residency| residency.counts())
+    }
+
+    // Classify a bundle by its destination, expedited taking precedence over bulk
+    pub fn classify(&self, bundle: &bpv7::Bundle) -> metadata::Priority {
+        if !self
+            .config
+            .expedited_destinations
+            .find(&bundle.destination)
+            .is_empty()
+        {
+            metadata::Priority::Expedited
+        } else if !self
+            .config
+            .bulk_destinations
+            .find(&bundle.destination)
+            .is_empty()
+        {
+            metadata::Priority::Bulk
+        } else {
+            metadata::Priority::Normal
+        }
     }
 
     #[instrument(skip_all)]
     pub async fn start(
-        &self,
+        self: &Arc<Self>,
         dispatcher: Arc<dispatcher::Dispatcher>,
         task_set: &mut tokio::task::JoinSet<()>,
         cancel_token: tokio_util::sync::CancellationToken,
     ) {
-        info!("Starting store consistency check...");
+        match self.approximate_count().await {
+            Some(count) => info!("Starting store consistency check of about {count} bundles..."),
+            None => info!("Starting store consistency check..."),
+        }
         self.bundle_storage_check(dispatcher.clone(), cancel_token.clone())
             .await;
 
@@ -129,19 +586,43 @@ impl Store {
             self.metadata_storage_check(dispatcher.clone(), cancel_token.clone())
                 .await;
 
+            // Restart bundles that have no data in the bundle storage
+            if !cancel_token.is_cancelled() {
+                self.inline_storage_check(dispatcher.clone(), cancel_token.clone())
+                    .await;
+            }
+
             if !cancel_token.is_cancelled() {
                 info!("Store restarted");
 
+                // Find the bundles that were waiting when we last stopped
+                self.recover_deadlines(cancel_token.clone()).await;
+
                 // Spawn a waiter
                 let wait_sample_interval =
                     time::Duration::seconds(self.config.wait_sample_interval as i64);
-                let metadata_storage = self.metadata_storage.clone();
+                let duplicate_window = match self.config.duplicate_window {
+                    0 => None,
+                    secs => Some(time::Duration::seconds(secs as i64)),
+                };
                 task_set.spawn(Self::check_waiting(
                     wait_sample_interval,
-                    metadata_storage,
-                    dispatcher,
+                    self.config.wait_scan_limit,
+                    duplicate_window,
+                    self.deadlines.clone(),
+                    self.metadata_storage.clone(),
+                    dispatcher.clone(),
                     cancel_token.clone(),
                 ));
+
+                // And the periodic consistency check
+                if self.config.consistency_check_interval != 0 {
+                    task_set.spawn(self.clone().consistency_task(
+                        time::Duration::seconds(self.config.consistency_check_interval as i64),
+                        dispatcher,
+                        cancel_token.clone(),
+                    ));
+                }
             }
         }
     }
@@ -174,17 +655,20 @@ impl Store {
                             } else {
                                 bundles = bundles.saturating_add(1);
 
-                                // The data associated with `bundle` has gone!
-                                dispatcher.report_bundle_deletion(
-                                    &bundle,
-                                    bpv7::StatusReportReasonCode::DepletedStorage,
-                                )
+                                // The data associated with `bundle` has gone!  If the bundle has
+                                // expired, the bundle storage may have collected it by itself
+                                let reason = if bundle.expiry() <= utils::clock::now() {
+                                    bpv7::StatusReportReasonCode::LifetimeExpired
+                                } else {
+                                    bpv7::StatusReportReasonCode::DepletedStorage
+                                };
+                                dispatcher.report_bundle_deletion(&bundle, reason)
                                 .await.trace_expect("Failed to report bundle deletion");
 
-                                // Delete it
+                                // Leave a Tombstone, so we still recognise it if it is received again
                                 metadata_storage
-                                    .remove(&bundle.bundle.id)
-                                    .await.trace_expect("Failed to remove orphan bundle")
+                                    .set_bundle_status(&bundle.bundle.id, &metadata::BundleStatus::Tombstone(utils::clock::now()))
+                                    .await.trace_expect("Failed to tombstone orphan bundle")
                             }
                         }
                     },
@@ -201,11 +685,52 @@ impl Store {
         h.await.trace_expect("Task terminated unexpectedly")
     }
 
+    #[instrument(skip_all)]
+    async fn inline_storage_check(
+        &self,
+        dispatcher: Arc<dispatcher::Dispatcher>,
+        cancel_token: tokio_util::sync::CancellationToken,
+    ) {
+        let (tx, mut rx) = tokio::sync::mpsc::channel::<metadata::Bundle>(16);
+        let quota = self.quota.clone();
+        let h = tokio::spawn(async move {
+            let mut bundles = 0u64;
+            loop {
+                tokio::select! {
+                    bundle = rx.recv() => match bundle {
+                        None => break,
+                        Some(bundle) => {
+                            bundles = bundles.saturating_add(1);
+                            if let Some(quota) = &quota {
+                                quota.add(&bundle.bundle, &bundle.metadata);
+                            }
+                            dispatcher
+                                .check_bundle(bundle, None)
+                                .await
+                                .trace_expect("Failed to restart inline bundle");
+                        }
+                    },
+                    _ = cancel_token.cancelled() => break,
+                }
+            }
+            if bundles != 0 {
+                info!("{bundles} bundles with inline data restarted");
+            }
+        });
+
+        self.metadata_storage
+            .get_inline_bundles(tx)
+            .await
+            .trace_expect("Failed to get inline bundles");
+
+        h.await.trace_expect("Task terminated unexpectedly")
+    }
+
     #[instrument(skip_all)]
     async fn list_stored_bundles(
         &self,
         cancel_token: tokio_util::sync::CancellationToken,
-    ) -> Vec<storage::ListResponse> {
+    ) -> (Vec<storage::ListResponse>, utils::memory::Reservation) {
         /* This is done as a big Vec buffer, as we cannot start processing stored bundles
          * until we have enumerated them all, as the processing can create more bundles
          * which causes all kinds of double-processing issues */
@@ -215,6 +740,7 @@ impl Store {
         let (tx, mut rx) = tokio::sync::mpsc::channel::<storage::ListResponse>(16);
         let h = tokio::spawn(async move {
             let mut results = Vec::new();
+            let mut reservation = utils::memory::reserve(utils::memory::Subsystem::Restart, 0);
 
             // Give some feedback
             let mut bundles = 0u64;
@@ -231,13 +757,14 @@ impl Store {
                         None => break,
                         Some(r) => {
                             bundles = bundles.saturating_add(1);
+                            reservation.grow(std::mem::size_of::<storage::ListResponse>() + r.0.len());
                             results.push(r);
                         },
                     },
                     _ = cancel_token.cancelled() => break
                 }
             }
-            results
+            (results, reservation)
         });
 
         self.bundle_storage
@@ -259,76 +786,128 @@ impl Store {
             .map(Into::into)
             .unwrap_or(1)
             + 1;
+        let mut limit = match self.config.restart_concurrency {
+            0 => concurrency::Limit::adaptive(parallelism, self.config.restart_max_concurrency),
+            n => concurrency::Limit::fixed(n),
+        };
         let mut task_set = tokio::task::JoinSet::new();
-        let semaphore = Arc::new(tokio::sync::Semaphore::new(parallelism));
 
         // Give some feedback
         let timer = tokio::time::sleep(tokio::time::Duration::from_secs(5));
         tokio::pin!(timer);
-        let mut bundles = 0u64;
         let mut orphans = 0u64;
         let mut bad = 0u64;
+        let mut skipped = 0u64;
+
+        // Bundles stored after we start listing are being handled by ingress already,
+        // which happens when a new instance takes over while still serving
+        let started = time::OffsetDateTime::now_utc();
+
+        // For each bundle in the store, most urgent first
+        let (stored_bundles, _reservation) = self.list_stored_bundles(cancel_token.clone()).await;
+        let (urgencies, urgencies_reservation) =
+            urgency::scan(&self.metadata_storage, cancel_token.clone()).await;
+        let mut queue = urgency::Queue::new();
+        for (storage_name, file_time) in stored_bundles
+            .into_iter()
+            .filter(|(_, file_time)| !file_time.is_some_and(|t| t > started))
+        {
+            let urgency = urgencies
+                .get(&storage_name)
+                .copied()
+                .unwrap_or(urgency::Urgency::UNKNOWN);
+            queue.push((storage_name, file_time, 0u32), urgency);
+        }
+        drop(urgencies);
+        drop(urgencies_reservation);
+        let bundles = queue.len() as u64;
+
+        while !cancel_token.is_cancelled() && (!queue.is_empty() || !task_set.is_empty()) {
+            tokio::select! {
+                () = &mut timer => {
+                    info!("Bundle restart in progress, {} bundles processed, {orphans} orphan and {bad} bad bundles found, {skipped} skipped, {} in parallel", bundles - queue.len() as u64, limit.get());
+                    timer.as_mut().reset(tokio::time::Instant::now() + tokio::time::Duration::from_secs(5));
+                },
+                // Throttle the number of tasks, and shed concurrency if we are holding too much data
+                _ = std::future::ready(()), if !queue.is_empty() && task_set.len() < limit.get() && (task_set.is_empty() || !utils::memory::over_limit(utils::memory::Subsystem::Restart)) => {
+                    let (storage_name, file_time, retries) = queue.pop().unwrap();
+                    let metadata_storage = self.metadata_storage.clone();
+                    let bundle_storage = self.bundle_storage.clone();
+                    let dispatcher = dispatcher.clone();
+                    let hash_algorithm = self.config.hash_algorithm;
+                    let quota = self.quota.clone();
+                    let cancel_token = cancel_token.clone();
+
+                    task_set.spawn(async move {
+                        // Give the storage a moment to recover before retrying
+                        if retries != 0 && !utils::cancel::cancellable_sleep(RESTART_RETRY_DELAY * (1u32 << (retries - 1)), &cancel_token).await {
+                            return (Err((storage_name, file_time, retries)), std::time::Duration::ZERO);
+                        }
 
-        // For each bundle in the store
-        for (storage_name, file_time) in self.list_stored_bundles(cancel_token.clone()).await {
-            bundles = bundles.saturating_add(1);
-
-            loop {
-                tokio::select! {
-                    () = &mut timer => {
-                        info!("Bundle restart in progress, {bundles} bundles processed, {orphans} orphan and {bad} bad bundles found");
-                        timer.as_mut().reset(tokio::time::Instant::now() + tokio::time::Duration::from_secs(5));
-                    },
-                    // Throttle the number of tasks
-                    permit = semaphore.clone().acquire_owned() => {
-                        // We have a permit to process a bundle
-                        let permit = permit.trace_expect("Failed to acquire permit");
-                        let metadata_storage = self.metadata_storage.clone();
-                        let bundle_storage = self.bundle_storage.clone();
-                        let dispatcher = dispatcher.clone();
-
-                        task_set.spawn(async move {
-                            let (o,b) = Self::restart_bundle(metadata_storage, bundle_storage, dispatcher, storage_name, file_time).await;
-                            drop(permit);
-                            (o,b)
-                        });
-                        break;
-                    }
-                    Some(r) = task_set.join_next(), if !task_set.is_empty() => {
-                        let (o,b) = r.trace_expect("Task terminated unexpectedly");
-                        orphans = orphans.saturating_add(o);
-                        bad = bad.saturating_add(b);
-                    },
-                    _ = cancel_token.cancelled() => break
+                        let start = std::time::Instant::now();
+                        let r = Self::restart_bundle(metadata_storage, bundle_storage, dispatcher, hash_algorithm, quota, storage_name.clone(), file_time).await;
+                        (r.ok_or((storage_name, file_time, retries)), start.elapsed())
+                    });
                 }
+                Some(r) = task_set.join_next(), if !task_set.is_empty() => {
+                    match r.trace_expect("Task terminated unexpectedly") {
+                        (Ok((o,b)), elapsed) => {
+                            limit.completed(elapsed, std::time::Instant::now());
+                            orphans = orphans.saturating_add(o);
+                            bad = bad.saturating_add(b);
+                        }
+                        (Err((storage_name, file_time, retries)), _) => {
+                            limit.failed(std::time::Instant::now());
+                            if retries >= RESTART_RETRIES {
+                                // Leave the data where it is, for the consistency check or the next restart
+                                error!("Failed to load bundle data {storage_name} after {RESTART_RETRIES} retries, skipping it");
+                                skipped = skipped.saturating_add(1);
+                            } else {
+                                // Back off, and try again later
+                                queue.push((storage_name, file_time, retries + 1), urgency::Urgency::RETRY);
+                            }
+                        }
+                    }
+                },
+                _ = cancel_token.cancelled() => {}
             }
         }
 
         // Wait for all sub-tasks to complete
         while let Some(r) = task_set.join_next().await {
-            let (o, b) = r.trace_expect("Task terminated unexpectedly");
-            orphans = orphans.saturating_add(o);
-            bad = bad.saturating_add(b);
+            if let (Ok((o, b)), _) = r.trace_expect("Task terminated unexpectedly") {
+                orphans = orphans.saturating_add(o);
+                bad = bad.saturating_add(b);
+            }
         }
-        info!("Bundle restart complete, {bundles} bundles processed, {orphans} orphan and {bad} bad bundles found");
+        info!("Bundle restart complete, {bundles} bundles processed, {orphans} orphan and {bad} bad bundles found, {skipped} skipped");
     }
 
-    #[instrument(skip(metadata_storage, bundle_storage, dispatcher))]
+    #[instrument(skip(metadata_storage, bundle_storage, dispatcher, quota))]
     async fn restart_bundle(
         metadata_storage: Arc<dyn storage::MetadataStorage>,
         bundle_storage: Arc<dyn storage::BundleStorage>,
         dispatcher: Arc<dispatcher::Dispatcher>,
+        hash_algorithm: HashAlgorithm,
+        quota: Option<Arc<quota::Quota>>,
         mut storage_name: Arc<str>,
         file_time: Option<time::OffsetDateTime>,
-    ) -> (u64, u64) {
-        let Some(data) = bundle_storage
-            .load(&storage_name)
-            .await
-            .trace_expect(&format!("Failed to load bundle data: {storage_name}"))
-        else {
-            // Data has gone while we were restarting
-            return (0, 0);
+    ) -> Option<(u64, u64)> {
+        let data = match bundle_storage.load(&storage_name).await {
+            Ok(Some(data)) => data,
+            Ok(None) => {
+                // Data has gone while we were restarting
+                return Some((0, 0));
+            }
+            Err(e) => {
+                warn!("Failed to load bundle data: {storage_name}, {e}");
+                return None;
+            }
         };
+        let reservation = utils::memory::reserve(
+            utils::memory::Subsystem::Restart,
+            data.as_ref().as_ref().len(),
+        );
 
         // Parse the bundle
         let (bundle, reason, hash, report_unsupported) =
@@ -336,7 +915,7 @@ impl Store {
                 Ok(bpv7::ValidBundle::Valid(bundle, report_unsupported)) => (
                     bundle,
                     None,
-                    Some(hash(data.as_ref().as_ref())),
+                    Some(hash_algorithm.hash(data.as_ref().as_ref())),
                     report_unsupported,
                 ),
                 Ok(bpv7::ValidBundle::Rewritten(bundle, data, report_unsupported)) => {
@@ -356,14 +935,19 @@ impl Store {
                         ));
 
                     storage_name = new_storage_name;
-                    (bundle, None, Some(hash(&data)), report_unsupported)
+                    (
+                        bundle,
+                        None,
+                        Some(hash_algorithm.hash(&data)),
+                        report_unsupported,
+                    )
                 }
                 Ok(bpv7::ValidBundle::Invalid(bundle, reason, e)) => {
                     warn!("Invalid bundle found: {storage_name}, {e}");
                     (
                         bundle,
                         Some(reason),
-                        Some(hash(data.as_ref().as_ref())),
+                        Some(hash_algorithm.hash(data.as_ref().as_ref())),
                         false,
                     )
                 }
@@ -378,10 +962,11 @@ impl Store {
                         .trace_expect(&format!(
                             "Failed to remove malformed bundle: {storage_name}"
                         ));
-                    return (0, 1);
+                    return Some((0, 1));
                 }
             };
         drop(data);
+        drop(reservation);
 
         // Check if the metadata_storage knows about this bundle
         let metadata = metadata_storage
@@ -393,7 +978,11 @@ impl Store {
                 // Tombstone, ignore
                 warn!("Tombstone bundle data found: {storage_name}");
                 true
-            } else if metadata.storage_name.as_ref() == Some(&storage_name) && metadata.hash == hash
+            } else if metadata.storage_name.as_ref() == Some(&storage_name)
+                && (metadata.hash == hash
+                    // Hashed with a previous algorithm, awaiting a rehash
+                    || metadata.hash.as_deref().and_then(HashAlgorithm::from_hash)
+                        != Some(hash_algorithm))
             {
                 false
             } else {
@@ -409,15 +998,18 @@ impl Store {
                     .trace_expect(&format!(
                         "Failed to remove duplicate bundle: {storage_name}"
                     ));
-                return (0, 1);
+                return Some((0, 1));
             }
 
+            if let Some(quota) = &quota {
+                quota.add(&bundle, &metadata);
+            }
             dispatcher
                 .check_bundle(metadata::Bundle { metadata, bundle }, reason)
                 .await
                 .trace_expect(&format!("Bundle validation failed for: {storage_name}"));
 
-            return (0, 0);
+            return Some((0, 0));
         }
 
         let mut bundle = metadata::Bundle {
@@ -432,8 +1024,7 @@ impl Store {
 
         // If the bundle isn't valid, it must always be a Tombstone
         if reason.is_some() {
-            bundle.metadata.status =
-                metadata::BundleStatus::Tombstone(time::OffsetDateTime::now_utc())
+            bundle.metadata.status = metadata::BundleStatus::Tombstone(utils::clock::now())
         }
 
         // Send to the dispatcher ingress as it is effectively a new bundle
@@ -442,65 +1033,193 @@ impl Store {
             .await
             .trace_expect("Failed to restart bundle");
 
-        (1, 0)
+        Some((1, 0))
+    }
+
+    // Schedule every waiting bundle in the metadata storage, the only time it is scanned for them
+    #[instrument(skip_all)]
+    async fn recover_deadlines(&self, cancel_token: tokio_util::sync::CancellationToken) {
+        let (tx, mut rx) = tokio::sync::mpsc::channel::<metadata::Bundle>(16);
+        let deadlines = self.deadlines.clone();
+        let h = tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    bundle = rx.recv() => match bundle {
+                        None => break,
+                        Some(bundle) => deadlines.schedule(&bundle.bundle, &bundle.metadata),
+                    },
+                    _ = cancel_token.cancelled() => break,
+                }
+            }
+        });
+
+        self.metadata_storage
+            .get_waiting_bundles(
+                time::macros::datetime!(9999-12-31 23:59:59 UTC),
+                storage::ScanBudget::default(),
+                tx,
+            )
+            .await
+            .trace_expect("get_waiting_bundles failed");
+
+        h.await.trace_expect("Task terminated unexpectedly");
+        info!("{} waiting bundles scheduled", self.deadlines.len());
     }
 
     #[instrument(skip_all)]
     async fn check_waiting(
         wait_sample_interval: time::Duration,
+        wait_scan_limit: Option<usize>,
+        duplicate_window: Option<time::Duration>,
+        deadlines: Arc<wheel::Deadlines>,
         metadata_storage: Arc<dyn storage::MetadataStorage>,
         dispatcher: Arc<dispatcher::Dispatcher>,
         cancel_token: tokio_util::sync::CancellationToken,
     ) {
-        while utils::cancel::cancellable_sleep(wait_sample_interval, &cancel_token).await {
-            // Get all bundles that are ready before now() + self.config.wait_sample_interval
-            let limit = time::OffsetDateTime::now_utc() + wait_sample_interval;
-
-            let (tx, mut rx) = tokio::sync::mpsc::channel::<metadata::Bundle>(16);
-            let dispatcher = dispatcher.clone();
-            let cancel_token = cancel_token.clone();
-
-            let h = tokio::spawn(async move {
-                loop {
-                    tokio::select! {
-                        bundle = rx.recv() => match bundle {
-                            None => break,
-                            Some(bundle) => {
-                                // Double check returned bundles
-                                match bundle.metadata.status {
-                                    metadata::BundleStatus::ForwardAckPending(_, until)
-                                    | metadata::BundleStatus::Waiting(until)
-                                        if until <= limit =>
-                                    {
-                                        dispatcher.dispatch_bundle(bundle).await.trace_expect("Failed to dispatch bundle");
-                                    }
-                                    _ => {}
-                                }
-                            },
-                        },
-                        _ = cancel_token.cancelled() => break,
+        let mut next_purge = utils::clock::now() + wait_sample_interval;
+        while utils::clock::sleep(time::Duration::SECOND, &cancel_token).await {
+            let now = utils::clock::now();
+            for bundle_id in deadlines.due(now, wait_scan_limit) {
+                let Some(bundle) = metadata_storage
+                    .load(&bundle_id)
+                    .await
+                    .trace_expect("Failed to load bundle metadata")
+                else {
+                    continue;
+                };
+
+                // The bundle may have moved on since it was scheduled
+                match bundle.metadata.status {
+                    metadata::BundleStatus::ForwardAckPending(_, until)
+                    | metadata::BundleStatus::Waiting(until)
+                        if until.min(bundle.expiry()) <= now =>
+                    {
+                        dispatcher
+                            .dispatch_bundle(bundle)
+                            .await
+                            .trace_expect("Failed to dispatch bundle");
                     }
+                    _ => {}
                 }
-            });
-
-            metadata_storage
-                .get_waiting_bundles(limit, tx)
-                .await
-                .trace_expect("get_waiting_bundles failed");
+            }
 
-            h.await.trace_expect("polling task failed")
+            // Forget Tombstones that have fallen out of the duplicate suppression window
+            if now < next_purge {
+                continue;
+            }
+            next_purge = now + wait_sample_interval;
+            if let Some(duplicate_window) = duplicate_window {
+                let purged = metadata_storage
+                    .purge_tombstones(now - duplicate_window)
+                    .await
+                    .trace_expect("purge_tombstones failed");
+                if purged != 0 {
+                    trace!("Purged {purged} expired tombstones");
+                }
+            }
         }
     }
 
-    #[inline]
     pub async fn load_data(&self, storage_name: &str) -> Result<Option<storage::DataRef>, Error> {
-        self.bundle_storage.load(storage_name).await
+        if !is_inline(storage_name) {
+            return self.bundle_storage.load(storage_name).await;
+        }
+
+        // The data might not have been written yet
+        let pending = self
+            .pending_inline
+            .lock()
+            .trace_expect("Failed to lock mutex")
+            .get(storage_name)
+            .cloned();
+        if let Some(data) = pending {
+            return Ok(Some(Arc::new(data) as storage::DataRef));
+        }
+        self.metadata_storage.load_inline(storage_name).await
+    }
+
+    // Check bundle data against the hash in its metadata, if configured.  Encrypted data in
+    // the bundle storage is only bound to its bundle through the hash, so is always checked
+    pub fn verify_data(&self, metadata: &metadata::Metadata, data: &[u8]) -> bool {
+        !(self.config.verify_on_load || self.encrypted)
+            || metadata
+                .hash
+                .as_deref()
+                .is_none_or(|hash| hash_matches(hash, data))
+    }
+
+    // Keep a copy of bad bundle data for later inspection, if configured
+    pub async fn quarantine_data(&self, storage_name: &str, data: &[u8]) {
+        let Some(quarantine_dir) = &self.config.quarantine_dir else {
+            return;
+        };
+
+        // Storage names may contain path separators
+        let path = quarantine_dir.join(storage_name.replace(['/', '\\', ':'], "_"));
+        match tokio::fs::write(&path, data).await {
+            Ok(()) => info!(
+                "Bundle data {storage_name} quarantined as {}",
+                path.display()
+            ),
+            Err(e) => warn!(
+                "Failed to quarantine bundle data {storage_name} as {}: {e}",
+                path.display()
+            ),
+        }
+    }
+
+    pub async fn load_data_range(
+        &self,
+        storage_name: &str,
+        offset: u64,
+        len: u64,
+    ) -> Result<Option<storage::DataRef>, Error> {
+        if !is_inline(storage_name) {
+            return self
+                .bundle_storage
+                .load_range(storage_name, offset, len)
+                .await;
+        }
+
+        // Inline data is small, so just take a copy of the range
+        let Some(data) = self.load_data(storage_name).await? else {
+            return Ok(None);
+        };
+        let range = usize::try_from(offset)?..usize::try_from(offset.saturating_add(len))?;
+        let Some(data) = (*data).as_ref().get(range) else {
+            return Err("Requested range is beyond the end of the bundle data".into());
+        };
+        Ok(Some(Arc::new(data.to_vec()) as storage::DataRef))
     }
 
-    #[inline]
     pub async fn store_data(&self, data: &[u8]) -> Result<(Arc<str>, Arc<[u8]>), Error> {
         // Calculate hash
-        let hash = hash(data);
+        let hash = self.config.hash_algorithm.hash(data);
+
+        if data.len() <= self.config.inline_data_threshold {
+            // Defer the write until the metadata is stored.  The name is derived from the
+            // hash, unless that would give away the hash of encrypted data
+            let random;
+            let name_source = if self.encrypted {
+                random = rand::random::<[u8; 32]>();
+                &random[..]
+            } else {
+                &hash[..]
+            };
+            let storage_name: Arc<str> = name_source
+                .iter()
+                .fold(INLINE_PREFIX.to_string(), |mut s, b| {
+                    s.push_str(&format!("{b:02x}"));
+                    s
+                })
+                .into();
+
+            self.pending_inline
+                .lock()
+                .trace_expect("Failed to lock mutex")
+                .insert(storage_name.clone(), data.into());
+            return Ok((storage_name, hash));
+        }
 
         // Write to bundle storage
         self.bundle_storage
@@ -509,18 +1228,126 @@ impl Store {
             .map(|storage_name| (storage_name, hash))
     }
 
-    #[inline]
+    // Whether a bundle that has already reached `len` bytes should be streamed into the
+    // bundle storage, rather than buffered in memory
+    pub fn should_stream(&self, len: usize) -> bool {
+        self.bundle_storage.capabilities().streaming
+            && len
+                > self
+                    .config
+                    .inline_data_threshold
+                    .max(storage::STREAM_CHUNK_SIZE)
+    }
+
+    // Write bundle data to the bundle storage in chunks as they arrive, hashing as we go
+    pub async fn store_data_stream(
+        &self,
+        mut rx: tokio::sync::mpsc::Receiver<Vec<u8>>,
+    ) -> Result<(Arc<str>, Arc<[u8]>), Error> {
+        let (tx, storage_rx) = tokio::sync::mpsc::channel(16);
+        let hash = async move {
+            let mut hasher = self.config.hash_algorithm.hasher();
+            while let Some(chunk) = rx.recv().await {
+                hasher.update(&chunk);
+                if tx.send(chunk).await.is_err() {
+                    // The storage engine has given up, and will say why
+                    break;
+                }
+            }
+            hasher.finish()
+        };
+
+        let (storage_name, hash) = tokio::join!(self.bundle_storage.store_stream(storage_rx), hash);
+        storage_name.map(|storage_name| (storage_name, hash))
+    }
+
+    // Hash stored bundle data with the configured algorithm, streaming it from the bundle
+    // storage rather than loading it whole.  None if the data has gone
+    pub async fn hash_data(&self, storage_name: &str) -> Result<Option<Arc<[u8]>>, Error> {
+        if is_inline(storage_name) {
+            return Ok(self
+                .load_data(storage_name)
+                .await?
+                .map(|data| self.config.hash_algorithm.hash((*data).as_ref())));
+        }
+
+        let (tx, mut rx) = tokio::sync::mpsc::channel::<Vec<u8>>(16);
+        let hash = async move {
+            let mut hasher = self.config.hash_algorithm.hasher();
+            while let Some(chunk) = rx.recv().await {
+                hasher.update(&chunk);
+            }
+            hasher.finish()
+        };
+        let (found, hash) = tokio::join!(self.bundle_storage.load_stream(storage_name, tx), hash);
+        Ok(found?.then_some(hash))
+    }
+
     pub async fn store_metadata(
         &self,
         metadata: &metadata::Metadata,
         bundle: &bpv7::Bundle,
     ) -> Result<bool, Error> {
+        // Pick up any deferred data write, leaving it visible to load_data() until written
+        let inline_name = metadata
+            .storage_name
+            .as_ref()
+            .filter(|storage_name| is_inline(storage_name));
+        let data = inline_name.and_then(|storage_name| {
+            self.pending_inline
+                .lock()
+                .trace_expect("Failed to lock mutex")
+                .get(storage_name)
+                .cloned()
+        });
+
         // Write to metadata store
-        Ok(self
-            .metadata_storage
-            .store(metadata, bundle)
-            .await
-            .trace_expect("Failed to store metadata"))
+        let stored = if let Some(data) = data {
+            self.metadata_storage
+                .store_inline(metadata, bundle, &data)
+                .await
+        } else {
+            self.metadata_storage.store(metadata, bundle).await
+        };
+
+        // Whether written, a duplicate, or failed, the deferred data is no longer pending
+        if let Some(storage_name) = inline_name {
+            self.pending_inline
+                .lock()
+                .trace_expect("Failed to lock mutex")
+                .remove(storage_name);
+        }
+        let stored = stored.trace_expect("Failed to store metadata");
+
+        if stored {
+            self.hint_expiry(metadata, bundle).await;
+            if let Some(residency) = &self.residency {
+                residency.enter(&bundle.id, &metadata.status);
+            }
+            self.deadlines.schedule(bundle, metadata);
+            if let Some(quota) = &self.quota {
+                quota.add(bundle, metadata);
+            }
+        }
+        Ok(stored)
+    }
+
+    // Let the bundle storage collect the data of the bundle by itself once it has expired
+    async fn hint_expiry(&self, metadata: &metadata::Metadata, bundle: &bpv7::Bundle) {
+        if !self.bundle_storage.supports_expiry_hints() {
+            return;
+        }
+        let Some(storage_name) = metadata
+            .storage_name
+            .as_ref()
+            .filter(|storage_name| !is_inline(storage_name))
+        else {
+            return;
+        };
+        let expiry = metadata::Bundle::expiry_of(bundle, metadata.received_at);
+        if let Err(e) = self.bundle_storage.hint_expiry(storage_name, expiry).await {
+            warn!("Failed to hint the expiry of bundle data {storage_name}: {e}");
+        }
     }
 
     #[inline]
@@ -531,6 +1358,11 @@ impl Store {
         self.metadata_storage.load(bundle_id).await
     }
 
+    #[inline]
+    pub async fn get_stored_bundles(&self, tx: storage::Sender) -> Result<(), Error> {
+        self.metadata_storage.get_stored_bundles(tx).await
+    }
+
     #[instrument(skip(self, data))]
     pub async fn store(
         &self,
@@ -538,6 +1370,7 @@ impl Store {
         data: &[u8],
         status: metadata::BundleStatus,
         received_at: Option<time::OffsetDateTime>,
+        return_path: Option<bpv7::Eid>,
     ) -> Result<Option<metadata::Metadata>, Error> {
         // Write to bundle storage
         let (storage_name, hash) = self.store_data(data).await?;
@@ -548,6 +1381,8 @@ impl Store {
             storage_name: Some(storage_name.clone()),
             hash: Some(hash),
             received_at,
+            priority: self.classify(bundle),
+            return_path,
         };
 
         // Write to metadata store
@@ -555,13 +1390,13 @@ impl Store {
             Ok(true) => Ok(Some(metadata)),
             Ok(false) => {
                 // We have a duplicate, remove the duplicate from the bundle store
-                _ = self.bundle_storage.remove(&storage_name).await;
+                _ = self.delete_data(&storage_name).await;
                 Ok(None)
             }
             Err(e) => {
                 // This is just bad, we can't really claim to have stored the bundle,
                 // so just cleanup and get out
-                _ = self.bundle_storage.remove(&storage_name).await;
+                _ = self.delete_data(&storage_name).await;
                 Err(e)
             }
         }
@@ -571,11 +1406,107 @@ impl Store {
     pub async fn poll_for_collection(
         &self,
         destination: bpv7::Eid,
+        budget: storage::ScanBudget,
         tx: tokio::sync::mpsc::Sender<metadata::Bundle>,
     ) -> Result<(), Error> {
         self.metadata_storage
-            .poll_for_collection(destination, tx)
+            .poll_for_collection(destination, budget, tx)
+            .await
+    }
+
+    #[inline]
+    pub fn supports_multicast_delivery(&self) -> bool {
+        self.metadata_storage.supports_multicast_delivery()
+    }
+
+    #[inline]
+    pub async fn confirm_delivery(
+        &self,
+        bundle_id: &bpv7::BundleId,
+        registration: &str,
+    ) -> Result<bool, Error> {
+        self.metadata_storage
+            .confirm_delivery(bundle_id, registration)
+            .await
+    }
+
+    #[inline]
+    pub async fn get_deliveries(&self, bundle_id: &bpv7::BundleId) -> Result<Vec<String>, Error> {
+        self.metadata_storage.get_deliveries(bundle_id).await
+    }
+
+    // The number of stored bundles, if the engine can tell without walking them
+    async fn approximate_count(&self) -> Option<u64> {
+        if !self.metadata_storage.capabilities().approximate_counts {
+            return None;
+        }
+        self.metadata_storage
+            .approximate_count()
             .await
+            .inspect_err(|e| warn!("Failed to count stored bundles: {e}"))
+            .ok()
+            .flatten()
+    }
+
+    // Bundles waiting to be forwarded to destinations matching `pattern`
+    #[instrument(skip(self, tx))]
+    pub async fn get_waiting_bundles_matching(
+        &self,
+        pattern: &bpv7::EidPattern,
+        tx: storage::Sender,
+    ) -> Result<(), Error> {
+        if self.metadata_storage.capabilities().destination_queries {
+            // Use the destination index to find the affected destinations
+            let destinations = self
+                .metadata_storage
+                .get_waiting_destinations()
+                .await?
+                .into_iter()
+                .filter(|destination| pattern.is_match(destination));
+            for destination in destinations {
+                if tx.is_closed() {
+                    break;
+                }
+                self.metadata_storage
+                    .get_waiting_bundles_for(&destination, tx.clone())
+                    .await?;
+            }
+            return Ok(());
+        }
+
+        // Walk every bundle once, rather than once for each destination
+        let (inner_tx, mut rx) = tokio::sync::mpsc::channel::<metadata::Bundle>(16);
+        let metadata_storage = self.metadata_storage.clone();
+        let h = tokio::spawn(async move { metadata_storage.get_stored_bundles(inner_tx).await });
+        while let Some(bundle) = rx.recv().await {
+            if matches!(
+                bundle.metadata.status,
+                metadata::BundleStatus::Waiting(_) | metadata::BundleStatus::ForwardPending
+            ) && pattern.is_match(&bundle.bundle.destination)
+                && tx.send(bundle).await.is_err()
+            {
+                break;
+            }
+        }
+        drop(rx);
+        h.await.trace_expect("Task terminated unexpectedly")
+    }
+
+    #[inline]
+    pub async fn get_fragments(
+        &self,
+        bundle_id: &bpv7::BundleId,
+        tx: tokio::sync::mpsc::Sender<metadata::Bundle>,
+    ) -> Result<(), Error> {
+        self.metadata_storage.get_fragments(bundle_id, tx).await
+    }
+
+    #[inline]
+    pub async fn get_reassembly_pending(
+        &self,
+        tx: tokio::sync::mpsc::Sender<metadata::Bundle>,
+    ) -> Result<(), Error> {
+        self.metadata_storage.get_reassembly_pending(tx).await
     }
 
     #[inline]
@@ -596,20 +1527,194 @@ impl Store {
             Ok(())
         } else {
             bundle.metadata.status = status;
+            self.enter_status(bundle);
             self.metadata_storage
                 .set_bundle_status(&bundle.bundle.id, &bundle.metadata.status)
                 .await
         }
     }
 
-    #[inline]
+    // Set the status of a number of bundles, in a single batch
+    #[instrument(skip_all)]
+    pub async fn set_statuses(
+        &self,
+        bundles: &mut [metadata::Bundle],
+        status: metadata::BundleStatus,
+    ) -> Result<(), Error> {
+        let mut updates = Vec::new();
+        for bundle in bundles
+            .iter_mut()
+            .filter(|bundle| bundle.metadata.status != status)
+        {
+            bundle.metadata.status = status.clone();
+            self.enter_status(bundle);
+            updates.push((bundle.bundle.id.clone(), status.clone()));
+        }
+        if updates.is_empty() {
+            return Ok(());
+        }
+        self.metadata_storage.set_bundle_statuses(&updates).await
+    }
+
+    // As set_status(), but only if the stored status is still the one `bundle` was loaded with.
+    // Returns false if another task has moved the bundle on, and should dispatch it instead
+    #[instrument(skip(self))]
+    pub async fn claim_status(
+        &self,
+        bundle: &mut metadata::Bundle,
+        status: metadata::BundleStatus,
+    ) -> Result<bool, Error> {
+        let mut bundles = vec![bundle.clone()];
+        self.claim_statuses(&mut bundles, status).await?;
+        let Some(claimed) = bundles.pop() else {
+            return Ok(false);
+        };
+        *bundle = claimed;
+        Ok(true)
+    }
+
+    // As set_statuses(), but drops from `bundles` any whose stored status has moved on
+    #[instrument(skip_all)]
+    pub async fn claim_statuses(
+        &self,
+        bundles: &mut Vec<metadata::Bundle>,
+        status: metadata::BundleStatus,
+    ) -> Result<(), Error> {
+        let claims = bundles
+            .iter()
+            .map(|bundle| (bundle.bundle.id.clone(), bundle.metadata.status.clone()))
+            .collect::<Vec<_>>();
+        let mut claimed = self
+            .metadata_storage
+            .claim_bundle_statuses(&claims, &status)
+            .await?
+            .into_iter();
+        bundles.retain(|_| claimed.next().unwrap_or(false));
+
+        // The store is updated, so only our own view of the bundles is left to catch up
+        for bundle in bundles
+            .iter_mut()
+            .filter(|bundle| bundle.metadata.status != status)
+        {
+            bundle.metadata.status = status.clone();
+            self.enter_status(bundle);
+        }
+        Ok(())
+    }
+
+    // Bring our own bookkeeping into line with the new status of `bundle`
+    fn enter_status(&self, bundle: &metadata::Bundle) {
+        if let Some(residency) = &self.residency {
+            residency.enter(&bundle.bundle.id, &bundle.metadata.status);
+        }
+        self.deadlines.schedule(&bundle.bundle, &bundle.metadata);
+        self.leave_quota(&bundle.bundle.id, &bundle.metadata.status);
+    }
+
+    // Tombstones no longer count against the quota
+    fn leave_quota(&self, bundle_id: &bpv7::BundleId, status: &metadata::BundleStatus) {
+        if let (Some(quota), metadata::BundleStatus::Tombstone(_)) = (&self.quota, status) {
+            quota.remove(bundle_id);
+        }
+    }
+
+    // The stored bundles to evict to make room for `bundle`, if the quota is exhausted, and
+    // the room reserved for it until it is stored
+    pub fn make_room(
+        &self,
+        bundle: &bpv7::Bundle,
+        received_at: Option<time::OffsetDateTime>,
+    ) -> Result<(Vec<bpv7::BundleId>, Reservation), quota::Refusal> {
+        let Some(quota) = &self.quota else {
+            return Ok((Vec::new(), Reservation::default()));
+        };
+        quota.make_room(
+            &bundle.id,
+            &quota::Entry::new(
+                bundle,
+                &metadata::Metadata {
+                    received_at,
+                    priority: self.classify(bundle),
+                    ..Default::default()
+                },
+            ),
+        )
+    }
+
     pub async fn delete_data(&self, storage_name: &str) -> Result<(), Error> {
+        if is_inline(storage_name) {
+            // Inline data is removed along with the metadata
+            self.pending_inline
+                .lock()
+                .trace_expect("Failed to lock mutex")
+                .remove(storage_name);
+            return Ok(());
+        }
+
         // Delete the bundle from the bundle store
         self.bundle_storage.remove(storage_name).await
     }
 
+    #[inline]
+    pub fn supports_sparse_reassembly(&self) -> bool {
+        self.bundle_storage.supports_sparse_reassembly()
+    }
+
+    #[inline]
+    pub async fn write_partial(
+        &self,
+        reassembly_id: &str,
+        offset: u64,
+        data: &[u8],
+    ) -> Result<(), Error> {
+        self.bundle_storage
+            .write_partial(reassembly_id, offset, data)
+            .await
+    }
+
+    #[inline]
+    pub async fn load_partial(
+        &self,
+        reassembly_id: &str,
+        offset: u64,
+        len: u64,
+    ) -> Result<Option<storage::DataRef>, Error> {
+        self.bundle_storage
+            .load_partial(reassembly_id, offset, len)
+            .await
+    }
+
+    #[inline]
+    pub async fn partial_ranges(&self, reassembly_id: &str) -> Result<Vec<(u64, u64)>, Error> {
+        self.bundle_storage.partial_ranges(reassembly_id).await
+    }
+
+    #[inline]
+    pub async fn release_partial(
+        &self,
+        reassembly_id: &str,
+        offset: u64,
+        len: u64,
+    ) -> Result<(), Error> {
+        self.bundle_storage
+            .release_partial(reassembly_id, offset, len)
+            .await
+    }
+
+    #[inline]
+    pub async fn remove_partial(&self, reassembly_id: &str) -> Result<(), Error> {
+        self.bundle_storage.remove_partial(reassembly_id).await
+    }
+
     #[inline]
     pub async fn delete_metadata(&self, bundle_id: &bpv7::BundleId) -> Result<(), Error> {
+        if let Some(residency) = &self.residency {
+            residency.leave(bundle_id);
+        }
+        if let Some(quota) = &self.quota {
+            quota.remove(bundle_id);
+        }
+
         // Delete the bundle from the bundle store
         self.metadata_storage.remove(bundle_id).await
     }
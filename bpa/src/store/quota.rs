@@ -0,0 +1,432 @@
+use super::*;
+
+/* Bundle storage is bounded by the 'quota' settings, a maximum number of bytes of bundle
+ * data and a maximum number of bundles.  The quota keeps an index of the size, priority,
+ * expiry and reception time of every stored bundle that is not a Tombstone, from when its
+ * metadata is stored or it is found by the restart scan, until it is dropped.  When a new
+ * bundle would not fit, bundles of no higher priority are chosen for eviction, bulk traffic
+ * first, and then by the eviction policy.  If no choice of bundles makes room, the new
+ * bundle is refused.  The room made is reserved for the new bundle at once, so bundles
+ * admitted concurrently cannot claim it too, and released again if the bundle is not
+ * stored in the end.  The limits are only approximate while the store restarts */
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Eviction {
+    // Refuse new bundles, evicting nothing
+    None,
+
+    // Evict the bundles with the least lifetime remaining first
+    ShortestLifetime,
+
+    // Evict the bundles received longest ago first
+    OldestReceived,
+}
+
+impl Eviction {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "none" => Some(Self::None),
+            "shortest_lifetime" => Some(Self::ShortestLifetime),
+            "oldest_received" => Some(Self::OldestReceived),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Entry {
+    size: u64,
+    priority: metadata::Priority,
+    expiry: time::OffsetDateTime,
+    received_at: time::OffsetDateTime,
+    // Room made for a bundle that has not been stored yet
+    reserved: bool,
+}
+
+impl Entry {
+    pub fn new(bundle: &bpv7::Bundle, metadata: &metadata::Metadata) -> Self {
+        Self {
+            size: bundle
+                .blocks
+                .values()
+                .map(|block| block.data_start + block.data_len)
+                .max()
+                .unwrap_or(0) as u64,
+            priority: metadata.priority,
+            expiry: metadata::Bundle::expiry_of(bundle, metadata.received_at),
+            received_at: metadata
+                .received_at
+                .unwrap_or_else(|| metadata::Bundle::creation_time_of(bundle, None)),
+            reserved: false,
+        }
+    }
+
+    // Whether this bundle may be evicted to make room for `incoming`
+    fn yields_to(&self, incoming: &Entry, eviction: Eviction) -> bool {
+        match eviction {
+            Eviction::None => false,
+            _ if self.priority != incoming.priority => self.priority < incoming.priority,
+            // Don't evict bundles that will outlive the new one
+            Eviction::ShortestLifetime => self.expiry <= incoming.expiry,
+            Eviction::OldestReceived => true,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Usage {
+    entries: HashMap<bpv7::BundleId, Entry>,
+    bytes: u64,
+}
+
+impl Usage {
+    fn insert(&mut self, bundle_id: bpv7::BundleId, entry: Entry) {
+        self.bytes = self.bytes.saturating_add(entry.size);
+        if let Some(previous) = self.entries.insert(bundle_id, entry) {
+            self.bytes = self.bytes.saturating_sub(previous.size);
+        }
+    }
+
+    fn remove(&mut self, bundle_id: &bpv7::BundleId) {
+        if let Some(entry) = self.entries.remove(bundle_id) {
+            self.bytes = self.bytes.saturating_sub(entry.size);
+        }
+    }
+
+    fn record(&self) {
+        metrics::gauge!("store_quota_bytes").set(self.bytes as f64);
+        metrics::gauge!("store_quota_bundles").set(self.entries.len() as f64);
+    }
+}
+
+// Room reserved for a bundle being admitted, released when dropped unless the bundle has
+// been stored by then
+#[derive(Default)]
+pub struct Reservation(Option<(Arc<Quota>, bpv7::BundleId)>);
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        if let Some((quota, bundle_id)) = &self.0 {
+            quota.release(bundle_id);
+        }
+    }
+}
+
+// Why a bundle cannot be admitted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Refusal {
+    Bytes,
+    Bundles,
+}
+
+impl std::fmt::Display for Refusal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Refusal::Bytes => write!(f, "byte quota exhausted"),
+            Refusal::Bundles => write!(f, "bundle quota exhausted"),
+        }
+    }
+}
+
+pub(super) struct Quota {
+    max_bytes: Option<u64>,
+    max_bundles: Option<u64>,
+    eviction: Eviction,
+    usage: std::sync::Mutex<Usage>,
+}
+
+impl Quota {
+    pub fn new(max_bytes: Option<u64>, max_bundles: Option<u64>, eviction: Eviction) -> Self {
+        Self {
+            max_bytes,
+            max_bundles,
+            eviction,
+            usage: Default::default(),
+        }
+    }
+
+    pub fn add(&self, bundle: &bpv7::Bundle, metadata: &metadata::Metadata) {
+        if let metadata::BundleStatus::Tombstone(_) = metadata.status {
+            return;
+        }
+        let mut usage = self.usage.lock().trace_expect("Failed to lock mutex");
+        usage.insert(bundle.id.clone(), Entry::new(bundle, metadata));
+        usage.record();
+    }
+
+    // The bytes and bundles counted against the quota
+    pub fn usage(&self) -> (u64, u64) {
+        let usage = self.usage.lock().trace_expect("Failed to lock mutex");
+        (usage.bytes, usage.entries.len() as u64)
+    }
+
+    pub fn remove(&self, bundle_id: &bpv7::BundleId) {
+        let mut usage = self.usage.lock().trace_expect("Failed to lock mutex");
+        usage.remove(bundle_id);
+        usage.record();
+    }
+
+    // The bundles to evict to make room for `incoming`, which are forgotten by the quota
+    // at once so they are not chosen twice, and the room reserved for `incoming` in their place
+    pub fn make_room(
+        self: &Arc<Self>,
+        bundle_id: &bpv7::BundleId,
+        incoming: &Entry,
+    ) -> Result<(Vec<bpv7::BundleId>, Reservation), Refusal> {
+        let mut usage = self.usage.lock().trace_expect("Failed to lock mutex");
+        let evict = select(
+            &usage,
+            self.max_bytes,
+            self.max_bundles,
+            self.eviction,
+            incoming,
+        )?;
+        for bundle_id in &evict {
+            usage.remove(bundle_id);
+        }
+
+        // A bundle already counted is a duplicate, and will not be stored again
+        let reservation = if usage.entries.contains_key(bundle_id) {
+            Reservation::default()
+        } else {
+            usage.insert(
+                bundle_id.clone(),
+                Entry {
+                    reserved: true,
+                    ..incoming.clone()
+                },
+            );
+            Reservation(Some((self.clone(), bundle_id.clone())))
+        };
+        usage.record();
+        Ok((evict, reservation))
+    }
+
+    // Release the room reserved for a bundle, unless it has been stored since
+    fn release(&self, bundle_id: &bpv7::BundleId) {
+        let mut usage = self.usage.lock().trace_expect("Failed to lock mutex");
+        if usage
+            .entries
+            .get(bundle_id)
+            .is_some_and(|entry| entry.reserved)
+        {
+            usage.remove(bundle_id);
+            usage.record();
+        }
+    }
+}
+
+fn select(
+    usage: &Usage,
+    max_bytes: Option<u64>,
+    max_bundles: Option<u64>,
+    eviction: Eviction,
+    incoming: &Entry,
+) -> Result<Vec<bpv7::BundleId>, Refusal> {
+    let mut excess_bytes = max_bytes.map_or(0, |max| {
+        usage
+            .bytes
+            .saturating_add(incoming.size)
+            .saturating_sub(max)
+    });
+    let mut excess_bundles = max_bundles.map_or(0, |max| {
+        (usage.entries.len() as u64)
+            .saturating_add(1)
+            .saturating_sub(max)
+    });
+    if excess_bytes == 0 && excess_bundles == 0 {
+        return Ok(Vec::new());
+    }
+
+    let mut candidates = usage
+        .entries
+        .iter()
+        .filter(|(_, entry)| entry.yields_to(incoming, eviction))
+        .collect::<Vec<_>>();
+    candidates.sort_unstable_by_key(|(_, entry)| {
+        (
+            entry.priority,
+            match eviction {
+                Eviction::ShortestLifetime => entry.expiry,
+                _ => entry.received_at,
+            },
+        )
+    });
+
+    let mut evict = Vec::new();
+    for (bundle_id, entry) in candidates {
+        if excess_bytes == 0 && excess_bundles == 0 {
+            break;
+        }
+        excess_bytes = excess_bytes.saturating_sub(entry.size);
+        excess_bundles = excess_bundles.saturating_sub(1);
+        evict.push(bundle_id.clone());
+    }
+
+    if excess_bytes != 0 {
+        Err(Refusal::Bytes)
+    } else if excess_bundles != 0 {
+        Err(Refusal::Bundles)
+    } else {
+        Ok(evict)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bundle_id(n: u64) -> bpv7::BundleId {
+        bpv7::BundleId {
+            source: "ipn:1.1".parse().unwrap(),
+            timestamp: bpv7::CreationTimestamp {
+                creation_time: Some(bpv7::DtnTime::new(1000)),
+                sequence_number: n,
+            },
+            ..Default::default()
+        }
+    }
+
+    fn entry(size: u64, priority: metadata::Priority, expiry: i64, received_at: i64) -> Entry {
+        Entry {
+            size,
+            priority,
+            expiry: time::OffsetDateTime::from_unix_timestamp(expiry).unwrap(),
+            received_at: time::OffsetDateTime::from_unix_timestamp(received_at).unwrap(),
+            reserved: false,
+        }
+    }
+
+    impl Quota {
+        fn add_entry(&self, bundle_id: bpv7::BundleId, entry: Entry) {
+            self.usage
+                .lock()
+                .trace_expect("Failed to lock mutex")
+                .insert(bundle_id, entry);
+        }
+    }
+
+    fn usage() -> Usage {
+        let mut usage = Usage::default();
+        usage.insert(
+            bundle_id(1),
+            entry(100, metadata::Priority::Normal, 300, 10),
+        );
+        usage.insert(
+            bundle_id(2),
+            entry(100, metadata::Priority::Normal, 200, 20),
+        );
+        usage.insert(bundle_id(3), entry(100, metadata::Priority::Bulk, 400, 30));
+        usage
+    }
+
+    #[test]
+    fn within_quota() {
+        let incoming = entry(100, metadata::Priority::Normal, 500, 40);
+        assert_eq!(
+            select(&usage(), Some(400), Some(4), Eviction::None, &incoming),
+            Ok(Vec::new())
+        );
+    }
+
+    #[test]
+    fn eviction_order() {
+        let incoming = entry(300, metadata::Priority::Normal, 500, 40);
+        assert_eq!(
+            select(
+                &usage(),
+                Some(400),
+                None,
+                Eviction::ShortestLifetime,
+                &incoming
+            ),
+            Ok(vec![bundle_id(3), bundle_id(2)])
+        );
+        assert_eq!(
+            select(
+                &usage(),
+                Some(400),
+                None,
+                Eviction::OldestReceived,
+                &incoming
+            ),
+            Ok(vec![bundle_id(3), bundle_id(1)])
+        );
+    }
+
+    #[test]
+    fn reservation() {
+        let quota = Arc::new(Quota::new(Some(300), None, Eviction::None));
+        quota.add_entry(
+            bundle_id(1),
+            entry(100, metadata::Priority::Normal, 300, 10),
+        );
+
+        // The room made for one bundle cannot be claimed by another at the same time
+        let incoming = entry(200, metadata::Priority::Normal, 500, 40);
+        let (evict, reservation) = quota.make_room(&bundle_id(2), &incoming).unwrap();
+        assert!(evict.is_empty());
+        assert_eq!(quota.usage(), (300, 2));
+        assert_eq!(
+            quota.make_room(&bundle_id(3), &incoming).err(),
+            Some(Refusal::Bytes)
+        );
+
+        // Released if the bundle is not stored...
+        drop(reservation);
+        assert_eq!(quota.usage(), (100, 1));
+
+        // ... but not once it has been
+        let (_, reservation) = quota.make_room(&bundle_id(2), &incoming).unwrap();
+        quota.add_entry(bundle_id(2), incoming.clone());
+        drop(reservation);
+        assert_eq!(quota.usage(), (300, 2));
+    }
+
+    #[test]
+    fn refusal() {
+        // Nothing may be evicted
+        let incoming = entry(100, metadata::Priority::Normal, 500, 40);
+        assert_eq!(
+            select(&usage(), None, Some(3), Eviction::None, &incoming),
+            Err(Refusal::Bundles)
+        );
+
+        // Bulk bundles cannot evict normal ones
+        let incoming = entry(100, metadata::Priority::Bulk, 500, 40);
+        assert_eq!(
+            select(
+                &usage(),
+                Some(300),
+                None,
+                Eviction::OldestReceived,
+                &incoming
+            ),
+            Ok(vec![bundle_id(3)])
+        );
+        let incoming = entry(200, metadata::Priority::Bulk, 500, 40);
+        assert_eq!(
+            select(
+                &usage(),
+                Some(300),
+                None,
+                Eviction::OldestReceived,
+                &incoming
+            ),
+            Err(Refusal::Bytes)
+        );
+
+        // Nor can bundles that expire sooner
+        let incoming = entry(400, metadata::Priority::Normal, 250, 40);
+        assert_eq!(
+            select(
+                &usage(),
+                Some(400),
+                None,
+                Eviction::ShortestLifetime,
+                &incoming
+            ),
+            Err(Refusal::Bytes)
+        );
+    }
+}
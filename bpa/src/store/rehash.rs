@@ -0,0 +1,95 @@
+use super::*;
+
+// The number of hashes updated in each metadata storage transaction
+const BATCH_SIZE: usize = 256;
+
+impl Store {
+    /* Recompute the hash of every stored bundle with the configured algorithm, so
+     * the algorithm can be changed on an existing store.  This is run offline, before
+     * the store is started */
+    #[instrument(skip_all)]
+    pub async fn rehash(&self, cancel_token: tokio_util::sync::CancellationToken) {
+        info!(
+            "Recomputing bundle hashes with {}",
+            self.config.hash_algorithm.name()
+        );
+
+        // Both the bundle storage and any data inline in the metadata storage
+        let (stored_bundles, _reservation) = self.list_stored_bundles(cancel_token.clone()).await;
+        let mut storage_names = stored_bundles
+            .into_iter()
+            .map(|(storage_name, _)| storage_name)
+            .collect::<Vec<_>>();
+        storage_names.extend(self.list_inline_bundles().await);
+
+        // Give some feedback
+        let total = storage_names.len();
+        let mut last_progress = std::time::Instant::now();
+        let mut bundles = 0usize;
+        let mut updated = 0u64;
+
+        let mut batch = Vec::with_capacity(BATCH_SIZE);
+        for storage_name in storage_names {
+            if cancel_token.is_cancelled() {
+                break;
+            }
+
+            if last_progress.elapsed() >= std::time::Duration::from_secs(5) {
+                info!("Rehash in progress, {bundles} of {total} bundles processed, {updated} hashes updated");
+                last_progress = std::time::Instant::now();
+            }
+
+            bundles = bundles.saturating_add(1);
+            let Some(hash) = self
+                .hash_data(&storage_name)
+                .await
+                .trace_expect(&format!("Failed to load bundle data: {storage_name}"))
+            else {
+                // Data has gone while we were working
+                continue;
+            };
+
+            batch.push((storage_name, hash));
+            if batch.len() >= BATCH_SIZE {
+                updated = updated.saturating_add(self.update_hashes(&mut batch).await);
+            }
+        }
+        updated = updated.saturating_add(self.update_hashes(&mut batch).await);
+
+        info!("Rehash complete, {bundles} of {total} bundles processed, {updated} hashes updated");
+    }
+
+    async fn list_inline_bundles(&self) -> Vec<Arc<str>> {
+        let (tx, mut rx) = tokio::sync::mpsc::channel::<metadata::Bundle>(16);
+        let h = tokio::spawn(async move {
+            let mut storage_names = Vec::new();
+            while let Some(bundle) = rx.recv().await {
+                if let Some(storage_name) = bundle.metadata.storage_name {
+                    storage_names.push(storage_name);
+                }
+            }
+            storage_names
+        });
+
+        self.metadata_storage
+            .get_inline_bundles(tx)
+            .await
+            .trace_expect("Failed to get inline bundles");
+
+        h.await.trace_expect("Task terminated unexpectedly")
+    }
+
+    async fn update_hashes(&self, batch: &mut Vec<(Arc<str>, Arc<[u8]>)>) -> u64 {
+        if batch.is_empty() {
+            return 0;
+        }
+
+        let updated = self
+            .metadata_storage
+            .update_hashes(batch)
+            .await
+            .trace_expect("Failed to update bundle hashes");
+        batch.clear();
+        updated
+    }
+}
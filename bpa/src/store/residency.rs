@@ -0,0 +1,71 @@
+use super::*;
+use std::time::Instant;
+
+/* The time bundles spend in each status, recorded in the 'bundle_state_seconds'
+ * histogram as they leave it, with the number currently in each status in the
+ * 'bundles_in_state' gauge.  Only status changes made since startup are seen, so a
+ * bundle found by the restart scan is timed from its first change of status.
+ * Tombstones are the end of the line, and are not timed */
+
+fn state_name(status: &metadata::BundleStatus) -> Option<&'static str> {
+    match status {
+        metadata::BundleStatus::IngressPending => Some("ingress_pending"),
+        metadata::BundleStatus::DispatchPending => Some("dispatch_pending"),
+        metadata::BundleStatus::ReassemblyPending => Some("reassembly_pending"),
+        metadata::BundleStatus::CollectionPending => Some("collection_pending"),
+        metadata::BundleStatus::ForwardPending => Some("forward_pending"),
+        metadata::BundleStatus::ForwardAckPending(..) => Some("forward_ack_pending"),
+        metadata::BundleStatus::Waiting(_) => Some("waiting"),
+        metadata::BundleStatus::Tombstone(_) => None,
+    }
+}
+
+#[derive(Default)]
+pub(super) struct Residency {
+    entered: std::sync::Mutex<HashMap<bpv7::BundleId, (&'static str, Instant)>>,
+}
+
+impl Residency {
+    pub fn enter(&self, bundle_id: &bpv7::BundleId, status: &metadata::BundleStatus) {
+        let now = Instant::now();
+        let mut entered = self.entered.lock().trace_expect("Failed to lock mutex");
+        if let Some((state, since)) = entered.remove(bundle_id) {
+            Self::record(state, now.saturating_duration_since(since));
+        }
+        if let Some(state) = state_name(status) {
+            metrics::gauge!("bundles_in_state", "state" => state).increment(1);
+            entered.insert(bundle_id.clone(), (state, now));
+        }
+    }
+
+    pub fn leave(&self, bundle_id: &bpv7::BundleId) {
+        if let Some((state, since)) = self
+            .entered
+            .lock()
+            .trace_expect("Failed to lock mutex")
+            .remove(bundle_id)
+        {
+            Self::record(state, since.elapsed());
+        }
+    }
+
+    // The number of bundles in each status
+    pub fn counts(&self) -> HashMap<&'static str, usize> {
+        let mut counts = HashMap::new();
+        for (state, _) in self
+            .entered
+            .lock()
+            .trace_expect("Failed to lock mutex")
+            .values()
+        {
+            *counts.entry(*state).or_default() += 1;
+        }
+        counts
+    }
+
+    fn record(state: &'static str, duration: std::time::Duration) {
+        metrics::histogram!("bundle_state_seconds", "state" => state)
+            .record(duration.as_secs_f64());
+        metrics::gauge!("bundles_in_state", "state" => state).decrement(1);
+    }
+}
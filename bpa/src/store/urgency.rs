@@ -0,0 +1,171 @@
+use super::*;
+use std::{cmp::Reverse, collections::BinaryHeap};
+
+/* The restart scan loads stored bundles in order of urgency, rather than in the order the
+ * bundle storage lists them, so that on large stores the most time-critical traffic flows
+ * again long before the scan completes.  Bundles awaiting confirmation of forwarding come
+ * first, as a CLA may be holding a transfer open for them, then every other bundle known to
+ * the metadata storage, soonest expiry first.  Bundles the metadata storage does not know,
+ * which are usually orphans, come next in the order listed, and bundles whose data failed
+ * to load are retried last */
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Class {
+    ForwardAckPending,
+    Known,
+    Unknown,
+    Retry,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Urgency {
+    class: Class,
+    expiry: Option<time::OffsetDateTime>,
+}
+
+impl Urgency {
+    pub const UNKNOWN: Self = Self {
+        class: Class::Unknown,
+        expiry: None,
+    };
+
+    pub const RETRY: Self = Self {
+        class: Class::Retry,
+        expiry: None,
+    };
+
+    pub fn of(bundle: &metadata::Bundle) -> Self {
+        Self {
+            class: match bundle.metadata.status {
+                metadata::BundleStatus::ForwardAckPending(..) => Class::ForwardAckPending,
+                _ => Class::Known,
+            },
+            expiry: Some(bundle.expiry()),
+        }
+    }
+}
+
+// The urgency of every stored bundle with data in the bundle storage, by storage name
+pub async fn scan(
+    metadata_storage: &Arc<dyn storage::MetadataStorage>,
+    cancel_token: tokio_util::sync::CancellationToken,
+) -> (HashMap<Arc<str>, Urgency>, utils::memory::Reservation) {
+    let (tx, mut rx) = tokio::sync::mpsc::channel::<metadata::Bundle>(16);
+    let h = tokio::spawn(async move {
+        let mut urgencies = HashMap::new();
+        let mut reservation = utils::memory::reserve(utils::memory::Subsystem::Restart, 0);
+        loop {
+            tokio::select! {
+                bundle = rx.recv() => match bundle {
+                    None => break,
+                    Some(bundle) => {
+                        let Some(storage_name) = bundle.metadata.storage_name.clone() else {
+                            continue;
+                        };
+                        reservation.grow(std::mem::size_of::<(Arc<str>, Urgency)>() + storage_name.len());
+                        urgencies.insert(storage_name, Urgency::of(&bundle));
+                    }
+                },
+                _ = cancel_token.cancelled() => break,
+            }
+        }
+        (urgencies, reservation)
+    });
+
+    metadata_storage
+        .get_stored_bundles(tx)
+        .await
+        .trace_expect("Failed to get stored bundles");
+
+    h.await.trace_expect("Task terminated unexpectedly")
+}
+
+// Most urgent first, and first in first out among equals
+pub struct Queue<T: Ord> {
+    heap: BinaryHeap<Reverse<(Urgency, u64, T)>>,
+    sequence: u64,
+}
+
+impl<T: Ord> Queue<T> {
+    pub fn new() -> Self {
+        Self {
+            heap: BinaryHeap::new(),
+            sequence: 0,
+        }
+    }
+
+    pub fn push(&mut self, item: T, urgency: Urgency) {
+        self.heap.push(Reverse((urgency, self.sequence, item)));
+        self.sequence += 1;
+    }
+
+    pub fn pop(&mut self) -> Option<T> {
+        self.heap.pop().map(|Reverse((_, _, item))| item)
+    }
+
+    pub fn len(&self) -> usize {
+        self.heap.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.heap.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bundle(status: metadata::BundleStatus, lifetime: u64) -> metadata::Bundle {
+        metadata::Bundle {
+            bundle: bpv7::Bundle {
+                lifetime,
+                ..Default::default()
+            },
+            metadata: metadata::Metadata {
+                status,
+                received_at: Some(time::OffsetDateTime::from_unix_timestamp(1_000_000).unwrap()),
+                ..Default::default()
+            },
+        }
+    }
+
+    #[test]
+    fn order() {
+        let mut queue = Queue::new();
+        queue.push("retry", Urgency::RETRY);
+        queue.push("orphan 1", Urgency::UNKNOWN);
+        queue.push(
+            "late",
+            Urgency::of(&bundle(metadata::BundleStatus::DispatchPending, 60_000)),
+        );
+        queue.push(
+            "ack",
+            Urgency::of(&bundle(
+                metadata::BundleStatus::ForwardAckPending(
+                    1,
+                    time::OffsetDateTime::from_unix_timestamp(2_000_000).unwrap(),
+                ),
+                3_600_000,
+            )),
+        );
+        queue.push("orphan 2", Urgency::UNKNOWN);
+        queue.push(
+            "soon",
+            Urgency::of(&bundle(
+                metadata::BundleStatus::Waiting(
+                    time::OffsetDateTime::from_unix_timestamp(2_000_000).unwrap(),
+                ),
+                1_000,
+            )),
+        );
+
+        assert_eq!(queue.len(), 6);
+        let order = std::iter::from_fn(|| queue.pop()).collect::<Vec<_>>();
+        assert_eq!(
+            order,
+            ["ack", "soon", "late", "orphan 1", "orphan 2", "retry"]
+        );
+        assert!(queue.is_empty());
+    }
+}
@@ -0,0 +1,242 @@
+use super::*;
+use std::collections::HashSet;
+
+/* Waiting bundles, and bundles awaiting confirmation of forwarding, are found when they
+ * fall due from a hierarchical timer wheel of their deadlines, rather than by polling the
+ * metadata storage.  A bundle is scheduled whenever the store sets such a status, at the
+ * earlier of the end of its wait and its expiry, and the metadata storage is only scanned
+ * once, at startup, for the bundles waiting when the previous instance stopped.  Entries
+ * are never removed, so a bundle whose status has changed since it was scheduled is passed
+ * over when it falls due.
+ *
+ * The wheel ticks once a second.  Each level has 64 slots, each slot of a level spanning
+ * the whole of the level below, so four levels cover about 194 days, and anything later
+ * waits in an overflow list until the top level comes round */
+
+const BITS: u32 = 6;
+const SLOTS: usize = 1 << BITS;
+const LEVELS: u32 = 4;
+
+// The ticks spanned by the whole of `level`
+const fn span(level: u32) -> i64 {
+    1 << (BITS * level)
+}
+
+pub struct Wheel<T> {
+    // Every deadline up to and including this tick has fallen due
+    now: i64,
+    levels: Vec<Vec<Vec<(i64, T)>>>,
+    overflow: Vec<(i64, T)>,
+    due: Vec<T>,
+    len: usize,
+}
+
+impl<T> Wheel<T> {
+    pub fn new(now: i64) -> Self {
+        Self {
+            now,
+            levels: (0..LEVELS)
+                .map(|_| (0..SLOTS).map(|_| Vec::new()).collect())
+                .collect(),
+            overflow: Vec::new(),
+            due: Vec::new(),
+            len: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn insert(&mut self, tick: i64, item: T) {
+        self.len += 1;
+        self.place(tick, item);
+    }
+
+    fn place(&mut self, tick: i64, item: T) {
+        let delta = tick - self.now;
+        if delta <= 0 {
+            self.due.push(item);
+            return;
+        }
+        for level in 0..LEVELS {
+            if delta < span(level + 1) {
+                let slot = ((tick >> (BITS * level)) as usize) & (SLOTS - 1);
+                self.levels[level as usize][slot].push((tick, item));
+                return;
+            }
+        }
+        self.overflow.push((tick, item));
+    }
+
+    // Re-place every entry of a slot, now the wheel has moved on
+    fn cascade(&mut self, level: u32) {
+        let slot = ((self.now >> (BITS * level)) as usize) & (SLOTS - 1);
+        for (tick, item) in std::mem::take(&mut self.levels[level as usize][slot]) {
+            self.place(tick, item);
+        }
+    }
+
+    // Move the wheel on to `now`, returning at most `max` of the items that have fallen due,
+    // in the order they fell due.  Any left over are returned by the next call
+    pub fn expire(&mut self, now: i64, max: Option<usize>) -> Vec<T> {
+        if now - self.now >= span(LEVELS) {
+            // Too far to turn the wheel tick by tick, so start again
+            let entries = self
+                .levels
+                .iter_mut()
+                .flatten()
+                .flat_map(std::mem::take)
+                .chain(std::mem::take(&mut self.overflow))
+                .collect::<Vec<_>>();
+            self.now = now;
+            for (tick, item) in entries {
+                self.place(tick, item);
+            }
+        }
+
+        while self.now < now {
+            self.now += 1;
+
+            // Cascade from the highest level to come round, as it may fill the levels below
+            let top = (1..=LEVELS)
+                .take_while(|level| self.now % span(*level) == 0)
+                .last();
+            if top == Some(LEVELS) {
+                for (tick, item) in std::mem::take(&mut self.overflow) {
+                    self.place(tick, item);
+                }
+            }
+            for level in (1..top.map_or(1, |top| top.min(LEVELS - 1) + 1)).rev() {
+                self.cascade(level);
+            }
+            self.cascade(0);
+        }
+
+        let n = max.unwrap_or(usize::MAX).min(self.due.len());
+        self.len -= n;
+        self.due.drain(..n).collect()
+    }
+}
+
+// The deadlines of the bundles in the store
+pub(super) struct Deadlines {
+    wheel: std::sync::Mutex<Wheel<bpv7::BundleId>>,
+}
+
+impl Default for Deadlines {
+    fn default() -> Self {
+        Self {
+            wheel: std::sync::Mutex::new(Wheel::new(utils::clock::now().unix_timestamp())),
+        }
+    }
+}
+
+// The time a bundle next needs dispatching, if it is waiting for one
+fn deadline(bundle: &bpv7::Bundle, metadata: &metadata::Metadata) -> Option<time::OffsetDateTime> {
+    match &metadata.status {
+        metadata::BundleStatus::Waiting(until)
+        | metadata::BundleStatus::ForwardAckPending(_, until) => {
+            Some((*until).min(metadata::Bundle::expiry_of(bundle, metadata.received_at)))
+        }
+        _ => None,
+    }
+}
+
+impl Deadlines {
+    pub fn schedule(&self, bundle: &bpv7::Bundle, metadata: &metadata::Metadata) {
+        let Some(deadline) = deadline(bundle, metadata) else {
+            return;
+        };
+
+        // Round up, so a bundle never falls due before its deadline
+        let tick = deadline.unix_timestamp() + i64::from(deadline.nanosecond() != 0);
+        self.wheel
+            .lock()
+            .trace_expect("Failed to lock mutex")
+            .insert(tick, bundle.id.clone());
+    }
+
+    // The bundles due by `now`, at most `max` of them, each only once
+    pub fn due(&self, now: time::OffsetDateTime, max: Option<usize>) -> Vec<bpv7::BundleId> {
+        let mut seen = HashSet::new();
+        self.wheel
+            .lock()
+            .trace_expect("Failed to lock mutex")
+            .expire(now.unix_timestamp(), max)
+            .into_iter()
+            .filter(|bundle_id| seen.insert(bundle_id.clone()))
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.wheel.lock().trace_expect("Failed to lock mutex").len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expire(wheel: &mut Wheel<i64>, now: i64) -> Vec<i64> {
+        let mut due = wheel.expire(now, None);
+        due.sort();
+        due
+    }
+
+    #[test]
+    fn levels() {
+        let start = 1_000_000;
+        let mut wheel = Wheel::new(start);
+        let deltas = [
+            -5,
+            0,
+            1,
+            63,
+            64,
+            65,
+            4095,
+            4096,
+            300_000,
+            span(LEVELS) - 1,
+            span(LEVELS) + 7,
+        ];
+        for delta in deltas {
+            wheel.insert(start + delta, delta);
+        }
+        assert_eq!(wheel.len(), deltas.len());
+
+        // Everything falls due on its tick, and not before
+        assert_eq!(expire(&mut wheel, start), [-5, 0]);
+        for delta in &deltas[2..] {
+            assert_eq!(expire(&mut wheel, start + delta - 1), Vec::<i64>::new());
+            assert_eq!(expire(&mut wheel, start + delta), [*delta]);
+        }
+        assert_eq!(wheel.len(), 0);
+    }
+
+    #[test]
+    fn jump() {
+        let mut wheel = Wheel::new(0);
+        wheel.insert(10, 1);
+        wheel.insert(span(LEVELS) * 3, 2);
+        wheel.insert(span(LEVELS) * 5, 3);
+
+        // Jumping far ahead still finds everything due, and keeps the rest
+        assert_eq!(expire(&mut wheel, span(LEVELS) * 4), [1, 2]);
+        assert_eq!(wheel.len(), 1);
+        assert_eq!(expire(&mut wheel, span(LEVELS) * 5), [3]);
+    }
+
+    #[test]
+    fn limit() {
+        let mut wheel = Wheel::new(0);
+        for item in 0..5 {
+            wheel.insert(1, item);
+        }
+        assert_eq!(wheel.expire(1, Some(2)).len(), 2);
+        assert_eq!(wheel.expire(1, Some(2)).len(), 2);
+        assert_eq!(wheel.expire(1, None).len(), 1);
+        assert_eq!(wheel.len(), 0);
+    }
+}
@@ -16,6 +16,23 @@ impl IpnNodeId {
             service_number,
         }
     }
+
+    pub fn node_number(&self) -> u32 {
+        self.node_number
+    }
+
+    // Another node under the same allocator
+    pub fn with_node_number(&self, node_number: u32) -> Self {
+        Self {
+            allocator_id: self.allocator_id,
+            node_number,
+        }
+    }
+
+    // Every service of the node
+    pub fn to_pattern(&self) -> Result<bpv7::EidPattern, bpv7::EidPatternError> {
+        format!("ipn:{}.{}.*", self.allocator_id, self.node_number).parse()
+    }
 }
 
 impl std::fmt::Display for IpnNodeId {
@@ -0,0 +1,74 @@
+use super::*;
+
+/* What this build of the BPA can do, and what it is attached to, so that deployments
+ * mixing builds and storage engines can be audited.  CLAs are separate processes,
+ * so only those currently registered are known */
+
+const FEATURES: &[(&str, bool)] = &[
+    ("sqlite-storage", cfg!(feature = "sqlite-storage")),
+    ("localdisk-storage", cfg!(feature = "localdisk-storage")),
+    ("rocksdb-storage", cfg!(feature = "rocksdb-storage")),
+    ("mem-storage", cfg!(feature = "mem-storage")),
+    (
+        "packaged-installation",
+        cfg!(feature = "packaged-installation"),
+    ),
+    ("otlp", cfg!(feature = "otlp")),
+];
+
+pub fn features() -> Vec<String> {
+    FEATURES
+        .iter()
+        .filter(|(_, enabled)| *enabled)
+        .map(|(name, _)| name.to_string())
+        .collect()
+}
+
+pub fn bpsec_contexts() -> Vec<String> {
+    [
+        bpv7::bpsec::Context::BIB_HMAC_SHA2,
+        bpv7::bpsec::Context::BCB_AES_GCM,
+    ]
+    .into_iter()
+    .map(|context| context.to_string())
+    .collect()
+}
+
+pub fn storage_capabilities(capabilities: hardy_bpa_api::storage::Capabilities) -> Vec<String> {
+    [
+        ("streaming", capabilities.streaming),
+        ("destination-queries", capabilities.destination_queries),
+        ("transactional-batches", capabilities.transactional_batches),
+        ("approximate-counts", capabilities.approximate_counts),
+    ]
+    .into_iter()
+    .filter(|(_, supported)| *supported)
+    .map(|(name, _)| name.to_string())
+    .collect()
+}
+
+fn join_or_none(names: Vec<String>) -> String {
+    if names.is_empty() {
+        "none".to_string()
+    } else {
+        names.join(", ")
+    }
+}
+
+pub fn log(store: &store::Store) {
+    info!("Compiled-in features: {}", features().join(", "));
+    info!("BPSec security contexts: {}", bpsec_contexts().join(", "));
+
+    let (engine, version) = store.metadata_engine();
+    info!(
+        "Metadata storage engine '{engine}', schema version {}, capabilities: {}",
+        version.as_deref().unwrap_or("unversioned"),
+        join_or_none(storage_capabilities(store.metadata_capabilities()))
+    );
+    let (engine, version) = store.bundle_engine();
+    info!(
+        "Bundle storage engine '{engine}', schema version {}, capabilities: {}",
+        version.as_deref().unwrap_or("unversioned"),
+        join_or_none(storage_capabilities(store.bundle_capabilities()))
+    );
+}
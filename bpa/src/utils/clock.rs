@@ -0,0 +1,73 @@
+use super::*;
+use std::sync::OnceLock;
+
+/* The clock is normally the system clock, but when running under the simulation harness
+ * virtual time runs 'time_scale' times faster than wall-clock time, starting at 'start_time',
+ * so that long contact plans can be exercised quickly */
+
+struct VirtualClock {
+    epoch: time::OffsetDateTime,
+    started: std::time::Instant,
+    time_scale: f64,
+}
+
+static VIRTUAL_CLOCK: OnceLock<VirtualClock> = OnceLock::new();
+
+pub fn init(config: &config::Config) {
+    let time_scale = settings::get_with_default::<f64, _>(config, "simulation.time_scale", 1.0)
+        .trace_expect("Invalid 'simulation.time_scale' value in configuration");
+    if !time_scale.is_normal() || time_scale < 0.0 {
+        error!("'simulation.time_scale' must be greater than 0");
+        panic!("'simulation.time_scale' must be greater than 0");
+    }
+
+    let start_time =
+        settings::get_with_default::<Option<String>, _>(config, "simulation.start_time", None)
+            .trace_expect("Invalid 'simulation.start_time' value in configuration")
+            .map(|s| {
+                time::OffsetDateTime::parse(&s, &time::format_description::well_known::Rfc3339)
+                    .trace_expect("Invalid 'simulation.start_time' value in configuration")
+            });
+
+    if time_scale == 1.0 && start_time.is_none() {
+        return;
+    }
+
+    let epoch = start_time.unwrap_or_else(time::OffsetDateTime::now_utc);
+    info!("Using virtual time starting at {epoch}, running at {time_scale}x real time");
+
+    if VIRTUAL_CLOCK
+        .set(VirtualClock {
+            epoch,
+            started: std::time::Instant::now(),
+            time_scale,
+        })
+        .is_err()
+    {
+        warn!("Virtual clock already initialized");
+    }
+
+    // Bundle expiry is computed outside the BPA too
+    hardy_bpa_api::clock::install(now);
+}
+
+pub fn now() -> time::OffsetDateTime {
+    match VIRTUAL_CLOCK.get() {
+        None => time::OffsetDateTime::now_utc(),
+        Some(clock) => clock
+            .epoch
+            .saturating_add(time::Duration::saturating_seconds_f64(
+                clock.started.elapsed().as_secs_f64() * clock.time_scale,
+            )),
+    }
+}
+
+pub async fn sleep(
+    duration: time::Duration,
+    cancel_token: &tokio_util::sync::CancellationToken,
+) -> bool {
+    match VIRTUAL_CLOCK.get() {
+        None => cancel::cancellable_sleep(duration, cancel_token).await,
+        Some(clock) => cancel::cancellable_sleep(duration / clock.time_scale, cancel_token).await,
+    }
+}
@@ -0,0 +1,29 @@
+use super::*;
+
+/* The counters, gauges and histograms the BPA emits go nowhere unless a recorder is
+ * installed.  When 'metrics.prometheus_address' is configured, they are served for
+ * Prometheus to scrape at '/metrics' on that address */
+
+pub fn init(config: &config::Config) {
+    let Some(address) =
+        settings::get_with_default::<Option<String>, _>(config, "metrics.prometheus_address", None)
+            .trace_expect("Invalid 'metrics.prometheus_address' value in configuration")
+    else {
+        return;
+    };
+
+    let address = address
+        .parse::<std::net::SocketAddr>()
+        .trace_expect(&format!(
+            "Invalid 'metrics.prometheus_address' value '{address}' in configuration"
+        ));
+
+    if let Err(e) = metrics_exporter_prometheus::PrometheusBuilder::new()
+        .with_http_listener(address)
+        .install()
+    {
+        error!("Failed to start the Prometheus exporter on {address}: {e}");
+        return;
+    }
+    info!("Serving metrics for Prometheus at http://{address}/metrics");
+}
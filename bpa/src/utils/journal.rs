@@ -0,0 +1,141 @@
+use super::*;
+use std::io::Read;
+use tokio_util::bytes::Bytes;
+
+/* An ingress journal is a header followed by one record per received bundle, holding the
+ * time it arrived, the name of the CLA and the CLA-specific address of the peer it arrived
+ * from, if it came from a CLA, and the bundle exactly as it was received.  All integers are
+ * big-endian, and the time is in nanoseconds since the Unix epoch */
+
+pub const MAGIC: &[u8; 8] = b"HARDYIJ1";
+
+#[derive(Debug, Clone)]
+pub struct Record {
+    pub received_at: time::OffsetDateTime,
+    pub cla: Option<String>,
+    pub source: Bytes,
+    pub data: Bytes,
+}
+
+impl Record {
+    pub fn encode(&self) -> Vec<u8> {
+        let cla = self.cla.as_deref().unwrap_or_default().as_bytes();
+        let cla = &cla[..cla.len().min(u16::MAX as usize)];
+        let source = &self.source[..self.source.len().min(u32::MAX as usize)];
+
+        let mut buf = Vec::with_capacity(30 + cla.len() + source.len() + self.data.len());
+        let nanos = self
+            .received_at
+            .unix_timestamp_nanos()
+            .clamp(0, i64::MAX as i128) as i64;
+        buf.extend_from_slice(&nanos.to_be_bytes());
+        buf.extend_from_slice(&(cla.len() as u16).to_be_bytes());
+        buf.extend_from_slice(cla);
+        buf.extend_from_slice(&(source.len() as u32).to_be_bytes());
+        buf.extend_from_slice(source);
+        buf.extend_from_slice(&(self.data.len() as u64).to_be_bytes());
+        buf.extend_from_slice(&self.data);
+        buf
+    }
+}
+
+fn read_bytes(r: &mut impl Read, len: usize) -> std::io::Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    r.take(len as u64).read_to_end(&mut buf)?;
+    if buf.len() != len {
+        return Err(std::io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(buf)
+}
+
+fn read_array<const N: usize>(r: &mut impl Read) -> std::io::Result<[u8; N]> {
+    let mut buf = [0u8; N];
+    r.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+pub struct Reader<R: Read> {
+    inner: R,
+}
+
+impl<R: Read> Reader<R> {
+    pub fn new(mut inner: R) -> Result<Self, Error> {
+        if &read_array::<8>(&mut inner)? != MAGIC {
+            return Err("Not an ingress journal".into());
+        }
+        Ok(Self { inner })
+    }
+
+    // None at the end of the journal
+    pub fn next_record(&mut self) -> Result<Option<Record>, Error> {
+        let mut nanos = [0u8; 8];
+        let mut read = 0;
+        while read < nanos.len() {
+            match self.inner.read(&mut nanos[read..])? {
+                0 if read == 0 => return Ok(None),
+                0 => return Err("Truncated ingress journal".into()),
+                n => read += n,
+            }
+        }
+        let received_at =
+            time::OffsetDateTime::from_unix_timestamp_nanos(i64::from_be_bytes(nanos) as i128)?;
+
+        let r = &mut self.inner;
+        let cla_len = u16::from_be_bytes(read_array(r)?) as usize;
+        let cla = String::from_utf8(read_bytes(r, cla_len)?)?;
+        let source_len = u32::from_be_bytes(read_array(r)?) as usize;
+        let source = read_bytes(r, source_len)?;
+        let data_len = usize::try_from(u64::from_be_bytes(read_array(r)?))?;
+        let data = read_bytes(r, data_len)?;
+
+        Ok(Some(Record {
+            received_at,
+            cla: (!cla.is_empty()).then_some(cla),
+            source: source.into(),
+            data: data.into(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let records = [
+            Record {
+                received_at: time::macros::datetime!(2024-06-01 12:00:00.5 UTC),
+                cla: Some("tcpcl".to_string()),
+                source: Bytes::from_static(b"192.0.2.1:4556"),
+                data: Bytes::from_static(b"bundle"),
+            },
+            Record {
+                received_at: time::macros::datetime!(2024-06-01 12:00:01 UTC),
+                cla: None,
+                source: Bytes::new(),
+                data: Bytes::from_static(b"another"),
+            },
+        ];
+
+        let mut journal = MAGIC.to_vec();
+        for record in &records {
+            journal.extend(record.encode());
+        }
+
+        let mut reader = Reader::new(journal.as_slice()).unwrap();
+        for record in &records {
+            let read = reader.next_record().unwrap().unwrap();
+            assert_eq!(read.received_at, record.received_at);
+            assert_eq!(read.cla, record.cla);
+            assert_eq!(read.source, record.source);
+            assert_eq!(read.data, record.data);
+        }
+        assert!(reader.next_record().unwrap().is_none());
+
+        // A record cut short is an error, not the end
+        let mut reader = Reader::new(&journal[..journal.len() - 1]).unwrap();
+        reader.next_record().unwrap();
+        assert!(reader.next_record().is_err());
+    }
+}
@@ -0,0 +1,115 @@
+use super::*;
+use std::collections::HashSet;
+use std::sync::{Mutex, OnceLock};
+
+/* Counters of the bundles sent, delivered and dropped, optionally labelled with the
+ * registered application or tenant they belong to.  The number of distinct label
+ * values is bounded: applications registering once the limit is reached share the
+ * 'other' label, and bundles that belong to no application have the 'none' label */
+
+const OTHER_LABEL: &str = "other";
+const NONE_LABEL: &str = "none";
+
+#[derive(Debug, Clone, Copy)]
+pub enum Counter {
+    Sent,
+    Delivered,
+    Dropped,
+}
+
+impl Counter {
+    fn name(self) -> &'static str {
+        match self {
+            Counter::Sent => "bundles_sent_total",
+            Counter::Delivered => "bundles_delivered_total",
+            Counter::Dropped => "bundles_dropped_total",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum LabelMode {
+    None,
+    Application,
+    Tenant,
+}
+
+struct Labels {
+    mode: LabelMode,
+    max_labels: usize,
+    seen: Mutex<HashSet<String>>,
+}
+
+static LABELS: OnceLock<Labels> = OnceLock::new();
+
+pub fn init(config: &config::Config) {
+    let mode =
+        settings::get_with_default::<String, _>(config, "metrics.application_labels", "none")
+            .trace_expect("Invalid 'metrics.application_labels' value in configuration");
+    let mode = match mode.as_str() {
+        "none" => LabelMode::None,
+        "application" => LabelMode::Application,
+        "tenant" => LabelMode::Tenant,
+        _ => {
+            error!("Invalid 'metrics.application_labels' value '{mode}' in configuration");
+            panic!("Invalid 'metrics.application_labels' value '{mode}' in configuration");
+        }
+    };
+
+    let max_labels = settings::get_with_default(config, "metrics.max_application_labels", 64usize)
+        .trace_expect("Invalid 'metrics.max_application_labels' value in configuration");
+
+    if LABELS
+        .set(Labels {
+            mode,
+            max_labels,
+            seen: Default::default(),
+        })
+        .is_err()
+    {
+        warn!("Metrics labels already initialized");
+    }
+}
+
+fn enabled() -> bool {
+    LABELS
+        .get()
+        .is_some_and(|labels| !matches!(labels.mode, LabelMode::None))
+}
+
+// The label to use for a registered application, or None if labels are disabled
+pub fn application_label(ident: &str) -> Option<String> {
+    let labels = LABELS.get()?;
+    let label = match labels.mode {
+        LabelMode::None => return None,
+        LabelMode::Application => ident,
+        // The tenant is the part of the application ident before the first '/'
+        LabelMode::Tenant => ident.split('/').next().unwrap_or(ident),
+    };
+
+    let mut seen = labels.seen.lock().trace_expect("Failed to lock mutex");
+    if seen.contains(label) || seen.len() < labels.max_labels {
+        seen.insert(label.to_string());
+        Some(label.to_string())
+    } else {
+        Some(OTHER_LABEL.to_string())
+    }
+}
+
+pub fn count(counter: Counter, label: Option<String>) {
+    if enabled() {
+        metrics::counter!(counter.name(), "application" => label.unwrap_or_else(|| NONE_LABEL.to_string()))
+            .increment(1);
+    } else {
+        metrics::counter!(counter.name()).increment(1);
+    }
+}
+
+// The label for the priority class of a bundle, there are only ever three
+pub fn priority_label(priority: metadata::Priority) -> &'static str {
+    match priority {
+        metadata::Priority::Bulk => "bulk",
+        metadata::Priority::Normal => "normal",
+        metadata::Priority::Expedited => "expedited",
+    }
+}
@@ -1,15 +1,32 @@
 use super::*;
+use tracing_subscriber::prelude::*;
 
-pub fn init(config: &config::Config) {
+// Keeps the logger's exporters running until dropped
+pub struct Guard {
+    #[cfg(feature = "otlp")]
+    _otlp: Option<otlp::Guard>,
+}
+
+pub fn init(config: &config::Config) -> Guard {
     let log_level = settings::get_with_default::<String, _>(config, "log_level", "info")
         .expect("Invalid 'log_level' value in configuration")
         .parse::<tracing_subscriber::filter::LevelFilter>()
         .expect("Invalid log level");
 
-    tracing_subscriber::fmt()
-        .with_max_level(log_level)
-        .with_target(
+    let registry = tracing_subscriber::registry().with(log_level).with(
+        tracing_subscriber::fmt::layer().with_target(
             log_level > tracing_subscriber::filter::LevelFilter::from_level(tracing::Level::INFO),
-        )
-        .init();
+        ),
+    );
+
+    #[cfg(feature = "otlp")]
+    let (layer, guard) = otlp::layer(config).unzip();
+    #[cfg(feature = "otlp")]
+    let registry = registry.with(layer);
+
+    registry.init();
+    Guard {
+        #[cfg(feature = "otlp")]
+        _otlp: guard,
+    }
 }
@@ -0,0 +1,236 @@
+use super::*;
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    OnceLock,
+};
+
+/* Lightweight accounting of the bytes held by each subsystem, published as the
+ * 'memory_bytes' gauge.  Each subsystem may have a configured soft limit: once it is
+ * reached, new work is shed until enough memory has been released.
+ *
+ * In strict mode, for constrained or certification-sensitive deployments, the limits are
+ * instead pre-sized budgets that are never exceeded: a reservation is only admitted if it
+ * fits entirely within what remains, and is explicitly rejected otherwise.  Together with
+ * a fixed dispatch queue depth this bounds the worst-case memory held for bundles in
+ * flight, at the cost of rejecting work that the soft limits would have let through */
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Subsystem {
+    Ingress,
+    Dispatcher,
+    Restart,
+    MemStorage,
+    Parse,
+}
+
+impl Subsystem {
+    const ALL: [Subsystem; 5] = [
+        Subsystem::Ingress,
+        Subsystem::Dispatcher,
+        Subsystem::Restart,
+        Subsystem::MemStorage,
+        Subsystem::Parse,
+    ];
+
+    // The subsystems that must have a budget in strict mode
+    const STRICT: [Subsystem; 4] = [
+        Subsystem::Ingress,
+        Subsystem::Dispatcher,
+        Subsystem::Restart,
+        Subsystem::Parse,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Subsystem::Ingress => "ingress",
+            Subsystem::Dispatcher => "dispatcher",
+            Subsystem::Restart => "restart",
+            Subsystem::MemStorage => "mem_storage",
+            Subsystem::Parse => "parse",
+        }
+    }
+}
+
+const DEFAULT_DISPATCH_QUEUE: usize = 16;
+
+struct Limits {
+    limits: [Option<usize>; 5],
+    strict: bool,
+    dispatch_queue: usize,
+}
+
+static USAGE: [AtomicUsize; 5] = [const { AtomicUsize::new(0) }; 5];
+static LIMITS: OnceLock<Limits> = OnceLock::new();
+
+pub fn init(config: &config::Config) {
+    let strict = settings::get_with_default(config, "memory_limits.strict", false)
+        .trace_expect("Invalid 'memory_limits.strict' value in configuration");
+
+    let mut limits = [None; 5];
+    for subsystem in Subsystem::ALL {
+        let key = format!("memory_limits.{}", subsystem.name());
+        let limit = settings::get_with_default::<usize, _>(config, &key, 0usize)
+            .trace_expect(&format!("Invalid '{key}' value in configuration"));
+        if limit != 0 {
+            info!(
+                "{} memory limit for {} is {limit} bytes",
+                if strict { "Strict" } else { "Soft" },
+                subsystem.name()
+            );
+            limits[subsystem as usize] = Some(limit);
+        } else if strict && Subsystem::STRICT.contains(&subsystem) {
+            error!("Strict memory mode requires a non-zero '{key}' budget");
+            panic!("Strict memory mode requires a non-zero '{key}' budget");
+        }
+    }
+
+    let dispatch_queue = settings::get_with_default::<usize, _>(
+        config,
+        "memory_limits.dispatch_queue",
+        DEFAULT_DISPATCH_QUEUE,
+    )
+    .trace_expect("Invalid 'memory_limits.dispatch_queue' value in configuration");
+    if dispatch_queue == 0 {
+        error!("'memory_limits.dispatch_queue' must be greater than 0");
+        panic!("'memory_limits.dispatch_queue' must be greater than 0");
+    }
+    if strict {
+        info!("Strict memory mode, with a dispatch queue depth of {dispatch_queue}");
+    }
+
+    if LIMITS
+        .set(Limits {
+            limits,
+            strict,
+            dispatch_queue,
+        })
+        .is_err()
+    {
+        warn!("Memory limits already initialized");
+    }
+}
+
+fn limit(subsystem: Subsystem) -> Option<usize> {
+    LIMITS
+        .get()
+        .and_then(|limits| limits.limits[subsystem as usize])
+}
+
+pub fn is_strict() -> bool {
+    LIMITS.get().is_some_and(|limits| limits.strict)
+}
+
+// The number of bundles that may be queued for dispatch
+pub fn dispatch_queue() -> usize {
+    LIMITS
+        .get()
+        .map_or(DEFAULT_DISPATCH_QUEUE, |limits| limits.dispatch_queue)
+}
+
+// Take 'bytes' from the budget of 'subsystem', only if they fit entirely within it
+fn admit(subsystem: Subsystem, limit: usize, bytes: usize) -> Option<usize> {
+    USAGE[subsystem as usize]
+        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+            used.checked_add(bytes).filter(|used| *used <= limit)
+        })
+        .ok()
+        .map(|used| used + bytes)
+}
+
+fn reject(subsystem: Subsystem) {
+    metrics::counter!("memory_shed_total", "subsystem" => subsystem.name()).increment(1);
+}
+
+fn publish(subsystem: Subsystem, used: usize) {
+    metrics::gauge!("memory_bytes", "subsystem" => subsystem.name()).set(used as f64);
+}
+
+// Bytes accounted against a subsystem, released on drop
+pub struct Reservation {
+    subsystem: Subsystem,
+    bytes: usize,
+}
+
+impl Reservation {
+    pub fn grow(&mut self, bytes: usize) {
+        let used = USAGE[self.subsystem as usize].fetch_add(bytes, Ordering::Relaxed) + bytes;
+        self.bytes += bytes;
+        publish(self.subsystem, used);
+    }
+
+    // Grow the reservation, unless the subsystem is over its soft limit, or in strict mode
+    // the bytes do not fit within its budget
+    pub fn try_grow(&mut self, bytes: usize) -> bool {
+        match (is_strict(), limit(self.subsystem)) {
+            (true, Some(limit)) => {
+                let Some(used) = admit(self.subsystem, limit, bytes) else {
+                    trace!(
+                        "{} memory budget exhausted, rejecting",
+                        self.subsystem.name()
+                    );
+                    reject(self.subsystem);
+                    return false;
+                };
+                self.bytes += bytes;
+                publish(self.subsystem, used);
+                true
+            }
+            _ if over_limit(self.subsystem) => {
+                trace!(
+                    "{} is over its soft memory limit, shedding",
+                    self.subsystem.name()
+                );
+                reject(self.subsystem);
+                false
+            }
+            _ => {
+                self.grow(bytes);
+                true
+            }
+        }
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        let used =
+            USAGE[self.subsystem as usize].fetch_sub(self.bytes, Ordering::Relaxed) - self.bytes;
+        publish(self.subsystem, used);
+    }
+}
+
+pub fn usage(subsystem: Subsystem) -> usize {
+    USAGE[subsystem as usize].load(Ordering::Relaxed)
+}
+
+// The bytes held by every subsystem, by name
+pub fn usages() -> Vec<(&'static str, usize)> {
+    Subsystem::ALL
+        .into_iter()
+        .map(|subsystem| (subsystem.name(), usage(subsystem)))
+        .collect()
+}
+
+pub fn over_limit(subsystem: Subsystem) -> bool {
+    limit(subsystem).is_some_and(|limit| usage(subsystem) >= limit)
+}
+
+// Account for memory unconditionally
+pub fn reserve(subsystem: Subsystem, bytes: usize) -> Reservation {
+    let mut reservation = Reservation {
+        subsystem,
+        bytes: 0,
+    };
+    reservation.grow(bytes);
+    reservation
+}
+
+// Account for memory, unless the subsystem is over its soft limit, or in strict mode the
+// bytes do not fit within its budget, and the work should be shed
+pub fn try_reserve(subsystem: Subsystem, bytes: usize) -> Option<Reservation> {
+    let mut reservation = Reservation {
+        subsystem,
+        bytes: 0,
+    };
+    reservation.try_grow(bytes).then_some(reservation)
+}
@@ -3,5 +3,14 @@ use super::*;
 pub mod admin_endpoints;
 pub mod built_info;
 pub mod cancel;
+pub mod capabilities;
+pub mod clock;
+pub mod exporter;
+pub mod journal;
+pub mod labels;
 pub mod logger;
+pub mod memory;
+#[cfg(feature = "otlp")]
+pub mod otlp;
+pub mod random;
 pub mod settings;
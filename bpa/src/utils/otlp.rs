@@ -0,0 +1,139 @@
+use super::*;
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::trace::Sampler;
+
+/* Spans are exported over OTLP/gRPC when the 'otlp' section is configured.  The W3C trace
+ * context is taken from the requests of CLAs handing bundles over, and passed on in the
+ * requests to CLAs forwarding bundles, so a trace can follow a bundle from node to node */
+
+#[derive(serde::Deserialize)]
+struct Config {
+    #[serde(default = "Config::default_endpoint")]
+    endpoint: String,
+
+    // The proportion of traces to sample, from 0 to 1
+    #[serde(default = "Config::default_sampling_ratio")]
+    sampling_ratio: f64,
+
+    #[serde(default = "Config::default_service_name")]
+    service_name: String,
+}
+
+impl Config {
+    fn default_endpoint() -> String {
+        "http://localhost:4317".to_string()
+    }
+
+    fn default_sampling_ratio() -> f64 {
+        1.0
+    }
+
+    fn default_service_name() -> String {
+        built_info::PKG_NAME.to_string()
+    }
+}
+
+// Flushes the spans not yet exported when dropped
+pub struct Guard(opentelemetry_sdk::trace::TracerProvider);
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        if let Err(e) = self.0.shutdown() {
+            eprintln!("Failed to flush OTLP spans: {e}");
+        }
+    }
+}
+
+// None unless the 'otlp' section is configured
+pub fn layer<S>(
+    config: &config::Config,
+) -> Option<(
+    tracing_opentelemetry::OpenTelemetryLayer<S, opentelemetry_sdk::trace::Tracer>,
+    Guard,
+)>
+where
+    S: tracing::Subscriber + for<'a> tracing_subscriber::registry::LookupSpan<'a>,
+{
+    // The logger is not running yet, so failures cannot be traced
+    let config = settings::get_with_default::<Option<Config>, _>(config, "otlp", None)
+        .expect("Invalid 'otlp' section in configuration")?;
+
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_tonic()
+        .with_endpoint(&config.endpoint)
+        .build()
+        .expect("Failed to create OTLP exporter");
+
+    let provider = opentelemetry_sdk::trace::TracerProvider::builder()
+        .with_batch_exporter(exporter, opentelemetry_sdk::runtime::Tokio)
+        .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(
+            config.sampling_ratio.clamp(0.0, 1.0),
+        ))))
+        .with_resource(opentelemetry_sdk::Resource::new([
+            opentelemetry::KeyValue::new("service.name", config.service_name.clone()),
+            opentelemetry::KeyValue::new("service.version", built_info::PKG_VERSION),
+        ]))
+        .build();
+
+    opentelemetry::global::set_text_map_propagator(
+        opentelemetry_sdk::propagation::TraceContextPropagator::new(),
+    );
+
+    let tracer = provider.tracer(config.service_name);
+    Some((
+        tracing_opentelemetry::layer().with_tracer(tracer),
+        Guard(provider),
+    ))
+}
+
+struct Injector<'a>(&'a mut tonic::metadata::MetadataMap);
+
+impl opentelemetry::propagation::Injector for Injector<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        if let (Ok(key), Ok(value)) = (
+            tonic::metadata::AsciiMetadataKey::from_bytes(key.as_bytes()),
+            value.parse::<tonic::metadata::AsciiMetadataValue>(),
+        ) {
+            self.0.insert(key, value);
+        }
+    }
+}
+
+struct Extractor<'a>(&'a tonic::metadata::MetadataMap);
+
+impl opentelemetry::propagation::Extractor for Extractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0
+            .keys()
+            .filter_map(|key| match key {
+                tonic::metadata::KeyRef::Ascii(key) => Some(key.as_str()),
+                tonic::metadata::KeyRef::Binary(_) => None,
+            })
+            .collect()
+    }
+}
+
+// Pass the context of the current span on in the metadata of an outgoing request
+pub fn inject(metadata: &mut tonic::metadata::MetadataMap) {
+    use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+    let context = tracing::Span::current().context();
+    opentelemetry::global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&context, &mut Injector(metadata))
+    });
+}
+
+// Make the current span a child of the context in the metadata of an incoming request
+pub fn extract(metadata: &tonic::metadata::MetadataMap) {
+    use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+    let context = opentelemetry::global::get_text_map_propagator(|propagator| {
+        propagator.extract(&Extractor(metadata))
+    });
+    tracing::Span::current().set_parent(context);
+}
@@ -0,0 +1,35 @@
+use super::*;
+use rand::SeedableRng;
+use std::sync::{Mutex, OnceLock};
+
+/* Randomness is normally drawn from the OS, but when running under the simulation harness or
+ * in integration tests a 'seed' makes it deterministic, so that CLA handles, application
+ * tokens and EIDs, storage names and the order equal cost routes are tried in are the same
+ * from run to run.  Encryption nonces, and anything else that must stay unpredictable, are
+ * always drawn from the OS */
+
+static SEEDED: OnceLock<Mutex<rand::rngs::StdRng>> = OnceLock::new();
+
+pub fn init(config: &config::Config) {
+    let Some(seed) = settings::get_with_default::<Option<u64>, _>(config, "simulation.seed", None)
+        .trace_expect("Invalid 'simulation.seed' value in configuration")
+    else {
+        return;
+    };
+
+    warn!("Using deterministic randomness with seed {seed}, this is not for production use");
+    if SEEDED
+        .set(Mutex::new(rand::rngs::StdRng::seed_from_u64(seed)))
+        .is_err()
+    {
+        warn!("Random number generator already initialized");
+    }
+}
+
+// Call `f` with the seeded generator if there is one, otherwise the OS-seeded thread generator
+pub fn with_rng<T>(f: impl FnOnce(&mut dyn rand::RngCore) -> T) -> T {
+    match SEEDED.get() {
+        None => f(&mut rand::thread_rng()),
+        Some(rng) => f(&mut *rng.lock().trace_expect("Failed to lock mutex")),
+    }
+}
@@ -1,8 +1,26 @@
 use super::*;
+use std::collections::{BTreeMap, HashMap};
 use std::path::{Path, PathBuf};
 
 pub const WAIT_SAMPLE_INTERVAL_SECS: u64 = 60;
 
+// Settings that are lists, split on commas when given by an environment variable
+const LIST_KEYS: &[&str] = &[
+    "multicast_endpoints",
+    "deny_sources",
+    "ipn_2_element",
+    "echo_endpoints",
+    "retransmit_sources",
+    "egress_transforms",
+    "egress_dry_run",
+    "domain_endpoints",
+    "boundary_clas",
+    "bpv6_clas",
+    "priority.bulk",
+    "priority.expedited",
+    "localdisk.shard_dirs",
+];
+
 fn options() -> getopts::Options {
     let mut opts = getopts::Options::new();
     opts.optflag("h", "help", "print this help menu")
@@ -12,7 +30,30 @@ fn options() -> getopts::Options {
             "upgrade-store",
             "upgrade the bundle store to the current format",
         )
-        .optopt("c", "config", "use a custom configuration file", "FILE");
+        .optflag(
+            "",
+            "rehash-store",
+            "recompute the hashes of stored bundles with the configured 'hash_algorithm', then exit",
+        )
+        .optopt("c", "config", "use a custom configuration file", "FILE")
+        .optopt(
+            "",
+            "replay",
+            "feed the bundles recorded in an ingress journal back in once started",
+            "FILE",
+        )
+        .optopt(
+            "",
+            "replay-speed",
+            "replay this many times faster than recorded, or 0 for as fast as possible, defaults to 1",
+            "SPEED",
+        )
+        .optmulti(
+            "s",
+            "set",
+            "override a configuration setting, taking precedence over the file and environment",
+            "KEY=VALUE",
+        );
     opts
 }
 
@@ -54,7 +95,62 @@ pub fn get_with_default<'de, T: serde::Deserialize<'de>, D: Into<T>>(
     }
 }
 
-pub fn init() -> Option<(config::Config, bool, String)> {
+// Keys and the like, given in hex
+pub fn decode_hex(s: &str) -> Option<Box<[u8]>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+// The effective settings of each module, as loaded, for reporting via the admin API
+static EFFECTIVE: std::sync::Mutex<BTreeMap<String, String>> =
+    std::sync::Mutex::new(BTreeMap::new());
+
+// Load the typed settings of a module from the layered configuration
+pub fn load<T: serde::de::DeserializeOwned + serde::Serialize>(
+    config: &config::Config,
+    module: &str,
+) -> T {
+    let settings = config.clone().try_deserialize::<T>().unwrap_or_else(|e| {
+        error!("Invalid {module} configuration: {e}");
+        panic!("Invalid {module} configuration: {e}");
+    });
+    record(&settings);
+    settings
+}
+
+fn record<T: serde::Serialize>(settings: &T) {
+    let Ok(values) = config::Config::try_from(settings)
+        .and_then(|c| c.try_deserialize::<HashMap<String, config::Value>>())
+    else {
+        return;
+    };
+    let mut effective = EFFECTIVE.lock().trace_expect("Failed to lock mutex");
+    for (key, value) in values {
+        effective.insert(key, value.to_string());
+    }
+}
+
+// The effective value of every typed setting, by key
+pub fn effective() -> BTreeMap<String, String> {
+    EFFECTIVE
+        .lock()
+        .trace_expect("Failed to lock mutex")
+        .clone()
+}
+
+pub struct Flags {
+    pub upgrade: bool,
+    pub rehash: bool,
+    // The journal to replay, and whether to keep its original pace
+    pub replay: Option<(PathBuf, bool)>,
+}
+
+pub fn init() -> Option<(config::Config, Flags, String)> {
     // Parse cmdline
     let opts = options();
     let args: Vec<String> = std::env::args().collect();
@@ -102,13 +198,77 @@ pub fn init() -> Option<(config::Config, bool, String)> {
         )
     }
 
-    // Pull in environment vars
-    b = b.add_source(config::Environment::with_prefix("HARDY_BPA"));
+    // Pull in environment vars, with '__' separating nested keys and ',' separating the items
+    // of lists, e.g. HARDY_BPA_PRIORITY__BULK=ipn:*.7,ipn:*.8
+    b = b.add_source(
+        LIST_KEYS.iter().fold(
+            config::Environment::with_prefix("HARDY_BPA")
+                .prefix_separator("_")
+                .separator("__")
+                .try_parsing(true)
+                .list_separator(","),
+            |env, key| env.with_list_parse_key(key),
+        ),
+    );
+
+    // And finally the command line
+    for setting in flags.opt_strs("set") {
+        let Some((key, value)) = setting.split_once('=') else {
+            eprintln!("Invalid --set '{setting}', expected KEY=VALUE");
+            return None;
+        };
+        b = b
+            .set_override(key.trim(), value.trim())
+            .expect("Failed to apply command line setting");
+    }
+
+    // Replay in virtual time starting from the first arrival, so bundles see the clock they did
+    let mut replay = None;
+    if let Some(path) = flags.opt_str("replay") {
+        let speed = match flags.opt_str("replay-speed").map(|s| s.parse::<f64>()) {
+            None => 1.0,
+            Some(Ok(speed)) if speed >= 0.0 => speed,
+            Some(_) => {
+                eprintln!("Invalid --replay-speed, expected a number of at least 0");
+                return None;
+            }
+        };
+        let first = match std::fs::File::open(&path)
+            .map_err(Error::from)
+            .and_then(|file| journal::Reader::new(std::io::BufReader::new(file)))
+            .and_then(|mut reader| reader.next_record())
+        {
+            Ok(first) => first,
+            Err(e) => {
+                eprintln!("Failed to read ingress journal '{path}': {e}");
+                return None;
+            }
+        };
+        if let Some(first) = first {
+            let start_time = first
+                .received_at
+                .format(&time::format_description::well_known::Rfc3339)
+                .expect("Failed to format replay start time");
+            b = b
+                .set_override("simulation.start_time", start_time)
+                .expect("Failed to apply replay start time");
+            if speed > 0.0 {
+                b = b
+                    .set_override("simulation.time_scale", speed)
+                    .expect("Failed to apply replay speed");
+            }
+        }
+        replay = Some((PathBuf::from(path), speed > 0.0));
+    }
 
     // And parse...
     Some((
         b.build().expect("Failed to build configuration"),
-        flags.opt_present("u"),
+        Flags {
+            upgrade: flags.opt_present("u"),
+            rehash: flags.opt_present("rehash-store"),
+            replay,
+        },
         config_source,
     ))
 }
@@ -0,0 +1,335 @@
+use super::*;
+use sdnv::Reader;
+
+/* A bundle as described by RFC 5050.  Endpoints are held as the scheme name and
+ * scheme-specific part they are stored as in the primary block dictionary, so that
+ * endpoints of any scheme survive parsing and emitting unchanged.  Blocks other than the
+ * payload are kept as raw data: this crate exists to bridge to BPv7, not to process them */
+
+// Bundle processing control flags, RFC 5050 section 4.2
+pub const FLAG_FRAGMENT: u64 = 1 << 0;
+pub const FLAG_ADMIN_RECORD: u64 = 1 << 1;
+pub const FLAG_DO_NOT_FRAGMENT: u64 = 1 << 2;
+pub const FLAG_CUSTODY_REQUESTED: u64 = 1 << 3;
+pub const FLAG_SINGLETON: u64 = 1 << 4;
+pub const FLAG_APP_ACK_REQUESTED: u64 = 1 << 5;
+pub const FLAG_RECEIPT_REPORT: u64 = 1 << 14;
+pub const FLAG_CUSTODY_REPORT: u64 = 1 << 15;
+pub const FLAG_FORWARD_REPORT: u64 = 1 << 16;
+pub const FLAG_DELIVERY_REPORT: u64 = 1 << 17;
+pub const FLAG_DELETE_REPORT: u64 = 1 << 18;
+
+// Block processing control flags, RFC 5050 section 4.3
+pub const BLOCK_FLAG_REPLICATE: u64 = 1 << 0;
+pub const BLOCK_FLAG_REPORT_ON_FAILURE: u64 = 1 << 1;
+pub const BLOCK_FLAG_DELETE_BUNDLE_ON_FAILURE: u64 = 1 << 2;
+pub const BLOCK_FLAG_LAST_BLOCK: u64 = 1 << 3;
+pub const BLOCK_FLAG_DISCARD_ON_FAILURE: u64 = 1 << 4;
+pub const BLOCK_FLAG_FORWARDED_UNPROCESSED: u64 = 1 << 5;
+pub const BLOCK_FLAG_EID_REFERENCES: u64 = 1 << 6;
+
+pub const BLOCK_TYPE_PAYLOAD: u8 = 1;
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Endpoint {
+    pub scheme: String,
+    pub ssp: String,
+}
+
+impl Endpoint {
+    pub fn none() -> Self {
+        Self {
+            scheme: "dtn".to_string(),
+            ssp: "none".to_string(),
+        }
+    }
+}
+
+impl std::fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.scheme, self.ssp)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Block {
+    pub block_type: u8,
+    pub flags: u64,
+    pub eid_references: Vec<Endpoint>,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bundle {
+    pub flags: u64,
+    pub destination: Endpoint,
+    pub source: Endpoint,
+    pub report_to: Endpoint,
+    pub custodian: Endpoint,
+    // Seconds since the DTN epoch, 2000-01-01 00:00:00 UTC
+    pub creation_time: u64,
+    pub sequence_number: u64,
+    // Seconds
+    pub lifetime: u64,
+    pub fragment: Option<(u64, u64)>,
+    // The payload is the block of type BLOCK_TYPE_PAYLOAD
+    pub blocks: Vec<Block>,
+}
+
+fn lookup(dictionary: &[u8], scheme: u64, ssp: u64) -> Result<Endpoint, Error> {
+    let string = |offset: u64| {
+        let s = usize::try_from(offset)
+            .ok()
+            .and_then(|offset| dictionary.get(offset..))
+            .ok_or(Error::InvalidDictionaryOffset(offset))?;
+        let s = s
+            .split(|b| *b == 0)
+            .next()
+            .filter(|_| s.contains(&0))
+            .ok_or(Error::InvalidDictionaryOffset(offset))?;
+        std::str::from_utf8(s)
+            .map(str::to_string)
+            .map_err(|_| Error::InvalidDictionaryString)
+    };
+    Ok(Endpoint {
+        scheme: string(scheme)?,
+        ssp: string(ssp)?,
+    })
+}
+
+// Builds the dictionary as endpoints are added, sharing strings that repeat
+#[derive(Default)]
+struct Dictionary {
+    data: Vec<u8>,
+    offsets: std::collections::HashMap<String, u64>,
+}
+
+impl Dictionary {
+    fn offset(&mut self, s: &str) -> u64 {
+        if let Some(offset) = self.offsets.get(s) {
+            return *offset;
+        }
+        let offset = self.data.len() as u64;
+        self.data.extend_from_slice(s.as_bytes());
+        self.data.push(0);
+        self.offsets.insert(s.to_string(), offset);
+        offset
+    }
+
+    fn emit(&mut self, buf: &mut Vec<u8>, eid: &Endpoint) {
+        let scheme = self.offset(&eid.scheme);
+        let ssp = self.offset(&eid.ssp);
+        sdnv::emit(buf, scheme);
+        sdnv::emit(buf, ssp);
+    }
+}
+
+impl Bundle {
+    pub fn payload(&self) -> Option<&[u8]> {
+        self.blocks
+            .iter()
+            .find(|block| block.block_type == BLOCK_TYPE_PAYLOAD)
+            .map(|block| block.data.as_slice())
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Self, Error> {
+        let mut r = Reader::new(data);
+
+        // Primary block, RFC 5050 section 4.5.1
+        let version = r.byte()?;
+        if version != VERSION {
+            return Err(Error::InvalidVersion(version));
+        }
+        let flags = r.sdnv()?;
+        let block_len = r.sdnv()?;
+        let mut p = Reader::new(r.bytes(block_len)?);
+
+        let mut offsets = [0u64; 8];
+        for offset in &mut offsets {
+            *offset = p.sdnv()?;
+        }
+        let creation_time = p.sdnv()?;
+        let sequence_number = p.sdnv()?;
+        let lifetime = p.sdnv()?;
+        let dictionary_len = p.sdnv()?;
+        let dictionary = p.bytes(dictionary_len)?;
+        let fragment = if flags & FLAG_FRAGMENT != 0 {
+            Some((p.sdnv()?, p.sdnv()?))
+        } else {
+            None
+        };
+        if !p.is_empty() {
+            return Err(Error::AdditionalData);
+        }
+
+        let mut bundle = Self {
+            flags,
+            destination: lookup(dictionary, offsets[0], offsets[1])?,
+            source: lookup(dictionary, offsets[2], offsets[3])?,
+            report_to: lookup(dictionary, offsets[4], offsets[5])?,
+            custodian: lookup(dictionary, offsets[6], offsets[7])?,
+            creation_time,
+            sequence_number,
+            lifetime,
+            fragment,
+            blocks: Vec::new(),
+        };
+
+        // Canonical blocks, RFC 5050 section 4.5.2
+        loop {
+            let block_type = r.byte()?;
+            let flags = r.sdnv()?;
+            let mut eid_references = Vec::new();
+            if flags & BLOCK_FLAG_EID_REFERENCES != 0 {
+                for _ in 0..r.sdnv()? {
+                    let (scheme, ssp) = (r.sdnv()?, r.sdnv()?);
+                    eid_references.push(lookup(dictionary, scheme, ssp)?);
+                }
+            }
+            let len = r.sdnv()?;
+            bundle.blocks.push(Block {
+                block_type,
+                flags,
+                eid_references,
+                data: r.bytes(len)?.to_vec(),
+            });
+            if flags & BLOCK_FLAG_LAST_BLOCK != 0 {
+                break;
+            }
+        }
+        if !r.is_empty() {
+            return Err(Error::AdditionalData);
+        }
+        if bundle.payload().is_none() {
+            return Err(Error::MissingPayload);
+        }
+        Ok(bundle)
+    }
+
+    // The last block is flagged as such, whatever the flags of the blocks say
+    pub fn emit(&self) -> Vec<u8> {
+        let mut dictionary = Dictionary::default();
+
+        let mut primary = Vec::new();
+        dictionary.emit(&mut primary, &self.destination);
+        dictionary.emit(&mut primary, &self.source);
+        dictionary.emit(&mut primary, &self.report_to);
+        dictionary.emit(&mut primary, &self.custodian);
+
+        // Block EID references share the primary block dictionary
+        let mut blocks = Vec::new();
+        for (i, block) in self.blocks.iter().enumerate() {
+            let mut flags = block.flags & !(BLOCK_FLAG_LAST_BLOCK | BLOCK_FLAG_EID_REFERENCES);
+            if i == self.blocks.len() - 1 {
+                flags |= BLOCK_FLAG_LAST_BLOCK;
+            }
+            if !block.eid_references.is_empty() {
+                flags |= BLOCK_FLAG_EID_REFERENCES;
+            }
+            blocks.push(block.block_type);
+            sdnv::emit(&mut blocks, flags);
+            if !block.eid_references.is_empty() {
+                sdnv::emit(&mut blocks, block.eid_references.len() as u64);
+                for eid in &block.eid_references {
+                    dictionary.emit(&mut blocks, eid);
+                }
+            }
+            sdnv::emit(&mut blocks, block.data.len() as u64);
+            blocks.extend_from_slice(&block.data);
+        }
+
+        sdnv::emit(&mut primary, self.creation_time);
+        sdnv::emit(&mut primary, self.sequence_number);
+        sdnv::emit(&mut primary, self.lifetime);
+        sdnv::emit(&mut primary, dictionary.data.len() as u64);
+        primary.extend_from_slice(&dictionary.data);
+        let mut flags = self.flags & !FLAG_FRAGMENT;
+        if let Some((offset, total_len)) = self.fragment {
+            flags |= FLAG_FRAGMENT;
+            sdnv::emit(&mut primary, offset);
+            sdnv::emit(&mut primary, total_len);
+        }
+
+        let mut data = vec![VERSION];
+        sdnv::emit(&mut data, flags);
+        sdnv::emit(&mut data, primary.len() as u64);
+        data.extend_from_slice(&primary);
+        data.extend_from_slice(&blocks);
+        data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eid(s: &str) -> Endpoint {
+        let (scheme, ssp) = s.split_once(':').unwrap();
+        Endpoint {
+            scheme: scheme.to_string(),
+            ssp: ssp.to_string(),
+        }
+    }
+
+    #[test]
+    fn roundtrip() {
+        let bundle = Bundle {
+            flags: FLAG_SINGLETON | FLAG_DELIVERY_REPORT,
+            destination: eid("ipn:2.1"),
+            source: eid("ipn:1.1"),
+            report_to: eid("ipn:1.0"),
+            custodian: Endpoint::none(),
+            creation_time: 1000,
+            sequence_number: 7,
+            lifetime: 3600,
+            fragment: None,
+            blocks: vec![
+                Block {
+                    block_type: 20,
+                    flags: BLOCK_FLAG_DISCARD_ON_FAILURE,
+                    eid_references: vec![eid("ipn:1.1")],
+                    data: vec![1, 2, 3],
+                },
+                Block {
+                    block_type: BLOCK_TYPE_PAYLOAD,
+                    flags: BLOCK_FLAG_LAST_BLOCK,
+                    eid_references: Vec::new(),
+                    data: b"Hello".to_vec(),
+                },
+            ],
+        };
+        let data = bundle.emit();
+        assert_eq!(data[0], VERSION);
+        assert_eq!(Bundle::parse(&data).unwrap(), {
+            let mut bundle = bundle;
+            bundle.blocks[0].flags |= BLOCK_FLAG_EID_REFERENCES;
+            bundle
+        });
+    }
+
+    #[test]
+    fn truncated() {
+        let bundle = Bundle {
+            flags: 0,
+            destination: eid("dtn://b/"),
+            source: eid("dtn://a/"),
+            report_to: Endpoint::none(),
+            custodian: Endpoint::none(),
+            creation_time: 1,
+            sequence_number: 0,
+            lifetime: 60,
+            fragment: None,
+            blocks: vec![Block {
+                block_type: BLOCK_TYPE_PAYLOAD,
+                flags: 0,
+                eid_references: Vec::new(),
+                data: b"Hello".to_vec(),
+            }],
+        };
+        let data = bundle.emit();
+        assert!(matches!(
+            Bundle::parse(&data[..data.len() - 1]),
+            Err(Error::Truncated)
+        ));
+    }
+}
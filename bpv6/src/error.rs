@@ -0,0 +1,49 @@
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Bundle data is truncated")]
+    Truncated,
+
+    #[error("SDNV value is too large")]
+    SdnvOverflow,
+
+    #[error("Unsupported bundle protocol version {0}")]
+    InvalidVersion(u8),
+
+    #[error("Dictionary offset {0} is out of bounds")]
+    InvalidDictionaryOffset(u64),
+
+    #[error("Dictionary string is not valid UTF-8")]
+    InvalidDictionaryString,
+
+    #[error("Bundle has no payload block")]
+    MissingPayload,
+
+    #[error("Bundle has additional data after the last block")]
+    AdditionalData,
+
+    #[error("Bundle has no creation time")]
+    MissingCreationTime,
+
+    #[error("Fragmented bundles cannot be translated")]
+    Fragment,
+
+    #[error("Administrative records cannot be translated")]
+    AdminRecord,
+
+    #[error("Block type {0} must be processed, but cannot be translated")]
+    Untranslatable(u64),
+
+    #[error("Endpoint {0} cannot be expressed in BPv6")]
+    UnsupportedEid(String),
+
+    #[error("Payload is encrypted")]
+    EncryptedPayload,
+
+    #[error(transparent)]
+    InvalidEid(#[from] hardy_bpv7::prelude::EidError),
+
+    #[error(transparent)]
+    InvalidBpv7(#[from] hardy_bpv7::prelude::Error),
+}
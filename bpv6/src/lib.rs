@@ -0,0 +1,13 @@
+use hardy_bpv7::prelude as bpv7;
+
+mod bundle;
+mod error;
+mod sdnv;
+mod translate;
+
+pub use bundle::*;
+pub use error::Error;
+pub use translate::{from_bpv7, to_bpv7};
+
+// The first byte of every BPv6 bundle, the version of its primary block
+pub const VERSION: u8 = 0x06;
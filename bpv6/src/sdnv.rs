@@ -0,0 +1,97 @@
+use super::*;
+
+// Self-Delimiting Numeric Values, RFC 6256
+
+pub fn emit(buf: &mut Vec<u8>, mut v: u64) {
+    let mut tmp = [0u8; 10];
+    let mut i = tmp.len() - 1;
+    tmp[i] = (v & 0x7F) as u8;
+    v >>= 7;
+    while v != 0 {
+        i -= 1;
+        tmp[i] = (v & 0x7F) as u8 | 0x80;
+        v >>= 7;
+    }
+    buf.extend_from_slice(&tmp[i..]);
+}
+
+pub struct Reader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, offset: 0 }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.offset == self.data.len()
+    }
+
+    pub fn byte(&mut self) -> Result<u8, Error> {
+        let b = *self.data.get(self.offset).ok_or(Error::Truncated)?;
+        self.offset += 1;
+        Ok(b)
+    }
+
+    pub fn bytes(&mut self, len: u64) -> Result<&'a [u8], Error> {
+        let end = usize::try_from(len)
+            .ok()
+            .and_then(|len| self.offset.checked_add(len))
+            .filter(|end| *end <= self.data.len())
+            .ok_or(Error::Truncated)?;
+        let b = &self.data[self.offset..end];
+        self.offset = end;
+        Ok(b)
+    }
+
+    pub fn sdnv(&mut self) -> Result<u64, Error> {
+        let mut v = 0u64;
+        loop {
+            let b = self.byte()?;
+            if v > (u64::MAX >> 7) {
+                return Err(Error::SdnvOverflow);
+            }
+            v = (v << 7) | (b & 0x7F) as u64;
+            if b & 0x80 == 0 {
+                return Ok(v);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        for v in [0, 1, 0x7F, 0x80, 0x3FFF, 0x4000, u32::MAX as u64, u64::MAX] {
+            let mut buf = Vec::new();
+            emit(&mut buf, v);
+            let mut r = Reader::new(&buf);
+            assert_eq!(r.sdnv().unwrap(), v);
+            assert!(r.is_empty());
+        }
+    }
+
+    #[test]
+    fn rfc6256_examples() {
+        let mut buf = Vec::new();
+        emit(&mut buf, 0xABC);
+        assert_eq!(buf, [0x95, 0x3C]);
+
+        let mut buf = Vec::new();
+        emit(&mut buf, 0x1234);
+        assert_eq!(buf, [0xA4, 0x34]);
+    }
+
+    #[test]
+    fn overflow() {
+        assert!(matches!(
+            Reader::new(&[0xFF; 11]).sdnv(),
+            Err(Error::SdnvOverflow)
+        ));
+    }
+}
@@ -0,0 +1,222 @@
+use super::*;
+use bundle::*;
+
+/* Translation keeps what the two protocols have in common: the endpoints, creation
+ * timestamp, lifetime, the status report and acknowledgement flags, and the payload.
+ * Extension blocks are specific to each protocol and are dropped, unless they are flagged
+ * to delete the bundle if they cannot be processed, in which case the bundle cannot be
+ * translated.  BPv6 custody transfer has no BPv7 equivalent, so the custodian is dropped
+ * and bundles leave for BPv6 with none.  Fragments and administrative records are not
+ * translated, as their formats differ between the protocols */
+
+// The bundle processing flags that have the same meaning in both protocols
+const COMMON_FLAGS: u64 = FLAG_DO_NOT_FRAGMENT
+    | FLAG_APP_ACK_REQUESTED
+    | FLAG_RECEIPT_REPORT
+    | FLAG_FORWARD_REPORT
+    | FLAG_DELIVERY_REPORT
+    | FLAG_DELETE_REPORT;
+
+// And the block processing flags
+const COMMON_BLOCK_FLAGS: u64 = BLOCK_FLAG_REPLICATE
+    | BLOCK_FLAG_REPORT_ON_FAILURE
+    | BLOCK_FLAG_DELETE_BUNDLE_ON_FAILURE
+    | BLOCK_FLAG_DISCARD_ON_FAILURE;
+
+fn eid_to_bpv7(eid: &Endpoint) -> Result<bpv7::Eid, Error> {
+    Ok(eid.to_string().parse()?)
+}
+
+fn eid_from_bpv7(eid: &bpv7::Eid) -> Result<Endpoint, Error> {
+    match eid {
+        bpv7::Eid::Null => Ok(Endpoint::none()),
+        bpv7::Eid::LegacyIpn {
+            allocator_id: 0, ..
+        }
+        | bpv7::Eid::Ipn {
+            allocator_id: 0, ..
+        }
+        | bpv7::Eid::Dtn { .. } => {
+            let s = eid.to_string();
+            let (scheme, ssp) = s
+                .split_once(':')
+                .ok_or_else(|| Error::UnsupportedEid(s.clone()))?;
+            Ok(Endpoint {
+                scheme: scheme.to_string(),
+                ssp: ssp.to_string(),
+            })
+        }
+        _ => Err(Error::UnsupportedEid(eid.to_string())),
+    }
+}
+
+// Translate an RFC 5050 bundle into the bytes of an equivalent BPv7 bundle
+pub fn to_bpv7(data: &[u8]) -> Result<Vec<u8>, Error> {
+    let bundle = Bundle::parse(data)?;
+    if bundle.fragment.is_some() {
+        return Err(Error::Fragment);
+    }
+    if bundle.flags & FLAG_ADMIN_RECORD != 0 {
+        return Err(Error::AdminRecord);
+    }
+    if bundle.creation_time == 0 {
+        return Err(Error::MissingCreationTime);
+    }
+
+    let mut payload = None;
+    for block in &bundle.blocks {
+        if block.block_type == BLOCK_TYPE_PAYLOAD {
+            payload = Some(block);
+        } else if block.flags & BLOCK_FLAG_DELETE_BUNDLE_ON_FAILURE != 0 {
+            return Err(Error::Untranslatable(block.block_type as u64));
+        }
+    }
+    let payload = payload.ok_or(Error::MissingPayload)?;
+    let block_flags = payload.flags & COMMON_BLOCK_FLAGS;
+
+    let mut builder = bpv7::Builder::new()
+        .flags(bpv7::BundleFlags::from(bundle.flags & COMMON_FLAGS))
+        .source(eid_to_bpv7(&bundle.source)?)
+        .destination(eid_to_bpv7(&bundle.destination)?)
+        .lifetime(bundle.lifetime.saturating_mul(1000))
+        .creation_timestamp(bpv7::CreationTimestamp {
+            creation_time: Some(bpv7::DtnTime::new(
+                bundle.creation_time.saturating_mul(1000),
+            )),
+            sequence_number: bundle.sequence_number,
+        });
+    if bundle.report_to != Endpoint::none() {
+        builder = builder.report_to(eid_to_bpv7(&bundle.report_to)?);
+    }
+    Ok(builder
+        .add_extension_block(bpv7::BlockType::Payload)
+        .must_replicate(block_flags & BLOCK_FLAG_REPLICATE != 0)
+        .report_on_failure(block_flags & BLOCK_FLAG_REPORT_ON_FAILURE != 0)
+        .delete_bundle_on_failure(block_flags & BLOCK_FLAG_DELETE_BUNDLE_ON_FAILURE != 0)
+        .delete_block_on_failure(block_flags & BLOCK_FLAG_DISCARD_ON_FAILURE != 0)
+        .data(payload.data.clone())
+        .build()
+        .build()
+        .1)
+}
+
+// Translate a parsed BPv7 bundle, with its data, into the bytes of an RFC 5050 bundle
+pub fn from_bpv7(bundle: &bpv7::Bundle, data: &[u8]) -> Result<Vec<u8>, Error> {
+    if bundle.id.fragment_info.is_some() {
+        return Err(Error::Fragment);
+    }
+    if bundle.flags.is_admin_record {
+        return Err(Error::AdminRecord);
+    }
+    let creation_time = bundle
+        .id
+        .timestamp
+        .creation_time
+        .ok_or(Error::MissingCreationTime)?;
+
+    let mut payload = None;
+    for block in bundle.blocks.values() {
+        match block.block_type {
+            bpv7::BlockType::Payload => payload = Some(block),
+            bpv7::BlockType::Unrecognised(block_type) if block.flags.delete_bundle_on_failure => {
+                return Err(Error::Untranslatable(block_type))
+            }
+            _ => {}
+        }
+    }
+    let payload = payload.ok_or(Error::MissingPayload)?;
+    if payload.bcb.is_some() {
+        return Err(Error::EncryptedPayload);
+    }
+
+    Ok(Bundle {
+        flags: (u64::from(&bundle.flags) & COMMON_FLAGS) | FLAG_SINGLETON,
+        destination: eid_from_bpv7(&bundle.destination)?,
+        source: eid_from_bpv7(&bundle.id.source)?,
+        report_to: eid_from_bpv7(&bundle.report_to)?,
+        custodian: Endpoint::none(),
+        creation_time: creation_time.millisecs() / 1000,
+        sequence_number: bundle.id.timestamp.sequence_number,
+        lifetime: bundle.lifetime.div_ceil(1000),
+        fragment: None,
+        blocks: vec![Block {
+            block_type: BLOCK_TYPE_PAYLOAD,
+            flags: u64::from(&payload.flags) & COMMON_BLOCK_FLAGS,
+            eid_references: Vec::new(),
+            data: payload
+                .block_data(data)
+                .map_err(bpv7::Error::from)?
+                .into_vec(),
+        }],
+    }
+    .emit())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(data: &[u8]) -> bpv7::Bundle {
+        let bpv7::ValidBundle::Valid(bundle, _) =
+            bpv7::ValidBundle::parse(data, |_, _| Ok(None)).unwrap()
+        else {
+            panic!("Bundle is not valid");
+        };
+        bundle
+    }
+
+    #[test]
+    fn roundtrip() {
+        let (_, data) = bpv7::Builder::new()
+            .source("ipn:1.1".parse().unwrap())
+            .destination("dtn://node/service".parse().unwrap())
+            .report_to("ipn:1.0".parse().unwrap())
+            .lifetime(60_000)
+            .creation_timestamp(bpv7::CreationTimestamp {
+                creation_time: Some(bpv7::DtnTime::new(123_000)),
+                sequence_number: 42,
+            })
+            .add_payload_block(b"Hello".to_vec())
+            .build();
+        let bundle = parse(&data);
+
+        let v6 = from_bpv7(&bundle, &data).unwrap();
+        assert_eq!(v6[0], VERSION);
+        let parsed = Bundle::parse(&v6).unwrap();
+        assert_eq!(parsed.source.to_string(), "ipn:1.1");
+        assert_eq!(parsed.destination.to_string(), "dtn://node/service");
+        assert_eq!(parsed.creation_time, 123);
+        assert_eq!(parsed.lifetime, 60);
+        assert_eq!(parsed.payload(), Some(b"Hello".as_slice()));
+
+        let v7 = to_bpv7(&v6).unwrap();
+        let translated = parse(&v7);
+        assert_eq!(translated.id, bundle.id);
+        assert_eq!(translated.destination, bundle.destination);
+        assert_eq!(translated.report_to, bundle.report_to);
+        assert_eq!(translated.lifetime, bundle.lifetime);
+        assert_eq!(
+            translated
+                .blocks
+                .get(&1)
+                .unwrap()
+                .block_data(&v7)
+                .unwrap()
+                .as_ref(),
+            b"Hello"
+        );
+    }
+
+    #[test]
+    fn local_node() {
+        let (_, data) = bpv7::Builder::new()
+            .source("ipn:!.1".parse().unwrap())
+            .destination("ipn:2.1".parse().unwrap())
+            .add_payload_block(b"Hello".to_vec())
+            .build();
+        assert!(matches!(
+            from_bpv7(&parse(&data), &data),
+            Err(Error::UnsupportedEid(_))
+        ));
+    }
+}
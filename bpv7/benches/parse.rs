@@ -0,0 +1,30 @@
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use hardy_bpv7::prelude::*;
+
+fn build_bundle(payload_len: usize, crc_type: CrcType) -> Vec<u8> {
+    Builder::new()
+        .crc_type(crc_type)
+        .source("ipn:1.1".parse().unwrap())
+        .destination("ipn:2.1".parse().unwrap())
+        .add_extension_block(BlockType::HopCount)
+        .data(vec![0x82, 0x18, 0x20, 0x00])
+        .build()
+        .add_payload_block(vec![0x5a; payload_len])
+        .build()
+        .1
+}
+
+fn parse(c: &mut Criterion) {
+    let mut group = c.benchmark_group("ValidBundle::parse");
+    for mb in [1, 4, 16] {
+        let data = build_bundle(mb * 1024 * 1024, CrcType::CRC32_CASTAGNOLI);
+        group.throughput(Throughput::Bytes(data.len() as u64));
+        group.bench_function(format!("{mb}MiB"), |b| {
+            b.iter(|| ValidBundle::parse(&data, |_, _| Ok(None)).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, parse);
+criterion_main!(benches);
@@ -0,0 +1,170 @@
+#![no_main]
+
+use hardy_bpv7::prelude::*;
+use libfuzzer_sys::fuzz_target;
+use std::collections::HashMap;
+
+fn get_keys(
+    source: &Eid,
+    context: bpsec::Context,
+) -> Result<Option<bpsec::KeyMaterial>, bpsec::Error> {
+    let keys: &[(EidPattern, bpsec::Context, &'static [u8])] = &[
+        (
+            "ipn:3.0".parse().unwrap(),
+            bpsec::Context::BIB_HMAC_SHA2,
+            &hex_literal::hex!("1a2b1a2b1a2b1a2b1a2b1a2b1a2b1a2b"),
+        ),
+        (
+            "ipn:2.1".parse().unwrap(),
+            bpsec::Context::BCB_AES_GCM,
+            &hex_literal::hex!("71776572747975696f70617364666768"),
+        ),
+    ];
+
+    for (eid, c2, key) in keys {
+        if &context == c2 && eid.is_match(source) {
+            return Ok(Some(bpsec::KeyMaterial::SymmetricKey(Box::from(*key))));
+        }
+    }
+    Ok(None)
+}
+
+fn crc_type(v: u8) -> CrcType {
+    match v % 3 {
+        0 => CrcType::None,
+        1 => CrcType::CRC16_X25,
+        _ => CrcType::CRC32_CASTAGNOLI,
+    }
+}
+
+/* The input is a big-endian u16 length, that many bytes of bundle, and then a script of
+ * edits.  Only private-use blocks added by the script are replaced or removed, and CRC types
+ * are only changed in bundles without BPSec blocks, as anything else may legitimately
+ * invalidate the bundle */
+fuzz_target!(|data: &[u8]| {
+    let Some((len, data)) = data.split_first_chunk::<2>() else {
+        return;
+    };
+    let len = u16::from_be_bytes(*len) as usize;
+    if len > data.len() {
+        return;
+    }
+    let (data, mut script) = data.split_at(len);
+
+    // Start from the canonical form of the bundle
+    let data = match ValidBundle::parse(data, get_keys) {
+        Ok(ValidBundle::Valid(..)) => Box::from(data),
+        Ok(ValidBundle::Rewritten(_, data, _)) => data,
+        _ => return,
+    };
+    let Ok(ValidBundle::Valid(bundle, _)) = ValidBundle::parse(&data, get_keys) else {
+        return;
+    };
+
+    let has_bpsec = bundle.blocks.values().any(|block| {
+        matches!(
+            block.block_type,
+            BlockType::BlockIntegrity | BlockType::BlockSecurity
+        )
+    });
+
+    // The blocks added by the script, by block number
+    let mut added = HashMap::new();
+    let mut editor = Editor::new(&bundle, &data);
+    while let Some(([op, arg], rest)) = script.split_first_chunk::<2>() {
+        script = rest;
+        match op % 4 {
+            0 | 1 => {
+                let block_type = BlockType::Unrecognised(192 + (*arg as u64 % 64));
+                if bundle
+                    .blocks
+                    .values()
+                    .any(|block| block.block_type == block_type)
+                    || (op % 4 == 0 && added.values().any(|(t, _)| *t == block_type))
+                {
+                    continue;
+                }
+                let (block_data, rest) = script
+                    .split_first()
+                    .map(|(n, rest)| rest.split_at((*n as usize).min(rest.len())))
+                    .unwrap_or_default();
+                script = rest;
+
+                if op % 4 == 0 {
+                    // Mirror the Editor's choice of the lowest unused block number
+                    let block_number = (2..)
+                        .find(|n| !bundle.blocks.contains_key(n) && !added.contains_key(n))
+                        .unwrap();
+                    added.insert(block_number, (block_type, block_data.to_vec()));
+                    editor = editor
+                        .add_extension_block(block_type)
+                        .must_replicate(op & 0x10 != 0)
+                        .report_on_failure(op & 0x20 != 0)
+                        .crc_type(crc_type(op >> 6))
+                        .data(block_data.to_vec())
+                        .build();
+                } else if let Some((_, (_, d))) =
+                    added.iter_mut().find(|(_, (t, _))| *t == block_type)
+                {
+                    *d = block_data.to_vec();
+                    editor = editor
+                        .replace_extension_block(block_type)
+                        .data(block_data.to_vec())
+                        .build();
+                }
+            }
+            2 => {
+                let Some(block_number) = added.keys().min_by_key(|n| **n ^ *arg as u64).copied()
+                else {
+                    continue;
+                };
+                added.remove(&block_number);
+                editor = editor.remove_extension_block(block_number);
+            }
+            _ => {
+                if !has_bpsec {
+                    // Never drop the CRC of the primary block, which would need a BIB instead
+                    let crc_type = match arg % 2 {
+                        0 => CrcType::CRC16_X25,
+                        _ => CrcType::CRC32_CASTAGNOLI,
+                    };
+                    editor = editor.update_crc_types(|_| crc_type);
+                }
+            }
+        }
+    }
+    let new_data = editor.build();
+
+    let Ok(ValidBundle::Valid(new_bundle, _)) = ValidBundle::parse(&new_data, get_keys) else {
+        panic!("Edit borked");
+    };
+
+    // Every block must have survived the edit, with its data intact
+    for (block_number, block) in &bundle.blocks {
+        let Some(new_block) = new_bundle.blocks.get(block_number) else {
+            panic!("Block {block_number} lost");
+        };
+        assert_eq!(new_block.block_type, block.block_type);
+        if *block_number != 0 && !has_bpsec {
+            assert_eq!(new_block.payload(&new_data), block.payload(&data));
+        }
+    }
+    for (block_number, (block_type, block_data)) in &added {
+        let Some(new_block) = new_bundle.blocks.get(block_number) else {
+            panic!("Added block {block_number} lost");
+        };
+        assert_eq!(new_block.block_type, *block_type);
+        assert_eq!(
+            new_block.block_data(&new_data).unwrap().as_ref(),
+            block_data
+        );
+    }
+    assert_eq!(
+        new_bundle.blocks.len(),
+        bundle.blocks.len() + added.len(),
+        "Unexpected blocks"
+    );
+});
+
+// cargo cov -- export --format=lcov  -instr-profile ./fuzz/coverage/editor/coverage.profdata ./target/x86_64-unknown-linux-gnu/coverage/x86_64-unknown-linux-gnu/release/editor -ignore-filename-regex='/.cargo/|rustc/|/target/' > ./fuzz/coverage/editor/lcov.info
+// cargo cov -- show --format=html  -instr-profile ./fuzz/coverage/editor/coverage.profdata ./target/x86_64-unknown-linux-gnu/coverage/x86_64-unknown-linux-gnu/release/editor -o ./fuzz/coverage/editor/ -ignore-filename-regex='/.cargo/|rustc/|/target/'
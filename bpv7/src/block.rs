@@ -19,6 +19,19 @@ impl Block {
             ..self.data_start + self.payload_offset + self.payload_len]
     }
 
+    // The block-type-specific data, unwrapped from its byte string
+    pub fn block_data(&self, data: &[u8]) -> Result<Box<[u8]>, cbor::decode::Error> {
+        cbor::decode::parse_value(self.payload(data), |value, _, tags| match value {
+            cbor::decode::Value::Bytes(data) => Ok(data.into()),
+            cbor::decode::Value::ByteStream(data) => Ok(data.concat().into()),
+            value => Err(cbor::decode::Error::IncorrectType(
+                "Byte String".to_string(),
+                value.type_name(!tags.is_empty()),
+            )),
+        })
+        .map(|(v, _)| v)
+    }
+
     fn emit_inner(
         &mut self,
         block_number: u64,
@@ -42,6 +55,7 @@ impl Block {
                     // Payload
                     self.payload_offset = a.offset();
                     f(a);
+                    self.payload_len = a.offset() - self.payload_offset;
 
                     // CRC
                     if let CrcType::None = self.crc_type {
@@ -52,7 +66,7 @@ impl Block {
             ),
         );
         self.data_start = array.offset();
-        self.payload_len = block_data.len();
+        self.data_len = block_data.len();
         array.emit_raw(block_data)
     }
 
@@ -0,0 +1,159 @@
+use super::*;
+use std::{any::Any, collections::HashMap, sync::Arc};
+
+/* Applications that define their own extension blocks register a pair of callbacks for each
+ * block type code, one to encode a value as the block-type-specific data and one to decode
+ * it again.  The Builder encodes a value through the registry, and checks that the decoder
+ * accepts what the encoder produced before the block is added, so a bundle is never built
+ * with a block its own sender cannot read.  Only type codes the bpv7 crate does not
+ * handle itself may be registered */
+
+type Encoder<T> = dyn Fn(&T) -> Vec<u8> + Send + Sync;
+type Decoder<T> = dyn Fn(&[u8]) -> Result<T, Error> + Send + Sync;
+
+struct Codec<T> {
+    encode: Box<Encoder<T>>,
+    decode: Box<Decoder<T>>,
+}
+
+#[derive(Default, Clone)]
+pub struct BlockRegistry {
+    // Each is a Codec<T> for the value type T it was registered with
+    codecs: HashMap<u64, Arc<dyn Any + Send + Sync>>,
+}
+
+impl BlockRegistry {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn register<T: 'static>(
+        &mut self,
+        block_type: u64,
+        encode: impl Fn(&T) -> Vec<u8> + Send + Sync + 'static,
+        decode: impl Fn(&[u8]) -> Result<T, Error> + Send + Sync + 'static,
+    ) -> Result<(), Error> {
+        let BlockType::Unrecognised(block_type) = BlockType::from(block_type) else {
+            return Err(Error::ReservedBlockType(block_type.into()));
+        };
+        if self.codecs.contains_key(&block_type) {
+            return Err(Error::DuplicateBlockType(block_type));
+        }
+
+        self.codecs.insert(
+            block_type,
+            Arc::new(Codec {
+                encode: Box::new(encode),
+                decode: Box::new(decode),
+            }),
+        );
+        Ok(())
+    }
+
+    pub fn is_registered(&self, block_type: BlockType) -> bool {
+        self.codecs.contains_key(&block_type.into())
+    }
+
+    fn codec<T: 'static>(&self, block_type: u64) -> Result<&Codec<T>, Error> {
+        self.codecs
+            .get(&block_type)
+            .and_then(|codec| codec.downcast_ref())
+            .ok_or(Error::UnregisteredBlockType(block_type))
+    }
+
+    // Encode `value`, checking the decoder accepts the result
+    pub fn encode<T: 'static>(&self, block_type: u64, value: &T) -> Result<Vec<u8>, Error> {
+        let codec = self.codec::<T>(block_type)?;
+        let data = (codec.encode)(value);
+        (codec.decode)(&data)?;
+        Ok(data)
+    }
+
+    pub fn decode<T: 'static>(&self, block_type: u64, data: &[u8]) -> Result<T, Error> {
+        (self.codec::<T>(block_type)?.decode)(data)
+    }
+
+    // Decode the registered block of `block_type` in a parsed bundle, if it has one
+    pub fn decode_block<T: 'static>(
+        &self,
+        bundle: &Bundle,
+        block_type: u64,
+        source_data: &[u8],
+    ) -> Result<Option<T>, Error> {
+        let Some(block) = bundle
+            .blocks
+            .values()
+            .find(|block| block.block_type == BlockType::Unrecognised(block_type))
+        else {
+            return Ok(None);
+        };
+        self.decode(block_type, &block.block_data(source_data)?)
+            .map(Some)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_BLOCK_TYPE: u64 = 193;
+
+    fn registry() -> BlockRegistry {
+        let mut registry = BlockRegistry::new();
+        registry
+            .register::<u64>(
+                TEST_BLOCK_TYPE,
+                |value| cbor::encode::emit(*value),
+                |data| Ok(cbor::decode::parse(data)?),
+            )
+            .unwrap();
+        registry
+    }
+
+    #[test]
+    fn register() {
+        let mut registry = registry();
+        assert!(registry.is_registered(BlockType::Unrecognised(TEST_BLOCK_TYPE)));
+
+        // Standard block types and duplicates are refused
+        assert!(matches!(
+            registry.register::<u64>(10, |v| cbor::encode::emit(*v), |_| Ok(0)),
+            Err(Error::ReservedBlockType(BlockType::HopCount))
+        ));
+        assert!(matches!(
+            registry.register::<bool>(TEST_BLOCK_TYPE, |v| cbor::encode::emit(*v), |_| Ok(false)),
+            Err(Error::DuplicateBlockType(TEST_BLOCK_TYPE))
+        ));
+    }
+
+    #[test]
+    fn codec() {
+        let mut registry = registry();
+        let data = registry.encode(TEST_BLOCK_TYPE, &1234u64).unwrap();
+        assert_eq!(data, cbor::encode::emit(1234u64));
+        assert_eq!(
+            registry.decode::<u64>(TEST_BLOCK_TYPE, &data).unwrap(),
+            1234
+        );
+
+        // The wrong value type, or an unregistered block type
+        assert!(matches!(
+            registry.encode(TEST_BLOCK_TYPE, &true),
+            Err(Error::UnregisteredBlockType(TEST_BLOCK_TYPE))
+        ));
+        assert!(matches!(
+            registry.encode(200, &1234u64),
+            Err(Error::UnregisteredBlockType(200))
+        ));
+
+        // An encoder whose output its decoder rejects
+        registry
+            .register::<u64>(
+                200,
+                |v| cbor::encode::emit(*v),
+                |_| Err(Error::InvalidFlags),
+            )
+            .unwrap();
+        assert!(registry.encode(200, &7u64).is_err());
+    }
+}
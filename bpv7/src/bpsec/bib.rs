@@ -8,6 +8,16 @@ pub enum Operation {
     Unrecognised(u64, parse::UnknownOperation),
 }
 
+// What to do with a security target that fails integrity verification
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum FailurePolicy {
+    // Discard the whole bundle
+    #[default]
+    Drop,
+    // Remove the target block, unless the bundle cannot be processed without it
+    Strip,
+}
+
 pub struct OperationArgs<'a> {
     pub bpsec_source: &'a Eid,
     pub target: &'a block::Block,
@@ -133,6 +133,34 @@ mod test {
         )
     }
 
+    #[test]
+    fn bib_failure() {
+        // Appendix A.1 with the wrong key, where the payload target cannot be stripped
+        let data = hex_literal::hex!(
+            "9f89070001820282010282028202018202820201820118281a000f424042e4fe850b0200
+            005856810101018202820201828201078203008181820158403bdc69b3a34a2b5d3a
+            8554368bd1e808f606219d2a10a846eae3886ae4ecc83c4ee550fdfb1cc636b904e2
+            f1a73e303dcd4b6ccece003e95e8164dcc89a156e185010100005823526561647920
+            746f2067656e657261746520612033322d62797465207061796c6f6164ff"
+        );
+        for policy in [bib::FailurePolicy::Drop, bib::FailurePolicy::Strip] {
+            match ValidBundle::parse_with_policy(
+                &data,
+                |_, _| {
+                    Ok(Some(KeyMaterial::SymmetricKey(
+                        hex_literal::hex!("00000000000000000000000000000000").into(),
+                    )))
+                },
+                policy,
+            )
+            .expect("Failed to parse")
+            {
+                ValidBundle::Invalid(_, StatusReportReasonCode::FailedSecurityOperation, _) => {}
+                _ => panic!("Integrity check did not fail"),
+            }
+        }
+    }
+
     #[test]
     fn rfc9173_appendix_a_2() {
         do_test(
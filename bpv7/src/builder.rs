@@ -4,6 +4,14 @@ use super::*;
 const DEFAULT_CRC_TYPE: CrcType = CrcType::CRC32_CASTAGNOLI;
 const DEFAULT_LIFETIME: u64 = time::Duration::new(24 * 60 * 60, 0).whole_milliseconds() as u64;
 
+/* Given the same inputs, including the creation timestamp, the Builder emits exactly the
+ * same bytes: extension blocks are numbered and emitted in the order they are added,
+ * followed by the payload block, and every value has its shortest CBOR encoding.  Without
+ * a creation timestamp, the clock is read when the bundle is built.
+ *
+ * The Hop Count, Bundle Age and Previous Node blocks may only appear once, so adding one
+ * again replaces the earlier block in place, and the built Bundle carries their values just
+ * as a parsed one would.  Application-defined blocks are encoded through a BlockRegistry */
 pub struct Builder {
     bundle_flags: BundleFlags,
     crc_type: CrcType,
@@ -11,8 +19,12 @@ pub struct Builder {
     destination: Eid,
     report_to: Option<Eid>,
     lifetime: u64,
+    timestamp: Option<CreationTimestamp>,
     payload: BlockTemplate,
     extensions: Vec<BlockTemplate>,
+    previous_node: Option<Eid>,
+    age: Option<u64>,
+    hop_count: Option<HopInfo>,
 }
 
 impl Default for Builder {
@@ -24,12 +36,16 @@ impl Default for Builder {
             destination: Eid::default(),
             report_to: None,
             lifetime: DEFAULT_LIFETIME,
+            timestamp: None,
             payload: BlockTemplate::new(
                 BlockType::Payload,
                 BlockFlags::default(),
                 DEFAULT_CRC_TYPE,
             ),
             extensions: Vec::new(),
+            previous_node: None,
+            age: None,
+            hop_count: None,
         }
     }
 }
@@ -69,10 +85,62 @@ impl Builder {
         self
     }
 
+    pub fn creation_timestamp(mut self, timestamp: CreationTimestamp) -> Self {
+        self.timestamp = Some(timestamp);
+        self
+    }
+
     pub fn add_extension_block(self, block_type: BlockType) -> BlockBuilder {
         BlockBuilder::new(self, block_type)
     }
 
+    // Encode `value` as a block of a type registered with `registry`
+    pub fn add_registered_block<T: 'static>(
+        self,
+        registry: &BlockRegistry,
+        block_type: u64,
+        value: &T,
+    ) -> Result<BlockBuilder, Error> {
+        let data = registry.encode(block_type, value)?;
+        Ok(self
+            .add_extension_block(BlockType::Unrecognised(block_type))
+            .data(data))
+    }
+
+    pub fn add_hop_count(mut self, limit: u64) -> Self {
+        let hop_count = HopInfo { limit, count: 0 };
+        self.replace_extension_block(BlockType::HopCount, cbor::encode::emit(&hop_count));
+        self.hop_count = Some(hop_count);
+        self
+    }
+
+    // `age` is in milliseconds
+    pub fn add_bundle_age(mut self, age: u64) -> Self {
+        self.replace_extension_block(BlockType::BundleAge, cbor::encode::emit(age));
+        self.age = Some(age);
+        self
+    }
+
+    pub fn add_previous_node(mut self, previous_node: Eid) -> Self {
+        self.replace_extension_block(BlockType::PreviousNode, cbor::encode::emit(&previous_node));
+        self.previous_node = Some(previous_node);
+        self
+    }
+
+    fn replace_extension_block(&mut self, block_type: BlockType, data: Vec<u8>) {
+        let mut template = BlockTemplate::new(block_type, BlockFlags::default(), self.crc_type);
+        template.data(data);
+        if let Some(existing) = self
+            .extensions
+            .iter_mut()
+            .find(|t| t.block_type == block_type)
+        {
+            *existing = template;
+        } else {
+            self.extensions.push(template);
+        }
+    }
+
     pub fn add_payload_block(self, data: Vec<u8>) -> Self {
         self.add_extension_block(BlockType::Payload)
             .data(data)
@@ -88,13 +156,16 @@ impl Builder {
             },
             id: BundleId {
                 source: std::mem::take(&mut self.source),
-                timestamp: CreationTimestamp::now(),
+                timestamp: self.timestamp.take().unwrap_or_else(CreationTimestamp::now),
                 ..Default::default()
             },
             flags: self.bundle_flags.clone(),
             crc_type: self.crc_type,
             destination: std::mem::take(&mut self.destination),
             lifetime: self.lifetime,
+            previous_node: self.previous_node.take(),
+            age: self.age,
+            hop_count: self.hop_count.take(),
             ..Default::default()
         };
 
@@ -104,9 +175,10 @@ impl Builder {
 
             // Emit extension blocks
             for (block_number, block) in self.extensions.into_iter().enumerate() {
+                let block_number = block_number as u64 + 2;
                 bundle
                     .blocks
-                    .insert(block_number as u64, block.build(block_number as u64 + 2, a));
+                    .insert(block_number, block.build(block_number, a));
             }
 
             // Emit payload
@@ -214,6 +286,10 @@ impl BlockTemplate {
         self.crc_type = crc_type;
     }
 
+    pub fn current_crc_type(&self) -> CrcType {
+        self.crc_type
+    }
+
     pub fn data(&mut self, data: Vec<u8>) {
         // Just copy the data for now
         self.data = data;
@@ -243,3 +319,97 @@ fn test() {
         .report_to("ipn:3.0".parse().unwrap())
         .build();
 }
+
+#[test]
+fn deterministic() {
+    let build = || {
+        Builder::new()
+            .source("ipn:1.0".parse().unwrap())
+            .destination("ipn:2.0".parse().unwrap())
+            .creation_timestamp(CreationTimestamp {
+                creation_time: Some(DtnTime::new(1_000_000)),
+                sequence_number: 7,
+            })
+            .add_extension_block(BlockType::HopCount)
+            .data(vec![0x82, 0x10, 0x00])
+            .build()
+            .add_extension_block(BlockType::BundleAge)
+            .data(vec![0x00])
+            .build()
+            .add_payload_block(vec![1, 2, 3])
+            .build()
+    };
+
+    let (bundle, data) = build();
+    assert_eq!(build().1, data);
+
+    // The output is already canonical, and the blocks are where the bundle says
+    let ValidBundle::Valid(parsed, false) = ValidBundle::parse(&data, |_, _| Ok(None)).unwrap()
+    else {
+        panic!("Built bundle is not canonical");
+    };
+    assert_eq!(parsed.id, bundle.id);
+    let mut block_numbers = bundle.blocks.keys().copied().collect::<Vec<_>>();
+    block_numbers.sort();
+    assert_eq!(block_numbers, vec![0, 1, 2, 3]);
+    for (block_number, block) in &parsed.blocks {
+        assert_eq!(bundle.blocks[block_number].block_type, block.block_type);
+    }
+}
+
+#[test]
+fn typed_blocks() {
+    const SENSOR_BLOCK_TYPE: u64 = 194;
+
+    let mut registry = BlockRegistry::new();
+    registry
+        .register::<u64>(
+            SENSOR_BLOCK_TYPE,
+            |value| cbor::encode::emit(*value),
+            |data| Ok(cbor::decode::parse(data)?),
+        )
+        .unwrap();
+
+    let (bundle, data) = Builder::new()
+        .source("ipn:1.0".parse().unwrap())
+        .destination("ipn:2.0".parse().unwrap())
+        .creation_timestamp(CreationTimestamp {
+            creation_time: Some(DtnTime::new(1_000_000)),
+            sequence_number: 7,
+        })
+        .add_hop_count(8)
+        .add_bundle_age(0)
+        .add_previous_node("ipn:3.0".parse().unwrap())
+        .add_hop_count(16)
+        .add_registered_block(&registry, SENSOR_BLOCK_TYPE, &1234u64)
+        .unwrap()
+        .build()
+        .add_payload_block(vec![1, 2, 3])
+        .build();
+
+    // Adding the hop count again replaced it
+    assert_eq!(bundle.blocks.len(), 6);
+    assert_eq!(bundle.blocks[&2].block_type, BlockType::HopCount);
+
+    let ValidBundle::Valid(parsed, false) = ValidBundle::parse(&data, |_, _| Ok(None)).unwrap()
+    else {
+        panic!("Built bundle is not canonical");
+    };
+    assert_eq!(parsed.hop_count.as_ref().map(|h| h.limit), Some(16));
+    assert_eq!(bundle.hop_count.as_ref().map(|h| h.limit), Some(16));
+    assert_eq!(parsed.age, Some(0));
+    assert_eq!(bundle.age, Some(0));
+    assert_eq!(parsed.previous_node, bundle.previous_node);
+    assert_eq!(
+        registry
+            .decode_block::<u64>(&parsed, SENSOR_BLOCK_TYPE, &data)
+            .unwrap(),
+        Some(1234)
+    );
+
+    // Unregistered blocks are refused
+    assert!(matches!(
+        Builder::new().add_registered_block(&registry, 195, &1234u64),
+        Err(Error::UnregisteredBlockType(195))
+    ));
+}
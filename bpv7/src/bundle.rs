@@ -45,6 +45,14 @@ where
     }
 }
 
+// How Bundle::parse_blocks treats the blocks it parses
+struct ParseOptions {
+    // Whether the bundle and its primary block, parsed already, were canonically encoded
+    canonical_bundle: bool,
+    canonical_primary_block: bool,
+    bib_policy: bpsec::bib::FailurePolicy,
+}
+
 #[derive(Default, Debug, Clone)]
 pub struct Bundle {
     // From Primary Block
@@ -91,6 +99,94 @@ impl Bundle {
         );
     }
 
+    // Parse just the primary block from the start of an encoded bundle, so the bundle can
+    // be checked before the rest of it has been received.  Returns None if more data is needed
+    pub fn peek_primary_block(data: &[u8]) -> Result<Option<Self>, Error> {
+        let mut primary_block = None;
+        let r = cbor::decode::try_parse_array(data, |blocks, _, _| {
+            // Find the end of the primary block before parsing it, as parsing a truncated
+            // primary block reports the missing fields, rather than the lack of data
+            let block_start = blocks.offset();
+            if blocks.skip_value(16)?.is_some() {
+                primary_block = Some(
+                    cbor::decode::parse::<primary_block::PrimaryBlock>(
+                        &data[block_start..blocks.offset()],
+                    )
+                    .map_field_err("Primary Block")?,
+                );
+            }
+            Ok::<_, Error>(())
+        });
+
+        // The rest of the bundle is still to come, so its absence is not an error.  A definite
+        // length array cut short reports the items it is missing, rather than the lack of data
+        let Some(primary_block) = primary_block else {
+            return match r {
+                Ok(_)
+                | Err(Error::InvalidCBOR(
+                    cbor::decode::Error::NotEnoughData | cbor::decode::Error::AdditionalItems,
+                )) => Ok(None),
+                Err(e) => Err(e),
+            };
+        };
+
+        let (bundle, e) = primary_block.into_bundle();
+        match e {
+            None => Ok(Some(bundle)),
+            Some(e) => Err(Error::InvalidField {
+                field: "Primary Block",
+                source: e,
+            }),
+        }
+    }
+
+    // Decrypt the block-type-specific data of a block that is the target of a BCB.
+    // Returns None if the block is not encrypted
+    pub fn decrypt_block(
+        &self,
+        block_number: u64,
+        source_data: &[u8],
+        f: impl FnMut(&Eid, bpsec::Context) -> Result<Option<bpsec::KeyMaterial>, bpsec::Error>,
+    ) -> Result<Option<Box<[u8]>>, Error> {
+        let Some(bcb_block_number) = self
+            .blocks
+            .get(&block_number)
+            .ok_or(bpsec::Error::MissingSecurityTarget)?
+            .bcb
+        else {
+            return Ok(None);
+        };
+
+        let (bcb_block, bcb, _) = self
+            .parse_payload::<bpsec::bcb::OperationSet>(&bcb_block_number, None, source_data)
+            .map_field_err("BPSec confidentiality extension block")?;
+
+        let op = bcb
+            .operations
+            .get(&block_number)
+            .ok_or(bpsec::Error::MissingSecurityTarget)?;
+
+        let mut keys = KeyCacheImpl::new(f);
+        let r = op.decrypt(
+            keys.get(&bcb.source, op.context_id())?,
+            bpsec::bcb::OperationArgs {
+                bpsec_source: &bcb.source,
+                target: self.blocks.get(&block_number).unwrap(),
+                target_number: block_number,
+                source: bcb_block,
+                source_number: bcb_block_number,
+                bundle: self,
+                primary_block: None,
+                bundle_data: source_data,
+            },
+            None,
+        )?;
+
+        r.plaintext
+            .ok_or(bpsec::Error::NoKey(bcb.source.clone()).into())
+            .map(Some)
+    }
+
     fn parse_payload<T>(
         &self,
         block_number: &u64,
@@ -138,13 +234,17 @@ impl Bundle {
     #[allow(clippy::type_complexity)]
     fn parse_blocks(
         &mut self,
-        canonical_bundle: bool,
-        canonical_primary_block: bool,
+        options: ParseOptions,
         blocks: &mut cbor::decode::Array,
         mut offset: usize,
         source_data: &[u8],
         keys: &mut impl KeyCache,
-    ) -> Result<(Option<Box<[u8]>>, bool), Error> {
+    ) -> Result<(Option<(Box<[u8]>, Vec<u64>)>, bool), Error> {
+        let ParseOptions {
+            canonical_bundle,
+            canonical_primary_block,
+            bib_policy,
+        } = options;
         let mut last_block_number = 0;
         let mut noncanonical_blocks: HashMap<u64, bool> = HashMap::new();
         let mut blocks_to_check = HashMap::new();
@@ -399,6 +499,7 @@ impl Bundle {
         // Now parse all BIBs
         let mut bibs = HashMap::new();
         let mut bib_targets = HashSet::new();
+        let mut stripped_types = Vec::new();
         for bib_block_number in bibs_to_check {
             let (bib_block, mut bib, canonical) = self
                 .parse_payload::<bpsec::bib::OperationSet>(
@@ -454,7 +555,7 @@ impl Bundle {
                     .get(target_number)
                     .map_or((None, true), |(v, c)| (Some(v.as_ref()), *c));
 
-                let r = op.verify(
+                let r = match op.verify(
                     keys.get(&bib.source, op.context_id())?,
                     bpsec::bib::OperationArgs {
                         bpsec_source: &bib.source,
@@ -467,7 +568,22 @@ impl Bundle {
                         bundle_data: source_data,
                     },
                     payload_data,
-                )?;
+                ) {
+                    Err(bpsec::Error::IntegrityCheckFailed)
+                        if bib_policy == bpsec::bib::FailurePolicy::Strip
+                            && !matches!(
+                                target_block.block_type,
+                                BlockType::Primary | BlockType::Payload | BlockType::BundleAge
+                            ) =>
+                    {
+                        // Remove the target, and the operation with it
+                        noncanonical_blocks.remove(target_number);
+                        blocks_to_remove.insert(*target_number);
+                        stripped_types.push(target_block.block_type);
+                        continue;
+                    }
+                    r => r?,
+                };
 
                 if !blocks_to_remove.contains(target_number) {
                     if let BlockType::PreviousNode | BlockType::HopCount = target_block.block_type {
@@ -597,6 +713,15 @@ impl Bundle {
             new_payloads.insert(bib_block_number, cbor::encode::emit(bib).into());
         }
 
+        // Forget the contents of stripped blocks, now the BIBs are done with the bundle
+        for block_type in stripped_types {
+            match block_type {
+                BlockType::PreviousNode => self.previous_node = None,
+                BlockType::HopCount => self.hop_count = None,
+                _ => {}
+            }
+        }
+
         // Encrypt blocks and update BCBs
         for (bcb_block_number, mut bcb) in bcbs {
             let bcb_block = self.blocks.get(&bcb_block_number).unwrap();
@@ -625,9 +750,12 @@ impl Bundle {
             new_payloads.insert(bcb_block_number, cbor::encode::emit(bcb).into());
         }
 
+        // Remember which blocks are re-encoded, as their CRCs are recalculated
+        let mut reencoded = Vec::new();
         let new_data = cbor::encode::emit_array(None, |a| {
             // Emit primary
             if let Some(p) = primary_block {
+                reencoded.push(0);
                 a.emit_raw(p);
             } else {
                 self.blocks.get_mut(&0).unwrap().copy(source_data, a);
@@ -646,8 +774,10 @@ impl Bundle {
                 }
 
                 if let Some(data) = new_payloads.remove(block_number) {
+                    reencoded.push(*block_number);
                     block.emit(*block_number, &data, a);
                 } else if noncanonical_blocks.remove(block_number).is_some() {
+                    reencoded.push(*block_number);
                     block.rewrite(*block_number, a, source_data);
                 } else {
                     // Copy canonical blocks verbatim
@@ -658,13 +788,14 @@ impl Bundle {
 
             // Emit payload block
             if noncanonical_blocks.remove(&1).is_some() {
+                reencoded.push(1);
                 payload_block.rewrite(1, a, source_data);
             } else {
                 payload_block.write(source_data, a);
             }
             self.blocks.insert(1, payload_block);
         });
-        Ok((Some(new_data.into()), report_unsupported))
+        Ok((Some((new_data.into(), reencoded)), report_unsupported))
     }
 }
 
@@ -685,6 +816,24 @@ impl ValidBundle {
         data: &[u8],
         f: impl FnMut(&Eid, bpsec::Context) -> Result<Option<bpsec::KeyMaterial>, bpsec::Error>,
     ) -> Result<Self, Error> {
+        Self::parse_with_policy(data, f, bpsec::bib::FailurePolicy::Drop)
+    }
+
+    // As parse, but choosing what happens to blocks that fail integrity verification
+    pub fn parse_with_policy(
+        data: &[u8],
+        f: impl FnMut(&Eid, bpsec::Context) -> Result<Option<bpsec::KeyMaterial>, bpsec::Error>,
+        bib_policy: bpsec::bib::FailurePolicy,
+    ) -> Result<Self, Error> {
+        Self::parse_with_diagnostics(data, f, bib_policy).map(|(bundle, _)| bundle)
+    }
+
+    // As parse_with_policy, also reporting how the CRCs of the blocks fared
+    pub fn parse_with_diagnostics(
+        data: &[u8],
+        f: impl FnMut(&Eid, bpsec::Context) -> Result<Option<bpsec::KeyMaterial>, bpsec::Error>,
+        bib_policy: bpsec::bib::FailurePolicy,
+    ) -> Result<(Self, CrcReport), Error> {
         let mut keys = KeyCacheImpl::new(f);
         cbor::decode::parse_array(data, |blocks, mut canonical, tags| {
             // Check for shortest/correct form
@@ -711,10 +860,10 @@ impl ValidBundle {
 
             let (mut bundle, e) = primary_block.into_bundle();
             if let Some(e) = e {
-                return Ok(Self::Invalid(
-                    bundle,
-                    StatusReportReasonCode::BlockUnintelligible,
-                    e,
+                let crc_report = CrcReport::new(&bundle, &[]).failed(e.as_ref());
+                return Ok((
+                    Self::Invalid(bundle, StatusReportReasonCode::BlockUnintelligible, e),
+                    crc_report,
                 ));
             }
 
@@ -740,29 +889,282 @@ impl ValidBundle {
 
             // And now parse the blocks
             match bundle.parse_blocks(
-                canonical,
-                canonical_primary_block,
+                ParseOptions {
+                    canonical_bundle: canonical,
+                    canonical_primary_block,
+                    bib_policy,
+                },
                 blocks,
                 block_start + block_len,
                 data,
                 &mut keys,
             ) {
-                Ok((None, report_unsupported)) => Ok(Self::Valid(bundle, report_unsupported)),
-                Ok((Some(new_data), report_unsupported)) => {
-                    Ok(Self::Rewritten(bundle, new_data, report_unsupported))
+                Ok((None, report_unsupported)) => {
+                    let crc_report = CrcReport::new(&bundle, &[]);
+                    Ok((Self::Valid(bundle, report_unsupported), crc_report))
+                }
+                Ok((Some((new_data, reencoded)), report_unsupported)) => {
+                    let crc_report = CrcReport::new(&bundle, &reencoded);
+                    Ok((
+                        Self::Rewritten(bundle, new_data, report_unsupported),
+                        crc_report,
+                    ))
+                }
+                Err(e) => {
+                    // Step over the blocks not parsed, so the bundle can be reported invalid
+                    blocks.skip_to_end(16)?;
+
+                    let crc_report = CrcReport::new(&bundle, &[]).failed(&e);
+                    let invalid = match e {
+                        Error::Unsupported(n) => Self::Invalid(
+                            bundle,
+                            StatusReportReasonCode::BlockUnsupported,
+                            Error::Unsupported(n).into(),
+                        ),
+                        Error::InvalidBPSec(bpsec::Error::IntegrityCheckFailed) => Self::Invalid(
+                            bundle,
+                            StatusReportReasonCode::FailedSecurityOperation,
+                            Error::InvalidBPSec(bpsec::Error::IntegrityCheckFailed).into(),
+                        ),
+                        e => Self::Invalid(
+                            bundle,
+                            StatusReportReasonCode::BlockUnintelligible,
+                            e.into(),
+                        ),
+                    };
+                    Ok((invalid, crc_report))
                 }
-                Err(Error::Unsupported(n)) => Ok(Self::Invalid(
-                    bundle,
-                    StatusReportReasonCode::BlockUnsupported,
-                    Error::Unsupported(n).into(),
-                )),
-                Err(e) => Ok(Self::Invalid(
-                    bundle,
-                    StatusReportReasonCode::BlockUnintelligible,
-                    e.into(),
-                )),
             }
         })
         .map(|v| v.0)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn peek_primary_block() {
+        let (bundle, data) = Builder::new()
+            .source("ipn:1.0".parse().unwrap())
+            .destination("ipn:2.1".parse().unwrap())
+            .lifetime(1000)
+            .add_payload_block(vec![0; 64])
+            .build();
+        let primary_end = bundle.blocks[&0].data_start + bundle.blocks[&0].data_len;
+
+        for len in 0..primary_end {
+            assert!(Bundle::peek_primary_block(&data[..len]).unwrap().is_none());
+        }
+        for len in [primary_end, data.len() - 1, data.len()] {
+            let peeked = Bundle::peek_primary_block(&data[..len]).unwrap().unwrap();
+            assert_eq!(peeked.id, bundle.id);
+            assert_eq!(peeked.destination, bundle.destination);
+            assert_eq!(peeked.lifetime, bundle.lifetime);
+        }
+
+        // Not a bundle at all
+        assert!(Bundle::peek_primary_block(&[0x01]).is_err());
+    }
+
+    #[test]
+    fn zero_length_payload() {
+        // An explicitly empty payload, and no payload at all, are the same
+        for builder in [Builder::new().add_payload_block(Vec::new()), Builder::new()] {
+            let (bundle, data) = builder
+                .source("ipn:1.0".parse().unwrap())
+                .destination("ipn:2.1".parse().unwrap())
+                .build();
+            assert!(bundle.blocks[&1].block_data(&data).unwrap().is_empty());
+
+            let ValidBundle::Valid(parsed, false) =
+                ValidBundle::parse(&data, |_, _| Ok(None)).unwrap()
+            else {
+                panic!("Empty payload is not valid");
+            };
+            assert_eq!(parsed.id, bundle.id);
+            assert!(parsed.blocks[&1].block_data(&data).unwrap().is_empty());
+        }
+
+        // A payload can be emptied by the Editor, and filled again
+        let (bundle, data) = Builder::new()
+            .source("ipn:1.0".parse().unwrap())
+            .destination("ipn:2.1".parse().unwrap())
+            .add_payload_block(vec![1, 2, 3])
+            .build();
+        let data = Editor::new(&bundle, &data)
+            .replace_extension_block(BlockType::Payload)
+            .data(Vec::new())
+            .build()
+            .build();
+        let ValidBundle::Valid(bundle, _) = ValidBundle::parse(&data, |_, _| Ok(None)).unwrap()
+        else {
+            panic!("Emptied payload is not valid");
+        };
+        assert!(bundle.blocks[&1].block_data(&data).unwrap().is_empty());
+
+        let data = Editor::new(&bundle, &data)
+            .replace_extension_block(BlockType::Payload)
+            .data(vec![4, 5])
+            .build()
+            .build();
+        let ValidBundle::Valid(bundle, _) = ValidBundle::parse(&data, |_, _| Ok(None)).unwrap()
+        else {
+            panic!("Refilled payload is not valid");
+        };
+        assert_eq!(&*bundle.blocks[&1].block_data(&data).unwrap(), &[4, 5]);
+    }
+
+    #[test]
+    fn crc_report() {
+        let (_, mut data) = Builder::new()
+            .source("ipn:1.0".parse().unwrap())
+            .destination("ipn:2.1".parse().unwrap())
+            .add_payload_block(vec![1, 2, 3])
+            .build();
+
+        let (ValidBundle::Valid(..), report) = ValidBundle::parse_with_diagnostics(
+            &data,
+            |_, _| Ok(None),
+            bpsec::bib::FailurePolicy::Drop,
+        )
+        .unwrap() else {
+            panic!("Bundle is not valid");
+        };
+        assert_eq!(
+            report.present,
+            [
+                (0, CrcType::CRC32_CASTAGNOLI),
+                (1, CrcType::CRC32_CASTAGNOLI)
+            ]
+        );
+        assert!(report.absent.is_empty());
+        assert!(report.corrected.is_empty());
+        assert!(!report.incorrect);
+
+        // Damage the CRC of the payload block, just before the end of the bundle
+        let len = data.len();
+        data[len - 2] ^= 0xFF;
+        let (ValidBundle::Invalid(..), report) = ValidBundle::parse_with_diagnostics(
+            &data,
+            |_, _| Ok(None),
+            bpsec::bib::FailurePolicy::Drop,
+        )
+        .unwrap() else {
+            panic!("Bundle with an incorrect CRC is valid");
+        };
+        assert!(report.incorrect);
+    }
+
+    #[test]
+    fn fragment() {
+        let (bundle, data) = Builder::new()
+            .source("ipn:1.0".parse().unwrap())
+            .destination("ipn:2.1".parse().unwrap())
+            .add_payload_block(vec![1, 2, 3, 4, 5])
+            .build();
+
+        // Make the second fragment of the bundle
+        let data = Editor::new(&bundle, &data)
+            .fragment(3, 5)
+            .replace_extension_block(BlockType::Payload)
+            .data(vec![4, 5])
+            .build()
+            .build();
+        let ValidBundle::Valid(fragment, _) = ValidBundle::parse(&data, |_, _| Ok(None)).unwrap()
+        else {
+            panic!("Fragment is not valid");
+        };
+        assert!(fragment.flags.is_fragment);
+        assert_eq!(
+            fragment.id.fragment_info,
+            Some(FragmentInfo {
+                offset: 3,
+                total_len: 5
+            })
+        );
+        assert_eq!(fragment.id.timestamp, bundle.id.timestamp);
+        assert_eq!(&*fragment.blocks[&1].block_data(&data).unwrap(), &[4, 5]);
+
+        // And back again
+        let data = Editor::new(&fragment, &data)
+            .unfragment()
+            .replace_extension_block(BlockType::Payload)
+            .data(vec![1, 2, 3, 4, 5])
+            .build()
+            .build();
+        let ValidBundle::Valid(original, _) = ValidBundle::parse(&data, |_, _| Ok(None)).unwrap()
+        else {
+            panic!("Unfragmented bundle is not valid");
+        };
+        assert!(!original.flags.is_fragment);
+        assert_eq!(original.id, bundle.id);
+    }
+
+    // A bundle with an extension block protected by a BIB, which fails to verify with any key
+    fn bib_on_extension_block() -> Vec<u8> {
+        // The BIB of RFC9173 Appendix A.1, targeting block 2 rather than the payload
+        let bib = hex_literal::hex!(
+            "81020101820282020182820107820300818182015840"
+            "0000000000000000000000000000000000000000000000000000000000000000"
+            "0000000000000000000000000000000000000000000000000000000000000000"
+        );
+        Builder::new()
+            .source("ipn:1.0".parse().unwrap())
+            .destination("ipn:2.1".parse().unwrap())
+            .add_extension_block(BlockType::Unrecognised(192))
+            .data(vec![1, 2, 3])
+            .build()
+            .add_extension_block(BlockType::BlockIntegrity)
+            .data(bib.to_vec())
+            .build()
+            .add_payload_block(vec![1, 2, 3])
+            .build()
+            .1
+    }
+
+    fn parse_bib_on_extension_block(policy: bpsec::bib::FailurePolicy) -> ValidBundle {
+        ValidBundle::parse_with_policy(
+            &bib_on_extension_block(),
+            |_, _| {
+                Ok(Some(bpsec::KeyMaterial::SymmetricKey(
+                    hex_literal::hex!("1a2b1a2b1a2b1a2b1a2b1a2b1a2b1a2b").into(),
+                )))
+            },
+            policy,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn bib_failure_drop() {
+        let ValidBundle::Invalid(_, StatusReportReasonCode::FailedSecurityOperation, _) =
+            parse_bib_on_extension_block(bpsec::bib::FailurePolicy::Drop)
+        else {
+            panic!("Bundle with a failed BIB target was not dropped");
+        };
+    }
+
+    #[test]
+    fn bib_failure_strip() {
+        let ValidBundle::Rewritten(bundle, data, _) =
+            parse_bib_on_extension_block(bpsec::bib::FailurePolicy::Strip)
+        else {
+            panic!("Failed BIB target was not stripped");
+        };
+
+        // The target has gone, and the BIB with its only operation
+        assert!(!bundle.blocks.contains_key(&2));
+        assert!(!bundle
+            .blocks
+            .values()
+            .any(|block| block.block_type == BlockType::BlockIntegrity));
+        assert_eq!(&*bundle.blocks[&1].block_data(&data).unwrap(), &[1, 2, 3]);
+
+        // And what is left is a valid bundle
+        let ValidBundle::Valid(..) = ValidBundle::parse(&data, |_, _| Ok(None)).unwrap() else {
+            panic!("Stripped bundle is not valid");
+        };
+    }
+}
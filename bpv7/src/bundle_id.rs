@@ -2,6 +2,14 @@ use super::*;
 use base64::prelude::*;
 use thiserror::Error;
 
+/* A bundle id key is the string form of a BundleId, used to name a bundle outside the BPA,
+ * e.g. in the gRPC APIs.  A key is the key format version, a '.', and the unpadded URL-safe
+ * base64 encoding of the CBOR array [source EID, creation timestamp], or for a fragment
+ * [source EID, creation timestamp, fragment offset, total application data unit length],
+ * each encoded as in the primary block.  Keys can be used in URLs and file names as they
+ * are.  Unversioned keys, the unpadded standard base64 encoding of the same array, were
+ * issued before the format was versioned, and are still accepted */
+
 #[derive(Default, Debug, Clone, Hash, PartialEq, Eq)]
 pub struct BundleId {
     pub source: Eid,
@@ -16,10 +24,13 @@ pub struct FragmentInfo {
 }
 
 #[derive(Error, Debug)]
-pub enum Error {
+pub enum BundleIdError {
     #[error("Bad bundle id key")]
     BadKey,
 
+    #[error("Unsupported bundle id key version {0}")]
+    UnsupportedVersion(String),
+
     #[error("Bad base64 encoding")]
     BadBase64(#[from] base64::DecodeError),
 
@@ -34,14 +45,14 @@ pub enum Error {
 }
 
 trait CaptureFieldErr<T> {
-    fn map_field_err(self, field: &'static str) -> Result<T, Error>;
+    fn map_field_err(self, field: &'static str) -> Result<T, BundleIdError>;
 }
 
 impl<T, E: Into<Box<dyn std::error::Error + Send + Sync>>> CaptureFieldErr<T>
     for std::result::Result<T, E>
 {
-    fn map_field_err(self, field: &'static str) -> Result<T, Error> {
-        self.map_err(|e| Error::InvalidField {
+    fn map_field_err(self, field: &'static str) -> Result<T, BundleIdError> {
+        self.map_err(|e| BundleIdError::InvalidField {
             field,
             source: e.into(),
         })
@@ -49,8 +60,19 @@ impl<T, E: Into<Box<dyn std::error::Error + Send + Sync>>> CaptureFieldErr<T>
 }
 
 impl BundleId {
-    pub fn from_key(k: &str) -> Result<Self, Error> {
-        cbor::decode::parse_array(&BASE64_STANDARD_NO_PAD.decode(k)?, |array, _, _| {
+    pub const KEY_VERSION: u32 = 1;
+
+    pub fn from_key(k: &str) -> Result<Self, BundleIdError> {
+        let data = match k.split_once('.') {
+            Some((version, data)) if version == Self::KEY_VERSION.to_string() => {
+                BASE64_URL_SAFE_NO_PAD.decode(data)?
+            }
+            Some((version, _)) => {
+                return Err(BundleIdError::UnsupportedVersion(version.to_string()))
+            }
+            None => BASE64_STANDARD_NO_PAD.decode(k)?,
+        };
+        cbor::decode::parse_array(&data, |array, _, _| {
             let s = Self {
                 source: array.parse().map_field_err("source EID")?,
                 timestamp: array.parse().map_field_err("creation timestamp")?,
@@ -66,15 +88,16 @@ impl BundleId {
                 },
             };
             if array.end()?.is_none() {
-                Err(Error::BadKey)
+                Err(BundleIdError::BadKey)
             } else {
                 Ok(s)
             }
         })
         .map(|v| v.0)
     }
+
     pub fn to_key(&self) -> String {
-        BASE64_STANDARD_NO_PAD.encode(if let Some(fragment_info) = &self.fragment_info {
+        let data = if let Some(fragment_info) = &self.fragment_info {
             cbor::encode::emit_array(Some(4), |array| {
                 array.emit(&self.source);
                 array.emit(&self.timestamp);
@@ -86,6 +109,134 @@ impl BundleId {
                 array.emit(&self.source);
                 array.emit(&self.timestamp);
             })
-        })
+        };
+        format!(
+            "{}.{}",
+            Self::KEY_VERSION,
+            BASE64_URL_SAFE_NO_PAD.encode(data)
+        )
+    }
+}
+
+impl std::str::FromStr for BundleId {
+    type Err = BundleIdError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::from_key(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A small deterministic generator, so the properties below are checked over many ids
+    struct Gen(u64);
+
+    impl Gen {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: u64) -> u64 {
+            self.next() % n
+        }
+
+        fn name(&mut self) -> Box<str> {
+            const CHARS: &[u8] = b"abcz09-._~ %/?";
+            (0..=self.below(12))
+                .map(|_| CHARS[self.below(CHARS.len() as u64) as usize] as char)
+                .collect::<String>()
+                .into()
+        }
+
+        fn eid(&mut self) -> Eid {
+            match self.below(3) {
+                0 => Eid::Null,
+                1 => Eid::Ipn {
+                    allocator_id: self.next() as u32,
+                    node_number: self.next() as u32,
+                    service_number: self.next() as u32,
+                },
+                _ => Eid::Dtn {
+                    node_name: self.name(),
+                    demux: (0..=self.below(3)).map(|_| self.name()).collect(),
+                },
+            }
+        }
+
+        fn bundle_id(&mut self) -> BundleId {
+            BundleId {
+                source: self.eid(),
+                timestamp: CreationTimestamp {
+                    creation_time: match self.below(4) {
+                        0 => None,
+                        _ => Some(DtnTime::new(self.below(1 << 50) + 1)),
+                    },
+                    sequence_number: self.next() >> self.below(64),
+                },
+                fragment_info: match self.below(2) {
+                    0 => None,
+                    _ => Some(FragmentInfo {
+                        offset: self.next() >> self.below(64),
+                        total_len: self.next() >> self.below(64),
+                    }),
+                },
+            }
+        }
+    }
+
+    fn legacy_key(bundle_id: &BundleId) -> String {
+        let key = bundle_id.to_key();
+        let data = BASE64_URL_SAFE_NO_PAD
+            .decode(key.split_once('.').unwrap().1)
+            .unwrap();
+        BASE64_STANDARD_NO_PAD.encode(data)
+    }
+
+    #[test]
+    fn roundtrip() {
+        let mut gen = Gen(0x9e3779b97f4a7c15);
+        for _ in 0..10_000 {
+            let bundle_id = gen.bundle_id();
+            let key = bundle_id.to_key();
+            assert!(key.starts_with("1."));
+            assert!(key
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b"-_.".contains(&b)));
+            assert_eq!(BundleId::from_key(&key).unwrap(), bundle_id, "{key}");
+            assert_eq!(key.parse::<BundleId>().unwrap(), bundle_id);
+
+            // Keys issued before the format was versioned
+            assert_eq!(
+                BundleId::from_key(&legacy_key(&bundle_id)).unwrap(),
+                bundle_id
+            );
+        }
+    }
+
+    #[test]
+    fn invalid() {
+        let mut gen = Gen(0x2545f4914f6cdd1d);
+        for _ in 0..1_000 {
+            let key = gen.bundle_id().to_key();
+
+            // Every truncation is refused, rather than parsed as something else
+            for len in 0..key.len() {
+                assert!(BundleId::from_key(&key[..len]).is_err(), "{}", &key[..len]);
+            }
+        }
+
+        assert!(matches!(
+            BundleId::from_key("2.gwIA"),
+            Err(BundleIdError::UnsupportedVersion(v)) if v == "2"
+        ));
+        assert!(matches!(
+            BundleId::from_key("1.+/8"),
+            Err(BundleIdError::BadBase64(_))
+        ));
     }
 }
@@ -4,6 +4,12 @@ use thiserror::Error;
 const X25: ::crc::Crc<u16> = ::crc::Crc::<u16>::new(&::crc::CRC_16_IBM_SDLC);
 const CASTAGNOLI: ::crc::Crc<u32> = ::crc::Crc::<u32>::new(&::crc::CRC_32_ISCSI);
 
+// Reversed CRC-32C polynomial, used to combine digests of adjacent chunks
+const CASTAGNOLI_POLY_REVERSED: u32 = 0x82F6_3B78;
+
+// Data larger than this is digested in parallel chunks
+const PARALLEL_DIGEST_THRESHOLD: usize = 1024 * 1024;
+
 #[derive(Error, Debug)]
 pub enum Error {
     #[error("Invalid CRC Type {0}")]
@@ -26,7 +32,7 @@ pub enum Error {
 }
 
 #[allow(non_camel_case_types)]
-#[derive(Default, Debug, Copy, Clone)]
+#[derive(Default, Debug, Copy, Clone, PartialEq, Eq)]
 pub enum CrcType {
     #[default]
     None,
@@ -75,6 +81,60 @@ impl cbor::decode::FromCbor for CrcType {
     }
 }
 
+/* How the CRCs of the blocks of a received bundle fared in parsing: which blocks carried
+ * a CRC, which had none, and which had their CRC recalculated as the block was re-encoded
+ * in canonical form.  If parsing failed on a CRC that did not match, `incorrect` is set */
+#[derive(Debug, Default, Clone)]
+pub struct CrcReport {
+    pub present: Vec<(u64, CrcType)>,
+    pub absent: Vec<u64>,
+    pub corrected: Vec<u64>,
+    pub incorrect: bool,
+}
+
+impl CrcReport {
+    pub(crate) fn new(bundle: &Bundle, reencoded: &[u64]) -> Self {
+        let mut block_numbers = bundle.blocks.keys().copied().collect::<Vec<_>>();
+        block_numbers.sort_unstable();
+
+        let mut report = Self::default();
+        for block_number in block_numbers {
+            match bundle.blocks[&block_number].crc_type {
+                CrcType::None => report.absent.push(block_number),
+                crc_type => {
+                    report.present.push((block_number, crc_type));
+                    if reencoded.contains(&block_number) {
+                        report.corrected.push(block_number);
+                    }
+                }
+            }
+        }
+        report
+    }
+
+    pub(crate) fn failed(mut self, e: &(dyn std::error::Error + 'static)) -> Self {
+        self.incorrect = is_incorrect_crc(e);
+        self
+    }
+}
+
+// Whether an error, or any error it was caused by, is a CRC mismatch
+fn is_incorrect_crc(e: &(dyn std::error::Error + 'static)) -> bool {
+    let mut e = Some(e);
+    while let Some(err) = e {
+        // Transparent errors hide their inner error from the source chain
+        match (
+            err.downcast_ref::<Error>(),
+            err.downcast_ref::<super::Error>(),
+        ) {
+            (Some(Error::IncorrectCrc), _)
+            | (_, Some(super::Error::InvalidCrc(Error::IncorrectCrc))) => return true,
+            _ => e = err.source(),
+        }
+    }
+    false
+}
+
 pub fn parse_crc_value(
     data: &[u8],
     block: &mut cbor::decode::Array,
@@ -131,11 +191,25 @@ pub fn parse_crc_value(
             }
         }
         (CrcType::CRC32_CASTAGNOLI, Some((crc_value, shortest))) => {
-            let mut digest = CASTAGNOLI.digest();
-            digest.update(&data[0..crc_val_end - 4]);
-            digest.update(&[0u8; 4]);
-            digest.update(&data[crc_val_end..crc_end]);
-            if crc_value != digest.finalize() {
+            let bulk = &data[0..crc_val_end - 4];
+            let crc = if bulk.len() < PARALLEL_DIGEST_THRESHOLD {
+                let mut digest = CASTAGNOLI.digest();
+                digest.update(bulk);
+                digest.update(&[0u8; 4]);
+                digest.update(&data[crc_val_end..crc_end]);
+                digest.finalize()
+            } else {
+                // Digest the bulk of the block in parallel, then the zeroed CRC value and any trailer
+                let mut digest = CASTAGNOLI.digest();
+                digest.update(&[0u8; 4]);
+                digest.update(&data[crc_val_end..crc_end]);
+                castagnoli_combine(
+                    castagnoli_checksum(bulk),
+                    digest.finalize(),
+                    (4 + crc_end - crc_val_end) as u64,
+                )
+            };
+            if crc_value != crc {
                 Err(Error::IncorrectCrc)
             } else {
                 Ok(shortest)
@@ -146,6 +220,83 @@ pub fn parse_crc_value(
     }
 }
 
+fn castagnoli_checksum(data: &[u8]) -> u32 {
+    if data.len() < PARALLEL_DIGEST_THRESHOLD {
+        return CASTAGNOLI.checksum(data);
+    }
+
+    // Digest chunks on the shared rayon pool, and then stitch the results together in order
+    use rayon::prelude::*;
+    data.par_chunks(PARALLEL_DIGEST_THRESHOLD)
+        .map(|chunk| (CASTAGNOLI.checksum(chunk), chunk.len()))
+        .reduce_with(|(crc1, len1), (crc2, len2)| {
+            (castagnoli_combine(crc1, crc2, len2 as u64), len1 + len2)
+        })
+        .map_or(0, |(crc, _)| crc)
+}
+
+fn gf2_matrix_times(mat: &[u32; 32], mut vec: u32) -> u32 {
+    let mut sum = 0;
+    let mut i = 0;
+    while vec != 0 {
+        if vec & 1 != 0 {
+            sum ^= mat[i];
+        }
+        vec >>= 1;
+        i += 1;
+    }
+    sum
+}
+
+fn gf2_matrix_square(square: &mut [u32; 32], mat: &[u32; 32]) {
+    for (s, m) in square.iter_mut().zip(mat) {
+        *s = gf2_matrix_times(mat, *m);
+    }
+}
+
+// Combine crc1 = CRC(A) and crc2 = CRC(B) into CRC(A || B), as zlib's crc32_combine()
+fn castagnoli_combine(mut crc1: u32, crc2: u32, mut len2: u64) -> u32 {
+    if len2 == 0 {
+        return crc1;
+    }
+
+    // Operator for one zero bit in odd
+    let mut odd = [0u32; 32];
+    odd[0] = CASTAGNOLI_POLY_REVERSED;
+    let mut row = 1u32;
+    for o in odd.iter_mut().skip(1) {
+        *o = row;
+        row <<= 1;
+    }
+
+    // Operator for two zero bits in even, then four zero bits in odd
+    let mut even = [0u32; 32];
+    gf2_matrix_square(&mut even, &odd);
+    gf2_matrix_square(&mut odd, &even);
+
+    // Apply len2 zero bytes to crc1
+    loop {
+        gf2_matrix_square(&mut even, &odd);
+        if len2 & 1 != 0 {
+            crc1 = gf2_matrix_times(&even, crc1);
+        }
+        len2 >>= 1;
+        if len2 == 0 {
+            break;
+        }
+
+        gf2_matrix_square(&mut odd, &even);
+        if len2 & 1 != 0 {
+            crc1 = gf2_matrix_times(&odd, crc1);
+        }
+        len2 >>= 1;
+        if len2 == 0 {
+            break;
+        }
+    }
+    crc1 ^ crc2
+}
+
 pub fn append_crc_value(crc_type: CrcType, mut data: Vec<u8>) -> Vec<u8> {
     match crc_type {
         CrcType::None => {}
@@ -167,3 +318,65 @@ pub fn append_crc_value(crc_type: CrcType, mut data: Vec<u8>) -> Vec<u8> {
     }
     data
 }
+
+// The CRC value of a block whose data arrives in pieces, as when its payload is streamed
+pub(crate) enum StreamingCrc {
+    None,
+    X25(::crc::Digest<'static, u16>),
+    Castagnoli(::crc::Digest<'static, u32>),
+}
+
+impl StreamingCrc {
+    pub(crate) fn new(crc_type: CrcType) -> Self {
+        match crc_type {
+            CrcType::None => Self::None,
+            CrcType::CRC16_X25 => Self::X25(X25.digest()),
+            CrcType::CRC32_CASTAGNOLI => Self::Castagnoli(CASTAGNOLI.digest()),
+            _ => unreachable!(),
+        }
+    }
+
+    pub(crate) fn update(&mut self, data: &[u8]) {
+        match self {
+            Self::None => {}
+            Self::X25(digest) => digest.update(data),
+            Self::Castagnoli(digest) => digest.update(data),
+        }
+    }
+
+    // The CRC value to append to the block, as append_crc_value() would
+    pub(crate) fn finish(self) -> Vec<u8> {
+        match self {
+            Self::None => Vec::new(),
+            Self::X25(mut digest) => {
+                digest.update(&[0x42, 0, 0]);
+                let mut value = vec![0x42];
+                value.extend_from_slice(&digest.finalize().to_be_bytes());
+                value
+            }
+            Self::Castagnoli(mut digest) => {
+                digest.update(&[0x44, 0, 0, 0, 0]);
+                let mut value = vec![0x44];
+                value.extend_from_slice(&digest.finalize().to_be_bytes());
+                value
+            }
+        }
+    }
+}
+
+#[test]
+fn test_parallel_digest() {
+    let data = (0..3 * PARALLEL_DIGEST_THRESHOLD + 17)
+        .map(|i| (i % 251) as u8)
+        .collect::<Vec<u8>>();
+
+    assert_eq!(castagnoli_checksum(&data), CASTAGNOLI.checksum(&data));
+    assert_eq!(
+        castagnoli_combine(
+            CASTAGNOLI.checksum(&data[..1000]),
+            CASTAGNOLI.checksum(&data[1000..]),
+            (data.len() - 1000) as u64
+        ),
+        CASTAGNOLI.checksum(&data)
+    );
+}
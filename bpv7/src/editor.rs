@@ -1,15 +1,19 @@
 use super::*;
 use std::collections::HashMap;
 
+#[derive(Clone)]
 pub struct Editor<'a> {
     original: &'a Bundle,
     source_data: &'a [u8],
     blocks: HashMap<u64, BlockTemplate>,
+    fragment_info: Option<Option<FragmentInfo>>,
 }
 
+#[derive(Clone)]
 enum BlockTemplate {
     Keep(BlockType),
     Add(builder::BlockTemplate),
+    Recrc(BlockType, CrcType),
 }
 
 pub struct BlockBuilder<'a> {
@@ -28,6 +32,7 @@ impl<'a> Editor<'a> {
                 .collect(),
             source_data,
             original,
+            fragment_info: None,
         }
     }
 
@@ -55,7 +60,7 @@ impl<'a> Editor<'a> {
             .blocks
             .iter()
             .find(|(_, block)| match block {
-                BlockTemplate::Keep(t) => *t == block_type,
+                BlockTemplate::Keep(t) | BlockTemplate::Recrc(t, _) => *t == block_type,
                 BlockTemplate::Add(t) => t.block_type() == block_type,
             })
             .and_then(|(block_number, template)| match template {
@@ -69,6 +74,14 @@ impl<'a> Editor<'a> {
                         ),
                     )
                 }),
+                BlockTemplate::Recrc(_, crc_type) => {
+                    self.original.blocks.get(block_number).map(|block| {
+                        (
+                            *block_number,
+                            builder::BlockTemplate::new(block_type, block.flags.clone(), *crc_type),
+                        )
+                    })
+                }
                 BlockTemplate::Add(template) => Some((*block_number, template.clone())),
             })
         {
@@ -86,24 +99,122 @@ impl<'a> Editor<'a> {
         self
     }
 
-    pub fn build(mut self) -> Vec<u8> {
-        cbor::encode::emit_array(None, |a| {
-            let primary_block = self.blocks.remove(&0).expect("No primary block!");
-            let payload_block = self.blocks.remove(&1).expect("No payload block!");
+    // Clear the fragment flag and fragment info from the primary block, so that the first
+    // fragment of a bundle becomes the reassembled bundle once its payload is replaced
+    pub fn unfragment(mut self) -> Self {
+        self.fragment_info = Some(None);
+        self
+    }
 
-            // Emit primary block
-            self.build_block(0, primary_block, a);
+    // Set the fragment flag and fragment info of the primary block, so that the bundle
+    // becomes a fragment once its payload is replaced with the fragment's part of it
+    pub fn fragment(mut self, offset: u64, total_len: u64) -> Self {
+        self.fragment_info = Some(Some(FragmentInfo { offset, total_len }));
+        self
+    }
 
-            // Emit extension blocks
-            for (block_number, block) in std::mem::take(&mut self.blocks) {
-                self.build_block(block_number, block, a);
+    // Change the CRC type of every block, including the primary block, to the result of `f`
+    pub fn update_crc_types(mut self, f: impl Fn(CrcType) -> CrcType) -> Self {
+        for (block_number, template) in self.blocks.iter_mut() {
+            match template {
+                BlockTemplate::Keep(block_type) => {
+                    let current = self
+                        .original
+                        .blocks
+                        .get(block_number)
+                        .expect("Mismatched block in bundle!")
+                        .crc_type;
+                    let crc_type = f(current);
+                    if crc_type != current {
+                        *template = BlockTemplate::Recrc(*block_type, crc_type);
+                    }
+                }
+                BlockTemplate::Add(template) => {
+                    let crc_type = f(template.current_crc_type());
+                    template.crc_type(crc_type);
+                }
+                BlockTemplate::Recrc(_, crc_type) => *crc_type = f(*crc_type),
             }
+        }
+        self
+    }
+
+    pub fn build(mut self) -> Vec<u8> {
+        let payload_block = self.blocks.remove(&1).expect("No payload block!");
+        cbor::encode::emit_array(None, |a| {
+            self.build_leading_blocks(a);
 
             // Emit payload block
             self.build_block(1, payload_block, a);
         })
     }
 
+    /* Build the bundle around a payload of `payload_len` bytes that is too large to hold in
+     * memory.  Returns the bundle up to the start of the payload, and the PayloadStream that
+     * the payload is passed through before it gives the end of the bundle.  The payload
+     * block keeps the flags and CRC type of the original */
+    pub fn build_streamed(mut self, payload_len: u64) -> (Vec<u8>, PayloadStream) {
+        let payload_block = self.blocks.remove(&1).expect("No payload block!");
+        let original = self
+            .original
+            .blocks
+            .get(&1)
+            .expect("Mismatched block in bundle!");
+        let crc_type = match payload_block {
+            BlockTemplate::Recrc(_, crc_type) => crc_type,
+            _ => original.crc_type,
+        };
+
+        let mut data = cbor::encode::emit_array(None, |a| self.build_leading_blocks(a));
+
+        // The payload block follows, before the break that ends the bundle
+        data.pop();
+        let block_start = data.len();
+
+        // The header of a byte string is that of an unsigned integer of its length, in major type 2
+        let mut header = cbor::encode::emit(payload_len);
+        header[0] |= 2 << 5;
+
+        data.extend(cbor::encode::emit_array(
+            Some(if let CrcType::None = crc_type { 5 } else { 6 }),
+            |a| {
+                a.emit(BlockType::Payload);
+                a.emit(1u64);
+                a.emit(&original.flags);
+                a.emit(crc_type);
+                a.emit_raw(header);
+                if let CrcType::None = crc_type {
+                } else {
+                    a.skip_value();
+                }
+            },
+        ));
+
+        let mut crc = crc::StreamingCrc::new(crc_type);
+        crc.update(&data[block_start..]);
+        (
+            data,
+            PayloadStream {
+                crc,
+                remaining: payload_len,
+            },
+        )
+    }
+
+    // Emit the primary block, then the extension blocks in block number order, so the output is deterministic
+    fn build_leading_blocks(&mut self, array: &mut cbor::encode::Array) {
+        let primary_block = self.blocks.remove(&0).expect("No primary block!");
+        self.build_block(0, primary_block, array);
+
+        let mut blocks = std::mem::take(&mut self.blocks)
+            .into_iter()
+            .collect::<Vec<_>>();
+        blocks.sort_unstable_by_key(|(block_number, _)| *block_number);
+        for (block_number, block) in blocks {
+            self.build_block(block_number, block, array);
+        }
+    }
+
     fn build_block(
         &self,
         block_number: u64,
@@ -111,6 +222,18 @@ impl<'a> Editor<'a> {
         array: &mut cbor::encode::Array,
     ) {
         match template {
+            BlockTemplate::Keep(_) | BlockTemplate::Recrc(_, _)
+                if block_number == 0 && self.fragment_info.is_some() =>
+            {
+                let mut bundle = self.original.clone();
+                if let BlockTemplate::Recrc(_, crc_type) = template {
+                    bundle.crc_type = crc_type;
+                }
+                let fragment_info = self.fragment_info.clone().flatten();
+                bundle.flags.is_fragment = fragment_info.is_some();
+                bundle.id.fragment_info = fragment_info;
+                array.emit_raw(primary_block::PrimaryBlock::emit(&bundle));
+            }
             BlockTemplate::Keep(_) => {
                 self.original
                     .blocks
@@ -121,7 +244,48 @@ impl<'a> Editor<'a> {
             BlockTemplate::Add(template) => {
                 template.build(block_number, array);
             }
+            BlockTemplate::Recrc(_, crc_type) if block_number == 0 => {
+                let mut bundle = self.original.clone();
+                bundle.crc_type = crc_type;
+                array.emit_raw(primary_block::PrimaryBlock::emit(&bundle));
+            }
+            BlockTemplate::Recrc(_, crc_type) => {
+                let mut block = self
+                    .original
+                    .blocks
+                    .get(&block_number)
+                    .expect("Mismatched block in bundle!")
+                    .clone();
+                block.crc_type = crc_type;
+                block.rewrite(block_number, array, self.source_data);
+            }
+        }
+    }
+}
+
+// The payload of a bundle built by Editor::build_streamed(), passed through as it is written
+pub struct PayloadStream {
+    crc: crc::StreamingCrc,
+    remaining: u64,
+}
+
+impl PayloadStream {
+    pub fn update(&mut self, data: &[u8]) {
+        self.remaining = self
+            .remaining
+            .checked_sub(data.len() as u64)
+            .expect("Payload is longer than declared!");
+        self.crc.update(data);
+    }
+
+    // The end of the bundle, once all of the payload has been passed through
+    pub fn finish(self) -> Vec<u8> {
+        if self.remaining != 0 {
+            panic!("Payload is shorter than declared!");
         }
+        let mut data = self.crc.finish();
+        data.push(0xFF);
+        data
     }
 }
 
@@ -189,3 +353,36 @@ impl<'a> BlockBuilder<'a> {
         self.editor
     }
 }
+
+#[test]
+fn streamed() {
+    for crc_type in [
+        CrcType::None,
+        CrcType::CRC16_X25,
+        CrcType::CRC32_CASTAGNOLI,
+    ] {
+        let (bundle, data) = Builder::new()
+            .source("ipn:1.0".parse().unwrap())
+            .destination("ipn:2.0".parse().unwrap())
+            .crc_type(crc_type)
+            .add_payload_block(vec![1; 10])
+            .build();
+
+        let payload = (0..1000u32).map(|n| n as u8).collect::<Vec<_>>();
+        let expected = Editor::new(&bundle, &data)
+            .replace_extension_block(BlockType::Payload)
+            .crc_type(crc_type)
+            .data(payload.clone())
+            .build()
+            .build();
+
+        let (mut streamed, mut stream) =
+            Editor::new(&bundle, &data).build_streamed(payload.len() as u64);
+        for chunk in payload.chunks(300) {
+            stream.update(chunk);
+            streamed.extend_from_slice(chunk);
+        }
+        streamed.extend(stream.finish());
+        assert_eq!(streamed, expected);
+    }
+}
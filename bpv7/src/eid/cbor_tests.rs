@@ -13,6 +13,18 @@ fn tests() {
     null_check(&hex!("82 02 82 00 00"));
     null_check(&hex!("82 02 83 00 00 00"));
 
+    local_node_check(&hex!("82 02 82 1A FFFFFFFF 07"), 7);
+    local_node_check(&hex!("82 02 83 00 1A FFFFFFFF 07"), 7);
+    ipn_check(&hex!("82 02 83 01 1A FFFFFFFF 07"), 1, u32::MAX, 7);
+
+    // Encoding
+    encode_check("ipn:1.1", &hex!("82 02 82 01 01"));
+    encode_check("ipn:0.1.1", &hex!("82 02 82 01 01"));
+    encode_check("ipn:977000.1.1", &hex!("82 02 83 1A 000EE868 01 01"));
+    encode_check("ipn:977000.0.1", &hex!("82 02 83 1A 000EE868 00 01"));
+    encode_check("ipn:!.7", &hex!("82 02 82 1A FFFFFFFF 07"));
+    encode_check("dtn:none", &hex!("82 01 00"));
+
     // TODO: Add dtn tests
 
     // Negative tests
@@ -65,6 +77,21 @@ fn null_check(data: &[u8]) {
     );
 }
 
+fn local_node_check(data: &[u8], expected_service_number: u32) {
+    let Eid::LocalNode { service_number } = cbor::decode::parse(data).expect("Failed to parse")
+    else {
+        panic!("Not a LocalNode EID!")
+    };
+    assert_eq!(expected_service_number, service_number);
+}
+
+fn encode_check(s: &str, expected: &[u8]) {
+    assert_eq!(
+        cbor::encode::emit(&s.parse::<Eid>().expect("Failed to parse")),
+        expected
+    );
+}
+
 fn ipn_check_legacy(
     data: &[u8],
     expected_allocator_id: u32,
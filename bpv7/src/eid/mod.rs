@@ -10,8 +10,15 @@ mod str_tests;
 #[cfg(test)]
 mod cbor_tests;
 
+#[cfg(test)]
+mod roundtrip_tests;
+
 pub use error::EidError;
 
+/* ipn EIDs are written and encoded in their 2-element form when the allocator is 0, and in
+ * their 3-element form otherwise.  LocalNode is node 4294967295 of allocator 0, written as
+ * ipn:!.S.  Whatever text an Eid is parsed from, it encodes to CBOR that decodes to the same
+ * Eid, and is written out as text that parses to it again */
 #[derive(Default, Clone, Hash, PartialEq, Eq, PartialOrd, Ord)]
 pub enum Eid {
     #[default]
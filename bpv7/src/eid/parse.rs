@@ -62,30 +62,38 @@ fn ipn_from_parts(
     }
 }
 
+// The text form only allows the canonical spelling of a number, without a sign or leading zeros,
+// so that every ipn URI means a different EID
+fn ipn_number(s: &str, field: &'static str) -> Result<u32, EidError> {
+    if s.starts_with('+') || (s.len() > 1 && s.starts_with('0')) {
+        return Err("not a canonical number").map_field_err(field);
+    }
+    s.parse().map_field_err(field)
+}
+
 fn ipn_from_str(s: &str) -> Result<Eid, EidError> {
     let parts = s.split('.').collect::<Vec<&str>>();
-    if parts.len() == 2 {
+    let (allocator_id, node_number, service_number) = if parts.len() == 2 {
         let mut node_number = u32::MAX;
         if parts[0] != "!" {
-            node_number = parts[0].parse().map_field_err("node number")?;
+            node_number = ipn_number(parts[0], "node number")?;
         }
-        ipn_from_parts(
-            3,
-            0,
-            node_number,
-            parts[1].parse().map_field_err("service number")?,
-        )
-        .map(|e| e.0)
+        (0, node_number, ipn_number(parts[1], "service number")?)
     } else if parts.len() == 3 {
-        ipn_from_parts(
-            3,
-            parts[0].parse().map_field_err("allocator identifier")?,
-            parts[1].parse().map_field_err("node number")?,
-            parts[2].parse().map_field_err("service number")?,
+        (
+            ipn_number(parts[0], "allocator identifier")?,
+            ipn_number(parts[1], "node number")?,
+            ipn_number(parts[2], "service number")?,
         )
-        .map(|e| e.0)
     } else {
         return Err(EidError::IpnInvalidComponents);
+    };
+
+    // Only ipn:0.0 and ipn:0.0.0 are the null endpoint, any other service of it is an error
+    // here, as it would not survive being written out again
+    match ipn_from_parts(3, allocator_id, node_number, service_number)? {
+        (eid, true) => Ok(eid),
+        (_, false) => Err(EidError::IpnInvalidServiceNumber(service_number as u64)),
     }
 }
 
@@ -0,0 +1,62 @@
+use super::*;
+use proptest::prelude::*;
+
+// Numbers at the edges of the ipn scheme, as well as anywhere in between
+fn number() -> impl Strategy<Value = u32> {
+    prop_oneof![
+        Just(0),
+        Just(1),
+        Just(u32::MAX - 1),
+        Just(u32::MAX),
+        any::<u32>()
+    ]
+}
+
+fn ipn_uri() -> impl Strategy<Value = String> {
+    prop_oneof![
+        (number(), number(), number()).prop_map(|(a, n, s)| format!("ipn:{a}.{n}.{s}")),
+        (number(), number()).prop_map(|(n, s)| format!("ipn:{n}.{s}")),
+        number().prop_map(|s| format!("ipn:!.{s}")),
+    ]
+}
+
+fn dtn_uri() -> impl Strategy<Value = String> {
+    (
+        "[a-z0-9.-]{1,16}",
+        "[a-z0-9%-]{0,8}(/[a-z0-9%-]{1,8}){0,3}/?",
+    )
+        .prop_map(|(node_name, demux)| format!("dtn://{node_name}/{demux}"))
+}
+
+// Text that parses survives being encoded and decoded, and is written out the same each time
+fn check(s: &str) -> Result<(), TestCaseError> {
+    let Ok(eid) = s.parse::<Eid>() else {
+        return Ok(());
+    };
+
+    let data = cbor::encode::emit(&eid);
+    let (decoded, canonical, len) = <Eid as cbor::decode::FromCbor>::try_from_cbor(&data)
+        .expect("Failed to decode")
+        .expect("Not enough data");
+    prop_assert!(canonical);
+    prop_assert_eq!(len, data.len());
+    prop_assert_eq!(&decoded, &eid);
+
+    let text = decoded.to_string();
+    let reparsed = text.parse::<Eid>().expect("Failed to reparse");
+    prop_assert_eq!(&reparsed, &eid);
+    prop_assert_eq!(reparsed.to_string(), text);
+    Ok(())
+}
+
+proptest! {
+    #[test]
+    fn ipn(s in ipn_uri()) {
+        check(&s)?;
+    }
+
+    #[test]
+    fn dtn(s in dtn_uri()) {
+        check(&s)?;
+    }
+}
@@ -9,9 +9,14 @@ fn tests() {
     ipn_check("ipn:0.1.0", 0, 1, 0);
     ipn_check("ipn:977000.1.3", 977000, 1, 3);
     ipn_check("ipn:977000.1.0", 977000, 1, 0);
+    ipn_check("ipn:977000.0.1", 977000, 0, 1);
+    ipn_check("ipn:977000.4294967295.1", 977000, u32::MAX, 1);
+    ipn_check("ipn:4294967295.1.2", u32::MAX, 1, 2);
 
     local_node_check("ipn:!.7", 7);
     local_node_check("ipn:!.0", 0);
+    local_node_check("ipn:4294967295.7", 7);
+    local_node_check("ipn:0.4294967295.7", 7);
 
     null_check("ipn:0.0");
     null_check("ipn:0.0.0");
@@ -85,6 +90,23 @@ fn tests() {
         EidError::IpnInvalidComponents
     ));
 
+    assert!(matches!(
+        expect_error("ipn:0.5"),
+        EidError::IpnInvalidServiceNumber(5)
+    ));
+    assert!(matches!(
+        expect_error("ipn:0.0.5"),
+        EidError::IpnInvalidServiceNumber(5)
+    ));
+    assert!(
+        matches!(expect_error("ipn:01.2"), EidError::InvalidField{ field, ..} if field == "node number")
+    );
+    assert!(
+        matches!(expect_error("ipn:1.+2"), EidError::InvalidField{ field, ..} if field == "service number")
+    );
+    assert!(
+        matches!(expect_error("ipn:00.1.2"), EidError::InvalidField{ field, ..} if field == "allocator identifier")
+    );
     assert!(
         matches!(expect_error("ipn:11111111111111111111111111111.222222222222222222222222222222"), EidError::InvalidField{ field, ..} if field == "node number")
     );
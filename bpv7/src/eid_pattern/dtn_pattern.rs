@@ -22,6 +22,24 @@ impl DtnPatternItem {
         }
     }
 
+    // Whether every EID matching `other` matches this
+    pub fn covers(&self, other: &Self) -> bool {
+        match (self, other) {
+            (DtnPatternItem::None, DtnPatternItem::None) => true,
+            (DtnPatternItem::DtnSsp(s), DtnPatternItem::DtnSsp(o)) => s.covers(o),
+            _ => false,
+        }
+    }
+
+    // Whether any EID may match both this and `other`
+    pub fn intersects(&self, other: &Self) -> bool {
+        match (self, other) {
+            (DtnPatternItem::None, DtnPatternItem::None) => true,
+            (DtnPatternItem::DtnSsp(s), DtnPatternItem::DtnSsp(o)) => s.intersects(o),
+            _ => false,
+        }
+    }
+
     pub(super) fn is_exact(&self) -> Option<Eid> {
         match self {
             DtnPatternItem::None => Some(Eid::Null),
@@ -97,6 +115,88 @@ impl DtnSsp {
         }
     }
 
+    // The pattern for the demux part at `idx`, or None if any will do
+    fn demux_pattern(&self, idx: usize) -> Option<&DtnSinglePattern> {
+        match idx.cmp(&self.singles.len()) {
+            std::cmp::Ordering::Less => Some(&self.singles[idx]),
+            std::cmp::Ordering::Equal => match &self.last {
+                DtnLastPattern::Single(p) => Some(p),
+                DtnLastPattern::MultiWildcard => None,
+            },
+            std::cmp::Ordering::Greater => None,
+        }
+    }
+
+    // The most demux parts matched, if there is a limit
+    fn max_demux(&self) -> Option<usize> {
+        match self.last {
+            DtnLastPattern::Single(_) => Some(self.singles.len() + 1),
+            DtnLastPattern::MultiWildcard => None,
+        }
+    }
+
+    fn covers(&self, other: &Self) -> bool {
+        // A multi-wildcard authority matches every dtn EID, whatever follows it
+        match (&self.authority, &other.authority) {
+            (DtnAuthPattern::MultiWildcard, _) => return true,
+            (_, DtnAuthPattern::MultiWildcard) => return false,
+            (DtnAuthPattern::PatternMatch(s), DtnAuthPattern::PatternMatch(o)) => {
+                if !s.covers(o) {
+                    return false;
+                }
+            }
+        }
+
+        match (&self.last, &other.last) {
+            (DtnLastPattern::Single(s), DtnLastPattern::Single(o)) => {
+                self.singles.len() == other.singles.len()
+                    && self
+                        .singles
+                        .iter()
+                        .zip(&other.singles)
+                        .all(|(s, o)| s.covers(o))
+                    && s.covers(o)
+            }
+            (DtnLastPattern::Single(_), DtnLastPattern::MultiWildcard) => false,
+            (DtnLastPattern::MultiWildcard, _) => {
+                self.singles.len() <= other.singles.len()
+                    && self
+                        .singles
+                        .iter()
+                        .zip(&other.singles)
+                        .all(|(s, o)| s.covers(o))
+            }
+        }
+    }
+
+    fn intersects(&self, other: &Self) -> bool {
+        if let (DtnAuthPattern::PatternMatch(s), DtnAuthPattern::PatternMatch(o)) =
+            (&self.authority, &other.authority)
+        {
+            if !s.intersects(o) {
+                return false;
+            }
+
+            // There must be a number of demux parts both match...
+            let min = self.singles.len().max(other.singles.len()) + 1;
+            if self.max_demux().is_some_and(|max| max < min)
+                || other.max_demux().is_some_and(|max| max < min)
+            {
+                return false;
+            }
+
+            // ... and something both match in each part
+            for idx in 0..min {
+                if let (Some(s), Some(o)) = (self.demux_pattern(idx), other.demux_pattern(idx)) {
+                    if !s.intersects(o) {
+                        return false;
+                    }
+                }
+            }
+        }
+        true
+    }
+
     fn is_exact(&self) -> Option<Eid> {
         let node_name = self.authority.is_exact()?;
         let mut demux = self.singles.iter().try_fold(Vec::new(), |mut v, s| {
@@ -228,6 +328,23 @@ impl DtnSinglePattern {
         }
     }
 
+    fn covers(&self, other: &Self) -> bool {
+        match (self, other) {
+            (DtnSinglePattern::Wildcard, _) => true,
+            (_, DtnSinglePattern::Wildcard) => false,
+            (DtnSinglePattern::PatternMatch(s), DtnSinglePattern::PatternMatch(o)) => s.covers(o),
+        }
+    }
+
+    fn intersects(&self, other: &Self) -> bool {
+        match (self, other) {
+            (DtnSinglePattern::PatternMatch(s), DtnSinglePattern::PatternMatch(o)) => {
+                s.intersects(o)
+            }
+            _ => true,
+        }
+    }
+
     fn is_exact(&self) -> Option<Box<str>> {
         match self {
             DtnSinglePattern::PatternMatch(p) => p.is_exact(),
@@ -282,6 +399,27 @@ impl PatternMatch {
         }
     }
 
+    // Regular expressions are only compared by their text, so one only covers the same
+    // expression, or the exact names it matches
+    fn covers(&self, other: &Self) -> bool {
+        match (self, other) {
+            (PatternMatch::Exact(s), PatternMatch::Exact(o)) => s == o,
+            (PatternMatch::Regex(r), PatternMatch::Exact(o)) => r.is_match(o),
+            (PatternMatch::Regex(r), PatternMatch::Regex(o)) => r.as_str() == o.as_str(),
+            (PatternMatch::Exact(_), PatternMatch::Regex(_)) => false,
+        }
+    }
+
+    // Two different regular expressions are assumed to match something in common
+    fn intersects(&self, other: &Self) -> bool {
+        match (self, other) {
+            (PatternMatch::Exact(s), PatternMatch::Exact(o)) => s == o,
+            (PatternMatch::Regex(r), PatternMatch::Exact(s))
+            | (PatternMatch::Exact(s), PatternMatch::Regex(r)) => r.is_match(s),
+            (PatternMatch::Regex(_), PatternMatch::Regex(_)) => true,
+        }
+    }
+
     fn is_exact(&self) -> Option<Box<str>> {
         match self {
             PatternMatch::Exact(s) => Some(s.clone()),
@@ -47,6 +47,20 @@ impl IpnPatternItem {
         }
     }
 
+    // Whether every EID matching `other` matches this
+    pub fn covers(&self, other: &Self) -> bool {
+        self.allocator_id.covers(&other.allocator_id)
+            && self.node_number.covers(&other.node_number)
+            && self.service_number.covers(&other.service_number)
+    }
+
+    // Whether any EID matches both this and `other`
+    pub fn intersects(&self, other: &Self) -> bool {
+        self.allocator_id.intersects(&other.allocator_id)
+            && self.node_number.intersects(&other.node_number)
+            && self.service_number.intersects(&other.service_number)
+    }
+
     pub(super) fn is_exact(&self) -> Option<Eid> {
         Some(Eid::Ipn {
             allocator_id: self.allocator_id.is_exact()?,
@@ -114,6 +128,40 @@ impl IpnPattern {
         }
     }
 
+    // The intervals matched, in order
+    fn bounds(&self) -> Vec<(u32, u32)> {
+        match self {
+            IpnPattern::Range(r) => {
+                let mut bounds = r.iter().map(IpnInterval::bounds).collect::<Vec<_>>();
+                bounds.sort();
+                bounds
+            }
+            IpnPattern::Wildcard => vec![(0, u32::MAX)],
+        }
+    }
+
+    fn covers(&self, other: &Self) -> bool {
+        let bounds = self.bounds();
+        other.bounds().into_iter().all(|(start, end)| {
+            // Walk along the interval, across as many of ours as it takes
+            let mut next = start as u64;
+            for (s, e) in &bounds {
+                if *s as u64 <= next && *e as u64 >= next {
+                    next = *e as u64 + 1;
+                }
+            }
+            next > end as u64
+        })
+    }
+
+    fn intersects(&self, other: &Self) -> bool {
+        let bounds = self.bounds();
+        other
+            .bounds()
+            .into_iter()
+            .any(|(start, end)| bounds.iter().any(|(s, e)| *s <= end && start <= *e))
+    }
+
     fn is_exact(&self) -> Option<u32> {
         match self {
             IpnPattern::Range(r) => {
@@ -298,6 +346,13 @@ impl IpnInterval {
         }
     }
 
+    fn bounds(&self) -> (u32, u32) {
+        match self {
+            IpnInterval::Number(n) => (*n, *n),
+            IpnInterval::Range(r) => (*r.start(), *r.end()),
+        }
+    }
+
     fn is_exact(&self) -> Option<u32> {
         match self {
             IpnInterval::Number(n) => Some(*n),
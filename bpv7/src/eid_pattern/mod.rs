@@ -7,6 +7,9 @@ mod ipn_pattern;
 #[cfg(test)]
 mod str_tests;
 
+#[cfg(test)]
+mod set_tests;
+
 use error::Span;
 
 pub use dtn_pattern::*;
@@ -19,6 +22,12 @@ pub enum EidPattern {
     Any,
 }
 
+/* Patterns can be compared as the sets of EIDs they match, so configuration can spot entries
+ * that will never be used, or that overlap.  covers() and intersects() are exact for ipn
+ * patterns and for dtn patterns of exact names.  A regular expression is only compared by
+ * its text, so it only covers the same expression or the names it matches, and two different
+ * expressions are assumed to overlap.  A set covers another if each of its items is covered
+ * by one item of the set, so a pattern split across several items may go unnoticed */
 impl EidPattern {
     pub fn is_match(&self, eid: &Eid) -> bool {
         match self {
@@ -27,6 +36,45 @@ impl EidPattern {
         }
     }
 
+    // Whether every EID matching `other` matches this
+    pub fn covers(&self, other: &Self) -> bool {
+        match (self, other) {
+            (EidPattern::Any, _) => true,
+            (EidPattern::Set(_), EidPattern::Any) => false,
+            (EidPattern::Set(items), EidPattern::Set(others)) => {
+                others.iter().all(|o| items.iter().any(|i| i.covers(o)))
+            }
+        }
+    }
+
+    // Whether any EID may match both this and `other`
+    pub fn intersects(&self, other: &Self) -> bool {
+        match (self, other) {
+            (EidPattern::Any, EidPattern::Set(items))
+            | (EidPattern::Set(items), EidPattern::Any) => !items.is_empty(),
+            (EidPattern::Any, EidPattern::Any) => true,
+            (EidPattern::Set(items), EidPattern::Set(others)) => {
+                others.iter().any(|o| items.iter().any(|i| i.intersects(o)))
+            }
+        }
+    }
+
+    // The pattern matching every EID either matches, without items another covers
+    pub fn union(&self, other: &Self) -> Self {
+        let (EidPattern::Set(items), EidPattern::Set(others)) = (self, other) else {
+            return EidPattern::Any;
+        };
+
+        let mut union: Vec<EidPatternItem> = Vec::new();
+        for item in items.iter().chain(others.iter()) {
+            if !union.iter().any(|i| i.covers(item)) {
+                union.retain(|i| !item.covers(i));
+                union.push(item.clone());
+            }
+        }
+        EidPattern::Set(union.into())
+    }
+
     pub(super) fn is_exact(&self) -> Option<Eid> {
         match self {
             EidPattern::Any => None,
@@ -191,6 +239,30 @@ impl EidPatternItem {
         }
     }
 
+    fn covers(&self, other: &Self) -> bool {
+        match (self, other) {
+            (EidPatternItem::IpnPatternItem(i), EidPatternItem::IpnPatternItem(o)) => i.covers(o),
+            (EidPatternItem::DtnPatternItem(i), EidPatternItem::DtnPatternItem(o)) => i.covers(o),
+            (EidPatternItem::AnyNumericScheme(i), EidPatternItem::AnyNumericScheme(o)) => i == o,
+            (EidPatternItem::AnyTextScheme(i), EidPatternItem::AnyTextScheme(o)) => {
+                i.eq_ignore_ascii_case(o)
+            }
+            _ => false,
+        }
+    }
+
+    fn intersects(&self, other: &Self) -> bool {
+        match (self, other) {
+            (EidPatternItem::IpnPatternItem(i), EidPatternItem::IpnPatternItem(o)) => {
+                i.intersects(o)
+            }
+            (EidPatternItem::DtnPatternItem(i), EidPatternItem::DtnPatternItem(o)) => {
+                i.intersects(o)
+            }
+            _ => self.covers(other),
+        }
+    }
+
     pub(super) fn is_exact(&self) -> Option<Eid> {
         match self {
             EidPatternItem::IpnPatternItem(i) => i.is_exact(),
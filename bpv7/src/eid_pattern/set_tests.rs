@@ -0,0 +1,88 @@
+use super::*;
+
+fn pattern(s: &str) -> EidPattern {
+    s.parse().expect("Failed to parse")
+}
+
+fn covers(a: &str, b: &str) -> bool {
+    pattern(a).covers(&pattern(b))
+}
+
+fn intersects(a: &str, b: &str) -> bool {
+    let r = pattern(a).intersects(&pattern(b));
+    assert_eq!(r, pattern(b).intersects(&pattern(a)), "{a} and {b}");
+    r
+}
+
+#[test]
+fn ipn() {
+    assert!(covers("ipn:*.*", "ipn:1.2"));
+    assert!(covers("ipn:**", "ipn:977000.1.2"));
+    assert!(covers("ipn:1.*", "ipn:1.[0-99]"));
+    assert!(covers("ipn:1.[0-9,10-20]", "ipn:1.[5-15]"));
+    assert!(covers("ipn:1.[0-9,20-29]", "ipn:1.[3,25]"));
+    assert!(!covers("ipn:1.[0-9,20-29]", "ipn:1.[5-25]"));
+    assert!(!covers("ipn:1.2", "ipn:1.*"));
+    assert!(!covers("ipn:1.*", "ipn:977000.1.*"));
+    assert!(covers("ipn:0.*.*", "ipn:0.4294967295.7"));
+
+    assert!(intersects("ipn:1.[0-9]", "ipn:1.[9-20]"));
+    assert!(intersects("ipn:*.*.7", "ipn:977000.1.*"));
+    assert!(!intersects("ipn:1.[0-9]", "ipn:1.[10-20]"));
+    assert!(!intersects("ipn:1.*", "ipn:2.*"));
+    assert!(!intersects("ipn:0.1.*", "ipn:977000.1.*"));
+}
+
+#[test]
+fn dtn() {
+    assert!(covers("dtn://**/**", "dtn://node/a/b"));
+    assert!(covers("dtn://node/**", "dtn://node/a/b"));
+    assert!(covers("dtn://node/a/**", "dtn://node/a/b/c"));
+    assert!(covers("dtn://node/*/b", "dtn://node/a/b"));
+    assert!(covers("dtn://[^n.*]/a", "dtn://node/a"));
+    assert!(covers("dtn://[^n.*]/a", "dtn://[^n.*]/a"));
+    assert!(!covers("dtn://node/a/b", "dtn://node/a/**"));
+    assert!(!covers("dtn://node/a/**", "dtn://node/a"));
+    assert!(!covers("dtn://node/a", "dtn://node/a/b"));
+    assert!(!covers("dtn://[^n.*]/a", "dtn://[^no.*]/a"));
+    assert!(!covers("dtn://node/**", "dtn://**/**"));
+    assert!(covers("dtn:none", "dtn:none"));
+    assert!(!covers("dtn://**/**", "dtn:none"));
+
+    assert!(intersects("dtn://node/a/**", "dtn://node/*/b"));
+    assert!(intersects("dtn://[^n.*]/a", "dtn://node/**"));
+    assert!(intersects("dtn://[^n.*]/a", "dtn://[^no.*]/a"));
+    assert!(!intersects("dtn://node/a", "dtn://other/a"));
+    assert!(!intersects("dtn://node/a", "dtn://node/a/b"));
+    assert!(!intersects("dtn://node/a/b/**", "dtn://node/*/c"));
+    assert!(!intersects("dtn://[^n.*]/a", "dtn://other/a"));
+    assert!(!intersects("dtn:none", "dtn://**/**"));
+}
+
+#[test]
+fn sets() {
+    assert!(covers("*:**", "ipn:1.2|dtn://node/a"));
+    assert!(!covers("ipn:**|dtn:**", "*:**"));
+    assert!(covers("ipn:1.*|dtn://node/**", "dtn://node/a|ipn:1.2"));
+    assert!(!covers("ipn:1.*|dtn://node/**", "dtn://node/a|ipn:2.2"));
+    assert!(covers("spaniel:**|7:**", "SPANIEL:**"));
+    assert!(!covers("7:**", "8:**"));
+
+    assert!(intersects("*:**", "ipn:1.2"));
+    assert!(intersects("ipn:1.*|dtn://node/**", "ipn:2.*|dtn://node/a"));
+    assert!(!intersects(
+        "ipn:1.*|dtn://node/**",
+        "ipn:2.*|dtn://other/a"
+    ));
+    assert!(!intersects("ipn:**", "dtn:**"));
+
+    assert_eq!(
+        pattern("ipn:1.2|dtn://node/a").union(&pattern("ipn:1.*|ipn:3.4")),
+        pattern("dtn://node/a|ipn:1.*|ipn:3.4")
+    );
+    assert_eq!(
+        pattern("ipn:1.*").union(&pattern("ipn:1.2")),
+        pattern("ipn:1.*")
+    );
+    assert_eq!(pattern("ipn:1.*").union(&pattern("*:**")), EidPattern::Any);
+}
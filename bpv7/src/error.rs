@@ -42,6 +42,15 @@ pub enum Error {
     #[error("Block {0} is not in canonical form")]
     NonCanonical(u64),
 
+    #[error("{0} blocks cannot be registered")]
+    ReservedBlockType(BlockType),
+
+    #[error("Block type {0} is already registered")]
+    DuplicateBlockType(u64),
+
+    #[error("Block type {0} is not registered for this value type")]
+    UnregisteredBlockType(u64),
+
     #[error(transparent)]
     InvalidBPSec(#[from] bpsec::Error),
 
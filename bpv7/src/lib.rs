@@ -2,6 +2,7 @@ use hardy_cbor as cbor;
 
 mod block;
 mod block_flags;
+mod block_registry;
 mod block_type;
 mod bpsec;
 mod builder;
@@ -23,26 +24,28 @@ mod status_report;
 pub mod prelude {
     pub use super::block::Block;
     pub use super::block_flags::BlockFlags;
+    pub use super::block_registry::BlockRegistry;
     pub use super::block_type::BlockType;
     pub use super::builder::Builder;
     pub use super::bundle::{Bundle, ValidBundle};
     pub use super::bundle_flags::BundleFlags;
-    pub use super::bundle_id::{BundleId, FragmentInfo};
-    pub use super::crc::CrcType;
+    pub use super::bundle_id::{BundleId, BundleIdError, FragmentInfo};
+    pub use super::crc::{CrcReport, CrcType};
     pub use super::creation_timestamp::CreationTimestamp;
     pub use super::dtn_time::DtnTime;
-    pub use super::editor::Editor;
+    pub use super::editor::{Editor, PayloadStream};
     pub use super::eid::{Eid, EidError};
     pub use super::eid_pattern::{EidPattern, EidPatternError};
     pub use super::eid_pattern_map::EidPatternMap;
     pub use super::error::Error;
     pub use super::hop_info::HopInfo;
     pub use super::status_report::{
-        AdministrativeRecord, BundleStatusReport, StatusAssertion, StatusReportError,
-        StatusReportReasonCode,
+        AdministrativeRecord, BibeRecord, BundleStatusReport, DiagnosticRecord, StatusAssertion,
+        StatusReportError, StatusReportReasonCode,
     };
 
     pub mod bpsec {
+        pub use super::super::bpsec::bib::FailurePolicy as BibFailurePolicy;
         pub use super::super::bpsec::{Context, Error, KeyMaterial};
     }
 }
@@ -293,9 +293,176 @@ impl cbor::decode::FromCbor for BundleStatusReport {
     }
 }
 
+/* A Hardy-specific administrative record, carrying a human-readable description of a problem
+ * found in a received bundle back to the node that sent it */
+const DIAGNOSTIC_RECORD_TYPE: u64 = 0xFFFF;
+
+#[derive(Default, Debug, Clone)]
+pub struct DiagnosticRecord {
+    pub bundle_id: BundleId,
+    pub message: String,
+}
+
+impl cbor::encode::ToCbor for &DiagnosticRecord {
+    fn to_cbor(self, encoder: &mut cbor::encode::Encoder) {
+        encoder.emit_array(
+            Some(self.bundle_id.fragment_info.as_ref().map_or(3, |_| 5)),
+            |a| {
+                // Source EID
+                a.emit(&self.bundle_id.source);
+                // Creation Timestamp
+                a.emit(&self.bundle_id.timestamp);
+
+                if let Some(fragment_info) = &self.bundle_id.fragment_info {
+                    // Add fragment info
+                    a.emit(fragment_info.offset);
+                    a.emit(fragment_info.total_len);
+                }
+
+                a.emit(self.message.as_str());
+            },
+        )
+    }
+}
+
+impl cbor::decode::FromCbor for DiagnosticRecord {
+    type Error = StatusReportError;
+
+    fn try_from_cbor(data: &[u8]) -> Result<Option<(Self, bool, usize)>, Self::Error> {
+        cbor::decode::try_parse_array(data, |a, mut shortest, tags| {
+            shortest = shortest && tags.is_empty() && a.is_definite();
+
+            let source = a
+                .parse()
+                .map(|(v, s)| {
+                    shortest = shortest && s;
+                    v
+                })
+                .map_field_err("source")?;
+
+            let timestamp = a
+                .parse()
+                .map(|(v, s)| {
+                    shortest = shortest && s;
+                    v
+                })
+                .map_field_err("timestamp")?;
+
+            let fragment_info = if let Some(5) = a.count() {
+                Some(FragmentInfo {
+                    offset: a.parse().map_field_err("fragment offset")?,
+                    total_len: a.parse().map_field_err("fragment length")?,
+                })
+            } else {
+                None
+            };
+
+            let message = a
+                .parse_value(|value, s, tags| {
+                    shortest = shortest && s && tags.is_empty();
+                    match value {
+                        cbor::decode::Value::Text(s) => Ok(s.to_string()),
+                        value => Err(cbor::decode::Error::IncorrectType(
+                            "Text String".to_string(),
+                            value.type_name(!tags.is_empty()),
+                        )),
+                    }
+                })
+                .map_field_err("message")?;
+
+            Ok((
+                Self {
+                    bundle_id: BundleId {
+                        source,
+                        timestamp,
+                        fragment_info,
+                    },
+                    message,
+                },
+                shortest,
+            ))
+        })
+        .map(|o| o.map(|((v, s), len)| (v, s, len)))
+    }
+}
+
+/* A Bundle-in-Bundle Encapsulation (BIBE) protocol data unit, carrying a complete
+ * bundle through a tunnel to a decapsulating node, as described in draft-ietf-dtn-bibect */
+const BIBE_RECORD_TYPE: u64 = 3;
+
+#[derive(Default, Debug, Clone)]
+pub struct BibeRecord {
+    pub transmission_id: u64,
+    // Zero if custody transfer is not requested
+    pub retransmission_time: u64,
+    pub bundle: Box<[u8]>,
+}
+
+impl cbor::encode::ToCbor for &BibeRecord {
+    fn to_cbor(self, encoder: &mut cbor::encode::Encoder) {
+        encoder.emit_array(Some(3), |a| {
+            a.emit(self.transmission_id);
+            a.emit(self.retransmission_time);
+            a.emit(self.bundle.as_ref());
+        })
+    }
+}
+
+impl cbor::decode::FromCbor for BibeRecord {
+    type Error = StatusReportError;
+
+    fn try_from_cbor(data: &[u8]) -> Result<Option<(Self, bool, usize)>, Self::Error> {
+        cbor::decode::try_parse_array(data, |a, mut shortest, tags| {
+            shortest = shortest && tags.is_empty() && a.is_definite();
+
+            let transmission_id = a
+                .parse()
+                .map(|(v, s)| {
+                    shortest = shortest && s;
+                    v
+                })
+                .map_field_err("transmission id")?;
+
+            let retransmission_time = a
+                .parse()
+                .map(|(v, s)| {
+                    shortest = shortest && s;
+                    v
+                })
+                .map_field_err("retransmission time")?;
+
+            let bundle = a
+                .parse_value(|value, s, tags| {
+                    shortest = shortest && s && tags.is_empty();
+                    match value {
+                        cbor::decode::Value::Bytes(data) => Ok(data.into()),
+                        cbor::decode::Value::ByteStream(data) => Ok(data.concat().into()),
+                        value => Err(cbor::decode::Error::IncorrectType(
+                            "Byte String".to_string(),
+                            value.type_name(!tags.is_empty()),
+                        )),
+                    }
+                })
+                .map_field_err("encapsulated bundle")?;
+
+            Ok((
+                Self {
+                    transmission_id,
+                    retransmission_time,
+                    bundle,
+                },
+                shortest,
+            ))
+        })
+        .map(|o| o.map(|((v, s), len)| (v, s, len)))
+    }
+}
+
 #[derive(Debug)]
 pub enum AdministrativeRecord {
     BundleStatusReport(BundleStatusReport),
+    BundleInBundle(BibeRecord),
+    Diagnostic(DiagnosticRecord),
 }
 
 impl cbor::encode::ToCbor for &AdministrativeRecord {
@@ -305,6 +472,14 @@ impl cbor::encode::ToCbor for &AdministrativeRecord {
                 a.emit(1);
                 a.emit(report);
             }
+            AdministrativeRecord::BundleInBundle(record) => {
+                a.emit(BIBE_RECORD_TYPE);
+                a.emit(record);
+            }
+            AdministrativeRecord::Diagnostic(record) => {
+                a.emit(DIAGNOSTIC_RECORD_TYPE);
+                a.emit(record);
+            }
         })
     }
 }
@@ -328,6 +503,14 @@ impl cbor::decode::FromCbor for AdministrativeRecord {
                     let (r, s) = a.parse().map_field_err("bundle status report")?;
                     Ok((Self::BundleStatusReport(r), shortest && s))
                 }
+                BIBE_RECORD_TYPE => {
+                    let (r, s) = a.parse().map_field_err("bundle-in-bundle record")?;
+                    Ok((Self::BundleInBundle(r), shortest && s))
+                }
+                DIAGNOSTIC_RECORD_TYPE => {
+                    let (r, s) = a.parse().map_field_err("diagnostic record")?;
+                    Ok((Self::Diagnostic(r), shortest && s))
+                }
                 v => Err(StatusReportError::UnknownAdminRecordType(v)),
             }
         })
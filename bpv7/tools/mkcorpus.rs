@@ -0,0 +1,568 @@
+use clap::Parser;
+use hardy_bpv7::prelude::*;
+use std::{io::Write, path::PathBuf};
+
+/* Generates a corpus of bundles, as one file per bundle under 'valid', 'noncanonical' and
+ * 'invalid' directories, with a MANIFEST describing each.  The bundles are built with fixed
+ * creation timestamps, so the corpus is the same every time it is generated, and every
+ * bundle is checked against the parser before it is written.
+ *
+ * The BPSec bundles are the examples of RFC 9173 Appendix A, and are checked with the keys
+ * given there: 1a2b1a2b1a2b1a2b1a2b1a2b1a2b1a2b for every BIB, and for the BCBs
+ * 6162636465666768696a6b6c6d6e6f70 (A.2), 71776572747975696f70617364666768 (A.3) and the
+ * same key twice over (A.4) */
+
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// The directory to write the corpus to
+    #[arg(short, long)]
+    output: PathBuf,
+}
+
+const RFC9173_A_1: &str = "
+    9f89070001820282010282028202018202820201820118281a000f424042e4fe850b0200
+    005856810101018202820201828201078203008181820158403bdc69b3a34a2b5d3a
+    8554368bd1e808f606219d2a10a846eae3886ae4ecc83c4ee550fdfb1cc636b904e2
+    f1a73e303dcd4b6ccece003e95e8164dcc89a156e185010100005823526561647920
+    746f2067656e657261746520612033322d62797465207061796c6f6164ff";
+
+const RFC9173_A_2: &str = "
+    9f89070001820282010282028202018202820201820118281a000f424042e4fe850c0201
+    0058508101020182028202018482014c5477656c7665313231323132820201820358
+    1869c411276fecddc4780df42c8a2af89296fabf34d7fae7008204008181820150ef
+    a4b5ac0108e3816c5606479801bc04850101000058233a09c1e63fe23a7f66a59c73
+    03837241e070b02619fc59c5214a22f08cd70795e73e9aff";
+
+const RFC9173_A_3: &str = "
+    9f88070000820282010282028202018202820201820018281a000f4240850b0300
+    00585c8200020101820282030082820105820300828182015820cac6ce8e4c5dae57
+    988b757e49a6dd1431dc04763541b2845098265bc817241b81820158203ed614c0d9
+    7f49b3633627779aa18a338d212bf3c92b97759d9739cd50725596850c0401005834
+    8101020182028202018382014c5477656c7665313231323132820201820400818182
+    0150efa4b5ac0108e3816c5606479801bc0485070200004319012c85010100005823
+    3a09c1e63fe23a7f66a59c7303837241e070b02619fc59c5214a22f08cd70795e73e
+    9aff";
+
+const RFC9173_A_4: &str = "
+    9f88070000820282010282028202018202820201820018281a000f4240850b0300
+    005846438ed6208eb1c1ffb94d952175167df0902902064a2983910c4fb2340790bf
+    420a7d1921d5bf7c4721e02ab87a93ab1e0b75cf62e4948727c8b5dae46ed2af0543
+    9b88029191850c0201005849820301020182028202018382014c5477656c76653132
+    313231328202038204078281820150220ffc45c8a901999ecc60991dd78b29818201
+    50d2c51cb2481792dae8b21d848cede99b850704000041018501010000582390eab6
+    457593379298a8724e16e61f837488e127212b59ac91f8a86287b7d07630a122ff";
+
+fn hex(s: &str) -> Vec<u8> {
+    let s = s
+        .chars()
+        .filter(|c| !c.is_ascii_whitespace())
+        .collect::<Vec<_>>();
+    s.chunks(2)
+        .map(|c| u8::from_str_radix(&c.iter().collect::<String>(), 16).expect("Invalid hex string"))
+        .collect()
+}
+
+// The keys needed to check a bundle, by security source and context
+type Keys<'a> = &'a [(&'a str, bpsec::Context, &'a str)];
+
+const BIB_KEYS: Keys = &[(
+    "*:**",
+    bpsec::Context::BIB_HMAC_SHA2,
+    "1a2b1a2b1a2b1a2b1a2b1a2b1a2b1a2b",
+)];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Expect {
+    Valid,
+    NonCanonical,
+    Invalid,
+}
+
+impl Expect {
+    fn dir(&self) -> &'static str {
+        match self {
+            Expect::Valid => "valid",
+            Expect::NonCanonical => "noncanonical",
+            Expect::Invalid => "invalid",
+        }
+    }
+}
+
+struct Corpus {
+    output: PathBuf,
+    manifest: Vec<String>,
+}
+
+impl Corpus {
+    fn add(&mut self, expect: Expect, name: &str, description: &str, data: &[u8]) {
+        self.add_with_keys(expect, name, description, data, BIB_KEYS)
+    }
+
+    fn add_with_keys(
+        &mut self,
+        expect: Expect,
+        name: &str,
+        description: &str,
+        data: &[u8],
+        keys: Keys,
+    ) {
+        let actual = match ValidBundle::parse(data, |source, context| {
+            for (eid, c2, key) in keys {
+                if &context == c2 && eid.parse::<EidPattern>().unwrap().is_match(source) {
+                    return Ok(Some(bpsec::KeyMaterial::SymmetricKey(hex(key).into())));
+                }
+            }
+            Ok(None)
+        }) {
+            Ok(ValidBundle::Valid(..)) => Expect::Valid,
+            Ok(ValidBundle::Rewritten(..)) => Expect::NonCanonical,
+            Ok(ValidBundle::Invalid(..)) | Err(_) => Expect::Invalid,
+        };
+        if actual != expect {
+            panic!("Bundle '{name}' is {actual:?}, not {expect:?}");
+        }
+
+        let path = format!("{}/{name}.cbor", expect.dir());
+        std::fs::write(self.output.join(&path), data).expect("Failed to write bundle");
+        self.manifest.push(format!("{path}: {description}"));
+    }
+}
+
+fn builder() -> Builder {
+    Builder::new()
+        .source("ipn:1.1".parse().unwrap())
+        .destination("ipn:2.1".parse().unwrap())
+        .creation_timestamp(CreationTimestamp {
+            creation_time: Some(DtnTime::new(756_000_000_000)),
+            sequence_number: 0,
+        })
+}
+
+fn payload(builder: Builder) -> Vec<u8> {
+    builder
+        .add_payload_block(b"Hello, corpus!".to_vec())
+        .build()
+        .1
+}
+
+fn valid(corpus: &mut Corpus) {
+    corpus.add(
+        Expect::Valid,
+        "crc32",
+        "CRC-32C on every block",
+        &payload(builder()),
+    );
+    corpus.add(
+        Expect::Valid,
+        "crc16",
+        "CRC-16 on every block",
+        &payload(builder().crc_type(CrcType::CRC16_X25)),
+    );
+    corpus.add(
+        Expect::Valid,
+        "crc-mixed",
+        "CRC-32C primary block, CRC-16 extension block and no CRC on the payload block",
+        &builder()
+            .add_extension_block(BlockType::HopCount)
+            .crc_type(CrcType::CRC16_X25)
+            .data(vec![0x82, 0x10, 0x00])
+            .build()
+            .add_extension_block(BlockType::Payload)
+            .crc_type(CrcType::None)
+            .data(b"Hello, corpus!".to_vec())
+            .build()
+            .build()
+            .1,
+    );
+
+    // Bundle processing control flags
+    let flags: &[(&str, &str, BundleFlags)] = &[
+        (
+            "flag-do-not-fragment",
+            "Bundle must not be fragmented",
+            BundleFlags {
+                do_not_fragment: true,
+                ..Default::default()
+            },
+        ),
+        (
+            "flag-app-ack",
+            "Acknowledgement by application is requested",
+            BundleFlags {
+                app_ack_requested: true,
+                ..Default::default()
+            },
+        ),
+        (
+            "flag-status-time",
+            "Status time is requested in all status reports",
+            BundleFlags {
+                report_status_time: true,
+                ..Default::default()
+            },
+        ),
+        (
+            "flag-all-reports",
+            "Reception, forwarding, delivery and deletion reports are requested",
+            BundleFlags {
+                receipt_report_requested: true,
+                forward_report_requested: true,
+                delivery_report_requested: true,
+                delete_report_requested: true,
+                ..Default::default()
+            },
+        ),
+    ];
+    for (name, description, flags) in flags {
+        corpus.add(
+            Expect::Valid,
+            name,
+            description,
+            &payload(builder().flags(flags.clone())),
+        );
+    }
+
+    // EID schemes
+    let eids: &[(&str, &str, &str, &str)] = &[
+        (
+            "eid-ipn-2",
+            "Two-element ipn EIDs",
+            "ipn:977000.1",
+            "ipn:977001.0",
+        ),
+        (
+            "eid-ipn-3",
+            "Three-element ipn EIDs, with an allocator id",
+            "ipn:977000.1.1",
+            "ipn:977000.2.0",
+        ),
+        (
+            "eid-dtn",
+            "dtn EIDs",
+            "dtn://source/service",
+            "dtn://destination/a/b",
+        ),
+        (
+            "eid-mixed",
+            "ipn source and dtn destination",
+            "ipn:1.1",
+            "dtn://destination/",
+        ),
+    ];
+    for (name, description, source, destination) in eids {
+        corpus.add(
+            Expect::Valid,
+            name,
+            description,
+            &payload(
+                builder()
+                    .source(source.parse().unwrap())
+                    .destination(destination.parse().unwrap())
+                    .report_to(source.parse().unwrap()),
+            ),
+        );
+    }
+    corpus.add(
+        Expect::Valid,
+        "eid-null-source",
+        "Anonymous bundle from dtn:none, which must not be fragmented",
+        &payload(builder().source(Eid::Null).flags(BundleFlags {
+            do_not_fragment: true,
+            ..Default::default()
+        })),
+    );
+    corpus.add(
+        Expect::Valid,
+        "report-to",
+        "Report-to EID differs from the source",
+        &payload(builder().report_to("ipn:3.0".parse().unwrap())),
+    );
+
+    // Extension blocks
+    corpus.add(
+        Expect::Valid,
+        "block-hop-count",
+        "Hop count block",
+        &payload(
+            builder()
+                .add_extension_block(BlockType::HopCount)
+                .must_replicate(true)
+                .data(vec![0x82, 0x10, 0x00])
+                .build(),
+        ),
+    );
+    corpus.add(
+        Expect::Valid,
+        "block-previous-node",
+        "Previous node block",
+        &payload(
+            builder()
+                .add_extension_block(BlockType::PreviousNode)
+                .data(vec![0x82, 0x02, 0x82, 0x03, 0x00])
+                .build(),
+        ),
+    );
+    corpus.add(
+        Expect::Valid,
+        "block-bundle-age",
+        "No creation time, so a bundle age block",
+        &payload(
+            builder()
+                .creation_timestamp(CreationTimestamp {
+                    creation_time: None,
+                    sequence_number: 1,
+                })
+                .add_extension_block(BlockType::BundleAge)
+                .data(vec![0x19, 0x03, 0xe8])
+                .build(),
+        ),
+    );
+    corpus.add(
+        Expect::Valid,
+        "block-unrecognised",
+        "Private use block that asks for a report if it is not recognised",
+        &payload(
+            builder()
+                .add_extension_block(BlockType::Unrecognised(192))
+                .report_on_failure(true)
+                .data(vec![1, 2, 3])
+                .build(),
+        ),
+    );
+    corpus.add(
+        Expect::Valid,
+        "payload-empty",
+        "Zero-length payload",
+        &builder().build().1,
+    );
+
+    // Fragments
+    let (bundle, data) = builder()
+        .add_payload_block(b"Hello, corpus!".to_vec())
+        .build();
+    corpus.add(
+        Expect::Valid,
+        "fragment-first",
+        "First fragment of a 14 byte payload",
+        &Editor::new(&bundle, &data)
+            .fragment(0, 14)
+            .replace_extension_block(BlockType::Payload)
+            .data(b"Hello, ".to_vec())
+            .build()
+            .build(),
+    );
+    corpus.add(
+        Expect::Valid,
+        "fragment-last",
+        "Last fragment of a 14 byte payload",
+        &Editor::new(&bundle, &data)
+            .fragment(7, 14)
+            .replace_extension_block(BlockType::Payload)
+            .data(b"corpus!".to_vec())
+            .build()
+            .build(),
+    );
+
+    // BPSec
+    corpus.add(
+        Expect::Valid,
+        "bpsec-rfc9173-a1",
+        "RFC 9173 A.1: BIB-HMAC-SHA2 over the payload",
+        &hex(RFC9173_A_1),
+    );
+    corpus.add_with_keys(
+        Expect::Valid,
+        "bpsec-rfc9173-a2",
+        "RFC 9173 A.2: BCB-AES-GCM over the payload",
+        &hex(RFC9173_A_2),
+        &[(
+            "ipn:2.1",
+            bpsec::Context::BCB_AES_GCM,
+            "6162636465666768696a6b6c6d6e6f70",
+        )],
+    );
+    corpus.add_with_keys(
+        Expect::Valid,
+        "bpsec-rfc9173-a3",
+        "RFC 9173 A.3: BIB over the primary and hop count blocks, BCB over the payload",
+        &hex(RFC9173_A_3),
+        &[
+            BIB_KEYS[0],
+            (
+                "ipn:2.1",
+                bpsec::Context::BCB_AES_GCM,
+                "71776572747975696f70617364666768",
+            ),
+        ],
+    );
+    corpus.add_with_keys(
+        Expect::Valid,
+        "bpsec-rfc9173-a4",
+        "RFC 9173 A.4: BCB over the payload and a BIB, with a bundle age block",
+        &hex(RFC9173_A_4),
+        &[
+            BIB_KEYS[0],
+            (
+                "ipn:2.1",
+                bpsec::Context::BCB_AES_GCM,
+                "71776572747975696f7061736466676871776572747975696f70617364666768",
+            ),
+        ],
+    );
+}
+
+fn noncanonical(corpus: &mut Corpus) {
+    let data = payload(builder());
+
+    // Blocks in a definite-length array, rather than indefinite
+    let mut definite = vec![0x80 | 2];
+    definite.extend_from_slice(&data[1..data.len() - 1]);
+    corpus.add(
+        Expect::NonCanonical,
+        "definite-array",
+        "Blocks in a definite-length array",
+        &definite,
+    );
+
+    let mut tagged = vec![0xd9, 0xd9, 0xf7, 0xd9, 0xd9, 0xf7];
+    tagged.extend_from_slice(&data);
+    corpus.add(
+        Expect::NonCanonical,
+        "tag-repeated",
+        "Bundle with the self-described CBOR tag twice",
+        &tagged,
+    );
+
+    corpus.add(
+        Expect::NonCanonical,
+        "block-discard",
+        "Unrecognised block that must be discarded",
+        &payload(
+            builder()
+                .add_extension_block(BlockType::Unrecognised(193))
+                .delete_block_on_failure(true)
+                .data(vec![1, 2, 3])
+                .build(),
+        ),
+    );
+}
+
+fn invalid(corpus: &mut Corpus) {
+    let data = payload(builder());
+
+    let mut bad_crc = data.clone();
+    // The last byte of the payload, before the CRC and the end of the array
+    let idx = bad_crc.len() - 7;
+    bad_crc[idx] ^= 0xff;
+    corpus.add(
+        Expect::Invalid,
+        "crc-mismatch",
+        "Payload altered after its CRC was calculated",
+        &bad_crc,
+    );
+    corpus.add(
+        Expect::Invalid,
+        "crc-none",
+        "No CRC on the primary block, and no BIB to protect it instead",
+        &payload(builder().crc_type(CrcType::None)),
+    );
+    corpus.add(
+        Expect::Invalid,
+        "truncated",
+        "Bundle cut short",
+        &data[..data.len() / 2],
+    );
+    corpus.add(
+        Expect::Invalid,
+        "not-cbor",
+        "Not a bundle at all",
+        b"Not a bundle",
+    );
+    corpus.add(
+        Expect::Invalid,
+        "eid-null-source-fragmentable",
+        "Anonymous bundle that may be fragmented",
+        &payload(builder().source(Eid::Null)),
+    );
+    corpus.add(
+        Expect::Invalid,
+        "flag-admin-reports",
+        "Administrative record that requests status reports",
+        &payload(builder().flags(BundleFlags {
+            is_admin_record: true,
+            delivery_report_requested: true,
+            ..Default::default()
+        })),
+    );
+    corpus.add(
+        Expect::Invalid,
+        "block-bundle-age-missing",
+        "No creation time, and no bundle age block",
+        &payload(builder().creation_timestamp(CreationTimestamp {
+            creation_time: None,
+            sequence_number: 1,
+        })),
+    );
+    corpus.add(
+        Expect::Invalid,
+        "block-duplicate",
+        "Two hop count blocks",
+        &payload(
+            builder()
+                .add_extension_block(BlockType::HopCount)
+                .data(vec![0x82, 0x10, 0x00])
+                .build()
+                .add_extension_block(BlockType::HopCount)
+                .data(vec![0x82, 0x10, 0x01])
+                .build(),
+        ),
+    );
+    corpus.add(
+        Expect::Invalid,
+        "block-unsupported",
+        "Unrecognised block that requires the bundle be deleted",
+        &payload(
+            builder()
+                .add_extension_block(BlockType::Unrecognised(194))
+                .delete_bundle_on_failure(true)
+                .data(vec![1, 2, 3])
+                .build(),
+        ),
+    );
+
+    let mut tampered = hex(RFC9173_A_1);
+    // The last byte of the payload, which has no CRC
+    let idx = tampered.len() - 2;
+    tampered[idx] ^= 0xff;
+    corpus.add(
+        Expect::Invalid,
+        "bpsec-bib-mismatch",
+        "RFC 9173 A.1 with the payload altered after it was signed",
+        &tampered,
+    );
+}
+
+fn main() {
+    let args = Args::parse();
+
+    for expect in [Expect::Valid, Expect::NonCanonical, Expect::Invalid] {
+        std::fs::create_dir_all(args.output.join(expect.dir()))
+            .expect("Failed to create output directory");
+    }
+
+    let mut corpus = Corpus {
+        output: args.output,
+        manifest: Vec::new(),
+    };
+    valid(&mut corpus);
+    noncanonical(&mut corpus);
+    invalid(&mut corpus);
+
+    let mut manifest =
+        std::fs::File::create(corpus.output.join("MANIFEST")).expect("Failed to create manifest");
+    for line in &corpus.manifest {
+        writeln!(manifest, "{line}").expect("Failed to write manifest");
+    }
+    println!("{} bundles written", corpus.manifest.len());
+}
@@ -0,0 +1,308 @@
+use super::*;
+
+pub struct Case {
+    pub name: &'static str,
+    pub section: &'static str,
+}
+
+pub struct Outcome {
+    pub passed: bool,
+    pub detail: String,
+}
+
+impl Outcome {
+    fn pass(detail: impl Into<String>) -> Self {
+        Self {
+            passed: true,
+            detail: detail.into(),
+        }
+    }
+
+    fn fail(detail: impl Into<String>) -> Self {
+        Self {
+            passed: false,
+            detail: detail.into(),
+        }
+    }
+}
+
+// What came back from the BPA in response to a single test bundle
+#[derive(Default)]
+struct Observed {
+    forwarded: Vec<bpv7::Bundle>,
+    reports: Vec<bpv7::BundleStatusReport>,
+}
+
+impl Observed {
+    fn report(
+        &self,
+        f: impl Fn(&bpv7::BundleStatusReport) -> bool,
+    ) -> Option<&bpv7::BundleStatusReport> {
+        self.reports.iter().find(|r| f(r))
+    }
+}
+
+struct Context<'a> {
+    peer: &'a mut peer::Peer,
+    node: u32,
+    timeout: std::time::Duration,
+}
+
+impl Context<'_> {
+    fn eid(&self, service: u32) -> bpv7::Eid {
+        format!("ipn:{}.{service}", self.node)
+            .parse()
+            .expect("Invalid peer EID")
+    }
+
+    fn builder(&self, flags: bpv7::BundleFlags) -> bpv7::Builder {
+        bpv7::Builder::new()
+            .flags(flags)
+            .source(self.eid(1))
+            .destination(self.eid(2))
+            .report_to(self.eid(3))
+    }
+
+    // Send a bundle, and collect everything related to it until the BPA goes quiet
+    async fn exchange(&mut self, builder: bpv7::Builder) -> Result<Observed, Error> {
+        let (bundle, data) = builder.add_payload_block(b"conformance".to_vec()).build();
+        self.peer.send(data).await?;
+
+        let mut observed = Observed::default();
+        while let Some(f) = self.peer.recv(self.timeout).await {
+            let forwarded = match bpv7::ValidBundle::parse(&f.data, |_, _| Ok(None)) {
+                Ok(bpv7::ValidBundle::Valid(b, _)) | Ok(bpv7::ValidBundle::Rewritten(b, _, _)) => b,
+                Ok(bpv7::ValidBundle::Invalid(_, _, e)) => {
+                    return Err(format!("BPA forwarded an invalid bundle: {e}").into())
+                }
+                Err(e) => return Err(format!("BPA forwarded an unparseable bundle: {e}").into()),
+            };
+            trace!("Received {:?} for {}", forwarded.id, f.destination);
+
+            if forwarded.flags.is_admin_record {
+                let Some(payload) = forwarded.blocks.get(&1) else {
+                    return Err("Administrative record has no payload".into());
+                };
+                let record =
+                    cbor::decode::parse_value(
+                        payload.payload(&f.data),
+                        |value, _, _| match value {
+                            cbor::decode::Value::Bytes(data) => {
+                                cbor::decode::parse::<bpv7::AdministrativeRecord>(data)
+                                    .map_err(Error::from)
+                            }
+                            value => Err(cbor::decode::Error::IncorrectType(
+                                "Byte String".to_string(),
+                                value.type_name(false),
+                            )
+                            .into()),
+                        },
+                    )
+                    .map(|(record, _)| record);
+                match record {
+                    Ok(bpv7::AdministrativeRecord::BundleStatusReport(report))
+                        if report.bundle_id == bundle.id =>
+                    {
+                        observed.reports.push(report)
+                    }
+                    Ok(_) => {}
+                    Err(e) => return Err(format!("Invalid administrative record: {e}").into()),
+                }
+            } else if forwarded.id == bundle.id {
+                observed.forwarded.push(forwarded);
+            }
+        }
+        Ok(observed)
+    }
+}
+
+pub async fn run_all(peer: &mut peer::Peer, args: &Args) -> Vec<(Case, Outcome)> {
+    let mut ctx = Context {
+        peer,
+        node: args.node,
+        timeout: std::time::Duration::from_secs(args.timeout),
+    };
+    let mut results = Vec::new();
+
+    macro_rules! run {
+        ($name:literal, $section:literal, $f:ident) => {
+            info!("Running case '{}'", $name);
+            let outcome = $f(&mut ctx)
+                .await
+                .unwrap_or_else(|e| Outcome::fail(format!("Error: {e}")));
+            results.push((
+                Case {
+                    name: $name,
+                    section: $section,
+                },
+                outcome,
+            ));
+        };
+    }
+
+    run!("Forwarding without reports", "5.4", plain_forward);
+    run!("Reception report requested", "6.1.1", receipt_report);
+    run!("Forwarding report requested", "6.1.1", forward_report);
+    run!("Expired bundle deleted", "5.4.1", lifetime_expired);
+    run!("Hop limit exceeded", "4.4.3", hop_limit_exceeded);
+    run!(
+        "Unknown block: delete bundle",
+        "4.2.4",
+        unknown_block_delete_bundle
+    );
+    run!(
+        "Unknown block: discard block",
+        "4.2.4",
+        unknown_block_discard_block
+    );
+    run!("Unknown block: retain block", "4.2.4", unknown_block_retain);
+
+    results
+}
+
+fn expect_deletion(
+    observed: &Observed,
+    reason: bpv7::StatusReportReasonCode,
+) -> Result<Outcome, Error> {
+    if !observed.forwarded.is_empty() {
+        return Ok(Outcome::fail("Bundle was forwarded"));
+    }
+    Ok(match observed.report(|r| r.deleted.is_some()) {
+        None => Outcome::fail("No deletion report received"),
+        Some(r) if r.reason != reason => Outcome::fail(format!(
+            "Deletion reason {:?}, expected {reason:?}",
+            r.reason
+        )),
+        Some(_) => Outcome::pass(format!("Deleted with reason {reason:?}")),
+    })
+}
+
+async fn plain_forward(ctx: &mut Context<'_>) -> Result<Outcome, Error> {
+    let observed = ctx.exchange(ctx.builder(Default::default())).await?;
+    Ok(if observed.forwarded.len() != 1 {
+        Outcome::fail(format!(
+            "Expected 1 forwarded copy, got {}",
+            observed.forwarded.len()
+        ))
+    } else if !observed.reports.is_empty() {
+        Outcome::fail("Unrequested status report generated")
+    } else {
+        Outcome::pass("Forwarded")
+    })
+}
+
+async fn receipt_report(ctx: &mut Context<'_>) -> Result<Outcome, Error> {
+    let observed = ctx
+        .exchange(ctx.builder(bpv7::BundleFlags {
+            receipt_report_requested: true,
+            ..Default::default()
+        }))
+        .await?;
+    Ok(match observed.report(|r| r.received.is_some()) {
+        Some(_) => Outcome::pass("Reception report received"),
+        None => Outcome::fail("No reception report received"),
+    })
+}
+
+async fn forward_report(ctx: &mut Context<'_>) -> Result<Outcome, Error> {
+    let observed = ctx
+        .exchange(ctx.builder(bpv7::BundleFlags {
+            forward_report_requested: true,
+            ..Default::default()
+        }))
+        .await?;
+    Ok(if observed.forwarded.is_empty() {
+        Outcome::fail("Bundle was not forwarded")
+    } else if observed.report(|r| r.forwarded.is_some()).is_none() {
+        Outcome::fail("No forwarding report received")
+    } else {
+        Outcome::pass("Forwarding report received")
+    })
+}
+
+async fn lifetime_expired(ctx: &mut Context<'_>) -> Result<Outcome, Error> {
+    let observed = ctx
+        .exchange(
+            ctx.builder(bpv7::BundleFlags {
+                delete_report_requested: true,
+                ..Default::default()
+            })
+            .lifetime(0),
+        )
+        .await?;
+    expect_deletion(&observed, bpv7::StatusReportReasonCode::LifetimeExpired)
+}
+
+async fn hop_limit_exceeded(ctx: &mut Context<'_>) -> Result<Outcome, Error> {
+    let observed = ctx
+        .exchange(
+            ctx.builder(bpv7::BundleFlags {
+                delete_report_requested: true,
+                ..Default::default()
+            })
+            .add_extension_block(bpv7::BlockType::HopCount)
+            .data(cbor::encode::emit(&bpv7::HopInfo { limit: 1, count: 2 }))
+            .build(),
+        )
+        .await?;
+    expect_deletion(&observed, bpv7::StatusReportReasonCode::HopLimitExceeded)
+}
+
+// An unassigned block type from the private/experimental range
+const UNKNOWN_BLOCK_TYPE: u64 = 250;
+
+fn has_unknown_block(bundle: &bpv7::Bundle) -> bool {
+    bundle
+        .blocks
+        .values()
+        .any(|b| b.block_type == bpv7::BlockType::Unrecognised(UNKNOWN_BLOCK_TYPE))
+}
+
+async fn unknown_block_delete_bundle(ctx: &mut Context<'_>) -> Result<Outcome, Error> {
+    let observed = ctx
+        .exchange(
+            ctx.builder(bpv7::BundleFlags {
+                delete_report_requested: true,
+                ..Default::default()
+            })
+            .add_extension_block(bpv7::BlockType::Unrecognised(UNKNOWN_BLOCK_TYPE))
+            .delete_bundle_on_failure(true)
+            .data(vec![0x40])
+            .build(),
+        )
+        .await?;
+    expect_deletion(&observed, bpv7::StatusReportReasonCode::BlockUnsupported)
+}
+
+async fn unknown_block_discard_block(ctx: &mut Context<'_>) -> Result<Outcome, Error> {
+    let observed = ctx
+        .exchange(
+            ctx.builder(Default::default())
+                .add_extension_block(bpv7::BlockType::Unrecognised(UNKNOWN_BLOCK_TYPE))
+                .delete_block_on_failure(true)
+                .data(vec![0x40])
+                .build(),
+        )
+        .await?;
+    Ok(match observed.forwarded.first() {
+        None => Outcome::fail("Bundle was not forwarded"),
+        Some(b) if has_unknown_block(b) => Outcome::fail("Unknown block was not discarded"),
+        Some(_) => Outcome::pass("Forwarded without block"),
+    })
+}
+
+async fn unknown_block_retain(ctx: &mut Context<'_>) -> Result<Outcome, Error> {
+    let observed = ctx
+        .exchange(
+            ctx.builder(Default::default())
+                .add_extension_block(bpv7::BlockType::Unrecognised(UNKNOWN_BLOCK_TYPE))
+                .data(vec![0x40])
+                .build(),
+        )
+        .await?;
+    Ok(match observed.forwarded.first() {
+        None => Outcome::fail("Bundle was not forwarded"),
+        Some(b) if !has_unknown_block(b) => Outcome::fail("Unknown block was dropped"),
+        Some(_) => Outcome::pass("Forwarded with block retained"),
+    })
+}
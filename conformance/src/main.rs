@@ -0,0 +1,79 @@
+mod cases;
+mod peer;
+
+use clap::Parser;
+use hardy_bpv7::prelude as bpv7;
+use hardy_cbor as cbor;
+use tracing::{info, trace};
+
+// This is the generic Error type used almost everywhere
+type Error = Box<dyn std::error::Error + Send + Sync>;
+
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+pub struct Args {
+    /// gRPC address of the BPA under test
+    #[arg(short, long, default_value = "http://[::1]:50051")]
+    bpa: String,
+
+    /// Local address for the scripted peer's CLA gRPC server
+    #[arg(short, long, default_value = "[::1]:50099")]
+    listen: std::net::SocketAddr,
+
+    /// ipn node number used by the scripted peer
+    #[arg(short, long, default_value_t = 99)]
+    node: u32,
+
+    /// Seconds to wait for the BPA to respond to each case
+    #[arg(short, long, default_value_t = 2)]
+    timeout: u64,
+}
+
+#[tokio::main]
+async fn main() {
+    let args = Args::parse();
+
+    tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::INFO)
+        .init();
+
+    let mut peer = peer::Peer::connect(&args)
+        .await
+        .expect("Failed to connect to BPA under test");
+
+    info!(
+        "Connected to BPA at {}, running conformance cases",
+        args.bpa
+    );
+
+    let results = cases::run_all(&mut peer, &args).await;
+
+    peer.disconnect().await;
+
+    // Produce the report
+    println!();
+    println!("{:<6} {:<10} {:<48} Detail", "Result", "Section", "Case");
+    let mut failed = 0;
+    for (case, outcome) in &results {
+        if !outcome.passed {
+            failed += 1;
+        }
+        println!(
+            "{:<6} {:<10} {:<48} {}",
+            if outcome.passed { "PASS" } else { "FAIL" },
+            case.section,
+            case.name,
+            outcome.detail
+        );
+    }
+    println!();
+    println!(
+        "{} cases, {} passed, {failed} failed",
+        results.len(),
+        results.len() - failed
+    );
+
+    if failed != 0 {
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,135 @@
+use super::*;
+use hardy_proto::cla::*;
+use tokio::sync::mpsc;
+use tokio_util::bytes::Bytes;
+use tonic::{Request, Response, Status};
+
+pub struct Forwarded {
+    pub destination: String,
+    pub data: Bytes,
+}
+
+struct Service {
+    tx: mpsc::Sender<Forwarded>,
+}
+
+#[tonic::async_trait]
+impl cla_server::Cla for Service {
+    async fn forward_bundle(
+        &self,
+        request: Request<ForwardBundleRequest>,
+    ) -> Result<Response<ForwardBundleResponse>, Status> {
+        let request = request.into_inner();
+        trace!("BPA forwarded bundle to {}", request.destination);
+
+        self.tx
+            .send(Forwarded {
+                destination: request.destination,
+                data: request.bundle,
+            })
+            .await
+            .map_err(|_| Status::unavailable("Peer is shutting down"))?;
+
+        Ok(Response::new(ForwardBundleResponse {
+            result: forward_bundle_response::ForwardingResult::Sent as i32,
+            delay: None,
+        }))
+    }
+
+    async fn check_health(
+        &self,
+        _request: Request<CheckHealthRequest>,
+    ) -> Result<Response<CheckHealthResponse>, Status> {
+        Ok(Response::new(CheckHealthResponse {}))
+    }
+}
+
+pub struct Peer {
+    client: cla_sink_client::ClaSinkClient<tonic::transport::Channel>,
+    handle: u32,
+    rx: mpsc::Receiver<Forwarded>,
+    cancel_token: tokio_util::sync::CancellationToken,
+}
+
+impl Peer {
+    pub async fn connect(args: &Args) -> Result<Self, Error> {
+        // Bind before registering, as the BPA connects back to us immediately
+        let listener = tokio::net::TcpListener::bind(args.listen).await?;
+        let (tx, rx) = mpsc::channel(16);
+        let cancel_token = tokio_util::sync::CancellationToken::new();
+
+        let router = tonic::transport::Server::builder()
+            .add_service(cla_server::ClaServer::new(Service { tx }));
+        let cancel = cancel_token.clone();
+        tokio::spawn(async move {
+            router
+                .serve_with_incoming_shutdown(
+                    tokio_stream::wrappers::TcpListenerStream::new(listener),
+                    async move { cancel.cancelled().await },
+                )
+                .await
+                .expect("Failed to start gRPC server")
+        });
+
+        let mut client = cla_sink_client::ClaSinkClient::connect(args.bpa.clone()).await?;
+        let handle = client
+            .register_cla(RegisterClaRequest {
+                ident: format!("conformance-{}", args.node),
+                name: "conformance".to_string(),
+                grpc_address: format!("http://{}", args.listen),
+                instance_id: String::new(),
+                capabilities: None,
+            })
+            .await?
+            .into_inner()
+            .handle;
+
+        // Route everything for the scripted peer node back to us
+        client
+            .add_neighbour(AddNeighbourRequest {
+                handle,
+                priority: 0,
+                neighbour: format!("ipn:{}.*", args.node),
+                mtu: None,
+            })
+            .await?;
+
+        Ok(Self {
+            client,
+            handle,
+            rx,
+            cancel_token,
+        })
+    }
+
+    pub async fn send(&mut self, bundle: Vec<u8>) -> Result<(), Error> {
+        self.client
+            .receive_bundle(ReceiveBundleRequest {
+                handle: self.handle,
+                source: Bytes::new(),
+                bundle: bundle.into(),
+            })
+            .await?;
+        Ok(())
+    }
+
+    pub async fn recv(&mut self, timeout: std::time::Duration) -> Option<Forwarded> {
+        tokio::time::timeout(timeout, self.rx.recv())
+            .await
+            .ok()
+            .flatten()
+    }
+
+    pub async fn disconnect(mut self) {
+        if let Err(e) = self
+            .client
+            .unregister_cla(UnregisterClaRequest {
+                handle: self.handle,
+            })
+            .await
+        {
+            info!("Failed to unregister from BPA: {e}");
+        }
+        self.cancel_token.cancel();
+    }
+}
@@ -0,0 +1,422 @@
+use clap::{Parser, Subcommand};
+use hardy_proto::admin::{admin_client::AdminClient, *};
+
+// This is the generic Error type used almost everywhere
+type Error = Box<dyn std::error::Error + Send + Sync>;
+
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// gRPC address of the BPA
+    #[arg(short, long, default_value = "http://[::1]:50051")]
+    bpa: String,
+
+    /// Bearer token to present, if the BPA requires authorization
+    #[arg(long, env = "HARDY_TOKEN", hide_env_values = true)]
+    token: Option<String>,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// List the stored bundles
+    List {
+        /// Only the bundles for destinations matching this EID pattern
+        #[arg(default_value = "*:**")]
+        destination: String,
+
+        /// List at most this many bundles
+        #[arg(short, long)]
+        max: Option<u32>,
+    },
+
+    /// Show the status and metadata of a bundle
+    Show { bundle_id: String },
+
+    /// Re-dispatch a bundle now, whatever it is waiting for
+    Redispatch {
+        /// The bundle id, or with --waiting an EID pattern
+        target: String,
+
+        /// Re-dispatch every waiting bundle for destinations matching the pattern
+        #[arg(short, long)]
+        waiting: bool,
+    },
+
+    /// Drop a bundle, reporting its deletion if its source asked for deletion reports
+    Delete { bundle_id: String },
+
+    /// List the registered CLAs
+    Clas,
+
+    /// List the registered applications
+    Apps,
+
+    /// Dump the routes in the FIB
+    Fib,
+
+    /// Ping the echo service of another node, and report the round trip times and loss
+    Ping {
+        /// The EID of the echo service
+        destination: String,
+
+        /// Send this many pings
+        #[arg(short, long, default_value_t = 4)]
+        count: u32,
+
+        /// Milliseconds between pings
+        #[arg(short, long, default_value_t = 1000)]
+        interval: u64,
+
+        /// Milliseconds to wait for each reply
+        #[arg(short, long, default_value_t = 10000)]
+        timeout: u64,
+    },
+
+    /// Print bundle status reports as they arrive at the administrative endpoints
+    Reports {
+        /// Only the reports about bundles from sources matching this EID pattern
+        source: Option<String>,
+    },
+
+    /// Print the names of duty-cycled CLAs as the BPA asks for their links to be woken
+    Wakeups,
+
+    /// Signal whether the link of a duty-cycled CLA is ready
+    Link {
+        /// The name the CLA registered with
+        cla: String,
+
+        /// Whether the link is ready
+        #[arg(action = clap::ArgAction::Set)]
+        ready: bool,
+    },
+}
+
+fn format_timestamp(t: Option<prost_types::Timestamp>) -> String {
+    t.and_then(|t| {
+        time::OffsetDateTime::from_unix_timestamp_nanos(
+            t.seconds as i128 * 1_000_000_000 + t.nanos as i128,
+        )
+        .ok()
+    })
+    .and_then(|t| {
+        t.format(&time::format_description::well_known::Rfc3339)
+            .ok()
+    })
+    .unwrap_or_else(|| "-".to_string())
+}
+
+fn format_assertion(name: &str, assertion: Option<StatusAssertion>) -> Option<String> {
+    assertion.map(|assertion| match assertion.at {
+        Some(at) => format!("{name} at {}", format_timestamp(Some(at))),
+        None => name.to_string(),
+    })
+}
+
+fn format_hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+// Adds the bearer token, if there is one, to every call
+struct Authorization(Option<tonic::metadata::AsciiMetadataValue>);
+
+impl tonic::service::Interceptor for Authorization {
+    fn call(
+        &mut self,
+        mut request: tonic::Request<()>,
+    ) -> Result<tonic::Request<()>, tonic::Status> {
+        if let Some(token) = &self.0 {
+            request
+                .metadata_mut()
+                .insert("authorization", token.clone());
+        }
+        Ok(request)
+    }
+}
+
+async fn run(args: Args) -> Result<(), Error> {
+    let channel = tonic::transport::Endpoint::from_shared(args.bpa.clone())?
+        .connect()
+        .await?;
+    let token = args
+        .token
+        .map(|token| format!("Bearer {token}").parse::<tonic::metadata::AsciiMetadataValue>())
+        .transpose()?;
+    let mut client = AdminClient::with_interceptor(channel, Authorization(token));
+
+    match args.command {
+        Command::List { destination, max } => {
+            let bundles = client
+                .list_bundles(ListBundlesRequest {
+                    destination,
+                    max_bundles: max,
+                })
+                .await?
+                .into_inner()
+                .bundles;
+
+            println!(
+                "{:<44} {:<24} {:<24} {:<26} Status",
+                "Bundle", "Source", "Destination", "Expiry"
+            );
+            for bundle in &bundles {
+                println!(
+                    "{:<44} {:<24} {:<24} {:<26} {}",
+                    bundle.bundle_id,
+                    bundle.source,
+                    bundle.destination,
+                    format_timestamp(bundle.expiry),
+                    bundle.status
+                );
+            }
+            println!("{} bundles", bundles.len());
+        }
+        Command::Show { bundle_id } => {
+            let response = client
+                .get_bundle(GetBundleRequest { bundle_id })
+                .await?
+                .into_inner();
+            let bundle = response.bundle.unwrap_or_default();
+
+            println!("Bundle:        {}", bundle.bundle_id);
+            println!("Status:        {}", bundle.status);
+            println!("Source:        {}", bundle.source);
+            println!("Destination:   {}", bundle.destination);
+            println!("Report to:     {}", response.report_to);
+            println!("Created:       {}", format_timestamp(response.created));
+            println!("Lifetime:      {}ms", response.lifetime);
+            println!("Expiry:        {}", format_timestamp(bundle.expiry));
+            if let Some(previous_node) = response.previous_node {
+                println!("Previous node: {previous_node}");
+            }
+            if let Some(hop_count) = response.hop_count {
+                println!("Hop count:     {hop_count}");
+            }
+            println!("Priority:      {}", response.priority);
+            println!("Payload:       {} bytes", response.payload_length);
+            println!(
+                "Block types:   {}",
+                response
+                    .block_types
+                    .iter()
+                    .map(|block_type| block_type.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+            println!("Received:      {}", format_timestamp(response.received_at));
+            println!(
+                "Storage name:  {}",
+                response.storage_name.as_deref().unwrap_or("-")
+            );
+            println!(
+                "Hash:          {}",
+                response
+                    .hash
+                    .as_deref()
+                    .map_or_else(|| "-".to_string(), format_hex)
+            );
+        }
+        Command::Redispatch { target, waiting } => {
+            if waiting {
+                let count = client
+                    .redispatch(RedispatchRequest {
+                        destination: target,
+                    })
+                    .await?
+                    .into_inner()
+                    .count;
+                println!("{count} bundles re-dispatched");
+            } else {
+                client
+                    .redispatch_bundle(RedispatchBundleRequest { bundle_id: target })
+                    .await?;
+                println!("Bundle re-dispatched");
+            }
+        }
+        Command::Delete { bundle_id } => {
+            client
+                .delete_bundle(DeleteBundleRequest { bundle_id })
+                .await?;
+            println!("Bundle deleted");
+        }
+        Command::Clas => {
+            let clas = client
+                .get_capabilities(GetCapabilitiesRequest {})
+                .await?
+                .into_inner()
+                .clas;
+
+            println!("{:<24} Name", "Ident");
+            for cla in &clas {
+                println!("{:<24} {}", cla.ident, cla.name);
+            }
+            println!("{} CLAs", clas.len());
+        }
+        Command::Apps => {
+            let applications = client
+                .list_applications(ListApplicationsRequest {})
+                .await?
+                .into_inner()
+                .applications;
+
+            println!("{:<24} {:<24} {:<8} Address", "EID", "Ident", "Ordered");
+            for app in &applications {
+                println!(
+                    "{:<24} {:<24} {:<8} {}",
+                    app.eid,
+                    app.ident,
+                    if app.ordered_delivery { "yes" } else { "no" },
+                    app.grpc_address.as_deref().unwrap_or("-")
+                );
+            }
+            println!("{} applications", applications.len());
+        }
+        Command::Fib => {
+            let routes = client
+                .list_routes(ListRoutesRequest {})
+                .await?
+                .into_inner()
+                .routes;
+
+            println!(
+                "{:<24} {:<24} {:>8} {:>6} {:>8} Action",
+                "Source", "Destination", "Distance", "Cost", "Latency"
+            );
+            for route in &routes {
+                println!(
+                    "{:<24} {:<24} {:>8} {:>6} {:>8} {}",
+                    route.source,
+                    route.destination,
+                    route.distance,
+                    route.cost,
+                    route
+                        .latency
+                        .map_or("-".to_string(), |latency| format!("{latency}s")),
+                    route.action
+                );
+            }
+            println!("{} routes", routes.len());
+        }
+        Command::Ping {
+            destination,
+            count,
+            interval,
+            timeout,
+        } => {
+            let mut responses = client
+                .ping(PingRequest {
+                    destination: destination.clone(),
+                    count: Some(count),
+                    interval: Some(interval),
+                    timeout: Some(timeout),
+                })
+                .await?
+                .into_inner();
+
+            println!("PING {destination}");
+            let mut round_trips = Vec::new();
+            let mut sent = 0;
+            while let Some(response) = responses.message().await? {
+                sent += 1;
+                match response.round_trip {
+                    Some(round_trip) => {
+                        let ms = round_trip.seconds as f64 * 1000.0
+                            + round_trip.nanos as f64 / 1_000_000.0;
+                        println!(
+                            "reply from {destination}: seq={} time={ms:.3} ms",
+                            response.sequence
+                        );
+                        round_trips.push(ms);
+                    }
+                    None => println!("no reply from {destination}: seq={}", response.sequence),
+                }
+            }
+
+            println!(
+                "{sent} pings sent, {} replies received, {:.1}% loss",
+                round_trips.len(),
+                if sent == 0 {
+                    0.0
+                } else {
+                    (sent - round_trips.len()) as f64 * 100.0 / sent as f64
+                }
+            );
+            if !round_trips.is_empty() {
+                println!(
+                    "round trip min/avg/max = {:.3}/{:.3}/{:.3} ms",
+                    round_trips.iter().cloned().fold(f64::INFINITY, f64::min),
+                    round_trips.iter().sum::<f64>() / round_trips.len() as f64,
+                    round_trips
+                        .iter()
+                        .cloned()
+                        .fold(f64::NEG_INFINITY, f64::max)
+                );
+            }
+        }
+        Command::Reports { source } => {
+            let mut events = client
+                .watch_status_reports(WatchStatusReportsRequest { source })
+                .await?
+                .into_inner();
+
+            while let Some(event) = events.message().await? {
+                if event.lagged != 0 {
+                    println!("... {} reports missed", event.lagged);
+                }
+                let assertions = [
+                    format_assertion("received", event.received),
+                    format_assertion("forwarded", event.forwarded),
+                    format_assertion("delivered", event.delivered),
+                    format_assertion("deleted", event.deleted),
+                ]
+                .into_iter()
+                .flatten()
+                .collect::<Vec<_>>();
+                println!(
+                    "{} from {}: {} (reason {})",
+                    event.bundle_id,
+                    event.reported_by,
+                    assertions.join(", "),
+                    event.reason
+                );
+            }
+        }
+        Command::Wakeups => {
+            let mut events = client
+                .watch_wake_requests(WatchWakeRequestsRequest {})
+                .await?
+                .into_inner();
+
+            while let Some(event) = events.message().await? {
+                if event.lagged != 0 {
+                    println!("... {} wake requests missed", event.lagged);
+                }
+                println!("{}", event.cla);
+            }
+        }
+        Command::Link { cla, ready } => {
+            client
+                .set_link_ready(SetLinkReadyRequest { cla, ready })
+                .await?;
+            println!("Link {}", if ready { "ready" } else { "not ready" });
+        }
+    }
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() {
+    let args = Args::parse();
+
+    if let Err(e) = run(args).await {
+        // Make gRPC failures readable
+        match e.downcast_ref::<tonic::Status>() {
+            Some(status) => eprintln!("{}", status.message()),
+            None => eprintln!("{e}"),
+        }
+        std::process::exit(1);
+    }
+}
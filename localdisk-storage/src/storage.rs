@@ -3,7 +3,8 @@ use hardy_bpa_api::{async_trait, storage, storage::BundleStorage, storage::DataR
 use rand::prelude::*;
 use std::{
     collections::HashMap,
-    io::Write,
+    hash::{Hash, Hasher},
+    io::{Read, Seek, Write},
     path::{Path, PathBuf},
     str::FromStr,
     sync::Arc,
@@ -18,49 +19,137 @@ use std::os::windows::fs::OpenOptionsExt;
 use trace_err::*;
 use tracing::*;
 
+// Partially reassembled payloads live in sparse files in this subdirectory
+const REASSEMBLY_DIR: &str = "reassembly";
+const PARTIAL_EXTENSION: &str = "part";
+// The ranges written to a partial payload, as big-endian offset and length pairs
+const RANGES_EXTENSION: &str = "ranges";
+
+/* Bundles can be spread across the 'store_dir' and any 'shard_dirs', e.g. on different
+ * disks.  The storage names of bundles in a shard directory are prefixed with the shard,
+ * so the store directory of an existing store can become the first of many.  Each new
+ * bundle is placed by hashing its content, weighted by the free space in each
+ * directory, so fuller disks take proportionally fewer bundles */
+const SHARD_PREFIX: &str = "shard";
+
+// How much of the start of a bundle to hash when placing it, enough for the primary block
+const SHARD_HASH_LEN: usize = 4096;
+
 pub struct Storage {
-    store_root: PathBuf,
+    // The store directory first, then the shard directories in configured order
+    shards: Vec<PathBuf>,
+    min_free_space: u64,
+    read_only: bool,
+}
+
+// The engine settings, as they appear in its section of the configuration
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(default)]
+struct Settings {
+    store_dir: Option<PathBuf>,
+    shard_dirs: Vec<PathBuf>,
+    min_free_space: u64,
 }
 
 impl Storage {
     #[instrument(skip(config))]
-    pub fn init(config: &HashMap<String, config::Value>) -> Arc<dyn BundleStorage> {
-        let store_root = config.get("store_dir").map_or_else(
-            || {
-                directories::ProjectDirs::from("dtn", "Hardy", built_info::PKG_NAME).map_or_else(
-                    || {
-                        cfg_if::cfg_if! {
-                            if #[cfg(unix)] {
-                                Path::new("/var/spool").join(built_info::PKG_NAME)
-                            } else if #[cfg(windows)] {
-                                std::env::current_exe().join(built_info::PKG_NAME)
-                            } else {
-                                compile_error!("No idea how to determine default local store directory for target platform")
-                            }
+    pub fn init(
+        config: &HashMap<String, config::Value>,
+        read_only: bool,
+    ) -> Arc<dyn BundleStorage> {
+        let settings = config::Value::from(config.clone())
+            .try_deserialize::<Settings>()
+            .trace_expect("Invalid localdisk configuration");
+
+        let store_root = settings.store_dir.unwrap_or_else(|| {
+            directories::ProjectDirs::from("dtn", "Hardy", built_info::PKG_NAME).map_or_else(
+                || {
+                    cfg_if::cfg_if! {
+                        if #[cfg(unix)] {
+                            Path::new("/var/spool").join(built_info::PKG_NAME)
+                        } else if #[cfg(windows)] {
+                            std::env::current_exe().join(built_info::PKG_NAME)
+                        } else {
+                            compile_error!("No idea how to determine default local store directory for target platform")
                         }
-                    },
-                    |project_dirs| {
-                        project_dirs.cache_dir().into()
-                        // Lin: /home/alice/.cache/barapp
-                        // Win: C:\Users\Alice\AppData\Local\Foo Corp\Bar App\cache
-                        // Mac: /Users/Alice/Library/Caches/com.Foo-Corp.Bar-App
-                    },
-                )
-            },
-            |v| {
-                v.clone().into_string().trace_expect("Invalid 'store_dir' value in configuration").into()
-            },
-        );
+                    }
+                },
+                |project_dirs| {
+                    project_dirs.cache_dir().into()
+                    // Lin: /home/alice/.cache/barapp
+                    // Win: C:\Users\Alice\AppData\Local\Foo Corp\Bar App\cache
+                    // Mac: /Users/Alice/Library/Caches/com.Foo-Corp.Bar-App
+                },
+            )
+        });
+
+        let mut shards = vec![store_root];
+        shards.extend(settings.shard_dirs);
+
+        for store_root in &shards {
+            info!("Using bundle store directory: {}", store_root.display());
+
+            if read_only {
+                // The directory belongs to another instance, so must already exist
+                if !store_root.is_dir() {
+                    error!(
+                        "Bundle store directory {} does not exist",
+                        store_root.display()
+                    );
+                    panic!(
+                        "Bundle store directory {} does not exist",
+                        store_root.display()
+                    );
+                }
+            } else {
+                // Ensure directory exists
+                std::fs::create_dir_all(store_root).trace_expect(&format!(
+                    "Failed to create bundle store directory {}",
+                    store_root.display()
+                ));
+            }
+        }
 
-        info!("Using bundle store directory: {}", store_root.display());
+        if read_only {
+            info!("Bundle store opened read-only");
+        }
 
-        // Ensure directory exists
-        std::fs::create_dir_all(&store_root).trace_expect(&format!(
-            "Failed to create bundle store directory {}",
-            store_root.display()
-        ));
+        Arc::new(Storage {
+            shards,
+            min_free_space: settings.min_free_space,
+            read_only,
+        })
+    }
 
-        Arc::new(Storage { store_root })
+    // The store directory, which also holds partial payloads
+    fn store_root(&self) -> &Path {
+        &self.shards[0]
+    }
+
+    fn file_path(&self, storage_name: &str) -> storage::Result<PathBuf> {
+        let path = PathBuf::from_str(storage_name)?;
+        let mut components = path.components();
+        if let Some(std::path::Component::Normal(first)) = components.next() {
+            if let Some(shard) = first
+                .to_str()
+                .and_then(|s| s.strip_prefix(SHARD_PREFIX))
+                .and_then(|s| s.parse::<usize>().ok())
+            {
+                let Some(root) = self.shards.get(shard) else {
+                    return Err(format!("Unknown bundle store shard in {storage_name}").into());
+                };
+                return Ok(root.join(components.as_path()));
+            }
+        }
+        Ok(self.store_root().join(path))
+    }
+
+    fn check_writable(&self) -> storage::Result<()> {
+        if self.read_only {
+            Err("Bundle store is opened read-only".into())
+        } else {
+            Ok(())
+        }
     }
 }
 
@@ -99,12 +188,124 @@ fn random_file_path(root: &PathBuf) -> Result<PathBuf, std::io::Error> {
     }
 }
 
+fn shard_name(shard: usize, relative: &Path) -> Arc<str> {
+    if shard == 0 {
+        relative.to_string_lossy().into()
+    } else {
+        Path::new(&format!("{SHARD_PREFIX}{shard}"))
+            .join(relative)
+            .to_string_lossy()
+            .into()
+    }
+}
+
+// Weighted rendezvous hashing, so adding a shard moves as little placement as possible
+fn select_shard(
+    shards: &[PathBuf],
+    min_free_space: u64,
+    data: &[u8],
+) -> Result<usize, std::io::Error> {
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    data.len().hash(&mut hasher);
+    data[..data.len().min(SHARD_HASH_LEN)].hash(&mut hasher);
+    let key = hasher.finish();
+
+    let mut selected = None;
+    for (shard, root) in shards.iter().enumerate() {
+        let free = free_space(root)?;
+        if free <= min_free_space {
+            continue;
+        }
+
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        (key, shard).hash(&mut hasher);
+        let h = (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64;
+        let score = (free - min_free_space) as f64 / -h.max(f64::MIN_POSITIVE).ln();
+
+        if !selected.is_some_and(|(_, best)| best >= score) {
+            selected = Some((shard, score));
+        }
+    }
+
+    selected
+        .map(|(shard, _)| shard)
+        .ok_or_else(|| std::io::Error::other("No bundle store directory has free space"))
+}
+
+#[cfg(unix)]
+fn free_space(root: &Path) -> Result<u64, std::io::Error> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = std::ffi::CString::new(root.as_os_str().as_bytes())
+        .map_err(|_| std::io::Error::from(std::io::ErrorKind::InvalidInput))?;
+    let mut stat = unsafe { std::mem::zeroed::<libc::statvfs>() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    // The widths of the fields vary between platforms, and are already u64 on some
+    #[allow(clippy::useless_conversion)]
+    let (available, fragment_size) = (u64::from(stat.f_bavail), u64::from(stat.f_frsize));
+    Ok(available.saturating_mul(fragment_size))
+}
+
+#[cfg(not(unix))]
+fn free_space(_root: &Path) -> Result<u64, std::io::Error> {
+    // No portable way to find the free space, so weight every directory equally
+    Ok(u64::MAX)
+}
+
+fn partial_file_path(root: &Path, reassembly_id: &str) -> Result<PathBuf, storage::Error> {
+    if reassembly_id.is_empty() || !reassembly_id.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(format!("Invalid reassembly id: {reassembly_id}").into());
+    }
+    let mut file_path = root.join(REASSEMBLY_DIR).join(reassembly_id);
+    file_path.set_extension(PARTIAL_EXTENSION);
+    Ok(file_path)
+}
+
+fn remove_if_exists(file_path: &Path) -> Result<(), std::io::Error> {
+    match std::fs::remove_file(file_path) {
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        r => r,
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn punch_hole(file: &std::fs::File, offset: u64, len: u64) -> Result<(), std::io::Error> {
+    use std::os::fd::AsRawFd;
+
+    let (Ok(offset), Ok(len)) = (libc::off_t::try_from(offset), libc::off_t::try_from(len)) else {
+        return Err(std::io::ErrorKind::InvalidInput.into());
+    };
+    if unsafe {
+        libc::fallocate(
+            file.as_raw_fd(),
+            libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE,
+            offset,
+            len,
+        )
+    } != 0
+    {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn punch_hole(_file: &std::fs::File, _offset: u64, _len: u64) -> Result<(), std::io::Error> {
+    // No portable way to deallocate a file range, the space is recovered when the file is removed
+    Ok(())
+}
+
 fn walk_dirs(
-    root: &PathBuf,
+    root: &Path,
+    shard: usize,
     dir: PathBuf,
+    read_only: bool,
     tx: &tokio::sync::mpsc::Sender<storage::ListResponse>,
 ) -> Vec<PathBuf> {
-    let mut remove = true;
+    // Leave tidying up to the instance that owns the store
+    let mut remove = !read_only;
     let mut subdirs = Vec::new();
     if let Ok(dir) = std::fs::read_dir(dir.clone()) {
         for entry in dir.flatten() {
@@ -116,8 +317,16 @@ fn walk_dirs(
                     // Drop anything .tmp
                     if let Some(extension) = entry.path().extension() {
                         if extension == "tmp" {
-                            std::fs::remove_file(entry.path())
-                                .trace_expect("Failed to remove tmp file");
+                            if !read_only {
+                                std::fs::remove_file(entry.path())
+                                    .trace_expect("Failed to remove tmp file");
+                            }
+                            continue;
+                        }
+
+                        // Partial payloads are not bundles
+                        if extension == PARTIAL_EXTENSION || extension == RANGES_EXTENSION {
+                            remove = false;
                             continue;
                         }
                     }
@@ -129,8 +338,10 @@ fn walk_dirs(
                         .len()
                         == 0
                     {
-                        std::fs::remove_file(entry.path())
-                            .trace_expect("Failed to remove placeholder file");
+                        if !read_only {
+                            std::fs::remove_file(entry.path())
+                                .trace_expect("Failed to remove placeholder file");
+                        }
                         continue;
                     }
 
@@ -145,12 +356,7 @@ fn walk_dirs(
 
                     if tx
                         .blocking_send((
-                            entry
-                                .path()
-                                .strip_prefix(root)
-                                .unwrap()
-                                .to_string_lossy()
-                                .into(),
+                            shard_name(shard, entry.path().strip_prefix(root).unwrap()),
                             received_at,
                         ))
                         .is_err()
@@ -176,7 +382,7 @@ impl BundleStorage for Storage {
         &self,
         tx: tokio::sync::mpsc::Sender<storage::ListResponse>,
     ) -> storage::Result<()> {
-        let mut dirs = vec![self.store_root.clone()];
+        let mut dirs = self.shards.iter().cloned().enumerate().collect::<Vec<_>>();
 
         let parallelism = std::thread::available_parallelism()
             .map(Into::into)
@@ -195,12 +401,17 @@ impl BundleStorage for Storage {
                     // Throttle the number of threads
                     permit = semaphore.clone().acquire_owned() => {
                         let permit = permit.trace_expect("Failed to acquire permit");
-                        let root = self.store_root.clone();
+                        let shards = self.shards.clone();
+                        let read_only = self.read_only;
                         let tx = tx.clone();
                         task_set.spawn_blocking(move || {
                             let mut dirs = Vec::new();
-                            for dir in subdirs {
-                                dirs.extend(walk_dirs(&root, dir, &tx));
+                            for (shard, dir) in subdirs {
+                                dirs.extend(
+                                    walk_dirs(&shards[shard], shard, dir, read_only, &tx)
+                                        .into_iter()
+                                        .map(|dir| (shard, dir)),
+                                );
                             }
                             drop(permit);
                             dirs
@@ -227,7 +438,7 @@ impl BundleStorage for Storage {
 
     #[instrument(skip(self))]
     async fn load(&self, storage_name: &str) -> storage::Result<Option<DataRef>> {
-        let storage_name = self.store_root.join(PathBuf::from_str(storage_name)?);
+        let storage_name = self.file_path(storage_name)?;
 
         cfg_if::cfg_if! {
             if #[cfg(feature = "mmap")] {
@@ -260,13 +471,16 @@ impl BundleStorage for Storage {
     }
 
     async fn store(&self, data: &[u8]) -> storage::Result<Arc<str>> {
-        let root = self.store_root.clone();
+        self.check_writable()?;
+        let shards = self.shards.clone();
+        let min_free_space = self.min_free_space;
 
         // Spawn a thread to try to maintain linearity
         let data = Box::from(data);
-        let storage_name = tokio::task::spawn_blocking(move || {
-            // Create random filename
-            let mut storage_name = random_file_path(&root)?;
+        let (shard, storage_name) = tokio::task::spawn_blocking(move || {
+            // Pick a directory, then create random filename
+            let shard = select_shard(&shards, min_free_space, &data)?;
+            let mut storage_name = random_file_path(&shards[shard])?;
 
             /*
             create a new temp file (alongside the original)
@@ -312,21 +526,135 @@ impl BundleStorage for Storage {
 
             // No idea how to fsync the directory in portable Rust!
 
-            Ok(storage_name)
+            Ok((shard, storage_name))
         })
         .await
         .trace_expect("Failed to spawn write_atomic thread")?;
 
-        Ok(storage_name
-            .strip_prefix(&self.store_root)?
-            .to_string_lossy()
-            .into())
+        Ok(shard_name(
+            shard,
+            storage_name.strip_prefix(&self.shards[shard])?,
+        ))
+    }
+
+    fn capabilities(&self) -> storage::Capabilities {
+        storage::Capabilities {
+            streaming: true,
+            ..Default::default()
+        }
+    }
+
+    #[instrument(skip_all)]
+    async fn store_stream(
+        &self,
+        mut rx: tokio::sync::mpsc::Receiver<Vec<u8>>,
+    ) -> storage::Result<Arc<str>> {
+        self.check_writable()?;
+        let shards = self.shards.clone();
+        let min_free_space = self.min_free_space;
+
+        // The total length is not known yet, so place the bundle by the start of it alone
+        let mut head = Vec::new();
+        while head.len() < SHARD_HASH_LEN {
+            let Some(chunk) = rx.recv().await else {
+                break;
+            };
+            head.extend_from_slice(&chunk);
+        }
+
+        let (shard, mut storage_name, file) = tokio::task::spawn_blocking({
+            let head = head[..head.len().min(SHARD_HASH_LEN)].to_vec();
+            move || {
+                let shard = select_shard(&shards, min_free_space, &head)?;
+                let mut storage_name = random_file_path(&shards[shard])?;
+
+                // Use a temporary extension, as store() does
+                storage_name.set_extension("tmp");
+
+                let mut options = std::fs::OpenOptions::new();
+                options.write(true).create_new(true);
+                cfg_if::cfg_if! {
+                    if #[cfg(unix)] {
+                        options.custom_flags(libc::O_SYNC);
+                    } else if #[cfg(windows)] {
+                        options.custom_flags(winapi::FILE_FLAG_WRITE_THROUGH);
+                    }
+                }
+                let file = options.open(&storage_name)?;
+                Ok::<_, std::io::Error>((shard, storage_name, file))
+            }
+        })
+        .await
+        .trace_expect("Failed to spawn store_stream thread")?;
+
+        let mut file = tokio::fs::File::from_std(file);
+        if let Err(e) = async {
+            use tokio::io::AsyncWriteExt;
+
+            // Write the data as it arrives
+            file.write_all(&head).await?;
+            while let Some(chunk) = rx.recv().await {
+                file.write_all(&chunk).await?;
+            }
+
+            // Sync everything
+            file.sync_all().await
+        }
+        .await
+        {
+            _ = tokio::fs::remove_file(&storage_name).await;
+            return Err(e.into());
+        }
+        drop(file);
+
+        // Rename the file
+        let old_path = storage_name.clone();
+        storage_name.set_extension("");
+        if let Err(e) = tokio::fs::rename(&old_path, &storage_name).await {
+            _ = tokio::fs::remove_file(&old_path).await;
+            return Err(e.into());
+        }
+
+        Ok(shard_name(
+            shard,
+            storage_name.strip_prefix(&self.shards[shard])?,
+        ))
+    }
+
+    #[instrument(skip(self, tx))]
+    async fn load_stream(
+        &self,
+        storage_name: &str,
+        tx: tokio::sync::mpsc::Sender<Vec<u8>>,
+    ) -> storage::Result<bool> {
+        use tokio::io::AsyncReadExt;
+
+        let mut file = match tokio::fs::File::open(self.file_path(storage_name)?).await {
+            Err(e) => {
+                if let std::io::ErrorKind::NotFound = e.kind() {
+                    return Ok(false);
+                } else {
+                    return Err(e.into());
+                }
+            }
+            Ok(file) => file,
+        };
+
+        loop {
+            let mut chunk = vec![0u8; storage::STREAM_CHUNK_SIZE];
+            let len = file.read(&mut chunk).await?;
+            if len == 0 {
+                return Ok(true);
+            }
+            chunk.truncate(len);
+            tx.send(chunk).await?;
+        }
     }
 
     #[instrument(skip(self))]
     async fn remove(&self, storage_name: &str) -> storage::Result<()> {
-        match tokio::fs::remove_file(&self.store_root.join(PathBuf::from_str(storage_name)?)).await
-        {
+        self.check_writable()?;
+        match tokio::fs::remove_file(&self.file_path(storage_name)?).await {
             Ok(_) => Ok(()),
             Err(e) => {
                 if let std::io::ErrorKind::NotFound = e.kind() {
@@ -337,4 +665,174 @@ impl BundleStorage for Storage {
             }
         }
     }
+
+    #[instrument(skip(self))]
+    async fn load_range(
+        &self,
+        storage_name: &str,
+        offset: u64,
+        len: u64,
+    ) -> storage::Result<Option<DataRef>> {
+        let storage_name = self.file_path(storage_name)?;
+        let len = usize::try_from(len)?;
+        tokio::task::spawn_blocking(move || -> storage::Result<Option<DataRef>> {
+            let mut file = match std::fs::File::open(&storage_name) {
+                Err(e) => {
+                    if let std::io::ErrorKind::NotFound = e.kind() {
+                        return Ok(None);
+                    } else {
+                        return Err(e.into());
+                    }
+                }
+                Ok(file) => file,
+            };
+
+            // Only read the bytes requested
+            let mut data = vec![0u8; len];
+            file.seek(std::io::SeekFrom::Start(offset))?;
+            file.read_exact(&mut data)?;
+            Ok(Some(Arc::new(data) as DataRef))
+        })
+        .await
+        .trace_expect("Failed to spawn load_range thread")
+    }
+
+    fn supports_sparse_reassembly(&self) -> bool {
+        true
+    }
+
+    #[instrument(skip(self, data))]
+    async fn write_partial(
+        &self,
+        reassembly_id: &str,
+        offset: u64,
+        data: &[u8],
+    ) -> storage::Result<()> {
+        self.check_writable()?;
+        let file_path = partial_file_path(self.store_root(), reassembly_id)?;
+        let data = Box::from(data);
+        tokio::task::spawn_blocking(move || -> Result<(), std::io::Error> {
+            if let Some(parent) = file_path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+
+            // Writing beyond the end of the file leaves a hole, so only the fragments received take space
+            let mut file = std::fs::OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(&file_path)?;
+            file.seek(std::io::SeekFrom::Start(offset))?;
+            file.write_all(&data)?;
+            file.sync_data()?;
+
+            // Only once the data is safely written is the range recorded
+            let mut ranges = std::fs::OpenOptions::new()
+                .append(true)
+                .create(true)
+                .open(file_path.with_extension(RANGES_EXTENSION))?;
+            let mut record = [0u8; 16];
+            record[..8].copy_from_slice(&offset.to_be_bytes());
+            record[8..].copy_from_slice(&(data.len() as u64).to_be_bytes());
+            ranges.write_all(&record)?;
+            ranges.sync_data()
+        })
+        .await
+        .trace_expect("Failed to spawn write_partial thread")
+        .map_err(Into::into)
+    }
+
+    #[instrument(skip(self))]
+    async fn partial_ranges(&self, reassembly_id: &str) -> storage::Result<Vec<(u64, u64)>> {
+        let file_path = partial_file_path(self.store_root(), reassembly_id)?;
+        let records = match tokio::fs::read(file_path.with_extension(RANGES_EXTENSION)).await {
+            Ok(records) => records,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+
+        // A record torn by a crash was never acknowledged, so is ignored
+        Ok(records
+            .chunks_exact(16)
+            .map(|record| {
+                (
+                    u64::from_be_bytes(record[..8].try_into().unwrap()),
+                    u64::from_be_bytes(record[8..].try_into().unwrap()),
+                )
+            })
+            .collect())
+    }
+
+    #[instrument(skip(self))]
+    async fn load_partial(
+        &self,
+        reassembly_id: &str,
+        offset: u64,
+        len: u64,
+    ) -> storage::Result<Option<DataRef>> {
+        let file_path = partial_file_path(self.store_root(), reassembly_id)?;
+        let len = usize::try_from(len)?;
+        tokio::task::spawn_blocking(move || -> storage::Result<Option<DataRef>> {
+            let mut file = match std::fs::File::open(&file_path) {
+                Err(e) => {
+                    if let std::io::ErrorKind::NotFound = e.kind() {
+                        return Ok(None);
+                    } else {
+                        return Err(e.into());
+                    }
+                }
+                Ok(file) => file,
+            };
+
+            let mut data = vec![0u8; len];
+            file.seek(std::io::SeekFrom::Start(offset))?;
+            file.read_exact(&mut data)?;
+            Ok(Some(Arc::new(data) as DataRef))
+        })
+        .await
+        .trace_expect("Failed to spawn load_partial thread")
+    }
+
+    #[instrument(skip(self))]
+    async fn release_partial(
+        &self,
+        reassembly_id: &str,
+        offset: u64,
+        len: u64,
+    ) -> storage::Result<()> {
+        self.check_writable()?;
+        let file_path = partial_file_path(self.store_root(), reassembly_id)?;
+        tokio::task::spawn_blocking(move || {
+            // The file no longer holds every range written, so forget them before punching holes
+            remove_if_exists(&file_path.with_extension(RANGES_EXTENSION))?;
+
+            match std::fs::OpenOptions::new().write(true).open(&file_path) {
+                Err(e) => {
+                    if let std::io::ErrorKind::NotFound = e.kind() {
+                        Ok(())
+                    } else {
+                        Err(e)
+                    }
+                }
+                Ok(file) => punch_hole(&file, offset, len),
+            }
+        })
+        .await
+        .trace_expect("Failed to spawn release_partial thread")
+        .map_err(Into::into)
+    }
+
+    #[instrument(skip(self))]
+    async fn remove_partial(&self, reassembly_id: &str) -> storage::Result<()> {
+        self.check_writable()?;
+        let file_path = partial_file_path(self.store_root(), reassembly_id)?;
+        tokio::task::spawn_blocking(move || {
+            // The ranges first, so a partial file is never left looking complete
+            remove_if_exists(&file_path.with_extension(RANGES_EXTENSION))?;
+            remove_if_exists(&file_path)
+        })
+        .await
+        .trace_expect("Failed to spawn remove_partial thread")
+        .map_err(Into::into)
+    }
 }
@@ -0,0 +1,438 @@
+use thiserror::Error;
+use tokio_util::bytes::Bytes;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Segment is truncated")]
+    Truncated,
+
+    #[error("SDNV value is too large")]
+    SdnvOverflow,
+
+    #[error("Unsupported LTP version {0}")]
+    InvalidVersion(u8),
+
+    #[error("Reserved segment type {0:#x}")]
+    InvalidSegmentType(u8),
+
+    #[error("Report segment claims are out of bounds")]
+    InvalidBounds,
+}
+
+// The LTP client service of the Bundle Protocol, RFC7122
+pub const CLIENT_SERVICE_BP: u64 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SessionId {
+    pub originator: u64,
+    pub number: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Checkpoint {
+    pub serial: u64,
+    // The report this checkpoint responds to, or 0
+    pub report_serial: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DataSegment {
+    pub client_service_id: u64,
+    pub offset: u64,
+    pub data: Bytes,
+    pub red: bool,
+    pub checkpoint: Option<Checkpoint>,
+    pub end_of_red_part: bool,
+    pub end_of_block: bool,
+}
+
+impl DataSegment {
+    pub fn end(&self) -> u64 {
+        self.offset + self.data.len() as u64
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReportSegment {
+    pub serial: u64,
+    pub checkpoint_serial: u64,
+    pub upper_bound: u64,
+    pub lower_bound: u64,
+    // Offset and length of the data received, as offsets into the block
+    pub claims: Vec<(u64, u64)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CancelReason {
+    UserCancelled,
+    Unreachable,
+    RetransmissionLimit,
+    Miscolored,
+    SystemCancelled,
+    RetransmissionCycles,
+    Unrecognised(u8),
+}
+
+impl From<u8> for CancelReason {
+    fn from(value: u8) -> Self {
+        match value {
+            0 => Self::UserCancelled,
+            1 => Self::Unreachable,
+            2 => Self::RetransmissionLimit,
+            3 => Self::Miscolored,
+            4 => Self::SystemCancelled,
+            5 => Self::RetransmissionCycles,
+            v => Self::Unrecognised(v),
+        }
+    }
+}
+
+impl From<CancelReason> for u8 {
+    fn from(value: CancelReason) -> Self {
+        match value {
+            CancelReason::UserCancelled => 0,
+            CancelReason::Unreachable => 1,
+            CancelReason::RetransmissionLimit => 2,
+            CancelReason::Miscolored => 3,
+            CancelReason::SystemCancelled => 4,
+            CancelReason::RetransmissionCycles => 5,
+            CancelReason::Unrecognised(v) => v,
+        }
+    }
+}
+
+impl std::fmt::Display for CancelReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CancelReason::UserCancelled => write!(f, "Cancelled by user"),
+            CancelReason::Unreachable => write!(f, "Client service unreachable"),
+            CancelReason::RetransmissionLimit => write!(f, "Retransmission limit exceeded"),
+            CancelReason::Miscolored => write!(f, "Miscolored data received"),
+            CancelReason::SystemCancelled => write!(f, "Cancelled by system"),
+            CancelReason::RetransmissionCycles => write!(f, "Retransmission cycles exceeded"),
+            CancelReason::Unrecognised(v) => write!(f, "Unrecognised reason {v}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Content {
+    Data(DataSegment),
+    Report(ReportSegment),
+    ReportAck(u64),
+    CancelFromSender(CancelReason),
+    CancelAckToSender,
+    CancelFromReceiver(CancelReason),
+    CancelAckToReceiver,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Segment {
+    pub session: SessionId,
+    pub content: Content,
+}
+
+pub fn emit_sdnv(buf: &mut Vec<u8>, mut v: u64) {
+    let mut tmp = [0u8; 10];
+    let mut i = tmp.len() - 1;
+    tmp[i] = (v & 0x7F) as u8;
+    v >>= 7;
+    while v != 0 {
+        i -= 1;
+        tmp[i] = (v & 0x7F) as u8 | 0x80;
+        v >>= 7;
+    }
+    buf.extend_from_slice(&tmp[i..]);
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn byte(&mut self) -> Result<u8, Error> {
+        let b = *self.data.get(self.offset).ok_or(Error::Truncated)?;
+        self.offset += 1;
+        Ok(b)
+    }
+
+    fn bytes(&mut self, len: u64) -> Result<&'a [u8], Error> {
+        let end = usize::try_from(len)
+            .ok()
+            .and_then(|len| self.offset.checked_add(len))
+            .ok_or(Error::Truncated)?;
+        let bytes = self.data.get(self.offset..end).ok_or(Error::Truncated)?;
+        self.offset = end;
+        Ok(bytes)
+    }
+
+    fn sdnv(&mut self) -> Result<u64, Error> {
+        let mut v = 0u64;
+        loop {
+            let b = self.byte()?;
+            if v > (u64::MAX >> 7) {
+                return Err(Error::SdnvOverflow);
+            }
+            v = (v << 7) | (b & 0x7F) as u64;
+            if b & 0x80 == 0 {
+                return Ok(v);
+            }
+        }
+    }
+
+    fn skip_extensions(&mut self, count: u8) -> Result<(), Error> {
+        for _ in 0..count {
+            self.byte()?;
+            let len = self.sdnv()?;
+            self.bytes(len)?;
+        }
+        Ok(())
+    }
+}
+
+impl Segment {
+    fn segment_type(&self) -> u8 {
+        match &self.content {
+            Content::Data(d) if !d.red => {
+                if d.end_of_block {
+                    0x7
+                } else {
+                    0x4
+                }
+            }
+            Content::Data(d) => match (d.checkpoint, d.end_of_red_part, d.end_of_block) {
+                (None, _, _) => 0x0,
+                (Some(_), false, _) => 0x1,
+                (Some(_), true, false) => 0x2,
+                (Some(_), true, true) => 0x3,
+            },
+            Content::Report(_) => 0x8,
+            Content::ReportAck(_) => 0x9,
+            Content::CancelFromSender(_) => 0xC,
+            Content::CancelAckToSender => 0xD,
+            Content::CancelFromReceiver(_) => 0xE,
+            Content::CancelAckToReceiver => 0xF,
+        }
+    }
+
+    pub fn emit(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+
+        // Version 0, and no extensions
+        buf.push(self.segment_type());
+        emit_sdnv(&mut buf, self.session.originator);
+        emit_sdnv(&mut buf, self.session.number);
+        buf.push(0);
+
+        match &self.content {
+            Content::Data(d) => {
+                emit_sdnv(&mut buf, d.client_service_id);
+                emit_sdnv(&mut buf, d.offset);
+                emit_sdnv(&mut buf, d.data.len() as u64);
+                if let Some(checkpoint) = d.checkpoint.filter(|_| d.red) {
+                    emit_sdnv(&mut buf, checkpoint.serial);
+                    emit_sdnv(&mut buf, checkpoint.report_serial);
+                }
+                buf.extend_from_slice(&d.data);
+            }
+            Content::Report(r) => {
+                emit_sdnv(&mut buf, r.serial);
+                emit_sdnv(&mut buf, r.checkpoint_serial);
+                emit_sdnv(&mut buf, r.upper_bound);
+                emit_sdnv(&mut buf, r.lower_bound);
+                emit_sdnv(&mut buf, r.claims.len() as u64);
+                for (offset, len) in &r.claims {
+                    // Claim offsets are relative to the lower bound
+                    emit_sdnv(&mut buf, offset - r.lower_bound);
+                    emit_sdnv(&mut buf, *len);
+                }
+            }
+            Content::ReportAck(serial) => emit_sdnv(&mut buf, *serial),
+            Content::CancelFromSender(reason) | Content::CancelFromReceiver(reason) => {
+                buf.push((*reason).into())
+            }
+            Content::CancelAckToSender | Content::CancelAckToReceiver => {}
+        }
+        buf
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Self, Error> {
+        let mut r = Reader { data, offset: 0 };
+
+        let control = r.byte()?;
+        if control >> 4 != 0 {
+            return Err(Error::InvalidVersion(control >> 4));
+        }
+        let segment_type = control & 0x0F;
+
+        let session = SessionId {
+            originator: r.sdnv()?,
+            number: r.sdnv()?,
+        };
+
+        // Skip any header extensions, trailer extensions follow the content and are ignored
+        let extensions = r.byte()?;
+        r.skip_extensions(extensions >> 4)?;
+
+        let content = match segment_type {
+            0x0..=0x4 | 0x7 => {
+                let client_service_id = r.sdnv()?;
+                let offset = r.sdnv()?;
+                let len = r.sdnv()?;
+                let checkpoint = if (0x1..=0x3).contains(&segment_type) {
+                    Some(Checkpoint {
+                        serial: r.sdnv()?,
+                        report_serial: r.sdnv()?,
+                    })
+                } else {
+                    None
+                };
+                offset.checked_add(len).ok_or(Error::Truncated)?;
+                Content::Data(DataSegment {
+                    client_service_id,
+                    offset,
+                    data: Bytes::copy_from_slice(r.bytes(len)?),
+                    red: segment_type < 0x4,
+                    checkpoint,
+                    end_of_red_part: segment_type == 0x2 || segment_type == 0x3,
+                    end_of_block: segment_type == 0x3 || segment_type == 0x7,
+                })
+            }
+            0x8 => {
+                let serial = r.sdnv()?;
+                let checkpoint_serial = r.sdnv()?;
+                let upper_bound = r.sdnv()?;
+                let lower_bound = r.sdnv()?;
+                if lower_bound > upper_bound {
+                    return Err(Error::InvalidBounds);
+                }
+                let count = r.sdnv()?;
+                let mut claims = Vec::new();
+                for _ in 0..count {
+                    let offset = lower_bound
+                        .checked_add(r.sdnv()?)
+                        .ok_or(Error::InvalidBounds)?;
+                    let len = r.sdnv()?;
+                    if offset.checked_add(len).is_none_or(|end| end > upper_bound) {
+                        return Err(Error::InvalidBounds);
+                    }
+                    claims.push((offset, len));
+                }
+                Content::Report(ReportSegment {
+                    serial,
+                    checkpoint_serial,
+                    upper_bound,
+                    lower_bound,
+                    claims,
+                })
+            }
+            0x9 => Content::ReportAck(r.sdnv()?),
+            0xC => Content::CancelFromSender(r.byte()?.into()),
+            0xD => Content::CancelAckToSender,
+            0xE => Content::CancelFromReceiver(r.byte()?.into()),
+            0xF => Content::CancelAckToReceiver,
+            t => return Err(Error::InvalidSegmentType(t)),
+        };
+        Ok(Self { session, content })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sdnv() {
+        for (v, expected) in [
+            (0u64, &[0x00u8][..]),
+            (0x7F, &[0x7F]),
+            (0x80, &[0x81, 0x00]),
+            (0xABC, &[0x95, 0x3C]),
+            (0x1234, &[0xA4, 0x34]),
+        ] {
+            let mut buf = Vec::new();
+            emit_sdnv(&mut buf, v);
+            assert_eq!(buf, expected);
+            assert_eq!(
+                Reader {
+                    data: &buf,
+                    offset: 0
+                }
+                .sdnv()
+                .unwrap(),
+                v
+            );
+        }
+
+        let mut buf = Vec::new();
+        emit_sdnv(&mut buf, u64::MAX);
+        assert_eq!(
+            Reader {
+                data: &buf,
+                offset: 0
+            }
+            .sdnv()
+            .unwrap(),
+            u64::MAX
+        );
+
+        buf.insert(0, 0x81);
+        assert!(Reader {
+            data: &buf,
+            offset: 0
+        }
+        .sdnv()
+        .is_err());
+    }
+
+    #[test]
+    fn round_trip() {
+        let session = SessionId {
+            originator: 12,
+            number: 3456,
+        };
+        for content in [
+            Content::Data(DataSegment {
+                client_service_id: CLIENT_SERVICE_BP,
+                offset: 1000,
+                data: Bytes::from_static(b"red data"),
+                red: true,
+                checkpoint: Some(Checkpoint {
+                    serial: 7,
+                    report_serial: 0,
+                }),
+                end_of_red_part: true,
+                end_of_block: false,
+            }),
+            Content::Data(DataSegment {
+                client_service_id: CLIENT_SERVICE_BP,
+                offset: 1008,
+                data: Bytes::from_static(b"green data"),
+                red: false,
+                checkpoint: None,
+                end_of_red_part: false,
+                end_of_block: true,
+            }),
+            Content::Report(ReportSegment {
+                serial: 9,
+                checkpoint_serial: 7,
+                upper_bound: 1008,
+                lower_bound: 500,
+                claims: vec![(500, 100), (700, 308)],
+            }),
+            Content::ReportAck(9),
+            Content::CancelFromSender(CancelReason::RetransmissionLimit),
+            Content::CancelAckToSender,
+            Content::CancelFromReceiver(CancelReason::Unrecognised(42)),
+            Content::CancelAckToReceiver,
+        ] {
+            let segment = Segment { session, content };
+            assert_eq!(Segment::parse(&segment.emit()).unwrap(), segment);
+        }
+
+        // Truncated and reserved segments
+        assert!(Segment::parse(&[0x00, 0x0C]).is_err());
+        assert!(Segment::parse(&[0x05, 0x0C, 0x01, 0x00]).is_err());
+    }
+}
@@ -0,0 +1,424 @@
+use super::*;
+use codec::{CancelReason, Content, Segment, SessionId};
+use sender::Outcome;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use tokio::sync::{oneshot, Mutex};
+use tokio_util::bytes::Bytes;
+use utils::settings;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+
+    #[error("LTP engine is shutting down")]
+    Cancelled,
+}
+
+const DEFAULT_SEGMENT_MTU: usize = 1400;
+const DEFAULT_RETRANSMIT_TIMEOUT: u64 = 5;
+const DEFAULT_MAX_RETRANSMITS: u32 = 5;
+const DEFAULT_SESSION_TIMEOUT: u64 = 60;
+const DEFAULT_MAX_BLOCK_SIZE: u64 = 0x400_0000; // 64MiB
+const DEFAULT_MAX_SESSIONS: usize = 16;
+
+// How often retransmission timers are checked
+const TICK_INTERVAL: Duration = Duration::from_millis(250);
+
+#[derive(Clone)]
+pub struct Config {
+    pub engine_id: u64,
+    pub udp_address: SocketAddr,
+    pub segment_mtu: usize,
+    pub retransmit_timeout: Duration,
+    pub max_retransmits: u32,
+    pub session_timeout: Duration,
+    pub max_block_size: u64,
+    pub max_sessions: usize,
+}
+
+impl Config {
+    fn new(config: &config::Config) -> Self {
+        let config = Self {
+            engine_id: config
+                .get::<u64>("engine_id")
+                .trace_expect("Invalid or missing 'engine_id' value in configuration"),
+            udp_address: settings::get_with_default::<SocketAddr, SocketAddr>(
+                config,
+                "udp_address",
+                "[::1]:1113".parse().unwrap(),
+            )
+            .trace_expect("Invalid 'udp_address' value in configuration"),
+            segment_mtu: settings::get_with_default(config, "segment_mtu", DEFAULT_SEGMENT_MTU)
+                .trace_expect("Invalid 'segment_mtu' value in configuration"),
+            retransmit_timeout: Duration::from_secs(
+                settings::get_with_default(
+                    config,
+                    "retransmit_timeout",
+                    DEFAULT_RETRANSMIT_TIMEOUT,
+                )
+                .trace_expect("Invalid 'retransmit_timeout' value in configuration"),
+            ),
+            max_retransmits: settings::get_with_default(
+                config,
+                "max_retransmits",
+                DEFAULT_MAX_RETRANSMITS,
+            )
+            .trace_expect("Invalid 'max_retransmits' value in configuration"),
+            session_timeout: Duration::from_secs(
+                settings::get_with_default(config, "session_timeout", DEFAULT_SESSION_TIMEOUT)
+                    .trace_expect("Invalid 'session_timeout' value in configuration"),
+            ),
+            max_block_size: settings::get_with_default(
+                config,
+                "max_block_size",
+                DEFAULT_MAX_BLOCK_SIZE,
+            )
+            .trace_expect("Invalid 'max_block_size' value in configuration"),
+            max_sessions: settings::get_with_default(config, "max_sessions", DEFAULT_MAX_SESSIONS)
+                .trace_expect("Invalid 'max_sessions' value in configuration"),
+        };
+
+        if config.segment_mtu == 0 {
+            error!("'segment_mtu' must be greater than 0");
+            panic!("'segment_mtu' must be greater than 0");
+        }
+        config
+    }
+}
+
+struct Outbound {
+    sender: sender::Sender,
+    peer: SocketAddr,
+    result: oneshot::Sender<Outcome>,
+}
+
+struct Inbound {
+    receiver: receiver::Receiver,
+    peer: SocketAddr,
+}
+
+pub struct Engine {
+    config: Config,
+    socket: tokio::net::UdpSocket,
+    next_session: AtomicU64,
+    outbound: Mutex<HashMap<u64, Outbound>>,
+    inbound: Mutex<HashMap<SessionId, Inbound>>,
+}
+
+impl Engine {
+    pub async fn new(config: &config::Config) -> Arc<Self> {
+        let config = Config::new(config);
+        let socket = tokio::net::UdpSocket::bind(config.udp_address)
+            .await
+            .trace_expect("Failed to bind UDP socket");
+        info!(
+            "LTP engine {} listening on UDP {}",
+            config.engine_id, config.udp_address
+        );
+
+        // Start numbering sessions from the clock, so a restarted engine does not reuse recent session numbers
+        let first_session = (time::OffsetDateTime::now_utc().unix_timestamp() as u64) << 16;
+
+        Arc::new(Self {
+            config,
+            socket,
+            next_session: AtomicU64::new(first_session.max(1)),
+            outbound: Default::default(),
+            inbound: Default::default(),
+        })
+    }
+
+    pub fn start(
+        self: &Arc<Self>,
+        bpa: bpa::Bpa,
+        task_set: &mut tokio::task::JoinSet<()>,
+        cancel_token: tokio_util::sync::CancellationToken,
+    ) {
+        // Receive segments
+        let engine = self.clone();
+        let cancel = cancel_token.clone();
+        task_set.spawn(async move {
+            let mut buffer = vec![0u8; 65536];
+            loop {
+                tokio::select! {
+                    r = engine.socket.recv_from(&mut buffer) => match r {
+                        Ok((len, from)) => match Segment::parse(&buffer[..len]) {
+                            Ok(segment) => engine.on_segment(&bpa, from, segment).await,
+                            Err(e) => trace!("Invalid LTP segment received from {from}: {e}"),
+                        },
+                        Err(e) => warn!("Failed to receive from UDP socket: {e}"),
+                    },
+                    _ = cancel.cancelled() => break
+                }
+            }
+        });
+
+        // Run the retransmission timers
+        let engine = self.clone();
+        task_set.spawn(async move {
+            let mut interval = tokio::time::interval(TICK_INTERVAL);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => engine.on_tick().await,
+                    _ = cancel_token.cancelled() => break
+                }
+            }
+
+            // Fail anything still being sent
+            engine.outbound.lock().await.clear();
+        });
+    }
+
+    async fn transmit(&self, peer: SocketAddr, segments: &[Segment]) -> Result<(), Error> {
+        for segment in segments {
+            self.socket.send_to(&segment.emit(), peer).await?;
+        }
+        Ok(())
+    }
+
+    async fn reply(&self, peer: SocketAddr, segments: &[Segment]) {
+        if let Err(e) = self.transmit(peer, segments).await {
+            warn!("Failed to send LTP segments to {peer}: {e}");
+        }
+    }
+
+    // Send a block to a peer, completing once the red part has been reported received
+    #[instrument(skip(self, data))]
+    pub async fn send(&self, peer: &peers::Peer, data: Bytes) -> Result<Outcome, Error> {
+        let session = SessionId {
+            originator: self.config.engine_id,
+            number: self.next_session.fetch_add(1, Ordering::Relaxed),
+        };
+        let red_len = peer.red_part_length.unwrap_or(data.len() as u64);
+        let mut sender = sender::Sender::new(session, data, red_len);
+        let segments = sender.start(&self.config, Instant::now());
+
+        if sender.is_complete() {
+            // All green, there is nothing to wait for
+            self.transmit(peer.address, &segments).await?;
+            return Ok(Outcome::Sent);
+        }
+
+        let (tx, rx) = oneshot::channel();
+        {
+            let mut outbound = self.outbound.lock().await;
+            if outbound.values().filter(|o| o.peer == peer.address).count()
+                >= self.config.max_sessions
+            {
+                trace!("Too many sessions open to {}", peer.address);
+                return Ok(Outcome::Congested);
+            }
+            outbound.insert(
+                session.number,
+                Outbound {
+                    sender,
+                    peer: peer.address,
+                    result: tx,
+                },
+            );
+        }
+
+        if let Err(e) = self.transmit(peer.address, &segments).await {
+            self.outbound.lock().await.remove(&session.number);
+            return Err(e);
+        }
+        rx.await.map_err(|_| Error::Cancelled)
+    }
+
+    async fn on_segment(&self, bpa: &bpa::Bpa, from: SocketAddr, segment: Segment) {
+        let session = segment.session;
+        let now = Instant::now();
+        match segment.content {
+            Content::Data(data) => {
+                if data.client_service_id != codec::CLIENT_SERVICE_BP {
+                    trace!(
+                        "LTP data for unknown client service {}",
+                        data.client_service_id
+                    );
+                    return self
+                        .reply(
+                            from,
+                            &[Segment {
+                                session,
+                                content: Content::CancelFromReceiver(CancelReason::Unreachable),
+                            }],
+                        )
+                        .await;
+                }
+
+                let (replies, block) = {
+                    let mut inbound = self.inbound.lock().await;
+                    let i = inbound.entry(session).or_insert_with(|| Inbound {
+                        receiver: receiver::Receiver::new(session, now),
+                        peer: from,
+                    });
+                    match i.receiver.on_data(data, &self.config, now) {
+                        Ok(r) => r,
+                        Err(reason) => {
+                            trace!("Cancelling LTP session {session:?}: {reason}");
+                            inbound.remove(&session);
+                            (
+                                vec![Segment {
+                                    session,
+                                    content: Content::CancelFromReceiver(reason),
+                                }],
+                                None,
+                            )
+                        }
+                    }
+                };
+                self.reply(from, &replies).await;
+
+                if let Some(block) = block {
+                    // Don't hold up the receive loop while the BPA deals with the bundle
+                    let bpa = bpa.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = bpa.send(block).await {
+                            warn!("Failed to pass bundle to BPA: {e}");
+                        }
+                    });
+                }
+            }
+            Content::ReportAck(serial) => {
+                if let Some(i) = self.inbound.lock().await.get_mut(&session) {
+                    i.receiver.on_report_ack(serial, now);
+                }
+            }
+            Content::CancelFromSender(reason) => {
+                trace!("LTP session {session:?} cancelled by sender: {reason}");
+                self.inbound.lock().await.remove(&session);
+                self.reply(
+                    from,
+                    &[Segment {
+                        session,
+                        content: Content::CancelAckToSender,
+                    }],
+                )
+                .await;
+            }
+            Content::Report(report) if session.originator == self.config.engine_id => {
+                let replies = {
+                    let mut outbound = self.outbound.lock().await;
+                    let (replies, complete) = match outbound.get_mut(&session.number) {
+                        Some(o) => {
+                            let replies = o.sender.on_report(&report, &self.config, now);
+                            (replies, o.sender.is_complete())
+                        }
+                        None => {
+                            // Acknowledge reports for sessions already complete
+                            (
+                                vec![Segment {
+                                    session,
+                                    content: Content::ReportAck(report.serial),
+                                }],
+                                false,
+                            )
+                        }
+                    };
+                    if complete {
+                        if let Some(o) = outbound.remove(&session.number) {
+                            _ = o.result.send(Outcome::Sent);
+                        }
+                    }
+                    replies
+                };
+                self.reply(from, &replies).await;
+            }
+            Content::CancelFromReceiver(reason) if session.originator == self.config.engine_id => {
+                trace!("LTP session {session:?} cancelled by receiver: {reason}");
+                if let Some(o) = self.outbound.lock().await.remove(&session.number) {
+                    _ = o.result.send(Outcome::Cancelled(reason));
+                }
+                self.reply(
+                    from,
+                    &[Segment {
+                        session,
+                        content: Content::CancelAckToReceiver,
+                    }],
+                )
+                .await;
+            }
+            Content::Report(_) | Content::CancelFromReceiver(_) => {
+                trace!("LTP segment from {from} for a session we did not originate");
+            }
+            // Cancellations are sent once, so their acknowledgements are not awaited
+            Content::CancelAckToSender | Content::CancelAckToReceiver => {}
+        }
+    }
+
+    async fn on_tick(&self) {
+        let now = Instant::now();
+        let mut out = Vec::new();
+
+        {
+            let mut outbound = self.outbound.lock().await;
+            let mut failed = Vec::new();
+            for (number, o) in outbound.iter_mut() {
+                match o.sender.on_timer(&self.config, now) {
+                    Ok(segments) => out.extend(segments.into_iter().map(|s| (o.peer, s))),
+                    Err(reason) => failed.push((*number, reason)),
+                }
+            }
+            for (number, reason) in failed {
+                let o = outbound.remove(&number).unwrap();
+                trace!("Cancelling LTP session {number} to {}: {reason}", o.peer);
+                out.push((
+                    o.peer,
+                    Segment {
+                        session: SessionId {
+                            originator: self.config.engine_id,
+                            number,
+                        },
+                        content: Content::CancelFromSender(reason),
+                    },
+                ));
+                _ = o.result.send(Outcome::Cancelled(reason));
+            }
+        }
+
+        {
+            let mut inbound = self.inbound.lock().await;
+            let mut failed = Vec::new();
+            for (session, i) in inbound.iter_mut() {
+                if i.receiver.is_idle(&self.config, now) {
+                    if !i.receiver.is_delivered() {
+                        info!("Incomplete LTP block from {} timed out", i.peer);
+                    }
+                    failed.push((*session, None));
+                    continue;
+                }
+                match i.receiver.on_timer(&self.config, now) {
+                    Ok(segments) => out.extend(segments.into_iter().map(|s| (i.peer, s))),
+                    Err(reason) => failed.push((*session, Some(reason))),
+                }
+            }
+            for (session, reason) in failed {
+                let i = inbound.remove(&session).unwrap();
+                if let Some(reason) = reason {
+                    trace!(
+                        "Cancelling LTP session {session:?} from {}: {reason}",
+                        i.peer
+                    );
+                    out.push((
+                        i.peer,
+                        Segment {
+                            session,
+                            content: Content::CancelFromReceiver(reason),
+                        },
+                    ));
+                }
+            }
+        }
+
+        for (peer, segment) in out {
+            self.reply(peer, &[segment]).await;
+        }
+    }
+}
@@ -0,0 +1,113 @@
+use super::*;
+use cla_server::{Cla, ClaServer};
+use hardy_proto::cla::*;
+use sender::Outcome;
+use tonic::{Request, Response, Status};
+
+const DEFAULT_CONGESTION_BACKOFF: u64 = 5;
+
+pub struct Service {
+    engine: Arc<engine::Engine>,
+    peers: Arc<Vec<peers::Peer>>,
+    congestion_backoff: time::Duration,
+}
+
+impl Service {
+    fn new(
+        config: &config::Config,
+        engine: Arc<engine::Engine>,
+        peers: Arc<Vec<peers::Peer>>,
+    ) -> Self {
+        Service {
+            engine,
+            peers,
+            congestion_backoff: time::Duration::seconds(
+                settings::get_with_default::<u64, _>(
+                    config,
+                    "congestion_backoff",
+                    DEFAULT_CONGESTION_BACKOFF,
+                )
+                .trace_expect("Invalid 'congestion_backoff' value in configuration")
+                    as i64,
+            ),
+        }
+    }
+
+    fn congested(&self) -> ForwardBundleResponse {
+        ForwardBundleResponse {
+            result: forward_bundle_response::ForwardingResult::Congested as i32,
+            delay: Some(to_timestamp(
+                time::OffsetDateTime::now_utc() + self.congestion_backoff,
+            )),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl Cla for Service {
+    #[instrument(skip(self))]
+    async fn forward_bundle(
+        &self,
+        request: Request<ForwardBundleRequest>,
+    ) -> Result<Response<ForwardBundleResponse>, Status> {
+        let request = request.into_inner();
+        let destination = request
+            .destination
+            .parse::<bpv7::Eid>()
+            .map_err(|e| Status::invalid_argument(format!("Invalid destination: {e}")))?;
+        let peer = match request.address {
+            Some(address) => {
+                let address = address
+                    .parse()
+                    .map_err(|e| Status::invalid_argument(format!("Invalid address: {e}")))?;
+                peers::find_by_address(&self.peers, &address)
+            }
+            None => peers::find(&self.peers, &destination),
+        };
+        let Some(peer) = peer else {
+            return Err(Status::not_found(format!(
+                "No LTP peer for destination {destination}"
+            )));
+        };
+
+        let outcome = self
+            .engine
+            .send(peer, request.bundle)
+            .await
+            .map_err(|e| Status::unavailable(e.to_string()))?;
+
+        match outcome {
+            Outcome::Sent => Ok(Response::new(ForwardBundleResponse {
+                result: forward_bundle_response::ForwardingResult::Sent as i32,
+                delay: None,
+            })),
+            Outcome::Congested => Ok(Response::new(self.congested())),
+            // The link is disrupted, or the peer is short of resources, so try again later
+            Outcome::Cancelled(
+                codec::CancelReason::RetransmissionLimit
+                | codec::CancelReason::RetransmissionCycles
+                | codec::CancelReason::SystemCancelled
+                | codec::CancelReason::Unreachable,
+            ) => Ok(Response::new(self.congested())),
+            Outcome::Cancelled(reason) => {
+                Err(Status::aborted(format!("LTP session cancelled: {reason}")))
+            }
+        }
+    }
+
+    #[instrument(skip(self))]
+    async fn check_health(
+        &self,
+        _request: Request<CheckHealthRequest>,
+    ) -> Result<Response<CheckHealthResponse>, Status> {
+        Ok(Response::new(CheckHealthResponse {}))
+    }
+}
+
+pub fn new_service(
+    config: &config::Config,
+    engine: Arc<engine::Engine>,
+    peers: Arc<Vec<peers::Peer>>,
+) -> ClaServer<Service> {
+    ClaServer::new(Service::new(config, engine, peers))
+}
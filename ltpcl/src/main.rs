@@ -0,0 +1,81 @@
+mod bpa;
+mod codec;
+mod engine;
+mod grpc;
+mod peers;
+mod ranges;
+mod receiver;
+mod sender;
+mod utils;
+
+// Buildtime info
+mod built_info {
+    // The file has been placed there by the build script.
+    include!(concat!(env!("OUT_DIR"), "/built.rs"));
+}
+
+// This is the effective prelude
+use hardy_bpv7::prelude as bpv7;
+use trace_err::*;
+use tracing::{error, info, instrument, trace, warn};
+
+#[tokio::main]
+async fn main() {
+    // Parse command line
+    let Some((config, config_source)) = utils::settings::init() else {
+        return;
+    };
+
+    // Init logger
+    utils::logger::init(&config);
+    info!(
+        "{} version {} starting...",
+        built_info::PKG_NAME,
+        built_info::PKG_VERSION
+    );
+    info!("{config_source}");
+
+    // Load the peers
+    let peers = std::sync::Arc::new(peers::load(&config));
+
+    // New BPA connection
+    let mut bpa = bpa::Bpa::new(&config);
+
+    // Prepare for graceful shutdown
+    let (mut task_set, cancel_token) = utils::cancel::new_cancellable_set();
+
+    // New LTP engine
+    let engine = engine::Engine::new(&config).await;
+
+    // Init gRPC services
+    grpc::init(
+        &config,
+        engine.clone(),
+        peers.clone(),
+        &mut task_set,
+        cancel_token.clone(),
+    );
+
+    // Connect to the BPA
+    if !cancel_token.is_cancelled() {
+        bpa.connect(&peers).await;
+    }
+
+    // Start the engine
+    if !cancel_token.is_cancelled() {
+        engine.start(bpa.clone(), &mut task_set, cancel_token.clone());
+    }
+
+    // Wait for all tasks to finish
+    if !cancel_token.is_cancelled() {
+        info!("Started successfully");
+    }
+    while let Some(r) = task_set.join_next().await {
+        r.trace_expect("Task terminated unexpectedly")
+    }
+
+    // Unregister from BPA
+    bpa.disconnect().await;
+
+    info!("Stopped");
+}
@@ -0,0 +1,56 @@
+use super::*;
+use std::net::SocketAddr;
+use utils::settings;
+
+// A peer, as it appears in the configuration
+#[derive(serde::Deserialize)]
+struct PeerSettings {
+    neighbour: String,
+    address: SocketAddr,
+    red_part_length: Option<u64>,
+    #[serde(default)]
+    priority: u32,
+    mtu: Option<u64>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Peer {
+    // The EIDs reachable via the peer
+    pub neighbour: bpv7::EidPattern,
+    pub address: SocketAddr,
+    // None to send the whole block as red, 0 for all green
+    pub red_part_length: Option<u64>,
+    pub priority: u32,
+    // The largest bundle to send to the peer, advertised to the BPA
+    pub mtu: Option<u64>,
+}
+
+pub fn load(config: &config::Config) -> Vec<Peer> {
+    let peers = settings::get_with_default::<Vec<PeerSettings>, _>(config, "peers", Vec::new())
+        .trace_expect("Invalid 'peers' value in configuration")
+        .into_iter()
+        .map(|p| Peer {
+            neighbour: p
+                .neighbour
+                .parse()
+                .trace_expect("Invalid 'neighbour' EID pattern in 'peers' configuration"),
+            address: p.address,
+            red_part_length: p.red_part_length,
+            priority: p.priority,
+            mtu: p.mtu,
+        })
+        .collect::<Vec<_>>();
+
+    if peers.is_empty() {
+        warn!("No LTP peers configured, no bundles will be forwarded");
+    }
+    peers
+}
+
+pub fn find<'a>(peers: &'a [Peer], destination: &bpv7::Eid) -> Option<&'a Peer> {
+    peers.iter().find(|p| p.neighbour.is_match(destination))
+}
+
+pub fn find_by_address<'a>(peers: &'a [Peer], address: &SocketAddr) -> Option<&'a Peer> {
+    peers.iter().find(|p| &p.address == address)
+}
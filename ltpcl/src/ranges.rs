@@ -0,0 +1,98 @@
+// A set of half-open byte ranges, kept sorted and merged
+#[derive(Debug, Default, Clone)]
+pub struct RangeSet(Vec<(u64, u64)>);
+
+impl RangeSet {
+    pub fn insert(&mut self, mut start: u64, mut end: u64) {
+        if start >= end {
+            return;
+        }
+
+        let mut merged = Vec::with_capacity(self.0.len() + 1);
+        let mut placed = false;
+        for &(s, e) in &self.0 {
+            if e < start {
+                merged.push((s, e));
+            } else if s > end {
+                if !placed {
+                    merged.push((start, end));
+                    placed = true;
+                }
+                merged.push((s, e));
+            } else {
+                // Overlapping or adjacent
+                start = start.min(s);
+                end = end.max(e);
+            }
+        }
+        if !placed {
+            merged.push((start, end));
+        }
+        self.0 = merged;
+    }
+
+    pub fn covers(&self, start: u64, end: u64) -> bool {
+        start >= end || self.0.iter().any(|&(s, e)| s <= start && e >= end)
+    }
+
+    // The parts of [start, end) in the set
+    pub fn within(&self, start: u64, end: u64) -> Vec<(u64, u64)> {
+        self.0
+            .iter()
+            .filter_map(|&(s, e)| {
+                let s = s.max(start);
+                let e = e.min(end);
+                (s < e).then_some((s, e))
+            })
+            .collect()
+    }
+
+    // The parts of [start, end) not in the set
+    pub fn gaps(&self, start: u64, end: u64) -> Vec<(u64, u64)> {
+        let mut gaps = Vec::new();
+        let mut pos = start;
+        for &(s, e) in &self.0 {
+            if pos >= end || s >= end {
+                break;
+            }
+            if e <= pos {
+                continue;
+            }
+            if s > pos {
+                gaps.push((pos, s));
+            }
+            pos = e;
+        }
+        if pos < end {
+            gaps.push((pos, end));
+        }
+        gaps
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test() {
+        let mut r = RangeSet::default();
+        r.insert(10, 20);
+        r.insert(30, 40);
+        r.insert(0, 5);
+        assert_eq!(r.0, vec![(0, 5), (10, 20), (30, 40)]);
+
+        assert_eq!(r.gaps(0, 50), vec![(5, 10), (20, 30), (40, 50)]);
+        assert_eq!(r.gaps(12, 35), vec![(20, 30)]);
+        assert_eq!(r.within(3, 35), vec![(3, 5), (10, 20), (30, 35)]);
+        assert!(r.covers(11, 19));
+        assert!(!r.covers(15, 25));
+
+        // Adjacent and overlapping ranges merge
+        r.insert(5, 10);
+        r.insert(18, 32);
+        assert_eq!(r.0, vec![(0, 40)]);
+        assert!(r.covers(0, 40));
+        assert!(r.gaps(0, 40).is_empty());
+    }
+}
@@ -0,0 +1,166 @@
+use super::*;
+use codec::{CancelReason, Content, DataSegment, ReportSegment, Segment, SessionId};
+use std::collections::HashMap;
+use std::time::Instant;
+use tokio_util::bytes::Bytes;
+
+/* The receiving side of an LTP session.  Every checkpoint is answered with a report of
+ * the red data received so far, which is sent again until the sender acknowledges it.
+ * The block is complete once everything up to the end of the block has arrived: a
+ * block whose green data is lost cannot be delivered, and is dropped when the session
+ * goes idle */
+
+struct PendingReport {
+    segment: ReportSegment,
+    deadline: Instant,
+    retries: u32,
+}
+
+pub struct Receiver {
+    session: SessionId,
+    data: Vec<u8>,
+    received: ranges::RangeSet,
+    red_len: Option<u64>,
+    block_len: Option<u64>,
+    next_report_serial: u64,
+    reports: HashMap<u64, PendingReport>,
+    delivered: bool,
+    last_activity: Instant,
+}
+
+impl Receiver {
+    pub fn new(session: SessionId, now: Instant) -> Self {
+        Self {
+            session,
+            data: Vec::new(),
+            received: ranges::RangeSet::default(),
+            red_len: None,
+            block_len: None,
+            next_report_serial: 1,
+            reports: HashMap::new(),
+            delivered: false,
+            last_activity: now,
+        }
+    }
+
+    pub fn is_delivered(&self) -> bool {
+        self.delivered
+    }
+
+    pub fn is_idle(&self, config: &engine::Config, now: Instant) -> bool {
+        now.saturating_duration_since(self.last_activity) >= config.session_timeout
+    }
+
+    // Returns the segments to send in reply, and the block if it is now complete
+    pub fn on_data(
+        &mut self,
+        segment: DataSegment,
+        config: &engine::Config,
+        now: Instant,
+    ) -> Result<(Vec<Segment>, Option<Bytes>), CancelReason> {
+        self.last_activity = now;
+
+        let end = segment.end();
+        if end > config.max_block_size {
+            return Err(CancelReason::SystemCancelled);
+        }
+
+        // Red data must all come before green data
+        let miscolored = match self.red_len {
+            Some(red_len) if segment.red => end > red_len,
+            Some(red_len) => segment.offset < red_len,
+            None => false,
+        };
+        if miscolored {
+            return Err(CancelReason::Miscolored);
+        }
+
+        if !self.delivered {
+            if self.data.len() < end as usize {
+                self.data.resize(end as usize, 0);
+            }
+            self.data[segment.offset as usize..end as usize].copy_from_slice(&segment.data);
+        }
+        self.received.insert(segment.offset, end);
+
+        if segment.end_of_red_part {
+            self.red_len = Some(end);
+        }
+        if segment.end_of_block {
+            self.block_len = Some(end);
+        }
+
+        let mut out = Vec::new();
+        if let Some(checkpoint) = segment.checkpoint.filter(|_| segment.red) {
+            let serial = self.next_report_serial;
+            self.next_report_serial += 1;
+
+            let report = ReportSegment {
+                serial,
+                checkpoint_serial: checkpoint.serial,
+                upper_bound: end,
+                lower_bound: 0,
+                claims: self
+                    .received
+                    .within(0, end)
+                    .into_iter()
+                    .map(|(s, e)| (s, e - s))
+                    .collect(),
+            };
+            out.push(Segment {
+                session: self.session,
+                content: Content::Report(report.clone()),
+            });
+            self.reports.insert(
+                serial,
+                PendingReport {
+                    segment: report,
+                    deadline: now + config.retransmit_timeout,
+                    retries: 0,
+                },
+            );
+        }
+
+        if !self.delivered
+            && self
+                .block_len
+                .is_some_and(|block_len| self.received.covers(0, block_len))
+        {
+            // Keep the session, to answer checkpoints sent again, but not the data
+            self.delivered = true;
+            let mut data = std::mem::take(&mut self.data);
+            data.truncate(self.block_len.unwrap() as usize);
+            return Ok((out, Some(data.into())));
+        }
+        Ok((out, None))
+    }
+
+    pub fn on_report_ack(&mut self, serial: u64, now: Instant) {
+        self.last_activity = now;
+        self.reports.remove(&serial);
+    }
+
+    // Send reports again if they have not been acknowledged in time
+    pub fn on_timer(
+        &mut self,
+        config: &engine::Config,
+        now: Instant,
+    ) -> Result<Vec<Segment>, CancelReason> {
+        let mut out = Vec::new();
+        for pending in self.reports.values_mut() {
+            if pending.deadline > now {
+                continue;
+            }
+            if pending.retries >= config.max_retransmits {
+                return Err(CancelReason::RetransmissionLimit);
+            }
+            pending.retries += 1;
+            pending.deadline = now + config.retransmit_timeout;
+            out.push(Segment {
+                session: self.session,
+                content: Content::Report(pending.segment.clone()),
+            });
+        }
+        Ok(out)
+    }
+}
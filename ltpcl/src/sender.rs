@@ -0,0 +1,260 @@
+use super::*;
+use codec::{CancelReason, Checkpoint, Content, DataSegment, ReportSegment, Segment, SessionId};
+use std::collections::HashMap;
+use std::time::Instant;
+use tokio_util::bytes::Bytes;
+
+/* The sending side of an LTP session.  The red part of the block is sent reliably:
+ * its last segment is a checkpoint, which the receiver answers with a report of what
+ * it has received, and anything missing is sent again with a new checkpoint until the
+ * whole red part has been reported.  The green part is sent once, best-effort */
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Sent,
+    Congested,
+    Cancelled(CancelReason),
+}
+
+struct PendingCheckpoint {
+    segment: DataSegment,
+    deadline: Instant,
+    retries: u32,
+}
+
+pub struct Sender {
+    session: SessionId,
+    data: Bytes,
+    red_len: u64,
+    next_checkpoint_serial: u64,
+    checkpoints: HashMap<u64, PendingCheckpoint>,
+    reported: ranges::RangeSet,
+}
+
+impl Sender {
+    pub fn new(session: SessionId, data: Bytes, red_len: u64) -> Self {
+        Self {
+            session,
+            red_len: red_len.min(data.len() as u64),
+            data,
+            next_checkpoint_serial: 1,
+            checkpoints: HashMap::new(),
+            reported: ranges::RangeSet::default(),
+        }
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.reported.covers(0, self.red_len)
+    }
+
+    fn segment(&self, content: Content) -> Segment {
+        Segment {
+            session: self.session,
+            content,
+        }
+    }
+
+    // Split [start, end) of the block into segments
+    fn split(&self, start: u64, end: u64, red: bool, config: &engine::Config) -> Vec<DataSegment> {
+        let mut segments = Vec::new();
+        let mut offset = start;
+        while offset < end {
+            let len = (end - offset).min(config.segment_mtu as u64);
+            segments.push(DataSegment {
+                client_service_id: codec::CLIENT_SERVICE_BP,
+                offset,
+                data: self.data.slice(offset as usize..(offset + len) as usize),
+                red,
+                checkpoint: None,
+                end_of_red_part: false,
+                end_of_block: false,
+            });
+            offset += len;
+        }
+        segments
+    }
+
+    // Make the last of the segments a checkpoint
+    fn checkpoint(
+        &mut self,
+        segments: &mut [DataSegment],
+        report_serial: u64,
+        config: &engine::Config,
+        now: Instant,
+    ) {
+        let Some(segment) = segments.last_mut() else {
+            return;
+        };
+
+        let serial = self.next_checkpoint_serial;
+        self.next_checkpoint_serial += 1;
+
+        segment.end_of_red_part = segment.end() == self.red_len;
+        segment.end_of_block = segment.end() == self.data.len() as u64;
+        segment.checkpoint = Some(Checkpoint {
+            serial,
+            report_serial,
+        });
+        self.checkpoints.insert(
+            serial,
+            PendingCheckpoint {
+                segment: segment.clone(),
+                deadline: now + config.retransmit_timeout,
+                retries: 0,
+            },
+        );
+    }
+
+    pub fn start(&mut self, config: &engine::Config, now: Instant) -> Vec<Segment> {
+        let len = self.data.len() as u64;
+        let mut segments = self.split(0, self.red_len, true, config);
+        self.checkpoint(&mut segments, 0, config, now);
+
+        let mut green = self.split(self.red_len, len, false, config);
+        if let Some(last) = green.last_mut() {
+            last.end_of_block = true;
+        }
+        segments.extend(green);
+
+        segments
+            .into_iter()
+            .map(|s| self.segment(Content::Data(s)))
+            .collect()
+    }
+
+    pub fn on_report(
+        &mut self,
+        report: &ReportSegment,
+        config: &engine::Config,
+        now: Instant,
+    ) -> Vec<Segment> {
+        // Always acknowledge, even if we have seen the report before
+        let mut out = vec![self.segment(Content::ReportAck(report.serial))];
+
+        self.checkpoints.remove(&report.checkpoint_serial);
+        for (offset, len) in &report.claims {
+            self.reported
+                .insert(*offset, (offset + len).min(self.red_len));
+        }
+        if self.is_complete() {
+            return out;
+        }
+
+        // Send again what the report says is missing
+        let mut segments = Vec::new();
+        for (start, end) in self
+            .reported
+            .gaps(report.lower_bound, report.upper_bound.min(self.red_len))
+        {
+            segments.extend(self.split(start, end, true, config));
+        }
+        self.checkpoint(&mut segments, report.serial, config, now);
+
+        out.extend(segments.into_iter().map(|s| self.segment(Content::Data(s))));
+        out
+    }
+
+    // Send checkpoints again if they have not been reported in time
+    pub fn on_timer(
+        &mut self,
+        config: &engine::Config,
+        now: Instant,
+    ) -> Result<Vec<Segment>, CancelReason> {
+        let mut out = Vec::new();
+        for pending in self.checkpoints.values_mut() {
+            if pending.deadline > now {
+                continue;
+            }
+            if pending.retries >= config.max_retransmits {
+                return Err(CancelReason::RetransmissionLimit);
+            }
+            pending.retries += 1;
+            pending.deadline = now + config.retransmit_timeout;
+            out.push(Segment {
+                session: self.session,
+                content: Content::Data(pending.segment.clone()),
+            });
+        }
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test() {
+        let config = engine::Config {
+            engine_id: 1,
+            udp_address: "[::1]:1113".parse().unwrap(),
+            segment_mtu: 10,
+            retransmit_timeout: Duration::from_secs(1),
+            max_retransmits: 2,
+            session_timeout: Duration::from_secs(10),
+            max_block_size: 1000,
+            max_sessions: 1,
+        };
+        let session = SessionId {
+            originator: 1,
+            number: 1,
+        };
+        let data = Bytes::from((0..95u8).collect::<Vec<_>>());
+        let now = Instant::now();
+        let mut sender = Sender::new(session, data.clone(), 60);
+        let mut receiver = receiver::Receiver::new(session, now);
+
+        // 6 red segments and 4 green, losing the second
+        let segments = sender.start(&config, now);
+        assert_eq!(segments.len(), 10);
+        let mut replies = Vec::new();
+        for (i, segment) in segments.into_iter().enumerate() {
+            let Content::Data(d) = segment.content else {
+                panic!("Not a data segment");
+            };
+            if i != 1 {
+                let (r, block) = receiver.on_data(d, &config, now).unwrap();
+                assert!(block.is_none());
+                replies.extend(r);
+            }
+        }
+        assert_eq!(replies.len(), 1);
+        let Content::Report(report) = &replies[0].content else {
+            panic!("Not a report segment");
+        };
+        assert_eq!(report.claims, vec![(0, 10), (20, 40)]);
+
+        // The report is acknowledged, and the lost segment sent again as a checkpoint
+        let replies = sender.on_report(report, &config, now);
+        assert_eq!(replies.len(), 2);
+        assert_eq!(replies[0].content, Content::ReportAck(report.serial));
+        let Content::Data(d) = replies[1].content.clone() else {
+            panic!("Not a data segment");
+        };
+        assert_eq!((d.offset, d.data.len()), (10, 10));
+        assert!(d.checkpoint.is_some());
+
+        let (replies, block) = receiver.on_data(d, &config, now).unwrap();
+        assert_eq!(block.unwrap(), data);
+        let Content::Report(report) = &replies[0].content else {
+            panic!("Not a report segment");
+        };
+        assert_eq!(sender.on_report(report, &config, now).len(), 1);
+        assert!(sender.is_complete());
+
+        // Unreported checkpoints are sent again, until the limit
+        let mut sender = Sender::new(session, data, 60);
+        sender.start(&config, now);
+        let later = now + Duration::from_secs(2);
+        assert_eq!(sender.on_timer(&config, later).unwrap().len(), 1);
+        assert_eq!(sender.on_timer(&config, later).unwrap().len(), 0);
+        let later = later + Duration::from_secs(2);
+        assert_eq!(sender.on_timer(&config, later).unwrap().len(), 1);
+        let later = later + Duration::from_secs(2);
+        assert_eq!(
+            sender.on_timer(&config, later),
+            Err(CancelReason::RetransmissionLimit)
+        );
+    }
+}
@@ -0,0 +1,5 @@
+use super::*;
+
+pub mod cancel;
+pub mod logger;
+pub mod settings;
@@ -18,5 +18,7 @@ fn compile_proto(proto: impl AsRef<Path>) -> std::io::Result<()> {
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     compile_proto("cla.proto")?;
     compile_proto("application.proto")?;
+    compile_proto("admin.proto")?;
+    compile_proto("route_api.proto")?;
     Ok(())
 }
@@ -5,3 +5,11 @@ pub mod cla {
 pub mod application {
     tonic::include_proto!("application");
 }
+
+pub mod admin {
+    tonic::include_proto!("admin");
+}
+
+pub mod route_api {
+    tonic::include_proto!("route_api");
+}
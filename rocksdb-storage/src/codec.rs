@@ -0,0 +1,412 @@
+use hardy_bpa_api::{metadata, storage};
+use hardy_bpv7::prelude as bpv7;
+use hardy_cbor as cbor;
+use std::{collections::HashMap, sync::Arc};
+
+/* Keys are built so that RocksDB's byte-wise ordering does the work of an index: integers
+ * are big-endian, times are nanoseconds since the Unix epoch, and variable-length parts are
+ * CBOR, so that one never looks like the prefix of another.  A bundle key starts with the
+ * source and creation timestamp, so the fragments of a bundle are adjacent.  Records are a
+ * CBOR array of the metadata and the bundle, with the blocks as the sqlite engine encodes
+ * them */
+
+// The kinds of entry in the 'by_time' index
+pub const WAITING: u8 = 0;
+pub const TOMBSTONE: u8 = 1;
+
+pub fn encode_time(t: time::OffsetDateTime) -> [u8; 8] {
+    let nanos = t.unix_timestamp_nanos().clamp(0, i64::MAX as i128) as u64;
+    nanos.to_be_bytes()
+}
+
+pub fn decode_time(data: &[u8]) -> Option<time::OffsetDateTime> {
+    let nanos = u64::from_be_bytes(data.get(..8)?.try_into().ok()?);
+    time::OffsetDateTime::from_unix_timestamp_nanos(nanos as i128).ok()
+}
+
+// The key of all the fragments of `bundle_id`, and of the bundle itself
+pub fn timestamp_prefix(bundle_id: &bpv7::BundleId) -> Vec<u8> {
+    let mut key = cbor::encode::emit(&bundle_id.source);
+    key.extend_from_slice(
+        &bundle_id
+            .timestamp
+            .creation_time
+            .map_or(0, |t| t.millisecs())
+            .to_be_bytes(),
+    );
+    key.extend_from_slice(&bundle_id.timestamp.sequence_number.to_be_bytes());
+    key
+}
+
+pub fn bundle_key(bundle_id: &bpv7::BundleId) -> Vec<u8> {
+    let mut key = timestamp_prefix(bundle_id);
+    match &bundle_id.fragment_info {
+        None => key.push(0),
+        Some(fragment_info) => {
+            key.push(1);
+            key.extend_from_slice(&fragment_info.offset.to_be_bytes());
+            key.extend_from_slice(&fragment_info.total_len.to_be_bytes());
+        }
+    }
+    key
+}
+
+// The 'by_time' key of a bundle, if its status is one the index holds
+pub fn time_key(status: &metadata::BundleStatus, bundle_key: &[u8]) -> Option<Vec<u8>> {
+    let (kind, t) = match status {
+        metadata::BundleStatus::Waiting(until)
+        | metadata::BundleStatus::ForwardAckPending(_, until) => (WAITING, until),
+        metadata::BundleStatus::Tombstone(from) => (TOMBSTONE, from),
+        _ => return None,
+    };
+    let mut key = Vec::with_capacity(9 + bundle_key.len());
+    key.push(kind);
+    key.extend_from_slice(&encode_time(*t));
+    key.extend_from_slice(bundle_key);
+    Some(key)
+}
+
+pub fn destination_prefix(destination: &bpv7::Eid) -> Vec<u8> {
+    cbor::encode::emit(destination)
+}
+
+// The 'by_destination' key of a bundle, oldest first for each destination
+pub fn destination_key(bundle: &metadata::Bundle, bundle_key: &[u8]) -> Vec<u8> {
+    let mut key = destination_prefix(&bundle.bundle.destination);
+    key.extend_from_slice(&encode_time(
+        bundle
+            .metadata
+            .received_at
+            .unwrap_or(time::OffsetDateTime::UNIX_EPOCH),
+    ));
+    key.extend_from_slice(bundle_key);
+    key
+}
+
+// The parts of a 'by_destination' key
+pub fn split_destination_key(key: &[u8]) -> storage::Result<(bpv7::Eid, &[u8])> {
+    let (destination, len) = cbor::decode::parse::<(bpv7::Eid, usize)>(key)?;
+    let Some(bundle_key) = key.get(len + 8..) else {
+        return Err("Truncated destination index key".into());
+    };
+    Ok((destination, bundle_key))
+}
+
+// The key of a delivery of a bundle to a registration
+pub fn delivery_key(bundle_key: &[u8], registration: &str) -> Vec<u8> {
+    let mut key = cbor::encode::emit(bundle_key);
+    key.extend_from_slice(registration.as_bytes());
+    key
+}
+
+pub fn status_code(status: &metadata::BundleStatus) -> u8 {
+    match status {
+        metadata::BundleStatus::IngressPending => 0,
+        metadata::BundleStatus::DispatchPending => 1,
+        metadata::BundleStatus::ReassemblyPending => 2,
+        metadata::BundleStatus::CollectionPending => 3,
+        metadata::BundleStatus::ForwardPending => 4,
+        metadata::BundleStatus::ForwardAckPending(..) => 5,
+        metadata::BundleStatus::Waiting(_) => 6,
+        metadata::BundleStatus::Tombstone(_) => 7,
+    }
+}
+
+fn encode_nanos(t: time::OffsetDateTime) -> i64 {
+    t.unix_timestamp_nanos()
+        .clamp(i64::MIN as i128, i64::MAX as i128) as i64
+}
+
+fn decode_nanos(nanos: i64) -> storage::Result<time::OffsetDateTime> {
+    time::OffsetDateTime::from_unix_timestamp_nanos(nanos as i128).map_err(Into::into)
+}
+
+fn encode_priority(priority: metadata::Priority) -> u64 {
+    match priority {
+        metadata::Priority::Bulk => 0,
+        metadata::Priority::Normal => 1,
+        metadata::Priority::Expedited => 2,
+    }
+}
+
+fn decode_priority(priority: u64) -> storage::Result<metadata::Priority> {
+    match priority {
+        0 => Ok(metadata::Priority::Bulk),
+        1 => Ok(metadata::Priority::Normal),
+        2 => Ok(metadata::Priority::Expedited),
+        _ => Err(format!("Invalid priority {priority} in record").into()),
+    }
+}
+
+// `inline` is whether the bundle data is held alongside the record
+pub fn encode_record(bundle: &metadata::Bundle, inline: bool) -> Vec<u8> {
+    let (ack_handle, until) = match &bundle.metadata.status {
+        metadata::BundleStatus::ForwardAckPending(handle, until) => (Some(*handle), Some(*until)),
+        metadata::BundleStatus::Waiting(until) | metadata::BundleStatus::Tombstone(until) => {
+            (None, Some(*until))
+        }
+        _ => (None, None),
+    };
+    let return_path = &bundle.metadata.return_path;
+
+    cbor::encode::emit_array(Some(24), |a| {
+        a.emit(status_code(&bundle.metadata.status));
+        a.emit(ack_handle);
+        a.emit(until.map(encode_nanos));
+        a.emit(bundle.metadata.storage_name.as_deref());
+        a.emit(bundle.metadata.hash.as_deref());
+        a.emit(bundle.metadata.received_at.map(encode_nanos));
+        a.emit(encode_priority(bundle.metadata.priority));
+
+        let bundle = &bundle.bundle;
+        a.emit(&bundle.id.source);
+        a.emit(bundle.id.timestamp.creation_time.map(|t| t.millisecs()));
+        a.emit(bundle.id.timestamp.sequence_number);
+        a.emit(bundle.id.fragment_info.as_ref().map(|f| f.offset));
+        a.emit(bundle.id.fragment_info.as_ref().map(|f| f.total_len));
+        a.emit(u64::from(&bundle.flags));
+        a.emit(u64::from(bundle.crc_type));
+        a.emit(&bundle.destination);
+        a.emit(&bundle.report_to);
+        a.emit(bundle.lifetime);
+        a.emit_array(Some(bundle.previous_node.iter().len()), |a| {
+            if let Some(previous_node) = &bundle.previous_node {
+                a.emit(previous_node);
+            }
+        });
+        a.emit(bundle.age);
+        a.emit(bundle.hop_count.as_ref().map(|h| h.count));
+        a.emit(bundle.hop_count.as_ref().map(|h| h.limit));
+        a.emit(inline);
+        a.emit_array(Some(bundle.blocks.len()), |a| {
+            for (block_num, block) in &bundle.blocks {
+                a.emit_array(Some(9), |a| {
+                    a.emit(*block_num);
+                    a.emit(u64::from(block.block_type));
+                    a.emit(u64::from(&block.flags));
+                    a.emit(u64::from(block.crc_type));
+                    a.emit(block.data_start);
+                    a.emit(block.data_len);
+                    a.emit(block.payload_offset);
+                    a.emit(block.payload_len);
+                    a.emit(block.bcb);
+                });
+            }
+        });
+
+        // Last, so records written before it was added still decode
+        a.emit_array(Some(return_path.iter().len()), |a| {
+            if let Some(return_path) = return_path {
+                a.emit(return_path);
+            }
+        });
+    })
+}
+
+fn parse_blocks(a: &mut cbor::decode::Array) -> storage::Result<HashMap<u64, bpv7::Block>> {
+    a.parse_array(|a, _, _| {
+        let mut blocks = HashMap::new();
+        while let Some((block_num, block)) = a.try_parse_array(|a, _, _| {
+            Ok::<_, cbor::decode::Error>((
+                a.parse::<u64>()?,
+                bpv7::Block {
+                    block_type: a.parse::<u64>()?.into(),
+                    flags: a.parse::<u64>()?.into(),
+                    crc_type: a.parse::<u64>()?.into(),
+                    data_start: a.parse()?,
+                    data_len: a.parse()?,
+                    payload_offset: a.parse()?,
+                    payload_len: a.parse()?,
+                    bcb: a.parse()?,
+                },
+            ))
+        })? {
+            if blocks.insert(block_num, block).is_some() {
+                return Err(format!("Duplicate block number {block_num} in record").into());
+            }
+        }
+        Ok(blocks)
+    })
+}
+
+// Returns the bundle, and whether its data is held alongside the record
+pub fn decode_record(data: &[u8]) -> storage::Result<(metadata::Bundle, bool)> {
+    cbor::decode::parse_array(data, |a, _, _| {
+        let status_code = a.parse::<u8>()?;
+        let ack_handle = a.parse::<Option<u32>>()?;
+        let until = a.parse::<Option<i64>>()?.map(decode_nanos).transpose()?;
+        let status = match (status_code, ack_handle, until) {
+            (0, None, None) => metadata::BundleStatus::IngressPending,
+            (1, None, None) => metadata::BundleStatus::DispatchPending,
+            (2, None, None) => metadata::BundleStatus::ReassemblyPending,
+            (3, None, None) => metadata::BundleStatus::CollectionPending,
+            (4, None, None) => metadata::BundleStatus::ForwardPending,
+            (5, Some(handle), Some(until)) => {
+                metadata::BundleStatus::ForwardAckPending(handle, until)
+            }
+            (6, None, Some(until)) => metadata::BundleStatus::Waiting(until),
+            (7, None, Some(from)) => metadata::BundleStatus::Tombstone(from),
+            _ => return Err(format!("Invalid bundle status {status_code} in record").into()),
+        };
+
+        let storage_name = a.parse_value(|value, _, _| match value {
+            cbor::decode::Value::Text(s) => Ok(Some(Arc::from(s))),
+            cbor::decode::Value::Undefined => Ok(None),
+            _ => Err::<_, storage::Error>("Invalid storage name in record".into()),
+        })?;
+        let hash = a.parse_value(|value, _, _| match value {
+            cbor::decode::Value::Bytes(b) => Ok(Some(Arc::from(b))),
+            cbor::decode::Value::Undefined => Ok(None),
+            _ => Err::<_, storage::Error>("Invalid hash in record".into()),
+        })?;
+        let received_at = a.parse::<Option<i64>>()?.map(decode_nanos).transpose()?;
+        let priority = decode_priority(a.parse()?)?;
+
+        let source = a.parse::<bpv7::Eid>()?;
+        let creation_time = a.parse::<Option<u64>>()?.map(bpv7::DtnTime::new);
+        let sequence_number = a.parse()?;
+        let fragment_info = match (a.parse::<Option<u64>>()?, a.parse::<Option<u64>>()?) {
+            (Some(offset), Some(total_len)) => Some(bpv7::FragmentInfo { offset, total_len }),
+            (None, None) => None,
+            _ => return Err("Invalid fragment info in record".into()),
+        };
+        let flags = a.parse::<u64>()?.into();
+        let crc_type = a.parse::<u64>()?.into();
+        let destination = a.parse()?;
+        let report_to = a.parse()?;
+        let lifetime = a.parse()?;
+        let previous_node = a.parse_array(|a, _, _| a.try_parse::<bpv7::Eid>())?;
+        let age = a.parse()?;
+        let hop_count = match (a.parse::<Option<u64>>()?, a.parse::<Option<u64>>()?) {
+            (Some(count), Some(limit)) => Some(bpv7::HopInfo { count, limit }),
+            (None, None) => None,
+            _ => return Err("Invalid hop info in record".into()),
+        };
+        let inline = a.parse()?;
+        let blocks = parse_blocks(a)?;
+        let return_path = a
+            .try_parse_array(|a, _, _| a.try_parse::<bpv7::Eid>())?
+            .flatten();
+
+        Ok::<_, storage::Error>((
+            metadata::Bundle {
+                metadata: metadata::Metadata {
+                    status,
+                    storage_name,
+                    hash,
+                    received_at,
+                    priority,
+                    return_path,
+                },
+                bundle: bpv7::Bundle {
+                    id: bpv7::BundleId {
+                        source,
+                        timestamp: bpv7::CreationTimestamp {
+                            creation_time,
+                            sequence_number,
+                        },
+                        fragment_info,
+                    },
+                    flags,
+                    crc_type,
+                    destination,
+                    report_to,
+                    lifetime,
+                    previous_node,
+                    age,
+                    hop_count,
+                    blocks,
+                },
+            },
+            inline,
+        ))
+    })
+    .map(|(record, _)| record)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let bundle = metadata::Bundle {
+            metadata: metadata::Metadata {
+                status: metadata::BundleStatus::ForwardAckPending(
+                    3,
+                    time::OffsetDateTime::from_unix_timestamp_nanos(1_717_243_200_250_000_000)
+                        .unwrap(),
+                ),
+                storage_name: Some("abc".into()),
+                hash: Some(Arc::from(&b"hash"[..])),
+                received_at: Some(
+                    time::OffsetDateTime::from_unix_timestamp(1_717_243_199).unwrap(),
+                ),
+                priority: metadata::Priority::Expedited,
+                return_path: Some("ipn:3.0".parse().unwrap()),
+            },
+            bundle: bpv7::Bundle {
+                id: bpv7::BundleId {
+                    source: "ipn:1.2".parse().unwrap(),
+                    timestamp: bpv7::CreationTimestamp {
+                        creation_time: Some(bpv7::DtnTime::new(1000)),
+                        sequence_number: 7,
+                    },
+                    fragment_info: Some(bpv7::FragmentInfo {
+                        offset: 10,
+                        total_len: 100,
+                    }),
+                },
+                flags: 0u64.into(),
+                crc_type: 1u64.into(),
+                destination: "dtn://node/service".parse().unwrap(),
+                report_to: "ipn:1.0".parse().unwrap(),
+                lifetime: 3_600_000,
+                previous_node: Some("ipn:3.0".parse().unwrap()),
+                age: None,
+                hop_count: Some(bpv7::HopInfo {
+                    count: 2,
+                    limit: 30,
+                }),
+                blocks: HashMap::new(),
+            },
+        };
+
+        let (decoded, inline) = decode_record(&encode_record(&bundle, true)).unwrap();
+        assert!(inline);
+        assert_eq!(decoded.metadata.status, bundle.metadata.status);
+        assert_eq!(decoded.metadata.storage_name, bundle.metadata.storage_name);
+        assert_eq!(decoded.metadata.hash, bundle.metadata.hash);
+        assert_eq!(decoded.metadata.received_at, bundle.metadata.received_at);
+        assert_eq!(decoded.metadata.priority, bundle.metadata.priority);
+        assert_eq!(decoded.metadata.return_path, bundle.metadata.return_path);
+        assert_eq!(decoded.bundle.id, bundle.bundle.id);
+        assert_eq!(decoded.bundle.destination, bundle.bundle.destination);
+        assert_eq!(decoded.bundle.previous_node, bundle.bundle.previous_node);
+        assert_eq!(decoded.bundle.hop_count.unwrap().limit, 30);
+
+        // Fragments of a bundle share the prefix of its key, and the destination index key
+        // can be taken apart again
+        let key = bundle_key(&bundle.bundle.id);
+        assert!(key.starts_with(&timestamp_prefix(&bundle.bundle.id)));
+        let index_key = destination_key(&bundle, &key);
+        let (destination, rest) = split_destination_key(&index_key).unwrap();
+        assert_eq!(destination, bundle.bundle.destination);
+        assert_eq!(rest, key.as_slice());
+
+        // Waiting bundles sort by time
+        let early = time_key(
+            &metadata::BundleStatus::Waiting(
+                time::OffsetDateTime::from_unix_timestamp(1_704_067_200).unwrap(),
+            ),
+            &key,
+        );
+        let late = time_key(
+            &metadata::BundleStatus::Waiting(
+                time::OffsetDateTime::from_unix_timestamp(1_735_689_600).unwrap(),
+            ),
+            &[],
+        );
+        assert!(early < late);
+    }
+}
@@ -0,0 +1,889 @@
+use super::*;
+use hardy_bpa_api::{async_trait, metadata, storage};
+use hardy_bpv7::prelude as bpv7;
+use rand::prelude::*;
+use rocksdb::{ColumnFamily, ColumnFamilyDescriptor, Direction, IteratorMode, WriteBatch, DB};
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, Weak},
+};
+use thiserror::Error;
+use trace_err::*;
+use tracing::*;
+
+/* One RocksDB database holds both the metadata and the bundle data, in separate column
+ * families, so the BPA can use it as both its metadata and bundle storage engine, and a
+ * bundle stored inline is written with its metadata in a single atomic batch.  The engine
+ * is opened once for each role, and both share the database.  Bundle records are keyed by
+ * bundle id, and the secondary indexes are column families whose keys sort in the order
+ * they are scanned, see codec.rs.  Every change to a record and its index entries is one
+ * write batch, and changes that read a record first are serialized by a lock, as RocksDB
+ * has no transactions of its own here.  Work is done on tokio's blocking threads */
+
+const SCHEMA_VERSION: &str = "1";
+const SCHEMA_VERSION_KEY: &[u8] = b"schema_version";
+
+// Bundle key -> record
+const BUNDLES: &str = "bundles";
+// Kind, time and bundle key of waiting bundles and tombstones -> nothing
+const BY_TIME: &str = "by_time";
+// Destination, received time and bundle key of bundles that are not tombstones -> status code
+const BY_DESTINATION: &str = "by_destination";
+// Bundle key of bundles whose data has not been seen since startup -> nothing
+const UNCONFIRMED: &str = "unconfirmed";
+// Bundle key and registration -> nothing
+const DELIVERIES: &str = "deliveries";
+// Storage name -> bundle key
+const STORAGE_NAMES: &str = "storage_names";
+// Storage name -> data of bundles stored inline
+const INLINE: &str = "inline";
+// Storage name -> data and stored time of bundles in the bundle storage
+const DATA: &str = "data";
+
+const COLUMN_FAMILIES: [&str; 8] = [
+    BUNDLES,
+    BY_TIME,
+    BY_DESTINATION,
+    UNCONFIRMED,
+    DELIVERIES,
+    STORAGE_NAMES,
+    INLINE,
+    DATA,
+];
+
+// The status codes of bundles waiting for a route to their destination
+fn waiting_status_codes() -> [u8; 2] {
+    [
+        codec::status_code(&metadata::BundleStatus::ForwardPending),
+        codec::status_code(&metadata::BundleStatus::Waiting(
+            time::OffsetDateTime::UNIX_EPOCH,
+        )),
+    ]
+}
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("No such bundle")]
+    NotFound,
+}
+
+// The databases already open, so that metadata and bundle storage share one
+static OPEN: Mutex<Vec<(PathBuf, Weak<Db>)>> = Mutex::new(Vec::new());
+
+type KeyValue = (Box<[u8]>, Box<[u8]>);
+
+struct Db {
+    db: DB,
+    sync: bool,
+    // Held while reading a record to change it
+    write_lock: Mutex<()>,
+}
+
+pub struct Storage {
+    db: Arc<Db>,
+}
+
+impl Storage {
+    #[instrument(skip(config))]
+    pub fn init(config: &HashMap<String, config::Value>) -> Arc<Self> {
+        let db_dir: PathBuf = config.get("db_dir").map_or_else(
+            || {
+                directories::ProjectDirs::from("dtn", "Hardy", built_info::PKG_NAME).map_or_else(
+                    || {
+                        cfg_if::cfg_if! {
+                            if #[cfg(unix)] {
+                                Path::new("/var/spool").join(built_info::PKG_NAME)
+                            } else if #[cfg(windows)] {
+                                std::env::current_exe().join(built_info::PKG_NAME)
+                            } else {
+                                compile_error!("No idea how to determine default local store directory for target platform")
+                            }
+                        }
+                    },
+                    |project_dirs| project_dirs.cache_dir().into(),
+                )
+            },
+            |v| {
+                v.clone()
+                    .into_string()
+                    .trace_expect("Invalid 'db_dir' value in configuration")
+                    .into()
+            },
+        );
+
+        let sync = config
+            .get("sync")
+            .map(|sync| {
+                sync.clone()
+                    .into_bool()
+                    .trace_expect("Invalid 'sync' value in configuration")
+            })
+            .unwrap_or(true);
+
+        let mut open = OPEN.lock().trace_expect("Failed to lock mutex");
+        open.retain(|(_, db)| db.strong_count() != 0);
+        if let Some(db) = open
+            .iter()
+            .find(|(path, _)| path == &db_dir)
+            .and_then(|(_, db)| db.upgrade())
+        {
+            return Arc::new(Self { db });
+        }
+
+        info!("Using database: {}", db_dir.display());
+        std::fs::create_dir_all(&db_dir).trace_expect(&format!(
+            "Failed to create database directory {}",
+            db_dir.display()
+        ));
+
+        let mut options = rocksdb::Options::default();
+        options.create_if_missing(true);
+        options.create_missing_column_families(true);
+        let db = DB::open_cf_descriptors(
+            &options,
+            &db_dir,
+            COLUMN_FAMILIES
+                .iter()
+                .map(|name| ColumnFamilyDescriptor::new(*name, rocksdb::Options::default())),
+        )
+        .trace_expect(&format!("Failed to open database {}", db_dir.display()));
+
+        match db
+            .get(SCHEMA_VERSION_KEY)
+            .trace_expect("Failed to read schema version")
+        {
+            None => db
+                .put(SCHEMA_VERSION_KEY, SCHEMA_VERSION)
+                .trace_expect("Failed to write schema version"),
+            Some(version) if version == SCHEMA_VERSION.as_bytes() => {}
+            Some(version) => {
+                let version = String::from_utf8_lossy(&version);
+                error!("Unsupported database schema version {version}");
+                panic!("Unsupported database schema version {version}");
+            }
+        }
+
+        let db = Arc::new(Db {
+            db,
+            sync,
+            write_lock: Mutex::new(()),
+        });
+        db.mark_unconfirmed()
+            .trace_expect("Failed to prepare bundle records");
+
+        open.push((db_dir, Arc::downgrade(&db)));
+        Arc::new(Self { db })
+    }
+
+    async fn run<F, R>(&self, f: F) -> storage::Result<R>
+    where
+        F: FnOnce(&Db) -> storage::Result<R> + Send + 'static,
+        R: Send + 'static,
+    {
+        let db = self.db.clone();
+        tokio::task::spawn_blocking(move || f(&db)).await?
+    }
+}
+
+impl Db {
+    fn cf(&self, name: &str) -> &ColumnFamily {
+        self.db
+            .cf_handle(name)
+            .unwrap_or_else(|| panic!("Missing column family {name}"))
+    }
+
+    fn write(&self, batch: WriteBatch) -> storage::Result<()> {
+        let mut options = rocksdb::WriteOptions::default();
+        options.set_sync(self.sync);
+        self.db.write_opt(batch, &options).map_err(Into::into)
+    }
+
+    // Every entry in the column family with a key starting with `prefix`, in key order
+    fn scan<'a>(
+        &'a self,
+        cf: &str,
+        prefix: &'a [u8],
+    ) -> impl Iterator<Item = Result<KeyValue, rocksdb::Error>> + 'a {
+        self.db
+            .iterator_cf(self.cf(cf), IteratorMode::From(prefix, Direction::Forward))
+            .take_while(move |item| {
+                item.as_ref()
+                    .map_or(true, |(key, _)| key.starts_with(prefix))
+            })
+    }
+
+    // Returns the bundle, and whether its data is held inline
+    fn get_record(&self, key: &[u8]) -> storage::Result<Option<(metadata::Bundle, bool)>> {
+        self.db
+            .get_pinned_cf(self.cf(BUNDLES), key)?
+            .map(|record| codec::decode_record(&record))
+            .transpose()
+    }
+
+    fn put_record(
+        &self,
+        batch: &mut WriteBatch,
+        key: &[u8],
+        bundle: &metadata::Bundle,
+        inline: bool,
+    ) {
+        batch.put_cf(self.cf(BUNDLES), key, codec::encode_record(bundle, inline));
+        if let Some(time_key) = codec::time_key(&bundle.metadata.status, key) {
+            batch.put_cf(self.cf(BY_TIME), time_key, b"");
+        }
+        if !matches!(bundle.metadata.status, metadata::BundleStatus::Tombstone(_)) {
+            batch.put_cf(
+                self.cf(BY_DESTINATION),
+                codec::destination_key(bundle, key),
+                [codec::status_code(&bundle.metadata.status)],
+            );
+        }
+        if let Some(storage_name) = &bundle.metadata.storage_name {
+            batch.put_cf(self.cf(STORAGE_NAMES), storage_name.as_bytes(), key);
+        }
+    }
+
+    fn delete_indexes(&self, batch: &mut WriteBatch, key: &[u8], bundle: &metadata::Bundle) {
+        if let Some(time_key) = codec::time_key(&bundle.metadata.status, key) {
+            batch.delete_cf(self.cf(BY_TIME), time_key);
+        }
+        if !matches!(bundle.metadata.status, metadata::BundleStatus::Tombstone(_)) {
+            batch.delete_cf(self.cf(BY_DESTINATION), codec::destination_key(bundle, key));
+        }
+        if let Some(storage_name) = &bundle.metadata.storage_name {
+            batch.delete_cf(self.cf(STORAGE_NAMES), storage_name.as_bytes());
+        }
+    }
+
+    fn delete_deliveries(&self, batch: &mut WriteBatch, key: &[u8]) -> storage::Result<()> {
+        let prefix = codec::delivery_key(key, "");
+        for item in self.scan(DELIVERIES, &prefix) {
+            batch.delete_cf(self.cf(DELIVERIES), item?.0);
+        }
+        Ok(())
+    }
+
+    // Until their data is found again, bundles with data in the bundle storage are suspect
+    fn mark_unconfirmed(&self) -> storage::Result<()> {
+        let mut batch = WriteBatch::default();
+        for item in self.scan(BUNDLES, &[]) {
+            let (key, record) = item?;
+            let (bundle, inline) = codec::decode_record(&record)?;
+            if !inline && !matches!(bundle.metadata.status, metadata::BundleStatus::Tombstone(_)) {
+                batch.put_cf(self.cf(UNCONFIRMED), key, b"");
+            }
+        }
+        self.write(batch)
+    }
+
+    fn insert(&self, bundle: metadata::Bundle, data: Option<Vec<u8>>) -> storage::Result<bool> {
+        let key = codec::bundle_key(&bundle.bundle.id);
+        let _guard = self.write_lock.lock().trace_expect("Failed to lock mutex");
+        if self.db.get_pinned_cf(self.cf(BUNDLES), &key)?.is_some() {
+            return Ok(false);
+        }
+
+        let mut batch = WriteBatch::default();
+        self.put_record(&mut batch, &key, &bundle, data.is_some());
+        if let Some(data) = data {
+            let Some(storage_name) = &bundle.metadata.storage_name else {
+                return Err("Inline bundle data requires a storage name".into());
+            };
+            batch.put_cf(self.cf(INLINE), storage_name.as_bytes(), data);
+        }
+        self.write(batch)?;
+        Ok(true)
+    }
+
+    // Applies every update or none
+    fn update(&self, updates: &[(bpv7::BundleId, metadata::BundleStatus)]) -> storage::Result<()> {
+        let _guard = self.write_lock.lock().trace_expect("Failed to lock mutex");
+        self.update_locked(updates)
+    }
+
+    // As update(), but only for bundles whose stored status is still the one given
+    fn claim(
+        &self,
+        claims: &[(bpv7::BundleId, metadata::BundleStatus)],
+        status: &metadata::BundleStatus,
+    ) -> storage::Result<Vec<bool>> {
+        let _guard = self.write_lock.lock().trace_expect("Failed to lock mutex");
+        let mut claimed = Vec::with_capacity(claims.len());
+        let mut updates = Vec::new();
+        for (bundle_id, expected) in claims {
+            let claim = self
+                .get_record(&codec::bundle_key(bundle_id))?
+                .is_some_and(|(bundle, _)| &bundle.metadata.status == expected);
+            if claim {
+                updates.push((bundle_id.clone(), status.clone()));
+            }
+            claimed.push(claim);
+        }
+        if !updates.is_empty() {
+            self.update_locked(&updates)?;
+        }
+        Ok(claimed)
+    }
+
+    // Callers hold the write lock
+    fn update_locked(
+        &self,
+        updates: &[(bpv7::BundleId, metadata::BundleStatus)],
+    ) -> storage::Result<()> {
+        let mut batch = WriteBatch::default();
+        let mut records = HashMap::new();
+        for (bundle_id, status) in updates {
+            let (bundle, inline) = match records.entry(codec::bundle_key(bundle_id)) {
+                Entry::Occupied(e) => e.into_mut(),
+                Entry::Vacant(e) => {
+                    let Some(record) = self.get_record(e.key())? else {
+                        return Err(Error::NotFound.into());
+                    };
+                    self.delete_indexes(&mut batch, e.key(), &record.0);
+                    e.insert(record)
+                }
+            };
+
+            bundle.metadata.status = status.clone();
+            if let metadata::BundleStatus::Tombstone(_) = status {
+                let storage_name = bundle.metadata.storage_name.take();
+                if std::mem::take(inline) {
+                    if let Some(storage_name) = storage_name {
+                        batch.delete_cf(self.cf(INLINE), storage_name.as_bytes());
+                    }
+                }
+                bundle.metadata.hash = None;
+            }
+        }
+
+        for (key, (bundle, inline)) in &records {
+            self.put_record(&mut batch, key, bundle, *inline);
+            if let metadata::BundleStatus::Tombstone(_) = bundle.metadata.status {
+                batch.delete_cf(self.cf(UNCONFIRMED), key);
+            }
+        }
+        self.write(batch)
+    }
+
+    fn remove(&self, bundle_id: &bpv7::BundleId) -> storage::Result<()> {
+        let key = codec::bundle_key(bundle_id);
+        let _guard = self.write_lock.lock().trace_expect("Failed to lock mutex");
+        let Some((bundle, inline)) = self.get_record(&key)? else {
+            return Err(Error::NotFound.into());
+        };
+
+        let mut batch = WriteBatch::default();
+        self.delete_indexes(&mut batch, &key, &bundle);
+        if inline {
+            if let Some(storage_name) = &bundle.metadata.storage_name {
+                batch.delete_cf(self.cf(INLINE), storage_name.as_bytes());
+            }
+        }
+        self.delete_deliveries(&mut batch, &key)?;
+        batch.delete_cf(self.cf(UNCONFIRMED), &key);
+        batch.delete_cf(self.cf(BUNDLES), &key);
+        self.write(batch)
+    }
+
+    // Sends the bundles with the keys given, skipping any that have gone
+    fn send_bundles(
+        &self,
+        keys: impl Iterator<Item = storage::Result<Vec<u8>>>,
+        budget: storage::ScanBudget,
+        tx: &storage::Sender,
+    ) -> storage::Result<()> {
+        let mut count = 0;
+        for key in keys {
+            if budget.is_spent(count) {
+                break;
+            }
+            let Some((bundle, _)) = self.get_record(&key?)? else {
+                continue;
+            };
+            count += 1;
+            if tx.blocking_send(bundle).is_err() {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    // Sends the bundles for which `f` is true, walking every record
+    fn send_matching(
+        &self,
+        prefix: &[u8],
+        tx: &storage::Sender,
+        f: impl Fn(&metadata::Bundle, bool) -> bool,
+    ) -> storage::Result<()> {
+        for item in self.scan(BUNDLES, prefix) {
+            let (bundle, inline) = codec::decode_record(&item?.1)?;
+            if f(&bundle, inline) && tx.blocking_send(bundle).is_err() {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    // The bundle keys in the destination index under `prefix` with one of the status codes
+    fn destination_keys<'a>(
+        &'a self,
+        prefix: &'a [u8],
+        status_codes: &'a [u8],
+    ) -> impl Iterator<Item = storage::Result<Vec<u8>>> + 'a {
+        self.scan(BY_DESTINATION, prefix).filter_map(|item| {
+            let (key, status) = match item {
+                Ok(item) => item,
+                Err(e) => return Some(Err(e.into())),
+            };
+            if !status_codes.contains(status.first()?) {
+                return None;
+            }
+            Some(codec::split_destination_key(&key).map(|(_, key)| key.to_vec()))
+        })
+    }
+}
+
+#[async_trait]
+impl storage::MetadataStorage for Storage {
+    #[instrument(skip(self))]
+    async fn load(&self, bundle_id: &bpv7::BundleId) -> storage::Result<Option<metadata::Bundle>> {
+        let key = codec::bundle_key(bundle_id);
+        self.run(move |db| Ok(db.get_record(&key)?.map(|(bundle, _)| bundle)))
+            .await
+    }
+
+    #[instrument(skip(self))]
+    async fn store(
+        &self,
+        metadata: &metadata::Metadata,
+        bundle: &bpv7::Bundle,
+    ) -> storage::Result<bool> {
+        let bundle = metadata::Bundle {
+            metadata: metadata.clone(),
+            bundle: bundle.clone(),
+        };
+        self.run(move |db| db.insert(bundle, None)).await
+    }
+
+    #[instrument(skip(self))]
+    async fn get_bundle_status(
+        &self,
+        bundle_id: &bpv7::BundleId,
+    ) -> storage::Result<Option<metadata::BundleStatus>> {
+        let key = codec::bundle_key(bundle_id);
+        self.run(move |db| {
+            Ok(db
+                .get_record(&key)?
+                .map(|(bundle, _)| bundle.metadata.status))
+        })
+        .await
+    }
+
+    #[instrument(skip(self))]
+    async fn set_bundle_status(
+        &self,
+        bundle_id: &bpv7::BundleId,
+        status: &metadata::BundleStatus,
+    ) -> storage::Result<()> {
+        let updates = [(bundle_id.clone(), status.clone())];
+        self.run(move |db| db.update(&updates)).await
+    }
+
+    #[instrument(skip_all)]
+    async fn claim_bundle_statuses(
+        &self,
+        claims: &[(bpv7::BundleId, metadata::BundleStatus)],
+        status: &metadata::BundleStatus,
+    ) -> storage::Result<Vec<bool>> {
+        let claims = claims.to_vec();
+        let status = status.clone();
+        self.run(move |db| db.claim(&claims, &status)).await
+    }
+
+    #[instrument(skip(self))]
+    async fn remove(&self, bundle_id: &bpv7::BundleId) -> storage::Result<()> {
+        let bundle_id = bundle_id.clone();
+        self.run(move |db| db.remove(&bundle_id)).await
+    }
+
+    #[instrument(skip(self))]
+    async fn confirm_exists(
+        &self,
+        bundle_id: &bpv7::BundleId,
+    ) -> storage::Result<Option<metadata::Metadata>> {
+        let key = codec::bundle_key(bundle_id);
+        self.run(move |db| {
+            let _guard = db.write_lock.lock().trace_expect("Failed to lock mutex");
+            let Some((bundle, _)) = db.get_record(&key)? else {
+                return Ok(None);
+            };
+            let mut batch = WriteBatch::default();
+            batch.delete_cf(db.cf(UNCONFIRMED), &key);
+            db.write(batch)?;
+            Ok(Some(bundle.metadata))
+        })
+        .await
+    }
+
+    #[instrument(skip(self, tx))]
+    async fn get_waiting_bundles(
+        &self,
+        limit: time::OffsetDateTime,
+        budget: storage::ScanBudget,
+        tx: storage::Sender,
+    ) -> storage::Result<()> {
+        self.run(move |db| {
+            let limit = codec::encode_time(limit);
+            let keys = db
+                .scan(BY_TIME, &[codec::WAITING])
+                .take_while(|item| {
+                    item.as_ref()
+                        .map_or(true, |(key, _)| key[1..9] <= limit[..])
+                })
+                .map(|item| Ok(item?.0[9..].to_vec()));
+            db.send_bundles(keys, budget, &tx)
+        })
+        .await
+    }
+
+    #[instrument(skip_all)]
+    async fn get_unconfirmed_bundles(&self, tx: storage::Sender) -> storage::Result<()> {
+        self.run(move |db| {
+            let keys = db.scan(UNCONFIRMED, &[]).map(|item| Ok(item?.0.into_vec()));
+            db.send_bundles(keys, storage::ScanBudget::default(), &tx)
+        })
+        .await
+    }
+
+    #[instrument(skip(self, tx))]
+    async fn poll_for_collection(
+        &self,
+        destination: bpv7::Eid,
+        budget: storage::ScanBudget,
+        tx: storage::Sender,
+    ) -> storage::Result<()> {
+        self.run(move |db| {
+            let prefix = codec::destination_prefix(&destination);
+            let collection_pending = [codec::status_code(
+                &metadata::BundleStatus::CollectionPending,
+            )];
+            let keys = db.destination_keys(&prefix, &collection_pending);
+            db.send_bundles(keys, budget, &tx)
+        })
+        .await
+    }
+
+    #[instrument(skip(self))]
+    async fn get_waiting_destinations(&self) -> storage::Result<Vec<bpv7::Eid>> {
+        self.run(move |db| {
+            let waiting = waiting_status_codes();
+            let mut destinations = Vec::new();
+            for item in db.scan(BY_DESTINATION, &[]) {
+                let (key, status) = item?;
+                if !status
+                    .first()
+                    .is_some_and(|status| waiting.contains(status))
+                {
+                    continue;
+                }
+                let (destination, _) = codec::split_destination_key(&key)?;
+                if destinations.last() != Some(&destination) {
+                    destinations.push(destination);
+                }
+            }
+            Ok(destinations)
+        })
+        .await
+    }
+
+    #[instrument(skip(self, tx))]
+    async fn get_waiting_bundles_for(
+        &self,
+        destination: &bpv7::Eid,
+        tx: storage::Sender,
+    ) -> storage::Result<()> {
+        let prefix = codec::destination_prefix(destination);
+        self.run(move |db| {
+            let waiting = waiting_status_codes();
+            let keys = db.destination_keys(&prefix, &waiting);
+            db.send_bundles(keys, storage::ScanBudget::default(), &tx)
+        })
+        .await
+    }
+
+    #[instrument(skip(self))]
+    async fn purge_tombstones(&self, older_than: time::OffsetDateTime) -> storage::Result<u64> {
+        self.run(move |db| {
+            let older_than = codec::encode_time(older_than);
+            let _guard = db.write_lock.lock().trace_expect("Failed to lock mutex");
+            let mut batch = WriteBatch::default();
+            let mut purged = 0u64;
+            for item in db.scan(BY_TIME, &[codec::TOMBSTONE]) {
+                let (time_key, _) = item?;
+                if time_key[1..9] >= older_than[..] {
+                    break;
+                }
+                let key = &time_key[9..];
+                db.delete_deliveries(&mut batch, key)?;
+                batch.delete_cf(db.cf(BUNDLES), key);
+                batch.delete_cf(db.cf(BY_TIME), &time_key);
+                purged += 1;
+            }
+            db.write(batch)?;
+            Ok(purged)
+        })
+        .await
+    }
+
+    #[instrument(skip(self, tx))]
+    async fn get_fragments(
+        &self,
+        bundle_id: &bpv7::BundleId,
+        tx: storage::Sender,
+    ) -> storage::Result<()> {
+        let prefix = codec::timestamp_prefix(bundle_id);
+        self.run(move |db| {
+            db.send_matching(&prefix, &tx, |bundle, _| {
+                bundle.bundle.id.fragment_info.is_some()
+                    && bundle.metadata.status == metadata::BundleStatus::ReassemblyPending
+            })
+        })
+        .await
+    }
+
+    #[instrument(skip_all)]
+    async fn get_reassembly_pending(&self, tx: storage::Sender) -> storage::Result<()> {
+        self.run(move |db| {
+            db.send_matching(&[], &tx, |bundle, _| {
+                bundle.metadata.status == metadata::BundleStatus::ReassemblyPending
+            })
+        })
+        .await
+    }
+
+    #[instrument(skip_all)]
+    async fn get_stored_bundles(&self, tx: storage::Sender) -> storage::Result<()> {
+        self.run(move |db| {
+            db.send_matching(&[], &tx, |bundle, _| {
+                !matches!(bundle.metadata.status, metadata::BundleStatus::Tombstone(_))
+            })
+        })
+        .await
+    }
+
+    fn capabilities(&self) -> storage::Capabilities {
+        storage::Capabilities {
+            destination_queries: true,
+            transactional_batches: true,
+            approximate_counts: true,
+            ..Default::default()
+        }
+    }
+
+    #[instrument(skip_all)]
+    async fn set_bundle_statuses(
+        &self,
+        updates: &[(bpv7::BundleId, metadata::BundleStatus)],
+    ) -> storage::Result<()> {
+        let updates = updates.to_vec();
+        self.run(move |db| db.update(&updates)).await
+    }
+
+    // Tombstones are counted too, as they are not counted apart
+    async fn approximate_count(&self) -> storage::Result<Option<u64>> {
+        self.run(move |db| {
+            db.db
+                .property_int_value_cf(db.cf(BUNDLES), "rocksdb.estimate-num-keys")
+                .map_err(Into::into)
+        })
+        .await
+    }
+
+    fn supports_inline_data(&self) -> bool {
+        true
+    }
+
+    #[instrument(skip(self, data))]
+    async fn store_inline(
+        &self,
+        metadata: &metadata::Metadata,
+        bundle: &bpv7::Bundle,
+        data: &[u8],
+    ) -> storage::Result<bool> {
+        let bundle = metadata::Bundle {
+            metadata: metadata.clone(),
+            bundle: bundle.clone(),
+        };
+        let data = data.to_vec();
+        self.run(move |db| db.insert(bundle, Some(data))).await
+    }
+
+    #[instrument(skip(self))]
+    async fn load_inline(&self, storage_name: &str) -> storage::Result<Option<storage::DataRef>> {
+        let storage_name = storage_name.to_string();
+        self.run(move |db| {
+            Ok(db
+                .db
+                .get_cf(db.cf(INLINE), storage_name)?
+                .map(|data| Arc::new(data) as storage::DataRef))
+        })
+        .await
+    }
+
+    #[instrument(skip_all)]
+    async fn get_inline_bundles(&self, tx: storage::Sender) -> storage::Result<()> {
+        self.run(move |db| db.send_matching(&[], &tx, |_, inline| inline))
+            .await
+    }
+
+    fn supports_multicast_delivery(&self) -> bool {
+        true
+    }
+
+    #[instrument(skip(self))]
+    async fn confirm_delivery(
+        &self,
+        bundle_id: &bpv7::BundleId,
+        registration: &str,
+    ) -> storage::Result<bool> {
+        let key = codec::bundle_key(bundle_id);
+        let delivery_key = codec::delivery_key(&key, registration);
+        self.run(move |db| {
+            let _guard = db.write_lock.lock().trace_expect("Failed to lock mutex");
+            if db.db.get_pinned_cf(db.cf(BUNDLES), &key)?.is_none()
+                || db
+                    .db
+                    .get_pinned_cf(db.cf(DELIVERIES), &delivery_key)?
+                    .is_some()
+            {
+                return Ok(false);
+            }
+            let mut batch = WriteBatch::default();
+            batch.put_cf(db.cf(DELIVERIES), delivery_key, b"");
+            db.write(batch)?;
+            Ok(true)
+        })
+        .await
+    }
+
+    #[instrument(skip(self))]
+    async fn get_deliveries(&self, bundle_id: &bpv7::BundleId) -> storage::Result<Vec<String>> {
+        let prefix = codec::delivery_key(&codec::bundle_key(bundle_id), "");
+        self.run(move |db| {
+            db.scan(DELIVERIES, &prefix)
+                .map(|item| Ok(String::from_utf8(item?.0[prefix.len()..].to_vec())?))
+                .collect()
+        })
+        .await
+    }
+
+    #[instrument(skip_all)]
+    async fn update_hashes(&self, hashes: &[(Arc<str>, Arc<[u8]>)]) -> storage::Result<u64> {
+        let hashes = hashes.to_vec();
+        self.run(move |db| {
+            let _guard = db.write_lock.lock().trace_expect("Failed to lock mutex");
+            let mut batch = WriteBatch::default();
+            let mut updated = 0u64;
+            for (storage_name, hash) in hashes {
+                let Some(key) = db
+                    .db
+                    .get_cf(db.cf(STORAGE_NAMES), storage_name.as_bytes())?
+                else {
+                    continue;
+                };
+                let Some((mut bundle, inline)) = db.get_record(&key)? else {
+                    continue;
+                };
+                bundle.metadata.hash = Some(hash);
+                batch.put_cf(db.cf(BUNDLES), &key, codec::encode_record(&bundle, inline));
+                updated += 1;
+            }
+            db.write(batch)?;
+            Ok(updated)
+        })
+        .await
+    }
+
+    fn schema_version(&self) -> Option<String> {
+        Some(SCHEMA_VERSION.to_string())
+    }
+}
+
+#[async_trait]
+impl storage::BundleStorage for Storage {
+    #[instrument(skip_all)]
+    async fn list(
+        &self,
+        tx: tokio::sync::mpsc::Sender<storage::ListResponse>,
+    ) -> storage::Result<()> {
+        self.run(move |db| {
+            let mut iter = db.db.raw_iterator_cf(db.cf(DATA));
+            iter.seek_to_first();
+            while let Some((storage_name, value)) = iter.item() {
+                let storage_name = Arc::from(std::str::from_utf8(storage_name)?);
+                let stored_at = value
+                    .len()
+                    .checked_sub(8)
+                    .and_then(|len| codec::decode_time(&value[len..]));
+                if tx.blocking_send((storage_name, stored_at)).is_err() {
+                    break;
+                }
+                iter.next();
+            }
+            iter.status().map_err(Into::into)
+        })
+        .await
+    }
+
+    #[instrument(skip(self))]
+    async fn load(&self, storage_name: &str) -> storage::Result<Option<storage::DataRef>> {
+        let storage_name = storage_name.to_string();
+        self.run(move |db| {
+            let Some(mut data) = db.db.get_cf(db.cf(DATA), storage_name)? else {
+                return Ok(None);
+            };
+            // Drop the stored time
+            data.truncate(data.len().saturating_sub(8));
+            Ok(Some(Arc::new(data) as storage::DataRef))
+        })
+        .await
+    }
+
+    #[instrument(skip_all)]
+    async fn store(&self, data: &[u8]) -> storage::Result<Arc<str>> {
+        let mut value = Vec::with_capacity(data.len() + 8);
+        value.extend_from_slice(data);
+        value.extend_from_slice(&codec::encode_time(time::OffsetDateTime::now_utc()));
+        self.run(move |db| {
+            let _guard = db.write_lock.lock().trace_expect("Failed to lock mutex");
+            let mut rng = rand::thread_rng();
+            let storage_name = loop {
+                let storage_name = format!("{:016x}", rng.gen::<u64>());
+                if db.db.get_pinned_cf(db.cf(DATA), &storage_name)?.is_none() {
+                    break storage_name;
+                }
+            };
+            let mut batch = WriteBatch::default();
+            batch.put_cf(db.cf(DATA), &storage_name, value);
+            db.write(batch)?;
+            Ok(storage_name.into())
+        })
+        .await
+    }
+
+    #[instrument(skip(self))]
+    async fn remove(&self, storage_name: &str) -> storage::Result<()> {
+        let storage_name = storage_name.to_string();
+        self.run(move |db| {
+            let mut batch = WriteBatch::default();
+            batch.delete_cf(db.cf(DATA), storage_name);
+            db.write(batch)
+        })
+        .await
+    }
+
+    fn schema_version(&self) -> Option<String> {
+        Some(SCHEMA_VERSION.to_string())
+    }
+}
@@ -0,0 +1,3 @@
+ALTER TABLE bundles ADD COLUMN inline_data BLOB;
+
+CREATE UNIQUE INDEX idx_bundle_inline_data ON bundles (storage_name) WHERE inline_data IS NOT NULL;
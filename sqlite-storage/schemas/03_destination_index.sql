@@ -0,0 +1 @@
+CREATE INDEX idx_bundle_status_destination ON bundles (status,destination);
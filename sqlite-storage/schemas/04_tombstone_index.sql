@@ -0,0 +1 @@
+CREATE INDEX idx_bundle_status_wait_until ON bundles (status,wait_until);
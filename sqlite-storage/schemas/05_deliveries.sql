@@ -0,0 +1,5 @@
+CREATE TABLE deliveries (
+    bundle_id INTEGER NOT NULL REFERENCES bundles(id) ON DELETE CASCADE,
+    registration TEXT NOT NULL,
+    PRIMARY KEY (bundle_id,registration)
+) STRICT, WITHOUT ROWID;
@@ -0,0 +1 @@
+ALTER TABLE bundles ADD COLUMN priority INTEGER NOT NULL DEFAULT 1;
@@ -0,0 +1 @@
+ALTER TABLE bundles ADD COLUMN blocks BLOB;
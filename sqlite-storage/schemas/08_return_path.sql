@@ -0,0 +1 @@
+ALTER TABLE bundles ADD COLUMN return_path BLOB;
@@ -139,3 +139,43 @@ pub fn migrate(conn: &mut rusqlite::Connection, upgrade: bool) -> Result<(), Err
 
     Ok(())
 }
+
+// Check the schema is current without modifying the database, for read-only connections
+#[instrument]
+pub fn check(conn: &rusqlite::Connection) -> Result<(), Error> {
+    let migrations = include!(concat!(env!("OUT_DIR"), "/migrations.rs"));
+
+    let applied = conn
+        .prepare(r"SELECT file_name,hash FROM schema_versions ORDER BY seq_no")?
+        .query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+
+    let current = applied.len();
+    for (i, (file_name, hash)) in applied.into_iter().enumerate() {
+        match migrations.get(i) {
+            None => Err(Error::ExtraHistoric(file_name))?,
+            Some((_, expected_name, _, _)) if *expected_name != file_name => {
+                Err(Error::MissingHistoric(expected_name.to_string()))?
+            }
+            Some((_, _, expected_hash, _)) if *expected_hash != hash => {
+                Err(Error::AlteredHistoric(file_name))?
+            }
+            _ => {}
+        }
+    }
+
+    if current < migrations.len() {
+        Err(Error::UpdateRequired)?;
+    }
+    Ok(())
+}
+
+// The name of the latest migration, which a current database has applied last
+pub fn current_version() -> Option<String> {
+    let migrations = include!(concat!(env!("OUT_DIR"), "/migrations.rs"));
+    migrations
+        .last()
+        .map(|(_, file_name, _, _)| file_name.to_string())
+}
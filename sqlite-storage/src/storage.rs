@@ -4,23 +4,88 @@ use hardy_bpv7::prelude as bpv7;
 use hardy_cbor as cbor;
 use rusqlite::OptionalExtension;
 use std::{
-    cell::RefCell,
     collections::HashMap,
     path::{Path, PathBuf},
-    sync::Arc,
+    sync::{Arc, Mutex},
     time::Duration,
 };
 use thiserror::Error;
 use trace_err::*;
 use tracing::*;
 
-thread_local! {
-    static CONNECTION: RefCell<Option<rusqlite::Connection>> = const { RefCell::new(None) };
+/* Connections are pooled.  Reads share a few read-only connections, which in WAL mode run
+ * concurrently with each other and with writes.  All writes go through the one writable
+ * connection, so writers queue here rather than contending for the database lock.
+ * Connections are opened as they are first needed, and used on tokio's blocking threads */
+struct Pool {
+    path: PathBuf,
+    flags: rusqlite::OpenFlags,
+    timeout: Duration,
+    synchronous: &'static str,
+    idle: Mutex<Vec<rusqlite::Connection>>,
+    permits: Arc<tokio::sync::Semaphore>,
+}
+
+impl Pool {
+    fn new(
+        path: PathBuf,
+        flags: rusqlite::OpenFlags,
+        size: usize,
+        timeout: Duration,
+        synchronous: &'static str,
+    ) -> Arc<Self> {
+        Arc::new(Self {
+            path,
+            flags: flags | rusqlite::OpenFlags::SQLITE_OPEN_NO_MUTEX,
+            timeout,
+            synchronous,
+            idle: Mutex::new(Vec::with_capacity(size)),
+            permits: Arc::new(tokio::sync::Semaphore::new(size)),
+        })
+    }
+
+    fn open(&self) -> rusqlite::Result<rusqlite::Connection> {
+        let conn = rusqlite::Connection::open_with_flags(&self.path, self.flags)?;
+        conn.busy_timeout(self.timeout)?;
+        conn.pragma_update(None, "synchronous", self.synchronous)?;
+        Ok(conn)
+    }
+
+    async fn run<F, R>(self: &Arc<Self>, f: F) -> storage::Result<R>
+    where
+        F: FnOnce(&mut rusqlite::Connection) -> storage::Result<R> + Send + 'static,
+        R: Send + 'static,
+    {
+        let permit = self
+            .permits
+            .clone()
+            .acquire_owned()
+            .await
+            .trace_expect("Connection pool closed");
+        let pool = self.clone();
+        tokio::task::spawn_blocking(move || {
+            let _permit = permit;
+            let idle = pool.idle.lock().trace_expect("Failed to lock mutex").pop();
+            let mut conn = match idle {
+                Some(conn) => conn,
+                None => pool.open()?,
+            };
+            let r = f(&mut conn);
+            pool.idle
+                .lock()
+                .trace_expect("Failed to lock mutex")
+                .push(conn);
+            r
+        })
+        .await
+        .trace_expect("Failed to spawn blocking thread")
+    }
 }
 
 pub struct Storage {
-    path: PathBuf,
-    timeout: Duration,
+    readers: Arc<Pool>,
+    writer: Arc<Pool>,
+    compact_blocks: bool,
 }
 
 #[derive(Error, Debug)]
@@ -120,60 +185,100 @@ fn columns_to_bundle_status(
     }
 }
 
+// The engine settings, as they appear in its section of the configuration
+#[derive(Debug, serde::Deserialize)]
+#[serde(default)]
+struct Settings {
+    db_dir: Option<PathBuf>,
+    timeout: u64,
+    compact_blocks: bool,
+    read_connections: usize,
+    synchronous: String,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            db_dir: None,
+            timeout: 5,
+            compact_blocks: false,
+            read_connections: 4,
+            synchronous: "full".to_string(),
+        }
+    }
+}
+
 impl Storage {
     #[instrument(skip(config))]
     pub fn init(
         config: &HashMap<String, config::Value>,
         mut upgrade: bool,
+        read_only: bool,
     ) -> Arc<dyn storage::MetadataStorage> {
+        let settings = config::Value::from(config.clone())
+            .try_deserialize::<Settings>()
+            .trace_expect("Invalid sqlite configuration");
+
         // Compose DB name
-        let file_path = config
-            .get("db_dir")
-            .map_or_else(
-                || {
-                    directories::ProjectDirs::from("dtn", "Hardy", built_info::PKG_NAME)
-                        .map_or_else(
-                            || {
-                                cfg_if::cfg_if! {
-                                    if #[cfg(unix)] {
-                                        Path::new("/var/spool").join(built_info::PKG_NAME)
-                                    } else if #[cfg(windows)] {
-                                        std::env::current_exe().join(built_info::PKG_NAME)
-                                    } else {
-                                        compile_error!("No idea how to determine default local store directory for target platform")
-                                    }
-                                }
-                            },
-                            |project_dirs| {
-                                project_dirs.cache_dir().into()
-                                // Lin: /home/alice/.store/barapp
-                                // Win: C:\Users\Alice\AppData\Local\Foo Corp\Bar App\store
-                                // Mac: /Users/Alice/Library/stores/com.Foo-Corp.Bar-App
-                            },
-                        )
-                },
-                |v| {
-                    v.clone()
-                        .into_string().trace_expect("Invalid 'db_dir' value in configuration").into()
-                },
-            )
+        let file_path = settings
+            .db_dir
+            .unwrap_or_else(|| {
+                directories::ProjectDirs::from("dtn", "Hardy", built_info::PKG_NAME).map_or_else(
+                    || {
+                        cfg_if::cfg_if! {
+                            if #[cfg(unix)] {
+                                Path::new("/var/spool").join(built_info::PKG_NAME)
+                            } else if #[cfg(windows)] {
+                                std::env::current_exe().join(built_info::PKG_NAME)
+                            } else {
+                                compile_error!("No idea how to determine default local store directory for target platform")
+                            }
+                        }
+                    },
+                    |project_dirs| {
+                        project_dirs.cache_dir().into()
+                        // Lin: /home/alice/.store/barapp
+                        // Win: C:\Users\Alice\AppData\Local\Foo Corp\Bar App\store
+                        // Mac: /Users/Alice/Library/stores/com.Foo-Corp.Bar-App
+                    },
+                )
+            })
             .join("metadata.db");
 
-        let timeout = config
-            .get("timeout")
-            .map_or(Duration::from_secs(5), |timeout| {
-                Duration::from_secs(
-                    timeout
-                        .clone()
-                        .into_int()
-                        .trace_expect("Invalid 'timeout' value in configuration")
-                        .try_into()
-                        .trace_expect("Invalid 'timeout' value in configuration"),
-                )
-            });
+        let timeout = Duration::from_secs(settings.timeout);
+        let compact_blocks = settings.compact_blocks;
+
+        let read_connections = settings.read_connections;
+        if read_connections == 0 {
+            error!("'read_connections' must be greater than 0");
+            panic!("'read_connections' must be greater than 0");
+        }
+
+        let synchronous = match settings.synchronous.to_ascii_lowercase().as_str() {
+            "off" => "off",
+            "normal" => "normal",
+            "full" => "full",
+            "extra" => "extra",
+            s => {
+                error!("Invalid 'synchronous' value '{s}' in configuration");
+                panic!("Invalid 'synchronous' value '{s}' in configuration");
+            }
+        };
 
         info!("Using database: {}", file_path.display());
 
+        let readers = Pool::new(
+            file_path.clone(),
+            rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY,
+            read_connections,
+            timeout,
+            synchronous,
+        );
+
+        if read_only {
+            return Self::init_read_only(file_path, readers);
+        }
+
         // Ensure directory exists
         if let Some(parent) = file_path.parent() {
             std::fs::create_dir_all(parent).trace_expect(&format!(
@@ -211,53 +316,215 @@ impl Storage {
         migrate::migrate(&mut connection, upgrade)
             .trace_expect("Failed to migrate metadata store database");
 
+        // Readers only run alongside the writer in WAL mode
+        let journal_mode: String = connection
+            .pragma_update_and_check(None, "journal_mode", "WAL", |row| row.get(0))
+            .trace_expect("Failed to set metadata store database journal mode");
+        if !journal_mode.eq_ignore_ascii_case("wal") {
+            warn!("Metadata store database is in '{journal_mode}' journal mode, not WAL, reads will wait for writes");
+        }
+
         // Do an optimize check
         connection
             .execute_batch(r#"PRAGMA optimize=0x10002;"#)
             .trace_expect("Failed to set up metadata store database");
 
+        // Log the size of the database, to judge the effect of 'compact_blocks'
+        let size: i64 = connection
+            .query_row(
+                r#"SELECT page_count * page_size FROM pragma_page_count(), pragma_page_size();"#,
+                (),
+                |row| row.get(0),
+            )
+            .trace_expect("Failed to get metadata store database size");
+        if compact_blocks {
+            info!("Metadata store database is {size} bytes, new bundles will store their blocks compactly");
+        } else {
+            info!("Metadata store database is {size} bytes");
+        }
+
         // Mark all existing non-Tombstone bundles as unconfirmed
         connection
             .execute(
                 r#"
             INSERT OR IGNORE INTO unconfirmed_bundles (bundle_id)
-            SELECT id FROM bundles WHERE status != ?1;"#,
+            SELECT id FROM bundles WHERE status != ?1 AND inline_data IS NULL;"#,
                 [StatusCodes::Tombstone as i64],
             )
             .trace_expect("Failed to prepare metadata store database");
 
+        info!("Metadata store database has {read_connections} read connections, synchronous={synchronous}");
+
         Arc::new(Storage {
-            path: file_path,
-            timeout,
+            readers,
+            writer: Pool::new(
+                file_path,
+                rusqlite::OpenFlags::SQLITE_OPEN_READ_WRITE,
+                1,
+                timeout,
+                synchronous,
+            ),
+            compact_blocks,
+        })
+    }
+
+    /* Open a database maintained by another instance.  Each query runs in its own read
+     * transaction, and as the database is in WAL mode, sees a consistent snapshot that
+     * is unaffected by concurrent writes from the forwarding instance */
+    fn init_read_only(file_path: PathBuf, readers: Arc<Pool>) -> Arc<dyn storage::MetadataStorage> {
+        let connection = rusqlite::Connection::open_with_flags(
+            &file_path,
+            rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY | rusqlite::OpenFlags::SQLITE_OPEN_NO_MUTEX,
+        )
+        .trace_expect("Failed to open metadata store database read-only");
+
+        // We cannot migrate, so the schema must already be current
+        migrate::check(&connection).trace_expect("Metadata store database schema is not current");
+
+        info!("Metadata store database opened read-only");
+
+        // There is nothing to write, so writes fail as they would on a read-only connection
+        Arc::new(Storage {
+            writer: Pool::new(
+                file_path,
+                rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY,
+                1,
+                readers.timeout,
+                readers.synchronous,
+            ),
+            readers,
+            compact_blocks: false,
         })
     }
 
-    async fn pooled_connection<F, R>(&self, f: F) -> storage::Result<R>
+    async fn read<F, R>(&self, f: F) -> storage::Result<R>
     where
         F: FnOnce(&mut rusqlite::Connection) -> storage::Result<R> + Send + 'static,
         R: Send + 'static,
     {
-        let path = self.path.clone();
-        let timeout = self.timeout;
-        tokio::task::spawn_blocking(move || {
-            CONNECTION.with_borrow_mut(|v| {
-                if v.is_none() {
-                    let conn = rusqlite::Connection::open_with_flags(
-                        &path,
-                        rusqlite::OpenFlags::SQLITE_OPEN_READ_WRITE
-                            | rusqlite::OpenFlags::SQLITE_OPEN_NO_MUTEX,
-                    )?;
-                    conn.busy_timeout(timeout)?;
-                    *v = Some(conn);
-                }
-                f(v.as_mut().unwrap())
-            })
-        })
-        .await
-        .trace_expect("Failed to spawn blocking thread")
+        self.readers.run(f).await
+    }
+
+    async fn write<F, R>(&self, f: F) -> storage::Result<R>
+    where
+        F: FnOnce(&mut rusqlite::Connection) -> storage::Result<R> + Send + 'static,
+        R: Send + 'static,
+    {
+        self.writer.run(f).await
+    }
+}
+
+fn update_status(
+    conn: &rusqlite::Connection,
+    bundle_id: &bpv7::BundleId,
+    status: &metadata::BundleStatus,
+) -> storage::Result<()> {
+    let (status_code, ack_handle, until) = bundle_status_to_parts(status);
+
+    let r = if let metadata::BundleStatus::Tombstone(_) = status {
+        conn
+            .prepare_cached(
+                r#"UPDATE bundles 
+            SET status = ?1, ack_handle = ?2, wait_until = ?3, storage_name = NULL, hash = NULL, inline_data = NULL 
+            WHERE 
+                source = ?4 AND
+                creation_time = ?5 AND
+                creation_seq_num = ?6 AND
+                fragment_offset = ?7 AND 
+                fragment_total_len = ?8;"#,
+            )?
+            .execute((
+                status_code,
+                ack_handle,
+                until,
+                encode_eid(&bundle_id.source),
+                encode_creation_time(bundle_id.timestamp.creation_time),
+                as_i64(bundle_id.timestamp.sequence_number),
+                bundle_id.fragment_info.as_ref().map_or(-1, |f| as_i64(f.offset)),
+                bundle_id.fragment_info.as_ref().map_or(-1, |f| as_i64(f.total_len)),
+            ))
+    } else {
+        conn.prepare_cached(
+            r#"UPDATE bundles 
+            SET status = ?1, ack_handle = ?2, wait_until = ?3 
+            WHERE 
+                source = ?4 AND
+                creation_time = ?5 AND
+                creation_seq_num = ?6 AND
+                fragment_offset = ?7 AND 
+                fragment_total_len = ?8;"#,
+        )?
+        .execute((
+            status_code,
+            ack_handle,
+            until,
+            encode_eid(&bundle_id.source),
+            encode_creation_time(bundle_id.timestamp.creation_time),
+            as_i64(bundle_id.timestamp.sequence_number),
+            bundle_id
+                .fragment_info
+                .as_ref()
+                .map_or(-1, |f| as_i64(f.offset)),
+            bundle_id
+                .fragment_info
+                .as_ref()
+                .map_or(-1, |f| as_i64(f.total_len)),
+        ))
+    };
+
+    if !r.map(|count| count != 0)? {
+        Err(Error::NotFound.into())
+    } else {
+        Ok(())
     }
 }
 
+// As update_status(), but only if the stored status is still `expected`, returning whether it was
+fn claim_status(
+    conn: &rusqlite::Connection,
+    bundle_id: &bpv7::BundleId,
+    expected: &metadata::BundleStatus,
+    status: &metadata::BundleStatus,
+) -> storage::Result<bool> {
+    let (status_code, ack_handle, until) = bundle_status_to_parts(status);
+    let (expected_code, expected_ack_handle, expected_until) = bundle_status_to_parts(expected);
+
+    conn.prepare_cached(
+        r#"UPDATE bundles 
+        SET status = ?1, ack_handle = ?2, wait_until = ?3 
+        WHERE 
+            source = ?4 AND
+            creation_time = ?5 AND
+            creation_seq_num = ?6 AND
+            fragment_offset = ?7 AND 
+            fragment_total_len = ?8 AND
+            status = ?9 AND
+            ack_handle IS ?10 AND
+            wait_until IS ?11;"#,
+    )?
+    .execute(rusqlite::params!(
+        status_code,
+        ack_handle,
+        until,
+        encode_eid(&bundle_id.source),
+        encode_creation_time(bundle_id.timestamp.creation_time),
+        as_i64(bundle_id.timestamp.sequence_number),
+        bundle_id
+            .fragment_info
+            .as_ref()
+            .map_or(-1, |f| as_i64(f.offset)),
+        bundle_id
+            .fragment_info
+            .as_ref()
+            .map_or(-1, |f| as_i64(f.total_len)),
+        expected_code,
+        expected_ack_handle,
+        expected_until,
+    ))
+    .map(|count| count != 0)
+    .map_err(Into::into)
+}
+
 fn encode_eid(eid: &bpv7::Eid) -> rusqlite::types::Value {
     rusqlite::types::Value::Blob(cbor::encode::emit(eid))
 }
@@ -272,6 +539,16 @@ fn decode_eid(
     cbor::decode::parse(b).map_err(Into::into)
 }
 
+fn decode_optional_eid(row: &rusqlite::Row, idx: usize) -> rusqlite::Result<Option<bpv7::Eid>> {
+    match row.get_ref(idx)? {
+        rusqlite::types::ValueRef::Null => Ok(None),
+        rusqlite::types::ValueRef::Blob(b) => cbor::decode::parse(b).map(Some).map_err(|e| {
+            rusqlite::Error::FromSqlConversionFailure(idx, rusqlite::types::Type::Blob, Box::new(e))
+        }),
+        v => panic!("EID encoded as unusual sqlite type: {:?}", v),
+    }
+}
+
 fn encode_hash(hash: &Option<Arc<[u8]>>) -> rusqlite::types::Value {
     match hash {
         Some(hash) => rusqlite::types::Value::Blob(hash.to_vec()),
@@ -310,6 +587,84 @@ fn decode_creation_time(
     }
 }
 
+/* Block metadata takes a row per block in the bundle_blocks table, unless 'compact_blocks'
+ * is set, when it is stored as a single CBOR array in the blocks column of the bundle, one
+ * array of the block fields per block.  This saves the per-row and indexing overhead of
+ * SQLite, which is much of the size of the database for small bundles.  Bundles are read
+ * either way, so the option can be changed at any time, only affecting new bundles */
+fn encode_blocks(blocks: &HashMap<u64, bpv7::Block>) -> Vec<u8> {
+    cbor::encode::emit_array(Some(blocks.len()), |a| {
+        for (block_num, block) in blocks {
+            a.emit_array(Some(9), |a| {
+                a.emit(*block_num);
+                a.emit(u64::from(block.block_type));
+                a.emit(u64::from(&block.flags));
+                a.emit(u64::from(block.crc_type));
+                a.emit(block.data_start);
+                a.emit(block.data_len);
+                a.emit(block.payload_offset);
+                a.emit(block.payload_len);
+                a.emit(block.bcb);
+            });
+        }
+    })
+}
+
+fn parse_blocks(data: &[u8]) -> Result<HashMap<u64, bpv7::Block>, cbor::decode::Error> {
+    cbor::decode::parse_array(data, |a, _, _| {
+        let mut blocks = HashMap::new();
+        while let Some((block_num, block)) = a.try_parse_array(|a, _, _| {
+            Ok::<_, cbor::decode::Error>((
+                a.parse::<u64>()?,
+                bpv7::Block {
+                    block_type: a.parse::<u64>()?.into(),
+                    flags: a.parse::<u64>()?.into(),
+                    crc_type: a.parse::<u64>()?.into(),
+                    data_start: a.parse()?,
+                    data_len: a.parse()?,
+                    payload_offset: a.parse()?,
+                    payload_len: a.parse()?,
+                    bcb: a.parse()?,
+                },
+            ))
+        })? {
+            if blocks.insert(block_num, block).is_some() {
+                panic!("Duplicate block number {block_num} in DB!");
+            }
+        }
+        Ok(blocks)
+    })
+    .map(|(blocks, _)| blocks)
+}
+
+// Returns None if the blocks are in the bundle_blocks table
+fn decode_blocks(
+    row: &rusqlite::Row,
+    idx: impl rusqlite::RowIndex,
+) -> Result<Option<HashMap<u64, bpv7::Block>>, Box<dyn std::error::Error + Send + Sync>> {
+    match row.get_ref(idx)? {
+        rusqlite::types::ValueRef::Null => Ok(None),
+        rusqlite::types::ValueRef::Blob(b) => parse_blocks(b).map(Some).map_err(Into::into),
+        v => panic!("Blocks encoded as unusual sqlite type: {:?}", v),
+    }
+}
+
+fn encode_priority(priority: metadata::Priority) -> i64 {
+    match priority {
+        metadata::Priority::Bulk => 0,
+        metadata::Priority::Normal => 1,
+        metadata::Priority::Expedited => 2,
+    }
+}
+
+fn decode_priority(priority: i64) -> metadata::Priority {
+    match priority {
+        0 => metadata::Priority::Bulk,
+        2 => metadata::Priority::Expedited,
+        _ => metadata::Priority::Normal,
+    }
+}
+
 // Quick helper for type conversion
 #[inline]
 fn as_u64(v: i64) -> u64 {
@@ -322,7 +677,16 @@ fn as_i64<T: Into<u64>>(v: T) -> i64 {
     v as i64
 }
 
-fn unpack_bundles(mut rows: rusqlite::Rows<'_>, tx: &storage::Sender) -> storage::Result<()> {
+fn unpack_bundles(rows: rusqlite::Rows<'_>, tx: &storage::Sender) -> storage::Result<()> {
+    unpack_bundles_within(rows, storage::ScanBudget::default(), tx)
+}
+
+// As unpack_bundles(), but stops once `budget` is spent
+fn unpack_bundles_within(
+    mut rows: rusqlite::Rows<'_>,
+    budget: storage::ScanBudget,
+    tx: &storage::Sender,
+) -> storage::Result<()> {
     /* Expected query MUST look like:
            0:  bundles.id,
            1:  bundles.status,
@@ -354,15 +718,25 @@ fn unpack_bundles(mut rows: rusqlite::Rows<'_>, tx: &storage::Sender) -> storage
            27: bundle_blocks.payload_offset,
            28: bundle_blocks.payload_len,
            29: bundle_blocks.bcb,
+           30: bundles.priority,
+           31: bundles.blocks,
+           32: bundles.return_path,
     */
 
+    let mut count = 0usize;
     while let Some(mut row) = rows.next()? {
+        if budget.is_spent(count) {
+            break;
+        }
+
         let bundle_id: i64 = row.get(0)?;
         let metadata = metadata::Metadata {
             status: columns_to_bundle_status(row, 1, 20, 19)?,
             storage_name: row.get(2)?,
             hash: decode_hash(row, 3)?,
             received_at: row.get(4)?,
+            priority: decode_priority(row.get(30)?),
+            return_path: decode_optional_eid(row, 32)?,
         };
 
         let fragment_info = {
@@ -409,30 +783,35 @@ fn unpack_bundles(mut rows: rusqlite::Rows<'_>, tx: &storage::Sender) -> storage
             },
         };
 
-        loop {
-            let block_number = as_u64(row.get(21)?);
-            let block = bpv7::Block {
-                block_type: as_u64(row.get(22)?).into(),
-                flags: as_u64(row.get(23)?).into(),
-                crc_type: as_u64(row.get(24)?).into(),
-                data_start: as_u64(row.get(25)?) as usize,
-                data_len: as_u64(row.get(26)?) as usize,
-                payload_offset: as_u64(row.get(27)?) as usize,
-                payload_len: as_u64(row.get(28)?) as usize,
-                bcb: row.get::<_, Option<i64>>(29)?.map(as_u64),
-            };
+        if let Some(blocks) = decode_blocks(row, 31)? {
+            // Compact blocks, there is only the one row
+            bundle.blocks = blocks;
+        } else {
+            loop {
+                let block_number = as_u64(row.get(21)?);
+                let block = bpv7::Block {
+                    block_type: as_u64(row.get(22)?).into(),
+                    flags: as_u64(row.get(23)?).into(),
+                    crc_type: as_u64(row.get(24)?).into(),
+                    data_start: as_u64(row.get(25)?) as usize,
+                    data_len: as_u64(row.get(26)?) as usize,
+                    payload_offset: as_u64(row.get(27)?) as usize,
+                    payload_len: as_u64(row.get(28)?) as usize,
+                    bcb: row.get::<_, Option<i64>>(29)?.map(as_u64),
+                };
 
-            if bundle.blocks.insert(block_number, block).is_some() {
-                panic!("Duplicate block number {block_number} in DB!");
-            }
+                if bundle.blocks.insert(block_number, block).is_some() {
+                    panic!("Duplicate block number {block_number} in DB!");
+                }
 
-            row = match rows.next()? {
-                None => break,
-                Some(row) => row,
-            };
+                row = match rows.next()? {
+                    None => break,
+                    Some(row) => row,
+                };
 
-            if row.get::<_, i64>(0)? != bundle_id {
-                break;
+                if row.get::<_, i64>(0)? != bundle_id {
+                    break;
+                }
             }
         }
 
@@ -442,16 +821,138 @@ fn unpack_bundles(mut rows: rusqlite::Rows<'_>, tx: &storage::Sender) -> storage
         {
             break;
         }
+        count += 1;
     }
     Ok(())
 }
 
+fn insert_bundle(
+    conn: &mut rusqlite::Connection,
+    metadata: &metadata::Metadata,
+    bundle: &bpv7::Bundle,
+    inline_data: Option<Vec<u8>>,
+    compact_blocks: bool,
+) -> storage::Result<bool> {
+    let trans = conn.transaction_with_behavior(rusqlite::TransactionBehavior::Immediate)?;
+    let (status, ack_handle, until) = bundle_status_to_parts(&metadata.status);
+
+    // Insert bundle
+    let bundle_id = trans
+        .prepare_cached(
+            r#"
+        INSERT INTO bundles (
+            status,
+            storage_name,
+            hash,
+            flags,
+            crc_type,
+            source,
+            destination,
+            report_to,
+            creation_time,
+            creation_seq_num,
+            lifetime,
+            fragment_offset,
+            fragment_total_len,
+            previous_node,
+            age,
+            hop_count,
+            hop_limit,
+            wait_until,
+            ack_handle,
+            inline_data,
+            priority,
+            blocks,
+            return_path
+            )
+        VALUES (?1,?2,?3,?4,?5,?6,?7,?8,?9,?10,?11,?12,?13,?14,?15,?16,?17,?18,?19,?20,?21,?22,?23)
+        RETURNING id;"#,
+        )?
+        .query_row(
+            rusqlite::params!(
+                status,
+                &metadata.storage_name,
+                encode_hash(&metadata.hash),
+                as_i64(&bundle.flags),
+                as_i64(bundle.crc_type),
+                encode_eid(&bundle.id.source),
+                encode_eid(&bundle.destination),
+                encode_eid(&bundle.report_to),
+                encode_creation_time(bundle.id.timestamp.creation_time),
+                as_i64(bundle.id.timestamp.sequence_number),
+                as_i64(bundle.lifetime),
+                bundle
+                    .id
+                    .fragment_info
+                    .as_ref()
+                    .map_or(-1, |f| as_i64(f.offset)),
+                bundle
+                    .id
+                    .fragment_info
+                    .as_ref()
+                    .map_or(-1, |f| as_i64(f.total_len)),
+                bundle.previous_node.as_ref().map(encode_eid),
+                bundle.age.map(as_i64),
+                bundle.hop_count.as_ref().map(|h| as_i64(h.count)),
+                bundle.hop_count.as_ref().map(|h| as_i64(h.limit)),
+                until,
+                ack_handle,
+                inline_data,
+                encode_priority(metadata.priority),
+                compact_blocks.then(|| encode_blocks(&bundle.blocks)),
+                metadata.return_path.as_ref().map(encode_eid)
+            ),
+            |row| Ok(as_u64(row.get(0)?)),
+        );
+
+    let bundle_id = match bundle_id {
+        Err(rusqlite::Error::SqliteFailure(e, _)) if e.extended_code == 2067 => return Ok(false),
+        bundle_id => bundle_id.trace_expect("Failed to load bundle metadata"),
+    };
+
+    if !compact_blocks {
+        // Insert extension blocks
+        let mut block_stmt = trans.prepare_cached(
+            r#"
+                INSERT INTO bundle_blocks (
+                    bundle_id,
+                    block_type,
+                    block_num,
+                    block_flags,
+                    block_crc_type,
+                    data_start,
+                    data_len
+                    payload_offset,
+                    payload_len,
+                    bcb)
+                VALUES (?1,?2,?3,?4,?5,?6,?7,?8);"#,
+        )?;
+        for (block_num, block) in &bundle.blocks {
+            block_stmt.execute((
+                bundle_id,
+                as_i64(block.block_type),
+                as_i64(*block_num),
+                as_i64(&block.flags),
+                as_i64(block.crc_type),
+                as_i64(block.data_start as u64),
+                as_i64(block.data_len as u64),
+                as_i64(block.payload_offset as u64),
+                as_i64(block.payload_len as u64),
+                block.bcb.map(as_i64),
+            ))?;
+        }
+    }
+
+    // Commit transaction
+    trans.commit().map(|_| true).map_err(Into::into)
+}
+
 #[async_trait]
 impl storage::MetadataStorage for Storage {
     #[instrument(skip(self))]
     async fn load(&self, bundle_id: &bpv7::BundleId) -> storage::Result<Option<metadata::Bundle>> {
         let bundle_id = bundle_id.clone();
-        self.pooled_connection(move |conn| {
+        self.read(move |conn| {
             let mut stmt = conn.prepare_cached(
                 r#"SELECT 
                     bundles.id,
@@ -483,9 +984,12 @@ impl storage::MetadataStorage for Storage {
                     data_len,
                     payload_offset,
                     payload_len,
-                    bcb
+                    bcb,
+                    priority,
+                    blocks,
+                    return_path
                 FROM bundles
-                JOIN bundle_blocks ON bundle_blocks.bundle_id = bundles.id
+                LEFT JOIN bundle_blocks ON bundle_blocks.bundle_id = bundles.id
                 WHERE 
                     source = ?1 AND
                     creation_time = ?2 AND
@@ -519,6 +1023,8 @@ impl storage::MetadataStorage for Storage {
                 storage_name: row.get(2)?,
                 hash: decode_hash(row, 3)?,
                 received_at: row.get(4)?,
+                priority: decode_priority(row.get(30)?),
+                return_path: decode_optional_eid(row, 32)?,
             };
 
             let fragment_info = {
@@ -565,30 +1071,35 @@ impl storage::MetadataStorage for Storage {
                 },
             };
 
-            loop {
-                let block_number = as_u64(row.get(21)?);
-                let block = bpv7::Block {
-                    block_type: as_u64(row.get(22)?).into(),
-                    flags: as_u64(row.get(23)?).into(),
-                    crc_type: as_u64(row.get(24)?).into(),
-                    data_start: as_u64(row.get(25)?) as usize,
-                    data_len: as_u64(row.get(26)?) as usize,
-                    payload_offset: as_u64(row.get(27)?) as usize,
-                    payload_len: as_u64(row.get(28)?) as usize,
-                    bcb: row.get::<_, Option<i64>>(29)?.map(as_u64),
-                };
+            if let Some(blocks) = decode_blocks(row, 31)? {
+                // Compact blocks, there is only the one row
+                bundle.blocks = blocks;
+            } else {
+                loop {
+                    let block_number = as_u64(row.get(21)?);
+                    let block = bpv7::Block {
+                        block_type: as_u64(row.get(22)?).into(),
+                        flags: as_u64(row.get(23)?).into(),
+                        crc_type: as_u64(row.get(24)?).into(),
+                        data_start: as_u64(row.get(25)?) as usize,
+                        data_len: as_u64(row.get(26)?) as usize,
+                        payload_offset: as_u64(row.get(27)?) as usize,
+                        payload_len: as_u64(row.get(28)?) as usize,
+                        bcb: row.get::<_, Option<i64>>(29)?.map(as_u64),
+                    };
 
-                if bundle.blocks.insert(block_number, block).is_some() {
-                    panic!("Duplicate block number {block_number} in DB!");
-                }
+                    if bundle.blocks.insert(block_number, block).is_some() {
+                        panic!("Duplicate block number {block_number} in DB!");
+                    }
 
-                row = match rows.next()? {
-                    None => break,
-                    Some(row) => row,
-                };
+                    row = match rows.next()? {
+                        None => break,
+                        Some(row) => row,
+                    };
 
-                if row.get::<_, i64>(0)? != bundle_id {
-                    panic!("More than one bundle in query!");
+                    if row.get::<_, i64>(0)? != bundle_id {
+                        panic!("More than one bundle in query!");
+                    }
                 }
             }
             Ok(Some(metadata::Bundle { bundle, metadata }))
@@ -604,121 +1115,15 @@ impl storage::MetadataStorage for Storage {
     ) -> storage::Result<bool> {
         let metadata = metadata.clone();
         let bundle = bundle.clone();
-        self.pooled_connection(move |conn| {
-            let trans = conn.transaction_with_behavior(rusqlite::TransactionBehavior::Immediate)?;
-            let (status, ack_handle, until) = bundle_status_to_parts(&metadata.status);
-
-            // Insert bundle
-            let bundle_id = trans
-                .prepare_cached(
-                    r#"
-                INSERT INTO bundles (
-                    status,
-                    storage_name,
-                    hash,
-                    flags,
-                    crc_type,
-                    source,
-                    destination,
-                    report_to,
-                    creation_time,
-                    creation_seq_num,
-                    lifetime,
-                    fragment_offset,
-                    fragment_total_len,
-                    previous_node,
-                    age,
-                    hop_count,
-                    hop_limit,
-                    wait_until,
-                    ack_handle
-                    )
-                VALUES (?1,?2,?3,?4,?5,?6,?7,?8,?9,?10,?11,?12,?13,?14,?15,?16,?17,?18,?19)
-                RETURNING id;"#,
-                )?
-                .query_row(
-                    rusqlite::params!(
-                        status,
-                        &metadata.storage_name,
-                        encode_hash(&metadata.hash),
-                        as_i64(&bundle.flags),
-                        as_i64(bundle.crc_type),
-                        encode_eid(&bundle.id.source),
-                        encode_eid(&bundle.destination),
-                        encode_eid(&bundle.report_to),
-                        encode_creation_time(bundle.id.timestamp.creation_time),
-                        as_i64(bundle.id.timestamp.sequence_number),
-                        as_i64(bundle.lifetime),
-                        bundle
-                            .id
-                            .fragment_info
-                            .as_ref()
-                            .map_or(-1, |f| as_i64(f.offset)),
-                        bundle
-                            .id
-                            .fragment_info
-                            .as_ref()
-                            .map_or(-1, |f| as_i64(f.total_len)),
-                        bundle.previous_node.as_ref().map(encode_eid),
-                        bundle.age.map(as_i64),
-                        bundle.hop_count.as_ref().map(|h| as_i64(h.count)),
-                        bundle.hop_count.as_ref().map(|h| as_i64(h.limit)),
-                        until,
-                        ack_handle
-                    ),
-                    |row| Ok(as_u64(row.get(0)?)),
-                );
-
-            let bundle_id = match bundle_id {
-                Err(rusqlite::Error::SqliteFailure(e, _)) if e.extended_code == 2067 => {
-                    return Ok(false)
-                }
-                bundle_id => bundle_id.trace_expect("Failed to load bundle metadata"),
-            };
-
-            {
-                // Insert extension blocks
-                let mut block_stmt = trans.prepare_cached(
-                    r#"
-                        INSERT INTO bundle_blocks (
-                            bundle_id,
-                            block_type,
-                            block_num,
-                            block_flags,
-                            block_crc_type,
-                            data_start,
-                            data_len
-                            payload_offset,
-                            payload_len,
-                            bcb)
-                        VALUES (?1,?2,?3,?4,?5,?6,?7,?8);"#,
-                )?;
-                for (block_num, block) in &bundle.blocks {
-                    block_stmt.execute((
-                        bundle_id,
-                        as_i64(block.block_type),
-                        as_i64(*block_num),
-                        as_i64(&block.flags),
-                        as_i64(block.crc_type),
-                        as_i64(block.data_start as u64),
-                        as_i64(block.data_len as u64),
-                        as_i64(block.payload_offset as u64),
-                        as_i64(block.payload_len as u64),
-                        block.bcb.map(as_i64),
-                    ))?;
-                }
-            }
-
-            // Commit transaction
-            trans.commit().map(|_| true).map_err(Into::into)
-        })
-        .await
+        let compact_blocks = self.compact_blocks;
+        self.write(move |conn| insert_bundle(conn, &metadata, &bundle, None, compact_blocks))
+            .await
     }
 
     #[instrument(skip(self))]
     async fn remove(&self, bundle_id: &bpv7::BundleId) -> storage::Result<()> {
         let bundle_id = bundle_id.clone();
-        self.pooled_connection(move |conn| {
+        self.write(move |conn| {
             if !conn
                 .prepare_cached(
                     r#"DELETE FROM bundles 
@@ -758,7 +1163,7 @@ impl storage::MetadataStorage for Storage {
         bundle_id: &bpv7::BundleId,
     ) -> storage::Result<Option<metadata::Metadata>> {
         let bundle_id = bundle_id.clone();
-        self.pooled_connection(move |conn| {
+        self.write(move |conn| {
             let trans = conn.transaction_with_behavior(rusqlite::TransactionBehavior::Immediate)?;
 
             // Check if bundle exists
@@ -771,7 +1176,9 @@ impl storage::MetadataStorage for Storage {
                             wait_until,
                             storage_name,
                             hash,
-                            received_at
+                            received_at,
+                            priority,
+                            return_path
                         FROM bundles
                         WHERE 
                             source = ?1 AND
@@ -803,6 +1210,8 @@ impl storage::MetadataStorage for Storage {
                                 storage_name: row.get(4)?,
                                 hash: decode_hash(row, 5)?,
                                 received_at: row.get(6)?,
+                                priority: decode_priority(row.get(7)?),
+                                return_path: decode_optional_eid(row, 8)?,
                             },
                         ))
                     },
@@ -832,7 +1241,7 @@ impl storage::MetadataStorage for Storage {
         bundle_id: &bpv7::BundleId,
     ) -> storage::Result<Option<metadata::BundleStatus>> {
         let bundle_id = bundle_id.clone();
-        self.pooled_connection(move |conn| {
+        self.read(move |conn| {
             conn.prepare_cached(
                 r#"SELECT status,ack_handle,wait_until 
                 FROM bundles 
@@ -874,112 +1283,116 @@ impl storage::MetadataStorage for Storage {
     ) -> storage::Result<()> {
         let bundle_id = bundle_id.clone();
         let status = status.clone();
-        self.pooled_connection(move |conn| {
-            let (status_code, ack_handle, until) = bundle_status_to_parts(&status);
-
-            let r = if let metadata::BundleStatus::Tombstone(_) = status {
-                conn
-                    .prepare_cached(
-                        r#"UPDATE bundles 
-                    SET status = ?1, ack_handle = ?2, wait_until = ?3, storage_name = NULL, hash = NULL 
-                    WHERE 
-                        source = ?4 AND
-                        creation_time = ?5 AND
-                        creation_seq_num = ?6 AND
-                        fragment_offset = ?7 AND 
-                        fragment_total_len = ?8;"#,
-                    )?
-                    .execute((
-                        status_code,
-                        ack_handle,
-                        until,
-                        encode_eid(&bundle_id.source),
-                        encode_creation_time(bundle_id.timestamp.creation_time),
-                        as_i64(bundle_id.timestamp.sequence_number),
-                        bundle_id.fragment_info.as_ref().map_or(-1, |f| as_i64(f.offset)),
-                        bundle_id.fragment_info.as_ref().map_or(-1, |f| as_i64(f.total_len)),
-                    ))
-            } else {
-                conn
-                    .prepare_cached(
-                        r#"UPDATE bundles 
-                    SET status = ?1, ack_handle = ?2, wait_until = ?3 
-                    WHERE 
-                        source = ?4 AND
-                        creation_time = ?5 AND
-                        creation_seq_num = ?6 AND
-                        fragment_offset = ?7 AND 
-                        fragment_total_len = ?8;"#,
-                    )?
-                    .execute((
-                        status_code,
-                        ack_handle,
-                        until,
-                        encode_eid(&bundle_id.source),
-                        encode_creation_time(bundle_id.timestamp.creation_time),
-                        as_i64(bundle_id.timestamp.sequence_number),
-                        bundle_id.fragment_info.as_ref().map_or(-1, |f| as_i64(f.offset)),
-                        bundle_id.fragment_info.as_ref().map_or(-1, |f| as_i64(f.total_len)),
-                    ))
-            };
+        self.write(move |conn| update_status(conn, &bundle_id, &status))
+            .await
+    }
 
-            if !r.map(|count| count != 0)? {
-                Err(Error::NotFound.into())
-            } else {
-                Ok(())
-            }
-        }).await
+    #[instrument(skip_all)]
+    async fn claim_bundle_statuses(
+        &self,
+        claims: &[(bpv7::BundleId, metadata::BundleStatus)],
+        status: &metadata::BundleStatus,
+    ) -> storage::Result<Vec<bool>> {
+        let claims = claims.to_vec();
+        let status = status.clone();
+        self.write(move |conn| {
+            let trans = conn.transaction_with_behavior(rusqlite::TransactionBehavior::Immediate)?;
+            let claimed = claims
+                .iter()
+                .map(|(bundle_id, expected)| claim_status(&trans, bundle_id, expected, &status))
+                .collect::<storage::Result<Vec<_>>>()?;
+            trans.commit()?;
+            Ok(claimed)
+        })
+        .await
     }
 
     #[instrument(skip(self, tx))]
     async fn get_waiting_bundles(
         &self,
         limit: time::OffsetDateTime,
+        budget: storage::ScanBudget,
         tx: storage::Sender,
     ) -> storage::Result<()> {
-        self.pooled_connection(move |conn| {
-            unpack_bundles(
+        self.read(move |conn| {
+            unpack_bundles_within(
                 conn.prepare_cached(
-                    r#"SELECT 
-                        bundles.id,
-                        status,
-                        storage_name,
-                        hash,
-                        received_at,
-                        flags,
-                        crc_type,
-                        source,
-                        destination,
-                        report_to,
-                        creation_time,
-                        creation_seq_num,
-                        lifetime,                    
-                        fragment_offset,
-                        fragment_total_len,
-                        previous_node,
-                        age,
-                        hop_count,
-                        hop_limit,
-                        wait_until,
-                        ack_handle,
-                        block_num,
-                        block_type,
-                        block_flags,
-                        block_crc_type,
-                        data_start,
-                        data_len,
-                        payload_offset,
-                        payload_len,
-                        bcb
-                    FROM bundles
-                    JOIN bundle_blocks ON bundle_blocks.bundle_id = bundles.id
-                    WHERE status IN (?1,?2) AND unixepoch(wait_until) <= unixepoch(?3);"#,
+                    r#"WITH subset AS (
+                            SELECT
+                                id,
+                                status,
+                                storage_name,
+                                hash,
+                                received_at,
+                                flags,
+                                crc_type,
+                                source,
+                                destination,
+                                report_to,
+                                creation_time,
+                                creation_seq_num,
+                                lifetime,
+                                fragment_offset,
+                                fragment_total_len,
+                                previous_node,
+                                age,
+                                hop_count,
+                                hop_limit,
+                                wait_until,
+                                ack_handle,
+                                priority,
+                                blocks,
+                                return_path
+                            FROM bundles
+                            WHERE status IN (?1,?2) AND unixepoch(wait_until) <= unixepoch(?3)
+                            ORDER BY unixepoch(wait_until), id
+                            LIMIT ?4
+                        )
+                        SELECT
+                            subset.id,
+                            status,
+                            storage_name,
+                            hash,
+                            received_at,
+                            flags,
+                            crc_type,
+                            source,
+                            destination,
+                            report_to,
+                            creation_time,
+                            creation_seq_num,
+                            lifetime,
+                            fragment_offset,
+                            fragment_total_len,
+                            previous_node,
+                            age,
+                            hop_count,
+                            hop_limit,
+                            wait_until,
+                            ack_handle,
+                            block_num,
+                            block_type,
+                            block_flags,
+                            block_crc_type,
+                            data_start,
+                            data_len,
+                            payload_offset,
+                            payload_len,
+                            bcb,
+                            priority,
+                            blocks,
+                            return_path
+                        FROM subset
+                        LEFT JOIN bundle_blocks ON bundle_blocks.bundle_id = subset.id
+                        ORDER BY unixepoch(wait_until), subset.id;"#,
                 )?
                 .query((
                     StatusCodes::ForwardAckPending as i64,
                     StatusCodes::Waiting as i64,
                     limit,
+                    budget.max_bundles.map_or(-1, |max| as_i64(max as u64)),
                 ))?,
+                budget,
                 &tx,
             )
         })
@@ -988,7 +1401,7 @@ impl storage::MetadataStorage for Storage {
 
     #[instrument(skip_all)]
     async fn get_unconfirmed_bundles(&self, tx: storage::Sender) -> storage::Result<()> {
-        self.pooled_connection(move |conn| {
+        self.read(move |conn| {
             unpack_bundles(
                 conn.prepare_cached(
                     r#"WITH subset AS (
@@ -1028,9 +1441,11 @@ impl storage::MetadataStorage for Storage {
                             data_len,
                             payload_offset,
                             payload_len,
-                            bcb
+                            bcb,
+                            (SELECT priority FROM bundles WHERE bundles.id = subset.id),
+                            (SELECT blocks FROM bundles WHERE bundles.id = subset.id)
                         FROM subset
-                        JOIN bundle_blocks ON bundle_blocks.bundle_id = subset.id;"#,
+                        LEFT JOIN bundle_blocks ON bundle_blocks.bundle_id = subset.id;"#,
                 )?
                 .query(())?,
                 &tx,
@@ -1043,9 +1458,121 @@ impl storage::MetadataStorage for Storage {
     async fn poll_for_collection(
         &self,
         destination: bpv7::Eid,
+        budget: storage::ScanBudget,
+        tx: storage::Sender,
+    ) -> storage::Result<()> {
+        self.read(move |conn| {
+            unpack_bundles_within(
+                conn.prepare_cached(
+                    r#"WITH subset AS (
+                            SELECT
+                                id,
+                                status,
+                                storage_name,
+                                hash,
+                                received_at,
+                                flags,
+                                crc_type,
+                                source,
+                                destination,
+                                report_to,
+                                creation_time,
+                                creation_seq_num,
+                                lifetime,
+                                fragment_offset,
+                                fragment_total_len,
+                                previous_node,
+                                age,
+                                hop_count,
+                                hop_limit,
+                                wait_until,
+                                ack_handle,
+                                priority,
+                                blocks,
+                                return_path
+                            FROM bundles
+                            WHERE status = ?1 AND destination = ?2
+                            ORDER BY id
+                            LIMIT ?3
+                        )
+                        SELECT
+                            subset.id,
+                            status,
+                            storage_name,
+                            hash,
+                            received_at,
+                            flags,
+                            crc_type,
+                            source,
+                            destination,
+                            report_to,
+                            creation_time,
+                            creation_seq_num,
+                            lifetime,
+                            fragment_offset,
+                            fragment_total_len,
+                            previous_node,
+                            age,
+                            hop_count,
+                            hop_limit,
+                            wait_until,
+                            ack_handle,
+                            block_num,
+                            block_type,
+                            block_flags,
+                            block_crc_type,
+                            data_start,
+                            data_len,
+                            payload_offset,
+                            payload_len,
+                            bcb,
+                            priority,
+                            blocks,
+                            return_path
+                        FROM subset
+                        LEFT JOIN bundle_blocks ON bundle_blocks.bundle_id = subset.id
+                        ORDER BY subset.id;"#,
+                )?
+                .query((
+                    StatusCodes::CollectionPending as i64,
+                    encode_eid(&destination),
+                    budget.max_bundles.map_or(-1, |max| as_i64(max as u64)),
+                ))?,
+                budget,
+                &tx,
+            )
+        })
+        .await
+    }
+
+    #[instrument(skip(self))]
+    async fn get_waiting_destinations(&self) -> storage::Result<Vec<bpv7::Eid>> {
+        self.read(move |conn| {
+            let mut stmt = conn.prepare_cached(
+                r#"SELECT DISTINCT destination FROM bundles WHERE status IN (?1,?2);"#,
+            )?;
+            let mut rows = stmt.query((
+                StatusCodes::Waiting as i64,
+                StatusCodes::ForwardPending as i64,
+            ))?;
+
+            let mut destinations = Vec::new();
+            while let Some(row) = rows.next()? {
+                destinations.push(decode_eid(row, 0)?);
+            }
+            Ok(destinations)
+        })
+        .await
+    }
+
+    #[instrument(skip(self, tx))]
+    async fn get_waiting_bundles_for(
+        &self,
+        destination: &bpv7::Eid,
         tx: storage::Sender,
     ) -> storage::Result<()> {
-        self.pooled_connection(move |conn| {
+        let destination = encode_eid(destination);
+        self.read(move |conn| {
             unpack_bundles(
                 conn.prepare_cached(
                     r#"SELECT 
@@ -1078,18 +1605,414 @@ impl storage::MetadataStorage for Storage {
                         data_len,
                         payload_offset,
                         payload_len,
-                        bcb
+                        bcb,
+                        priority,
+                        blocks,
+                        return_path
                     FROM bundles
-                    JOIN bundle_blocks ON bundle_blocks.bundle_id = bundles.id
-                    WHERE status = ?1 AND destination = ?2;"#,
+                    LEFT JOIN bundle_blocks ON bundle_blocks.bundle_id = bundles.id
+                    WHERE status IN (?1,?2) AND destination = ?3;"#,
                 )?
                 .query((
-                    StatusCodes::CollectionPending as i64,
-                    encode_eid(&destination),
+                    StatusCodes::Waiting as i64,
+                    StatusCodes::ForwardPending as i64,
+                    destination,
                 ))?,
                 &tx,
             )
         })
         .await
     }
+
+    #[instrument(skip(self, tx))]
+    async fn get_fragments(
+        &self,
+        bundle_id: &bpv7::BundleId,
+        tx: storage::Sender,
+    ) -> storage::Result<()> {
+        let bundle_id = bundle_id.clone();
+        self.read(move |conn| {
+            unpack_bundles(
+                conn.prepare_cached(
+                    r#"SELECT 
+                        bundles.id,
+                        status,
+                        storage_name,
+                        hash,
+                        received_at,
+                        flags,
+                        crc_type,
+                        source,
+                        destination,
+                        report_to,
+                        creation_time,
+                        creation_seq_num,
+                        lifetime,                    
+                        fragment_offset,
+                        fragment_total_len,
+                        previous_node,
+                        age,
+                        hop_count,
+                        hop_limit,
+                        wait_until,
+                        ack_handle,
+                        block_num,
+                        block_type,
+                        block_flags,
+                        block_crc_type,
+                        data_start,
+                        data_len,
+                        payload_offset,
+                        payload_len,
+                        bcb,
+                        priority,
+                        blocks,
+                        return_path
+                    FROM bundles
+                    LEFT JOIN bundle_blocks ON bundle_blocks.bundle_id = bundles.id
+                    WHERE
+                        status = ?1 AND
+                        source = ?2 AND
+                        creation_time = ?3 AND
+                        creation_seq_num = ?4 AND
+                        fragment_offset != -1;"#,
+                )?
+                .query((
+                    StatusCodes::ReassemblyPending as i64,
+                    encode_eid(&bundle_id.source),
+                    encode_creation_time(bundle_id.timestamp.creation_time),
+                    as_i64(bundle_id.timestamp.sequence_number),
+                ))?,
+                &tx,
+            )
+        })
+        .await
+    }
+
+    #[instrument(skip(self, tx))]
+    async fn get_reassembly_pending(&self, tx: storage::Sender) -> storage::Result<()> {
+        self.read(move |conn| {
+            unpack_bundles(
+                conn.prepare_cached(
+                    r#"SELECT 
+                        bundles.id,
+                        status,
+                        storage_name,
+                        hash,
+                        received_at,
+                        flags,
+                        crc_type,
+                        source,
+                        destination,
+                        report_to,
+                        creation_time,
+                        creation_seq_num,
+                        lifetime,                    
+                        fragment_offset,
+                        fragment_total_len,
+                        previous_node,
+                        age,
+                        hop_count,
+                        hop_limit,
+                        wait_until,
+                        ack_handle,
+                        block_num,
+                        block_type,
+                        block_flags,
+                        block_crc_type,
+                        data_start,
+                        data_len,
+                        payload_offset,
+                        payload_len,
+                        bcb,
+                        priority,
+                        blocks,
+                        return_path
+                    FROM bundles
+                    LEFT JOIN bundle_blocks ON bundle_blocks.bundle_id = bundles.id
+                    WHERE status = ?1;"#,
+                )?
+                .query([StatusCodes::ReassemblyPending as i64])?,
+                &tx,
+            )
+        })
+        .await
+    }
+
+    async fn get_stored_bundles(&self, tx: storage::Sender) -> storage::Result<()> {
+        self.read(move |conn| {
+            unpack_bundles(
+                conn.prepare_cached(
+                    r#"SELECT 
+                        bundles.id,
+                        status,
+                        storage_name,
+                        hash,
+                        received_at,
+                        flags,
+                        crc_type,
+                        source,
+                        destination,
+                        report_to,
+                        creation_time,
+                        creation_seq_num,
+                        lifetime,                    
+                        fragment_offset,
+                        fragment_total_len,
+                        previous_node,
+                        age,
+                        hop_count,
+                        hop_limit,
+                        wait_until,
+                        ack_handle,
+                        block_num,
+                        block_type,
+                        block_flags,
+                        block_crc_type,
+                        data_start,
+                        data_len,
+                        payload_offset,
+                        payload_len,
+                        bcb,
+                        priority,
+                        blocks,
+                        return_path
+                    FROM bundles
+                    LEFT JOIN bundle_blocks ON bundle_blocks.bundle_id = bundles.id
+                    WHERE status != ?1;"#,
+                )?
+                .query([StatusCodes::Tombstone as i64])?,
+                &tx,
+            )
+        })
+        .await
+    }
+
+    #[instrument(skip(self))]
+    async fn purge_tombstones(&self, older_than: time::OffsetDateTime) -> storage::Result<u64> {
+        self.write(move |conn| {
+            Ok(conn
+                .prepare_cached(
+                    r#"DELETE FROM bundles
+                    WHERE status = ?1 AND unixepoch(wait_until) < unixepoch(?2);"#,
+                )?
+                .execute((StatusCodes::Tombstone as i64, older_than))? as u64)
+        })
+        .await
+    }
+
+    fn capabilities(&self) -> storage::Capabilities {
+        storage::Capabilities {
+            destination_queries: true,
+            transactional_batches: true,
+            ..Default::default()
+        }
+    }
+
+    #[instrument(skip_all)]
+    async fn set_bundle_statuses(
+        &self,
+        updates: &[(bpv7::BundleId, metadata::BundleStatus)],
+    ) -> storage::Result<()> {
+        let updates = updates.to_vec();
+        self.write(move |conn| {
+            let trans = conn.transaction_with_behavior(rusqlite::TransactionBehavior::Immediate)?;
+            for (bundle_id, status) in &updates {
+                update_status(&trans, bundle_id, status)?;
+            }
+            trans.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    fn supports_inline_data(&self) -> bool {
+        true
+    }
+
+    fn schema_version(&self) -> Option<String> {
+        migrate::current_version()
+    }
+
+    #[instrument(skip(self, data))]
+    async fn store_inline(
+        &self,
+        metadata: &metadata::Metadata,
+        bundle: &bpv7::Bundle,
+        data: &[u8],
+    ) -> storage::Result<bool> {
+        let metadata = metadata.clone();
+        let bundle = bundle.clone();
+        let data = data.to_vec();
+        let compact_blocks = self.compact_blocks;
+        self.write(move |conn| insert_bundle(conn, &metadata, &bundle, Some(data), compact_blocks))
+            .await
+    }
+
+    #[instrument(skip(self))]
+    async fn load_inline(&self, storage_name: &str) -> storage::Result<Option<storage::DataRef>> {
+        let storage_name = storage_name.to_string();
+        self.read(move |conn| {
+            conn.prepare_cached(
+                r#"SELECT inline_data FROM bundles 
+                WHERE storage_name = ?1 AND inline_data IS NOT NULL
+                LIMIT 1;"#,
+            )?
+            .query_row([storage_name], |row| row.get::<_, Vec<u8>>(0))
+            .optional()
+            .map(|data| data.map(|data| Arc::new(data) as storage::DataRef))
+            .map_err(Into::into)
+        })
+        .await
+    }
+
+    #[instrument(skip_all)]
+    async fn get_inline_bundles(&self, tx: storage::Sender) -> storage::Result<()> {
+        self.read(move |conn| {
+            unpack_bundles(
+                conn.prepare_cached(
+                    r#"SELECT 
+                        bundles.id,
+                        status,
+                        storage_name,
+                        hash,
+                        received_at,
+                        flags,
+                        crc_type,
+                        source,
+                        destination,
+                        report_to,
+                        creation_time,
+                        creation_seq_num,
+                        lifetime,                    
+                        fragment_offset,
+                        fragment_total_len,
+                        previous_node,
+                        age,
+                        hop_count,
+                        hop_limit,
+                        wait_until,
+                        ack_handle,
+                        block_num,
+                        block_type,
+                        block_flags,
+                        block_crc_type,
+                        data_start,
+                        data_len,
+                        payload_offset,
+                        payload_len,
+                        bcb,
+                        priority,
+                        blocks,
+                        return_path
+                    FROM bundles
+                    LEFT JOIN bundle_blocks ON bundle_blocks.bundle_id = bundles.id
+                    WHERE inline_data IS NOT NULL AND status != ?1;"#,
+                )?
+                .query([StatusCodes::Tombstone as i64])?,
+                &tx,
+            )
+        })
+        .await
+    }
+
+    fn supports_multicast_delivery(&self) -> bool {
+        true
+    }
+
+    #[instrument(skip(self))]
+    async fn confirm_delivery(
+        &self,
+        bundle_id: &bpv7::BundleId,
+        registration: &str,
+    ) -> storage::Result<bool> {
+        let bundle_id = bundle_id.clone();
+        let registration = registration.to_string();
+        self.write(move |conn| {
+            Ok(conn
+                .prepare_cached(
+                    r#"INSERT OR IGNORE INTO deliveries (bundle_id,registration)
+                    SELECT id,?6 FROM bundles
+                    WHERE
+                        source = ?1 AND
+                        creation_time = ?2 AND
+                        creation_seq_num = ?3 AND
+                        fragment_offset = ?4 AND
+                        fragment_total_len = ?5;"#,
+                )?
+                .execute((
+                    encode_eid(&bundle_id.source),
+                    encode_creation_time(bundle_id.timestamp.creation_time),
+                    as_i64(bundle_id.timestamp.sequence_number),
+                    bundle_id
+                        .fragment_info
+                        .as_ref()
+                        .map_or(-1, |f| as_i64(f.offset)),
+                    bundle_id
+                        .fragment_info
+                        .as_ref()
+                        .map_or(-1, |f| as_i64(f.total_len)),
+                    registration,
+                ))?
+                != 0)
+        })
+        .await
+    }
+
+    #[instrument(skip(self))]
+    async fn get_deliveries(&self, bundle_id: &bpv7::BundleId) -> storage::Result<Vec<String>> {
+        let bundle_id = bundle_id.clone();
+        self.read(move |conn| {
+            conn.prepare_cached(
+                r#"SELECT registration FROM deliveries
+                JOIN bundles ON bundles.id = deliveries.bundle_id
+                WHERE
+                    source = ?1 AND
+                    creation_time = ?2 AND
+                    creation_seq_num = ?3 AND
+                    fragment_offset = ?4 AND
+                    fragment_total_len = ?5;"#,
+            )?
+            .query_map(
+                (
+                    encode_eid(&bundle_id.source),
+                    encode_creation_time(bundle_id.timestamp.creation_time),
+                    as_i64(bundle_id.timestamp.sequence_number),
+                    bundle_id
+                        .fragment_info
+                        .as_ref()
+                        .map_or(-1, |f| as_i64(f.offset)),
+                    bundle_id
+                        .fragment_info
+                        .as_ref()
+                        .map_or(-1, |f| as_i64(f.total_len)),
+                ),
+                |row| row.get::<_, String>(0),
+            )?
+            .collect::<rusqlite::Result<Vec<_>>>()
+            .map_err(Into::into)
+        })
+        .await
+    }
+
+    #[instrument(skip_all)]
+    async fn update_hashes(&self, hashes: &[(Arc<str>, Arc<[u8]>)]) -> storage::Result<u64> {
+        let hashes = hashes.to_vec();
+        self.write(move |conn| {
+            let trans = conn.transaction_with_behavior(rusqlite::TransactionBehavior::Immediate)?;
+            let mut updated = 0u64;
+            {
+                let mut stmt = trans
+                    .prepare_cached(r#"UPDATE bundles SET hash = ?1 WHERE storage_name = ?2;"#)?;
+                for (storage_name, hash) in hashes {
+                    updated = updated.saturating_add(
+                        stmt.execute((hash.as_ref(), storage_name.as_ref()))? as u64,
+                    );
+                }
+            }
+            trans.commit()?;
+            Ok(updated)
+        })
+        .await
+    }
 }
@@ -79,13 +79,22 @@ impl Bpa {
         }
     }
 
-    pub async fn send(&self, bundle: Bytes) -> Result<(), tonic::Status> {
+    pub async fn send(&self, bundle: Bytes) -> Result<Option<i32>, tonic::Status> {
         self.endpoint
             .as_ref()
             .trace_expect("Called send on disconnected BPA endpoint")
             .send(bundle)
             .await
     }
+
+    // Tell the BPA what the session with a peer has revealed of it
+    pub async fn advertise_peer(&self, node_id: &bpv7::Eid, transfer_mru: u64) {
+        self.endpoint
+            .as_ref()
+            .trace_expect("Called advertise_peer on disconnected BPA endpoint")
+            .advertise_peer(node_id, transfer_mru)
+            .await
+    }
 }
 
 impl BpaEndpoint {
@@ -100,6 +109,11 @@ impl BpaEndpoint {
                 ident: config.ident.clone(),
                 name: "TCPCLv4".to_string(),
                 grpc_address: config.external_address.clone(),
+                instance_id: config.external_address.clone(),
+                capabilities: Some(ClaCapabilities {
+                    reliable: true,
+                    ..Default::default()
+                }),
             })
             .await
             .trace_expect("Failed to register with BPA")
@@ -126,8 +140,10 @@ impl BpaEndpoint {
         }
     }
 
-    pub async fn send(&self, bundle: Bytes) -> Result<(), tonic::Status> {
-        self.channel
+    // Returns the reason the BPA rejected the bundle, if it did
+    pub async fn send(&self, bundle: Bytes) -> Result<Option<i32>, tonic::Status> {
+        let response = self
+            .channel
             .lock()
             .await
             .receive_bundle(ReceiveBundleRequest {
@@ -135,7 +151,29 @@ impl BpaEndpoint {
                 source: Bytes::new(),
                 bundle,
             })
+            .await?
+            .into_inner();
+
+        if response.rejected.is_some() {
+            info!("BPA rejected bundle: {}", response.detail);
+        }
+        Ok(response.rejected)
+    }
+
+    async fn advertise_peer(&self, node_id: &bpv7::Eid, transfer_mru: u64) {
+        if let Err(e) = self
+            .channel
+            .lock()
+            .await
+            .advertise_peer(AdvertisePeerRequest {
+                handle: self.handle,
+                node_id: node_id.to_string(),
+                max_bundle_size: Some(transfer_mru),
+                ..Default::default()
+            })
             .await
-            .map(|_| ())
+        {
+            warn!("Failed to advertise peer {node_id} to BPA: {e}")
+        }
     }
 }
@@ -22,6 +22,14 @@ impl Cla for Service {
         let _request = request.into_inner();
         todo!()
     }
+
+    #[instrument(skip(self))]
+    async fn check_health(
+        &self,
+        _request: Request<CheckHealthRequest>,
+    ) -> Result<Response<CheckHealthResponse>, Status> {
+        Ok(Response::new(CheckHealthResponse {}))
+    }
 }
 
 pub fn new_service(config: &config::Config) -> ClaServer<Service> {
@@ -255,7 +255,38 @@ where
             let bundle = std::mem::take(&mut self.ingress_bundle).unwrap();
 
             // Send the bundle to the BPA
-            self.bpa.send(bundle.freeze()).await?;
+            let reason_code = match self.bpa.send(bundle.freeze()).await {
+                Ok(None) => None,
+                // Tell the peer not to bother sending it again
+                Ok(Some(rejection)) => Some(match rejection {
+                    v if v == (receive_bundle_response::Rejection::Duplicate as i32) => {
+                        codec::TransferRefuseReasonCode::Completed
+                    }
+                    v if v == (receive_bundle_response::Rejection::StorageFull as i32) => {
+                        codec::TransferRefuseReasonCode::NoResources
+                    }
+                    _ => codec::TransferRefuseReasonCode::NotAcceptable,
+                }),
+                // The BPA wants the peer to back off, e.g. it is over its rate limit
+                Err(status) if status.code() == tonic::Code::ResourceExhausted => {
+                    info!("BPA refused bundle: {}", status.message());
+                    Some(codec::TransferRefuseReasonCode::NoResources)
+                }
+                Err(e) => return Err(e.into()),
+            };
+            if let Some(reason_code) = reason_code {
+                return self
+                    .transport
+                    .feed(codec::Message::TransferRefuse(
+                        codec::TransferRefuseMessage {
+                            transfer_id: msg.transfer_id,
+                            reason_code,
+                        },
+                    ))
+                    .await
+                    .map_err(Into::into)
+                    .map(|_| self.last_sent = tokio::time::Instant::now());
+            }
         }
 
         // Acknowledge the transfer
@@ -777,6 +808,11 @@ where
         }
     }
 
+    // The peer will not accept a bundle larger than its transfer MRU
+    if let Some(node_id) = &peer_init.node_id {
+        bpa.advertise_peer(node_id, peer_init.transfer_mru).await;
+    }
+
     let (send_request, recv_request) = channel::<Vec<u8>>(1);
     let (send_response, recv_response) =
         unbounded_channel::<Result<ForwardBundleResponse, tonic::Status>>();